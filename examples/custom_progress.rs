@@ -0,0 +1,80 @@
+//! Downloads a URL drawing a progress line of its own, to show that the
+//! library's progress model needs no terminal crate to be displayed:
+//!
+//! ```text
+//! cargo run --example custom_progress -- <url> [target-dir]
+//! ```
+
+use anyhow::Context;
+use download_manager::{
+    ClientOptions, Renderer, TransferOptions, TransferProgress, download_file_async,
+};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// One line of bytes and percentage, redrawn in place.
+struct Line;
+
+impl Renderer for Line {
+    fn render(&self, progress: &TransferProgress) {
+        let downloaded = progress.downloaded();
+        let mut line = format!("\r{downloaded} bytes");
+        if let Some(percent) = (downloaded * 100).checked_div(progress.total()) {
+            line += &format!(" of {} ({percent}%)", progress.total());
+        }
+        for note in progress.take_notes() {
+            print!("\r{note}\n");
+        }
+        print!("{line}");
+        let _ = std::io::stdout().flush();
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        self.render(progress);
+        println!("\n{message}");
+    }
+
+    fn clear(&self, _progress: &TransferProgress) {
+        print!("\r\x1b[K");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args
+        .next()
+        .context("usage: custom_progress <url> [target-dir]")?
+        .parse()?;
+    let target_dir = PathBuf::from(args.next().unwrap_or_else(|| ".".to_string()));
+    std::fs::create_dir_all(&target_dir)?;
+
+    let client = ClientOptions::default().build_async()?;
+    let options = TransferOptions::default();
+    let progress = TransferProgress::new(Default::default());
+    let mut handle = progress.handle();
+    let renderer = Line;
+
+    let download = download_file_async(&client, url, &target_dir, progress.clone(), &options);
+    let draw = async {
+        // Redraw on every update until the download reaches its end state.
+        while handle.changed().await {
+            renderer.render(&progress);
+            if handle.snapshot().state.is_terminal() {
+                break;
+            }
+        }
+    };
+    let (result, ()) = tokio::join!(download, draw);
+    match result {
+        Ok(path) => {
+            renderer.finish(&progress, &format!("Saved to {}", path.display()));
+            Ok(())
+        }
+        Err(error) => {
+            renderer.clear(&progress);
+            Err(error)
+        }
+    }
+}
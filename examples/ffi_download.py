@@ -0,0 +1,64 @@
+"""Downloads a URL through the C ABI, printing its progress events.
+
+Build the shared library and run it:
+
+    cargo rustc --release --lib --features ffi --crate-type cdylib
+    python3 examples/ffi_download.py <url> [destination]
+
+Ctrl+C cancels the download with dm_cancel.
+"""
+
+import ctypes
+import json
+import pathlib
+import signal
+import sys
+
+LIBRARY = {"darwin": "libdownload_manager.dylib", "win32": "download_manager.dll"}.get(
+    sys.platform, "libdownload_manager.so"
+)
+
+lib = ctypes.CDLL(str(pathlib.Path(__file__).parent.parent / "target" / "release" / LIBRARY))
+PROGRESS_CB = ctypes.CFUNCTYPE(None, ctypes.c_char_p)
+lib.dm_download.argtypes = [ctypes.c_char_p, PROGRESS_CB, ctypes.POINTER(ctypes.c_uint64)]
+lib.dm_download.restype = ctypes.c_int32
+lib.dm_cancel.argtypes = [ctypes.c_uint64]
+lib.dm_cancel.restype = ctypes.c_int32
+
+# dm_download writes the download's handle here before it blocks.
+handle = ctypes.c_uint64(0)
+
+
+@PROGRESS_CB
+def on_event(raw):
+    event = json.loads(raw)
+    kind = event["event"]
+    if kind == "progress":
+        total = event["total"] or "?"
+        print(f"\r{event['downloaded']} of {total} bytes", end="", flush=True)
+    elif kind != "started":
+        print(f"\n{kind}: {event}")
+
+
+# Python only runs signal handlers between its own bytecodes, which
+# on_event gives it the chance to several times a second.
+def cancel(*_):
+    if handle.value:
+        lib.dm_cancel(handle.value)
+
+
+def main():
+    if len(sys.argv) < 2:
+        sys.exit("usage: ffi_download.py <url> [destination]")
+    # The fields of `dlm --dry-run --json`'s plan that matter, which can be
+    # passed as is instead; without workers it's a single stream.
+    config = {"url": sys.argv[1]}
+    if len(sys.argv) > 2:
+        config["destination"] = sys.argv[2]
+    signal.signal(signal.SIGINT, cancel)
+    code = lib.dm_download(json.dumps(config).encode(), on_event, ctypes.byref(handle))
+    sys.exit(code)
+
+
+if __name__ == "__main__":
+    main()
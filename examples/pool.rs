@@ -0,0 +1,78 @@
+//! Downloads several URLs at once through a pool, with one progress line
+//! for all of them:
+//!
+//! ```text
+//! cargo run --example pool -- <target-dir> <url>...
+//! ```
+
+use anyhow::Context;
+use download_manager::{
+    ClientOptions, DownloadPool, DownloadRequest, PoolEvent, PoolOptions, PoolTotals,
+};
+use std::io::Write;
+use std::path::PathBuf;
+
+fn draw(totals: &PoolTotals) {
+    let mut line = format!("\r{} bytes", totals.downloaded);
+    if let Some(percent) = (totals.downloaded * 100).checked_div(totals.total) {
+        line += &format!(" of {} ({percent}%)", totals.total);
+    }
+    print!(
+        "{line}, {} running, {} queued, {} done",
+        totals.running,
+        totals.queued,
+        totals.completed + totals.failed
+    );
+    let _ = std::io::stdout().flush();
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let target_dir = PathBuf::from(args.next().context("usage: pool <target-dir> <url>...")?);
+    std::fs::create_dir_all(&target_dir)?;
+
+    let client = ClientOptions::default().build_async()?;
+    let pool = DownloadPool::new(
+        client,
+        PoolOptions {
+            parallelism: 2,
+            ..PoolOptions::default()
+        },
+    );
+    let mut events = pool.events();
+    let mut handles = Vec::new();
+    for url in args {
+        handles.push(pool.submit(DownloadRequest::new(url.parse()?, &target_dir)));
+    }
+
+    // Redraw on every event until each download has ended one way or the
+    // other.
+    let mut left = handles.len();
+    while left > 0 {
+        let Ok(event) = events.recv().await else {
+            break;
+        };
+        draw(&pool.totals());
+        match event {
+            PoolEvent::Finished { id, path } => {
+                println!("\r\x1b[K#{id} saved to {}", path.display());
+                left -= 1;
+            }
+            PoolEvent::Failed { id, error } => {
+                println!("\r\x1b[K#{id} failed: {error}");
+                left -= 1;
+            }
+            _ => {}
+        }
+    }
+    draw(&pool.totals());
+    println!();
+
+    let mut failed = 0;
+    for handle in handles {
+        failed += usize::from(handle.wait().await.is_err());
+    }
+    anyhow::ensure!(failed == 0, "{failed} download(s) failed");
+    Ok(())
+}
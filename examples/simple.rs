@@ -0,0 +1,24 @@
+//! Downloads a URL with the blocking API and prints what it got:
+//!
+//! ```text
+//! cargo run --example simple -- <url> [target-dir]
+//! ```
+
+use anyhow::Context;
+use download_manager::BlockingDownloader;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args
+        .next()
+        .context("usage: simple <url> [target-dir]")?
+        .parse()?;
+    let target_dir = args.next().unwrap_or_else(|| ".".to_string());
+
+    let result = BlockingDownloader::new()?
+        .with_target_dir(target_dir)
+        .download(url)?;
+    println!("{result:#?}");
+    println!("sha256: {}", hex::encode(result.sha256));
+    Ok(())
+}
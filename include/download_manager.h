@@ -0,0 +1,30 @@
+#ifndef DOWNLOAD_MANAGER_H
+#define DOWNLOAD_MANAGER_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit by hand. */
+
+#include <stdint.h>
+
+// Returned when the download panicked, as a Rust program exits with.
+#define DM_PANIC 101
+
+// Downloads what `config_json`, a plan as `dlm --dry-run --json` prints it,
+// describes, blocking until it's done, and returns `dlm`'s exit code for
+// how it went. `progress_cb`, if not null, is called on this thread with
+// every event. `handle`, if not null, is set to the handle [`dm_cancel`]
+// takes before anything else happens, atomically, so another thread can
+// poll it: handles are never 0.
+//
+// # Safety
+//
+// `config_json` must be a valid NUL-terminated string, and `handle` null
+// or a `uint64_t` aligned to 8 bytes that outlives the call.
+int32_t dm_download(const char *config_json,
+                    void (*progress_cb)(const char *event),
+                    uint64_t *handle);
+
+// Stops the download `handle` names, making its [`dm_download`] return.
+// Returns 0, or 1 when no download has that handle (any more).
+int32_t dm_cancel(uint64_t handle);
+
+#endif  /* DOWNLOAD_MANAGER_H */
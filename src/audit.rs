@@ -0,0 +1,290 @@
+//! `dlm audit`: hashing every file in the target directory and comparing
+//! the hashes with the ones recorded last time, to catch bit rot and
+//! tampering. The baseline is a sums file in the directory itself,
+//! `.dlm-audit.sha256` for sha256, so `sha256sum -c` can check it too from
+//! inside the directory.
+
+use crate::hash;
+use anyhow::{Context, bail};
+use download_manager::__private::checksum::{self, Algorithm, SumsLine};
+use download_manager::MemoryLimit;
+use download_manager::schema::{Audit, AuditEntry, AuditStatus, Versioned};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Metadata;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Baselines are named this plus the algorithm, and aren't audited.
+pub const BASELINE_PREFIX: &str = ".dlm-audit.";
+
+/// A file and the paths it has, more than one when it's hard linked, so
+/// it's only hashed once.
+struct Inode {
+    paths: Vec<PathBuf>,
+    size: u64,
+}
+
+/// Audits `dir` against its `algorithm` baseline, hashing `jobs` files at
+/// once (one per CPU by default), and prints what changed. Fails if
+/// anything did, unless `update` is set, which records the current hashes
+/// as the new baseline instead.
+pub fn run(
+    dir: &Path,
+    algorithm: Algorithm,
+    jobs: Option<NonZeroUsize>,
+    update: bool,
+    json: bool,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<()> {
+    let baseline_path = dir.join(format!("{BASELINE_PREFIX}{algorithm}"));
+    let baseline = match std::fs::read_to_string(&baseline_path) {
+        Ok(text) => read_baseline(&text, algorithm),
+        Err(error) if error.kind() == io::ErrorKind::NotFound && update => BTreeMap::new(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => bail!(
+            "No {algorithm} baseline in '{}' yet; record one with `dlm -t {} audit --update`",
+            dir.display(),
+            dir.display()
+        ),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Cannot read '{}'", baseline_path.display()));
+        }
+    };
+
+    let mut files = Vec::new();
+    walk(dir, Path::new(""), &mut files)
+        .with_context(|| format!("Cannot list '{}'", dir.display()))?;
+    let inodes = group_links(files);
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let hashes = hash_all(dir, &inodes, algorithm, jobs, memory)?;
+
+    let mut entries = Vec::new();
+    for (inode, hash) in inodes.iter().zip(hashes) {
+        for path in &inode.paths {
+            let recorded = baseline.get(path);
+            entries.push(match &hash {
+                Ok(hash) => AuditEntry {
+                    path: path.clone(),
+                    status: match recorded {
+                        None => AuditStatus::New,
+                        Some(recorded) if recorded == hash => AuditStatus::Verified,
+                        Some(_) => AuditStatus::Modified,
+                    },
+                    hash: Some(hash.clone()),
+                    error: None,
+                },
+                Err(error) => AuditEntry {
+                    path: path.clone(),
+                    status: AuditStatus::Unreadable,
+                    hash: None,
+                    error: Some(error.clone()),
+                },
+            });
+        }
+    }
+    let seen: BTreeSet<_> = entries.iter().map(|entry| entry.path.clone()).collect();
+    for path in baseline.keys().filter(|path| !seen.contains(*path)) {
+        entries.push(AuditEntry {
+            path: path.clone(),
+            status: AuditStatus::Missing,
+            hash: None,
+            error: None,
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if update {
+        write_baseline(&baseline_path, &entries, &baseline)?;
+    }
+    let count = |status| {
+        entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    };
+    let (verified, modified, new, missing, unreadable) = (
+        count(AuditStatus::Verified),
+        count(AuditStatus::Modified),
+        count(AuditStatus::New),
+        count(AuditStatus::Missing),
+        count(AuditStatus::Unreadable),
+    );
+    if json {
+        let audit = Audit {
+            directory: dir.to_path_buf(),
+            algorithm: algorithm.to_string(),
+            baseline: baseline_path.clone(),
+            updated: update,
+            files: entries,
+        };
+        println!("{}", serde_json::to_string_pretty(&Versioned::new(&audit))?);
+    } else {
+        for entry in &entries {
+            let name = entry.path.display();
+            match entry.status {
+                AuditStatus::Verified => {}
+                AuditStatus::Modified => println!("MODIFIED    {name}"),
+                AuditStatus::New => println!("NEW         {name}"),
+                AuditStatus::Missing => println!("MISSING     {name}"),
+                AuditStatus::Unreadable => println!(
+                    "UNREADABLE  {name}: {}",
+                    entry.error.as_deref().unwrap_or_default()
+                ),
+            }
+        }
+        println!(
+            "{verified} verified, {modified} modified, {new} new, {missing} missing, {unreadable} unreadable"
+        );
+        if update {
+            println!("Recorded the new baseline in '{}'", baseline_path.display());
+        }
+    }
+
+    if unreadable > 0 {
+        bail!("{unreadable} files could not be read");
+    }
+    let changed = modified + new + missing;
+    if changed > 0 && !update {
+        bail!(
+            "{changed} files changed since the baseline; if that's expected, record them with `audit --update`"
+        );
+    }
+    Ok(())
+}
+
+/// The baseline's hashes by path, relative to the directory.
+fn read_baseline(text: &str, algorithm: Algorithm) -> BTreeMap<PathBuf, String> {
+    text.lines()
+        .filter_map(|line| SumsLine::parse(line, algorithm))
+        .map(|line| (line.path, line.hash))
+        .collect()
+}
+
+/// Writes the hashes in `entries` to `path`, keeping the recorded hash of
+/// files that couldn't be read rather than forgetting them.
+fn write_baseline(
+    path: &Path,
+    entries: &[AuditEntry],
+    previous: &BTreeMap<PathBuf, String>,
+) -> anyhow::Result<()> {
+    let mut text = String::new();
+    for entry in entries {
+        let hash = match entry.status {
+            AuditStatus::Missing => continue,
+            AuditStatus::Unreadable => match previous.get(&entry.path) {
+                Some(hash) => hash.clone(),
+                None => continue,
+            },
+            _ => entry.hash.clone().unwrap_or_default(),
+        };
+        let line = SumsLine {
+            hash,
+            path: entry.path.clone(),
+        };
+        text.push_str(&format!("{line}\n"));
+    }
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, text)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .with_context(|| format!("Cannot write '{}'", path.display()))
+}
+
+/// Collects the regular files under `dir`, relative to the audited
+/// directory, along with their metadata. Symlinks aren't followed: what
+/// they point to is audited where it is, if it's in the directory.
+fn walk(dir: &Path, relative: &Path, files: &mut Vec<(PathBuf, Metadata)>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let relative = relative.join(&name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), &relative, files)?;
+        } else if file_type.is_file() && !name.to_string_lossy().starts_with(BASELINE_PREFIX) {
+            files.push((relative, entry.metadata()?));
+        }
+    }
+    Ok(())
+}
+
+/// Puts hard links to the same file together.
+fn group_links(files: Vec<(PathBuf, Metadata)>) -> Vec<Inode> {
+    let mut inodes: Vec<Inode> = Vec::new();
+    let mut by_id: BTreeMap<_, usize> = BTreeMap::new();
+    for (path, metadata) in files {
+        match file_id(&metadata).and_then(|id| by_id.get(&id).copied()) {
+            Some(index) => inodes[index].paths.push(path),
+            None => {
+                if let Some(id) = file_id(&metadata) {
+                    by_id.insert(id, inodes.len());
+                }
+                inodes.push(Inode {
+                    paths: vec![path],
+                    size: metadata.len(),
+                });
+            }
+        }
+    }
+    inodes
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+/// Windows only has file IDs through an unstable API, so hard links there
+/// are hashed once per path.
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Hashes the `inodes` with `jobs` threads, in hex, or why they couldn't
+/// be read, in the same order.
+fn hash_all(
+    dir: &Path,
+    inodes: &[Inode],
+    algorithm: Algorithm,
+    jobs: usize,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<Vec<Result<String, String>>> {
+    let total = inodes.iter().map(|inode| inode.size).sum();
+    let bar = hash::progress_bar(total, format!("Auditing {algorithm} of {}", dir.display()))?;
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; inodes.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(inodes.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(inode) = inodes.get(index) else {
+                        break;
+                    };
+                    let path = dir.join(&inode.paths[0]);
+                    let mut counted = 0;
+                    let hash = checksum::hash_file(&path, algorithm, None, memory, |hashed| {
+                        bar.inc(hashed - counted);
+                        counted = hashed;
+                    });
+                    bar.inc(inode.size.saturating_sub(counted));
+                    results.lock().unwrap()[index] =
+                        Some(hash.map(hex::encode).map_err(|error| error.to_string()));
+                }
+            });
+        }
+    });
+    bar.finish_and_clear();
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
@@ -0,0 +1,556 @@
+//! `--input-file`: downloads every URL a file lists, a few at a time, in
+//! one [`DownloadPool`], so they share its rate limit and the run's retry
+//! budget. One line per download shows how it's going, over a count of
+//! those done; one URL failing doesn't stop the others.
+
+use crate::phases::Phases;
+use crate::resume_all::Outcome;
+use crate::shutdown::Shutdown;
+use crate::snapshot;
+use crate::state::Tracker;
+use crate::usage::UsageLog;
+use anyhow::{Context, bail};
+use download_manager::__private::checksum::Checksum;
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::error;
+use download_manager::__private::host_health::{self, HostCircuits};
+use download_manager::__private::http;
+use download_manager::__private::preflight;
+use download_manager::__private::render;
+use download_manager::__private::speed::{Rate, Size};
+use download_manager::__private::utils;
+use download_manager::DownloadError;
+use download_manager::Pipeline;
+use download_manager::schema;
+use download_manager::{DownloadPool, DownloadRequest};
+use download_manager::{ProgressHandle, ProgressSnapshot};
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// What an input file lists.
+#[derive(Debug, Default)]
+pub struct Listing {
+    /// Each URL the first time it's listed.
+    pub entries: Vec<Entry>,
+    /// Lines that aren't URLs, by line number.
+    pub invalid: Vec<(usize, String)>,
+    /// Lines listing a URL again, and the line that listed it first.
+    pub repeats: Vec<(usize, usize)>,
+}
+
+/// A URL to download, with the settings it's downloaded with.
+#[derive(Debug)]
+pub struct Entry {
+    /// Where the file lists it.
+    pub line: usize,
+    pub url: Url,
+    /// The file's defaults, with the entry's own over them.
+    pub settings: Settings,
+}
+
+/// How to download an entry, from `key = value` lines: those before the
+/// first URL are defaults for every entry, those under a URL are its own.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// Saved under this name in the target directory.
+    pub output: Option<String>,
+    /// Replaces the batch's --checksum, --sha256, --sha1 or --md5.
+    pub checksum: Option<String>,
+    /// Added to the batch's --tag.
+    pub tags: Vec<String>,
+    /// Added to the batch's --header, as `Name: value`.
+    pub headers: Vec<String>,
+    /// Replaces download-async's --workers.
+    pub workers: Option<u8>,
+}
+
+impl Settings {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return Err("no value".to_string());
+        }
+        match key {
+            "output" => self.output = Some(value.to_string()),
+            "checksum" => {
+                value.parse::<Checksum>()?;
+                self.checksum = Some(value.to_string());
+            }
+            "tags" => self.tags.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string),
+            ),
+            "header" => {
+                utils::parse_header(value)?;
+                self.headers.push(value.to_string());
+            }
+            "workers" => match value.parse() {
+                Ok(workers) if workers > 0 => self.workers = Some(workers),
+                _ => return Err(format!("'{value}' isn't a number of workers from 1 to 255")),
+            },
+            _ => {
+                return Err(
+                    "unknown key, expected output, checksum, tags, header or workers".to_string(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// These settings over `defaults`: the tags and headers of both, and
+    /// the rest of these where they're set.
+    fn over(self, defaults: &Settings) -> Settings {
+        Settings {
+            output: self.output,
+            checksum: self.checksum.or_else(|| defaults.checksum.clone()),
+            tags: [defaults.tags.clone(), self.tags].concat(),
+            headers: [defaults.headers.clone(), self.headers].concat(),
+            workers: self.workers.or(defaults.workers),
+        }
+    }
+
+    /// The names of the headers, whose values may be credentials.
+    pub fn header_names(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter_map(|header| header.split_once(':'))
+            .map(|(name, _)| name.trim().to_string())
+            .collect()
+    }
+}
+
+/// What a `--dry-run` found about an entry.
+#[derive(Debug)]
+pub enum Preview {
+    /// What asking the server about it found.
+    Checked(preflight::Entry),
+    /// Left out of the batch, and why.
+    Skipped(String),
+    /// Left out as the server says it's not `--newer-than` asked, and why.
+    NotNewer(String),
+    /// Why the server couldn't be asked.
+    Failed(String),
+}
+
+impl Preview {
+    /// Whether downloading it would fail.
+    pub fn fails(&self) -> bool {
+        match self {
+            Preview::Checked(entry) => entry.failed(),
+            Preview::Skipped(_) | Preview::NotNewer(_) => false,
+            Preview::Failed(_) => true,
+        }
+    }
+}
+
+/// Reads the URLs listed in the file at `path`, or on stdin for `-`.
+pub fn read(path: &Path) -> anyhow::Result<Listing> {
+    let text = match path.to_str() {
+        Some("-") => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .context("Cannot read URLs from stdin")?;
+            text
+        }
+        _ => std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read '{}'", path.display()))?,
+    };
+    parse(&text).with_context(|| format!("Cannot read '{}'", path.display()))
+}
+
+/// One URL a line, each followed by any `key = value` settings of its own,
+/// leaving out blank lines, `#` comments and repeats. Settings before the
+/// first URL apply to all of them. A setting that can't be used fails the
+/// whole file, saying which entry and key it was.
+pub fn parse(text: &str) -> anyhow::Result<Listing> {
+    let mut defaults = Settings::default();
+    // Every line that isn't a setting, numbered from 1, with its settings.
+    let mut entries: Vec<(usize, &str, Settings)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = index + 1;
+        let Some((key, value)) = setting(line) else {
+            entries.push((number, line, Settings::default()));
+            continue;
+        };
+        let count = entries.len();
+        let (settings, place) = match entries.last_mut() {
+            Some((_, _, settings)) => (settings, format!("Entry {count} (line {number})")),
+            None if key == "output" => {
+                bail!("Defaults (line {number}), output: one name can't be every entry's")
+            }
+            None => (&mut defaults, format!("Defaults (line {number})")),
+        };
+        if let Err(error) = settings.set(key, value) {
+            bail!("{place}, {key}: {error}");
+        }
+    }
+
+    let mut listing = Listing::default();
+    let mut seen = HashMap::new();
+    for (line, text, settings) in entries {
+        match Url::parse(text) {
+            Ok(url) => match seen.get(&url) {
+                Some(&first) => listing.repeats.push((line, first)),
+                None => {
+                    seen.insert(url.clone(), line);
+                    listing.entries.push(Entry {
+                        line,
+                        url,
+                        settings: settings.over(&defaults),
+                    });
+                }
+            },
+            Err(_) => listing.invalid.push((line, text.to_string())),
+        }
+    }
+    Ok(listing)
+}
+
+/// The key and value of a `key = value` line. A URL's `=` comes after
+/// characters no key has.
+fn setting(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_key.then(|| (key, value.trim()))
+}
+
+/// One entry's download, ready for [`run`].
+pub struct Job {
+    /// What its lines start with, as in `URL 2 of 5`.
+    pub label: String,
+    pub url: Url,
+    /// Where it's saved.
+    pub destination: PathBuf,
+    pub request: DownloadRequest,
+    /// Its quota, `--newer-than` and `--cache-dir`.
+    pub phases: Phases,
+    /// Hashing it, its checksum and the rest, once it's downloaded.
+    pub post_processing: Pipeline,
+    /// Where `dlm usage` counts it, with its tags.
+    pub usage: Option<(UsageLog, Vec<String>)>,
+    /// Starts keeping its manifest for `dlm status` once it's under way:
+    /// for a download `dlm resume-all` carries on with, which keeps it on
+    /// failing, as `dlm resume` would.
+    pub track: Option<Box<dyn FnOnce() -> Option<Tracker> + Send>>,
+}
+
+/// How a [`Job`] went.
+pub struct Report {
+    /// Its URL, redacted.
+    pub url: String,
+    pub outcome: Outcome,
+    /// Why it was skipped, if it was.
+    pub reason: Option<String>,
+}
+
+impl Report {
+    fn new(url: String, outcome: Outcome) -> Self {
+        Self {
+            url,
+            outcome,
+            reason: None,
+        }
+    }
+
+    fn skipped(url: String, outcome: Outcome, reason: impl Into<String>) -> Self {
+        Self {
+            url,
+            outcome,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+/// Runs `jobs` in `pool`, `parallel` at a time, and returns how each went.
+/// Once Ctrl+C is pressed, those not started yet aren't. With `circuits`,
+/// a job whose host has failed too often in a row is skipped instead.
+pub async fn run(
+    pool: &DownloadPool,
+    jobs: Vec<Job>,
+    parallel: usize,
+    circuits: Option<&Mutex<HostCircuits>>,
+    shutdown: &Shutdown,
+) -> Vec<Report> {
+    let display = Display::new(jobs.len());
+    // A full directory won't have room for the rest either.
+    let full = AtomicBool::new(false);
+    let outcomes = futures::stream::iter(jobs)
+        .map(|job| run_job(pool, job, &display, circuits, &full, shutdown))
+        .buffer_unordered(parallel)
+        .collect()
+        .await;
+    display.clear();
+    outcomes
+}
+
+async fn run_job(
+    pool: &DownloadPool,
+    job: Job,
+    display: &Display,
+    circuits: Option<&Mutex<HostCircuits>>,
+    full: &AtomicBool,
+    shutdown: &Shutdown,
+) -> Report {
+    let (label, url) = (&job.label, http::redact_url(&job.url));
+    if shutdown.is_requested() {
+        return Report::new(url, Outcome::Interrupted);
+    }
+    if full.load(Ordering::SeqCst) {
+        let reason = "over --dir-quota-hard";
+        display.skip(&format!("{label}: skipped, {reason}: {url}"));
+        return Report::skipped(url, Outcome::Skipped, reason);
+    }
+    if let Some(circuits) = circuits
+        && !circuits.lock().unwrap().admit(&job.url, Instant::now())
+    {
+        let reason = host_health::UNHEALTHY;
+        display.skip(&format!("{label}: skipped: {reason}: {url}"));
+        return Report::skipped(url, Outcome::Skipped, reason);
+    }
+    let checked = match job.phases.not_newer(&job.url).await {
+        Ok(None) => job.phases.check_quota(&job.url, &job.destination).await,
+        Ok(Some(reason)) => {
+            display.skip(&format!("{label}: skipped, {reason}: {url}"));
+            return Report::skipped(url, Outcome::NotNewer, reason);
+        }
+        Err(error) => Err(error),
+    };
+    if let Err(error) = checked {
+        if let Some(DownloadError::OverQuota { .. }) = error.downcast_ref() {
+            full.store(true, Ordering::SeqCst);
+        }
+        display.skip(&failed(label, &url, &error));
+        return Report::new(url, Outcome::Failed);
+    }
+    let tracker = job.track.and_then(|track| track());
+    let bar = display.start(label);
+    let progress = OnceCell::new();
+    let result = job
+        .phases
+        .transfer(&job.url, &job.destination, &job.post_processing, || async {
+            let handle = pool.submit(job.request);
+            let progress = progress.get_or_init(|| {
+                let progress = handle.progress();
+                snapshot::attach(&job.url, progress.clone(), job.phases.memory.clone());
+                if let Some(tracker) = &tracker {
+                    tracker.attach(progress.clone());
+                }
+                progress
+            });
+            let (result, ()) = tokio::join!(handle.wait(), follow(progress.clone(), &bar));
+            // Only the download itself says anything about the host.
+            if let Some(circuits) = circuits
+                && !(result.is_err() && shutdown.is_requested())
+            {
+                circuits
+                    .lock()
+                    .unwrap()
+                    .record(&job.url, result.as_ref().err(), Instant::now());
+            }
+            Ok((result?, progress.snapshot()))
+        })
+        .await;
+    let snapshot = progress
+        .get()
+        .map(ProgressHandle::snapshot)
+        .unwrap_or_default();
+    let interrupted = result.is_err() && shutdown.is_requested();
+    if let Some(tracker) = tracker {
+        tracker.finish(result.is_ok()).await;
+    }
+    if let Some((log, tags)) = &job.usage {
+        let outcome = match &result {
+            Ok(_) => schema::Outcome::Completed,
+            Err(_) if interrupted => schema::Outcome::Interrupted,
+            Err(_) => schema::Outcome::Failed,
+        };
+        let mut transfer = schema::Transfer::new(url.clone(), &snapshot, outcome);
+        transfer.tags = tags.clone();
+        if let Err(error) = log.record(&transfer) {
+            diagnostics::warn(
+                WarningId::NotRecorded,
+                format!("`dlm usage` won't count this download: {error}"),
+            );
+        }
+    }
+    let (outcome, line) = match result {
+        Ok(_) => (
+            Outcome::Completed,
+            format!("{label}: saved to '{}'", job.destination.display()),
+        ),
+        Err(_) if interrupted => (Outcome::Interrupted, format!("{label}: interrupted")),
+        Err(error) => (Outcome::Failed, failed(label, &url, &error)),
+    };
+    display.finish(bar, &line);
+    Report::new(url, outcome)
+}
+
+/// The line saying the download labelled `label`, of `url`, failed with
+/// `error`.
+fn failed(label: &str, url: &str, error: &anyhow::Error) -> String {
+    format!(
+        "{label}: failed (exit code {}): {url}: {error:#}",
+        error::exit_code(error)
+    )
+}
+
+/// Shows how the download `progress` is of is going on `bar`, until it's
+/// over.
+async fn follow(mut progress: ProgressHandle, bar: &ProgressBar) {
+    while progress.changed().await {
+        let snapshot = progress.snapshot();
+        bar.set_message(progress_line(&snapshot));
+        if snapshot.state.is_terminal() {
+            break;
+        }
+    }
+}
+
+/// What a download's line says while it runs, as in `Downloaded: 2 MiB /
+/// 8 MiB (25%) @ 1 MiB/s`.
+pub fn progress_line(snapshot: &ProgressSnapshot) -> String {
+    let downloaded = snapshot.downloaded;
+    let line = match snapshot.total {
+        0 => format!("Downloaded: {}", Size(downloaded)),
+        total => format!(
+            "Downloaded: {} / {} ({}%)",
+            Size(downloaded),
+            Size(total),
+            downloaded * 100 / total
+        ),
+    };
+    format!("{line} @ {}", Rate(snapshot.speed))
+}
+
+/// A line per download running, over a count of those done.
+pub struct Display {
+    multi: MultiProgress,
+    done: ProgressBar,
+}
+
+impl Display {
+    pub fn new(count: usize) -> Self {
+        let multi = MultiProgress::new();
+        let done = multi.add(
+            ProgressBar::new(count as u64)
+                .with_style(ProgressStyle::with_template("{pos}/{len} complete").expect("valid")),
+        );
+        render::track(&done);
+        Self { multi, done }
+    }
+
+    /// A line for a download starting, labelled `label`.
+    pub fn start(&self, label: &str) -> ProgressBar {
+        let bar = self.multi.insert_before(
+            &self.done,
+            ProgressBar::new_spinner()
+                .with_style(
+                    ProgressStyle::with_template("{spinner} {prefix}: {msg}").expect("valid"),
+                )
+                .with_prefix(label.to_string()),
+        );
+        bar.enable_steady_tick(Duration::from_millis(100));
+        render::track(&bar);
+        bar
+    }
+
+    /// Takes a download's line off, prints `line` above the others in its
+    /// stead, and counts it as done.
+    pub fn finish(&self, bar: ProgressBar, line: &str) {
+        bar.finish_and_clear();
+        self.multi.remove(&bar);
+        self.multi.suspend(|| println!("{line}"));
+        self.done.inc(1);
+    }
+
+    /// Prints `line` above the others for a download that didn't start,
+    /// and counts it as done.
+    pub fn skip(&self, line: &str) {
+        self.multi.suspend(|| println!("{line}"));
+        self.done.inc(1);
+    }
+
+    pub fn clear(&self) {
+        self.done.finish_and_clear();
+    }
+}
+
+/// Points stdout at stderr until dropped, for `--json`: the downloads' own
+/// lines go there instead, leaving stdout to the summary.
+pub struct StdoutToStderr {
+    #[cfg(unix)]
+    stdout: std::os::fd::OwnedFd,
+    #[cfg(windows)]
+    stdout: windows_sys::Win32::Foundation::HANDLE,
+}
+
+impl StdoutToStderr {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        io::stdout().flush()?;
+        let stdout = nix::unistd::dup(io::stdout())?;
+        nix::unistd::dup2_stdout(io::stderr())?;
+        Ok(Self { stdout })
+    }
+
+    /// The standard library looks the handle up on every write, so
+    /// swapping the process's stdout handle moves `println!` over too.
+    #[cfg(windows)]
+    pub fn new() -> io::Result<Self> {
+        use windows_sys::Win32::System::Console::{
+            GetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE, SetStdHandle,
+        };
+        io::stdout().flush()?;
+        // SAFETY: both only read or set the process's standard handles.
+        unsafe {
+            let stdout = GetStdHandle(STD_OUTPUT_HANDLE);
+            if SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { stdout })
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn new() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--json cannot keep the downloads' lines off stdout here",
+        ))
+    }
+}
+
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        #[cfg(unix)]
+        let _ = nix::unistd::dup2_stdout(&self.stdout);
+        #[cfg(windows)]
+        // SAFETY: as in `new`.
+        let _ = unsafe {
+            windows_sys::Win32::System::Console::SetStdHandle(
+                windows_sys::Win32::System::Console::STD_OUTPUT_HANDLE,
+                self.stdout,
+            )
+        };
+    }
+}
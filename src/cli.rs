@@ -1,250 +1,4732 @@
-use crate::download::progress::{ChunkProgressBar, DownloadProgress, ProgressTracker};
-use crate::download::utils;
-use crate::download::{
-    download_file_async, download_file_blocking, download_with_workers, get_content_length,
+use crate::audit;
+use crate::batch;
+#[cfg(feature = "clipboard")]
+use crate::clipboard;
+use crate::control::{self, ControlGuard, ControlSocket};
+use crate::dry_run;
+use crate::env_flags;
+use crate::error_report;
+use crate::hash;
+use crate::logging::{self, Rotation};
+use crate::phases::{self, Phases};
+use crate::post_steps::{self, Expected};
+use crate::replaced;
+use crate::replay;
+use crate::report;
+use crate::resume_all::{self, Outcome, Summary};
+use crate::shutdown::Shutdown;
+use crate::snapshot;
+use crate::state::{self, ActiveDownloads, Tracker};
+use crate::stats::{Stats, WriteOut};
+use crate::status_file::StatusFile;
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+use crate::systemd;
+use crate::title::{TerminalTitle, TitleGuard};
+use crate::usage::{self, UsageLog};
+use crate::version;
+use crate::web_status::{self, WebStatus, WebStatusGuard};
+use anyhow::{Context, anyhow, bail};
+use bytes::Bytes;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand};
+use colored::Colorize;
+use download_manager::__private::adopt;
+use download_manager::__private::cache::{Cache, Lookup};
+use download_manager::__private::checksum::{Algorithm, Checksum, ChecksumOf};
+use download_manager::__private::chunk_log::ChunkLog;
+use download_manager::__private::chunks::{ChunkOrder, ResumeOrder};
+#[cfg(feature = "http3")]
+use download_manager::__private::client::Http3Mode;
+use download_manager::__private::client::IpFamily;
+use download_manager::__private::compare::{Sampling, compare_mirrors};
+use download_manager::__private::compress::Compression;
+use download_manager::__private::conflicts::{self, OnConflict, Planned};
+use download_manager::__private::connections;
+use download_manager::__private::cosign;
+#[cfg(feature = "sigstore")]
+use download_manager::__private::cosign::{Cosign, Signer};
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::dns::DnsCache;
+use download_manager::__private::fd_limit;
+use download_manager::__private::fs_ops;
+use download_manager::__private::host_health::{self, HostCircuits};
+use download_manager::__private::http;
+use download_manager::__private::in_place;
+use download_manager::__private::landing;
+use download_manager::__private::mirrors;
+use download_manager::__private::naming::{self, NameTemplate, Settled};
+use download_manager::__private::network_wait;
+use download_manager::__private::newer::{self, NewerThan};
+use download_manager::__private::one_connection;
+use download_manager::__private::options::ContinueAt;
+use download_manager::__private::pacing::Pacing;
+use download_manager::__private::partial;
+use download_manager::__private::parts::ResumeFrom;
+use download_manager::__private::pieces::PieceHashes;
+use download_manager::__private::postprocess::Timings;
+use download_manager::__private::preflight::{self, PreflightCache};
+use download_manager::__private::presigned;
+use download_manager::__private::proxy::{self, ProxyCredentials};
+use download_manager::__private::quota::{self, DirQuota};
+use download_manager::__private::rate_limit;
+use download_manager::__private::releases::{Asset, Forge, Release};
+use download_manager::__private::remote;
+use download_manager::__private::removal::{PartialFileGuard, Removal, Removed};
+use download_manager::__private::render::{
+    self, ChunkBar, Glyphs, JsonEvents, ProgressMode, Spinner,
 };
-use clap::{Parser, Subcommand};
+use download_manager::__private::retry::{self, RetryPolicy};
+use download_manager::__private::retry_budget;
+use download_manager::__private::segment_tuning::{SegmentBounds, SegmentSize};
+use download_manager::__private::speed::{self, MinSpeedPolicy, Size, SpeedUnits};
+use download_manager::__private::stall::{ChunkFloor, StallPolicy};
+use download_manager::__private::suspicious;
+use download_manager::__private::target_wait;
+use download_manager::__private::throttle::Throttle;
+use download_manager::__private::tls::{self, SessionLog, TlsVersion};
+#[cfg(feature = "torrent")]
+use download_manager::__private::torrent::Torrent;
+use download_manager::__private::torrent::{self, TorrentMode};
+use download_manager::__private::tracking;
+use download_manager::__private::transaction::Transaction;
+use download_manager::__private::transcript;
+use download_manager::__private::utils;
+use download_manager::__private::validator::IfChanged;
+use download_manager::__private::{
+    download_tail, extract_zip_member, get_content_length, plan_download, repair_file,
+    resolve_landing_page,
+};
+use download_manager::BlockingDownloader;
+use download_manager::ClientOptions;
+use download_manager::DownloadError;
+use download_manager::Downloader;
+use download_manager::MemoryLimit;
+use download_manager::RetryBudget;
+use download_manager::TransferOptions;
+use download_manager::TransferProgress;
+use download_manager::Workers;
+use download_manager::schema::{
+    self, Artifact, BatchSummary, DirUsage, EnvFlag, Manifest, ProgressEvent, Replaced,
+    SkippedEntry, Versioned,
+};
+use download_manager::{AuthToken, TokenPath, TokenSource};
+use download_manager::{DownloadOutcome, Pipeline, StepTiming};
+use download_manager::{DownloadPool, DownloadRequest, PoolOptions};
+use download_manager::{PlainText, Renderer, Silent};
+use download_manager::{ProgressHandle, ProgressSnapshot, TransferState};
+use futures::StreamExt;
+use reqwest::Method;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use serde_json::{Value, json};
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::time::Duration;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{Instrument, field};
 use url::Url;
 
+/// `--keepalive-output` when stderr isn't a terminal, well inside the ten
+/// minutes of silence CI systems tend to allow.
+const KEEPALIVE_OUTPUT: Duration = Duration::from_secs(5 * 60);
+
 /// Download manager application.
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 #[command(version, about, long_about=None)]
+#[command(group(ArgGroup::new("json_output").args(["dry_run", "input_file"]).multiple(true)))]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
 
-    /// URL to a file to download
-    url: Url,
+    /// URL to a file to download (not needed for `report`), or
+    /// github://OWNER/REPO/PATTERN or gitlab://GROUP/PROJECT/PATTERN for
+    /// the asset of the latest release whose name matches PATTERN (with the
+    /// releases feature)
+    url: Option<Url>,
+
+    /// Another URL serving the same file, tried when URL can't be reached
+    /// and carried on from when it fails partway; repeat for more. With
+    /// --workers, chunks are spread across them. download-async only
+    #[arg(long, value_name = "URL")]
+    mirror: Vec<Url>,
+
+    /// Target directory
+    #[arg(short, long, default_value = ".download")]
+    target_directory: PathBuf,
+
+    /// Download chunk size
+    #[arg(short, long, default_value_t = 65_536)]
+    chunk_size: usize,
+
+    /// Resume if the file already exists and isn't complete; with --workers,
+    /// what's left after it is split between them
+    #[arg(short, long)]
+    resume: bool,
+
+    /// Resume from this byte offset, cutting the local file to it first;
+    /// `-` uses the local file's size like --resume. Single stream only.
+    #[arg(long, value_name = "OFFSET", conflicts_with_all = ["resume", "overwrite", "tail"])]
+    continue_at: Option<ContinueAt>,
+
+    /// Allow --continue-at past the end of the local file, zero-filling the gap
+    #[arg(long, requires = "continue_at")]
+    sparse_fill: bool,
+
+    /// Continue from a partial file another tool left behind (a browser's
+    /// .crdownload, a .part), moving it to the destination first. Single
+    /// stream only.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["continue_at", "overwrite", "tail", "cache_dir"])]
+    adopt: Option<PathBuf>,
+
+    /// Before --adopt, check that the partial file's last SIZE bytes match
+    /// the remote file (e.g. 64k)
+    #[arg(long, value_name = "SIZE", requires = "adopt", value_parser = utils::parse_byte_size)]
+    adopt_verify: Option<u64>,
+
+    /// Carry on from a worker-mode download stopped in DIR, one `dlm status`
+    /// lists: the parts it left there are kept where they are, the rest is
+    /// downloaded into --target-directory, and both are merged into the file
+    /// there
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["resume", "continue_at", "adopt", "tail", "cache_dir", "piece_hashes", "dry_run"])]
+    resume_from: Option<PathBuf>,
+
+    /// Before a download-async --workers run carries on with the parts an
+    /// earlier one left (--resume, --resume-from), compare each with the
+    /// server: by the SHA-256 recorded when it was completed, or else by a
+    /// few small ranges fetched again. A part that differs is downloaded
+    /// again. Without it, parts are only checked to be no longer than their
+    /// range
+    #[arg(long)]
+    verify_parts: bool,
+
+    /// When the server can't resume (it ignores the range, or the remote file
+    /// is now a different size), start over from zero instead of failing
+    #[arg(long)]
+    restart_on_unresumable: bool,
+
+    /// What resuming a single-stream download does when the remote file
+    /// changed since it started, which the ETag or Last-Modified kept in
+    /// <name>.meta tells: restart downloads the new file from zero, abort
+    /// fails and leaves the partial file. Without it, restart with
+    /// --restart-on-unresumable and abort otherwise
+    #[arg(long, value_name = "ACTION")]
+    if_changed: Option<IfChanged>,
+
+    /// Overwrite existing file
+    #[arg(short, long)]
+    overwrite: bool,
+
+    /// Move files --overwrite replaces and --remove-on-error removes to the
+    /// trash instead of deleting them, deleting them anyway where there's no
+    /// trash
+    #[arg(long, overrides_with = "force_delete")]
+    use_trash: bool,
+
+    /// Delete replaced and removed files for good, even after --use-trash
+    #[arg(long, overrides_with = "use_trash")]
+    force_delete: bool,
+
+    /// Hash the file --overwrite replaces before replacing it, to compare
+    /// with the new one; off by default as it reads all of the old file
+    #[arg(long, requires = "overwrite")]
+    diff_hash: bool,
+
+    /// Keep retrying for this long (e.g. 10s) when files in the target
+    /// directory can't be created, opened or renamed because it's not there
+    /// or not ready yet (ENOENT, EACCES, ESTALE), as with an NFS automount
+    /// or a volume still being mounted
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+    wait_for_target: Option<Duration>,
+
+    /// When the first connection fails because there's no network, or the
+    /// host can't be resolved or reached, wait for it instead of failing,
+    /// checking again with a backoff; for up to DURATION if given (as in
+    /// --wait-for-network=10m), else until Ctrl+C
+    #[arg(long, value_name = "DURATION", num_args = 0..=1, default_missing_value = "0", value_parser = utils::parse_duration)]
+    wait_for_network: Option<Duration>,
+
+    /// Retries of any kind (5xx answers, dropped connections, stalls, short
+    /// responses) allowed over the whole run, across every chunk and URL;
+    /// once they're spent, whatever would retry fails instead. 0 for no
+    /// limit
+    #[arg(long, value_name = "N", default_value_t = retry_budget::DEFAULT)]
+    retry_budget: u64,
+
+    /// Times a chunk or a download that lost its connection or timed out
+    /// asks for the rest again, waiting twice as long before each retry.
+    /// Answers like 404 or 403 aren't retried
+    #[arg(long, value_name = "N", default_value_t = retry::DEFAULT_RETRIES)]
+    retries: u32,
+
+    /// Once a server's rate-limit headers (X-RateLimit-Remaining,
+    /// RateLimit-Remaining) say only a few requests are left, wait up to
+    /// this long for the limit to reset before the next one. 0 never waits
+    #[arg(long, value_name = "DURATION", default_value = "60s", value_parser = utils::parse_duration)]
+    max_rate_limit_wait: Duration,
+
+    /// On Linux, move a single-stream http:// download's body from the
+    /// socket to the file with splice(2), sparing the CPU the copies. Falls
+    /// back to the usual download for TLS, proxies, resuming and anything
+    /// else it can't handle
+    #[arg(long)]
+    zero_copy: bool,
+
+    /// Bytes each worker buffers before writing to its part file (e.g. 4M).
+    /// Memory use is at most workers × this.
+    #[arg(long, default_value = "4M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    write_buffer: u64,
+
+    /// Cap on buffer memory (write buffers, merging, hashing), e.g. 64M.
+    /// Near it, new write buffers come out smaller and further chunks wait
+    /// to start until others finish
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    max_memory: Option<u64>,
+
+    /// Do all workers' disk writes from a single thread, for spinning disks
+    /// where concurrent writes to different part files cause seek storms
+    #[arg(long)]
+    serial_writes: bool,
+
+    /// Keep download-async --workers chunks near an even share of the
+    /// bandwidth: every few seconds, while a chunk gets under half its share,
+    /// cap any chunk above twice its share. -v reports each intervention
+    #[arg(long)]
+    fair_workers: bool,
+
+    /// Don't open a connection per worker (up to 8) before download-async
+    /// --workers starts its chunks; each chunk connects on its own instead
+    #[arg(long)]
+    no_warm_up: bool,
+
+    /// Split download-async --workers into 4 segments per worker, taken in
+    /// this order as workers free up: sequential gets the start of the file
+    /// done first, spread keeps the workers far apart in the file. The parts
+    /// are merged once all are done; with --hybrid-streaming, sequential
+    /// appends each to the file as it comes instead
+    #[arg(long, value_name = "ORDER")]
+    chunk_order: Option<ChunkOrder>,
+
+    /// The order a resumed download-async --workers takes the segments it
+    /// has left in: most-complete finishes those nearly done first, so a
+    /// second interruption leaves the most whole; largest-remaining starts
+    /// the longest first; sequential goes from the start of the file
+    #[arg(long, default_value = "sequential", value_name = "ORDER")]
+    resume_order: ResumeOrder,
+
+    /// Split a download-async --workers file bigger than a segment of this
+    /// size per worker into segments of about this size (e.g. 32M), which
+    /// the workers take one after another as they finish the last, so a
+    /// slow connection only holds up its own; 0 keeps one per worker.
+    /// `auto` starts at 8M and sizes each next segment by how long the
+    /// finished ones waited for their first byte, keeping that under 5% of
+    /// their transfer, between --segment-size-min and --segment-size-max
+    #[arg(long, default_value = "16M", value_name = "SIZE|auto")]
+    segment_size: SegmentSize,
+
+    /// The smallest --segment-size auto makes a segment
+    #[arg(long, default_value = "1M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    segment_size_min: u64,
+
+    /// The largest --segment-size auto makes a segment
+    #[arg(long, default_value = "256M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    segment_size_max: u64,
+
+    /// Once no segment is left to start, have an idle download-async
+    /// --workers worker take the second half of what's left of the segment
+    /// with the most left, as long as both halves are at least this long;
+    /// 0 never splits one
+    #[arg(long, default_value = "1M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    min_steal_size: u64,
+
+    /// Split a download-async --workers file between fewer workers rather
+    /// than into parts shorter than this, so a small file isn't split at
+    /// all; 0 splits any file between as many as asked for, down to a byte
+    /// each
+    #[arg(long, default_value = "16K", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    min_split_size: u64,
+
+    /// Download a download-async --workers file's chunks one after another
+    /// over a single connection, for servers that refuse a second; worker
+    /// mode falls back to this by itself when they're refused
+    #[arg(long)]
+    one_connection: bool,
+
+    /// Stream the start of a download-async --workers file into it from
+    /// byte 0 while the other workers fetch the rest, appended in order as
+    /// it comes, so the file can be opened (a video, a disk image) before
+    /// it's done
+    #[arg(long, conflicts_with_all = ["resume", "resume_from", "piece_hashes", "store_compressed", "tail"])]
+    hybrid_streaming: bool,
+
+    /// Have each download-async --workers chunk write to a part file merged
+    /// into the file at the end, instead of straight into the file, sized up
+    /// front, at its offset, which needs no merge and no room for a second
+    /// copy. --hybrid-streaming, --resume-from, --store-compressed and
+    /// --no-cleanup write part files anyway
+    #[arg(long)]
+    part_files: bool,
+
+    /// Connect every download-async --workers chunk to the node of a
+    /// round-robin DNS name that answered the first request, so all bytes
+    /// come from one CDN node. On by default; -v shows the node
+    #[arg(long, overrides_with = "no_pin_ip")]
+    pin_ip: bool,
+
+    /// Let each chunk connect to whichever node DNS gives it, spreading the
+    /// download across nodes for throughput
+    #[arg(long, overrides_with = "pin_ip")]
+    no_pin_ip: bool,
+
+    /// File of per-piece SHA-256s to verify chunks against: the piece size on
+    /// the first line, then one hash per line. Corrupt pieces are fetched
+    /// again. Needs download-async --workers 2 or more.
+    #[arg(long, value_name = "PATH", conflicts_with = "tail")]
+    piece_hashes: Option<PathBuf>,
+
+    /// Don't cleanup part files after merging (for debugging)
+    #[arg(long)]
+    no_cleanup: bool,
+
+    /// Remove the NAME.part file a download is written to if it fails,
+    /// rather than keep it for --resume
+    #[arg(long)]
+    no_partial: bool,
+
+    /// Save as this file name (inside the target directory) instead of the
+    /// one the server gives it in Content-Disposition, or the URL's last
+    /// segment
+    #[arg(short = 'O', long, value_name = "NAME")]
+    output: Option<PathBuf>,
+
+    /// Name the file after its SHA-256, per --name-template, once it's
+    /// downloaded; if a file with that name and content is already there,
+    /// keep it and drop the download
+    #[arg(long, conflicts_with_all = ["output", "tail", "cache_dir"])]
+    name_by_hash: bool,
+
+    /// File name for --name-by-hash, from {sha256}, {sha256_short}, {name},
+    /// {stem} and {ext}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{sha256}{ext}",
+        requires = "name_by_hash"
+    )]
+    name_template: NameTemplate,
+
+    /// How often the progress display is refreshed, in milliseconds
+    #[arg(long, default_value_t = 100, value_name = "MS", value_parser = clap::value_parser!(u64).range(10..))]
+    progress_interval: u64,
+
+    /// How progress is shown: bar, plain (a line every --plain-interval, no
+    /// escape codes), json (a JSON event per line, for scripts) or none
+    #[arg(long, value_name = "MODE", default_value = "bar", value_parser = ProgressMode::from_str)]
+    progress: ProgressMode,
+
+    /// Write --progress plain and json lines to stdout instead of stderr
+    #[arg(long)]
+    progress_stdout: bool,
+
+    /// The least time between two --progress plain lines, e.g. 30s
+    #[arg(long, value_name = "INTERVAL", default_value = "5s", value_parser = utils::parse_duration)]
+    plain_interval: Duration,
+
+    /// Also print a plain progress line (percentage, bytes, speed) to stderr
+    /// this often, e.g. 5m, so CI systems don't kill a long download for
+    /// being silent. On at 5m when stderr isn't a terminal; 0 turns it off.
+    /// Only next to --progress bar
+    #[arg(long, value_name = "INTERVAL", value_parser = utils::parse_duration)]
+    keepalive_output: Option<Duration>,
+
+    /// Don't end the progress line with a sparkline of the last half
+    /// minute's speed
+    #[arg(long)]
+    no_sparkline: bool,
+
+    /// Draw progress with ASCII (#, -, .) instead of block characters; on
+    /// when the locale or terminal doesn't look like it can show Unicode
+    #[arg(long)]
+    ascii: bool,
+
+    /// Increase verbosity; -vv dumps request and response headers
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Also write log output to this file (at least at debug level)
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this size (e.g. 10M), at
+    /// startup or mid-run
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size, requires = "log_file")]
+    log_max_size: Option<u64>,
+
+    /// How many rotated log files to keep, as <log-file>.1, .2 and so on
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "log_max_size")]
+    log_keep: usize,
+
+    /// Don't redact Authorization and Cookie values in header dumps
+    #[arg(long)]
+    show_secrets: bool,
+
+    /// Treat the URL as a presigned object-store URL even if its signature
+    /// isn't recognized (S3's and GCS's are): sizes come from a one-byte GET
+    /// rather than HEAD, and a 403 saying it expired is reported as such
+    #[arg(long)]
+    presigned: bool,
+
+    /// Remove tracking parameters (utm_*, fbclid, gclid, ...) from the URL
+    /// before requesting it. Anything that could be part of a signature is
+    /// kept, and so is every parameter of a presigned URL
+    #[arg(long)]
+    strip_tracking_params: bool,
+
+    /// Another query parameter --strip-tracking-params removes; repeat it or
+    /// give a comma-separated list
+    #[arg(
+        long,
+        env = "DM_TRACKING_PARAMS",
+        value_name = "NAME",
+        value_delimiter = ','
+    )]
+    tracking_param: Vec<String>,
+
+    /// Include the gist of error responses' bodies (S3's XML message, a JSON
+    /// API's error fields, an HTML page's text) in status errors
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+    show_error_body: bool,
+
+    /// Append a JSON line per chunk lifecycle event to this file, for
+    /// `report` or post-mortem analysis
+    #[arg(long, value_name = "PATH")]
+    chunk_log: Option<PathBuf>,
+
+    /// On failure, write what's known about it (error chain, progress, last
+    /// response, retries, version) to this file as JSON, for `report` or
+    /// alerting
+    #[arg(long, value_name = "PATH")]
+    error_report: Option<PathBuf>,
+
+    /// Write a transcript of the run to this file: every request and the
+    /// status and headers it got, the chunk plan, retries and the exit
+    /// code, with timings, but no bodies or credentials. `replay` runs the
+    /// download again against it
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
+    /// Export a trace of the download (one span per download, chunk and
+    /// retry) to this OTLP/HTTP collector, e.g. http://localhost:4318
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    otel_endpoint: Option<Url>,
+
+    /// Require HTTP/3 (QUIC), failing instead of falling back to HTTP/2 or
+    /// 1.1. Without it HTTP/3 is used when the server advertises it
+    #[cfg(feature = "http3")]
+    #[arg(long)]
+    http3: bool,
+
+    /// Try HTTP/3 first without waiting for the server to advertise it,
+    /// falling back to HTTP/2 or 1.1 if QUIC can't connect
+    #[cfg(feature = "http3")]
+    #[arg(long, conflicts_with = "http3")]
+    http3_prior_knowledge: bool,
+
+    /// Keep downloads that look like an error or login page (tiny, or HTML
+    /// where a binary was expected) instead of exiting with code 14
+    #[arg(long)]
+    allow_suspicious: bool,
+
+    /// The digest the download should have, as ALGO:HEX (sha256, sha512,
+    /// sha1, md5 or blake3) or a bare sha256 digest; a mismatch exits with
+    /// code 4
+    #[arg(long, value_name = "[ALGO:]HEX", group = "expected")]
+    checksum: Option<Checksum>,
+
+    /// The sha256 the download should have: its digest, or a sums file
+    /// (sha256sum's output) to look the file's name up in. Prints PASS or
+    /// FAIL; a mismatch exits with code 4
+    #[arg(long, value_name = "HEX|SUMS", group = "expected", value_parser = parse_sha256)]
+    sha256: Option<Expected>,
+
+    /// As --sha256, with a sha1 digest or sums file
+    #[arg(long, value_name = "HEX|SUMS", group = "expected", value_parser = parse_sha1)]
+    sha1: Option<Expected>,
+
+    /// As --sha256, with an md5 digest or sums file
+    #[arg(long, value_name = "HEX|SUMS", group = "expected", value_parser = parse_md5)]
+    md5: Option<Expected>,
+
+    /// The size the download must come to, e.g. from a release manifest
+    /// without digests: checked against the server's Content-Length before
+    /// anything is written, the bytes downloaded and the file on disk, and
+    /// a partial file to resume mustn't be past it. A mismatch exits with
+    /// code 11
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size, conflicts_with = "tail")]
+    expected_size: Option<u64>,
+
+    /// Leave a download that doesn't match its --checksum, --sha256, --sha1
+    /// or --md5 under its name, instead of renaming it to <name>.corrupt
+    #[arg(long, requires = "expected")]
+    keep_on_mismatch: bool,
+
+    /// What --checksum is the digest of: the file, or what it decompresses
+    /// to (gzip or zstd), for a .gz published with the checksum of its
+    /// contents. The file stays compressed (file or decompressed)
+    #[arg(
+        long,
+        requires = "expected",
+        conflicts_with = "store_compressed",
+        default_value = "file",
+        value_name = "WHAT"
+    )]
+    checksum_of: ChecksumOf,
+
+    /// Fetch the download's cosign signature from this URL once it's
+    /// hashed, a Sigstore bundle (JSON) or a bare signature, and check it.
+    /// A signature that doesn't hold exits with code 12; one that can't be
+    /// fetched, with 8
+    #[cfg(feature = "sigstore")]
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "cosign_signer",
+        conflicts_with = "many_urls"
+    )]
+    cosign_signature_url: Option<Url>,
+
+    /// The public key the --cosign-signature-url signature must be made
+    /// with, a PEM file as `cosign generate-key-pair` writes
+    #[cfg(feature = "sigstore")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        group = "cosign_signer",
+        requires = "cosign_signature_url",
+        conflicts_with = "cosign_certificate_identity"
+    )]
+    cosign_key: Option<PathBuf>,
+
+    /// For keyless signing: the identity, e.g. an email or a workflow URL,
+    /// the --cosign-signature-url bundle's certificate must be issued to
+    #[cfg(feature = "sigstore")]
+    #[arg(
+        long,
+        value_name = "IDENTITY",
+        group = "cosign_signer",
+        requires_all = ["cosign_signature_url", "cosign_oidc_issuer"]
+    )]
+    cosign_certificate_identity: Option<String>,
+
+    /// For keyless signing: the OIDC issuer that vouched for
+    /// --cosign-certificate-identity, e.g.
+    /// https://token.actions.githubusercontent.com
+    #[cfg(feature = "sigstore")]
+    #[arg(long, value_name = "URL", requires = "cosign_certificate_identity")]
+    cosign_oidc_issuer: Option<String>,
+
+    /// Fail before writing anything when the server's Content-Type
+    /// contradicts the file's extension (e.g. text/html for an .iso),
+    /// instead of warning
+    #[arg(long)]
+    strict_content_type: bool,
+
+    /// Fail on anything dlm would otherwise carry on past with a warning,
+    /// exiting with the code of the error it stands in for, or 10. The
+    /// warnings are listed by `dlm diagnostics list`
+    #[arg(long)]
+    strict: bool,
+
+    /// Only warn about this one under --strict, by its ID in `dlm
+    /// diagnostics list`; can be repeated
+    #[arg(long, value_name = "WARNING", requires = "strict", value_parser = WarningId::from_str)]
+    strict_allow: Vec<WarningId>,
+
+    /// What to do with a .torrent URL or a response served as a torrent,
+    /// which dlm otherwise refuses, not being a BitTorrent client:
+    /// `webseeds` downloads a single-file torrent's content from the HTTP
+    /// seeds it lists and checks it against the piece hashes (needs the
+    /// torrent feature); `save` keeps the .torrent file itself
+    #[arg(long, value_name = "MODE")]
+    torrent: Option<TorrentMode>,
+
+    /// Store the file compressed as <name>.gz or <name>.zst, with gzip
+    /// (levels 0-9, 6 by default) or zstd (1-22, 3 by default; needs the
+    /// zstd feature). The SHA-256 printed is of the content; such a file
+    /// can't be resumed
+    #[arg(
+        long,
+        value_name = "FORMAT[:LEVEL]",
+        conflicts_with_all = ["resume", "continue_at", "adopt", "tail", "name_by_hash", "cache_dir", "torrent"]
+    )]
+    store_compressed: Option<Compression>,
+
+    /// Ask for the file uncompressed with Accept-Encoding: identity, in
+    /// place of any --header's, and fail if the server compresses it
+    /// anyway, for a file that must match the published checksum of the
+    /// plain bytes. Otherwise a compressed response is saved as it's sent
+    #[arg(long)]
+    no_compression: bool,
+
+    /// Only download if the remote file changed after this RFC 3339
+    /// timestamp (e.g. 2024-05-01T12:00:00Z), or after this file's
+    /// modification time; otherwise skip it and exit successfully
+    #[arg(long, value_name = "TIMESTAMP|PATH", value_parser = utils::parse_newer_than)]
+    newer_than: Option<NewerThan>,
+
+    /// If the URL serves a small HTML page instead of the file, follow its
+    /// meta refresh or its one link to the expected file (once)
+    #[arg(long, conflicts_with = "tail")]
+    follow_landing_page: bool,
+
+    /// Download an asset of this GitHub repository's latest release, the
+    /// one --asset-pattern matches, as github://OWNER/REPO/PATTERN does
+    #[arg(
+        long,
+        value_name = "OWNER/REPO",
+        conflicts_with = "url",
+        requires = "asset_pattern"
+    )]
+    github_latest: Option<String>,
+
+    /// As --github-latest, for a GitLab project
+    #[arg(
+        long,
+        value_name = "GROUP/PROJECT",
+        conflicts_with_all = ["url", "github_latest"],
+        requires = "asset_pattern"
+    )]
+    gitlab_latest: Option<String>,
+
+    /// The name of the release asset to download, where * is any run of
+    /// characters and ? any one (e.g. '*linux-x86_64*'); exactly one asset
+    /// must match
+    #[arg(long, value_name = "PATTERN")]
+    asset_pattern: Option<String>,
+
+    /// Bearer token sent with every request. For a github:// or gitlab://
+    /// URL it's only sent to the releases API, for private projects and a
+    /// higher rate limit
+    #[arg(
+        long,
+        env = "DM_BEARER_TOKEN",
+        value_name = "TOKEN",
+        value_parser = utils::parse_token,
+        hide_env_values = true
+    )]
+    bearer_token: Option<String>,
+
+    /// Get a bearer token by POSTing --auth-token-body to this URL before
+    /// the download, sent like --bearer-token; a request answered 401 gets
+    /// a new one and is sent again with it
+    #[arg(long, value_name = "URL", conflicts_with_all = ["bearer_token", "user"])]
+    auth_token_url: Option<Url>,
+
+    /// The body of the --auth-token-url request, sent as application/json
+    /// when it's JSON and as a form otherwise
+    #[arg(long, value_name = "BODY", requires = "auth_token_url")]
+    auth_token_body: Option<String>,
+
+    /// Where the token is in the --auth-token-url answer, e.g.
+    /// $.data.access_token
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "$.token",
+        requires = "auth_token_url"
+    )]
+    auth_token_jsonpath: TokenPath,
+
+    /// Root of the releases API, for GitHub Enterprise or a self-hosted
+    /// GitLab (e.g. https://gitlab.example.com/api/v4)
+    #[arg(long, value_name = "URL")]
+    releases_api: Option<Url>,
+
+    /// Limit the download to this many bytes per second (e.g. 500k, 2M),
+    /// shared by all workers
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    limit_rate: Option<u64>,
+
+    /// Start worker mode's chunks spread evenly over this long (e.g. 5s)
+    /// rather than all at once, to go easy on small servers
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+    ramp_up: Option<Duration>,
+
+    /// Wait a random while of up to this many milliseconds before each new
+    /// request: a chunk's first, its retries, a batch's downloads
+    #[arg(long, default_value_t = 0, value_name = "MS")]
+    request_jitter: u64,
+
+    /// Send at most this many requests a second (e.g. 2, or 0.5 for one
+    /// every other second) over the whole run
+    #[arg(long, value_name = "N", value_parser = parse_request_rate)]
+    max_requests_per_second: Option<f64>,
+
+    /// Listen on this Unix socket (named pipe on Windows) for JSON-RPC
+    /// requests to check on, pause, resume, cancel or rate-limit the
+    /// download; see `dlm ctl`
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    control_socket: Option<PathBuf>,
+
+    /// Serve a read-only status page for a browser on this address, or on
+    /// localhost at this port (e.g. 8080, 0.0.0.0:8080)
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = web_status::parse_address,
+        conflicts_with = "dry_run"
+    )]
+    web_status: Option<SocketAddr>,
+
+    /// Keep the download's status, as --web-status serves it, in this JSON
+    /// file, replaced whole every --status-interval. It's removed once the
+    /// download succeeds, and left with how it ended otherwise
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    status_file: Option<PathBuf>,
+
+    /// How often --status-file is rewritten, e.g. 500ms
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        default_value = "1s",
+        value_parser = utils::parse_duration,
+        requires = "status_file"
+    )]
+    status_interval: Duration,
+
+    /// Leave --status-file in place after a successful download, with its
+    /// final state
+    #[arg(long, requires = "status_file")]
+    keep_status_file: bool,
+
+    /// Don't show progress in the terminal title
+    #[arg(long)]
+    no_title: bool,
+
+    /// Only download the last N bytes (e.g. 64k) into `<name>.tail`, using a
+    /// suffix range. The remote file size is taken from Content-Range.
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size, conflicts_with = "resume")]
+    tail: Option<u64>,
+
+    /// Start the download with this method instead of GET, for endpoints
+    /// like `POST /export` that stream the file back. POST and PUT can't be
+    /// resumed or split between workers
+    #[arg(long, default_value = "GET", value_parser = parse_method)]
+    method: Method,
+
+    /// Request body to send with --method
+    #[arg(long, value_name = "STRING", conflicts_with = "data_file")]
+    data: Option<String>,
+
+    /// Send this file's contents as the --method request body
+    #[arg(long, value_name = "PATH")]
+    data_file: Option<PathBuf>,
+
+    /// Content-Type of the --data or --data-file body, e.g. application/json
+    #[arg(long, value_name = "TYPE")]
+    content_type: Option<String>,
+
+    /// Send this header with every request, as 'Name: value'; can be
+    /// repeated
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = utils::parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Basic auth for the server, as 'user:password'. Like --bearer-token
+    /// it's left out of the requests to other hosts a redirect leads to
+    #[arg(
+        long,
+        env = "DM_USER",
+        value_name = "USER:PASSWORD",
+        value_parser = utils::parse_user,
+        conflicts_with = "bearer_token",
+        hide_env_values = true
+    )]
+    user: Option<(String, String)>,
+
+    /// Send this as User-Agent rather than dlm/<version>
+    #[arg(long, value_name = "AGENT", value_parser = utils::parse_header_value)]
+    user_agent: Option<HeaderValue>,
+
+    /// Label the download with this tag in usage.jsonl; can be repeated or
+    /// given a comma-separated list
+    #[arg(long = "tag", value_name = "TAG", value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Keep downloads in this directory with their ETag and Last-Modified;
+    /// later runs ask the server if they changed and reuse them if not
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["resume", "continue_at", "tail"])]
+    cache_dir: Option<PathBuf>,
+
+    /// Warn when the target directory and the download together would pass
+    /// this size (e.g. 50G)
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    dir_quota: Option<u64>,
+
+    /// Refuse to download when the target directory and the download
+    /// together would pass this size
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    dir_quota_hard: Option<u64>,
+
+    /// Download even past --dir-quota-hard or an enforced `usage --limit`,
+    /// or to a filesystem that can't hold a file that big (FAT32 stops at
+    /// 4 GiB)
+    #[arg(long)]
+    force: bool,
+
+    /// Evict the least recently used downloads once --cache-dir grows past
+    /// this size
+    #[arg(long, default_value = "10G", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    cache_size: u64,
+
+    /// Only print what would be downloaded, where, and how, then exit
+    /// without writing anything
+    #[arg(long, conflicts_with = "tail")]
+    dry_run: bool,
+
+    /// Where to keep manifests of downloads in flight, for `status` and
+    /// `resume` [default: ~/.local/state/download-manager]
+    #[arg(long, value_name = "PATH")]
+    state_dir: Option<PathBuf>,
+
+    /// Download the URLs on the clipboard, one after another, after
+    /// listing them and asking
+    #[cfg(feature = "clipboard")]
+    #[arg(long, group = "many_urls", conflicts_with_all = ["url", "input_file"])]
+    from_clipboard: bool,
+
+    /// Download every URL listed in this file, or on stdin for '-', one a
+    /// line; blank lines, '#' comments and repeats are skipped. 'key =
+    /// value' lines under a URL set its output, checksum, tags, header or
+    /// workers, and before the first URL every entry's. They share
+    /// --limit-rate and --retry-budget. A URL failing doesn't stop the
+    /// others, and the batch fails at the end if any did
+    #[arg(
+        long,
+        value_name = "PATH",
+        group = "many_urls",
+        conflicts_with_all = ["url", "output", "control_socket", "web_status", "status_file"]
+    )]
+    input_file: Option<PathBuf>,
+
+    /// How many --input-file downloads run at once, each with the
+    /// command's own workers, or how many URLs its --dry-run asks about
+    #[arg(long, default_value = "3", value_name = "N", requires = "input_file")]
+    parallel_downloads: NonZeroUsize,
+
+    /// Skip the rest of an --input-file's entries on a host for a minute
+    /// after this many of its downloads in a row failed because of it (it
+    /// couldn't be reached, timed out or answered 5xx); then one is tried
+    /// again
+    #[arg(long, default_value = "3", value_name = "N", requires = "input_file")]
+    host_failure_threshold: NonZeroU32,
+
+    /// Try every --input-file entry, however often its host failed
+    #[arg(
+        long,
+        requires = "input_file",
+        conflicts_with = "host_failure_threshold"
+    )]
+    no_host_circuit_breaker: bool,
+
+    /// Keep what a --dry-run of an --input-file finds out about each URL in
+    /// this JSON file, and don't ask the server again about one it found
+    /// less than --preflight-max-age ago
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "input_file",
+        requires = "dry_run"
+    )]
+    preflight_cache: Option<PathBuf>,
+
+    /// How long what --preflight-cache keeps of a URL is trusted, e.g. 30m
+    #[arg(
+        long,
+        value_name = "AGE",
+        default_value = "1h",
+        value_parser = utils::parse_duration,
+        requires = "preflight_cache"
+    )]
+    preflight_max_age: Duration,
+
+    /// Don't ask before downloading what --from-clipboard found
+    #[cfg(feature = "clipboard")]
+    #[arg(short, long, requires = "from_clipboard")]
+    yes: bool,
+
+    /// Download every URL from --input-file or --from-clipboard into a
+    /// staging directory and move them into the target directory only once
+    /// all have succeeded
+    #[arg(long, requires = "many_urls", conflicts_with_all = ["output", "dry_run"])]
+    all_or_nothing: bool,
+
+    /// Remove the staged files when --all-or-nothing rolls back, instead of
+    /// keeping them to resume
+    #[arg(long, requires = "all_or_nothing")]
+    remove_on_error: bool,
+
+    /// What to do with a download whose file holds something else: a
+    /// --from-clipboard or --input-file URL saved to the same file as an
+    /// earlier one, or a
+    /// --resume onto a file that isn't the start of the URL's. Rename it,
+    /// skip it, or refuse to start (rename, skip or error)
+    #[arg(long, default_value = "rename", value_name = "ACTION")]
+    on_conflict: OnConflict,
+
+    /// The download `resume <id>` is continuing.
+    #[arg(skip)]
+    resumed: Option<Manifest>,
+
+    /// The flags taken from `DM_*` variables.
+    #[arg(skip)]
+    from_env: Vec<EnvFlag>,
+
+    /// dlm's flags as settled, for `config show`.
+    #[arg(skip)]
+    settings: Vec<env_flags::Setting>,
+
+    /// The resolver every client of this run shares.
+    #[arg(skip)]
+    dns: DnsCache,
+
+    /// The `--retry-budget` every download of this run spends.
+    #[arg(skip)]
+    budget: Arc<RetryBudget>,
+
+    /// The buffers `--max-memory` caps, shared by every download of this
+    /// run.
+    #[arg(skip)]
+    memory: Arc<MemoryLimit>,
+
+    /// `--auth-token-url`'s tokens, shared by every client of this run.
+    #[arg(skip)]
+    auth_token: Option<Arc<AuthToken>>,
+
+    /// What the download's connections negotiated; an `--input-file`
+    /// entry has one of its own.
+    #[arg(skip)]
+    tls: SessionLog,
+
+    /// `--ramp-up`, `--request-jitter` and `--max-requests-per-second`,
+    /// shared by every download of the run.
+    #[arg(skip)]
+    pacing: Pacing,
+
+    /// Print the --dry-run plan, or how an --input-file run went, as JSON;
+    /// each download's line goes to stderr instead
+    #[arg(long, requires = "json_output")]
+    json: bool,
+
+    /// Print a table of how the download went once it's done: size, time,
+    /// time to first byte (and its spread across chunks in worker mode),
+    /// and the speed after the first byte
+    #[arg(long, conflicts_with = "many_urls")]
+    stats: bool,
+
+    /// Print this once the download is done, as curl does: %{url},
+    /// %{filename_effective}, %{size_download}, %{speed_download},
+    /// %{time_total}, %{time_starttransfer}, %{ttfb_min_ms},
+    /// %{ttfb_median_ms}, %{ttfb_max_ms}, %{sha256}, %{num_retries} or
+    /// %{json}, with \n and \t for a newline and a tab
+    #[arg(long, value_name = "FORMAT", conflicts_with = "many_urls")]
+    write_out: Option<WriteOut>,
+
+    /// Leave what differs from run to run out of --progress json: no
+    /// progress, chunk_completed or message events and no duration_ms, so
+    /// the same download writes the same lines, for golden tests and
+    /// reproducible pipelines
+    #[arg(long)]
+    json_deterministic: bool,
+
+    /// Bind outgoing connections to this interface name or source IP
+    #[arg(long, value_name = "NAME_OR_IP")]
+    interface: Option<String>,
+
+    /// Send requests through this proxy rather than the one HTTPS_PROXY,
+    /// HTTP_PROXY or ALL_PROXY names. NO_PROXY still applies
+    #[arg(long, value_name = "URL", value_parser = utils::parse_proxy)]
+    proxy: Option<Url>,
+
+    /// User for Basic auth to the proxy from --proxy, HTTPS_PROXY,
+    /// HTTP_PROXY or ALL_PROXY, overriding any in its URL
+    #[arg(
+        long,
+        env = "DM_PROXY_USER",
+        value_name = "USER",
+        requires = "proxy_password"
+    )]
+    proxy_user: Option<String>,
+
+    /// Password for --proxy-user
+    #[arg(
+        long,
+        env = "DM_PROXY_PASSWORD",
+        value_name = "PASSWORD",
+        requires = "proxy_user",
+        hide_env_values = true
+    )]
+    proxy_password: Option<String>,
+
+    /// Send loopback and link-local hosts (localhost, 127.0.0.1, ::1,
+    /// 169.254.0.0/16, fe80::/10) through the proxy too, rather than
+    /// straight to them. NO_PROXY still applies
+    #[arg(long)]
+    proxy_all: bool,
+
+    /// Only use IPv4 addresses
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only use IPv6 addresses
+    #[arg(short = '6', long)]
+    ipv6: bool,
+
+    /// Seconds without any data before a transfer counts as stalled and is re-requested
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    stall_timeout: u64,
+
+    /// Seconds a connection may take to open, TLS included, before it
+    /// fails and is retried like any other; 0 waits for as long as the
+    /// system does
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    connect_timeout: u64,
+
+    /// The oldest TLS version an https:// download may use, 1.2 or 1.3. A
+    /// host that can't meet it is refused before any request; -v and the
+    /// summary say what it negotiated either way
+    #[arg(long, value_name = "VERSION")]
+    min_tls: Option<TlsVersion>,
+
+    /// Seconds a response may go without a byte before the read fails and
+    /// the rest is re-requested, counting as one of --retries; sooner than
+    /// --stall-timeout, which only checks every so often
+    #[arg(long, value_name = "SECS")]
+    read_timeout: Option<u64>,
+
+    /// Minimum average speed in bytes/s, enforced over --min-speed-time
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    min_speed: Option<u64>,
+
+    /// Window in seconds over which --min-speed is measured
+    #[arg(
+        long,
+        default_value_t = 30,
+        value_name = "SECS",
+        requires = "min_speed"
+    )]
+    min_speed_time: u64,
+
+    /// How to show speeds: bytes-binary (MiB/s), bytes-si (MB/s) or bits
+    /// (Mbit/s)
+    #[arg(long, default_value = "bytes-binary", value_name = "UNITS")]
+    speed_units: SpeedUnits,
+
+    /// Abort when the smoothed speed stays below this rate (e.g. 1M) for --min-avg-window
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    min_avg_speed: Option<u64>,
+
+    /// How long the speed may stay below --min-avg-speed (e.g. 60s, 5m)
+    #[arg(
+        long,
+        default_value = "60s",
+        value_name = "DURATION",
+        value_parser = utils::parse_duration,
+        requires = "min_avg_speed"
+    )]
+    min_avg_window: Duration,
+
+    /// With workers: once some chunks are done, re-request the rest of a
+    /// chunk that stays below this rate (e.g. 200k) on a new connection,
+    /// keeping what it already has
+    #[arg(long, value_name = "SIZE", value_parser = utils::parse_byte_size)]
+    chunk_min_speed: Option<u64>,
+
+    /// How long a chunk may stay below --chunk-min-speed (e.g. 10s)
+    #[arg(
+        long,
+        default_value = "10s",
+        value_name = "DURATION",
+        value_parser = utils::parse_duration,
+        requires = "chunk_min_speed"
+    )]
+    chunk_min_speed_grace: Duration,
+}
+
+/// An `--all-or-nothing` run's [`Transaction`], with the guard applying
+/// `--remove-on-error` to what it staged.
+struct Staging {
+    transaction: Transaction,
+    staged: PartialFileGuard,
+    target: PathBuf,
+}
+
+impl Staging {
+    /// Where the downloads are saved until the commit.
+    fn dir(&self) -> &Path {
+        self.transaction.staging_dir()
+    }
+
+    /// Moves the staged files into the target directory if `result`, the
+    /// downloads', is a success, and rolls the transaction back otherwise.
+    fn finish(self, result: anyhow::Result<()>, overwrite: bool) -> anyhow::Result<()> {
+        if let Err(error) = result {
+            let context = match self.staged.apply()? {
+                Some(removed) => format!(
+                    "Transaction rolled back, nothing was moved and the staged files were {removed}"
+                ),
+                None => "Transaction rolled back, nothing was moved".to_string(),
+            };
+            return Err(error.context(context));
+        }
+        self.staged.disarm();
+        let files = self
+            .transaction
+            .commit(overwrite)
+            .context("Transaction not committed")?;
+        println!(
+            "Transaction committed: moved {} files into {}",
+            files.len(),
+            self.target.display()
+        );
+        Ok(())
+    }
+}
+
+impl Cli {
+    /// Parses the command line, taking each flag it doesn't give from its
+    /// `DM_*` variable, if that's set.
+    pub fn parse_with_env() -> Self {
+        Self::try_parse_with_env(std::env::args_os()).unwrap_or_else(|error| error.exit())
+    }
+
+    fn try_parse_with_env<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let command = env_flags::with_env(Cli::command());
+        let mut matches = command.clone().try_get_matches_from(args)?;
+        let from_env = env_flags::from_env(&command, &matches);
+        let settings = env_flags::settings(&command, &matches);
+        let mut cli = Cli::from_arg_matches_mut(&mut matches)?;
+        cli.from_env = from_env;
+        cli.settings = settings;
+        cli.pacing = Pacing::new(
+            cli.ramp_up.unwrap_or_default(),
+            Duration::from_millis(cli.request_jitter),
+            cli.max_requests_per_second,
+        );
+        Ok(cli)
+    }
+
+    /// The steps after a download that the flags ask for: hashing it,
+    /// looking for an error page, `--checksum`, `--cosign-signature-url`.
+    /// The ones that depend on the download are added to it after.
+    fn post_processing(&self, interrupted: &Arc<AtomicBool>) -> Pipeline {
+        let mut pipeline = Pipeline::default().with_step(post_steps::Hash {
+            interrupted: interrupted.clone(),
+        });
+        // A `--tail` is meant to be a small piece of the file, and a
+        // compressed one is meant to be smaller than announced. A
+        // `--checksum` tells for sure.
+        let expected = self.expected();
+        if self.tail.is_none() && self.store_compressed.is_none() && expected.is_none() {
+            pipeline.push(post_steps::Suspicious {
+                allow: self.allow_suspicious,
+            });
+        }
+        if let Some(expected) = expected {
+            let of = match (self.checksum_of, self.store_compressed) {
+                // What was downloaded is in there compressed; its SHA-256
+                // is already that of what was sent.
+                (ChecksumOf::File, Some(_)) if expected.algorithm() != Algorithm::Sha256 => {
+                    ChecksumOf::Decompressed
+                }
+                (of, _) => of,
+            };
+            pipeline.push(post_steps::Verify {
+                expected,
+                of,
+                keep: self.keep_on_mismatch,
+                interrupted: interrupted.clone(),
+            });
+        }
+        #[cfg(feature = "sigstore")]
+        if let Some(cosign) = self.cosign() {
+            pipeline.push(post_steps::Signature {
+                cosign,
+                client_options: self.client_options(),
+                retry_budget: self.budget.clone(),
+            });
+        }
+        pipeline
+    }
+
+    /// The signature `--cosign-signature-url` checks the download against,
+    /// and who `--cosign-key` or `--cosign-certificate-identity` says it
+    /// must be from.
+    #[cfg(feature = "sigstore")]
+    fn cosign(&self) -> Option<Cosign> {
+        let signer = match (&self.cosign_key, &self.cosign_certificate_identity) {
+            (Some(key), _) => Signer::Key(key.clone()),
+            (None, Some(identity)) => Signer::Keyless {
+                identity: identity.clone(),
+                issuer: self.cosign_oidc_issuer.clone()?,
+            },
+            (None, None) => return None,
+        };
+        Some(Cosign {
+            url: self.cosign_signature_url.clone()?,
+            signer,
+        })
+    }
+
+    /// The digest the download should have, from whichever of
+    /// `--checksum`, `--sha256`, `--sha1` and `--md5` was given.
+    fn expected(&self) -> Option<Expected> {
+        let checksum = self.checksum.clone().map(|checksum| Expected::Digest {
+            checksum,
+            flag: "--checksum",
+        });
+        checksum
+            .or_else(|| self.sha256.clone())
+            .or_else(|| self.sha1.clone())
+            .or_else(|| self.md5.clone())
+    }
+
+    /// Where `--error-report` goes, if anywhere.
+    pub fn error_report(&self) -> Option<PathBuf> {
+        self.error_report.clone()
+    }
+
+    /// Starts the `--record` transcript, if asked for one.
+    pub fn record(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.record else {
+            return Ok(());
+        };
+        let env = self
+            .from_env
+            .iter()
+            .filter_map(|flag| Some((flag.variable.clone(), flag.value.clone()?)))
+            .collect();
+        transcript::record_to(
+            path,
+            env_flags::redacted_args(std::env::args().skip(1)),
+            env,
+        )
+    }
+
+    /// What the flags set for the whole process rather than for a
+    /// download.
+    fn globals(&self) -> Globals {
+        Globals {
+            show_secrets: self.show_secrets,
+            show_error_body: self.show_error_body,
+            presigned: self.presigned,
+            wait_for_target: self.wait_for_target,
+            wait_for_network: self.wait_for_network,
+            max_rate_limit_wait: self.max_rate_limit_wait,
+            strict: self.strict,
+            strict_allow: self.strict_allow.clone(),
+            speed_units: self.speed_units,
+        }
+    }
+
+    pub async fn execute(mut self, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let rotation = self.log_max_size.map(|max_size| Rotation {
+            max_size,
+            keep: self.log_keep,
+        });
+        let _logging = logging::init(
+            self.verbose,
+            self.log_file.as_deref(),
+            rotation,
+            self.otel_endpoint(),
+        )?;
+        for flag in &self.from_env {
+            tracing::info!("From the environment: {flag}");
+        }
+        self.globals().apply();
+        self.budget = self.new_budget();
+        self.memory = Arc::new(MemoryLimit::new(self.max_memory));
+        self.fetch_auth_token().await?;
+        // A dry run writes nothing, and a debug build checks nothing got
+        // past fs_ops to the target directory.
+        let untouched = self.dry_run.then(|| {
+            fs_ops::forbid();
+            fs_ops::snapshot(&self.target_directory)
+        });
+        let target_directory = self.target_directory.clone();
+        let result = match &self.input_file {
+            Some(input) => self.download_batch(input, shutdown).await,
+            #[cfg(feature = "clipboard")]
+            None if self.from_clipboard => self.download_clipboard(shutdown).await,
+            None => self.command.execute(&self, shutdown).await,
+        };
+        if let Some(before) = untouched {
+            for operation in fs_ops::refused() {
+                tracing::warn!(
+                    "--dry-run refused to {} '{}'",
+                    operation.what,
+                    operation.path.display()
+                );
+            }
+            debug_assert!(
+                fs_ops::snapshot(&target_directory) == before,
+                "--dry-run changed '{}'",
+                target_directory.display()
+            );
+        }
+        // Warnings that couldn't stop the download fail it once it's over.
+        result.and_then(|()| Ok(diagnostics::check_strict()?))
+    }
+
+    /// `--from-clipboard`: runs the command once per URL on the clipboard,
+    /// stopping at the first that fails. With `--all-or-nothing` they're
+    /// downloaded as one [`Transaction`].
+    #[cfg(feature = "clipboard")]
+    async fn download_clipboard(mut self, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let urls = clipboard::urls()?;
+        if urls.len() > 1 && self.output.is_some() {
+            bail!(
+                "--output names one file, but the clipboard holds {} URLs",
+                urls.len()
+            );
+        }
+        if !self.yes && !clipboard::confirm(&urls)? {
+            println!("Nothing downloaded");
+            return Ok(());
+        }
+        let destinations: Vec<_> = urls
+            .iter()
+            .map(|url| match &self.output {
+                Some(output) => self.target_directory.join(output),
+                None => utils::build_download_path(url, &self.target_directory),
+            })
+            .collect();
+        let plan = self.plan_batch(&destinations)?;
+        let Some(staging) = self.stage()? else {
+            return self.download_each(&urls, &plan, shutdown).await;
+        };
+        self.target_directory = staging.dir().to_path_buf();
+        let result = self.download_each(&urls, &plan, shutdown).await;
+        staging.finish(result, self.overwrite)
+    }
+
+    /// Begins the [`Transaction`] `--all-or-nothing` downloads into the
+    /// target directory through, or none without it.
+    fn stage(&self) -> anyhow::Result<Option<Staging>> {
+        if !self.all_or_nothing {
+            return Ok(None);
+        }
+        utils::prepare_target_dir(&self.target_directory)?;
+        let transaction = Transaction::begin(&self.target_directory)?.with_removal(self.removal());
+        // Until the commit, even a panic leaves the staged files to the policy.
+        let staged = transaction.staged(self.remove_on_error);
+        Ok(Some(Staging {
+            transaction,
+            staged,
+            target: self.target_directory.clone(),
+        }))
+    }
+
+    /// Finds the URLs that would be saved to the same file as an earlier
+    /// one, by their `destinations`, and settles them per `--on-conflict`.
+    /// With `error`, refuses the batch if there are any.
+    fn plan_batch(&self, destinations: &[PathBuf]) -> anyhow::Result<Vec<Planned>> {
+        let plan = conflicts::plan(destinations, self.on_conflict);
+        if self.on_conflict != OnConflict::Error {
+            return Ok(plan);
+        }
+        let conflicts: Vec<_> = plan
+            .iter()
+            .zip(destinations)
+            .enumerate()
+            .filter_map(|(index, (planned, destination))| match planned {
+                Planned::Conflict { conflicts_with } => Some(format!(
+                    "URL {}: '{}' conflicts with URL {}",
+                    index + 1,
+                    destination.display(),
+                    conflicts_with + 1
+                )),
+                _ => None,
+            })
+            .collect();
+        if !conflicts.is_empty() {
+            bail!(
+                "Nothing downloaded, the destinations of some URLs conflict ({}); pass --on-conflict rename or skip",
+                conflicts.join(", ")
+            );
+        }
+        Ok(plan)
+    }
+
+    /// Runs the command once per URL, stopping at the first that fails.
+    /// Entries `plan` renamed or left out are said so as they come up.
+    #[cfg(feature = "clipboard")]
+    async fn download_each(
+        &mut self,
+        urls: &[Url],
+        plan: &[Planned],
+        shutdown: &Shutdown,
+    ) -> anyhow::Result<()> {
+        let count = urls.len();
+        for (index, (url, planned)) in urls.iter().zip(plan).enumerate() {
+            let redacted = http::redact_url(url);
+            let output = self.output.clone();
+            match planned {
+                Planned::Keep => {}
+                Planned::Renamed {
+                    path,
+                    conflicts_with,
+                } => {
+                    let name = path.file_name().unwrap_or_default();
+                    println!(
+                        "Saving URL {} of {count} as '{}', its name is taken by URL {}: {redacted}",
+                        index + 1,
+                        name.to_string_lossy(),
+                        conflicts_with + 1
+                    );
+                    self.output = Some(PathBuf::from(name));
+                }
+                Planned::Conflict { conflicts_with } => {
+                    println!(
+                        "Skipped URL {} of {count}, its destination conflicts with URL {}: {redacted}",
+                        index + 1,
+                        conflicts_with + 1
+                    );
+                    continue;
+                }
+            }
+            self.url = Some(url.clone());
+            let result = self.command.execute(self, shutdown).await;
+            self.output = output;
+            // A full directory won't have room for the rest either.
+            let error = result.as_ref().err().and_then(|error| error.downcast_ref());
+            if let Some(DownloadError::OverQuota { .. }) = error {
+                for (index, url) in urls.iter().enumerate().skip(index + 1) {
+                    println!(
+                        "Skipped URL {} of {count}, over --dir-quota-hard: {}",
+                        index + 1,
+                        http::redact_url(url)
+                    );
+                }
+            }
+            result.with_context(|| format!("URL {} of {count}: {redacted}", index + 1))?;
+        }
+        Ok(())
+    }
+
+    /// `--input-file`: downloads every URL the file lists,
+    /// `--parallel-downloads` at a time under its entry's settings, in one
+    /// pool sharing `--limit-rate` and `--retry-budget`, then sums up how
+    /// they went. Destinations that clash are settled per `--on-conflict`
+    /// before anything starts, and files already there are left alone
+    /// unless `--resume` or `--overwrite` says otherwise. With `--dry-run`,
+    /// the server is asked about each instead.
+    async fn download_batch(&self, input: &Path, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let listing = batch::read(input)?;
+        let workers = match self.command {
+            Commands::DownloadAsync { workers } => Some(workers),
+            Commands::DownloadBlocking => None,
+            _ => bail!("--input-file downloads with download-async or download-blocking"),
+        };
+        if let Some(entry) = listing
+            .entries
+            .iter()
+            .find(|entry| entry.settings.workers.is_some())
+            && workers.is_none()
+        {
+            bail!(
+                "Line {}, workers: only download-async splits a download between workers",
+                entry.line
+            );
+        }
+        // With --json, stdout is left to the summary.
+        let stdout = match self.json && !self.dry_run {
+            true => Some(batch::StdoutToStderr::new()?),
+            false => None,
+        };
+        let mut summary = Summary::default();
+        if !self.dry_run {
+            for (line, text) in &listing.invalid {
+                println!("Line {line}: failed, not a URL: {text}");
+                summary.add(Outcome::Failed);
+            }
+            for (line, first) in &listing.repeats {
+                println!("Line {line}: skipped, the same URL as line {first}");
+            }
+        }
+        if listing.entries.is_empty() && listing.invalid.is_empty() {
+            println!("Nothing to download, '{}' lists no URLs", input.display());
+            return Ok(());
+        }
+        let destinations: Vec<_> = listing
+            .entries
+            .iter()
+            .map(|entry| match &entry.settings.output {
+                Some(output) => self.target_directory.join(output),
+                None => utils::build_download_path(&entry.url, &self.target_directory),
+            })
+            .collect();
+        let plan = self.plan_batch(&destinations)?;
+        let count = listing.entries.len();
+        let mut queue = Vec::new();
+        for (index, (entry, planned)) in listing.entries.iter().zip(&plan).enumerate() {
+            let label = format!("URL {} of {count}", index + 1);
+            let redacted = http::redact_url(&entry.url);
+            let mut destination = destinations[index].clone();
+            let skipped = match planned {
+                Planned::Keep => None,
+                Planned::Renamed {
+                    path,
+                    conflicts_with,
+                } => {
+                    if !self.dry_run {
+                        println!(
+                            "{label}: saving as '{}', its name is taken by URL {}: {redacted}",
+                            path.file_name().unwrap_or_default().to_string_lossy(),
+                            conflicts_with + 1
+                        );
+                    }
+                    destination = path.clone();
+                    None
+                }
+                Planned::Conflict { conflicts_with } => Some(format!(
+                    "its destination conflicts with URL {}",
+                    conflicts_with + 1
+                )),
+            };
+            let skipped = skipped.or_else(|| {
+                (destination.exists() && !self.resume && !self.overwrite).then(|| {
+                    format!(
+                        "'{}' exists; pass --resume or --overwrite",
+                        destination.display()
+                    )
+                })
+            });
+            queue.push((label, entry, destination, skipped));
+        }
+
+        if self.dry_run {
+            let previews = self.preview_batch(queue).await?;
+            let workers = workers.map_or(1, Workers::most);
+            dry_run::print_batch(&listing, &previews, workers, self.json)?;
+            let failed = previews
+                .iter()
+                .filter(|(_, preview)| preview.fails())
+                .count();
+            if failed > 0 {
+                bail!("{failed} of {count} URLs would fail");
+            }
+            return Ok(());
+        }
+
+        // The quota sizes it up before the first entry lands in it.
+        utils::prepare_target_dir(&self.target_directory)?;
+        let usage_before = match self.dir_quota.or(self.dir_quota_hard) {
+            Some(_) => Some(quota::dir_usage(&self.target_directory)?),
+            None => None,
+        };
+        let pool = DownloadPool::new(
+            self.client_options().build_async()?,
+            PoolOptions {
+                parallelism: self.parallel_downloads.get(),
+                // Each download has the connections its workers ask for.
+                per_host: usize::MAX,
+                rate_limit: self.limit_rate,
+                retry_budget: self.budget.clone(),
+                memory: Some(self.memory.clone()),
+            },
+        );
+        let staging = self.stage()?;
+        let directory = staging
+            .as_ref()
+            .map_or(self.target_directory.as_path(), Staging::dir);
+        let mut jobs = Vec::new();
+        let mut skipped = Vec::new();
+        for (label, entry, destination, reason) in queue {
+            let url = http::redact_url(&entry.url);
+            if let Some(reason) = reason {
+                println!("{label}: skipped, {reason}: {url}");
+                summary.add(Outcome::Skipped);
+                skipped.push(SkippedEntry { url, reason });
+                continue;
+            }
+            match self.batch_job(&label, entry, &destination, directory, shutdown) {
+                Ok(job) => jobs.push(job),
+                Err(error) => {
+                    println!("{label}: failed, {error:#}: {url}");
+                    summary.add(Outcome::Failed);
+                }
+            }
+        }
+        let circuits = (!self.no_host_circuit_breaker).then(|| {
+            Mutex::new(HostCircuits::new(
+                self.host_failure_threshold.get(),
+                host_health::COOLDOWN,
+            ))
+        });
+        let reports = batch::run(
+            &pool,
+            jobs,
+            self.parallel_downloads.get(),
+            circuits.as_ref(),
+            shutdown,
+        )
+        .await;
+        for report in reports {
+            summary.add(report.outcome);
+            if let Some(reason) = report.reason {
+                skipped.push(SkippedEntry {
+                    url: report.url,
+                    reason,
+                });
+            }
+        }
+        let total = count + listing.invalid.len();
+        let target_directory = match usage_before {
+            Some(before) => Some(DirUsage {
+                before,
+                after: quota::dir_usage(&self.target_directory)?,
+            }),
+            None => None,
+        };
+        let hosts = circuits
+            .map(|circuits| circuits.into_inner().unwrap().stats())
+            .unwrap_or_default();
+        drop(stdout);
+        if self.json {
+            let batch = BatchSummary {
+                total,
+                completed: summary.completed,
+                failed: summary.failed,
+                interrupted: summary.interrupted,
+                skipped,
+                not_newer: summary.not_newer,
+                retry_budget: self.budget.usage(),
+                hosts,
+                target_directory,
+            };
+            println!("{}", serde_json::to_string_pretty(&Versioned::new(batch))?);
+        } else {
+            println!("Downloaded {total} URLs: {summary}");
+            if let Some(DirUsage { before, after }) = target_directory {
+                println!(
+                    "Target directory: {} before, {} after",
+                    speed::Size(before),
+                    speed::Size(after)
+                );
+            }
+            for (host, stats) in hosts {
+                println!("  {host}: {stats}");
+            }
+        }
+        // Stopped by Ctrl+C, the batch exits as a download stopped by it does,
+        // and a staged one isn't moved into place: what an interrupted
+        // download saved isn't all of it.
+        let result = match summary.failed {
+            _ if summary.interrupted > 0 || shutdown.is_requested() => {
+                Err(DownloadError::Interrupted.into())
+            }
+            0 => Ok(()),
+            failed => Err(anyhow!("{failed} of {total} URLs failed")),
+        };
+        match staging {
+            Some(staging) => staging.finish(result, self.overwrite),
+            None => result,
+        }
+    }
+
+    /// `--dry-run` of `--input-file`: asks the server about every entry of
+    /// `queue` that isn't skipped, `--parallel-downloads` at a time, each
+    /// with its own headers, through `--preflight-cache` if given.
+    async fn preview_batch(
+        &self,
+        queue: Vec<(String, &batch::Entry, PathBuf, Option<String>)>,
+    ) -> anyhow::Result<Vec<(PathBuf, batch::Preview)>> {
+        let mut cache = self
+            .preflight_cache
+            .as_deref()
+            .map(|path| PreflightCache::load(path, self.preflight_max_age))
+            .transpose()?;
+        // Those to ask about are filled in once they're asked.
+        let mut previews = Vec::new();
+        let mut clients = Vec::new();
+        for (index, (_, entry, destination, skipped)) in queue.iter().enumerate() {
+            let preview = match skipped {
+                Some(reason) => Some(batch::Preview::Skipped(reason.clone())),
+                None => match self
+                    .entry_cli(entry, destination)
+                    .and_then(|cli| cli.client_options().build_async())
+                {
+                    Ok(client) => {
+                        clients.push((index, client));
+                        None
+                    }
+                    Err(error) => Some(batch::Preview::Failed(format!("{error:#}"))),
+                },
+            };
+            previews.push((destination.clone(), preview));
+        }
+        let concurrency = self.parallel_downloads.get();
+        // Those that haven't changed needn't be asked about any further.
+        if let Some(since) = self.newer_than {
+            let answers: Vec<_> = futures::stream::iter(&clients)
+                .map(|(index, client)| {
+                    newer::not_newer(
+                        client,
+                        &queue[*index].1.url,
+                        since,
+                        &self.budget,
+                        self.auth_token.as_deref(),
+                    )
+                })
+                .buffered(concurrency)
+                .collect()
+                .await;
+            for ((index, client), answer) in std::mem::take(&mut clients).into_iter().zip(answers) {
+                match answer {
+                    Ok(None) => clients.push((index, client)),
+                    Ok(Some(reason)) => previews[index].1 = Some(batch::Preview::NotNewer(reason)),
+                    Err(error) => {
+                        previews[index].1 = Some(batch::Preview::Failed(format!("{error:#}")))
+                    }
+                }
+            }
+        }
+        let checks: Vec<_> = clients
+            .iter()
+            .map(|(index, client)| (client, &queue[*index].1.url))
+            .collect();
+        let checked = preflight::check_each(
+            &checks,
+            concurrency,
+            cache.as_mut(),
+            &self.budget,
+            self.auth_token.as_deref(),
+        )
+        .await;
+        for ((index, _), checked) in clients.iter().zip(checked) {
+            previews[*index].1 = Some(batch::Preview::Checked(checked));
+        }
+        if let Some(cache) = &cache {
+            cache.save()?;
+        }
+        Ok(previews
+            .into_iter()
+            .map(|(destination, preview)| {
+                (destination, preview.expect("every entry was asked about"))
+            })
+            .collect())
+    }
+
+    /// This run's flags as an `--input-file` entry saved to `destination`
+    /// is downloaded with: its URL, under the entry's settings, without the
+    /// batch's own.
+    fn entry_cli(&self, entry: &batch::Entry, destination: &Path) -> anyhow::Result<Cli> {
+        let settings = &entry.settings;
+        let mut cli = self.clone();
+        cli.url = Some(entry.url.clone());
+        cli.tls = SessionLog::default();
+        cli.output = self.batch_output(entry, destination).map(PathBuf::from);
+        cli.input_file = None;
+        cli.all_or_nothing = false;
+        cli.remove_on_error = false;
+        if let Some(checksum) = &settings.checksum {
+            cli.checksum = Some(checksum.parse().map_err(|error: String| anyhow!(error))?);
+            (cli.sha256, cli.sha1, cli.md5) = (None, None, None);
+        }
+        for header in &settings.headers {
+            cli.headers
+                .push(utils::parse_header(header).map_err(|error| anyhow!(error))?);
+        }
+        cli.tags.extend(settings.tags.iter().cloned());
+        if let Some(workers) = settings.workers {
+            cli.command = Commands::DownloadAsync {
+                workers: Workers::Count(workers),
+            };
+        }
+        Ok(cli)
+    }
+
+    /// The download of an `--input-file` entry to `destination`: this run's
+    /// flags under the entry's settings. The pool it runs in brings the
+    /// rate limit. It's saved in `directory`, `--all-or-nothing`'s staging
+    /// directory if not the target one, where `destination` would be in the
+    /// target.
+    fn batch_job(
+        &self,
+        label: &str,
+        entry: &batch::Entry,
+        destination: &Path,
+        directory: &Path,
+        shutdown: &Shutdown,
+    ) -> anyhow::Result<batch::Job> {
+        let cli = self.entry_cli(entry, destination)?;
+        let destination = directory.join(
+            destination
+                .strip_prefix(&self.target_directory)
+                .unwrap_or(destination),
+        );
+        let workers = match cli.command {
+            Commands::DownloadAsync { workers } => workers,
+            _ => Workers::Count(1),
+        };
+        let interrupted = shutdown.child();
+        let client_options = cli.client_options();
+        let mut options = cli.transfer_options();
+        options.body = cli.request_body()?;
+        let request = DownloadRequest::new(entry.url.clone(), directory)
+            .workers(workers)
+            .options(options)
+            .client(client_options.build_async()?)
+            .interrupted(interrupted.clone());
+        Ok(batch::Job {
+            label: label.to_string(),
+            url: entry.url.clone(),
+            destination,
+            request,
+            phases: cli.phases(&client_options)?,
+            post_processing: cli.post_processing(&interrupted),
+            usage: cli.usage_log().map(|log| (log, cli.tags.clone())),
+            track: None,
+        })
+    }
+
+    /// The `--output` an `--input-file` entry saved to `destination` is
+    /// downloaded with: none when that's where its URL would go anyway.
+    fn batch_output<'a>(&self, entry: &batch::Entry, destination: &'a Path) -> Option<&'a OsStr> {
+        let default = utils::build_download_path(&entry.url, &self.target_directory);
+        (destination != default).then(|| {
+            destination
+                .strip_prefix(&self.target_directory)
+                .unwrap_or(destination)
+                .as_os_str()
+        })
+    }
+
+    /// Serves `--control-socket`, or the socket named "control" passed in by
+    /// systemd socket activation.
+    fn start_control(
+        &self,
+        throttle: Throttle,
+        memory: Arc<MemoryLimit>,
+        interrupted: Arc<AtomicBool>,
+    ) -> anyhow::Result<Option<(ControlSocket, ControlGuard)>> {
+        #[cfg(all(feature = "systemd", target_os = "linux"))]
+        if let Some(listener) = systemd::listener("control")? {
+            return ControlSocket::inherit(listener, throttle, memory, interrupted).map(Some);
+        }
+        self.control_socket
+            .as_deref()
+            .map(|path| ControlSocket::start(path, throttle, memory, interrupted))
+            .transpose()
+    }
+
+    /// Where manifests of downloads in flight are kept.
+    fn active_downloads(&self) -> anyhow::Result<ActiveDownloads> {
+        ActiveDownloads::open(self.state_dir.as_deref())
+            .context("Cannot create the state directory")?
+            .context("No home directory to keep state in, pass --state-dir")
+    }
+
+    /// Starts keeping a manifest of the download to `destination` for
+    /// `dlm status`. Not being able to isn't worth failing the download for.
+    fn track(&self, url: &Url, destination: &Path) -> Option<Tracker> {
+        let downloads = match ActiveDownloads::open(self.state_dir.as_deref()) {
+            Ok(downloads) => downloads?,
+            Err(error) => {
+                diagnostics::warn(
+                    WarningId::NotRecorded,
+                    format!("`dlm status` won't list this download: {error}"),
+                );
+                return None;
+            }
+        };
+        let args: Vec<_> = std::env::args().skip(1).collect();
+        let manifest = Manifest::new(
+            url.as_str(),
+            destination,
+            args.clone(),
+            env_flags::redacted_args(args),
+            self.resumed.as_ref(),
+        );
+        Some(downloads.track(manifest, self.dns.clone()))
+    }
+
+    /// The log of what every run transferred, for `dlm usage`. Not being
+    /// able to keep it isn't worth failing a download for.
+    fn usage_log(&self) -> Option<UsageLog> {
+        match UsageLog::open(self.state_dir.as_deref()) {
+            Ok(log) => log,
+            Err(error) => {
+                diagnostics::warn(
+                    WarningId::NotRecorded,
+                    format!("`dlm usage` won't count this download: {error}"),
+                );
+                None
+            }
+        }
+    }
+
+    /// `dlm usage`: sets or removes the limits, then prints the totals.
+    fn usage(
+        &self,
+        by: usage::Period,
+        limit: &[usage::Limit],
+        enforce: bool,
+        no_limit: bool,
+    ) -> anyhow::Result<()> {
+        let log = UsageLog::open(self.state_dir.as_deref())
+            .context("Cannot create the state directory")?
+            .context("No home directory to keep state in, pass --state-dir")?;
+        if no_limit {
+            log.set_limits(&usage::Limits::default())?;
+        } else if !limit.is_empty() {
+            let mut limits = usage::Limits {
+                enforce,
+                ..Default::default()
+            };
+            for limit in limit {
+                match limit.period {
+                    usage::Period::Month => limits.monthly = Some(limit.bytes),
+                    _ => limits.daily = Some(limit.bytes),
+                }
+            }
+            log.set_limits(&limits)?;
+        }
+        usage::print_usage(&log, by)
+    }
+
+    /// Pins the download `dlm resume` carries on with to the node it was
+    /// pinned to, if that still answers.
+    async fn reuse_pin(&self) {
+        let Some(pin) = self
+            .resumed
+            .as_ref()
+            .and_then(|manifest| manifest.pinned.clone())
+            .filter(|_| !self.no_pin_ip)
+        else {
+            return;
+        };
+        if pin.still_answers().await {
+            tracing::info!("Pinning {} to {} again", pin.host, pin.address.ip());
+            self.dns.pin(pin);
+        } else {
+            diagnostics::warn(
+                WarningId::PinLost,
+                format!(
+                    "Node {} of {} no longer answers, looking {} up again",
+                    pin.address.ip(),
+                    pin.host,
+                    pin.host
+                ),
+            );
+        }
+    }
+
+    /// Runs a download `dlm status` listed again, from where it ran and with
+    /// its flags, picking up the partial file it left.
+    async fn resume(&self, id: &str, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let manifest = self.active_downloads()?.find(id)?;
+        if manifest.is_running() {
+            bail!("Download {} is still running", manifest.id);
+        }
+        std::env::set_current_dir(&manifest.cwd)
+            .with_context(|| format!("Cannot change to '{}'", manifest.cwd.display()))?;
+        let mut cli = manifest_cli(&manifest)?;
+        self.carry_on(&mut cli, manifest, self.overwrite);
+        cli.globals().apply();
+        cli.budget = cli.new_budget();
+        cli.memory = Arc::new(MemoryLimit::new(cli.max_memory));
+        cli.fetch_auth_token().await?;
+        Box::pin(cli.command.execute(&cli, shutdown)).await
+    }
+
+    /// Sets `cli`, the command line of `manifest`'s download, to carry on
+    /// with it: picking up the partial file rather than starting over,
+    /// where that makes sense, unless `overwrite` starts it over.
+    fn carry_on(&self, cli: &mut Cli, manifest: Manifest, overwrite: bool) {
+        if cli.method == Method::GET && cli.tail.is_none() && cli.cache_dir.is_none() {
+            cli.resume = !overwrite;
+            cli.overwrite = overwrite;
+            cli.continue_at = None;
+            // It's been moved into place by now.
+            cli.adopt = None;
+        }
+        cli.state_dir = cli.state_dir.take().or_else(|| self.state_dir.clone());
+        cli.resumed = Some(manifest);
+    }
+
+    /// `dlm resume-all`: lists the cut-off downloads, under `under` if
+    /// given, and the parts no manifest accounts for, then resumes them in
+    /// a pool, `parallel` at a time. Fails if any of them does, and stopped
+    /// by Ctrl+C, as an interrupted download does.
+    async fn resume_all(
+        &self,
+        under: Option<&Path>,
+        parallel: NonZeroUsize,
+        shutdown: &Shutdown,
+    ) -> anyhow::Result<()> {
+        let downloads = self.active_downloads()?;
+        let found = resume_all::find(&downloads, under, under.unwrap_or(&self.target_directory))?;
+        if found.is_empty() {
+            println!("Nothing to resume ({})", downloads.dir().display());
+            return Ok(());
+        }
+        found.print();
+        let count = found.manifests.len();
+        let mut summary = Summary::default();
+        // Checked up front, so nothing's left half done by a skip later.
+        let mut clis = Vec::new();
+        for (index, manifest) in found.manifests.iter().enumerate() {
+            let label = format!("Download {} of {count}, {}", index + 1, manifest.id);
+            match manifest_cli(manifest) {
+                Ok(cli) => clis.push((label, manifest, cli)),
+                Err(error) => {
+                    println!("{label}: failed, {error:#}");
+                    summary.add(Outcome::Failed);
+                }
+            }
+        }
+        // The downloads run in one process, so they have to agree on what
+        // the flags set for all of it.
+        if let Some((_, first, cli)) = clis.first() {
+            let globals = cli.globals();
+            if let Some((_, other, _)) = clis.iter().find(|(_, _, cli)| cli.globals() != globals) {
+                bail!(
+                    "Downloads {} and {} were started with different --strict, --presigned, --speed-units, --show-secrets, --show-error-body or waits, so they can't be resumed together; `dlm resume` carries on with each",
+                    first.id,
+                    other.id
+                );
+            }
+            globals.apply();
+        }
+        let mut jobs = Vec::new();
+        for (label, manifest, mut cli) in clis {
+            // Each with its own buffers and token, as `dlm resume` has.
+            cli.memory = Arc::new(MemoryLimit::new(cli.max_memory));
+            if let Err(error) = cli.fetch_auth_token().await {
+                println!("{label}: failed, {error:#}");
+                summary.add(Outcome::Failed);
+                continue;
+            }
+            let restart = match remote_change(manifest, &cli, &self.budget).await {
+                Ok(None) => false,
+                Ok(Some(change)) if self.restart_on_unresumable => {
+                    println!("{label}: the remote file {change}, starting it over");
+                    true
+                }
+                Ok(Some(change)) => {
+                    println!(
+                        "{label}: skipped, the remote file {change}; pass --restart-on-unresumable to start it over"
+                    );
+                    summary.add(Outcome::Skipped);
+                    continue;
+                }
+                Err(error) => {
+                    println!("{label}: failed, cannot check the remote file: {error:#}");
+                    summary.add(Outcome::Failed);
+                    continue;
+                }
+            };
+            match self.resume_job(&label, manifest, cli, restart, shutdown) {
+                Ok(job) => {
+                    println!("{label}: resuming {}", manifest.destination.display());
+                    jobs.push(job);
+                }
+                Err(error) => {
+                    println!("{label}: failed, {error:#}");
+                    summary.add(Outcome::Failed);
+                }
+            }
+        }
+        let pool = DownloadPool::new(
+            self.client_options().build_async()?,
+            PoolOptions {
+                parallelism: parallel.get(),
+                // Each download has the connections its workers ask for.
+                per_host: usize::MAX,
+                rate_limit: self.limit_rate,
+                retry_budget: self.budget.clone(),
+                // And the --max-memory it was started with.
+                memory: None,
+            },
+        );
+        let reports = batch::run(&pool, jobs, parallel.get(), None, shutdown).await;
+        for report in reports {
+            summary.add(report.outcome);
+        }
+        println!("Resumed {count} downloads: {summary}");
+        if summary.interrupted > 0 || shutdown.is_requested() {
+            return Err(DownloadError::Interrupted.into());
+        }
+        if summary.failed > 0 {
+            bail!(
+                "{} of {count} downloads could not be resumed",
+                summary.failed
+            );
+        }
+        Ok(())
+    }
+
+    /// The download of `manifest` for `dlm resume-all`'s pool, with `cli`,
+    /// the flags it was started with, read from where it was started,
+    /// carried on with or, with `restart`, started over. The pool runs
+    /// what `--input-file` can; a `zip-extract` or a `repair` is left to
+    /// `dlm resume`.
+    fn resume_job(
+        &self,
+        label: &str,
+        manifest: &Manifest,
+        mut cli: Cli,
+        restart: bool,
+        shutdown: &Shutdown,
+    ) -> anyhow::Result<batch::Job> {
+        let workers = match cli.command {
+            Commands::DownloadAsync { workers } => workers,
+            Commands::DownloadBlocking => Workers::Count(1),
+            _ => bail!(
+                "only downloads are resumed together, `dlm resume {}` carries on with it",
+                manifest.id
+            ),
+        };
+        cli.relative_to(&manifest.cwd);
+        self.carry_on(&mut cli, manifest.clone(), restart);
+        // The pool's, which every download of the run shares.
+        cli.budget = self.budget.clone();
+        let url = Url::parse(&manifest.url)?;
+        let destination = manifest.destination.clone();
+        cli.output = Some(destination.clone());
+        let interrupted = shutdown.child();
+        let client_options = cli.client_options();
+        let mut options = cli.transfer_options();
+        options.body = cli.request_body()?;
+        let request = DownloadRequest::new(url.clone(), &cli.target_directory)
+            .workers(workers)
+            .options(options)
+            .client(client_options.build_async()?)
+            .interrupted(interrupted.clone());
+        let phases = cli.phases(&client_options)?;
+        let post_processing = cli.post_processing(&interrupted);
+        let usage = cli.usage_log().map(|log| (log, cli.tags.clone()));
+        Ok(batch::Job {
+            label: label.to_string(),
+            url: url.clone(),
+            destination: destination.clone(),
+            request,
+            phases,
+            post_processing,
+            usage,
+            track: Some(Box::new(move || cli.track(&url, &destination))),
+        })
+    }
+
+    /// Makes the relative paths of a command line that ran in `cwd` point
+    /// where they did there.
+    fn relative_to(&mut self, cwd: &Path) {
+        self.target_directory = cwd.join(&self.target_directory);
+        #[cfg(feature = "sigstore")]
+        let cosign_key = self.cosign_key.as_mut();
+        #[cfg(not(feature = "sigstore"))]
+        let cosign_key = None;
+        let paths = [
+            self.adopt.as_mut(),
+            self.resume_from.as_mut(),
+            self.piece_hashes.as_mut(),
+            self.output.as_mut(),
+            self.log_file.as_mut(),
+            self.chunk_log.as_mut(),
+            self.error_report.as_mut(),
+            self.record.as_mut(),
+            cosign_key,
+            self.control_socket.as_mut(),
+            self.status_file.as_mut(),
+            self.data_file.as_mut(),
+            self.cache_dir.as_mut(),
+            self.state_dir.as_mut(),
+            self.input_file.as_mut(),
+            self.preflight_cache.as_mut(),
+        ];
+        for path in paths.into_iter().flatten() {
+            *path = cwd.join(&*path);
+        }
+    }
+
+    /// `url` without tracking parameters with `--strip-tracking-params`, or
+    /// as is with a warning about them.
+    fn strip_tracking_params(&self, url: Url) -> Url {
+        if !self.strip_tracking_params {
+            let found = tracking::tracking_params(&url, &self.tracking_param);
+            if !found.is_empty() {
+                diagnostics::warn(
+                    WarningId::TrackingParams,
+                    format!(
+                        "The URL has tracking parameters ({}); --strip-tracking-params removes them",
+                        found.join(", ")
+                    ),
+                );
+            }
+            return url;
+        }
+        for name in &self.tracking_param {
+            if tracking::is_protected(name) {
+                diagnostics::warn(
+                    WarningId::TrackingParams,
+                    format!("Not stripping '{name}', it may be part of a signature"),
+                );
+            }
+        }
+        let (stripped, removed) = tracking::strip(&url, &self.tracking_param);
+        if !removed.is_empty() {
+            tracing::info!("Removed tracking parameters: {}", removed.join(", "));
+        }
+        stripped
+    }
+
+    fn otel_endpoint(&self) -> Option<&Url> {
+        #[cfg(feature = "otel")]
+        return self.otel_endpoint.as_ref();
+        #[cfg(not(feature = "otel"))]
+        None
+    }
+
+    fn client_options(&self) -> ClientOptions {
+        let ip_family = if self.ipv4 {
+            Some(IpFamily::V4)
+        } else if self.ipv6 {
+            Some(IpFamily::V6)
+        } else {
+            None
+        };
+        ClientOptions {
+            interface: self.interface.clone(),
+            ip_family,
+            stall_timeout: Some(self.stall_policy().stall_timeout),
+            connect_timeout: (self.connect_timeout > 0)
+                .then(|| Duration::from_secs(self.connect_timeout)),
+            min_tls: self.min_tls,
+            read_timeout: self
+                .read_timeout
+                .map(|secs| Duration::from_secs(secs.max(1))),
+            max_idle_per_host: match self.command {
+                Commands::DownloadAsync { workers } => Some(workers.most().max(1).into()),
+                _ => None,
+            },
+            #[cfg(feature = "http3")]
+            http3: false,
+            dns: self.dns.clone(),
+            tls: self.tls.clone(),
+            proxy: self.proxy.clone(),
+            proxy_all: self.proxy_all,
+            auth_token: self.auth_token.clone(),
+            proxy_credentials: self
+                .proxy_user
+                .clone()
+                .zip(self.proxy_password.clone())
+                .map(|(user, password)| ProxyCredentials { user, password }),
+            headers: self.request_headers(),
+            user_agent: self.user_agent.clone(),
+        }
+    }
+
+    /// `--auth-token-url`: where tokens come from, and the first one.
+    async fn fetch_auth_token(&mut self) -> anyhow::Result<()> {
+        let Some(url) = self.auth_token_url.clone() else {
+            self.auth_token = None;
+            return Ok(());
+        };
+        let auth_token = AuthToken::new(TokenSource {
+            url,
+            body: self.auth_token_body.clone(),
+            path: self.auth_token_jsonpath.clone(),
+        });
+        auth_token
+            .fetch(&self.client_options().build_async()?)
+            .await?;
+        self.auth_token = Some(Arc::new(auth_token));
+        Ok(())
+    }
+
+    /// `--header`, with the `Authorization` of `--user` or `--bearer-token`
+    /// unless one was given there. A release URL's token is for the API
+    /// alone, which [`Release::resolve`] sends it to itself.
+    fn request_headers(&self) -> HeaderMap {
+        let mut headers: HeaderMap = self.headers.iter().cloned().collect();
+        if self.no_compression {
+            headers.insert(
+                header::ACCEPT_ENCODING,
+                HeaderValue::from_static("identity"),
+            );
+        }
+        let release = self.latest_release().is_some()
+            || self.url.as_ref().is_some_and(Release::is_release_url);
+        if release || headers.contains_key(header::AUTHORIZATION) {
+            return headers;
+        }
+        let authorization = match (&self.user, &self.bearer_token) {
+            (Some((user, password)), _) => http::basic_auth(user, password),
+            (None, Some(token)) => format!("Bearer {token}"),
+            (None, None) => return headers,
+        };
+        if let Ok(mut value) = HeaderValue::from_str(&authorization) {
+            value.set_sensitive(true);
+            headers.insert(header::AUTHORIZATION, value);
+        }
+        headers
+    }
+
+    #[cfg(feature = "http3")]
+    fn http3_mode(&self) -> Http3Mode {
+        if self.http3 {
+            Http3Mode::Require
+        } else if self.http3_prior_knowledge {
+            Http3Mode::PriorKnowledge
+        } else {
+            Http3Mode::AltSvc
+        }
+    }
+
+    fn glyphs(&self) -> Glyphs {
+        match self.ascii {
+            true => Glyphs::Ascii,
+            false => Glyphs::detect(),
+        }
+    }
+
+    /// `--segment-size-min` and `--segment-size-max`.
+    fn segment_bounds(&self) -> SegmentBounds {
+        SegmentBounds {
+            min: self.segment_size_min.max(1),
+            max: self.segment_size_max.max(self.segment_size_min.max(1)),
+        }
+    }
+
+    fn stall_policy(&self) -> StallPolicy {
+        StallPolicy {
+            stall_timeout: Duration::from_secs(self.stall_timeout.max(1)),
+            min_speed: self
+                .min_speed
+                .map(|speed| (speed, Duration::from_secs(self.min_speed_time.max(1)))),
+        }
+    }
+
+    fn min_speed_policy(&self) -> Option<MinSpeedPolicy> {
+        self.min_avg_speed.map(|threshold| MinSpeedPolicy {
+            threshold,
+            window: self.min_avg_window,
+            ramp_up: self.min_avg_window.min(Duration::from_secs(10)),
+        })
+    }
+
+    fn progress_interval(&self) -> Duration {
+        Duration::from_millis(self.progress_interval)
+    }
+
+    /// How often `--keepalive-output` prints, if at all.
+    fn keepalive_output(&self) -> Option<Duration> {
+        self.keepalive_output
+            .or_else(|| (!std::io::stderr().is_terminal()).then_some(KEEPALIVE_OUTPUT))
+            .filter(|every| !every.is_zero() && self.progress == ProgressMode::Bar)
+    }
+
+    /// The renderer `--progress` asks for, for the download of `url`; `bar`
+    /// is the interactive one.
+    fn renderer<R: Renderer + 'static>(&self, url: &Url, bar: R) -> Box<dyn Renderer> {
+        match self.progress {
+            ProgressMode::Bar => Box::new(bar),
+            ProgressMode::Plain => Box::new(
+                PlainText::new(self.progress_stream(), self.stall_policy().stall_timeout)
+                    .with_interval(self.plain_interval),
+            ),
+            ProgressMode::Json => Box::new(
+                JsonEvents::new(self.progress_stream(), http::redact_url(url))
+                    .with_deterministic(self.json_deterministic),
+            ),
+            ProgressMode::None => Box::new(Silent),
+        }
+    }
+
+    /// Where `--progress plain` and `json` write.
+    fn progress_stream(&self) -> Box<dyn Write + Send> {
+        match self.progress_stdout {
+            true => Box::new(std::io::stdout()),
+            false => Box::new(std::io::stderr()),
+        }
+    }
+
+    /// Where `--progress json` writes its events, if it's on, for the
+    /// `completed` or `error` event that ends them.
+    pub fn progress_events(&self) -> Option<Box<dyn Write + Send>> {
+        (self.progress == ProgressMode::Json).then(|| self.progress_stream())
+    }
+
+    /// How files are removed: `--use-trash` or not.
+    fn removal(&self) -> Removal {
+        match self.use_trash && !self.force_delete {
+            true => Removal::Trash,
+            false => Removal::Delete,
+        }
+    }
+
+    /// Removes the file at `path` before `--overwrite` replaces it, saying
+    /// where it went if not deleted for good.
+    fn replace(&self, path: &Path) -> anyhow::Result<Removed> {
+        phases::replace(self.removal(), path)
+    }
+
+    /// A budget of `--retry-budget` retries, 0 standing for no limit.
+    fn new_budget(&self) -> Arc<RetryBudget> {
+        Arc::new(RetryBudget::new(
+            Some(self.retry_budget).filter(|budget| *budget > 0),
+        ))
+    }
+
+    /// Transfer options without the chunk log, which is only opened once a
+    /// download actually starts, or the piece hashes.
+    fn transfer_options(&self) -> TransferOptions {
+        TransferOptions {
+            resume: self.resume,
+            overwrite: self.overwrite,
+            no_cleanup: self.no_cleanup,
+            no_partial: self.no_partial,
+            stall: self.stall_policy(),
+            retry: RetryPolicy {
+                retries: self.retries,
+                ..RetryPolicy::default()
+            },
+            chunk_floor: self.chunk_min_speed.map(|speed| ChunkFloor {
+                speed,
+                grace: self.chunk_min_speed_grace,
+            }),
+            output: self.output.clone(),
+            chunk_log: None,
+            continue_at: self.continue_at,
+            resume_from: None,
+            sparse_fill: self.sparse_fill,
+            write_buffer: self.write_buffer as usize,
+            serial_writes: self.serial_writes,
+            pieces: None,
+            throttle: Throttle::new(self.limit_rate),
+            pacing: self.pacing.clone(),
+            fair_workers: self.fair_workers,
+            no_warm_up: self.no_warm_up,
+            chunk_order: self.chunk_order,
+            resume_order: self.resume_order,
+            segment_size: match self.segment_size {
+                SegmentSize::Fixed(size) => (size > 0).then_some(size),
+                SegmentSize::Auto => Some(self.segment_bounds().initial()),
+            },
+            segment_tuning: (self.segment_size == SegmentSize::Auto).then(|| self.segment_bounds()),
+            min_steal: (self.min_steal_size > 0).then_some(self.min_steal_size),
+            min_split_size: (self.min_split_size > 0).then_some(self.min_split_size),
+            one_connection: self.one_connection,
+            hybrid_streaming: self.hybrid_streaming,
+            part_files: self.part_files,
+            method: self.method.clone(),
+            body: Bytes::new(),
+            content_type: self.content_type.clone(),
+            restart_on_unresumable: self.restart_on_unresumable,
+            if_changed: self
+                .if_changed
+                .unwrap_or(match self.restart_on_unresumable {
+                    true => IfChanged::Restart,
+                    false => IfChanged::Abort,
+                }),
+            strict_content_type: self.strict_content_type,
+            force: self.force,
+            save_torrent: self.torrent == Some(TorrentMode::Save),
+            newer_than: self.newer_than,
+            store_compressed: self.store_compressed,
+            no_compression: self.no_compression,
+            dns: self.dns.clone(),
+            tls: self.tls.clone(),
+            retry_budget: self.budget.clone(),
+            memory: self.memory.clone(),
+            proxy: self.proxy.clone(),
+            proxy_all: self.proxy_all,
+            auth_token: self.auth_token.clone(),
+            pin_ip: !self.no_pin_ip,
+            verify_parts: self.verify_parts,
+            expected_size: self.expected_size,
+            mirrors: self.mirror.clone(),
+        }
+    }
+
+    /// The `--data` or `--data-file` body, after checking that nothing asks
+    /// for ranges of a response that can't be requested again.
+    fn request_body(&self) -> anyhow::Result<Bytes> {
+        let method = &self.method;
+        if *method == Method::GET {
+            if self.data.is_some() || self.data_file.is_some() || self.content_type.is_some() {
+                bail!("--data, --data-file and --content-type need --method POST or PUT");
+            }
+            return Ok(Bytes::new());
+        }
+        let range_flags = [
+            ("--resume", self.resume),
+            ("--continue-at", self.continue_at.is_some()),
+            ("--adopt", self.adopt.is_some()),
+            ("--tail", self.tail.is_some()),
+            ("--piece-hashes", self.piece_hashes.is_some()),
+            ("--follow-landing-page", self.follow_landing_page),
+            ("--dry-run", self.dry_run),
+            ("--cache-dir", self.cache_dir.is_some()),
+        ];
+        if let Some((flag, _)) = range_flags.iter().find(|(_, set)| *set) {
+            bail!(
+                "{flag} can't be used with --method {method}: only GET downloads can be requested again"
+            );
+        }
+        match self.command {
+            Commands::DownloadAsync { workers } if workers.splits() => bail!(
+                "--method {method} can't be split between workers, the response can only be read once; drop --workers"
+            ),
+            Commands::ZipExtract { .. } | Commands::Repair { .. } => {
+                bail!("zip-extract and repair work in ranges, which --method {method} rules out")
+            }
+            _ => {}
+        }
+        match (&self.data, &self.data_file) {
+            (Some(data), _) => Ok(Bytes::from(data.clone())),
+            (None, Some(path)) => fs::read(path)
+                .map(Bytes::from)
+                .with_context(|| format!("Cannot read --data-file '{}'", path.display())),
+            (None, None) => Ok(Bytes::new()),
+        }
+    }
+
+    /// `url`, or the file its landing page points to.
+    async fn resolve_landing_page(
+        &self,
+        url: Url,
+        client_options: &ClientOptions,
+    ) -> anyhow::Result<Url> {
+        let client = client_options.build_async()?;
+        let expected = match &self.output {
+            Some(output) => output
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            None => landing::expected_file_name(&url),
+        };
+        match resolve_landing_page(
+            &client,
+            &url,
+            expected.as_deref(),
+            client_options.auth_token.as_deref(),
+        )
+        .await?
+        {
+            Some(resolved) => {
+                println!(
+                    "Landing page {} resolved to {}",
+                    http::redact_url(&url),
+                    http::redact_url(&resolved)
+                );
+                Ok(resolved)
+            }
+            None => Ok(url),
+        }
+    }
+
+    /// The `github://` or `gitlab://` URL `--github-latest` or
+    /// `--gitlab-latest` stands for.
+    fn latest_release(&self) -> Option<Url> {
+        let (forge, project) = match (&self.github_latest, &self.gitlab_latest) {
+            (Some(project), _) => (Forge::GitHub, project),
+            (None, Some(project)) => (Forge::GitLab, project),
+            (None, None) => return None,
+        };
+        let release = Release {
+            forge,
+            project: project.trim_matches('/').to_string(),
+            pattern: self.asset_pattern.clone()?,
+        };
+        Some(release.to_url())
+    }
+
+    /// `url`, or for a `github://` or `gitlab://` URL the asset of the
+    /// latest release it stands for, along with that URL.
+    #[cfg(feature = "releases")]
+    async fn resolve_release(&self, url: Url) -> anyhow::Result<(Url, Option<(Url, Asset)>)> {
+        if !Release::is_release_url(&url) {
+            return Ok((url, None));
+        }
+        let client = self.client_options().build_async()?;
+        let asset = Release::parse(&url)?
+            .resolve(
+                &client,
+                self.releases_api.as_ref(),
+                self.bearer_token.as_deref(),
+                &self.budget,
+                self.auth_token.as_deref(),
+            )
+            .await?;
+        println!(
+            "{url} resolved to {} of release {}: {}",
+            asset.name,
+            asset.tag,
+            http::redact_url(&asset.url)
+        );
+        Ok((asset.url.clone(), Some((url, asset))))
+    }
+
+    /// Refuses magnet links and `.torrent` URLs, dlm being no BitTorrent
+    /// client, unless `--torrent save` keeps the file.
+    #[cfg(not(feature = "torrent"))]
+    fn check_torrent(&self, url: &Url) -> anyhow::Result<()> {
+        if torrent::is_magnet(url) {
+            return Err(torrent::refuse_magnet(url).into());
+        }
+        if self.torrent.is_none() && torrent::is_torrent_url(url) {
+            return Err(torrent::refuse_torrent_url(url).into());
+        }
+        Ok(())
+    }
+
+    /// Refuses magnet links and `.torrent` URLs, dlm being no BitTorrent
+    /// client, offering the torrent's HTTP seeds if it has any. With
+    /// `--torrent webseeds`, swaps `url` for the file on the first seed and
+    /// returns the torrent, to check the download against.
+    #[cfg(feature = "torrent")]
+    async fn check_torrent(
+        &self,
+        url: Url,
+        client_options: &ClientOptions,
+    ) -> anyhow::Result<(Url, Option<Torrent>)> {
+        if torrent::is_magnet(&url) {
+            return Err(torrent::refuse_magnet(&url).into());
+        }
+        match self.torrent {
+            Some(TorrentMode::Save) => Ok((url, None)),
+            Some(TorrentMode::Webseeds) => {
+                if self.tail.is_some() || self.cache_dir.is_some() {
+                    bail!("--torrent webseeds can't be used with --tail or --cache-dir");
+                }
+                if let Commands::ZipExtract { .. } | Commands::Repair { .. } = self.command {
+                    bail!("--torrent webseeds can't be used with zip-extract or repair");
+                }
+                let torrent = Torrent::fetch(
+                    &client_options.build_async()?,
+                    &url,
+                    &self.budget,
+                    self.auth_token.as_deref(),
+                )
+                .await?;
+                let Some(seed) = torrent.webseeds.first() else {
+                    bail!(
+                        "The torrent for '{}' lists no HTTP seeds to download it from",
+                        torrent.name
+                    );
+                };
+                let seed = torrent.file_url(seed);
+                println!(
+                    "Torrent '{}' ({}, {} pieces): downloading from its HTTP seed {}",
+                    torrent.name,
+                    speed::Size(torrent.length),
+                    torrent.pieces.len(),
+                    http::redact_url(&seed)
+                );
+                Ok((seed, Some(torrent)))
+            }
+            None if torrent::is_torrent_url(&url) => {
+                let client = client_options.build_async()?;
+                match Torrent::fetch(&client, &url, &self.budget, self.auth_token.as_deref()).await
+                {
+                    Ok(torrent) => Err(torrent.refusal(&url).into()),
+                    Err(error) => {
+                        tracing::debug!("Cannot read the torrent: {error:#}");
+                        Err(torrent::refuse_torrent_url(&url).into())
+                    }
+                }
+            }
+            None => Ok((url, None)),
+        }
+    }
+
+    fn piece_hashes(&self) -> anyhow::Result<Option<Arc<PieceHashes>>> {
+        let Some(path) = &self.piece_hashes else {
+            return Ok(None);
+        };
+        if !matches!(self.command, Commands::DownloadAsync { workers } if workers.splits())
+            && !matches!(self.command, Commands::Repair { .. })
+        {
+            bail!("--piece-hashes needs download-async --workers 2 or more, or repair");
+        }
+        Ok(Some(Arc::new(PieceHashes::load(path)?)))
+    }
+
+    /// Moves the `--adopt` partial file to `destination`, returning how many
+    /// bytes of it the download carries on from.
+    async fn adopt(
+        &self,
+        url: &Url,
+        destination: &Path,
+        client_options: &ClientOptions,
+    ) -> anyhow::Result<Option<u64>> {
+        let Some(partial) = &self.adopt else {
+            return Ok(None);
+        };
+        if !matches!(
+            self.command,
+            Commands::DownloadBlocking
+                | Commands::DownloadAsync {
+                    workers: Workers::Count(..=1)
+                }
+        ) {
+            bail!("--adopt needs download-blocking or download-async without --workers");
+        }
+        let client = client_options.build_async()?;
+        let reused = adopt::adopt_partial(
+            &client,
+            url,
+            partial,
+            destination,
+            self.adopt_verify,
+            &self.budget,
+            self.auth_token.as_deref(),
+        )
+        .await?;
+        println!(
+            "Adopted '{}' ({}) as '{}'",
+            partial.display(),
+            speed::Size(reused),
+            destination.display()
+        );
+        Ok(Some(reused))
+    }
+
+    /// Where to `--resume` the download of `url` to `destination`, or
+    /// `None` to skip it. A file that isn't the start of `url`'s, another
+    /// URL's with the same name or another version of it, is settled per
+    /// `--on-conflict` rather than carried on from. A manifest of a stopped
+    /// download of `url` to it vouches for it, as does the sidecar of one
+    /// written `--in-place`, whose end is a hole; otherwise its end is
+    /// compared with the remote file's. A file that can't be compared is
+    /// resumed as before.
+    async fn settle_resume(
+        &self,
+        url: &Url,
+        destination: &Path,
+        client_options: &ClientOptions,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        if !self.resume || self.overwrite || self.resumed.is_some() || !destination.is_file() {
+            return Ok(Some(destination.to_path_buf()));
+        }
+        let absolute = std::env::current_dir()
+            .unwrap_or_default()
+            .join(destination);
+        let vouched = ActiveDownloads::open(self.state_dir.as_deref())
+            .ok()
+            .flatten()
+            .and_then(|downloads| downloads.list().ok())
+            .unwrap_or_default()
+            .iter()
+            .any(|manifest| manifest.destination == absolute && manifest.url == url.as_str());
+        // A `.part` beside it is what's resumed; the file itself is left be.
+        if vouched || in_place::unfinished(destination) || partial::path(destination).is_file() {
+            return Ok(Some(destination.to_path_buf()));
+        }
+        let client = client_options.build_async()?;
+        let conflict = match conflicts::resume_conflict(
+            &client,
+            url,
+            destination,
+            &self.budget,
+            self.auth_token.as_deref(),
+        )
+        .await
+        {
+            Ok(conflict) => conflict,
+            Err(error) => {
+                diagnostics::warn_or_fail(
+                    WarningId::ResumeUnchecked,
+                    format!(
+                        "Cannot tell whether '{}' is the start of {}, resuming it anyway: {error:#}",
+                        destination.display(),
+                        http::redact_url(url)
+                    ),
+                )?;
+                None
+            }
+        };
+        let Some(conflict) = conflict else {
+            return Ok(Some(destination.to_path_buf()));
+        };
+        match self.on_conflict {
+            OnConflict::Rename => {
+                let renamed = conflicts::unused(destination);
+                println!(
+                    "Saving as '{}', '{}' isn't the start of this download: {conflict}",
+                    renamed.display(),
+                    destination.display()
+                );
+                Ok(Some(renamed))
+            }
+            OnConflict::Skip => {
+                println!(
+                    "Skipped {}, '{}' isn't the start of it: {conflict}",
+                    http::redact_url(url),
+                    destination.display()
+                );
+                Ok(None)
+            }
+            OnConflict::Error => bail!(
+                "Cannot resume into '{}', it isn't the start of this download: {conflict}; pass --on-conflict rename or skip, or --overwrite",
+                destination.display()
+            ),
+        }
+    }
+
+    /// The manifest `--resume-from` carries on from: the latest stopped
+    /// worker-mode download of `url` into its directory.
+    fn resume_from(&self, url: &Url) -> anyhow::Result<Option<Manifest>> {
+        let Some(dir) = &self.resume_from else {
+            return Ok(None);
+        };
+        if !matches!(self.command, Commands::DownloadAsync { workers } if workers.splits()) {
+            bail!("--resume-from needs download-async with --workers");
+        }
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Cannot find '{}'", dir.display()))?;
+        let downloads = self.active_downloads()?;
+        let earlier = downloads.list()?.into_iter().rev().find(|manifest| {
+            manifest.url == url.as_str()
+                && manifest.parts.is_some()
+                && manifest
+                    .destination
+                    .parent()
+                    .and_then(|parent| parent.canonicalize().ok())
+                    .is_some_and(|parent| parent == dir)
+        });
+        let Some(earlier) = earlier else {
+            bail!(
+                "No worker-mode download of {} into '{}' in '{}'; --resume-from carries on from one `dlm status` lists",
+                http::redact_url(url),
+                dir.display(),
+                downloads.dir().display()
+            );
+        };
+        if earlier.is_running() {
+            bail!("Download {} is still running", earlier.id);
+        }
+        Ok(Some(earlier))
+    }
+
+    /// The phases a download to the target directory goes through around
+    /// its transfer, asking the server with `client_options`.
+    fn phases(&self, client_options: &ClientOptions) -> anyhow::Result<Phases> {
+        let quota = DirQuota {
+            soft: self.dir_quota,
+            hard: self.dir_quota_hard,
+        };
+        if quota.is_set()
+            && let Commands::ZipExtract { .. } | Commands::Repair { .. } = self.command
+        {
+            bail!("--dir-quota and --dir-quota-hard can't be used with zip-extract or repair");
+        }
+        Ok(Phases {
+            client_options: client_options.clone(),
+            retry_budget: self.budget.clone(),
+            memory: self.memory.clone(),
+            target_directory: self.target_directory.clone(),
+            quota,
+            resuming: self.resume || self.continue_at.is_some(),
+            force: self.force,
+            newer_than: self.newer_than,
+            cache: self.cache()?,
+            overwrite: self.overwrite,
+            removal: self.removal(),
+        })
+    }
+
+    /// Starts following the download of `url` to `destination`, named
+    /// `name`, for whatever reports on it.
+    async fn start_session(
+        &self,
+        url: Url,
+        options: TransferOptions,
+        interrupted: Arc<AtomicBool>,
+        destination: &Path,
+        name: &str,
+    ) -> anyhow::Result<(Session, SessionGuards)> {
+        let tracker = self.track(&url, destination);
+        error_report::track(&url, Some(destination));
+        let (title, title_guard) = TerminalTitle::start(name, !self.no_title).unzip();
+        let (control, control_guard) = self
+            .start_control(
+                options.throttle.clone(),
+                options.memory.clone(),
+                interrupted.clone(),
+            )?
+            .unzip();
+        let (web_status, web_status_guard) = match self.web_status {
+            Some(address) => {
+                let (throttle, memory) = (options.throttle.clone(), options.memory.clone());
+                Some(WebStatus::start(address, throttle, memory, &url, destination).await?)
+            }
+            None => None,
+        }
+        .unzip();
+        let status_file = self.status_file.as_deref().map(|path| {
+            let (throttle, memory) = (options.throttle.clone(), options.memory.clone());
+            StatusFile::start(
+                path,
+                self.status_interval,
+                throttle,
+                memory,
+                &url,
+                destination,
+            )
+        });
+        let session = Session {
+            url,
+            options,
+            interrupted,
+            start: Instant::now(),
+            title,
+            control,
+            web_status,
+            status_file,
+            progress: Mutex::new(None),
+            tracker,
+            #[cfg(all(feature = "systemd", target_os = "linux"))]
+            systemd: systemd::Status::start(name),
+        };
+        let guards = SessionGuards {
+            _web_status: web_status_guard,
+            _control: control_guard,
+            _title: title_guard,
+        };
+        Ok((session, guards))
+    }
+
+    fn cache(&self) -> anyhow::Result<Option<Cache>> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(None);
+        };
+        if let Commands::ZipExtract { .. } | Commands::Repair { .. } = self.command {
+            bail!("--cache-dir keeps whole downloads, it can't be used with zip-extract or repair");
+        }
+        let cache = Cache::open(dir, self.cache_size)
+            .with_context(|| format!("Cannot create cache directory '{}'", dir.display()))?;
+        Ok(Some(cache))
+    }
+}
+
+/// State shared by every download mode for the duration of one run.
+struct Session {
+    url: Url,
+    options: TransferOptions,
+    interrupted: Arc<AtomicBool>,
+    start: Instant,
+    title: Option<TerminalTitle>,
+    control: Option<ControlSocket>,
+    web_status: Option<WebStatus>,
+    status_file: Option<StatusFile>,
+    /// The latest transfer attached, for a look at it once it's done.
+    progress: Mutex<Option<ProgressHandle>>,
+    /// Keeps this download's manifest for `dlm status` current.
+    tracker: Option<Tracker>,
+    /// Keeps systemd's `STATUS=` current when running as a service.
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    systemd: Option<systemd::Status>,
+}
+
+impl Session {
+    /// Starts following a transfer: `--control-socket`, `--web-status`
+    /// and `dlm status` report on it.
+    fn attach(&self, handle: ProgressHandle) {
+        if let Some(control) = &self.control {
+            control.attach(handle.clone());
+        }
+        if let Some(web_status) = &self.web_status {
+            web_status.attach(handle.clone());
+        }
+        if let Some(status_file) = &self.status_file {
+            status_file.attach(handle.clone());
+        }
+        if let Some(tracker) = &self.tracker {
+            tracker.attach(handle.clone());
+        }
+        error_report::attach(handle.clone());
+        snapshot::attach(&self.url, handle.clone(), self.options.memory.clone());
+        #[cfg(all(feature = "systemd", target_os = "linux"))]
+        if let Some(systemd) = &self.systemd {
+            systemd.attach(handle.clone());
+        }
+        *self.progress.lock().unwrap() = Some(handle);
+    }
+
+    /// The latest look at the attached transfer.
+    fn snapshot(&self) -> Option<ProgressSnapshot> {
+        self.progress
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(ProgressHandle::snapshot)
+    }
+
+    /// Winds down what followed the download once `result` is in: the
+    /// manifest for `dlm status`, `--status-file`, the line `usage_log`
+    /// counts it with, the download `--resume-from` took over from, and
+    /// systemd's status.
+    async fn finish(
+        &mut self,
+        cli: &Cli,
+        result: &anyhow::Result<(DownloadOutcome, Vec<StepTiming>)>,
+        usage_log: Option<&UsageLog>,
+        target: &Target,
+        release: &Option<(Url, Asset)>,
+    ) {
+        // An unverified file is still all there.
+        let downloaded = match result {
+            Ok(_) => true,
+            Err(error) => matches!(error.downcast_ref(), Some(DownloadError::Unverified { .. })),
+        };
+        let interrupted = self.interrupted.load(Ordering::SeqCst);
+        if let Some(tracker) = self.tracker.take() {
+            tracker.finish(downloaded).await;
+        }
+        if let Some(status_file) = self.status_file.take() {
+            let state = match result {
+                Ok(_) => TransferState::Completed,
+                Err(_) if interrupted => TransferState::Interrupted,
+                Err(error) => TransferState::Failed(format!("{error:#}")),
+            };
+            status_file.finish(state, cli.keep_status_file).await;
+        }
+        if let (Some(log), Some(snapshot)) = (usage_log, self.snapshot()) {
+            let outcome = match result {
+                Ok(_) => schema::Outcome::Completed,
+                Err(_) if interrupted => schema::Outcome::Interrupted,
+                Err(_) => schema::Outcome::Failed,
+            };
+            let url = http::redact_url(&self.url);
+            let mut transfer = schema::Transfer::new(url, &snapshot, outcome);
+            transfer.replaced = target.replaced.clone();
+            transfer.release = release.as_ref().map(|(from, _)| from.to_string());
+            transfer.tags = cli.tags.clone();
+            transfer.signature = match result {
+                Ok((outcome, _)) => outcome.signature.clone(),
+                Err(error) => cosign::failed(error),
+            };
+            if let Err(error) = log.record(&transfer) {
+                diagnostics::warn(
+                    WarningId::NotRecorded,
+                    format!("`dlm usage` won't count this download: {error}"),
+                );
+            }
+        }
+        // This download took over from the one --resume-from found.
+        if downloaded
+            && let Some(earlier) = &target.earlier
+            && let Err(error) = cli.active_downloads().and_then(|downloads| {
+                downloads.forget(&earlier.id)?;
+                Ok(())
+            })
+        {
+            diagnostics::warn(
+                WarningId::NotRecorded,
+                format!(
+                    "`dlm status` still lists download {}: {error:#}",
+                    earlier.id
+                ),
+            );
+        }
+        #[cfg(all(feature = "systemd", target_os = "linux"))]
+        if let Some(systemd) = self.systemd.take() {
+            systemd.finish(downloaded).await;
+        }
+    }
+
+    /// Ends `--progress json` with the `completed` event of `outcome`, a
+    /// file of `size` bytes.
+    fn completed(&self, cli: &Cli, outcome: &DownloadOutcome, size: u64) {
+        let Some(events) = cli.progress_events() else {
+            return;
+        };
+        let event = ProgressEvent::Completed {
+            path: outcome.path.clone(),
+            bytes: size,
+            duration_ms: (!cli.json_deterministic).then(|| self.start.elapsed().as_millis() as u64),
+            sha256: hex::encode(outcome.sha256.expect("the hash step runs first")),
+            expected_size: cli.expected_size,
+            segment_tuning: self
+                .snapshot()
+                .and_then(|snapshot| snapshot.segment_tuning)
+                .filter(|_| !cli.json_deterministic),
+            tls: outcome.snapshot.tls.clone(),
+            signature: outcome.signature.clone(),
+        };
+        render::write_event(events, &event);
+    }
+}
+
+/// A download's URL once resolved, with what was found on the way.
+struct Resolved {
+    url: Url,
+    /// The release URL `url` is the asset of, and the asset.
+    release: Option<(Url, Asset)>,
+    /// The torrent `url` seeds.
+    #[cfg(feature = "torrent")]
+    torrent: Option<Torrent>,
+    client_options: ClientOptions,
+    /// `--data` or `--data-file`.
+    body: Bytes,
+}
+
+/// Where a download goes, once what was there is settled.
+struct Target {
+    destination: PathBuf,
+    /// Bytes reused from the file adopted there.
+    adopted: Option<u64>,
+    /// What `--overwrite` replaces.
+    replaced: Option<Replaced>,
+    /// The download `--resume-from` carries on from.
+    earlier: Option<Manifest>,
+}
+
+/// What keeps a [`Session`]'s terminal title, control socket and status
+/// page up, dropped once it's over.
+struct SessionGuards {
+    _web_status: Option<WebStatusGuard>,
+    _control: Option<ControlGuard>,
+    _title: Option<TitleGuard>,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum Commands {
+    DownloadBlocking,
+    DownloadAsync {
+        /// Use workers to download, by default this is 1, for single-worker driven.
+        /// auto picks them by the size of the file once the server says it
+        /// takes ranges: one under 10 MiB, then one per 64 MiB up to 16
+        #[arg(short, long, default_value = "1")]
+        workers: Workers,
+    },
+    /// Summarize a --chunk-log file (slowest chunks, errors and retries), or
+    /// print an --error-report
+    Report {
+        /// Chunk log written by --chunk-log, or a report by --error-report
+        chunk_log: PathBuf,
+    },
+    /// Extract one member of a remote zip archive, fetching only the central
+    /// directory and that member's bytes with range requests.
+    ZipExtract {
+        /// Path of the member inside the archive, e.g. docs/readme.txt
+        member: String,
+    },
+    /// Fix a corrupt or partial local copy of the URL in place, re-fetching
+    /// only the ranges that are wrong. Needs range support on the server.
+    Repair {
+        /// The local copy, by default where downloading the URL would put it
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+        /// Without --piece-hashes: fetch the whole file in ranges and compare
+        /// them with the local copy
+        #[arg(long)]
+        compare: bool,
+        /// Size of the ranges --compare fetches
+        #[arg(long, default_value = "4M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+        sample_size: u64,
+    },
+    /// Check that two mirrors serve the same file, comparing random ranges
+    /// of it or every byte. Fails if they differ.
+    Compare {
+        url_a: Url,
+        url_b: Url,
+        /// How many ranges to compare, the first and last among them
+        #[arg(long, default_value_t = 16)]
+        samples: usize,
+        /// Size of each range
+        #[arg(long, default_value = "1M", value_name = "SIZE", value_parser = utils::parse_byte_size)]
+        sample_size: u64,
+        /// Stream both files and compare every byte instead
+        #[arg(long, conflicts_with_all = ["samples", "sample_size"])]
+        full: bool,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Talk to a download started with --control-socket
+    Ctl {
+        /// The running download's --control-socket
+        #[arg(long, value_name = "PATH")]
+        socket: PathBuf,
+        #[command(subcommand)]
+        request: CtlRequest,
+    },
+    /// Manage the --cache-dir
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Hash local files and print sha256sum-style lines, or check the files
+    /// listed in a sums file
+    Hash {
+        /// Files to hash
+        #[arg(required_unless_present = "check", conflicts_with = "check")]
+        paths: Vec<PathBuf>,
+        /// sha256, sha512, sha1, md5 or blake3
+        #[arg(long, default_value = "sha256", value_parser = Algorithm::from_str)]
+        algo: Algorithm,
+        /// Verify the files listed in this sums file instead, printing OK or
+        /// FAILED for each
+        #[arg(long, value_name = "SUMSFILE")]
+        check: Option<PathBuf>,
+    },
+    /// Hash every file in the target directory and compare them with the
+    /// hashes the last `audit --update` recorded, listing files that are
+    /// new, missing or modified. Fails if any are.
+    Audit {
+        /// Record the current hashes as the new baseline instead of failing
+        #[arg(long)]
+        update: bool,
+        /// sha256, sha512, sha1, md5 or blake3; each has its own baseline
+        #[arg(long, default_value = "sha256", value_parser = Algorithm::from_str)]
+        algo: Algorithm,
+        /// Files hashed at once, by default one per CPU
+        #[arg(long, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        /// Print every file and its status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List downloads that are running or were cut off, with the commands
+    /// to continue them
+    Status,
+    /// Continue a download listed by `status`, with its original URL and
+    /// flags; with --overwrite, start it over instead
+    Resume {
+        /// ID from `status`; a unique prefix will do
+        id: String,
+    },
+    /// Continue every download `status` lists as cut off, and list the
+    /// parts in the target directory that no manifest accounts for. One
+    /// whose remote file changed size or ETag is skipped, unless
+    /// --restart-on-unresumable starts it over
+    ResumeAll {
+        /// Only the downloads saved under this directory, where parts are
+        /// looked for too [default: every download, and parts in the
+        /// target directory]
+        dir: Option<PathBuf>,
+        /// Downloads resumed at once, each with the flags it was started
+        /// with, but for those that hold for the whole run, as --proxy and
+        /// --max-memory do, which are this one's
+        #[arg(long, default_value = "1", value_name = "N")]
+        parallel: NonZeroUsize,
+    },
+    /// Run the download a --record transcript is of again, against a
+    /// stand-in server answering as the recorded one did, and check that
+    /// it plans, requests and ends the same way. For reproducing bug
+    /// reports, and as regression tests
+    Replay {
+        /// The transcript --record wrote
+        transcript: PathBuf,
+    },
+    /// Print how much every run transferred per day, week or month,
+    /// failed and interrupted ones included, or set a limit that warns
+    /// about or refuses downloads past it
+    Usage {
+        /// day, week or month
+        #[arg(long, default_value = "day", value_parser = usage::Period::from_str)]
+        by: usage::Period,
+        /// Warn before downloading once a day or month has transferred this
+        /// much, as day=5G or month=100G; replaces the limits set before
+        #[arg(long, value_name = "PERIOD=SIZE", value_parser = usage::Limit::from_str)]
+        limit: Vec<usage::Limit>,
+        /// Refuse to download past the --limit instead (unless --force)
+        #[arg(long, requires = "limit")]
+        enforce: bool,
+        /// Remove the limits
+        #[arg(long, conflicts_with = "limit")]
+        no_limit: bool,
+    },
+    /// The warnings --strict fails on, by the ID --strict-allow takes
+    Diagnostics {
+        #[command(subcommand)]
+        action: DiagnosticsAction,
+    },
+    /// The JSON Schemas of what dlm writes as JSON
+    Schema {
+        #[command(subcommand)]
+        action: SchemaAction,
+    },
+    /// What dlm's flags come to once defaults, `DM_*` variables and the
+    /// command line are settled
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print the version, git commit, build date, enabled features and TLS
+    /// backend
+    Version {
+        /// Print it as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// What `dlm cache` can do with --cache-dir.
+#[derive(Clone, Subcommand)]
+pub enum CacheAction {
+    /// Delete everything in the cache
+    Purge,
+}
 
-    /// Target directory
-    #[arg(short, long, default_value = ".download")]
-    target_directory: PathBuf,
+impl CacheAction {
+    fn run(&self, cli: &Cli) -> anyhow::Result<()> {
+        let Some(dir) = &cli.cache_dir else {
+            bail!("Pass the cache with --cache-dir <PATH> before `cache`");
+        };
+        match self {
+            CacheAction::Purge => {
+                let (entries, bytes) = Cache::open(dir, cli.cache_size)?.purge()?;
+                println!(
+                    "Removed {entries} cached downloads ({})",
+                    indicatif::HumanBytes(bytes)
+                );
+            }
+        }
+        Ok(())
+    }
+}
 
-    /// Download chunk size
-    #[arg(short, long, default_value_t = 65_536)]
-    chunk_size: usize,
+/// What `dlm config` can do.
+#[derive(Clone, Subcommand)]
+pub enum ConfigAction {
+    /// Print each flag with a value, and whether it's the default or came
+    /// from a variable or the command line; secrets are redacted
+    Show,
+}
 
-    /// Resume if the file already exists and isn't complete
-    #[arg(short, long)]
-    resume: bool,
+impl ConfigAction {
+    fn run(&self, cli: &Cli) -> anyhow::Result<()> {
+        match self {
+            ConfigAction::Show => {
+                for setting in &cli.settings {
+                    println!("{setting}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
-    /// Overwrite existing file
-    #[arg(short, long)]
-    overwrite: bool,
+/// What `dlm diagnostics` can do.
+#[derive(Clone, Subcommand)]
+pub enum DiagnosticsAction {
+    /// List every warning's ID, what it's about and the code --strict
+    /// exits with
+    List,
+}
 
-    /// Don't cleanup part files after merging (for debugging)
-    #[arg(long)]
-    no_cleanup: bool,
+impl DiagnosticsAction {
+    fn run(&self) -> anyhow::Result<()> {
+        match self {
+            DiagnosticsAction::List => {
+                println!("{:<24}  EXIT  WHAT", "ID");
+                for id in WarningId::ALL {
+                    println!(
+                        "{:<24}  {:>4}  {}",
+                        id.as_str(),
+                        id.exit_code(),
+                        id.description()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-impl Cli {
-    pub async fn execute(self) -> anyhow::Result<()> {
-        self.command
-            .execute(
-                self.url,
-                &self.target_directory,
-                self.chunk_size,
-                self.resume,
-                self.overwrite,
-                self.no_cleanup,
-            )
-            .await
+/// What `dlm schema` can do.
+#[derive(Clone, Subcommand)]
+pub enum SchemaAction {
+    /// Print an artifact's JSON Schema, or all of them keyed by name
+    Dump {
+        /// progress, status, web-status, plan, batch-plan, chunk-log,
+        /// transcript, error-report, manifest, usage, version, compare or
+        /// audit
+        #[arg(value_parser = parse_artifact)]
+        artifact: Option<Artifact>,
+    },
+}
+
+impl SchemaAction {
+    fn run(&self) -> anyhow::Result<()> {
+        match self {
+            SchemaAction::Dump { artifact } => {
+                let schema = match artifact {
+                    Some(artifact) => serde_json::to_value(artifact.schema())?,
+                    None => Value::Object(
+                        Artifact::ALL
+                            .into_iter()
+                            .map(|artifact| {
+                                Ok((
+                                    artifact.name().to_string(),
+                                    serde_json::to_value(artifact.schema())?,
+                                ))
+                            })
+                            .collect::<serde_json::Result<_>>()?,
+                    ),
+                };
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Subcommand)]
-pub enum Commands {
-    DownloadBlocking,
-    DownloadAsync {
-        /// Use workers to download, by default this is 1, for single-worker driven.
-        #[arg(short, long, default_value_t = 1)]
-        workers: u8,
+fn parse_artifact(name: &str) -> Result<Artifact, String> {
+    Artifact::named(name).ok_or_else(|| {
+        let names: Vec<&str> = Artifact::ALL
+            .iter()
+            .map(|artifact| artifact.name())
+            .collect();
+        format!(
+            "unknown artifact '{name}', expected one of {}",
+            names.join(", ")
+        )
+    })
+}
+
+/// Requests `dlm ctl` can send; each prints the download's status after it.
+#[derive(Clone, Subcommand)]
+pub enum CtlRequest {
+    /// Print progress, state, and pause and rate limit settings as JSON
+    Status,
+    Pause,
+    Resume,
+    /// Stop the download, like Ctrl+C
+    Cancel,
+    /// Change the rate limit while downloading
+    SetRateLimit {
+        /// Bytes per second (e.g. 500k, 2M), 0 for unlimited
+        #[arg(value_parser = utils::parse_byte_size)]
+        rate: u64,
     },
 }
 
+impl CtlRequest {
+    async fn send(&self, socket: &Path) -> anyhow::Result<()> {
+        let (method, params) = match self {
+            CtlRequest::Status => ("status", Value::Null),
+            CtlRequest::Pause => ("pause", Value::Null),
+            CtlRequest::Resume => ("resume", Value::Null),
+            CtlRequest::Cancel => ("cancel", Value::Null),
+            CtlRequest::SetRateLimit { rate } => (
+                "set-rate-limit",
+                serde_json::to_value(control::RateLimit { rate: *rate })?,
+            ),
+        };
+        let status = control::call(socket, method, params).await?;
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        Ok(())
+    }
+}
+
 impl Commands {
-    async fn execute(
-        &self,
-        url: Url,
-        target_directory: &PathBuf,
-        chunk_size: usize,
-        resume: bool,
-        overwrite: bool,
-        no_cleanup: bool,
-    ) -> anyhow::Result<()> {
-        use std::sync::atomic::Ordering;
-        fs::create_dir_all(target_directory)?;
+    async fn execute(&self, cli: &Cli, shutdown: &Shutdown) -> anyhow::Result<()> {
+        match self {
+            Commands::Report { chunk_log } => return report::print_report(chunk_log),
+            Commands::Ctl { socket, request } => return request.send(socket).await,
+            Commands::Cache { action } => return action.run(cli),
+            Commands::Diagnostics { action } => return action.run(),
+            Commands::Schema { action } => return action.run(),
+            Commands::Config { action } => return action.run(cli),
+            Commands::Hash { paths, algo, check } => {
+                return match check {
+                    Some(sums) => hash::check_sums(sums, *algo, &cli.memory),
+                    None => hash::print_sums(paths, *algo, &cli.memory),
+                };
+            }
+            Commands::Audit {
+                update,
+                algo,
+                jobs,
+                json,
+            } => {
+                return audit::run(
+                    &cli.target_directory,
+                    *algo,
+                    *jobs,
+                    *update,
+                    *json,
+                    &cli.memory,
+                );
+            }
+            Commands::Version { json } => return version::print_version(*json, &cli.from_env),
+            Commands::Compare { .. } => return self.compare(cli, shutdown).await,
+            Commands::Status => return state::print_status(&cli.active_downloads()?),
+            Commands::Usage {
+                by,
+                limit,
+                enforce,
+                no_limit,
+            } => return cli.usage(*by, limit, *enforce, *no_limit),
+            Commands::Resume { id } => return cli.resume(id, shutdown).await,
+            Commands::ResumeAll { dir, parallel } => {
+                return cli.resume_all(dir.as_deref(), *parallel, shutdown).await;
+            }
+            Commands::Replay { transcript } => return replay::replay(transcript),
+            _ => {}
+        }
+        self.fetch(cli, shutdown)
+            .await
+            .map_err(|error| proxy::explain(error, &cli.client_options()))
+    }
+
+    /// Runs one of the commands that download.
+    async fn fetch(&self, cli: &Cli, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let resolved = self.resolve(cli).await?;
+        if cli.dry_run {
+            return self
+                .dry_run(cli, &resolved.url, &resolved.client_options)
+                .await;
+        }
+        let phases = cli.phases(&resolved.client_options)?;
+        if let Some(reason) = phases.not_newer(&resolved.url).await? {
+            println!("Skipping {}: {reason}", resolved.url);
+            return Ok(());
+        }
 
-        // Print initial info
-        println!("Downloading {} to {}", url, target_directory.display());
-        if resume {
+        utils::prepare_target_dir(&cli.target_directory)?;
+        let verb = match self {
+            Commands::Repair { .. } => "Repairing",
+            _ => "Downloading",
+        };
+        println!(
+            "{verb} {} to {}",
+            resolved.url,
+            cli.target_directory.display()
+        );
+        if cli.resume {
             println!("Resume mode enabled");
         }
-        if overwrite {
+        if cli.overwrite {
             println!("Overwrite mode enabled");
         }
 
-        let interrupted = Arc::new(AtomicBool::new(false));
-        let interrupted_clone = interrupted.clone();
-        ctrlc::set_handler(move || {
-            interrupted_clone.store(true, Ordering::SeqCst);
-        })
-        .expect("Could not set keyboard interrupt handler.");
-
-        let download_start = std::time::Instant::now();
-        let start_time = std::time::Instant::now();
-
-        let path = match &self {
-            Commands::DownloadBlocking => {
-                self.download_blocking(
-                    url,
-                    target_directory,
-                    chunk_size,
-                    resume,
-                    overwrite,
-                    interrupted,
-                    download_start,
-                    start_time,
-                )
-                .await?
-            }
-            Commands::DownloadAsync { workers } if *workers <= 1 => {
-                self.download_async_single(
-                    url,
-                    target_directory,
-                    chunk_size,
-                    resume,
-                    overwrite,
-                    interrupted,
-                    download_start,
-                    start_time,
-                )
-                .await?
-            }
-            Commands::DownloadAsync { workers } => {
-                self.download_async_multi(
-                    url,
-                    target_directory,
-                    chunk_size,
-                    *workers,
-                    interrupted,
-                    no_cleanup,
-                    download_start,
+        let interrupted = shutdown.child();
+        let (mut options, destination, name) = self.options(cli, &resolved)?;
+        cli.reuse_pin().await;
+        let usage_log = cli.usage_log();
+        if let Some(log) = &usage_log {
+            log.check(cli.force)?;
+        }
+        let usage_before = phases.check_quota(&resolved.url, &destination).await?;
+        let Some(target) = self
+            .settle(cli, &resolved, destination, &mut options, &interrupted)
+            .await?
+        else {
+            return Ok(());
+        };
+        let Resolved {
+            url,
+            release,
+            #[cfg(feature = "torrent")]
+            torrent,
+            client_options,
+            ..
+        } = resolved;
+        let (mut session, _guards) = cli
+            .start_session(url, options, interrupted, &target.destination, &name)
+            .await?;
+
+        // Trace level keeps this free unless tracing is asked for.
+        let span = tracing::trace_span!(
+            "download",
+            url = %http::redact_url(&session.url),
+            workers = %match self {
+                Commands::DownloadAsync { workers } => *workers,
+                _ => Workers::Count(1),
+            },
+            resumed = cli.resume || cli.continue_at.is_some() || target.adopted.is_some(),
+            size = field::Empty,
+            sha256 = field::Empty,
+            ttfb_ms = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+        );
+        let mut post_processing = cli.post_processing(&session.interrupted);
+        #[cfg(feature = "torrent")]
+        if let Some(torrent) = torrent {
+            post_processing.push(post_steps::TorrentPieces {
+                torrent,
+                interrupted: session.interrupted.clone(),
+            });
+        }
+        if cli.name_by_hash {
+            post_processing.push(post_steps::NameByHash {
+                name: name.clone(),
+                template: cli.name_template.clone(),
+            });
+        }
+        let result = phases
+            .transfer(
+                &session.url,
+                &target.destination,
+                &post_processing,
+                || async {
+                    let min_tls = client_options.min_tls;
+                    let path = self
+                        .download(cli, client_options, &session)
+                        .await
+                        .map_err(|error| tls::explain(error, min_tls))?;
+                    Ok((path, session.snapshot().unwrap_or_default()))
+                },
+            )
+            .instrument(span.clone())
+            .await;
+        session
+            .finish(cli, &result, usage_log.as_ref(), &target, &release)
+            .await;
+        let (outcome, timings, stats) = match result {
+            Ok((outcome, timings)) => {
+                let hash = outcome.sha256.expect("the hash step runs first");
+                let size = fs::metadata(&outcome.path).map_or(0, |metadata| metadata.len());
+                span.record("size", size);
+                span.record("sha256", hex::encode(hash));
+                if let Some(ttfb) = session.snapshot().and_then(|snapshot| snapshot.ttfb_ms) {
+                    span.record("ttfb_ms", ttfb);
+                }
+                span.record("otel.status_code", "OK");
+                session.completed(cli, &outcome, size);
+                let post_processing: Duration = timings.iter().map(|timing| timing.elapsed).sum();
+                let stats = Stats::new(
+                    http::redact_url(&session.url),
+                    &outcome.path,
+                    hex::encode(hash),
+                    session.start.elapsed().saturating_sub(post_processing),
+                    &session.snapshot().unwrap_or_default(),
+                );
+                (outcome, timings, stats)
+            }
+            Err(error) => {
+                span.record("otel.status_code", "ERROR");
+                span.record("otel.status_message", format!("{error:#}"));
+                return Err(error);
+            }
+        };
+        self.print_done(cli, &target, &outcome, &timings, usage_before)?;
+        if cli.stats {
+            print!("{}", stats.table());
+        }
+        if let Some(write_out) = &cli.write_out {
+            print!("{}", write_out.render(&stats));
+        }
+        Ok(())
+    }
+
+    /// The URL to download, resolved from a release, a landing page or a
+    /// torrent as the flags say, and the client options to ask for it with.
+    /// Fails before anything is downloaded when the flags don't go together.
+    async fn resolve(&self, cli: &Cli) -> anyhow::Result<Resolved> {
+        let Some(url) = cli.url.clone().or_else(|| cli.latest_release()) else {
+            Cli::command()
+                .bin_name("dlm")
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "a URL is required for downloads",
                 )
-                .await?
+                .exit();
+        };
+        #[cfg(feature = "releases")]
+        let (url, release) = cli.resolve_release(url).await?;
+        #[cfg(not(feature = "releases"))]
+        let release: Option<(Url, Asset)> = None;
+        let url = cli.strip_tracking_params(url);
+        utils::validate_url(&url)?;
+        error_report::track(&url, None);
+        if cli.method != Method::GET && presigned::is_presigned(&url) {
+            return Err(DownloadError::PresignedMethod {
+                method: cli.method.to_string(),
             }
+            .into());
+        }
+
+        // Resolve the source address before touching the disk or network so
+        // a bad `--interface` fails fast.
+        let mut client_options = cli.client_options();
+        client_options.local_address()?;
+        let body = cli.request_body()?;
+        if cli.hybrid_streaming
+            && !matches!(self, Commands::DownloadAsync { workers } if workers.splits())
+        {
+            bail!("--hybrid-streaming needs download-async --workers 2 or more");
+        }
+        if !cli.mirror.is_empty() && !matches!(self, Commands::DownloadAsync { .. }) {
+            bail!("--mirror needs download-async");
+        }
+        if cli.segment_size == SegmentSize::Auto && cli.segment_size_min > cli.segment_size_max {
+            bail!(
+                "--segment-size-min {} is more than --segment-size-max {}",
+                Size(cli.segment_size_min),
+                Size(cli.segment_size_max)
+            );
+        }
+        #[cfg(feature = "http3")]
+        if client_options
+            .negotiate_http3(&url, cli.http3_mode())
+            .await?
+        {
+            http::use_http3(true);
+            tracing::info!("Using HTTP/3");
+        }
+        let url = match cli.follow_landing_page {
+            true => cli.resolve_landing_page(url, &client_options).await?,
+            false => url,
         };
+        #[cfg(not(feature = "torrent"))]
+        cli.check_torrent(&url)?;
+        #[cfg(feature = "torrent")]
+        let (url, torrent) = cli.check_torrent(url, &client_options).await?;
+        Ok(Resolved {
+            url,
+            release,
+            #[cfg(feature = "torrent")]
+            torrent,
+            client_options,
+            body,
+        })
+    }
 
-        // Common hashing logic
-        let hash = utils::hash_file(&path, chunk_size)?;
-        println!("Downloaded to: {}", path.display());
-        println!("SHA256: {}", hex::encode(hash));
+    /// The transfer options of the download `resolved` is of, where it's
+    /// saved, and its name.
+    fn options(
+        &self,
+        cli: &Cli,
+        resolved: &Resolved,
+    ) -> anyhow::Result<(TransferOptions, PathBuf, String)> {
+        let mut options = cli.transfer_options();
+        options.chunk_log = cli.chunk_log.as_deref().map(ChunkLog::open).transpose()?;
+        options.pieces = cli.piece_hashes()?;
+        options.body = resolved.body.clone();
+        // Nor an asset's URL in its name.
+        if let Some((_, asset)) = &resolved.release
+            && options.output.is_none()
+        {
+            options.output = Some(PathBuf::from(&asset.name));
+        }
+        // A seed's URL needn't end in the torrent's name.
+        #[cfg(feature = "torrent")]
+        if let Some(torrent) = &resolved.torrent
+            && options.output.is_none()
+        {
+            options.output = Some(PathBuf::from(&torrent.name));
+        }
+        let mut destination = options.destination(&resolved.url, &cli.target_directory);
+        let name = destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        // Its name is only known once it's all there.
+        if cli.name_by_hash {
+            if let Commands::ZipExtract { .. } | Commands::Repair { .. } = self {
+                bail!("--name-by-hash can't be used with zip-extract or repair");
+            }
+            destination = naming::temporary_path(&destination);
+            options.output = Some(destination.clone());
+        }
+        if cli.store_compressed.is_some()
+            && let Commands::ZipExtract { .. } | Commands::Repair { .. } = self
+        {
+            bail!("--store-compressed can't be used with zip-extract or repair");
+        }
+        Ok((options, destination, name))
+    }
+
+    /// Settles what's already at `destination` before the download starts:
+    /// adopts it, checks it's the start of the download being resumed, or
+    /// takes note of it and moves it out of the way to be overwritten.
+    /// Returns `None` when `--on-conflict skip` leaves the download out.
+    async fn settle(
+        &self,
+        cli: &Cli,
+        resolved: &Resolved,
+        mut destination: PathBuf,
+        options: &mut TransferOptions,
+        interrupted: &Arc<AtomicBool>,
+    ) -> anyhow::Result<Option<Target>> {
+        let (url, client_options) = (&resolved.url, &resolved.client_options);
+        let adopted = cli.adopt(url, &destination, client_options).await?;
+        options.resume |= adopted.is_some();
+        if adopted.is_none()
+            && !cli.name_by_hash
+            && options.store_compressed.is_none()
+            && let Commands::DownloadBlocking | Commands::DownloadAsync { .. } = self
+        {
+            let Some(settled) = cli.settle_resume(url, &destination, client_options).await? else {
+                return Ok(None);
+            };
+            if settled != destination {
+                let name = PathBuf::from(settled.file_name().unwrap_or_default());
+                options.output = Some(match &options.output {
+                    Some(output) => output.with_file_name(name),
+                    None => name,
+                });
+                destination = settled;
+            }
+        }
+        let mut replaced = match cli.overwrite
+            && adopted.is_none()
+            && matches!(
+                self,
+                Commands::DownloadBlocking | Commands::DownloadAsync { .. }
+            )
+            && destination.is_file()
+        {
+            true => Some(
+                replaced::record(&destination, cli.diff_hash, interrupted, &cli.memory)
+                    .with_context(|| {
+                        format!(
+                            "Cannot read '{}' before replacing it",
+                            destination.display()
+                        )
+                    })?,
+            ),
+            false => None,
+        };
+        // Overwriting truncates in place, leaving nothing to trash.
+        if cli.overwrite
+            && adopted.is_none()
+            && cli.removal() == Removal::Trash
+            && !matches!(self, Commands::Repair { .. })
+            && destination.is_file()
+        {
+            let removed = cli.replace(&destination)?;
+            if let Some(replaced) = &mut replaced {
+                replaced.trashed = removed == Removed::Trashed;
+            }
+        }
+        // The `.part` of an earlier run goes too, rather than linger until
+        // this one's renamed over the file.
+        let stale = partial::path(&destination);
+        if cli.overwrite && adopted.is_none() && stale.is_file() {
+            fs_ops::remove_file(&stale)
+                .with_context(|| format!("Cannot remove '{}'", stale.display()))?;
+        }
+        let earlier = cli.resume_from(url)?;
+        options.resume_from = earlier.as_ref().map(|earlier| ResumeFrom {
+            file: earlier.destination.clone(),
+            layout: earlier.parts.clone().unwrap_or_default(),
+        });
+        Ok(Some(Target {
+            destination,
+            adopted,
+            replaced,
+            earlier,
+        }))
+    }
 
+    /// What a finished download prints: where it went, its hash, the TLS
+    /// it negotiated, how the file it replaced compares, and what the steps
+    /// after it took.
+    fn print_done(
+        &self,
+        cli: &Cli,
+        target: &Target,
+        outcome: &DownloadOutcome,
+        timings: &[StepTiming],
+        usage_before: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let path = &outcome.path;
+        let hash = outcome.sha256.expect("the hash step runs first");
+        let verb = match self {
+            Commands::Repair { .. } => "Repaired",
+            _ => "Downloaded to",
+        };
+        println!("{verb}: {}", path.display());
+        if let Some(reused) = target.adopted {
+            println!("Reused: {} from the adopted file", speed::Size(reused));
+        }
+        println!("SHA256: {}", hex::encode(hash));
+        if let Some(session) = &outcome.snapshot.tls {
+            println!("TLS: {session}");
+        }
+        if let Some(replaced) = &target.replaced {
+            let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+            println!("{}", replaced::compare(replaced, size, &hex::encode(hash)));
+        }
+        println!("Post-processing: {}", Timings(timings));
+        if let Some(before) = usage_before {
+            let after = quota::dir_usage(&cli.target_directory)?;
+            println!(
+                "Target directory: {} before, {} after",
+                speed::Size(before),
+                speed::Size(after)
+            );
+        }
         Ok(())
     }
 
+    /// Runs the download for this subcommand, returning where it landed.
+    async fn download(
+        &self,
+        cli: &Cli,
+        client_options: ClientOptions,
+        session: &Session,
+    ) -> anyhow::Result<PathBuf> {
+        match (&self, cli.tail) {
+            // The tail is a single small request, so the mode doesn't matter.
+            (_, Some(length)) => {
+                let client = client_options.build_async()?;
+                download_tail(
+                    &client,
+                    session.url.clone(),
+                    &cli.target_directory,
+                    length,
+                    &session.options,
+                )
+                .await
+            }
+            (Commands::DownloadBlocking, None) => {
+                self.download_blocking(cli, client_options, session).await
+            }
+            (Commands::DownloadAsync { workers }, None) => {
+                let downloader = Downloader::with_client_options(client_options)?
+                    .with_target_dir(&cli.target_directory)
+                    .with_workers(*workers)
+                    .with_zero_copy(cli.zero_copy)
+                    .with_options(session.options.clone());
+                match workers.splits() {
+                    true => {
+                        self.download_async_multi(cli, &downloader, *workers, session)
+                            .await
+                    }
+                    false => self.download_async_single(cli, &downloader, session).await,
+                }
+            }
+            (Commands::ZipExtract { member }, None) => {
+                self.zip_extract(cli, member, client_options, session).await
+            }
+            (
+                Commands::Repair {
+                    file,
+                    compare,
+                    sample_size,
+                },
+                None,
+            ) => {
+                let client = client_options.build_async()?;
+                let path = match file {
+                    Some(file) => file.clone(),
+                    None => session
+                        .options
+                        .destination(&session.url, &cli.target_directory),
+                };
+                self.repair(cli, &client, &path, *compare, *sample_size, session)
+                    .await?;
+                Ok(path)
+            }
+            (
+                Commands::Report { .. }
+                | Commands::Ctl { .. }
+                | Commands::Cache { .. }
+                | Commands::Hash { .. }
+                | Commands::Audit { .. }
+                | Commands::Usage { .. }
+                | Commands::Diagnostics { .. }
+                | Commands::Schema { .. }
+                | Commands::Config { .. }
+                | Commands::Version { .. }
+                | Commands::Compare { .. }
+                | Commands::Status
+                | Commands::Resume { .. }
+                | Commands::ResumeAll { .. }
+                | Commands::Replay { .. },
+                None,
+            ) => {
+                unreachable!("only downloads get here")
+            }
+        }
+    }
+
+    async fn dry_run(
+        &self,
+        cli: &Cli,
+        url: &Url,
+        client_options: &ClientOptions,
+    ) -> anyhow::Result<()> {
+        let workers = match self {
+            Commands::DownloadBlocking => Workers::Count(1),
+            Commands::DownloadAsync { workers } => *workers,
+            Commands::ZipExtract { .. } => bail!("--dry-run isn't supported for zip-extract"),
+            Commands::Repair { .. } => bail!("--dry-run isn't supported for repair"),
+            Commands::Report { .. }
+            | Commands::Ctl { .. }
+            | Commands::Cache { .. }
+            | Commands::Hash { .. }
+            | Commands::Audit { .. }
+            | Commands::Usage { .. }
+            | Commands::Diagnostics { .. }
+            | Commands::Schema { .. }
+            | Commands::Config { .. }
+            | Commands::Version { .. }
+            | Commands::Compare { .. }
+            | Commands::Status
+            | Commands::Resume { .. }
+            | Commands::ResumeAll { .. }
+            | Commands::Replay { .. } => {
+                unreachable!("only downloads get here")
+            }
+        };
+        let client = client_options.build_async()?;
+        let mut options = cli.transfer_options();
+        options.pieces = cli.piece_hashes()?;
+        let plan = plan_download(
+            &client,
+            client_options,
+            url,
+            &cli.target_directory,
+            workers,
+            &options,
+        )
+        .await?;
+        dry_run::print_plan(&plan, cli.json)
+    }
+
     async fn download_blocking(
         &self,
-        url: Url,
-        target_directory: &PathBuf,
-        chunk_size: usize,
-        resume: bool,
-        overwrite: bool,
-        interrupted: Arc<AtomicBool>,
-        download_start: std::time::Instant,
-        start_time: std::time::Instant,
+        cli: &Cli,
+        client_options: ClientOptions,
+        session: &Session,
     ) -> anyhow::Result<PathBuf> {
-        let progress = DownloadProgress::new(interrupted.clone());
-        let bar = indicatif::ProgressBar::new_spinner();
-        bar.enable_steady_tick(Duration::from_millis(100));
-        bar.set_message("Starting download...");
-
-        let target_directory = target_directory.clone();
-        let url = url.clone();
-        tokio::task::spawn_blocking(move || {
-            let path = download_file_blocking(
-                url,
-                &target_directory,
-                chunk_size,
-                resume,
-                overwrite,
-                progress,
-            )?;
-            let download_time = download_start.elapsed();
-            bar.finish_with_message(format!(
-                "Download complete in {}, calculating hash",
-                indicatif::HumanDuration(download_time)
-            ));
-            Ok(path)
+        let target_directory = cli.target_directory.clone();
+        let url = session.url.clone();
+        let chunk_size = cli.chunk_size;
+        let options = session.options.clone();
+        // The spinner and the hash with its own progress bar are done here.
+        let job = move |client: &reqwest::blocking::Client, progress| {
+            BlockingDownloader::with_client(client.clone())
+                .with_target_dir(target_directory)
+                .with_chunk_size(chunk_size)
+                .with_options(options)
+                .transfer(url, progress)
+        };
+        self.run_blocking(cli, client_options, session, job).await
+    }
+
+    async fn zip_extract(
+        &self,
+        cli: &Cli,
+        member: &str,
+        client_options: ClientOptions,
+        session: &Session,
+    ) -> anyhow::Result<PathBuf> {
+        let target_directory = cli.target_directory.clone();
+        let url = session.url.clone();
+        let member = member.to_string();
+        let options = session.options.clone();
+        let job = move |client: &reqwest::blocking::Client, progress| {
+            extract_zip_member(client, url, &member, &target_directory, progress, &options)
+        };
+        self.run_blocking(cli, client_options, session, job).await
+    }
+
+    /// Runs a job on the blocking client off the async runtime, with a
+    /// spinner and the `--min-avg-speed` watchdog around it.
+    async fn run_blocking(
+        &self,
+        cli: &Cli,
+        client_options: ClientOptions,
+        session: &Session,
+        job: impl FnOnce(&reqwest::blocking::Client, TransferProgress) -> anyhow::Result<PathBuf>
+        + Send
+        + 'static,
+    ) -> anyhow::Result<PathBuf> {
+        let progress = TransferProgress::new(session.interrupted.clone());
+        let renderer = cli.renderer(
+            &session.url,
+            Spinner::new(cli.stall_policy().stall_timeout)
+                .with_sparkline(!cli.no_sparkline)
+                .with_glyphs(cli.glyphs()),
+        );
+        let (renderer, render_task) = spawn_renderer(renderer, &progress, cli, session);
+
+        let download = tokio::task::spawn_blocking({
+            let progress = progress.clone();
+            let span = tracing::Span::current();
+            move || {
+                let _span = span.enter();
+                let client = client_options.build_blocking()?;
+                job(&client, progress)
+            }
+        });
+        let downloaded = || progress.downloaded();
+        let path = guard_min_speed(cli, &session.interrupted, downloaded, async {
+            download.await?
         })
-        .await?
+        .await?;
+        render_task.stop();
+        let download_time = session.start.elapsed();
+        let first_byte = session
+            .snapshot()
+            .and_then(|snapshot| snapshot.ttfb_ms)
+            .map(|ttfb| format!(" (first byte after {ttfb}ms)"))
+            .unwrap_or_default();
+        renderer.finish(
+            &progress,
+            &format!(
+                "Download complete in {}{first_byte}, calculating hash",
+                indicatif::HumanDuration(download_time)
+            ),
+        );
+        // Zip members are read without it.
+        if !progress.time_split.network().is_zero() {
+            println!("Time split: {}", progress.time_split);
+        }
+        print_wasted(&progress);
+        Ok(path)
     }
 
     async fn download_async_single(
         &self,
-        url: Url,
-        target_directory: &PathBuf,
-        chunk_size: usize,
-        resume: bool,
-        overwrite: bool,
-        interrupted: Arc<AtomicBool>,
-        download_start: std::time::Instant,
-        start_time: std::time::Instant,
+        cli: &Cli,
+        downloader: &Downloader,
+        session: &Session,
     ) -> anyhow::Result<PathBuf> {
-        let progress = DownloadProgress::new(interrupted);
-        let path = download_file_async(url, target_directory, resume, overwrite, progress).await?;
-        let download_time = download_start.elapsed();
-        println!("Download complete in {}, calculating hash", indicatif::HumanDuration(download_time));
+        let progress = TransferProgress::new(session.interrupted.clone());
+        let renderer = cli.renderer(
+            &session.url,
+            Spinner::new(cli.stall_policy().stall_timeout)
+                .with_sparkline(!cli.no_sparkline)
+                .with_glyphs(cli.glyphs()),
+        );
+        let (_, render_task) = spawn_renderer(renderer, &progress, cli, session);
+        let download = downloader.download(session.url.clone(), progress.clone());
+        let downloaded = || progress.downloaded();
+        let result = guard_min_speed(cli, &session.interrupted, downloaded, download).await;
+        drop(render_task);
+        let path = result?;
+        let download_time = session.start.elapsed();
+        println!(
+            "Download complete in {}, calculating hash",
+            indicatif::HumanDuration(download_time)
+        );
+        print_wasted(&progress);
         Ok(path)
     }
 
-    async fn download_async_multi(
+    async fn repair(
         &self,
-        url: Url,
-        target_directory: &PathBuf,
-        chunk_size: usize,
-        workers: u8,
-        interrupted: Arc<AtomicBool>,
-        no_cleanup: bool,
-        download_start: std::time::Instant,
-    ) -> anyhow::Result<PathBuf> {
-        // Get content length first to create progress bar
-        let content_length = get_content_length(&url).await?;
+        cli: &Cli,
+        client: &reqwest::Client,
+        path: &Path,
+        compare: bool,
+        sample_size: u64,
+        session: &Session,
+    ) -> anyhow::Result<()> {
+        let pieces = session.options.pieces.as_deref();
+        if pieces.is_none() && !compare {
+            bail!(
+                "repair needs --piece-hashes, or --compare to fetch the file in ranges and compare it"
+            );
+        }
+        let progress = TransferProgress::new(session.interrupted.clone());
+        let renderer = cli.renderer(
+            &session.url,
+            Spinner::new(cli.stall_policy().stall_timeout)
+                .with_sparkline(!cli.no_sparkline)
+                .with_glyphs(cli.glyphs()),
+        );
+        let (_, render_task) = spawn_renderer(renderer, &progress, cli, session);
+        let result = repair_file(
+            client,
+            &session.url,
+            path,
+            pieces,
+            sample_size,
+            progress.clone(),
+            &cli.budget,
+            cli.auth_token.as_deref(),
+        )
+        .await;
+        drop(render_task);
+        let summary = result?;
+        println!(
+            "Reused {} and re-fetched {} of {} ({} ranges)",
+            indicatif::HumanBytes(summary.reused),
+            indicatif::HumanBytes(summary.refetched),
+            indicatif::HumanBytes(summary.size),
+            summary.repaired.len()
+        );
+        for range in &summary.repaired {
+            println!("  Re-fetched bytes {}-{}", range.start(), range.end());
+        }
+        print_wasted(&progress);
+        Ok(())
+    }
 
-        // Create progress bar
-        let progress = ChunkProgressBar::new(workers as usize, content_length, interrupted.clone());
+    async fn compare(&self, cli: &Cli, shutdown: &Shutdown) -> anyhow::Result<()> {
+        let Commands::Compare {
+            url_a,
+            url_b,
+            samples,
+            sample_size,
+            full,
+            json,
+        } = self
+        else {
+            unreachable!("compare is only called for `dlm compare`");
+        };
+        utils::validate_url(url_a)?;
+        utils::validate_url(url_b)?;
+        let client = cli.client_options().build_async()?;
+        let sampling = match full {
+            true => Sampling::Full,
+            false => Sampling::Ranges {
+                count: *samples,
+                size: *sample_size,
+            },
+        };
+        let progress = TransferProgress::new(shutdown.child());
+        let renderer = Arc::new(
+            cli.renderer(
+                url_a,
+                Spinner::new(cli.stall_policy().stall_timeout)
+                    .with_sparkline(!cli.no_sparkline)
+                    .with_glyphs(cli.glyphs()),
+            ),
+        );
+        let render_task = tokio::spawn(follow_progress(
+            progress.handle(),
+            cli.progress_interval(),
+            {
+                let renderer = renderer.clone();
+                let progress = progress.clone();
+                move |_| renderer.render(&progress)
+            },
+        ));
+        let render_task = ProgressTaskGuard::new(render_task, &renderer, &progress);
+        let result = compare_mirrors(
+            &client,
+            url_a,
+            url_b,
+            sampling,
+            progress.clone(),
+            &cli.budget,
+            cli.auth_token.as_deref(),
+        )
+        .await;
+        drop(render_task);
+        let comparison = result?;
 
-        // Spawn a background task to render progress
-        let progress_clone = progress.clone();
-        let render_task = tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(100));
-            loop {
-                interval.tick().await;
-                progress_clone.render();
+        if *json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&Versioned::new(&comparison))?
+            );
+        } else {
+            for (name, mirror) in [("A", &comparison.a), ("B", &comparison.b)] {
+                println!(
+                    "{name}: {} ({}, ETag {}, Last-Modified {}{})",
+                    mirror.url,
+                    mirror
+                        .size
+                        .map_or("unknown size".to_string(), |size| size.to_string()
+                            + " bytes"),
+                    mirror.etag.as_deref().unwrap_or("none"),
+                    mirror.last_modified.as_deref().unwrap_or("none"),
+                    if mirror.ranges {
+                        ""
+                    } else {
+                        ", no range support"
+                    }
+                );
             }
-        });
+            let how = match comparison.full {
+                true => "every byte",
+                false => "samples",
+            };
+            match &comparison.divergence {
+                None => println!(
+                    "Identical: compared {} bytes ({how}){}",
+                    comparison.compared,
+                    comparison
+                        .sha256
+                        .as_ref()
+                        .map(|sha256| format!(", sha256 {sha256}"))
+                        .unwrap_or_default()
+                ),
+                Some(divergence) => println!(
+                    "Different at byte {}: {} (compared {} bytes, {how})",
+                    divergence.offset, divergence.reason, comparison.compared
+                ),
+            }
+        }
+        if let Some(divergence) = comparison.divergence {
+            bail!("The mirrors differ from byte {}", divergence.offset);
+        }
+        Ok(())
+    }
 
-        // Download with workers
-        let path =
-            download_with_workers(url, target_directory, workers, progress.clone(), no_cleanup)
-                .await?;
+    async fn download_async_multi(
+        &self,
+        cli: &Cli,
+        downloader: &Downloader,
+        workers: Workers,
+        session: &Session,
+    ) -> anyhow::Result<PathBuf> {
+        // The size isn't known yet: the download adds the chunks a bigger
+        // file is split into, and drops those a smaller one isn't.
+        let first = match workers {
+            Workers::Count(count) => fd_limit::cap_workers(count),
+            Workers::Auto => 1,
+        };
+        let progress = TransferProgress::chunked(
+            session.options.segments(first, 0).into(),
+            0,
+            session.interrupted.clone(),
+        );
+        let renderer = cli.renderer(
+            &session.url,
+            ChunkBar::new(cli.stall_policy().stall_timeout)
+                .with_sparkline(!cli.no_sparkline)
+                .with_glyphs(cli.glyphs()),
+        );
+        let (renderer, render_task) = spawn_renderer(renderer, &progress, cli, session);
+        // The download probes the length with the bar up, so it shows each
+        // step.
+        let download = downloader.download(session.url.clone(), progress.clone());
+        // Worker mode judges the aggregate speed, not individual chunks.
+        let downloaded = || progress.downloaded();
+        let path = guard_min_speed(cli, &session.interrupted, downloaded, download).await?;
 
         // Stop the render task
-        render_task.abort();
+        render_task.stop();
 
-        let download_time = download_start.elapsed();
+        let download_time = session.start.elapsed();
 
-        // Finish the progress bar
-        progress.finish(&format!(
-            "Download complete in {}, calculating hash",
-            indicatif::HumanDuration(download_time)
-        ));
+        // Finish the progress bar; the parts were hashed as they merged.
+        renderer.finish(
+            &progress,
+            &format!(
+                "Download complete in {}",
+                indicatif::HumanDuration(download_time)
+            ),
+        );
+        print_wasted(&progress);
+        print_part_checks(&progress);
+        print_reassignments(&progress);
+        print_segment_tuning(&progress);
+        print_redirects(&progress);
+        print_connections();
 
         Ok(path)
     }
 }
+
+/// What the flags of a command line set for the whole process rather than
+/// for a download, which downloads resumed together have to agree on.
+#[derive(PartialEq)]
+struct Globals {
+    show_secrets: bool,
+    show_error_body: bool,
+    presigned: bool,
+    wait_for_target: Option<Duration>,
+    wait_for_network: Option<Duration>,
+    max_rate_limit_wait: Duration,
+    strict: bool,
+    strict_allow: Vec<WarningId>,
+    speed_units: SpeedUnits,
+}
+
+impl Globals {
+    /// Sets them for the process.
+    fn apply(&self) {
+        http::show_secrets(self.show_secrets);
+        http::show_error_body(self.show_error_body);
+        presigned::force(self.presigned);
+        target_wait::set_wait(self.wait_for_target);
+        network_wait::set_wait(self.wait_for_network);
+        rate_limit::set_max_wait(self.max_rate_limit_wait);
+        diagnostics::set_strict(self.strict, &self.strict_allow);
+        speed::use_speed_units(self.speed_units);
+    }
+}
+
+/// The command line `manifest`'s download was started with.
+fn manifest_cli(manifest: &Manifest) -> anyhow::Result<Cli> {
+    Cli::try_parse_with_env(
+        std::iter::once("dlm".to_string()).chain(manifest.resume_args().to_vec()),
+    )
+    .with_context(|| format!("Cannot parse the command of download {}", manifest.id))
+}
+
+/// How the remote file of `manifest`'s download changed since, by size
+/// or ETag, if it did, as in `is now 2 MiB instead of 1 MiB`, asked with
+/// `cli`, the flags it was started with.
+async fn remote_change(
+    manifest: &Manifest,
+    cli: &Cli,
+    budget: &RetryBudget,
+) -> anyhow::Result<Option<String>> {
+    if cli.method != Method::GET || (manifest.total == 0 && manifest.etag.is_none()) {
+        return Ok(None);
+    }
+    let url = Url::parse(&manifest.url)?;
+    let client = cli.client_options().build_async()?;
+    let remote = remote::probe_remote(&client, &url, budget, cli.auth_token.as_deref()).await?;
+    if manifest.total > 0
+        && let Some(size) = remote.size
+        && size != manifest.total
+    {
+        return Ok(Some(format!(
+            "is now {} instead of {}",
+            Size(size),
+            Size(manifest.total)
+        )));
+    }
+    if let (Some(before), Some(now)) = (&manifest.etag, &remote.etag)
+        && before != now
+    {
+        return Ok(Some(format!("has ETag {now} instead of {before}")));
+    }
+    Ok(None)
+}
+
+/// Says how much was downloaded more than once, if anything was.
+fn print_wasted(progress: &TransferProgress) {
+    let snapshot = progress.snapshot();
+    if snapshot.wasted == 0 {
+        return;
+    }
+    let share = match snapshot.total {
+        0 => String::new(),
+        _ => format!(" ({:.1}% of the file)", snapshot.wasted_percent),
+    };
+    println!(
+        "Wasted: {} downloaded for nothing{share}",
+        indicatif::HumanBytes(snapshot.wasted)
+    );
+}
+
+/// Says how many parts were checked before being trusted, and which ones
+/// failed and were downloaded again, if any were checked.
+fn print_part_checks(progress: &TransferProgress) {
+    let checks = progress.snapshot().part_checks;
+    if checks.is_empty() {
+        return;
+    }
+    let failed: Vec<_> = checks
+        .iter()
+        .filter(|check| check.failed.is_some())
+        .collect();
+    println!(
+        "Parts: {} checked, {} downloaded again after failing",
+        checks.len(),
+        failed.len()
+    );
+    for check in failed {
+        println!("  {check}");
+    }
+}
+
+/// Says how often `--chunk-min-speed` moved a chunk to a new connection,
+/// and what that brought in, if it ever did.
+fn print_reassignments(progress: &TransferProgress) {
+    let snapshot = progress.snapshot();
+    if snapshot.reassignments == 0 {
+        return;
+    }
+    println!(
+        "Chunks re-assigned for --chunk-min-speed: {}, bringing in {} on new connections",
+        snapshot.reassignments,
+        indicatif::HumanBytes(snapshot.reassigned_bytes)
+    );
+}
+
+/// Says how `--segment-size auto` sized the segments, if it did.
+fn print_segment_tuning(progress: &TransferProgress) {
+    if let Some(tuning) = progress.snapshot().segment_tuning {
+        println!("Segment size: {tuning}");
+    }
+}
+
+/// Says which chunks' requests were redirected, and how many of the URLs
+/// they were sent to had expired when a retry went back to them.
+fn print_redirects(progress: &TransferProgress) {
+    let redirects = progress.snapshot().chunk_redirects;
+    if redirects.is_empty() {
+        return;
+    }
+    println!("Redirects:");
+    for chunk in redirects {
+        println!("  {chunk}");
+    }
+}
+
+/// Says how many connections the run opened, each a handshake, and how
+/// many of its requests went out on one already open.
+fn print_connections() {
+    let usage = connections::usage();
+    if usage.requests == 0 {
+        return;
+    }
+    println!(
+        "Connections: {} opened for {} requests, {} reused ({:.0}%)",
+        usage.opened,
+        usage.requests,
+        usage.reused(),
+        usage.reuse_percent()
+    );
+}
+
+/// The task drawing a download's progress. Dropped without being
+/// [`stop`](Self::stop)ped, as when the download fails and `?` returns, it's
+/// aborted and the progress taken off the screen, so nothing draws over the
+/// error.
+struct ProgressTaskGuard {
+    task: tokio::task::JoinHandle<()>,
+    clear: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl ProgressTaskGuard {
+    fn new<R: Renderer + 'static>(
+        task: tokio::task::JoinHandle<()>,
+        renderer: &Arc<R>,
+        progress: &TransferProgress,
+    ) -> Self {
+        let (renderer, progress) = (renderer.clone(), progress.clone());
+        Self {
+            task,
+            clear: Some(Box::new(move || renderer.clear(&progress))),
+        }
+    }
+
+    /// Stops drawing, leaving the progress on screen for the caller to
+    /// finish or clear.
+    fn stop(mut self) {
+        self.task.abort();
+        self.clear = None;
+    }
+}
+
+impl Drop for ProgressTaskGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(clear) = self.clear.take() {
+            clear();
+        }
+    }
+}
+
+/// Draws `progress` with `renderer`, and keeps the terminal title in sync,
+/// whenever the download reports progress. The renderer comes back to
+/// finish with once the task is stopped.
+fn spawn_renderer<R: Renderer + 'static>(
+    renderer: R,
+    progress: &TransferProgress,
+    cli: &Cli,
+    session: &Session,
+) -> (Arc<R>, ProgressTaskGuard) {
+    let renderer = Arc::new(renderer);
+    let title = session.title.clone();
+    let keepalive = cli.keepalive_output().map(|every| {
+        PlainText::keepalive(std::io::stderr(), cli.stall_policy().stall_timeout, every)
+    });
+    session.attach(progress.handle());
+    let task = tokio::spawn(follow_progress(
+        progress.handle(),
+        cli.progress_interval(),
+        {
+            let renderer = renderer.clone();
+            let progress = progress.clone();
+            move |snapshot| {
+                renderer.render(&progress);
+                if let Some(keepalive) = &keepalive {
+                    keepalive.render(&progress);
+                }
+                if let Some(title) = &title {
+                    title.update(snapshot.downloaded, snapshot.total);
+                }
+            }
+        },
+    ));
+    let task = ProgressTaskGuard::new(task, &renderer, progress);
+    (renderer, task)
+}
+
+/// Calls `render` whenever the download reports progress, at most once per
+/// `interval`, until it reaches a terminal state.
+async fn follow_progress(
+    mut handle: ProgressHandle,
+    interval: Duration,
+    mut render: impl FnMut(&ProgressSnapshot),
+) {
+    loop {
+        let snapshot = handle.snapshot();
+        render(&snapshot);
+        if snapshot.state.is_terminal() {
+            break;
+        }
+        tokio::time::sleep(interval).await;
+        // Without news, still redraw every second so the speed and stall
+        // hint keep moving.
+        if let Ok(false) = tokio::time::timeout(Duration::from_secs(1), handle.changed()).await {
+            break;
+        }
+    }
+}
+
+/// Races `download` against the `--min-avg-speed` watchdog, if configured.
+async fn guard_min_speed<T>(
+    cli: &Cli,
+    interrupted: &AtomicBool,
+    downloaded: impl Fn() -> u64,
+    download: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let Some(policy) = cli.min_speed_policy() else {
+        return download.await;
+    };
+    tokio::select! {
+        result = download => result,
+        error = speed::watch_min_speed(policy, downloaded) => {
+            // Workers and the blocking loop outlive the dropped future, so
+            // stop them the same way Ctrl+C does.
+            interrupted.store(true, Ordering::SeqCst);
+            Err(error.into())
+        }
+    }
+}
+
+/// `--sha256`: a digest or a sums file.
+fn parse_sha256(value: &str) -> Result<Expected, String> {
+    Expected::parse(value, Algorithm::Sha256, "--sha256")
+}
+
+fn parse_sha1(value: &str) -> Result<Expected, String> {
+    Expected::parse(value, Algorithm::Sha1, "--sha1")
+}
+
+fn parse_md5(value: &str) -> Result<Expected, String> {
+    Expected::parse(value, Algorithm::Md5, "--md5")
+}
+
+/// `--method`: GET, or one of the methods that can carry a request body.
+fn parse_method(value: &str) -> Result<Method, String> {
+    match value.to_ascii_uppercase().as_str() {
+        "GET" => Ok(Method::GET),
+        "POST" => Ok(Method::POST),
+        "PUT" => Ok(Method::PUT),
+        _ => Err(format!("'{value}' isn't one of GET, POST or PUT")),
+    }
+}
+
+/// `--max-requests-per-second`: a rate above zero.
+fn parse_request_rate(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("'{value}' isn't a number of requests above 0")),
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::Context;
+use download_manager::__private::{http, utils};
+use std::io::{BufRead, Write};
+use url::Url;
+
+/// The URLs on the clipboard, for `--from-clipboard`.
+pub fn urls() -> anyhow::Result<Vec<Url>> {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .context("Cannot read text from the clipboard")?;
+    utils::parse_url_list(&text)
+        .map_err(|error| anyhow::anyhow!("Nothing to download on the clipboard: {error}"))
+}
+
+/// Lists `urls` and asks on the terminal whether to download them.
+pub fn confirm(urls: &[Url]) -> anyhow::Result<bool> {
+    println!("On the clipboard:");
+    for url in urls {
+        println!("  {}", http::redact_url(url));
+    }
+    match urls.len() {
+        1 => print!("Download it? [y/N] "),
+        count => print!("Download all {count}? [y/N] "),
+    }
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,299 @@
+use anyhow::{Context, bail};
+use download_manager::__private::throttle::Throttle;
+use download_manager::MemoryLimit;
+use download_manager::ProgressHandle;
+use download_manager::schema::{Status, Versioned};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+
+/// `--control-socket`: lets another process watch and steer the download
+/// over a Unix socket (a named pipe on Windows), one JSON-RPC 2.0 request
+/// per line and one response line each.
+///
+/// Methods: `status`, `pause`, `resume`, `cancel` and `set-rate-limit` with
+/// `{"rate": <bytes/s>}` (0 lifts the limit). Every method answers with the
+/// status after it's applied.
+#[derive(Clone)]
+pub struct ControlSocket {
+    progress: Arc<Mutex<Option<ProgressHandle>>>,
+}
+
+/// Stops serving and removes the socket when dropped, i.e. when the
+/// download is over.
+pub struct ControlGuard {
+    task: JoinHandle<()>,
+    /// The socket file, unless someone else created it.
+    #[cfg(unix)]
+    path: Option<PathBuf>,
+}
+
+struct State {
+    progress: Arc<Mutex<Option<ProgressHandle>>>,
+    throttle: Throttle,
+    memory: Arc<MemoryLimit>,
+    interrupted: Arc<AtomicBool>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Result(Value),
+    Error { code: i64, message: String },
+}
+
+/// What `dlm ctl` sends.
+#[derive(Serialize)]
+struct Call<'a> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'a str,
+    params: Value,
+}
+
+/// The params of `set-rate-limit`.
+#[derive(Serialize, Deserialize)]
+pub struct RateLimit {
+    pub rate: u64,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+impl ControlSocket {
+    pub fn start(
+        path: &Path,
+        throttle: Throttle,
+        memory: Arc<MemoryLimit>,
+        interrupted: Arc<AtomicBool>,
+    ) -> anyhow::Result<(Self, ControlGuard)> {
+        let progress = Arc::default();
+        let state = Arc::new(State {
+            progress: Arc::clone(&progress),
+            throttle,
+            memory,
+            interrupted,
+        });
+        let task = listen(path, state)
+            .with_context(|| format!("Cannot listen on control socket '{}'", path.display()))?;
+        let guard = ControlGuard {
+            task,
+            #[cfg(unix)]
+            path: Some(path.to_path_buf()),
+        };
+        Ok((Self { progress }, guard))
+    }
+
+    /// Like [`start`](Self::start), but on a socket systemd passed in,
+    /// which is left in place afterwards.
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    pub fn inherit(
+        listener: std::os::unix::net::UnixListener,
+        throttle: Throttle,
+        memory: Arc<MemoryLimit>,
+        interrupted: Arc<AtomicBool>,
+    ) -> anyhow::Result<(Self, ControlGuard)> {
+        let progress = Arc::default();
+        let state = Arc::new(State {
+            progress: Arc::clone(&progress),
+            throttle,
+            memory,
+            interrupted,
+        });
+        let listener = tokio::net::UnixListener::from_std(listener)?;
+        let guard = ControlGuard {
+            task: accept(listener, state),
+            path: None,
+        };
+        Ok((Self { progress }, guard))
+    }
+
+    /// Makes `status` report on this transfer.
+    pub fn attach(&self, handle: ProgressHandle) {
+        *self.progress.lock().unwrap() = Some(handle);
+    }
+}
+
+impl Drop for ControlGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+        #[cfg(unix)]
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn listen(path: &Path, state: Arc<State>) -> anyhow::Result<JoinHandle<()>> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    // A socket left behind by a crashed run is replaced, a live one isn't.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("'{}' exists and is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("another download is already listening on it");
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(accept(listener, state))
+}
+
+#[cfg(unix)]
+fn accept(listener: tokio::net::UnixListener, state: Arc<State>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream, Arc::clone(&state)));
+        }
+    })
+}
+
+#[cfg(windows)]
+fn listen(path: &Path, state: Arc<State>) -> anyhow::Result<JoinHandle<()>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = path.as_os_str().to_owned();
+    let create = move |first| {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create(&name)
+    };
+    let mut server = create(true)?;
+    Ok(tokio::spawn(async move {
+        while server.connect().await.is_ok() {
+            let Ok(next) = create(false) else {
+                break;
+            };
+            let client = std::mem::replace(&mut server, next);
+            tokio::spawn(serve(client, Arc::clone(&state)));
+        }
+    }))
+}
+
+async fn serve(stream: impl AsyncRead + AsyncWrite, state: Arc<State>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, outcome) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => match state.handle(&request.method, &request.params) {
+                Ok(result) => (request.id, Outcome::Result(result)),
+                Err((code, message)) => (request.id, Outcome::Error { code, message }),
+            },
+            Err(parse) => (
+                Value::Null,
+                Outcome::Error {
+                    code: PARSE_ERROR,
+                    message: parse.to_string(),
+                },
+            ),
+        };
+        let response = Response {
+            jsonrpc: "2.0",
+            id,
+            outcome,
+        };
+        let mut response = serde_json::to_string(&response).expect("a response serializes");
+        response.push('\n');
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+impl State {
+    fn handle(&self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "status" => {}
+            "pause" => self.throttle.pause(),
+            "resume" => self.throttle.resume(),
+            "cancel" => {
+                // Same as Ctrl+C; a paused transfer has to run to notice.
+                self.interrupted.store(true, Ordering::SeqCst);
+                self.throttle.resume();
+            }
+            "set-rate-limit" => {
+                let Ok(RateLimit { rate }) = RateLimit::deserialize(params) else {
+                    return Err((INVALID_PARAMS, "expected {\"rate\": <bytes/s>}".into()));
+                };
+                self.throttle.set_rate(Some(rate).filter(|rate| *rate > 0));
+            }
+            _ => return Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        }
+        let status = Status::of(
+            self.progress.lock().unwrap().as_ref(),
+            &self.throttle,
+            &self.memory,
+        );
+        Ok(serde_json::to_value(Versioned::new(status)).expect("status serializes"))
+    }
+}
+
+/// `dlm ctl`: sends one request to a download's control socket and returns
+/// its result.
+pub async fn call(socket: &Path, method: &str, params: Value) -> anyhow::Result<Value> {
+    let stream = connect(socket)
+        .await
+        .with_context(|| format!("Cannot connect to control socket '{}'", socket.display()))?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let call = Call {
+        jsonrpc: "2.0",
+        id: 1,
+        method,
+        params,
+    };
+    let mut request = serde_json::to_string(&call)?;
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+
+    let Some(line) = BufReader::new(reader).lines().next_line().await? else {
+        bail!("The download closed the control socket without answering");
+    };
+    let mut response: Value = serde_json::from_str(&line)?;
+    if let Some(error) = response.get("error") {
+        bail!("{}", error["message"].as_str().unwrap_or("request failed"));
+    }
+    Ok(response["result"].take())
+}
+
+#[cfg(unix)]
+async fn connect(socket: &Path) -> std::io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(socket).await
+}
+
+#[cfg(windows)]
+async fn connect(
+    socket: &Path,
+) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    tokio::net::windows::named_pipe::ClientOptions::new().open(socket)
+}
@@ -0,0 +1,75 @@
+use crate::download::async_range::{fetch_range, get_content_length};
+use crate::download::auth_token::AuthToken;
+use crate::download::fs_ops;
+use crate::download::partial;
+use crate::download::retry_budget::RetryBudget;
+use anyhow::{Context, bail};
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+/// Takes over `partial`, a file another tool (a browser's `.crdownload`, a
+/// `.part`) left half downloaded, as the start of `destination`, so the
+/// download carries on from its end. Returns how many bytes it brings.
+///
+/// A file bigger than the remote one can't be its start and is refused.
+/// With `verify`, its last `verify` bytes are fetched and compared first,
+/// which catches a partial of some other file or an older version. It's
+/// renamed to the `.part` of `destination`, or copied when that crosses
+/// filesystems. A 5xx is retried while `budget` allows.
+pub async fn adopt_partial(
+    client: &reqwest::Client,
+    url: &Url,
+    partial: &Path,
+    destination: &Path,
+    verify: Option<u64>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<u64> {
+    let size = tokio::fs::metadata(partial)
+        .await
+        .with_context(|| format!("Cannot adopt '{}'", partial.display()))?
+        .len();
+    if destination.exists() {
+        bail!(
+            "Cannot adopt '{}': '{}' already exists",
+            partial.display(),
+            destination.display()
+        );
+    }
+    let remote = get_content_length(client, url, budget, auth).await?;
+    if size > remote {
+        bail!(
+            "Cannot adopt '{}': it has {size} bytes, more than the {remote} of the remote file",
+            partial.display()
+        );
+    }
+    if let Some(verify) = verify.filter(|_| size > 0) {
+        let start = size - verify.min(size);
+        let expected = fetch_range(client, url, start, size - 1, None, budget, auth)
+            .await?
+            .bytes()
+            .await?;
+        let mut local = vec![0; (size - start) as usize];
+        let mut file = tokio::fs::File::open(partial).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(&mut local).await?;
+        if local != expected {
+            bail!(
+                "Cannot adopt '{}': bytes {start}-{} differ from the remote file",
+                partial.display(),
+                size - 1
+            );
+        }
+    }
+    // The download carries on from its `.part`, like any other.
+    let into = partial::path(destination);
+    if fs_ops::rename_async(partial, &into).await.is_err() {
+        fs_ops::copy_async(partial, &into)
+            .await
+            .with_context(|| format!("Cannot copy '{}' into place", partial.display()))?;
+        fs_ops::remove_file_async(partial).await?;
+    }
+    Ok(size)
+}
@@ -1,104 +1,566 @@
 use anyhow::bail;
-use std::path::PathBuf;
+use reqwest::{StatusCode, header};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use tokio::time::Instant;
+use tracing::Instrument;
 use url::Url;
 
-use crate::download::progress::DownloadProgress;
-use crate::download::utils;
+use crate::download::content_type;
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::error::DownloadError;
+use crate::download::expected_size::{self, SizeCheck};
+use crate::download::file_watch::FileWatch;
+use crate::download::filesystem;
+use crate::download::fs_ops;
+use crate::download::http::{self, StatusClass, unsatisfiable};
+use crate::download::mirrors;
+use crate::download::network_wait;
+use crate::download::options::TransferOptions;
+use crate::download::partial;
+use crate::download::progress::TransferProgress;
+use crate::download::progress_handle::PreflightStep;
+use crate::download::rate_limit;
+use crate::download::retry;
+use crate::download::speed::{self, FirstByte};
+use crate::download::stall::{MAX_STALL_RESTARTS, StallMonitor};
+use crate::download::target_wait;
+use crate::download::torrent;
+use crate::download::validator::{self, IfChanged, Validator};
 
 pub async fn download_file_async(
+    client: &reqwest::Client,
     url: Url,
-    target_dir: &PathBuf,
-    resume: bool,
-    overwrite: bool,
-    progress: DownloadProgress,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    progress.set_retry_budget(&options.retry_budget);
+    let reporter = progress.clone();
+    let result = reporter
+        .preflight(download_from_mirrors(
+            client, url, target_dir, progress, options,
+        ))
+        .await;
+    reporter.finish_with(&result);
+    result
+}
+
+/// [`download`] from `url`, then from each `--mirror` in turn while it
+/// fails in a way another server might get past, carrying on from the bytes
+/// the last left.
+pub(super) async fn download_from_mirrors(
+    client: &reqwest::Client,
+    url: Url,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    if options.mirrors.is_empty() {
+        return download(client, url, target_dir, progress, options).await;
+    }
+    let options = mirrors::named_after(&url, target_dir, options);
+    let destination = options.destination(&url, target_dir);
+    let mut result = download(client, url, target_dir, progress.clone(), &options).await;
+    for mirror in &options.mirrors {
+        let error = match result {
+            Err(error) if mirrors::falls_back(&error) => error,
+            result => return result,
+        };
+        progress.println(&format!(
+            "{error:#}, carrying on from {}",
+            http::redact_url(mirror)
+        ));
+        // Another server's validators say nothing about this one's file.
+        validator::remove(&destination);
+        let retry =
+            match partial::path(&destination).is_file() && options.store_compressed.is_none() {
+                true => TransferOptions {
+                    resume: true,
+                    overwrite: false,
+                    continue_at: None,
+                    ..options.clone()
+                },
+                false => options.clone(),
+            };
+        result = download(client, mirror.clone(), target_dir, progress.clone(), &retry).await;
+    }
+    result
+}
+
+/// [`download_file_async`] without the preflight tracking, for worker mode
+/// to fall back on.
+pub(super) async fn download(
+    client: &reqwest::Client,
+    url: Url,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
 ) -> anyhow::Result<PathBuf> {
     use futures::StreamExt;
+    use std::io::SeekFrom;
     use tokio::fs::OpenOptions;
-    use tokio::io::AsyncWriteExt;
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
     use tokio::time::{Duration, interval};
 
     let start_time = Instant::now();
 
-    let fname = utils::build_download_path(&url, &target_dir);
+    if options.resume_from.is_some() {
+        bail!("--resume-from only works for worker-mode downloads");
+    }
+    options.check_compressed_resume()?;
+    let mut fname = options.destination(&url, target_dir);
+    let resuming = (options.resume && !options.overwrite) || options.continue_at.is_some();
+    // Where the bytes go until they're all there.
+    let mut target = partial::target(&fname, resuming);
     let mut resume_from = 0;
+    let continue_from = options.continue_offset(&target)?;
 
-    let mut dest = if fname.exists() && fname.is_file() {
-        if overwrite {
-            OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(&fname)
-                .await?
-        } else if resume {
-            resume_from = tokio::fs::metadata(&fname).await?.len() as usize;
-            OpenOptions::new().append(true).open(&fname).await?
-        } else {
-            bail!("File exists");
-        }
-    } else {
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&fname)
-            .await?
-    };
-    let mut downloaded = resume_from;
+    if let Some(offset) = continue_from {
+        resume_from = offset as usize;
+    } else if options.resume && !options.overwrite && target.is_file() {
+        resume_from = tokio::fs::metadata(&target).await?.len() as usize;
+    } else if fname.is_file() && !options.overwrite {
+        return Err(DownloadError::FileExists { path: fname }.into());
+    }
+    expected_size::check(&fname, options, SizeCheck::Partial, resume_from as u64)?;
 
-    let response = if resume_from > 0 {
-        let resp = reqwest::Client::new()
-            .get(url)
-            .header("Range", format!("bytes={}-", resume_from))
-            .send()
-            .await?;
-        match resp.status().as_u16() {
-            206 => resp,
-            416 => bail!("File already complete"),
-            200 => {
-                eprintln!("Server doesn't support resume. Try --overwrite");
-                bail!("Cannot resume.");
+    progress
+        .reporter()
+        .set_preflight(PreflightStep::AwaitingResponse);
+    let mut first_byte = FirstByte::new();
+    if !options.pacing.before_request(&progress.interrupted).await
+        || !rate_limit::before_request(&url, &progress).await
+    {
+        return Err(DownloadError::Interrupted.into());
+    }
+    // What the file was when its download started, to resume only that.
+    let mut started = match resume_from {
+        0 => None,
+        _ => Validator::load(&fname),
+    };
+    let mut response = network_wait::retry(&url, options, &progress, || async {
+        match resume_from {
+            0 => Ok(http::check_status(
+                http::send_retrying(
+                    options.request(client, &url),
+                    None,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )
+                .await?,
+            )
+            .await?),
+            _ => request_from(client, &url, resume_from, options, started.as_ref()).await,
+        }
+    })
+    .await?;
+    // The server may name the file itself, which only its answer tells.
+    if resume_from == 0 && continue_from.is_none() {
+        let named = options.served_destination(&url, target_dir, response.headers());
+        if named != fname {
+            target = partial::target(&named, resuming);
+            if options.resume && !options.overwrite && target.is_file() {
+                resume_from = tokio::fs::metadata(&target).await?.len() as usize;
+                expected_size::check(&named, options, SizeCheck::Partial, resume_from as u64)?;
+                started = Validator::load(&named);
+                response =
+                    request_from(client, &url, resume_from, options, started.as_ref()).await?;
+            } else if named.is_file() && !options.overwrite {
+                return Err(DownloadError::FileExists { path: named }.into());
             }
-            _ => bail!("Unexpected status: {}", resp.status()),
         }
-    } else {
-        reqwest::get(url).await?.error_for_status()?
+        fname = named;
+    }
+    // `--restart-on-unresumable` or `--if-changed restart`: the whole file
+    // is coming, again.
+    if resume_from > 0 && response.status() == StatusCode::OK {
+        progress.add_wasted(resume_from as u64);
+        resume_from = 0;
+    }
+    progress.set_prefix(resume_from as u64);
+    if StatusClass::of(response.status()) == StatusClass::Empty {
+        tracing::info!("Server answered {}, the file is empty", response.status());
+        progress.reporter().set_no_content();
+    } else if resume_from == 0 && response.content_length() == Some(0) {
+        tracing::info!("Server sent a Content-Length of 0, the file is empty");
+        progress.reporter().set_no_content();
+    }
+    // Nothing is written to the file until the server has agreed to send
+    // what's wanted and the response passed its checks.
+    torrent::check_content_type(&fname, response.headers(), options.save_torrent)?;
+    content_type::check(
+        &fname,
+        response.headers(),
+        options.strict_content_type,
+        progress.reporter(),
+    )?;
+    if resume_from == 0 {
+        http::check_encoding(response.headers(), &fname, options.no_compression)?;
+    }
+    match response.content_length() {
+        Some(length) => {
+            let size = resume_from as u64 + length;
+            expected_size::check(&fname, options, SizeCheck::Announced, size)?;
+            filesystem::check(&fname, size, options)?
+        }
+        None if StatusClass::of(response.status()) != StatusClass::Empty => {
+            diagnostics::warn_or_fail(
+                WarningId::NoContentLength,
+                format!(
+                    "The server didn't say how big '{}' is, so a download cut short would look complete",
+                    fname.display()
+                ),
+            )?
+        }
+        None => {}
+    }
+    if resume_from == 0 && options.can_resume() {
+        let validator = Validator::of(response.headers(), response.content_length());
+        validator.save(&fname)?;
+        started = Some(validator);
+    }
+    let discard = partial::Discard::new(&target, &fname, options.no_partial);
+    let mut dest = match continue_from {
+        Some(offset) if resume_from > 0 => {
+            let mut open = OpenOptions::new();
+            open.create(true)
+                .write(true)
+                .truncate(false)
+                .append(cfg!(unix));
+            let mut dest =
+                target_wait::retry_async("open", &target, || fs_ops::open_async(&open, &target))
+                    .await?;
+            fs_ops::set_len_async(&dest, &target, offset).await?;
+            dest.seek(SeekFrom::Start(offset)).await?;
+            dest
+        }
+        _ if resume_from > 0 => {
+            let mut open = OpenOptions::new();
+            open.append(true);
+            target_wait::retry_async("open", &target, || fs_ops::open_async(&open, &target)).await?
+        }
+        _ => {
+            let mut open = OpenOptions::new();
+            // A file opened to append can't be truncated as it's opened.
+            open.create(true)
+                .write(true)
+                .truncate(false)
+                .append(cfg!(unix));
+            let dest =
+                target_wait::retry_async("create", &target, || fs_ops::open_async(&open, &target))
+                    .await?;
+            fs_ops::set_len_async(&dest, &target, 0).await?;
+            dest
+        }
     };
+    let metadata = dest.metadata().await?;
+    let mut watch = FileWatch::new(&target, &metadata, metadata.len());
+    let mut compressor = options
+        .store_compressed
+        .map(|compression| compression.compressor())
+        .transpose()?;
+    let mut downloaded = resume_from;
     let content_length = response.content_length();
-    progress
-        .total_bytes
-        .store(content_length.unwrap_or(0), Ordering::Relaxed);
+    // Of the whole file, to name what's missing if the retries run out.
+    let mut size = content_length.map(|length| resume_from as u64 + length);
+    // The prefix counts towards what's downloaded, so towards the total.
+    progress.set_total(size.unwrap_or(0));
+    progress.set_content_type(
+        response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    );
+    progress.set_tls(options.tls.negotiated());
 
     let mut stream = response.bytes_stream();
+    let mut stall = StallMonitor::new(options.stall);
+    let mut restarts = 0;
+    // Retries of lost connections and timeouts, for --retries.
+    let mut retries = 0;
     let mut interrupt_interval = interval(Duration::from_millis(500));
+    // Since the last piece was handed to the disk.
+    let mut waiting = Instant::now();
     loop {
-        tokio::select! {
+        // Why the rest is re-requested, and whether the connection was
+        // lost, which takes one of the retries and looks the host up again.
+        let (mut reason, mut lost) = tokio::select! {
             chunk_option = stream.next() => {
+                progress.time_split.add_network(waiting.elapsed());
                 match chunk_option {
-                    Some(chunk_result) => {
-                    let chunk = chunk_result?;
-                    dest.write_all(&chunk).await?;
-                    downloaded += chunk.len();
-                    progress.bytes_downloaded.store(downloaded, Ordering::Relaxed);
-
+                    Some(Ok(chunk)) => {
+                        if let Some(ttfb) = first_byte.arrived() {
+                            tracing::debug!("First byte after {}ms", ttfb.as_millis());
+                            progress.set_first_byte(ttfb);
+                        }
+                        options.throttle.take(chunk.len()).await;
+                        // A cancel ends the wait early; don't start another.
+                        if progress.interrupted.load(Ordering::SeqCst) {
+                            return interrupted(&mut dest).await;
+                        }
+                        let writing = Instant::now();
+                        let compressed;
+                        let written: &[u8] = match &mut compressor {
+                            Some(compressor) => {
+                                compressed = compressor.compress(&chunk)?;
+                                &compressed
+                            }
+                            None => &chunk,
+                        };
+                        dest.write_all(written).await?;
+                        watch.wrote(written.len());
+                        if watch.due() {
+                            dest.flush().await?;
+                            watch.check()?;
+                        }
+                        progress.time_split.add_disk(writing.elapsed());
+                        downloaded += chunk.len();
+                        stall.record(chunk.len());
+                        progress.set_downloaded(downloaded);
+                        waiting = Instant::now();
+                        continue;
+                    }
+                    Some(Err(error)) if options.can_resume() && retry::is_transient(&error) => {
+                        (format!("Connection lost ({error})"), true)
+                    }
+                    Some(Err(error)) => return Err(error.into()),
+                    None => break,
                 }
-                None => break,
-            }
             }
             _ = interrupt_interval.tick() => {
                 if progress.interrupted.load(Ordering::SeqCst) {
-                    bail!("Download interrupted.");
+                    return interrupted(&mut dest).await;
                 }
+                let Some(reason) = stall.check() else {
+                    continue;
+                };
+                restarts += 1;
+                if restarts > MAX_STALL_RESTARTS {
+                    bail!("{reason}, giving up after {MAX_STALL_RESTARTS} restarts");
+                }
+                if !options.can_resume() {
+                    bail!("{reason}, and a {} download can't be resumed", options.method);
+                }
+                (reason.to_string(), false)
             }
             else => break,
+        };
+        dest.flush().await?;
+        let response = loop {
+            let attempt = match lost {
+                true => {
+                    retries += 1;
+                    if retries > options.retry.retries {
+                        return Err(DownloadError::RetriesExhausted {
+                            chunk: None,
+                            start: downloaded as u64,
+                            end: size.and_then(|size| size.checked_sub(1)),
+                            retries: options.retry.retries,
+                            error: reason,
+                        }
+                        .into());
+                    }
+                    retries as usize
+                }
+                false => restarts,
+            };
+            options.retry_budget.spend(None, attempt, &reason)?;
+            // A lost connection waits out the backoff; a stall has waited
+            // long enough.
+            let wait = match lost {
+                true => options.retry.wait(retries),
+                false => Duration::ZERO,
+            };
+            match lost {
+                true => eprintln!(
+                    "{reason}, re-requesting from byte {downloaded} in {:.1}s ({retries} of {})",
+                    wait.as_secs_f64(),
+                    options.retry.retries
+                ),
+                false => eprintln!("{reason}, re-requesting from byte {downloaded}"),
+            }
+            // The server may have been drained from under its DNS name:
+            // ask wherever the name points now.
+            let stale = lost.then(|| options.dns.forget(&url));
+            tokio::time::sleep(wait).await;
+            if progress.interrupted.load(Ordering::SeqCst)
+                || !options.pacing.before_request(&progress.interrupted).await
+                || !rate_limit::before_request(&url, &progress).await
+            {
+                return interrupted(&mut dest).await;
+            }
+            let span = tracing::trace_span!("retry", attempt, %reason, resume_at = downloaded);
+            let response = request_from(client, &url, downloaded, options, started.as_ref())
+                .instrument(span)
+                .await;
+            if let Some(stale) = stale {
+                options.dns.log_change(&url, &stale);
+            }
+            match response {
+                Err(error) if retry::is_transient(error.as_ref()) => {
+                    reason = format!("Request failed ({error:#})");
+                    lost = true;
+                }
+                response => break response?,
+            }
+        };
+        if response.status() == StatusCode::OK {
+            if let Some(length) = response.content_length() {
+                expected_size::check(&fname, options, SizeCheck::Announced, length)?;
+            }
+            let validator = Validator::of(response.headers(), response.content_length());
+            validator.save(&fname)?;
+            started = Some(validator);
+            progress.add_wasted(downloaded as u64);
+            fs_ops::set_len_async(&dest, &target, 0).await?;
+            dest.seek(SeekFrom::Start(0)).await?;
+            watch.reset(0);
+            if let Some(compression) = options.store_compressed {
+                compressor = Some(compression.compressor()?);
+            }
+            resume_from = 0;
+            downloaded = 0;
+            progress.set_prefix(0);
+            progress.set_downloaded(0);
+            progress.set_total(response.content_length().unwrap_or(0));
+            size = response.content_length();
         }
+        stream = response.bytes_stream();
+        stall.reset();
     }
-    let speed = (downloaded - resume_from) as u64 / start_time.elapsed().as_secs().max(1);
+    // tokio hands writes to a background task, make sure they've all landed
+    // before anyone reads the file back.
+    let writing = Instant::now();
+    let stored = match compressor {
+        Some(compressor) => {
+            let (tail, stored) = compressor.finish()?;
+            dest.write_all(&tail).await?;
+            watch.wrote(tail.len());
+            Some(stored)
+        }
+        None => None,
+    };
+    dest.flush().await?;
+    watch.check()?;
+    progress.time_split.add_disk(writing.elapsed());
+    expected_size::check_done(&target, options, downloaded as u64)?;
+    drop(dest);
+    partial::finish_async(&target, &fname).await?;
+    discard.keep();
+    // The wait for the first byte isn't transfer time.
+    let speed = speed::transfer_rate(
+        (downloaded - resume_from) as u64,
+        start_time.elapsed(),
+        first_byte.ttfb(),
+    );
     println!(
-        "Downloaded: {}, speed: {}/s. Total Time: {}.",
-        indicatif::HumanBytes(downloaded as u64),
-        indicatif::HumanBytes(speed),
+        "Downloaded: {}, speed: {}{}. Total Time: {}.",
+        speed::Size(downloaded as u64),
+        speed::Rate(speed),
+        first_byte
+            .ttfb()
+            .map(|ttfb| format!(", first byte after {}ms", ttfb.as_millis()))
+            .unwrap_or_default(),
         indicatif::HumanDuration(start_time.elapsed())
     );
+    println!("Time split: {}", progress.time_split);
+    if let Some(stored) = stored {
+        println!("Compressed: {stored}");
+        progress.reporter().set_sha256(stored.sha256);
+    }
+    validator::remove(&fname);
     Ok(fname)
 }
+
+/// Stops at a Ctrl+C with what arrived on disk, so the partial file is as
+/// long as `--resume` will take it to be.
+async fn interrupted<T>(dest: &mut tokio::fs::File) -> anyhow::Result<T> {
+    use tokio::io::AsyncWriteExt;
+    dest.flush().await?;
+    dest.sync_all().await?;
+    Err(DownloadError::Interrupted.into())
+}
+
+/// Requests the remainder of `url` starting at byte `offset`, only if it's
+/// still the file `started` describes.
+/// With `--restart-on-unresumable`, a server that can't resume there sends
+/// the whole file instead, with a 200; with `--if-changed restart`, so does
+/// one whose file changed.
+async fn request_from(
+    client: &reqwest::Client,
+    url: &Url,
+    offset: usize,
+    options: &TransferOptions,
+    started: Option<&Validator>,
+) -> anyhow::Result<reqwest::Response> {
+    let mut headers = http::range_headers(&format!("bytes={offset}-"));
+    if let Some(validator) = started.and_then(Validator::if_range) {
+        headers.insert(header::IF_RANGE, validator);
+    }
+    let resp = http::send_retrying(
+        client.get(url.clone()).headers(headers),
+        None,
+        &options.retry_budget,
+        options.auth_token.as_deref(),
+    )
+    .await?;
+    if resp.status().is_success() {
+        http::check_identity(resp.headers())?;
+    }
+    if let Some(change) = started.and_then(|started| started.changed(resp.status(), resp.headers()))
+    {
+        if options.if_changed == IfChanged::Abort {
+            return Err(DownloadError::RemoteChanged { change }.into());
+        }
+        http::announce_restart(
+            offset,
+            &format!("the remote file changed since the download started, {change}"),
+        );
+        return match resp.status() {
+            StatusCode::OK => Ok(resp),
+            _ => {
+                http::check_status(
+                    http::send_retrying(
+                        client.get(url.clone()),
+                        None,
+                        &options.retry_budget,
+                        options.auth_token.as_deref(),
+                    )
+                    .await?,
+                )
+                .await
+            }
+        };
+    }
+    let restart = options.restart_on_unresumable;
+    match resp.status().as_u16() {
+        206 => Ok(resp),
+        416 if restart => match http::unsatisfied_total(resp.headers()) {
+            Some(total) if total != offset as u64 => {
+                http::announce_restart(offset, &format!("the remote file is {total} bytes"));
+                http::check_status(
+                    http::send_retrying(
+                        client.get(url.clone()),
+                        None,
+                        &options.retry_budget,
+                        options.auth_token.as_deref(),
+                    )
+                    .await?,
+                )
+                .await
+            }
+            _ => bail!(unsatisfiable(resp.headers(), offset)),
+        },
+        416 => bail!(unsatisfiable(resp.headers(), offset)),
+        200 if restart => {
+            http::announce_restart(offset, "the server ignored the range request");
+            Ok(resp)
+        }
+        200 => {
+            eprintln!("Server doesn't support resume. Try --overwrite");
+            bail!("Cannot resume.");
+        }
+        _ => Err(http::unexpected_status(resp).await.into()),
+    }
+}
@@ -1,171 +1,1980 @@
-use crate::download::progress::{ChunkProgressBar, ChunkState};
-use crate::download::utils;
+use crate::download::async_download;
+use crate::download::auth_token::AuthToken;
+use crate::download::checksum::HASH_BUFFER;
+use crate::download::chunk_log::{ChunkEvent, Milestones};
+use crate::download::chunk_url::ChunkUrl;
+use crate::download::chunks::{self, ResumeOrder, Segment, SplitReason, Workers};
+use crate::download::compress::Compression;
+use crate::download::connections;
+use crate::download::content_type;
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::dns::Pin;
+use crate::download::error::{self, DownloadError};
+use crate::download::etag;
+use crate::download::expected_size::{self, SizeCheck};
+use crate::download::fairness;
+use crate::download::filesystem;
+use crate::download::fs_ops;
+use crate::download::http::{self, StatusClass};
+use crate::download::hybrid;
+use crate::download::in_place::{self, InPlace};
+use crate::download::inodes;
+use crate::download::memory::MemoryLimit;
+use crate::download::mirrors::{self, Source, Sources};
+use crate::download::network_wait;
+use crate::download::one_connection;
+use crate::download::options::TransferOptions;
+use crate::download::pacing;
+use crate::download::part_check::{self, CheckedBy, PartCheck};
+use crate::download::partial;
+use crate::download::parts::{self, PartLayout, ResumeFrom, Resumption};
+use crate::download::pieces::{PieceHashes, PieceTally, PieceVerifier};
+use crate::download::progress::{ChunkState, TransferProgress};
+use crate::download::progress_handle::{MergeProgress, PreflightStep};
+use crate::download::rate_limit;
+use crate::download::remote::{RemoteInfo, probe_remote};
+use crate::download::retry;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::segment_tuning::SegmentTuner;
+use crate::download::speed::{self, FirstByte, Rate, Size, TimeSplit, TtfbSpread};
+use crate::download::stall::{FloorMonitor, MAX_REASSIGNMENTS, MAX_STALL_RESTARTS, StallMonitor};
+use crate::download::target_wait;
+use crate::download::torrent;
+use crate::download::transcript;
+use crate::download::writer::{ChunkWriter, DiskWriter};
 use anyhow::bail;
 use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::io::SeekFrom;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::{Duration, interval};
-use url::Url;
+use tracing::Instrument;
+use url::{Host, Url};
 
-pub async fn get_content_length(url: &Url) -> anyhow::Result<u64> {
-    let response = reqwest::Client::new().get(url.as_str()).send().await?;
+/// How many times a piece that fails its hash check is fetched again before
+/// the chunk gives up.
+const MAX_PIECE_RETRIES: usize = 3;
 
-    response.content_length().ok_or_else(|| {
-        anyhow::anyhow!("Content length not available")
-    })
+/// How many follow-up requests in a row may end without a byte before a
+/// chunk that came up short gives up.
+const MAX_SHORT_RETRIES: usize = 3;
+
+pub async fn get_content_length(
+    client: &reqwest::Client,
+    url: &Url,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<u64> {
+    content_length(&probe_remote(client, url, budget, auth).await?)
+}
+
+fn content_length(remote: &RemoteInfo) -> anyhow::Result<u64> {
+    remote
+        .size
+        .ok_or_else(|| anyhow::anyhow!("Content length not available"))
 }
 
 pub async fn download_with_workers(
+    client: &reqwest::Client,
     url: Url,
     target_dir: &Path,
-    workers: u8,
-    progress: ChunkProgressBar,
-    no_cleanup: bool,
+    workers: impl Into<Workers>,
+    progress: TransferProgress,
+    options: &TransferOptions,
 ) -> anyhow::Result<PathBuf> {
-    let content_length = get_content_length(&url).await?;
-
-    let chunk_size = content_length / workers as u64;
-    let mut chunks_array: Vec<(usize, usize)> = vec![];
-
-    for i in 0..workers {
-        progress.set_chunk_state(i as usize, ChunkState::Pending);
-        let start = i as u64 * chunk_size;
-        let end = if i == workers - 1 {
-            content_length - 1 // last chunk goes to end
-        } else {
-            (i + 1) as u64 * chunk_size - 1
+    let workers = workers.into();
+    progress.set_retry_budget(&options.retry_budget);
+    let reporter = progress.clone();
+    let result = reporter
+        .preflight(download(
+            client, url, target_dir, workers, progress, options,
+        ))
+        .await;
+    reporter.finish_with(&result);
+    result
+}
+
+async fn download(
+    client: &reqwest::Client,
+    url: Url,
+    target_dir: &Path,
+    workers: Workers,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    if options.continue_at.is_some() {
+        bail!("--continue-at only works for single-stream downloads");
+    }
+    options.check_compressed_resume()?;
+    if options.hybrid_streaming {
+        hybrid::check(workers.most(), options)?;
+    }
+    // Named after the URL given, whichever mirror answers first.
+    let named =
+        (!options.mirrors.is_empty()).then(|| mirrors::named_after(&url, target_dir, options));
+    let options = named.as_ref().unwrap_or(options);
+    let (remote, sources) = network_wait::retry(&url, options, &progress, || async {
+        match options.mirrors.is_empty() {
+            true => {
+                let remote = probe_remote(
+                    client,
+                    &url,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )
+                .await?;
+                let source = Source::of(&url, &remote);
+                Ok((remote, Sources::from([source])))
+            }
+            false => {
+                mirrors::probe(
+                    client,
+                    &url,
+                    &options.mirrors,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )
+                .await
+            }
+        }
+    })
+    .await?;
+    // Named once the server's had its say, so the parts are too.
+    let final_path = options.served_destination(&url, target_dir, &remote.headers);
+    // A file left unfinished in place is as long as a whole one, so it's
+    // only carried on from in place, whatever the flags.
+    let resume_in_place = options.resume && !options.overwrite && in_place::unfinished(&final_path);
+    if resume_in_place {
+        in_place::check(&final_path, options)?;
+    }
+    // What the parts are merged into, or the chunks written into in place,
+    // and renamed once they all are.
+    let merge_target = match options.hybrid_streaming {
+        true => final_path.clone(),
+        false => partial::target(&final_path, options.resume && !options.overwrite),
+    };
+    let carrying_on = options.resume && !options.overwrite && merge_target.is_file();
+    if final_path.is_file() && !options.overwrite && !carrying_on {
+        return Err(DownloadError::FileExists { path: final_path }.into());
+    }
+    let discard = partial::Discard::new(&merge_target, &final_path, options.no_partial);
+    torrent::check_content_type(&final_path, &remote.headers, options.save_torrent)?;
+    content_type::check(
+        &final_path,
+        &remote.headers,
+        options.strict_content_type,
+        progress.reporter(),
+    )?;
+    if let Some(size) = remote.size {
+        expected_size::check(&final_path, options, SizeCheck::Announced, size)?;
+    }
+    let no_content = StatusClass::of(remote.status) == StatusClass::Empty;
+    if no_content || remote.size == Some(0) {
+        match no_content {
+            true => tracing::info!("Server answered {}, the file is empty", remote.status),
+            false => tracing::info!("The file is empty, there's nothing to split"),
+        }
+        // A longer file is no start of it, as ever.
+        resume_prefix(&merge_target, 0, options)?;
+        progress.reporter().set_no_content();
+        let mut file = target_wait::retry_async("create", &merge_target, || {
+            fs_ops::create_async(&merge_target)
+        })
+        .await?;
+        // Even nothing takes a few bytes compressed.
+        if let Some(compression) = options.store_compressed {
+            let (empty, _) = compression.compressor()?.finish()?;
+            file.write_all(&empty).await?;
+            file.flush().await?;
+        }
+        drop(file);
+        expected_size::check_done(&merge_target, options, 0)?;
+        partial::finish_async(&merge_target, &final_path).await?;
+        discard.keep();
+        return Ok(final_path);
+    }
+    // Rather than have every worker find out on its own that the server
+    // sends the whole file whatever range is asked for, or that there's no
+    // size to split, download it in one stream.
+    if !remote.ranges || remote.size.is_none() || remote.encoding.is_some() {
+        let why = match (&remote.encoding, remote.ranges) {
+            (Some(encoding), _) => format!("sends the file with Content-Encoding: {encoding}"),
+            (None, false) => "doesn't accept ranges".to_string(),
+            (None, true) => "doesn't say how big the file is".to_string(),
         };
-        chunks_array.push((start as usize, end as usize));
+        if options.pieces.is_some() {
+            bail!("The server {why}, so chunks can't be checked against --piece-hashes");
+        }
+        if resume_in_place {
+            bail!(
+                "The server {why}, so '{}' can't be carried on in place; pass --overwrite to start over",
+                final_path.display()
+            );
+        }
+        let instead = match workers {
+            Workers::Auto => String::new(),
+            Workers::Count(count) => format!(" instead of {count} workers"),
+        };
+        eprintln!(
+            "The server {why}, downloading '{}' in one stream{instead}",
+            final_path.display()
+        );
+        discard.keep();
+        return one_stream(client, &sources, target_dir, progress, options).await;
+    }
+    let content_length = content_length(&remote)?;
+    let split = chunks::split(workers, content_length, options.min_split_size.unwrap_or(0));
+    if split.why != SplitReason::Asked {
+        eprintln!("{split}");
+    }
+    let workers = split.workers;
+    // Parts an earlier run left are carried on from as parts.
+    let earlier_parts = earlier_plan(&url, content_length, &final_path, options);
+    let write_in_place = resume_in_place
+        || (earlier_parts.is_none()
+            && match in_place::unavailable(options) {
+                Some(flag) => {
+                    tracing::info!("Writing part files, as {flag} needs them");
+                    false
+                }
+                None => true,
+            });
+    // What only worker mode does is left to it, even in a single part.
+    let needs_parts = resume_in_place
+        || options.hybrid_streaming
+        || options.pieces.is_some()
+        || options.resume_from.is_some()
+        || earlier_parts.is_some();
+    if workers == 1 && !needs_parts {
+        discard.keep();
+        return one_stream(client, &sources, target_dir, progress, options).await;
+    }
+    progress.reporter().set_etag(remote.etag.as_deref());
+    progress.reporter().set_tls(options.tls.negotiated());
+    if options.pin_ip {
+        pin_node(options, &remote);
+    }
+    if sources.len() > 1 {
+        progress.println(&format!(
+            "Spreading the chunks across {} sources",
+            sources.len()
+        ));
     }
+    if let Some(pieces) = &options.pieces {
+        pieces.check_length(content_length)?;
+    }
+    filesystem::check(&final_path, content_length, options)?;
 
-    let mut tasks = Vec::new();
-    for (chunk_id, (start, end)) in chunks_array.into_iter().enumerate() {
-        let url_clone = url.clone();
-        let target_dir = target_dir.to_path_buf();
+    let earlier = match &options.resume_from {
+        Some(earlier) => Some(earlier.clone()),
+        None => earlier_parts,
+    };
+    // --segment-size auto sizes the segments of a new layout that only
+    // workers fill, from the start of the file on.
+    let tuner = options
+        .segment_tuning
+        .filter(|_| earlier.is_none() && !resume_in_place && !options.hybrid_streaming)
+        .map(|bounds| SegmentTuner::new(bounds, options.chunk_alignment()));
+    // The rest of the file past the first segments, which segments of the
+    // size tuned so far are taken off the start of.
+    let mut tail = None;
+    // What each chunk left to fetch had of its range, and has left of it.
+    let (prefix, layout, fetch, segments) = match &earlier {
+        Some(earlier) => {
+            let mut resumption = earlier.carry_on(&url, content_length, &final_path)?;
+            check_kept_parts(
+                client,
+                &sources[0].url,
+                earlier,
+                &mut resumption,
+                &final_path,
+                &progress,
+                options,
+            )
+            .await?;
+            progress.set_prefix(resumption.kept);
+            progress.println(&format!(
+                "Carrying on from the parts beside '{}', downloading the {} they're missing",
+                earlier.file.display(),
+                Size(content_length - resumption.kept)
+            ));
+            let segments = resumption.segments();
+            (
+                resumption.prefix,
+                resumption.layout,
+                resumption.fetch,
+                segments,
+            )
+        }
+        None => {
+            let prefix = match resume_in_place {
+                true => 0,
+                false => resume_prefix(&merge_target, content_length, options)?,
+            };
+            if prefix > 0 {
+                progress.set_prefix(prefix);
+                progress.println(&format!(
+                    "Resuming at byte {prefix}, splitting the {} left between the workers",
+                    Size(content_length - prefix)
+                ));
+            }
+            let mut ranges = chunks::part_ranges(
+                content_length,
+                prefix,
+                options.segments(workers, content_length - prefix),
+                options.chunk_alignment(),
+            );
+            if let Some(tuner) = &tuner {
+                // A segment each to start with, if that leaves a rest.
+                let head = u64::from(workers) * tuner.size();
+                if content_length - prefix > head + tuner.size() {
+                    ranges = chunks::part_ranges(
+                        prefix + head,
+                        prefix,
+                        workers.into(),
+                        options.chunk_alignment(),
+                    );
+                    ranges.push((prefix + head, content_length - 1));
+                    tail = Some(ranges.len() - 1);
+                }
+            }
+            let layout = PartLayout::new(&url, content_length, ranges).beside(&final_path)?;
+            let fetch: Vec<usize> = (0..layout.ranges.len())
+                .filter(|index| Some(*index) != tail)
+                .collect();
+            let segments = fetch
+                .iter()
+                .map(|index| {
+                    let (start, end) = layout.ranges[*index];
+                    (0, end + 1 - start)
+                })
+                .collect();
+            (prefix, layout, fetch, segments)
+        }
+    };
+    let (in_place, mut layout, fetch, segments) = match write_in_place {
+        true => {
+            inodes::check(&final_path, 2)?;
+            let (in_place, rest, kept) = InPlace::start(
+                &layout,
+                &final_path,
+                &merge_target,
+                content_length,
+                prefix,
+                options,
+            )
+            .await?;
+            if kept > prefix {
+                progress.set_prefix(kept);
+                progress.println(&format!(
+                    "Carrying on with '{}' in place, downloading the {} it's missing",
+                    merge_target.display(),
+                    Size(content_length - kept)
+                ));
+            }
+            let fetch: Vec<usize> = in_place
+                .unfinished()
+                .into_iter()
+                .filter(|index| Some(*index) != tail)
+                .collect();
+            let segments = fetch
+                .iter()
+                .map(|index| {
+                    let (start, end) = rest.ranges[*index];
+                    (in_place.written(*index), end + 1 - start)
+                })
+                .collect();
+            (Some(in_place), rest, fetch, segments)
+        }
+        false => {
+            progress.reporter().set_parts(layout.clone());
+            // Every new part, the plan, and the file they're merged into.
+            inodes::check(&final_path, fetch.len() as u64 + 2)?;
+            layout.save_plan(&final_path)?;
+            (None, layout, fetch, segments)
+        }
+    };
+    progress.set_chunk_count(fetch.len());
+    for (chunk_id, index) in fetch.iter().enumerate() {
+        progress.set_chunk_state(chunk_id, ChunkState::Pending);
+        progress.set_chunk_range(chunk_id, layout.ranges[*index]);
+    }
+
+    let one_at_a_time = options.one_connection || one_connection::known(&url);
+    if one_at_a_time && fetch.len() > 1 {
+        eprintln!(
+            "Downloading the {} chunks one after another over one connection (--one-connection)",
+            fetch.len()
+        );
+    }
+    // With --one-connection there's no second connection to warm up.
+    if remote.ranges && !options.no_warm_up && !one_at_a_time {
+        // Each source takes its turn of the chunks.
+        let count = fetch.len().min(workers.into()).div_ceil(sources.len());
+        for source in sources.iter() {
+            connections::warm_up(client, &source.url, count, options.auth_token.as_deref()).await;
+        }
+    }
+    let disk_writer = match options.serial_writes {
+        true => Some(DiskWriter::spawn(workers as usize)?),
+        false => None,
+    };
+    progress
+        .reporter()
+        .set_preflight(PreflightStep::AwaitingResponse);
+    let balancer = options.fair_workers.then(|| {
+        tokio::spawn(fairness::balance(
+            progress.clone(),
+            options.throttle.clone(),
+        ))
+    });
+    // Without a --chunk-order the segments are taken from the start of the
+    // file on; with one per worker, they all start at once. A --resume-order
+    // takes over once any has something to carry on from.
+    let resumed = segments.iter().any(|(done, _)| *done > 0);
+    let order = match options.chunk_order {
+        _ if resumed && options.resume_order != ResumeOrder::Sequential => {
+            chunks::resume_schedule(&segments, options.resume_order)
+        }
+        Some(order) => chunks::schedule(fetch.len(), order),
+        None => (0..fetch.len()).collect(),
+    };
+    let frontier = options.hybrid_streaming.then(|| {
+        progress.println(&format!(
+            "Streaming '{}' in order from its start, appending the other workers' chunks as they come",
+            final_path.display()
+        ));
+        let parts = layout.paths[1..]
+            .iter()
+            .cloned()
+            .zip(layout.ranges[1..].iter().map(|(start, end)| end - start + 1))
+            .collect();
+        tokio::spawn(hybrid::advance_frontier(
+            final_path.clone(),
+            parts,
+            progress.clone(),
+            options.no_cleanup,
+        ))
+    });
+    let saving = in_place.as_ref().map(InPlace::keep_saving);
+    // The frontier moves the parts into the file as they come.
+    let hash_parts = in_place.is_none() && !options.hybrid_streaming;
+    let workers_free = Arc::new(Semaphore::new(match one_at_a_time {
+        true => 1,
+        false => workers.into(),
+    }));
+    // Chunks streamed or checked by pieces keep their ranges.
+    let min_steal = options
+        .min_steal
+        .filter(|_| !options.hybrid_streaming && options.pieces.is_none());
+    // Set once a chunk's connection was refused while another had one open.
+    let refused = Arc::new(AtomicBool::new(false));
+    let tuner = tuner.map(|tuner| Arc::new(Mutex::new(tuner)));
+    let run_chunk = |worker: OwnedSemaphorePermit,
+                     position: usize,
+                     chunk_id: usize,
+                     index: usize,
+                     segment: Arc<Segment>,
+                     part: PathBuf| {
+        let (start, end) = (segment.start as usize, segment.end() as usize);
+        let client = client.clone();
+        let target = ChunkUrl::new(sources.clone(), chunk_id);
         let progress_clone = progress.clone();
+        let options = options.clone();
+        let disk_writer = disk_writer.clone();
+        let written = in_place.as_ref().map(|in_place| in_place.progress(index));
+        let refused = refused.clone();
+        let tuner = tuner.clone();
+        transcript::scheduled(chunk_id, start as u64, end as u64);
+        if let Some(log) = &options.chunk_log {
+            log.record(
+                chunk_id,
+                ChunkEvent::Scheduled {
+                    start: start as u64,
+                    end: end as u64,
+                },
+            );
+        }
 
-        let task = tokio::spawn(async move {
-            download_range_async(url_clone, &target_dir, start, end, chunk_id, progress_clone).await
+        let span = tracing::trace_span!(
+            "chunk",
+            chunk = chunk_id,
+            start,
+            end,
+            ttfb_ms = tracing::field::Empty
+        );
+        // --ramp-up spreads the first segment of each worker.
+        let ramp_up = options
+            .pacing
+            .ramp_up_delay(position, fetch.len().min(workers.into()));
+        tokio::spawn(
+            async move {
+                if !pacing::wait(ramp_up, &progress_clone.interrupted).await {
+                    return Err(DownloadError::Interrupted.into());
+                }
+                options
+                    .memory
+                    .wait_for_room(&format!("chunk {chunk_id}"))
+                    .await;
+                let buffer = options.memory.reserve(
+                    &format!("chunk {chunk_id}'s write buffer"),
+                    options.write_buffer,
+                );
+                let dest = match written {
+                    Some(written) => {
+                        ChunkWriter::in_place(part, start as u64, buffer, disk_writer, written)
+                            .await
+                    }
+                    None => ChunkWriter::create(part, buffer, disk_writer).await,
+                };
+                let result = match dest {
+                    Ok(mut dest) => {
+                        let downloading = Instant::now();
+                        let result = download_range_async(
+                            &client,
+                            target,
+                            &mut dest,
+                            &segment,
+                            chunk_id,
+                            progress_clone,
+                            &options,
+                        )
+                        .await;
+                        if let (Some(tuner), Ok((_, _, Some(ttfb)))) = (&tuner, &result) {
+                            let bytes = segment.end() + 1 - segment.start;
+                            tuner
+                                .lock()
+                                .unwrap()
+                                .record(bytes, downloading.elapsed(), *ttfb);
+                        }
+                        // What arrived is kept on disk, for --resume to
+                        // carry on after.
+                        match result {
+                            Err(error) if error::is_interrupted(&error) => {
+                                dest.sync().await?;
+                                Err(error)
+                            }
+                            result => result,
+                        }
+                    }
+                    Err(error) => Err(error.into()),
+                };
+                match &result {
+                    Err(error) if one_connection::is_refused(error) => {
+                        refused.store(true, Ordering::SeqCst)
+                    }
+                    Err(error) => {
+                        if let Some(log) = &options.chunk_log {
+                            let error = format!("{error:#}");
+                            log.record(chunk_id, ChunkEvent::Failed { error });
+                        }
+                    }
+                    Ok(_) => {}
+                }
+                drop(worker);
+                // For --verify-parts to check the part by once resumed.
+                match result {
+                    Ok(done) if hash_parts => {
+                        let sha256 = part_check::sha256(&done.0, &options.memory).await?;
+                        Ok((index, done, Some(sha256)))
+                    }
+                    result => result.map(|done| (index, done, None)),
+                }
+            }
+            .instrument(span),
+        )
+    };
+    // Every chunk started, with its part's index, and each part's chunk.
+    let mut started: Vec<(usize, usize, Arc<Segment>)> = Vec::new();
+    let mut chunk_of_part: Vec<Option<usize>> = vec![None; layout.ranges.len()];
+    for (chunk_id, index) in fetch.iter().enumerate() {
+        chunk_of_part[*index] = Some(chunk_id);
+    }
+    let mut queue = order.into_iter().enumerate();
+    let mut splits = 0;
+    let mut tasks = Vec::new();
+    loop {
+        // The next segment waits for a worker to finish the last.
+        let worker = Arc::clone(&workers_free).acquire_owned().await?;
+        // Once a connection was refused, the rest wait for the chunks
+        // downloading to be done.
+        if refused.load(Ordering::SeqCst) {
+            break;
+        }
+        let (position, chunk_id, index) = match queue.next() {
+            Some((position, chunk_id)) => (position, chunk_id, fetch[chunk_id]),
+            // Then the next segment off the rest, of the size tuned so far.
+            None if tail.is_some() => {
+                if progress.interrupted.load(Ordering::SeqCst)
+                    || progress.chunk_states().contains(&ChunkState::Failed)
+                {
+                    break;
+                }
+                let index = tail.take().unwrap();
+                let size = tuner
+                    .as_ref()
+                    .map_or(u64::MAX, |tuner| tuner.lock().unwrap().size());
+                let (start, end) = layout.ranges[index];
+                // A rest not much longer than a segment goes whole.
+                if end + 1 - start > size + size / 2 {
+                    tail = Some(split_off(
+                        &mut layout,
+                        in_place.as_ref(),
+                        index,
+                        start + size,
+                        &final_path,
+                    )?);
+                    chunk_of_part.push(None);
+                }
+                let chunk_id = progress.add_chunk();
+                progress.set_chunk_range(chunk_id, layout.ranges[index]);
+                chunk_of_part[index] = Some(chunk_id);
+                (fetch.len(), chunk_id, index)
+            }
+            // None left to start: the worker takes half of what's left of
+            // the segment with the most left, while that's worth it.
+            None => {
+                let Some(min_steal) = min_steal.filter(|_| {
+                    splits < chunks::MAX_SPLITS
+                        && !progress.interrupted.load(Ordering::SeqCst)
+                        && !progress.chunk_states().contains(&ChunkState::Failed)
+                }) else {
+                    break;
+                };
+                let Some((victim, victim_index, victim_start, (at, end))) = started
+                    .iter()
+                    .max_by_key(|(_, _, segment)| segment.left())
+                    .and_then(|(chunk, index, segment)| {
+                        Some((*chunk, *index, segment.start, segment.split(min_steal)?))
+                    })
+                else {
+                    break;
+                };
+                splits += 1;
+                let index = split_off(
+                    &mut layout,
+                    in_place.as_ref(),
+                    victim_index,
+                    at,
+                    &final_path,
+                )?;
+                progress.set_chunk_range(victim, (victim_start, at - 1));
+                let chunk_id = progress.add_chunk();
+                progress.set_chunk_range(chunk_id, (at, end));
+                chunk_of_part.push(Some(chunk_id));
+                progress.println(&format!(
+                    "Chunk {victim}: handing bytes {at}-{end} to an idle worker as chunk {chunk_id}"
+                ));
+                // Past the chunks ramped up, so it starts right away.
+                (fetch.len(), chunk_id, index)
+            }
+        };
+        let segment = Arc::new(Segment::new(layout.ranges[index]));
+        started.push((chunk_id, index, segment.clone()));
+        // --hybrid-streaming: the first chunk goes straight into the file.
+        let part = match options.hybrid_streaming && index == 0 {
+            true => final_path.clone(),
+            false => layout.paths[index].clone(),
+        };
+        tasks.push(run_chunk(worker, position, chunk_id, index, segment, part));
+    }
+    let deferred: Vec<usize> = queue.map(|(_, chunk_id)| chunk_id).collect();
+
+    let mut results = futures::future::join_all(tasks).await;
+    // The chunks refused a connection, and those not started since, go one
+    // after another now that none has one open; unless another failed some
+    // other way, which fails the download anyway.
+    let is_refused = |result: &Result<anyhow::Result<_>, _>| matches!(result, Ok(Err(error)) if one_connection::is_refused(error));
+    let again: Vec<usize> = (0..results.len())
+        .filter(|position| is_refused(&results[*position]))
+        .collect();
+    let failed = results
+        .iter()
+        .any(|result| !is_refused(result) && !matches!(result, Ok(Ok(_))));
+    if !again.is_empty() && !failed {
+        // What's left of the file past the segments goes whole.
+        let rest_of_file = tail.take().map(|index| {
+            let chunk_id = progress.add_chunk();
+            progress.set_chunk_range(chunk_id, layout.ranges[index]);
+            chunk_of_part[index] = Some(chunk_id);
+            (None, chunk_id, index)
         });
-        tasks.push(task)
+        one_connection::remember(&url);
+        eprintln!(
+            "{}",
+            one_connection::notice(
+                &url,
+                again.len() + deferred.len() + usize::from(rest_of_file.is_some())
+            )
+        );
+        let rest: Vec<_> = again
+            .iter()
+            .map(|position| (Some(*position), started[*position].0, started[*position].1))
+            .chain(
+                deferred
+                    .iter()
+                    .map(|chunk_id| (None, *chunk_id, fetch[*chunk_id])),
+            )
+            .chain(rest_of_file)
+            .collect();
+        for (position, chunk_id, index) in rest {
+            let result = match progress.interrupted.load(Ordering::SeqCst) {
+                true => Ok(Err(DownloadError::Interrupted.into())),
+                false => {
+                    let worker = Arc::clone(&workers_free).acquire_owned().await?;
+                    let segment = match position {
+                        Some(position) => started[position].2.clone(),
+                        None => {
+                            let segment = Arc::new(Segment::new(layout.ranges[index]));
+                            started.push((chunk_id, index, segment.clone()));
+                            segment
+                        }
+                    };
+                    let part = match options.hybrid_streaming && index == 0 {
+                        true => final_path.clone(),
+                        false => layout.paths[index].clone(),
+                    };
+                    run_chunk(worker, fetch.len(), chunk_id, index, segment, part).await
+                }
+            };
+            match position {
+                Some(position) => results[position] = result,
+                None => results.push(result),
+            }
+        }
+    }
+    results.retain(|result| !is_refused(result));
+    if let Some(tuner) = &tuner {
+        let tuning = tuner.lock().unwrap().tuning();
+        tracing::info!("Segment size: {tuning}");
+        progress.reporter().set_segment_tuning(tuning);
+    }
+    if hash_parts {
+        for result in &results {
+            if let Ok(Ok((index, _, Some(sha256)))) = result {
+                layout.record_sha256(*index, sha256.clone());
+            }
+        }
+        // Split-off parts go back among the others, in the order they're
+        // merged.
+        if splits > 0 {
+            let order = layout.sort();
+            chunk_of_part = order.iter().map(|index| chunk_of_part[*index]).collect();
+        }
+        progress.reporter().set_parts(layout.clone());
+        // Merged parts need no plan; ones a failed run left do.
+        if !results.iter().all(|result| matches!(result, Ok(Ok(_))))
+            && let Err(error) = layout.save_plan(&final_path)
+        {
+            tracing::warn!("{error:#}");
+        }
+    }
+    if let (Some(saving), Some(in_place)) = (saving, &in_place) {
+        saving.abort();
+        in_place.save()?;
+        in_place.check()?;
+    }
+    if let Some(balancer) = balancer {
+        balancer.abort();
+        options.throttle.cap_chunks(&[]);
     }
 
-    let results = futures::future::join_all(tasks).await;
+    if let Some(frontier) = &frontier
+        && !results.iter().all(|result| matches!(result, Ok(Ok(_))))
+    {
+        frontier.abort();
+    }
 
-    // Collect part paths in the same order as chunks_array
-    // (results are in the same order as tasks were spawned)
-    let mut part_paths = Vec::new();
+    if results
+        .iter()
+        .any(|result| matches!(result, Ok(Err(error)) if error::is_interrupted(error)))
+    {
+        return Err(
+            anyhow::Error::from(DownloadError::Interrupted).context(format!(
+                "Interrupted at {} / {}, rerun with --resume to continue",
+                Size(progress.downloaded()),
+                Size(content_length)
+            )),
+        );
+    }
+    let mut tally = PieceTally::default();
+    let mut first_bytes = Vec::new();
     for result in results {
-        let path = result??;
-        part_paths.push(path);
+        let (_, (_, pieces, ttfb), _) = result??;
+        tally += pieces;
+        first_bytes.extend(ttfb);
+    }
+    // The layout has the parts in order, any kept from an earlier run among
+    // the new ones.
+    // What the frontier appended, or the chunks wrote in place, is only
+    // left to hash.
+    let (parts, prefix) = match frontier {
+        Some(frontier) => {
+            frontier.await??;
+            (&[][..], content_length)
+        }
+        None if in_place.is_some() => (&[][..], content_length),
+        None => {
+            check_part_sizes(
+                client,
+                &sources,
+                &mut layout,
+                &chunk_of_part,
+                &final_path,
+                &progress,
+                options,
+                disk_writer,
+            )
+            .await?;
+            (&layout.paths[..], prefix)
+        }
+    };
+    let merging = Instant::now();
+    let sha256 = merge_parts(
+        parts,
+        &merge_target,
+        prefix,
+        &progress,
+        options.no_cleanup,
+        options.store_compressed,
+        &options.memory,
+    )
+    .await?;
+    let merged = merging.elapsed();
+    expected_size::check_done(&merge_target, options, progress.downloaded())?;
+    partial::finish_async(&merge_target, &final_path).await?;
+    discard.keep();
+    if !options.no_cleanup
+        && let Err(error) = parts::remove_stale(&final_path, &layout)
+    {
+        diagnostics::warn(
+            WarningId::CleanupFailed,
+            format!(
+                "Could not remove stale parts of '{}': {error}",
+                final_path.display()
+            ),
+        );
+    }
+    // The file is whole, so even parts kept by --no-cleanup have nothing
+    // left to resume.
+    if let Err(error) = match &in_place {
+        Some(in_place) => in_place.finish(),
+        None => layout.remove_plan(&final_path),
+    } {
+        diagnostics::warn(
+            WarningId::CleanupFailed,
+            format!(
+                "Could not remove the plan of '{}': {error:#}",
+                final_path.display()
+            ),
+        );
+    }
+    if let Some(earlier) = options.resume_from.as_ref().filter(|_| !options.no_cleanup)
+        && let Err(error) = parts::remove_stale(&earlier.file, &layout)
+            .and_then(|_| earlier.layout.remove_plan(&earlier.file))
+    {
+        diagnostics::warn(
+            WarningId::CleanupFailed,
+            format!(
+                "Could not remove stale parts of '{}': {error}",
+                earlier.file.display()
+            ),
+        );
+    }
+    tracing::info!(
+        "Hashed while merging the parts, in {:.2}s",
+        merged.as_secs_f64()
+    );
+    progress.reporter().set_sha256(sha256);
+    if options.pieces.is_some() {
+        progress.println(&format!(
+            "Pieces: {} verified, {} re-downloaded after a hash mismatch",
+            tally.verified, tally.repaired
+        ));
+    }
+    if let Some(spread) = TtfbSpread::of(first_bytes) {
+        progress.println(&format!("Time to first byte: {spread}"));
+        progress.reporter().set_ttfb_spread(spread);
+    }
+    progress.println(&format!(
+        "Time split, over all chunks: {}",
+        progress.time_split
+    ));
+    match in_place {
+        Some(_) => progress.println(&format!(
+            "Wrote {} chunks in place, hashed in {:.2}s",
+            layout.ranges.len(),
+            merged.as_secs_f64()
+        )),
+        None => progress.println(&format!(
+            "Merged {} parts in {:.2}s",
+            layout.paths.len(),
+            merged.as_secs_f64()
+        )),
+    }
+    if let Some(limit) = options.memory.limit() {
+        progress.println(&format!(
+            "Buffers peaked at {} of --max-memory {}",
+            Size(options.memory.peak()),
+            Size(limit)
+        ));
     }
-    // No need to sort - tasks were spawned in order, results maintain that order
-    let final_path = merge_parts(&part_paths, target_dir, &url, no_cleanup).await?;
     Ok(final_path)
 }
 
-async fn merge_parts(
-    part_paths: &[PathBuf],
-    target_dir: &Path,
+/// `--pin-ip`: sends every chunk to the node `remote` came from. Not for a
+/// host given by address, which is one node anyway, or through a proxy,
+/// which picks the node itself.
+fn pin_node(options: &TransferOptions, remote: &RemoteInfo) {
+    let url = &remote.final_url;
+    let (Some(Host::Domain(host)), Some(address)) = (url.host(), remote.remote_addr) else {
+        return;
+    };
+    if options.proxy_for(url).is_some() {
+        return;
+    }
+    let pin = Pin {
+        host: host.to_string(),
+        address,
+    };
+    if options.dns.pinned().as_ref() != Some(&pin) {
+        tracing::info!("Pinning {host} to {} for every chunk", address.ip());
+        options.dns.pin(pin);
+    }
+}
+
+/// Checks the parts `resumption` keeps from the `earlier` run against
+/// `url`, planning any that fail to be downloaded again.
+async fn check_kept_parts(
+    client: &reqwest::Client,
     url: &Url,
-    no_cleanup: bool,
-) -> anyhow::Result<PathBuf> {
-    let final_path = utils::build_download_path(url, target_dir);
+    earlier: &ResumeFrom,
+    resumption: &mut Resumption,
+    final_path: &Path,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<()> {
+    let kept: Vec<_> = (0..resumption.layout.ranges.len())
+        .filter(|index| resumption.is_kept_part(*index, earlier))
+        .collect();
+    for index in kept {
+        let check = part_check::check_kept(client, url, &resumption.layout, index, options).await?;
+        match &check.failed {
+            Some(_) => {
+                progress.println(&format!("{check}, downloading it again"));
+                progress.add_wasted(check.end + 1 - check.start);
+                resumption.refetch(index, final_path)?;
+            }
+            None => tracing::info!("{check}"),
+        }
+        progress.reporter().add_part_check(check);
+    }
+    Ok(())
+}
 
-    let mut final_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&final_path)
+/// Downloads again, into a new part, every part of `layout` that isn't as
+/// long as its range, before they're merged.
+#[allow(clippy::too_many_arguments)]
+async fn check_part_sizes(
+    client: &reqwest::Client,
+    sources: &Sources,
+    layout: &mut PartLayout,
+    chunk_of_part: &[Option<usize>],
+    final_path: &Path,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+    disk_writer: Option<DiskWriter>,
+) -> anyhow::Result<()> {
+    for (index, chunk_id) in chunk_of_part.iter().enumerate() {
+        let Some(reason) = part_check::check_size(layout, index)? else {
+            continue;
+        };
+        let (start, end) = layout.ranges[index];
+        let check = PartCheck {
+            path: layout.paths[index].clone(),
+            start,
+            end,
+            by: CheckedBy::Size,
+            failed: Some(reason),
+        };
+        progress.println(&format!("{check}, downloading it again"));
+        progress.add_wasted(end + 1 - start);
+        progress.reporter().add_part_check(check);
+        layout.move_part(index, final_path)?;
+        // Kept parts have no chunk, and count for nothing in the progress.
+        let chunk_id = chunk_id.unwrap_or(progress.chunk_states().len() + index);
+        let buffer = options.memory.reserve(
+            &format!("chunk {chunk_id}'s write buffer"),
+            options.write_buffer,
+        );
+        let mut dest =
+            ChunkWriter::create(layout.paths[index].clone(), buffer, disk_writer.clone()).await?;
+        download_range_async(
+            client,
+            ChunkUrl::new(sources.clone(), chunk_id),
+            &mut dest,
+            &Segment::new((start, end)),
+            chunk_id,
+            progress.clone(),
+            options,
+        )
         .await?;
+        if let Some(reason) = part_check::check_size(layout, index)? {
+            bail!(
+                "'{}' is still the wrong size after downloading it again: {reason}",
+                layout.paths[index].display()
+            );
+        }
+    }
+    progress.reporter().set_parts(layout.clone());
+    Ok(())
+}
+
+/// With `--resume`, the parts an earlier run of the download left beside
+/// `final_path`, split as its plan says. Not once the earlier run had
+/// started merging them, or the file ahead of them is gone: the file is
+/// carried on from as a partial one instead, and the parts left go.
+/// Downloads from the first of `sources` in one stream, the others as its
+/// mirrors, rather than splitting the download.
+async fn one_stream(
+    client: &reqwest::Client,
+    sources: &Sources,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    progress.set_chunk_count(1);
+    progress.set_chunk_state(0, ChunkState::Downloading { worker_id: 0 });
+    let options = TransferOptions {
+        mirrors: sources[1..]
+            .iter()
+            .map(|source| source.url.clone())
+            .collect(),
+        ..options.clone()
+    };
+    let url = sources[0].url.clone();
+    async_download::download_from_mirrors(client, url, target_dir, progress, &options).await
+}
+
+/// Hands bytes `at` on of range `index` to a new range, which is written
+/// into the file along with the others when they're written in place.
+fn split_off(
+    layout: &mut PartLayout,
+    in_place: Option<&InPlace>,
+    index: usize,
+    at: u64,
+    final_path: &Path,
+) -> anyhow::Result<usize> {
+    let new = layout.split(index, at, final_path)?;
+    if let Some(in_place) = in_place {
+        let split = in_place.split(index, at);
+        debug_assert_eq!(split, new);
+        layout.paths[new] = layout.paths[index].clone();
+    }
+    Ok(new)
+}
 
+fn earlier_plan(
+    url: &Url,
+    content_length: u64,
+    final_path: &Path,
+    options: &TransferOptions,
+) -> Option<ResumeFrom> {
+    if !options.resume || options.overwrite {
+        return None;
+    }
+    let layout = PartLayout::load_plan(url, content_length, final_path)?;
+    let first = layout
+        .ranges
+        .first()
+        .map_or(content_length, |(start, _)| *start);
+    let ahead = partial::target(final_path, true);
+    let local = std::fs::metadata(&ahead).map_or(0, |metadata| metadata.len());
+    if local > first {
+        tracing::info!(
+            "'{}' was being merged, carrying on after its {local} bytes",
+            ahead.display()
+        );
+    }
+    if local != first {
+        return None;
+    }
+    Some(ResumeFrom {
+        file: final_path.to_path_buf(),
+        layout,
+    })
+}
+
+/// Where worker mode carries on from a file already at `path`: with
+/// `--resume`, after what's there, rounded down to whole pieces; otherwise
+/// from the start, replacing it.
+pub(crate) fn resume_prefix(
+    path: &Path,
+    content_length: u64,
+    options: &TransferOptions,
+) -> anyhow::Result<u64> {
+    let local = std::fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file() && options.resume && !options.overwrite)
+        .map_or(0, |metadata| metadata.len());
+    if local > content_length {
+        if options.restart_on_unresumable {
+            return Ok(0);
+        }
+        bail!(
+            "'{}' has {local} bytes, more than the {content_length} of the remote file; pass --overwrite to start over",
+            path.display()
+        );
+    }
+    Ok(local - local % options.chunk_alignment())
+}
+
+/// Appends the parts to the first `prefix` bytes of `final_path`, hashing
+/// the bytes on their way through so the file doesn't need reading again.
+/// Returns the SHA-256 of the merged content, which with `store_compressed`
+/// is compressed on its way into the file.
+///
+/// An interrupt stops it between reads, leaving the file a correct prefix
+/// of the download that `--resume` carries on from.
+async fn merge_parts(
+    part_paths: &[PathBuf],
+    final_path: &Path,
+    prefix: u64,
+    progress: &TransferProgress,
+    no_cleanup: bool,
+    store_compressed: Option<Compression>,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<[u8; 32]> {
+    let mut merging = MergeProgress {
+        part: 0,
+        parts: part_paths.len(),
+        copied: 0,
+        total: 0,
+    };
     for part_path in part_paths {
+        merging.total += tokio::fs::metadata(part_path).await?.len();
+    }
+    let mut open = OpenOptions::new();
+    open.create(true).read(true).write(true).truncate(false);
+    let mut final_file =
+        target_wait::retry_async("open", final_path, || fs_ops::open_async(&open, final_path))
+            .await?;
+    fs_ops::set_len_async(&final_file, final_path, prefix).await?;
+
+    let mut hasher = Sha256::new();
+    let reserved = memory.reserve_buffer("merging", HASH_BUFFER).await;
+    let mut buffer = vec![0; reserved.size()];
+    // Reading the prefix leaves the file positioned after it.
+    loop {
+        let bytes_read = final_file.read(&mut buffer).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    let mut compressor = store_compressed
+        .map(|compression| compression.compressor())
+        .transpose()?;
+    let reporter = progress.reporter();
+    for (index, part_path) in part_paths.iter().enumerate() {
+        merging.part = index + 1;
+        reporter.set_merging(Some(merging));
         let mut part_file = tokio::fs::File::open(part_path).await?;
-        tokio::io::copy(&mut part_file, &mut final_file).await?;
+        loop {
+            if progress.interrupted.load(Ordering::SeqCst) {
+                final_file.flush().await?;
+                bail!("Interrupted while merging the parts");
+            }
+            let bytes_read = part_file.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            match &mut compressor {
+                Some(compressor) => {
+                    final_file
+                        .write_all(&compressor.compress(&buffer[..bytes_read])?)
+                        .await?
+                }
+                None => {
+                    hasher.update(&buffer[..bytes_read]);
+                    final_file.write_all(&buffer[..bytes_read]).await?;
+                }
+            }
+            merging.copied += bytes_read as u64;
+            reporter.set_merging(Some(merging));
+        }
 
         if !no_cleanup {
-            tokio::fs::remove_file(part_path).await?;
+            fs_ops::remove_file_async(part_path).await?;
         }
     }
+    let sha256 = match compressor {
+        Some(compressor) => {
+            let (tail, stored) = compressor.finish()?;
+            final_file.write_all(&tail).await?;
+            progress.println(&format!("Compressed: {stored}"));
+            stored.sha256
+        }
+        None => hasher.finalize().into(),
+    };
+    final_file.flush().await?;
+    reporter.set_merging(None);
 
-    Ok(final_path)
+    Ok(sha256)
 }
 
 async fn download_range_async(
-    url: Url,
-    target_dir: &Path,
-    start: usize,
-    end: usize,
+    client: &reqwest::Client,
+    mut target: ChunkUrl,
+    dest: &mut ChunkWriter,
+    segment: &Segment,
     chunk_id: usize,
-    progress: ChunkProgressBar,
-) -> anyhow::Result<PathBuf> {
-    let _start_time = Instant::now();
-    let fname = utils::build_download_path(&url, &target_dir);
-    let base_name = fname
-        .file_name()
-        .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
-        .to_string_lossy();
-    let fname = target_dir.join(format!("{base_name}.part.{start}-{end}"));
-
-    let mut dest = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&fname)
-        .await?;
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<(PathBuf, PieceTally, Option<Duration>)> {
+    let start_time = Instant::now();
+    // Where the segment ends may come sooner, once an idle worker has
+    // split it: the ranges asked for from then on end there.
+    let (start, end) = (segment.start as usize, segment.end() as usize);
+    let last = || segment.end() as usize;
+    let log = options.chunk_log.as_ref();
+    let pieces = options.pieces.as_deref();
+    let mut verifier = pieces.map(|pieces| PieceVerifier::new(pieces, (start as u64, end as u64)));
 
     let mut downloaded = 0;
 
     // Mark this chunk as downloading
-    progress.set_chunk_state(chunk_id, ChunkState::Downloading { worker_id: chunk_id });
-
-    let response = reqwest::Client::new()
-        .get(url)
-        .header("Range", format!("bytes={}-{}", start, end))
-        .send()
-        .await?;
+    progress.set_chunk_state(
+        chunk_id,
+        ChunkState::Downloading {
+            worker_id: chunk_id,
+        },
+    );
 
-    let response = match response.status().as_u16() {
-        206 => response,
-        200 => {
-            let message = "Server doesn't support the `range` header, cannot download chunks.";
-            eprintln!("{}", message);
-            progress.set_chunk_state(chunk_id, ChunkState::Failed);
-            bail!(message);
-        }
-        _ => {
-            progress.set_chunk_state(chunk_id, ChunkState::Failed);
-            bail!("Unexpected status: {}", response.status())
-        }
-    };
+    if let Some(log) = log {
+        let mirror = target.original().to_string();
+        log.record(chunk_id, ChunkEvent::Started { mirror });
+    }
+    let mut first_byte = FirstByte::new();
+    // Retries of lost connections and timeouts, for --retries.
+    let mut retries = 0;
+    let response = connect_first(
+        client,
+        &mut target,
+        (start, end),
+        chunk_id,
+        &progress,
+        options,
+        &mut retries,
+    )
+    .await?;
     let _content_length = response.content_length();
 
     let mut stream = response.bytes_stream();
+    let mut stall = StallMonitor::new(options.stall);
+    let mut floor = options.chunk_floor.map(|floor| FloorMonitor::new(floor, 0));
+    // Re-assignments for --chunk-min-speed, and where the first was made.
+    let mut reassignments = 0;
+    let mut reassigned_at = None;
+    let mut milestones = Milestones::new((end - start + 1) as u64);
+    let mut restarts = 0;
+    // Follow-ups for responses that ended early, and where the last began.
+    let mut follow_ups = 0;
+    let mut fruitless = 0;
+    let mut requested_at = 0;
     let mut interrupt_interval = interval(Duration::from_millis(500));
+    let split = TimeSplit::default();
+    // Since the last piece was handed to the disk.
+    let mut waiting = Instant::now();
     loop {
         tokio::select! {
             chunk_option = stream.next() => {
+                split.add_network(waiting.elapsed());
                 match chunk_option {
-                    Some(chunk_result) => {
-                        let chunk = chunk_result?;
-                        dest.write_all(&chunk).await?;
+                    Some(Ok(chunk)) => {
+                        if let Some(ttfb) = first_byte.arrived() {
+                            let ttfb_ms = ttfb.as_millis() as u64;
+                            tracing::debug!("Chunk {chunk_id}: first byte after {ttfb_ms}ms");
+                            tracing::Span::current().record("ttfb_ms", ttfb_ms);
+                            progress.set_first_byte(ttfb);
+                            if let Some(log) = log {
+                                log.record(chunk_id, ChunkEvent::FirstByte { ttfb_ms });
+                            }
+                        }
+                        options.throttle.take_chunk(chunk_id, chunk.len()).await;
+                        let chunk = chunk.slice(..segment.claim(chunk.len()));
+                        let writing = Instant::now();
+                        dest.write(&chunk).await?;
+                        split.add_disk(writing.elapsed());
+                        if let Some(verifier) = &mut verifier {
+                            verifier.feed(&chunk);
+                        }
                         downloaded += chunk.len();
+                        stall.record(chunk.len());
                         progress.update_chunk_bytes(chunk_id, downloaded);
+                        if let (Some(log), Some(percent)) =
+                            (log, milestones.reached(downloaded as u64))
+                        {
+                            let downloaded = downloaded as u64;
+                            log.record(chunk_id, ChunkEvent::Bytes { downloaded, percent });
+                        }
+                        waiting = Instant::now();
+                        // A cancel ends the wait early; don't ask for more
+                        // once what arrived is written.
+                        if progress.interrupted.load(Ordering::SeqCst) {
+                            progress.set_chunk_state(chunk_id, ChunkState::Failed);
+                            return Err(DownloadError::Interrupted.into());
+                        }
+                        // Split: the rest of the response is another worker's.
+                        if last() < end && segment.left() == 0 {
+                            break;
+                        }
                     },
+                    Some(Err(error)) if retry::is_transient(&error) => {
+                        let resume_at = start + downloaded;
+                        let reason = format!("connection lost ({error})");
+                        dest.flush().await?;
+                        let range = (resume_at, last());
+                        let url = target.original().clone();
+                        let stale =
+                            match back_off(&reason, &url, range, chunk_id, &progress, options, &mut retries).await {
+                                Ok(stale) => stale,
+                                Err(error) => {
+                                    fall_back(&mut target, error, chunk_id, &progress, &mut retries)?;
+                                    Vec::new()
+                                }
+                            };
+                        let retry = tracing::trace_span!("retry", attempt = retries, %reason, resume_at);
+                        stream = connect(client, &mut target, range, chunk_id, &progress, options, &mut retries)
+                            .instrument(retry)
+                            .await?
+                            .bytes_stream();
+                        options.dns.log_change(&url, &stale);
+                        stall.reset();
+                    }
+                    Some(Err(error)) => {
+                        progress.set_chunk_state(chunk_id, ChunkState::Failed);
+                        return Err(error.into());
+                    }
+                    // Some servers cap how much of a range they send, and a
+                    // connection closed cleanly ends the stream early too:
+                    // ask for the rest.
+                    None if downloaded < last() - start + 1 => {
+                        fruitless = if downloaded == requested_at { fruitless + 1 } else { 0 };
+                        let short = last() - start + 1 - downloaded;
+                        if fruitless > MAX_SHORT_RETRIES {
+                            progress.set_chunk_state(chunk_id, ChunkState::Failed);
+                            bail!(
+                                "Chunk {chunk_id}: the server keeps ending the response {short} bytes short, giving up after {MAX_SHORT_RETRIES} retries"
+                            );
+                        }
+                        follow_ups += 1;
+                        let resume_at = start + downloaded;
+                        let reason = format!("response ended {short} bytes short");
+                        // Following up on a server that caps its ranges is
+                        // progress rather than a retry.
+                        match fruitless {
+                            0 => diagnostics::record_retry(Some(chunk_id), follow_ups, &reason),
+                            _ => options.retry_budget.spend(Some(chunk_id), follow_ups, &reason)?,
+                        }
+                        tracing::debug!("Chunk {chunk_id}: {reason}, re-requesting from byte {resume_at}");
+                        if let Some(log) = log {
+                            let error = reason.clone();
+                            log.record(chunk_id, ChunkEvent::Retry { attempt: follow_ups, error });
+                        }
+                        let retry = tracing::trace_span!("retry", attempt = follow_ups, %reason, resume_at);
+                        let range = (resume_at, last());
+                        stream = connect(client, &mut target, range, chunk_id, &progress, options, &mut retries)
+                            .instrument(retry)
+                            .await?
+                            .bytes_stream();
+                        requested_at = downloaded;
+                        stall.reset();
+                    }
                     None => break,
                 }
             }
             _ = interrupt_interval.tick() => {
                 if progress.interrupted.load(Ordering::SeqCst) {
                     progress.set_chunk_state(chunk_id, ChunkState::Failed);
-                    bail!("Download interrupted.");
+                    return Err(DownloadError::Interrupted.into());
+                }
+                if let Some(reason) = stall.check() {
+                    restarts += 1;
+                    if restarts > MAX_STALL_RESTARTS {
+                        progress.set_chunk_state(chunk_id, ChunkState::Failed);
+                        bail!(
+                            "Chunk {chunk_id}: {reason}, giving up after {MAX_STALL_RESTARTS} restarts"
+                        );
+                    }
+                    let resume_at = start + downloaded;
+                    options
+                        .retry_budget
+                        .spend(Some(chunk_id), restarts, &reason.to_string())?;
+                    if let Some(log) = log {
+                        let error = reason.to_string();
+                        log.record(chunk_id, ChunkEvent::Retry { attempt: restarts, error });
+                    }
+                    progress.println(&format!(
+                        "Chunk {chunk_id}: {reason}, re-requesting from byte {resume_at}"
+                    ));
+                    dest.flush().await?;
+                    let retry = tracing::trace_span!("retry", attempt = restarts, %reason, resume_at);
+                    let range = (resume_at, last());
+                    stream = connect(client, &mut target, range, chunk_id, &progress, options, &mut retries)
+                        .instrument(retry)
+                        .await?
+                        .bytes_stream();
+                    stall.reset();
+                    if let Some(floor) = &mut floor {
+                        floor.reset(downloaded as u64);
+                    }
+                }
+                // Only worth it once other chunks are done: until then the
+                // bandwidth is taken anyway.
+                if let Some(floor) = &mut floor
+                    && let Some(speed) = floor.check(downloaded as u64)
+                    && reassignments < MAX_REASSIGNMENTS
+                    && progress.chunk_states().contains(&ChunkState::Completed)
+                {
+                    reassignments += 1;
+                    reassigned_at.get_or_insert(downloaded);
+                    let resume_at = start + downloaded;
+                    let reason = format!("only {} while other chunks are done", Rate(speed));
+                    options
+                        .retry_budget
+                        .spend(Some(chunk_id), reassignments, &reason)?;
+                    if let Some(log) = log {
+                        let error = reason.clone();
+                        log.record(chunk_id, ChunkEvent::Retry { attempt: reassignments, error });
+                    }
+                    progress.println(&format!(
+                        "Chunk {chunk_id}: {reason}, re-assigning bytes {resume_at}-{} to a new connection ({reassignments} of {MAX_REASSIGNMENTS})",
+                        last()
+                    ));
+                    progress.reporter().add_reassignment();
+                    dest.flush().await?;
+                    // A node the download is pinned to stays pinned.
+                    let url = target.original().clone();
+                    let stale = match options.dns.pinned() {
+                        Some(_) => Vec::new(),
+                        None => options.dns.forget(&url),
+                    };
+                    let retry = tracing::trace_span!("retry", attempt = reassignments, %reason, resume_at);
+                    let range = (resume_at, last());
+                    stream = connect(client, &mut target, range, chunk_id, &progress, options, &mut retries)
+                        .instrument(retry)
+                        .await?
+                        .bytes_stream();
+                    options.dns.log_change(&url, &stale);
+                    stall.reset();
+                    floor.reset(downloaded as u64);
+                }
+            }
+        }
+    }
+    if let Some(at) = reassigned_at {
+        progress
+            .reporter()
+            .add_reassigned_bytes((downloaded - at) as u64);
+    }
+
+    let writing = Instant::now();
+    dest.flush().await?;
+    split.add_disk(writing.elapsed());
+    progress.time_split.add(&split);
+    let offset = dest.offset();
+    let path = dest.path().to_path_buf();
+
+    let mut tally = PieceTally::default();
+    if let (Some(pieces), Some(verifier)) = (pieces, verifier) {
+        tally.verified = verifier.verified;
+        if !verifier.failed.is_empty() {
+            let mut part = fs_ops::open_async(OpenOptions::new().write(true), &path).await?;
+            for piece in verifier.failed {
+                let (piece_start, piece_end) = pieces.range(piece, end as u64 + 1);
+                progress.add_wasted(piece_end - piece_start + 1);
+                progress.println(&format!(
+                    "Chunk {chunk_id}: piece {piece} (bytes {piece_start}-{piece_end}) failed its hash check, re-downloading it"
+                ));
+                if let Some(log) = log {
+                    log.record(
+                        chunk_id,
+                        ChunkEvent::PieceMismatch {
+                            piece,
+                            start: piece_start,
+                            end: piece_end,
+                            mirror: target.original().to_string(),
+                        },
+                    );
                 }
+                let data = refetch_piece(
+                    client,
+                    &target,
+                    pieces,
+                    piece,
+                    (piece_start, piece_end),
+                    chunk_id,
+                    &progress,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )
+                .await?;
+                part.seek(SeekFrom::Start(offset + piece_start - start as u64))
+                    .await?;
+                part.write_all(&data).await?;
+                tally.verified += 1;
+                tally.repaired += 1;
             }
+            part.flush().await?;
         }
     }
 
     // Mark this chunk as completed
     progress.set_chunk_state(chunk_id, ChunkState::Completed);
-    Ok(fname)
+    if let Some(log) = log {
+        let elapsed = start_time.elapsed();
+        log.record(
+            chunk_id,
+            ChunkEvent::Completed {
+                duration_ms: elapsed.as_millis() as u64,
+                avg_speed: speed::transfer_rate(downloaded as u64, elapsed, first_byte.ttfb()),
+                network_ms: split.network().as_millis() as u64,
+                disk_ms: split.disk().as_millis() as u64,
+            },
+        );
+    }
+    Ok((path, tally, first_byte.ttfb()))
+}
+
+/// Fetches a piece that failed its hash check into memory until it matches.
+#[allow(clippy::too_many_arguments)]
+async fn refetch_piece(
+    client: &reqwest::Client,
+    target: &ChunkUrl,
+    pieces: &PieceHashes,
+    piece: usize,
+    (start, end): (u64, u64),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<bytes::Bytes> {
+    for attempt in 1..=MAX_PIECE_RETRIES {
+        budget
+            .spend(Some(chunk_id), attempt, "piece hash mismatch")
+            .inspect_err(|_| progress.set_chunk_state(chunk_id, ChunkState::Failed))?;
+        let span = tracing::trace_span!("retry", attempt, reason = "piece hash mismatch", piece);
+        let data = request_range(
+            client,
+            target.original(),
+            target.etag(),
+            (start as usize, end as usize),
+            chunk_id,
+            progress,
+            budget,
+            auth,
+        )
+        .instrument(span)
+        .await
+        .inspect_err(|_| progress.set_chunk_state(chunk_id, ChunkState::Failed))?
+        .bytes()
+        .await?;
+        if pieces.matches(piece, &data) {
+            return Ok(data);
+        }
+        progress.add_wasted(data.len() as u64);
+    }
+    progress.set_chunk_state(chunk_id, ChunkState::Failed);
+    bail!(
+        "Chunk {chunk_id}: piece {piece} (bytes {start}-{end}) still fails its hash check after {MAX_PIECE_RETRIES} re-downloads"
+    )
+}
+
+/// Requests bytes `resume_at..=end` for chunk `chunk_id`, from where its
+/// last request was redirected to if it was. A request that can't connect
+/// or times out is made again after [`back_off`], for as long as
+/// `--retries` allows; `retries` counts the chunk's retries so far. One
+/// refused by the URL redirected to is made again at once, from the URL the
+/// chunk was given. One that fails for good is made again from the next
+/// mirror, with the chunk's `--retries` afresh, while there's one left.
+async fn connect(
+    client: &reqwest::Client,
+    target: &mut ChunkUrl,
+    range: (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+    retries: &mut u32,
+) -> anyhow::Result<reqwest::Response> {
+    connect_as(
+        client, target, range, chunk_id, progress, options, retries, false,
+    )
+    .await
+}
+
+/// [`connect`] for a chunk's first request, which is sent once, 5xx and
+/// all: when a server that allows one connection at a time refuses it
+/// straight away while another chunk has one open, the chunk ends as
+/// [`Refused`](one_connection::Refused), to be downloaded once the others
+/// are done.
+async fn connect_first(
+    client: &reqwest::Client,
+    target: &mut ChunkUrl,
+    range: (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+    retries: &mut u32,
+) -> anyhow::Result<reqwest::Response> {
+    connect_as(
+        client, target, range, chunk_id, progress, options, retries, true,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn connect_as(
+    client: &reqwest::Client,
+    target: &mut ChunkUrl,
+    (resume_at, end): (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+    retries: &mut u32,
+    mut first: bool,
+) -> anyhow::Result<reqwest::Response> {
+    let mut stale: Option<Vec<IpAddr>> = None;
+    loop {
+        let url = target.original().clone();
+        if !options.pacing.before_request(&progress.interrupted).await
+            || !rate_limit::before_request(&url, progress).await
+        {
+            progress.set_chunk_state(chunk_id, ChunkState::Failed);
+            return Err(DownloadError::Interrupted.into());
+        }
+        let requested = target.target().clone();
+        let etag = target.etag().map(str::to_string);
+        let range = (resume_at, end);
+        let once = std::mem::take(&mut first);
+        let response = match once {
+            true => {
+                request_range_once(
+                    client,
+                    &requested,
+                    etag.as_deref(),
+                    range,
+                    chunk_id,
+                    progress,
+                    options.auth_token.as_deref(),
+                )
+                .await
+            }
+            false => {
+                request_range(
+                    client,
+                    &requested,
+                    etag.as_deref(),
+                    range,
+                    chunk_id,
+                    progress,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )
+                .await
+            }
+        };
+        if let Some(stale) = stale.take() {
+            options.dns.log_change(&url, &stale);
+        }
+        match response {
+            Ok(response) => {
+                if target.answered(&requested, response.url()) {
+                    progress.reporter().add_redirect(chunk_id, false);
+                }
+                let worker_id = chunk_id;
+                progress.set_chunk_state(chunk_id, ChunkState::Downloading { worker_id });
+                return Ok(response);
+            }
+            Err(error)
+                if once
+                    && one_connection::is_refusal(&error)
+                    && others_connected(progress, chunk_id) =>
+            {
+                progress.set_chunk_state(chunk_id, ChunkState::Pending);
+                return Err(error.context(one_connection::Refused));
+            }
+            // Sent once, a 5xx is sent again the way any request is.
+            Err(error)
+                if once
+                    && matches!(
+                        error.downcast_ref::<DownloadError>(),
+                        Some(DownloadError::ServerError { .. })
+                    ) => {}
+            Err(error) if target.expired(&error) => {
+                progress.reporter().add_redirect(chunk_id, true);
+                progress.println(&format!(
+                    "Chunk {chunk_id}: the URL it was redirected to was refused ({error:#}), following the redirect from {} again",
+                    http::redact_url(&url)
+                ));
+            }
+            Err(error) if retry::is_transient(error.as_ref()) => {
+                let reason = format!("request failed ({error:#})");
+                match back_off(&reason, &url, range, chunk_id, progress, options, retries).await {
+                    Ok(addresses) => stale = Some(addresses),
+                    Err(error) => fall_back(target, error, chunk_id, progress, retries)?,
+                }
+            }
+            Err(error) => fall_back(target, error, chunk_id, progress, retries)?,
+        }
+    }
+}
+
+/// Whether a chunk other than `chunk_id` is downloading.
+fn others_connected(progress: &TransferProgress, chunk_id: usize) -> bool {
+    progress
+        .chunk_states()
+        .iter()
+        .enumerate()
+        .any(|(id, state)| id != chunk_id && matches!(state, ChunkState::Downloading { .. }))
+}
+
+/// Moves `target` on to the next mirror after `error` ended the chunk's
+/// tries at the one it was at, giving it its `--retries` afresh. Fails with
+/// `error` when another server wouldn't get past it, or there's none left.
+fn fall_back(
+    target: &mut ChunkUrl,
+    error: anyhow::Error,
+    chunk_id: usize,
+    progress: &TransferProgress,
+    retries: &mut u32,
+) -> anyhow::Result<()> {
+    if !mirrors::falls_back(&error) || !target.fall_back() {
+        progress.set_chunk_state(chunk_id, ChunkState::Failed);
+        return Err(error);
+    }
+    *retries = 0;
+    progress.println(&format!(
+        "Chunk {chunk_id}: {error:#}, carrying on from {}",
+        http::redact_url(target.original())
+    ));
+    Ok(())
+}
+
+/// Takes one of the chunk's `--retries` for `reason`, and waits out its
+/// backoff with the chunk shown as retrying. Fails naming the range the
+/// chunk couldn't fetch once they're used up. Returns the addresses of the
+/// host it forgot, so the retry looks it up again: the server may have
+/// been drained from under its DNS name.
+async fn back_off(
+    reason: &str,
+    url: &Url,
+    (resume_at, end): (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    options: &TransferOptions,
+    retries: &mut u32,
+) -> anyhow::Result<Vec<IpAddr>> {
+    let policy = options.retry;
+    *retries += 1;
+    if *retries > policy.retries {
+        progress.set_chunk_state(chunk_id, ChunkState::Failed);
+        return Err(DownloadError::RetriesExhausted {
+            chunk: Some(chunk_id),
+            start: resume_at as u64,
+            end: Some(end as u64),
+            retries: policy.retries,
+            error: reason.to_string(),
+        }
+        .into());
+    }
+    let attempt = *retries;
+    options
+        .retry_budget
+        .spend(Some(chunk_id), attempt as usize, reason)
+        .inspect_err(|_| progress.set_chunk_state(chunk_id, ChunkState::Failed))?;
+    if let Some(log) = &options.chunk_log {
+        let error = reason.to_string();
+        let attempt = attempt as usize;
+        log.record(chunk_id, ChunkEvent::Retry { attempt, error });
+    }
+    let wait = policy.wait(attempt);
+    progress.set_chunk_state(chunk_id, ChunkState::Retrying);
+    progress.println(&format!(
+        "Chunk {chunk_id}: {reason}, re-requesting from byte {resume_at} in {:.1}s ({attempt} of {})",
+        wait.as_secs_f64(),
+        policy.retries
+    ));
+    let stale = options.dns.forget(url);
+    tokio::time::sleep(wait).await;
+    if progress.interrupted.load(Ordering::SeqCst) {
+        progress.set_chunk_state(chunk_id, ChunkState::Failed);
+        return Err(DownloadError::Interrupted.into());
+    }
+    Ok(stale)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn request_range(
+    client: &reqwest::Client,
+    url: &Url,
+    etag: Option<&str>,
+    (start, end): (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<reqwest::Response> {
+    fetch_range(
+        client,
+        url,
+        start as u64,
+        end as u64,
+        Some(chunk_id),
+        budget,
+        auth,
+    )
+    .await
+    .and_then(|response| check_etag(response, etag, chunk_id, progress))
+}
+
+/// [`request_range`] sent once, whatever the server answers.
+async fn request_range_once(
+    client: &reqwest::Client,
+    url: &Url,
+    etag: Option<&str>,
+    (start, end): (usize, usize),
+    chunk_id: usize,
+    progress: &TransferProgress,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<reqwest::Response> {
+    let (start, end) = (start as u64, end as u64);
+    let response = http::send(range_request(client, url, start, end), Some(chunk_id), auth).await?;
+    check_range(response, start, end)
+        .await
+        .and_then(|response| check_etag(response, etag, chunk_id, progress))
+}
+
+/// Fails on a response whose strong ETag isn't `expected`, the one the
+/// probe of its source got, before any of its bytes are written; a weak one
+/// is only warned about.
+fn check_etag(
+    response: reqwest::Response,
+    expected: Option<&str>,
+    chunk_id: usize,
+    progress: &TransferProgress,
+) -> anyhow::Result<reqwest::Response> {
+    let Some(mismatch) = etag::compare(chunk_id, expected, response.headers()) else {
+        return Ok(response);
+    };
+    progress.reporter().add_etag_mismatch(mismatch.clone());
+    if !mismatch.weak {
+        return Err(DownloadError::EtagChanged {
+            chunk: chunk_id,
+            expected: mismatch.expected,
+            actual: mismatch.actual,
+        }
+        .into());
+    }
+    diagnostics::warn_or_fail(
+        WarningId::WeakEtagChanged,
+        format!(
+            "Chunk {chunk_id}: the weak ETag changed from {} to {}, which may be the same file; carrying on",
+            mismatch.expected, mismatch.actual
+        ),
+    )?;
+    Ok(response)
+}
+
+/// Requests bytes `start..=end` of `url`, failing unless the server answers
+/// with just that range.
+pub(crate) async fn fetch_range(
+    client: &reqwest::Client,
+    url: &Url,
+    start: u64,
+    end: u64,
+    chunk_id: Option<usize>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<reqwest::Response> {
+    let response = http::send_retrying(
+        range_request(client, url, start, end),
+        chunk_id,
+        budget,
+        auth,
+    )
+    .await?;
+    check_range(response, start, end).await
+}
+
+fn range_request(
+    client: &reqwest::Client,
+    url: &Url,
+    start: u64,
+    end: u64,
+) -> reqwest::RequestBuilder {
+    client
+        .get(url.clone())
+        .headers(http::range_headers(&format!("bytes={start}-{end}")))
+}
+
+/// Fails unless `response` has just bytes `start..=end`.
+async fn check_range(
+    response: reqwest::Response,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<reqwest::Response> {
+    match response.status().as_u16() {
+        206 => {
+            http::check_identity(response.headers())?;
+            // A redirect that lost the Range header on the way may still
+            // answer with some range; a server may send less than asked.
+            let sent = http::content_range(&response)?;
+            if sent.first != start || sent.last > end {
+                bail!(
+                    "Asked {} for bytes {start}-{end}, but it sent bytes {}-{}",
+                    http::redact_url(response.url()),
+                    sent.first,
+                    sent.last
+                );
+            }
+            Ok(response)
+        }
+        200 => {
+            let message = "Server doesn't support the `range` header, cannot download chunks.";
+            eprintln!("{}", message);
+            bail!(message);
+        }
+        _ => Err(http::unexpected_status(response).await.into()),
+    }
+}
+
+/// The body of bytes `start..=end` of `url`, failing unless all of them
+/// came.
+pub(crate) async fn fetch_range_bytes(
+    client: &reqwest::Client,
+    url: &Url,
+    start: u64,
+    end: u64,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<bytes::Bytes> {
+    let data = fetch_range(client, url, start, end, None, budget, auth)
+        .await?
+        .bytes()
+        .await?;
+    if data.len() as u64 != end - start + 1 {
+        bail!(
+            "Asked for bytes {start}-{end} but received {} bytes",
+            data.len()
+        );
+    }
+    Ok(data)
 }
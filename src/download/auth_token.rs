@@ -0,0 +1,281 @@
+//! `--auth-token-url`: artifact stores that hand out short-lived tokens.
+//! Before the download, `--auth-token-body` is POSTed to the token URL, and
+//! the token found at `--auth-token-jsonpath` in its JSON answer is sent as
+//! a bearer token with every request, like `--bearer-token`. A request
+//! answered 401 gets a new token and is sent again with it, once, so a
+//! token that expires mid-download costs a request rather than the chunk.
+//!
+//! The token only ever goes out in a sensitive header, which the header
+//! dumps and transcripts redact, and neither the body sent for it nor the
+//! answer it came in is logged.
+
+use crate::download::http;
+use anyhow::{Context, bail};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::Mutex;
+use url::Url;
+
+/// Where tokens come from.
+#[derive(Clone, Debug)]
+pub struct TokenSource {
+    pub url: Url,
+    /// Sent as JSON when it parses as JSON, as a form otherwise.
+    pub body: Option<String>,
+    pub path: TokenPath,
+}
+
+/// Where the token is in the token URL's answer: `$.data.token`,
+/// `$.tokens[0]`, or without the `$.` as `data.token`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenPath(Vec<Step>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+impl FromStr for TokenPath {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad token path '{value}', expected one like $.data.token");
+        let path = match value.starts_with('$') {
+            true => value.to_string(),
+            false => format!("$.{value}"),
+        };
+        let mut rest = &path[1..];
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(bad());
+                }
+                steps.push(Step::Key(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after.split_once(']').ok_or_else(bad)?;
+                steps.push(Step::Index(index.parse().map_err(|_| bad())?));
+                rest = after;
+            } else {
+                return Err(bad());
+            }
+        }
+        match steps.is_empty() {
+            true => Err(bad()),
+            false => Ok(TokenPath(steps)),
+        }
+    }
+}
+
+impl TokenPath {
+    /// The string at this path in `json`.
+    pub fn find<'a>(&self, json: &'a Value) -> Option<&'a str> {
+        self.0
+            .iter()
+            .try_fold(json, |value, step| match step {
+                Step::Key(key) => value.get(key),
+                Step::Index(index) => value.get(index),
+            })?
+            .as_str()
+    }
+}
+
+impl std::fmt::Display for TokenPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$")?;
+        for step in &self.0 {
+            match step {
+                Step::Key(key) => write!(f, ".{key}")?,
+                Step::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A token, and how many have been fetched before it, so requests that
+/// were refused the same one get a single new one between them.
+struct Token {
+    value: HeaderValue,
+    generation: u64,
+}
+
+/// The tokens of one client: where they come from and the one in use,
+/// shared by its requests through [`ClientOptions`] and
+/// [`TransferOptions`].
+///
+/// [`ClientOptions`]: crate::download::client::ClientOptions
+/// [`TransferOptions`]: crate::download::options::TransferOptions
+pub struct AuthToken {
+    source: TokenSource,
+    token: Mutex<Option<Token>>,
+    /// Held while a new token is fetched.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl std::fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthToken")
+            .field("source", &self.source)
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
+
+impl AuthToken {
+    /// Tokens from `source`, none fetched yet.
+    pub fn new(source: TokenSource) -> Self {
+        Self {
+            source,
+            token: Mutex::new(None),
+            refreshing: tokio::sync::Mutex::const_new(()),
+        }
+    }
+
+    /// Fetches the first token, so a token URL that refuses fails the
+    /// download before it starts.
+    pub async fn fetch(&self, client: &reqwest::Client) -> anyhow::Result<()> {
+        let _refreshing = self.refreshing.lock().await;
+        self.store(token_request(client, &self.source).await?);
+        Ok(())
+    }
+
+    /// Puts the token in `headers`, unless they have an `Authorization` of
+    /// their own, returning which token it was.
+    pub(crate) fn authorize(&self, headers: &mut HeaderMap) -> Option<u64> {
+        if headers.contains_key(header::AUTHORIZATION) {
+            return None;
+        }
+        let token = self.token.lock().unwrap();
+        let token = token.as_ref()?;
+        headers.insert(header::AUTHORIZATION, token.value.clone());
+        Some(token.generation)
+    }
+
+    /// Fetches a new token after a request with token `used` was answered
+    /// 401, unless another request got one since. Whether there's a token
+    /// to try again with.
+    pub(crate) async fn refresh(&self, client: &reqwest::Client, used: u64) -> bool {
+        let _refreshing = self.refreshing.lock().await;
+        if self.generation() != Some(used) {
+            return true;
+        }
+        tracing::info!("Token refused, fetching a new one");
+        match token_request(client, &self.source).await {
+            Ok(value) => {
+                self.store(value);
+                true
+            }
+            Err(error) => {
+                tracing::warn!("Cannot get a new token: {error:#}");
+                false
+            }
+        }
+    }
+
+    /// [`refresh`](Self::refresh) for the blocking client.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn refresh_blocking(&self, client: &reqwest::blocking::Client, used: u64) -> bool {
+        let _refreshing = self.refreshing.blocking_lock();
+        if self.generation() != Some(used) {
+            return true;
+        }
+        tracing::info!("Token refused, fetching a new one");
+        let source = &self.source;
+        let mut request = client.post(source.url.clone());
+        if let Some((content_type, body)) = body(source) {
+            request = request
+                .header(header::CONTENT_TYPE, content_type)
+                .body(body);
+        }
+        let fetched = request
+            .send()
+            .map_err(anyhow::Error::from)
+            .and_then(|response| {
+                let status = response.status();
+                let body = response.bytes()?;
+                parse(source, status, &body)
+            });
+        match fetched {
+            Ok(value) => {
+                self.store(value);
+                true
+            }
+            Err(error) => {
+                tracing::warn!("Cannot get a new token: {error:#}");
+                false
+            }
+        }
+    }
+
+    fn generation(&self) -> Option<u64> {
+        self.token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|token| token.generation)
+    }
+
+    fn store(&self, value: HeaderValue) {
+        let mut token = self.token.lock().unwrap();
+        let generation = token.as_ref().map_or(0, |token| token.generation + 1);
+        *token = Some(Token { value, generation });
+    }
+}
+
+/// Sends the token request, straight to the client rather than through
+/// [`http::send`], which would dump it and try to authorize it.
+async fn token_request(
+    client: &reqwest::Client,
+    source: &TokenSource,
+) -> anyhow::Result<HeaderValue> {
+    let mut request = client.post(source.url.clone());
+    if let Some((content_type, body)) = body(source) {
+        request = request
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    parse(source, status, &body)
+}
+
+/// The `Content-Type` and body of the token request, if it has one.
+fn body(source: &TokenSource) -> Option<(&'static str, String)> {
+    let body = source.body.clone()?;
+    let content_type = match serde_json::from_str::<Value>(&body) {
+        Ok(_) => "application/json",
+        Err(_) => "application/x-www-form-urlencoded",
+    };
+    Some((content_type, body))
+}
+
+/// The `Authorization` value for the token in the token URL's answer. The
+/// answer is left out of the errors, the token being in it.
+fn parse(
+    source: &TokenSource,
+    status: reqwest::StatusCode,
+    body: &[u8],
+) -> anyhow::Result<HeaderValue> {
+    let url = http::redact_url(&source.url);
+    if !status.is_success() {
+        bail!("The token URL {url} answered {status}");
+    }
+    let json: Value = serde_json::from_slice(body)
+        .with_context(|| format!("The token URL {url} didn't answer with JSON"))?;
+    let Some(token) = source.path.find(&json) else {
+        bail!(
+            "The token URL {url} answered without a string at {}",
+            source.path
+        );
+    };
+    let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+        .with_context(|| format!("The token from {url} can't be sent in a header"))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, bail};
+use std::collections::BTreeMap;
+
+/// How deeply lists and dictionaries may nest. Torrents need 4 levels, so
+/// this only stops a hostile file from overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+/// A bencoded value, borrowing its strings from the input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Integer(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    Dict(BTreeMap<&'a [u8], Value<'a>>),
+}
+
+impl<'a> Value<'a> {
+    /// The value under `key`, if this is a dictionary that has one.
+    pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[Value<'a>]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+/// Parses one bencoded value that takes up all of `input`.
+pub fn parse(input: &[u8]) -> anyhow::Result<Value<'_>> {
+    let mut parser = Parser { input, at: 0 };
+    let value = parser.value(0)?;
+    if parser.at != input.len() {
+        bail!(
+            "{} bytes left over at byte {}",
+            input.len() - parser.at,
+            parser.at
+        );
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self, depth: usize) -> anyhow::Result<Value<'a>> {
+        if depth > MAX_DEPTH {
+            bail!(
+                "nested more than {MAX_DEPTH} levels deep at byte {}",
+                self.at
+            );
+        }
+        match self.peek()? {
+            b'i' => {
+                self.at += 1;
+                let digits = self.until(b'e')?;
+                let value = std::str::from_utf8(digits)
+                    .ok()
+                    .filter(|digits| canonical_integer(digits))
+                    .and_then(|digits| digits.parse().ok())
+                    .with_context(|| format!("bad integer at byte {}", self.at))?;
+                Ok(Value::Integer(value))
+            }
+            b'l' => {
+                self.at += 1;
+                let mut list = Vec::new();
+                while self.peek()? != b'e' {
+                    list.push(self.value(depth + 1)?);
+                }
+                self.at += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.at += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.bytes()?;
+                    dict.insert(key, self.value(depth + 1)?);
+                }
+                self.at += 1;
+                Ok(Value::Dict(dict))
+            }
+            b'0'..=b'9' => Ok(Value::Bytes(self.bytes()?)),
+            other => bail!("unexpected '{}' at byte {}", other.escape_ascii(), self.at),
+        }
+    }
+
+    /// A `<length>:<bytes>` string.
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let start = self.at;
+        let length = std::str::from_utf8(self.until(b':')?)
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .with_context(|| format!("bad string length at byte {start}"))?;
+        let end = self
+            .at
+            .checked_add(length)
+            .filter(|end| *end <= self.input.len())
+            .with_context(|| format!("string at byte {start} runs past the end"))?;
+        let bytes = &self.input[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    /// The bytes up to `end`, skipping past it.
+    fn until(&mut self, end: u8) -> anyhow::Result<&'a [u8]> {
+        let rest = &self.input[self.at..];
+        let length = rest
+            .iter()
+            .position(|byte| *byte == end)
+            .context("unexpected end of input")?;
+        self.at += length + 1;
+        Ok(&rest[..length])
+    }
+
+    fn peek(&self) -> anyhow::Result<u8> {
+        self.input
+            .get(self.at)
+            .copied()
+            .context("unexpected end of input")
+    }
+}
+
+/// Whether an integer is written the one way bencode allows: no leading
+/// zeros, a plus sign or `-0`.
+fn canonical_integer(digits: &str) -> bool {
+    let unsigned = digits.strip_prefix('-').unwrap_or(digits);
+    !unsigned.is_empty()
+        && unsigned.bytes().all(|byte| byte.is_ascii_digit())
+        && (unsigned == "0" && unsigned.len() == digits.len() || !unsigned.starts_with('0'))
+}
@@ -1,85 +1,647 @@
+//! Downloads for callers without a tokio runtime, on `reqwest::blocking`.
+
 use anyhow::bail;
+use reqwest::{StatusCode, header};
 use std::fs::{self, OpenOptions};
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::sync::atomic::Ordering;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use url::Url;
 
-use crate::download::progress::DownloadProgress;
+use crate::download::client::ClientOptions;
+use crate::download::content_type;
+use crate::download::error::DownloadError;
+use crate::download::expected_size::{self, SizeCheck};
+use crate::download::file_watch::FileWatch;
+use crate::download::filesystem;
+use crate::download::fs_ops;
+use crate::download::http::{self, StatusClass, unsatisfiable};
+use crate::download::network_wait;
+use crate::download::options::TransferOptions;
+use crate::download::partial;
+use crate::download::postprocess::{DownloadOutcome, Hash, Pipeline, PostProcessor, StepTiming};
+use crate::download::progress::TransferProgress;
+use crate::download::progress_handle::{PreflightStep, ProgressSnapshot};
+use crate::download::rate_limit;
+use crate::download::retry;
+use crate::download::speed::FirstByte;
+use crate::download::stall::{MAX_STALL_RESTARTS, Stall, StallMonitor};
+use crate::download::target_wait;
+use crate::download::torrent;
 use crate::download::utils;
+use crate::download::validator::{self, IfChanged, Validator};
+
+/// The blocking counterpart of [`Downloader`](crate::download::Downloader),
+/// for applications that don't run tokio. It downloads to a file, the same
+/// way `dlm download-blocking` does:
+///
+/// ```no_run
+/// use download_manager::BlockingDownloader;
+///
+/// # fn run() -> anyhow::Result<()> {
+/// let url = "https://example.com/file.bin".parse()?;
+/// let result = BlockingDownloader::new()?
+///     .with_target_dir("downloads")
+///     .download_with_progress(url, |snapshot| {
+///         println!("{} of {} bytes", snapshot.downloaded, snapshot.total);
+///     })?;
+/// println!("{} ({})", result.path.display(), hex::encode(result.sha256));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct BlockingDownloader {
+    client: reqwest::blocking::Client,
+    target_dir: PathBuf,
+    chunk_size: usize,
+    options: TransferOptions,
+    interrupted: Arc<AtomicBool>,
+    progress_interval: Duration,
+    post_processing: Pipeline,
+}
+
+/// A finished download.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadResult {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Of the content, which with `store_compressed` isn't the file's.
+    pub sha256: [u8; 32],
+    /// How long each step after the transfer took.
+    pub post_processing: Vec<StepTiming>,
+}
+
+impl BlockingDownloader {
+    /// A downloader with a default client, saving to the current directory.
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self::with_client(
+            ClientOptions::default().build_blocking()?,
+        ))
+    }
+
+    pub fn with_client(client: reqwest::blocking::Client) -> Self {
+        Self {
+            client,
+            target_dir: PathBuf::from("."),
+            chunk_size: 65_536,
+            options: TransferOptions::default(),
+            interrupted: Arc::default(),
+            progress_interval: Duration::from_millis(100),
+            post_processing: Pipeline::default().with_step(Hash),
+        }
+    }
+
+    /// Where downloads are saved, created if needed.
+    pub fn with_target_dir(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = target_dir.into();
+        self
+    }
+
+    /// How much is read from the response at a time.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Resuming, overwriting, the file name, stall handling and the rest.
+    pub fn with_options(mut self, options: TransferOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// A flag that stops the download when set, from any thread.
+    pub fn with_interrupt(mut self, interrupted: Arc<AtomicBool>) -> Self {
+        self.interrupted = interrupted;
+        self
+    }
+
+    /// How often [`download_with_progress`](Self::download_with_progress)
+    /// looks at the progress.
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = interval;
+        self
+    }
+
+    /// Runs `step` on every finished download, after hashing it and the
+    /// steps added before.
+    pub fn with_post_processor(mut self, step: impl PostProcessor + 'static) -> Self {
+        self.post_processing.push(step);
+        self
+    }
+
+    /// Downloads `url` on this thread.
+    pub fn download(&self, url: Url) -> anyhow::Result<DownloadResult> {
+        utils::prepare_target_dir(&self.target_dir)?;
+        let progress = TransferProgress::new(self.interrupted.clone());
+        let path = self.transfer(url.clone(), progress.clone())?;
+        self.finished(DownloadOutcome::new(
+            url,
+            path,
+            progress.snapshot(),
+            self.options.memory.clone(),
+        ))
+    }
+
+    /// Downloads `url` on a thread of its own, calling `on_progress` on
+    /// this one whenever the progress changed, then once more at the end.
+    pub fn download_with_progress(
+        &self,
+        url: Url,
+        mut on_progress: impl FnMut(&ProgressSnapshot),
+    ) -> anyhow::Result<DownloadResult> {
+        utils::prepare_target_dir(&self.target_dir)?;
+        let progress = TransferProgress::new(self.interrupted.clone());
+        let handle = progress.handle();
+        let (done, outcome) = mpsc::channel();
+        let transfer = url.clone();
+        let path = std::thread::scope(|scope| {
+            scope.spawn(move || done.send(self.transfer(transfer, progress)));
+            let mut last = None;
+            loop {
+                let received = outcome.recv_timeout(self.progress_interval);
+                let snapshot = handle.snapshot();
+                if last.as_ref() != Some(&snapshot) {
+                    on_progress(&snapshot);
+                    last = Some(snapshot);
+                }
+                match received {
+                    Ok(result) => return result,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => bail!("The download thread panicked"),
+                }
+            }
+        })?;
+        self.finished(DownloadOutcome::new(
+            url,
+            path,
+            handle.snapshot(),
+            self.options.memory.clone(),
+        ))
+    }
+
+    /// Downloads `url` reporting to `progress`, without hashing the file
+    /// afterwards; for callers that show progress and hash it their own way,
+    /// like `dlm`. Returns where it was saved.
+    pub fn transfer(&self, url: Url, progress: TransferProgress) -> anyhow::Result<PathBuf> {
+        download_file_blocking(
+            &self.client,
+            url,
+            &self.target_dir,
+            self.chunk_size,
+            progress,
+            &self.options,
+        )
+    }
 
+    /// Runs the steps after the transfer on `outcome`.
+    fn finished(&self, mut outcome: DownloadOutcome) -> anyhow::Result<DownloadResult> {
+        let post_processing = self.post_processing.run(&mut outcome)?;
+        Ok(DownloadResult {
+            size: fs::metadata(&outcome.path)?.len(),
+            sha256: outcome.sha256.expect("the hash step runs first"),
+            path: outcome.path,
+            post_processing,
+        })
+    }
+}
+
+/// Downloads `url` on the current thread.
+///
+/// Stalls are detected through the client's timeout, which `reqwest` applies
+/// to every body read, so it should be set to the stall timeout.
 pub fn download_file_blocking(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    target_dir: &Path,
+    chunk_size: usize,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    progress.set_retry_budget(&options.retry_budget);
+    let reporter = progress.clone();
+    // reqwest's blocking client works on a thread of its own, so only the
+    // wait for the answer is reported; a timeout still names it.
+    let result = download(client, url, target_dir, chunk_size, progress, options)
+        .map_err(|error| reporter.reporter().name_timeout(error));
+    reporter.finish_with(&result);
+    result
+}
+
+fn download(
+    client: &reqwest::blocking::Client,
     url: Url,
-    target_dir: &PathBuf,
+    target_dir: &Path,
     chunk_size: usize,
-    resume: bool,
-    overwrite: bool,
-    progress: DownloadProgress,
+    progress: TransferProgress,
+    options: &TransferOptions,
 ) -> anyhow::Result<PathBuf> {
-    let fname = utils::build_download_path(&url, target_dir);
+    if options.resume_from.is_some() {
+        bail!("--resume-from only works for worker-mode downloads");
+    }
+    options.check_compressed_resume()?;
+    let mut fname = options.destination(&url, target_dir);
+    let resuming = (options.resume && !options.overwrite) || options.continue_at.is_some();
+    // Where the bytes go until they're all there.
+    let mut target = partial::target(&fname, resuming);
     let mut resume_from = 0;
-    let mut dest = if fname.exists() && fname.is_file() {
-        if overwrite {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .truncate(true)
-                .open(&fname)?
-        } else if resume {
-            resume_from = fs::metadata(&fname)?.len() as usize;
-            OpenOptions::new().read(true).append(true).open(&fname)?
-        } else {
-            bail!("File exists at '{}'", fname.display());
-        }
-    } else {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&fname)?
+    let continue_from = options.continue_offset(&target)?;
+    if let Some(offset) = continue_from {
+        resume_from = offset as usize;
+    } else if options.resume && !options.overwrite && target.is_file() {
+        resume_from = fs::metadata(&target)?.len() as usize;
+    } else if fname.is_file() && !options.overwrite {
+        return Err(DownloadError::FileExists { path: fname }.into());
+    }
+    expected_size::check(&fname, options, SizeCheck::Partial, resume_from as u64)?;
+    progress
+        .reporter()
+        .set_preflight(PreflightStep::AwaitingResponse);
+    let mut first_byte = FirstByte::new();
+    if !options
+        .pacing
+        .before_request_blocking(&progress.interrupted)
+        || !rate_limit::before_request_blocking(&url, &progress)
+    {
+        return Err(DownloadError::Interrupted.into());
+    }
+    // What the file was when its download started, to resume only that.
+    let mut started = match resume_from {
+        0 => None,
+        _ => Validator::load(&fname),
     };
-    let mut response = if resume_from > 0 {
-        let resp = reqwest::blocking::Client::new()
-            .get(url)
-            .header("Range", format!("bytes={}-", resume_from))
-            .send()?;
-        match resp.status().as_u16() {
-            206 => resp,
-            416 => bail!("File already complete"),
-            200 => {
-                eprintln!("Server doesn't support resume. Try --overwrite");
-                bail!("Cannot resume - server sent full file");
+    let mut response =
+        network_wait::retry_blocking(&url, options, &progress, || match resume_from {
+            0 => Ok(http::check_status_blocking(http::send_retrying_blocking(
+                options.request_blocking(client, &url),
+                None,
+                &options.retry_budget,
+                options.auth_token.as_deref(),
+            )?)?),
+            _ => request_from(client, &url, resume_from, options, started.as_ref()),
+        })?;
+    // The server may name the file itself, which only its answer tells.
+    if resume_from == 0 && continue_from.is_none() {
+        let named = options.served_destination(&url, target_dir, response.headers());
+        if named != fname {
+            target = partial::target(&named, resuming);
+            if options.resume && !options.overwrite && target.is_file() {
+                resume_from = fs::metadata(&target)?.len() as usize;
+                expected_size::check(&named, options, SizeCheck::Partial, resume_from as u64)?;
+                started = Validator::load(&named);
+                response = request_from(client, &url, resume_from, options, started.as_ref())?;
+            } else if named.is_file() && !options.overwrite {
+                return Err(DownloadError::FileExists { path: named }.into());
             }
-            _ => bail!("Unexpected status: {}", resp.status()),
         }
-    } else {
-        reqwest::blocking::get(url)?
+        fname = named;
+    }
+    // `--restart-on-unresumable` or `--if-changed restart`: the whole file
+    // is coming, again.
+    if resume_from > 0 && response.status() == StatusCode::OK {
+        progress.add_wasted(resume_from as u64);
+        resume_from = 0;
+    }
+    progress.set_prefix(resume_from as u64);
+    if StatusClass::of(response.status()) == StatusClass::Empty {
+        tracing::info!("Server answered {}, the file is empty", response.status());
+        progress.reporter().set_no_content();
+    } else if resume_from == 0 && response.content_length() == Some(0) {
+        tracing::info!("Server sent a Content-Length of 0, the file is empty");
+        progress.reporter().set_no_content();
+    }
+    // Nothing is written to the file until the server has agreed to send
+    // what's wanted and the response passed its checks.
+    torrent::check_content_type(&fname, response.headers(), options.save_torrent)?;
+    content_type::check(
+        &fname,
+        response.headers(),
+        options.strict_content_type,
+        progress.reporter(),
+    )?;
+    if resume_from == 0 {
+        http::check_encoding(response.headers(), &fname, options.no_compression)?;
+    }
+    if let Some(length) = response.content_length() {
+        let size = resume_from as u64 + length;
+        expected_size::check(&fname, options, SizeCheck::Announced, size)?;
+        filesystem::check(&fname, size, options)?;
+    }
+    if resume_from == 0 && options.can_resume() {
+        let validator = Validator::of(response.headers(), response.content_length());
+        validator.save(&fname)?;
+        started = Some(validator);
+    }
+    let discard = partial::Discard::new(&target, &fname, options.no_partial);
+    let mut dest = match continue_from {
+        Some(offset) if resume_from > 0 => {
+            let mut dest = target_wait::retry("open", &target, || {
+                fs_ops::open(
+                    OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(false)
+                        .append(cfg!(unix)),
+                    &target,
+                )
+            })?;
+            fs_ops::set_len(&dest, &target, offset)?;
+            dest.seek(SeekFrom::Start(offset))?;
+            dest
+        }
+        _ if resume_from > 0 => target_wait::retry("open", &target, || {
+            fs_ops::open(OpenOptions::new().read(true).append(true), &target)
+        })?,
+        _ => {
+            // A file opened to append can't be truncated as it's opened.
+            let dest = target_wait::retry("create", &target, || {
+                fs_ops::open(
+                    OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .append(cfg!(unix)),
+                    &target,
+                )
+            })?;
+            fs_ops::set_len(&dest, &target, 0)?;
+            dest
+        }
     };
+    let metadata = dest.metadata()?;
+    let mut watch = FileWatch::new(&target, &metadata, metadata.len());
     let content_length = response.content_length();
-    progress
-        .total_bytes
-        .store(content_length.unwrap_or(0), Ordering::Relaxed);
+    // Of the whole file, to name what's missing if the retries run out.
+    let mut size = content_length.map(|length| resume_from as u64 + length);
+    // The prefix counts towards what's downloaded, so towards the total.
+    progress.set_total(size.unwrap_or(0));
+    progress.set_content_type(
+        response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    );
+    progress.set_tls(options.tls.negotiated());
+    let mut compressor = options
+        .store_compressed
+        .map(|compression| compression.compressor())
+        .transpose()?;
     let mut downloaded = resume_from;
+    let mut stall = StallMonitor::new(options.stall);
+    let mut restarts = 0;
+    // Retries of lost connections, for --retries.
+    let mut retries = 0;
     loop {
         let mut buffer = vec![0; chunk_size];
-        let data = response.read(&mut buffer[..])?;
-        if data == 0 {
-            break;
+        let reading = Instant::now();
+        let result = response.read(&mut buffer[..]);
+        progress.time_split.add_network(reading.elapsed());
+        // Why the rest is re-requested, and whether the connection was
+        // lost, which takes one of the retries and looks the host up again.
+        let (mut reason, mut lost) = match result {
+            Ok(0) => break,
+            Ok(data) => {
+                if let Some(ttfb) = first_byte.arrived() {
+                    tracing::debug!("First byte after {}ms", ttfb.as_millis());
+                    progress.set_first_byte(ttfb);
+                }
+                options.throttle.take_blocking(data);
+                stall.record(data);
+                if progress.interrupted.load(Ordering::SeqCst) {
+                    break;
+                }
+                let writing = Instant::now();
+                let compressed;
+                let written = match &mut compressor {
+                    Some(compressor) => {
+                        compressed = compressor.compress(&buffer[..data])?;
+                        &compressed
+                    }
+                    None => &buffer[..data],
+                };
+                dest.write_all(written)?;
+                watch.wrote(written.len());
+                if watch.due() {
+                    watch.check()?;
+                }
+                progress.time_split.add_disk(writing.elapsed());
+                downloaded += data;
+                progress.set_downloaded(downloaded);
+                match stall.check() {
+                    Some(stalled) => (stalled.to_string(), false),
+                    None => continue,
+                }
+            }
+            Err(e) if is_timeout(&e) => (
+                Stall::NoData(options.stall.stall_timeout).to_string(),
+                false,
+            ),
+            Err(e) if options.can_resume() && retry::is_transient(&e) => {
+                (format!("Connection lost ({e})"), true)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !lost {
+            restarts += 1;
+            if restarts > MAX_STALL_RESTARTS {
+                bail!("{reason}, giving up after {MAX_STALL_RESTARTS} restarts");
+            }
+            if !options.can_resume() {
+                bail!(
+                    "{reason}, and a {} download can't be resumed",
+                    options.method
+                );
+            }
         }
-        downloaded += data;
-        progress
-            .bytes_downloaded
-            .store(downloaded, Ordering::Relaxed);
-        if progress.interrupted.load(Ordering::SeqCst) {
-            break;
+        response = loop {
+            let attempt = match lost {
+                true => {
+                    retries += 1;
+                    if retries > options.retry.retries {
+                        return Err(DownloadError::RetriesExhausted {
+                            chunk: None,
+                            start: downloaded as u64,
+                            end: size.and_then(|size| size.checked_sub(1)),
+                            retries: options.retry.retries,
+                            error: reason,
+                        }
+                        .into());
+                    }
+                    retries as usize
+                }
+                false => restarts,
+            };
+            options.retry_budget.spend(None, attempt, &reason)?;
+            // A lost connection waits out the backoff; a stall has waited
+            // long enough.
+            let wait = match lost {
+                true => options.retry.wait(retries),
+                false => Duration::ZERO,
+            };
+            match lost {
+                true => eprintln!(
+                    "{reason}, re-requesting from byte {downloaded} in {:.1}s ({retries} of {})",
+                    wait.as_secs_f64(),
+                    options.retry.retries
+                ),
+                false => eprintln!("{reason}, re-requesting from byte {downloaded}"),
+            }
+            // The server may have been drained from under its DNS name:
+            // ask wherever the name points now.
+            let stale = lost.then(|| options.dns.forget(&url));
+            std::thread::sleep(wait);
+            if progress.interrupted.load(Ordering::SeqCst)
+                || !options
+                    .pacing
+                    .before_request_blocking(&progress.interrupted)
+                || !rate_limit::before_request_blocking(&url, &progress)
+            {
+                dest.sync_all()?;
+                return Err(DownloadError::Interrupted.into());
+            }
+            let _retry =
+                tracing::trace_span!("retry", attempt, %reason, resume_at = downloaded).entered();
+            let next = request_from(client, &url, downloaded, options, started.as_ref());
+            if let Some(stale) = stale {
+                options.dns.log_change(&url, &stale);
+            }
+            match next {
+                Err(error) if retry::is_transient(error.as_ref()) => {
+                    reason = format!("Request failed ({error:#})");
+                    lost = true;
+                }
+                next => break next?,
+            }
+        };
+        if response.status() == StatusCode::OK {
+            if let Some(length) = response.content_length() {
+                expected_size::check(&fname, options, SizeCheck::Announced, length)?;
+            }
+            let validator = Validator::of(response.headers(), response.content_length());
+            validator.save(&fname)?;
+            started = Some(validator);
+            progress.add_wasted(downloaded as u64);
+            fs_ops::set_len(&dest, &target, 0)?;
+            dest.seek(SeekFrom::Start(0))?;
+            watch.reset(0);
+            if let Some(compression) = options.store_compressed {
+                compressor = Some(compression.compressor()?);
+            }
+            downloaded = 0;
+            progress.set_downloaded(0);
+            progress.set_total(response.content_length().unwrap_or(0));
+            size = response.content_length();
         }
-        dest.write_all(&mut buffer[..data])?;
+        stall.reset();
     }
-    dest.sync_all()?;
-
     if progress.interrupted.load(Ordering::SeqCst) {
-        bail!("Download cancelled by user");
+        dest.sync_all()?;
+        return Err(DownloadError::Interrupted.into());
+    }
+    let writing = Instant::now();
+    let stored = match compressor {
+        Some(compressor) => {
+            let (tail, stored) = compressor.finish()?;
+            dest.write_all(&tail)?;
+            watch.wrote(tail.len());
+            Some(stored)
+        }
+        None => None,
+    };
+    dest.sync_all()?;
+    watch.check()?;
+    progress.time_split.add_disk(writing.elapsed());
+    expected_size::check_done(&target, options, downloaded as u64)?;
+    drop(dest);
+    partial::finish(&target, &fname)?;
+    discard.keep();
+    if let Some(stored) = stored {
+        println!("Compressed: {stored}");
+        progress.reporter().set_sha256(stored.sha256);
     }
+    validator::remove(&fname);
 
     Ok(fname)
 }
+
+/// Requests the remainder of `url` starting at byte `offset`, only if it's
+/// still the file `started` describes.
+/// With `--restart-on-unresumable`, a server that can't resume there sends
+/// the whole file instead, with a 200; with `--if-changed restart`, so does
+/// one whose file changed.
+fn request_from(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+    offset: usize,
+    options: &TransferOptions,
+    started: Option<&Validator>,
+) -> anyhow::Result<reqwest::blocking::Response> {
+    let mut headers = http::range_headers(&format!("bytes={offset}-"));
+    if let Some(validator) = started.and_then(Validator::if_range) {
+        headers.insert(header::IF_RANGE, validator);
+    }
+    let resp = http::send_retrying_blocking(
+        client.get(url.clone()).headers(headers),
+        None,
+        &options.retry_budget,
+        options.auth_token.as_deref(),
+    )?;
+    if resp.status().is_success() {
+        http::check_identity(resp.headers())?;
+    }
+    if let Some(change) = started.and_then(|started| started.changed(resp.status(), resp.headers()))
+    {
+        if options.if_changed == IfChanged::Abort {
+            return Err(DownloadError::RemoteChanged { change }.into());
+        }
+        http::announce_restart(
+            offset,
+            &format!("the remote file changed since the download started, {change}"),
+        );
+        return match resp.status() {
+            StatusCode::OK => Ok(resp),
+            _ => http::check_status_blocking(http::send_retrying_blocking(
+                client.get(url.clone()),
+                None,
+                &options.retry_budget,
+                options.auth_token.as_deref(),
+            )?),
+        };
+    }
+    let restart = options.restart_on_unresumable;
+    match resp.status().as_u16() {
+        206 => Ok(resp),
+        416 if restart => match http::unsatisfied_total(resp.headers()) {
+            Some(total) if total != offset as u64 => {
+                http::announce_restart(offset, &format!("the remote file is {total} bytes"));
+                http::check_status_blocking(http::send_retrying_blocking(
+                    client.get(url.clone()),
+                    None,
+                    &options.retry_budget,
+                    options.auth_token.as_deref(),
+                )?)
+            }
+            _ => bail!(unsatisfiable(resp.headers(), offset)),
+        },
+        416 => bail!(unsatisfiable(resp.headers(), offset)),
+        200 if restart => {
+            http::announce_restart(offset, "the server ignored the range request");
+            Ok(resp)
+        }
+        200 => {
+            eprintln!("Server doesn't support resume. Try --overwrite");
+            bail!("Cannot resume - server sent full file");
+        }
+        _ => Err(http::unexpected_status_blocking(resp).into()),
+    }
+}
+
+fn is_timeout(error: &std::io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+        .is_some_and(reqwest::Error::is_timeout)
+}
@@ -0,0 +1,283 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::fs_ops;
+use crate::download::http;
+use crate::download::memory::MemoryLimit;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::utils;
+use anyhow::Context;
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// `--cache-dir`: downloads kept by URL with the validators they were served
+/// with, so later runs can ask the server whether they're still current.
+///
+/// Each URL has a `<sha256 of the URL>.data` file and a `.json` [`Entry`]
+/// next to it. The least recently used entries are dropped once the cache
+/// grows past `max_size`.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+/// What's stored about a cached download.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Hex SHA-256 of the data file, checked before every reuse.
+    pub sha256: String,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch.
+    pub last_used: u64,
+}
+
+/// The validators a response came with, to revalidate a cached copy with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Entry {
+    /// The stored hash as bytes.
+    pub fn hash(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.sha256).ok()?.try_into().ok()
+    }
+}
+
+impl Validators {
+    fn from_headers(headers: &header::HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// The outcome of [`Cache::revalidate`].
+#[derive(Debug)]
+pub enum Lookup {
+    /// The server answered 304 and the cached copy passed its hash check.
+    Fresh(Entry),
+    /// Nothing usable is cached; download and [`Cache::store`] the result
+    /// with these validators.
+    Stale(Validators),
+}
+
+impl Cache {
+    pub fn open(dir: &Path, max_size: u64) -> io::Result<Self> {
+        fs_ops::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_size,
+        })
+    }
+
+    /// Asks the server whether the cached copy of `url` is still current,
+    /// with `If-None-Match`/`If-Modified-Since`. A cached copy that no
+    /// longer matches its stored hash, hashed within `memory`, is dropped
+    /// and fetched again. A 5xx is retried while `budget` allows.
+    pub async fn revalidate(
+        &self,
+        client: &reqwest::Client,
+        url: &Url,
+        budget: &RetryBudget,
+        auth: Option<&AuthToken>,
+        memory: &Arc<MemoryLimit>,
+    ) -> anyhow::Result<Lookup> {
+        let cached = self.entry(url).filter(|entry| {
+            let intact = self.is_intact(url, entry, memory);
+            if !intact {
+                diagnostics::warn(
+                    WarningId::CacheCorrupt,
+                    format!(
+                        "Cached copy of {} is corrupt, dropping it",
+                        http::redact_url(url)
+                    ),
+                );
+                self.remove(url);
+            }
+            intact
+        });
+
+        let mut request = client.get(url.clone());
+        if let Some(entry) = &cached {
+            if let Some(etag) = &entry.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        // Only the headers are wanted: a changed file is downloaded the
+        // usual way afterwards, and dropping the response closes it.
+        let response = http::send_retrying(request, None, budget, auth).await?;
+        match (cached, response.status()) {
+            (Some(entry), StatusCode::NOT_MODIFIED) => Ok(Lookup::Fresh(entry)),
+            (_, status) if status.is_success() => {
+                Ok(Lookup::Stale(Validators::from_headers(response.headers())))
+            }
+            _ => Err(http::unexpected_status(response).await.into()),
+        }
+    }
+
+    /// Puts the cached copy of `url` at `dest`, hard-linked when possible.
+    /// An existing `dest` is replaced rather than written through, so it
+    /// can't be another link to the cached data.
+    pub fn restore(&self, url: &Url, entry: &Entry, dest: &Path) -> io::Result<()> {
+        self.link(&self.data_path(url), dest)?;
+        self.touch(url, entry.clone())
+    }
+
+    /// Keeps the download of `url` at `path`, then evicts the least recently
+    /// used entries past the size cap. Responses without an ETag or
+    /// Last-Modified can't be revalidated, so they aren't kept, and neither
+    /// are files bigger than the whole cache.
+    pub fn store(
+        &self,
+        url: &Url,
+        path: &Path,
+        validators: Validators,
+        sha256: [u8; 32],
+    ) -> anyhow::Result<()> {
+        let size = fs::metadata(path)?.len();
+        if validators.is_empty() || size > self.max_size {
+            self.remove(url);
+            return Ok(());
+        }
+        self.link(path, &self.data_path(url))
+            .with_context(|| format!("Cannot add '{}' to the cache", path.display()))?;
+        let entry = Entry {
+            url: url.to_string(),
+            etag: validators.etag,
+            last_modified: validators.last_modified,
+            sha256: hex::encode(sha256),
+            size,
+            last_used: 0,
+        };
+        self.touch(url, entry)?;
+        self.evict(url)
+    }
+
+    /// Empties the cache, returning how many entries and bytes it held.
+    pub fn purge(&self) -> io::Result<(usize, u64)> {
+        let entries = self.entries()?;
+        let bytes = entries.iter().map(|(_, entry)| entry.size).sum();
+        for (key, _) in &entries {
+            self.remove_key(key);
+        }
+        Ok((entries.len(), bytes))
+    }
+
+    /// Drops least recently used entries other than `keep` until the cache
+    /// fits in its size cap.
+    fn evict(&self, keep: &Url) -> anyhow::Result<()> {
+        let keep = key(keep);
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, entry)| entry.size).sum();
+        entries.sort_by_key(|(_, entry)| entry.last_used);
+        for (key, entry) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            if key != keep {
+                tracing::debug!("Evicting {} from the cache", entry.url);
+                self.remove_key(&key);
+                total -= entry.size;
+            }
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> io::Result<Vec<(String, Entry)>> {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && let Some(key) = path.file_stem().and_then(|stem| stem.to_str())
+                && let Some(entry) = read_entry(&path)
+            {
+                entries.push((key.to_string(), entry));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn entry(&self, url: &Url) -> Option<Entry> {
+        read_entry(&self.entry_path(url)).filter(|entry| entry.url == url.as_str())
+    }
+
+    fn is_intact(&self, url: &Url, entry: &Entry, memory: &Arc<MemoryLimit>) -> bool {
+        let data = self.data_path(url);
+        fs::metadata(&data).is_ok_and(|metadata| metadata.len() == entry.size)
+            && utils::hash_file(&data, memory).is_ok_and(|hash| hex::encode(hash) == entry.sha256)
+    }
+
+    /// Writes `entry` with `last_used` set to now, through a rename so a
+    /// crash never leaves half an entry.
+    fn touch(&self, url: &Url, mut entry: Entry) -> io::Result<()> {
+        entry.last_used = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let path = self.entry_path(url);
+        let partial = path.with_extension("json.partial");
+        fs_ops::write(&partial, serde_json::to_vec(&entry)?)?;
+        fs_ops::rename(&partial, &path)
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match fs_ops::remove_file(to) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+        fs_ops::hard_link(from, to).or_else(|_| fs_ops::copy(from, to).map(drop))
+    }
+
+    fn remove(&self, url: &Url) {
+        self.remove_key(&key(url));
+    }
+
+    fn remove_key(&self, key: &str) {
+        // The entry goes first: data without one is never used.
+        let _ = fs_ops::remove_file(&self.dir.join(format!("{key}.json")));
+        let _ = fs_ops::remove_file(&self.dir.join(format!("{key}.data")));
+    }
+
+    fn entry_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.json", key(url)))
+    }
+
+    fn data_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.data", key(url)))
+    }
+}
+
+fn key(url: &Url) -> String {
+    hex::encode(Sha256::digest(url.as_str()))
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
@@ -0,0 +1,364 @@
+use crate::download::memory::MemoryLimit;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Read size for hashing local files. Bigger reads don't hash any faster,
+/// so this is fixed rather than following `--chunk-size`.
+pub const HASH_BUFFER: usize = 1024 * 1024;
+
+/// Digests the `hash` subcommand can compute.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Sha256,
+    Sha512,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+impl Algorithm {
+    /// Length of the digest in hex, to tell a sums line apart from garbage.
+    pub fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Md5 => 32,
+            Algorithm::Sha1 => 40,
+            Algorithm::Sha256 | Algorithm::Blake3 => 64,
+            Algorithm::Sha512 => 128,
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            Algorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            Algorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            "sha1" => Ok(Algorithm::Sha1),
+            "md5" => Ok(Algorithm::Md5),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => Err(format!(
+                "unknown algorithm '{value}', expected sha256, sha512, sha1, md5 or blake3"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Md5 => "md5",
+            Algorithm::Blake3 => "blake3",
+        })
+    }
+}
+
+/// A digest being computed with one of the [`Algorithm`]s.
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// `--checksum`: the digest a download should have, and with what.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub digest: Vec<u8>,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    /// `ALGO:HEX`, or a bare digest: sha256, or md5, sha1 or sha512 by its
+    /// length.
+    fn from_str(value: &str) -> Result<Self, String> {
+        let (algorithm, hex) = match value.split_once(':') {
+            Some((algorithm, hex)) => (algorithm.parse()?, hex),
+            None => match value.len() {
+                32 => (Algorithm::Md5, value),
+                40 => (Algorithm::Sha1, value),
+                128 => (Algorithm::Sha512, value),
+                _ => (Algorithm::Sha256, value),
+            },
+        };
+        match hex::decode(hex) {
+            Ok(digest) if hex.len() == algorithm.hex_len() => Ok(Self { algorithm, digest }),
+            _ => Err(format!(
+                "'{hex}' is not a {algorithm} digest, expected {} hex digits",
+                algorithm.hex_len()
+            )),
+        }
+    }
+}
+
+impl Checksum {
+    /// The digest `sums`, the text of a `sha256sum`-style file, lists for
+    /// the file called `name`. Lines are matched by file name, so
+    /// `./dist/tool.tar.gz` is the line for `tool.tar.gz`.
+    pub fn listed(sums: &str, algorithm: Algorithm, name: &str) -> Option<Self> {
+        sums.lines()
+            .filter_map(|line| SumsLine::parse(line, algorithm))
+            .find(|line| line.path.file_name().is_some_and(|listed| listed == name))
+            .map(|line| Self {
+                algorithm,
+                digest: hex::decode(line.hash).expect("SumsLine::parse checks the hex"),
+            })
+    }
+}
+
+/// `--checksum-of`: what a [`Checksum`] is the digest of.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumOf {
+    /// The file as downloaded.
+    #[default]
+    File,
+    /// What the file decompresses to, for a `.gz` or `.zst` published with
+    /// the checksum of its contents. The file itself stays compressed.
+    Decompressed,
+}
+
+impl FromStr for ChecksumOf {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "file" => Ok(ChecksumOf::File),
+            "decompressed" => Ok(ChecksumOf::Decompressed),
+            other => Err(format!(
+                "unknown target '{other}', expected file or decompressed"
+            )),
+        }
+    }
+}
+
+/// Hashes the file at `path`, calling `progress` with the bytes hashed so
+/// far after every read. Setting `interrupted` stops it with an
+/// [`io::ErrorKind::Interrupted`] error. The read buffer counts against
+/// `memory`.
+pub fn hash_file(
+    path: &Path,
+    algorithm: Algorithm,
+    interrupted: Option<&AtomicBool>,
+    memory: &Arc<MemoryLimit>,
+    progress: impl FnMut(u64),
+) -> io::Result<Vec<u8>> {
+    hash_of(
+        path,
+        ChecksumOf::File,
+        algorithm,
+        interrupted,
+        memory,
+        progress,
+    )
+}
+
+/// Hashes the file at `path`, or with [`ChecksumOf::Decompressed`] what it
+/// decompresses to, gzip or zstd as its first bytes tell. That's streamed
+/// through the decoder a buffer at a time, never held whole; `progress`
+/// gets the bytes of the file read so far. A file that doesn't decompress
+/// is an [`io::ErrorKind::InvalidData`] error.
+pub fn hash_of(
+    path: &Path,
+    of: ChecksumOf,
+    algorithm: Algorithm,
+    interrupted: Option<&AtomicBool>,
+    memory: &Arc<MemoryLimit>,
+    progress: impl FnMut(u64),
+) -> io::Result<Vec<u8>> {
+    let file = Counted {
+        inner: File::open(path)?,
+        read: 0,
+        progress,
+    };
+    let mut reader: Box<dyn Read + '_> = match of {
+        ChecksumOf::File => Box::new(file),
+        ChecksumOf::Decompressed => decoder(file)?,
+    };
+    let mut hasher = algorithm.hasher();
+    let reserved = memory.reserve_buffer_blocking("hashing", HASH_BUFFER);
+    let mut buffer = vec![0; reserved.size()];
+    loop {
+        if interrupted.is_some_and(|interrupted| interrupted.load(Ordering::SeqCst)) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "hashing interrupted",
+            ));
+        }
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(bytes_read) => bytes_read,
+            Err(error) if of == ChecksumOf::Decompressed => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+            Err(error) => return Err(error),
+        };
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// A decoder for `file`, picked by its magic number.
+fn decoder<'a>(mut file: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    let mut magic = [0; 6];
+    let read = read_up_to(&mut file, &mut magic)?;
+    let file = io::Cursor::new(magic[..read].to_vec()).chain(file);
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    match &magic[..read] {
+        [0x1f, 0x8b, ..] => Ok(Box::new(flate2::read::MultiGzDecoder::new(file))),
+        #[cfg(feature = "zstd")]
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Ok(Box::new(zstd::Decoder::new(file)?)),
+        #[cfg(not(feature = "zstd"))]
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Err(invalid(
+            "it's zstd, which needs dlm built with the zstd feature",
+        )),
+        [0xfd, b'7', b'z', b'X', b'Z', 0] => Err(invalid("it's xz, which dlm can't decompress")),
+        _ => Err(invalid("it's neither gzip nor zstd")),
+    }
+}
+
+/// Fills as much of `buffer` as `reader` has, returning how much that is.
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// A reader telling `progress` how much has been read from it.
+struct Counted<R, F> {
+    inner: R,
+    read: u64,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(u64)> Read for Counted<R, F> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.read += read as u64;
+        (self.progress)(self.read);
+        Ok(read)
+    }
+}
+
+/// One line of a `sha256sum`-style sums file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SumsLine {
+    /// Lowercase hex digest.
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+impl SumsLine {
+    /// Parses `<hex>  <path>` or `<hex> *<path>`, undoing the backslash
+    /// escapes coreutils writes for names with a newline or backslash in
+    /// them. `None` for lines that don't fit, or whose digest isn't as long
+    /// as `algorithm`'s.
+    pub fn parse(line: &str, algorithm: Algorithm) -> Option<Self> {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (hash, rest) = line.split_once(' ')?;
+        let name = rest.strip_prefix([' ', '*'])?;
+        if hash.len() != algorithm.hex_len() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let name = match escaped {
+            true => unescape(name)?,
+            false => name.to_string(),
+        };
+        (!name.is_empty()).then(|| Self {
+            hash: hash.to_ascii_lowercase(),
+            path: PathBuf::from(name),
+        })
+    }
+}
+
+impl fmt::Display for SumsLine {
+    /// Writes the line as `sha256sum` would, so the output can be checked
+    /// with it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.path.to_string_lossy();
+        if name.contains(['\\', '\n', '\r']) {
+            let name = name
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            write!(f, "\\{}  {name}", self.hash)
+        } else {
+            write!(f, "{}  {name}", self.hash)
+        }
+    }
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
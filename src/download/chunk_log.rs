@@ -0,0 +1,141 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::fs_ops;
+use crate::download::schema::Versioned;
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of a `--chunk-log` file.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ChunkRecord {
+    /// Unix time in milliseconds.
+    pub ts: u64,
+    /// When the download run started (Unix ms), so a log appended to by
+    /// several attempts can be told apart.
+    pub run: u64,
+    pub chunk: usize,
+    #[serde(flatten)]
+    pub event: ChunkEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChunkEvent {
+    Scheduled {
+        start: u64,
+        end: u64,
+    },
+    Started {
+        mirror: String,
+    },
+    /// The first body byte arrived, this long after the request went out.
+    FirstByte {
+        ttfb_ms: u64,
+    },
+    /// Every quarter of the chunk.
+    Bytes {
+        downloaded: u64,
+        percent: u64,
+    },
+    Retry {
+        attempt: usize,
+        error: String,
+    },
+    Completed {
+        duration_ms: u64,
+        avg_speed: u64,
+        /// Time spent awaiting the response body, and awaiting the disk.
+        /// Absent from logs written before they were recorded.
+        #[serde(default)]
+        network_ms: u64,
+        #[serde(default)]
+        disk_ms: u64,
+    },
+    Failed {
+        error: String,
+    },
+    /// A piece failed its `--piece-hashes` check and is fetched again.
+    PieceMismatch {
+        piece: usize,
+        start: u64,
+        end: u64,
+        mirror: String,
+    },
+}
+
+/// Appends chunk lifecycle events to a file as JSON lines. Each event is
+/// written with a single `write` straight to the file, so everything up to a
+/// crash survives it.
+#[derive(Clone, Debug)]
+pub struct ChunkLog {
+    file: Arc<Mutex<File>>,
+    run: u64,
+}
+
+impl ChunkLog {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = fs_ops::open(OpenOptions::new().create(true).append(true), path)
+            .with_context(|| format!("Cannot open chunk log '{}'", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            run: unix_ms(),
+        })
+    }
+
+    pub fn record(&self, chunk: usize, event: ChunkEvent) {
+        let record = ChunkRecord {
+            ts: unix_ms(),
+            run: self.run,
+            chunk,
+            event,
+        };
+        let Ok(mut line) = serde_json::to_vec(&Versioned::new(record)) else {
+            return;
+        };
+        line.push(b'\n');
+        let written = match self.file.lock() {
+            Ok(mut file) => file.write_all(&line),
+            Err(_) => return,
+        };
+        if let Err(error) = written {
+            diagnostics::warn(
+                WarningId::NotRecorded,
+                format!("Could not write to the chunk log: {error}"),
+            );
+        }
+    }
+}
+
+/// Tells when a chunk crosses the next quarter of its length.
+pub struct Milestones {
+    length: u64,
+    next: u64,
+}
+
+impl Milestones {
+    pub fn new(length: u64) -> Self {
+        Self { length, next: 25 }
+    }
+
+    /// Returns the percentage reached, if `downloaded` crossed a milestone
+    /// short of completion.
+    pub fn reached(&mut self, downloaded: u64) -> Option<u64> {
+        let reached = (downloaded * 100).checked_div(self.length)? / 25 * 25;
+        if reached < self.next || reached >= 100 {
+            return None;
+        }
+        self.next = reached + 25;
+        Some(reached)
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
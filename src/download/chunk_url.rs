@@ -0,0 +1,113 @@
+//! Where a worker-mode chunk sends its requests. Some CDNs answer a range
+//! request with a redirect to a URL signed for that range, which expires
+//! soon after. A chunk's retries go straight to the URL it was last
+//! redirected to, saving the hop, and back to the URL it was given once
+//! that one is refused with a 403: following the redirect again gets a
+//! fresh signature. reqwest keeps the `Range` header on every hop, and the
+//! `Content-Range` of the answer is checked against it all the same.
+//!
+//! With `--mirror`, a chunk starts from the mirror its turn falls on, and
+//! moves on to the next once the one it's at gives up on it.
+
+use crate::download::error::DownloadError;
+use crate::download::mirrors::Sources;
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+
+/// The redirects a chunk's requests were answered with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChunkRedirects {
+    pub chunk: usize,
+    /// Requests that ended up somewhere else than they were sent.
+    pub followed: u64,
+    /// Of the URLs redirected to, those refused when the chunk went back
+    /// to them, so it followed the redirect again.
+    pub expired: u64,
+}
+
+impl fmt::Display for ChunkRedirects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chunk {}: {} followed", self.chunk, self.followed)?;
+        if self.expired > 0 {
+            write!(f, ", {} expired", self.expired)?;
+        }
+        Ok(())
+    }
+}
+
+/// The source a chunk is downloading from, and the URL its last request
+/// ended up at when that was another.
+#[derive(Clone, Debug)]
+pub struct ChunkUrl {
+    sources: Sources,
+    current: usize,
+    /// Sources moved on from, the first included.
+    tried: usize,
+    resolved: Option<Url>,
+}
+
+impl ChunkUrl {
+    /// Starting from the source the `turn`th chunk falls on.
+    pub fn new(sources: Sources, turn: usize) -> Self {
+        ChunkUrl {
+            current: turn % sources.len(),
+            sources,
+            tried: 1,
+            resolved: None,
+        }
+    }
+
+    /// The URL of the source the chunk is at.
+    pub fn original(&self) -> &Url {
+        &self.sources[self.current].url
+    }
+
+    /// The ETag the source answered its probe with.
+    pub fn etag(&self) -> Option<&str> {
+        self.sources[self.current].etag.as_deref()
+    }
+
+    /// Moves on to the next source, unless the chunk has been at them all.
+    pub fn fall_back(&mut self) -> bool {
+        if self.tried == self.sources.len() {
+            return false;
+        }
+        self.tried += 1;
+        self.current = (self.current + 1) % self.sources.len();
+        self.resolved = None;
+        true
+    }
+
+    /// Where the next request goes.
+    pub fn target(&self) -> &Url {
+        self.resolved.as_ref().unwrap_or(self.original())
+    }
+
+    /// Records that a request to `requested` was answered from `answered`,
+    /// returning whether it was redirected there.
+    pub fn answered(&mut self, requested: &Url, answered: &Url) -> bool {
+        if requested == answered {
+            return false;
+        }
+        self.resolved = Some(answered.clone());
+        true
+    }
+
+    /// Whether `error`, in answer to a request to
+    /// [`target`](Self::target), says the URL redirected to expired. It's
+    /// forgotten if so, for the next request to follow the redirect again.
+    pub fn expired(&mut self, error: &anyhow::Error) -> bool {
+        let refused = matches!(
+            error.downcast_ref::<DownloadError>(),
+            Some(DownloadError::PresignedExpired { .. })
+                | Some(DownloadError::UnexpectedStatus {
+                    status: StatusCode::FORBIDDEN,
+                    ..
+                })
+        );
+        refused && self.resolved.take().is_some()
+    }
+}
@@ -0,0 +1,353 @@
+use crate::download::speed::Size;
+use std::collections::VecDeque;
+use std::fmt;
+use std::num::NonZeroU16;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// How many segments each worker gets with `--chunk-order`.
+pub const SEGMENTS_PER_WORKER: u16 = 4;
+
+/// How many times a download splits a segment for an idle worker, at most.
+pub const MAX_SPLITS: usize = 64;
+
+/// `--chunk-order`: the order worker mode's connections take segments of
+/// the file in, when there are more segments than connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkOrder {
+    /// From the start of the file to its end, so the start is done first.
+    Sequential,
+    /// Each next one as far from those already taken as it gets, so the
+    /// connections are spread across the file.
+    Spread,
+}
+
+impl FromStr for ChunkOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sequential" => Ok(ChunkOrder::Sequential),
+            "spread" => Ok(ChunkOrder::Spread),
+            other => Err(format!(
+                "unknown chunk order '{other}', expected sequential or spread"
+            )),
+        }
+    }
+}
+
+/// `--resume-order`: the order worker mode takes the segments a resumed
+/// download has left in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResumeOrder {
+    /// From the start of the file to its end, which with
+    /// `--hybrid-streaming` makes the most of the file usable soonest.
+    #[default]
+    Sequential,
+    /// The segments with the most of their range already there first, so
+    /// as many as can be are whole should the download stop again.
+    MostComplete,
+    /// The segments with the most left first, so the longest don't start
+    /// last and hold up the end.
+    LargestRemaining,
+}
+
+impl FromStr for ResumeOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sequential" => Ok(ResumeOrder::Sequential),
+            "most-complete" => Ok(ResumeOrder::MostComplete),
+            "largest-remaining" => Ok(ResumeOrder::LargestRemaining),
+            other => Err(format!(
+                "unknown resume order '{other}', expected most-complete, sequential or largest-remaining"
+            )),
+        }
+    }
+}
+
+/// The most workers `--workers auto` picks.
+pub const AUTO_MAX_WORKERS: u8 = 16;
+
+/// `--workers auto` downloads a file smaller than this in one stream.
+pub const AUTO_SPLIT_FROM: u64 = 10 * 1024 * 1024;
+
+/// About how much of the file each worker `--workers auto` picks gets.
+pub const AUTO_BYTES_PER_WORKER: u64 = 64 * 1024 * 1024;
+
+/// `--workers`: how many connections worker mode splits a download
+/// between, or `auto` to go by the size of the file once the server has
+/// said it accepts ranges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workers {
+    Auto,
+    Count(u8),
+}
+
+impl Workers {
+    /// The most workers this can come to.
+    pub fn most(self) -> u8 {
+        match self {
+            Workers::Auto => AUTO_MAX_WORKERS,
+            Workers::Count(count) => count,
+        }
+    }
+
+    /// Whether this can split a download, as `auto` can.
+    pub fn splits(self) -> bool {
+        self.most() > 1
+    }
+}
+
+impl From<u8> for Workers {
+    fn from(count: u8) -> Self {
+        Workers::Count(count)
+    }
+}
+
+impl FromStr for Workers {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Workers::Auto),
+            count => match count.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "bad worker count '{count}', expected 1 to 255 or auto"
+                )),
+                Ok(count) => Ok(Workers::Count(count)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Workers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Workers::Auto => f.write_str("auto"),
+            Workers::Count(count) => write!(f, "{count}"),
+        }
+    }
+}
+
+/// How many workers a download of a known size takes, and why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub workers: u8,
+    pub asked: Workers,
+    pub size: u64,
+    pub why: SplitReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitReason {
+    /// As many as `--workers` asked for.
+    Asked,
+    /// `--workers auto` went by the size.
+    Auto,
+    /// Fewer bytes than workers asked for.
+    TooSmall,
+    /// More would make parts shorter than `--min-split-size`, this long.
+    MinPartSize(u64),
+}
+
+/// How many of `workers` split a download of `size` bytes, with parts no
+/// shorter than `min_split_size` (0 for no limit) but for one, however
+/// short, when that's all it takes.
+pub fn split(workers: Workers, size: u64, min_split_size: u64) -> Split {
+    let (count, why) = match workers {
+        Workers::Auto if size < AUTO_SPLIT_FROM => (1, SplitReason::Auto),
+        Workers::Auto => (
+            size.div_ceil(AUTO_BYTES_PER_WORKER)
+                .clamp(2, AUTO_MAX_WORKERS.into()),
+            SplitReason::Auto,
+        ),
+        Workers::Count(count) => (u64::from(count.max(1)), SplitReason::Asked),
+    };
+    let (count, why) = match min_split_size {
+        0 => (count, why),
+        min if size / min < count => ((size / min).max(1), SplitReason::MinPartSize(min)),
+        _ => (count, why),
+    };
+    let (count, why) = match size.max(1) < count {
+        true => (size.max(1), SplitReason::TooSmall),
+        false => (count, why),
+    };
+    Split {
+        workers: count as u8,
+        asked: workers,
+        size,
+        why,
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let split = match self.workers {
+            1 => "downloading it in one stream".to_string(),
+            workers => format!("splitting it between {workers} workers"),
+        };
+        match self.why {
+            SplitReason::Asked => write!(f, "The file is {}, {split}", Size(self.size)),
+            SplitReason::Auto => write!(
+                f,
+                "The file is {}, {split} (--workers auto)",
+                Size(self.size)
+            ),
+            SplitReason::TooSmall => write!(
+                f,
+                "The file is only {} bytes, {split} instead of {}",
+                self.size, self.asked
+            ),
+            SplitReason::MinPartSize(min) => write!(
+                f,
+                "The file is {}, too small for parts of --min-split-size {} each, {split} instead of {}",
+                Size(self.size),
+                Size(min),
+                self.asked
+            ),
+        }
+    }
+}
+
+/// The order to download the rest of resumed segments in, given the bytes
+/// `(done, left)` of each, as indexes into them: every index once. Ties
+/// stay in the order of the file.
+pub fn resume_schedule(segments: &[(u64, u64)], order: ResumeOrder) -> Vec<usize> {
+    let mut schedule: Vec<usize> = (0..segments.len()).collect();
+    match order {
+        ResumeOrder::Sequential => {}
+        // The share of each done, compared without rounding.
+        ResumeOrder::MostComplete => schedule.sort_by(|a, b| {
+            let ((done_a, left_a), (done_b, left_b)) = (segments[*a], segments[*b]);
+            let share_a = u128::from(done_a) * u128::from(done_b + left_b);
+            let share_b = u128::from(done_b) * u128::from(done_a + left_a);
+            share_b.cmp(&share_a)
+        }),
+        ResumeOrder::LargestRemaining => {
+            schedule.sort_by_key(|index| std::cmp::Reverse(segments[*index].1))
+        }
+    }
+    schedule
+}
+
+/// The order to download `count` segments in, as indexes into them: every
+/// index once.
+pub fn schedule(count: usize, order: ChunkOrder) -> Vec<usize> {
+    match order {
+        ChunkOrder::Sequential => (0..count).collect(),
+        // The middle of the file, then of each half, then of each quarter.
+        ChunkOrder::Spread => {
+            let mut schedule = Vec::with_capacity(count);
+            let mut gaps = VecDeque::from([(0, count)]);
+            while let Some((start, end)) = gaps.pop_front() {
+                if start == end {
+                    continue;
+                }
+                let middle = start + (end - start) / 2;
+                schedule.push(middle);
+                gaps.push_back((start, middle));
+                gaps.push_back((middle + 1, end));
+            }
+            schedule
+        }
+    }
+}
+
+/// Splits `total` bytes into at most `workers` chunks, in order, from 0 to
+/// `total` with no gaps or overlaps. Every chunk is the same whole multiple
+/// of `min_size` bytes but the last, which takes whatever is left: a
+/// remainder that can be larger, or shorter than `min_size`. Chunks that
+/// would start past the end are left out, so there can be fewer than
+/// `workers`.
+///
+/// Worker mode, its manifest and `--dry-run` all take their ranges from
+/// here, so they always agree.
+pub fn plan_chunks(total: u64, workers: NonZeroU16, min_size: u64) -> Vec<Range<u64>> {
+    let workers = u64::from(workers.get());
+    // At least a byte each, or fewer bytes than workers would make every
+    // chunk empty.
+    let size = (total / workers).max(1).next_multiple_of(min_size.max(1));
+    (0..workers)
+        .map_while(|i| {
+            let start = i.checked_mul(size).filter(|start| *start < total)?;
+            let end = match i == workers - 1 {
+                true => total,
+                false => start.saturating_add(size).min(total),
+            };
+            Some(start..end)
+        })
+        .collect()
+}
+
+/// The inclusive byte ranges worker mode downloads into parts: the
+/// `content_length` bytes after the first `from` split into `segments`,
+/// each a multiple of `alignment` long but the last.
+pub(crate) fn part_ranges(
+    content_length: u64,
+    from: u64,
+    segments: u16,
+    alignment: u64,
+) -> Vec<(u64, u64)> {
+    let segments = NonZeroU16::new(segments).unwrap_or(NonZeroU16::MIN);
+    plan_chunks(content_length.saturating_sub(from), segments, alignment)
+        .into_iter()
+        .map(|chunk| (from + chunk.start, from + chunk.end - 1))
+        .collect()
+}
+
+/// A segment's byte range as a worker downloads it. Once the queue is
+/// empty, an idle worker can [`split`](Self::split) off the second half of
+/// what's left, which the first stops short of.
+#[derive(Debug)]
+pub(crate) struct Segment {
+    pub start: u64,
+    /// The next byte to write, and the last of the segment.
+    claim: Mutex<(u64, u64)>,
+}
+
+impl Segment {
+    pub fn new((start, end): (u64, u64)) -> Self {
+        Self {
+            start,
+            claim: Mutex::new((start, end)),
+        }
+    }
+
+    /// The last byte of the segment, sooner once it's been split.
+    pub fn end(&self) -> u64 {
+        self.claim.lock().unwrap().1
+    }
+
+    /// Bytes of the segment nothing was written to yet.
+    pub fn left(&self) -> u64 {
+        let (next, end) = *self.claim.lock().unwrap();
+        (end + 1).saturating_sub(next)
+    }
+
+    /// Claims the next `len` bytes for writing: how many of them are still
+    /// the segment's.
+    pub fn claim(&self, len: usize) -> usize {
+        let mut claim = self.claim.lock().unwrap();
+        let (next, end) = *claim;
+        let taken = (len as u64).min((end + 1).saturating_sub(next));
+        claim.0 += taken;
+        taken as usize
+    }
+
+    /// Gives up the second half of what's left, if both halves are at least
+    /// `min` bytes: the range an idle worker is to download instead.
+    pub fn split(&self, min: u64) -> Option<(u64, u64)> {
+        let mut claim = self.claim.lock().unwrap();
+        let (next, end) = *claim;
+        let left = (end + 1).saturating_sub(next);
+        if left < min.max(1) * 2 {
+            return None;
+        }
+        let middle = next + left / 2;
+        claim.1 = middle - 1;
+        Some((middle, end))
+    }
+}
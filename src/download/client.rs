@@ -0,0 +1,357 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::connections::CountConnections;
+use crate::download::dns::DnsCache;
+#[cfg(feature = "http3")]
+use crate::download::http;
+use crate::download::progress_handle::{self, PreflightStep};
+use crate::download::proxy::{self, ProxyCredentials};
+use crate::download::tls::{self, SessionLog, TlsVersion};
+use anyhow::{Context, bail};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::redirect;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// The most redirects followed for one request, as many as reqwest's own
+/// policy follows.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// Sent as `User-Agent` unless `--user-agent` says otherwise, so servers
+/// can tell dlm apart in their logs.
+pub const USER_AGENT: &str = concat!("dlm/", env!("CARGO_PKG_VERSION"));
+
+/// How often an idle connection is probed, so one a NAT or firewall dropped
+/// without a word is noticed rather than read from forever.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Address family to use for outgoing connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(&self, addr: &IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Settings shared by every HTTP client the download paths construct, so the
+/// probe, the single-stream request and every chunk request behave the same.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    /// Interface name or literal source IP to bind outgoing connections to.
+    pub interface: Option<String>,
+    pub ip_family: Option<IpFamily>,
+    /// Applied as the blocking client's timeout, which `reqwest` enforces on
+    /// every body read and therefore acts as the stall timeout there.
+    pub stall_timeout: Option<Duration>,
+    /// How long a connection may take to open, TLS included.
+    pub connect_timeout: Option<Duration>,
+    /// `--min-tls`, the oldest version connections offer: see
+    /// [`tls::client_config`].
+    pub min_tls: Option<TlsVersion>,
+    /// How long a response body may go without a byte before the read
+    /// fails with a timeout, which is retried like a lost connection.
+    pub read_timeout: Option<Duration>,
+    /// Connections kept open per host for the next request; one per worker,
+    /// so no chunk opens a new one while another sits idle.
+    pub max_idle_per_host: Option<usize>,
+    /// Speak HTTP/3 only, see [`ClientOptions::negotiate_http3`].
+    #[cfg(feature = "http3")]
+    pub http3: bool,
+    /// Shared with the transfer, which forgets a host's addresses when a
+    /// connection to it dies.
+    pub dns: DnsCache,
+    /// Where the connections' TLS sessions are recorded, shared with the
+    /// transfer, which reports them.
+    pub tls: SessionLog,
+    /// `--proxy`, in place of the environment's proxy variables; see
+    /// [`proxy::for_url`].
+    pub proxy: Option<Url>,
+    /// `--proxy-all`: loopback and link-local hosts go through the proxy
+    /// too, rather than straight to them.
+    pub proxy_all: bool,
+    /// `--auth-token-url`'s tokens, for the requests the client sends
+    /// itself, shared with the transfer.
+    pub auth_token: Option<Arc<AuthToken>>,
+    /// Basic auth for the proxy from the environment.
+    pub proxy_credentials: Option<ProxyCredentials>,
+    /// Sent with every request, from `--header`, `--user` and
+    /// `--bearer-token`.
+    pub headers: HeaderMap,
+    /// `--user-agent`, in place of [`USER_AGENT`]. A `User-Agent` in
+    /// `headers` wins over both.
+    pub user_agent: Option<HeaderValue>,
+}
+
+/// How long an HTTP/3 attempt may take before falling back, so a network
+/// that silently drops UDP doesn't hold up the download.
+#[cfg(feature = "http3")]
+const HTTP3_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When to try HTTP/3.
+#[cfg(feature = "http3")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Http3Mode {
+    /// Upgrade if the server advertises h3 in `Alt-Svc`, falling back on
+    /// failure.
+    AltSvc,
+    /// Try h3 straight away, falling back on failure.
+    PriorKnowledge,
+    /// h3 or nothing (`--http3`).
+    Require,
+}
+
+impl ClientOptions {
+    /// Resolves the source address to bind to, if any.
+    ///
+    /// This also checks that the address can actually be bound, so a bad
+    /// `--interface` is reported before any request is made.
+    pub fn local_address(&self) -> anyhow::Result<Option<IpAddr>> {
+        let addr = match (&self.interface, self.ip_family) {
+            (Some(interface), family) => resolve_interface(interface, family)?,
+            // Binding to the unspecified address pins the socket's family
+            // without choosing a particular interface.
+            (None, Some(IpFamily::V4)) => return Ok(Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+            (None, Some(IpFamily::V6)) => return Ok(Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))),
+            (None, None) => return Ok(None),
+        };
+        UdpSocket::bind((addr, 0))
+            .with_context(|| format!("Cannot bind to source address {addr}"))?;
+        Ok(Some(addr))
+    }
+
+    /// The proxy the client sends a request for `url` through, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        proxy::for_url(url, self.proxy.as_ref(), self.proxy_all)
+    }
+
+    /// The `User-Agent` requests go out with, unless `headers` has one.
+    pub fn user_agent(&self) -> HeaderValue {
+        self.user_agent
+            .clone()
+            .unwrap_or(HeaderValue::from_static(USER_AGENT))
+    }
+
+    /// [`tls::client_config`], or None to leave reqwest to its own where
+    /// that can't be set up, as on a system with no trusted certificates:
+    /// `http://` still works then, and `https://` fails on the certificate.
+    fn tls_config(&self) -> Option<rustls::ClientConfig> {
+        tls::client_config(self.min_tls, &self.tls)
+            .inspect_err(|error| tracing::debug!("Cannot set up TLS: {error}"))
+            .ok()
+    }
+
+    pub fn build_async(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .local_address(self.local_address()?)
+            .dns_resolver(Arc::new(self.dns.clone()))
+            .connector_layer(CountConnections)
+            .redirect(redirect::Policy::custom(follow_redirect))
+            .user_agent(self.user_agent())
+            .default_headers(self.headers.clone())
+            .tcp_keepalive(TCP_KEEPALIVE);
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(read_timeout) = self.read_timeout {
+            builder = builder.read_timeout(read_timeout);
+        }
+        if let Some(config) = self.tls_config() {
+            builder = builder.use_preconfigured_tls(config);
+        }
+        // For the rustls reqwest sets up itself, as for HTTP/3.
+        if let Some(min_tls) = self.min_tls {
+            builder = builder.min_tls_version(min_tls.client_minimum());
+        }
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(proxy) = proxy::proxy(self) {
+            builder = builder.proxy(proxy);
+        }
+        #[cfg(feature = "http3")]
+        let builder = match self.http3 {
+            true => builder.use_rustls_tls().http3_prior_knowledge(),
+            false => builder,
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Builds the blocking client. Like every `reqwest::blocking` client, this
+    /// must not be constructed or dropped on an async worker thread.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(&self) -> anyhow::Result<reqwest::blocking::Client> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .local_address(self.local_address()?)
+            .dns_resolver(Arc::new(self.dns.clone()))
+            .connector_layer(CountConnections)
+            .redirect(redirect::Policy::custom(follow_redirect))
+            .user_agent(self.user_agent())
+            .default_headers(self.headers.clone())
+            .tcp_keepalive(TCP_KEEPALIVE);
+        // The blocking client has one timeout for every read; the shorter
+        // of the two is the one to honor.
+        if let Some(timeout) = self
+            .stall_timeout
+            .into_iter()
+            .chain(self.read_timeout)
+            .min()
+        {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(config) = self.tls_config() {
+            builder = builder.use_preconfigured_tls(config);
+        }
+        // For the rustls reqwest sets up itself, as for HTTP/3.
+        if let Some(min_tls) = self.min_tls {
+            builder = builder.min_tls_version(min_tls.client_minimum());
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(proxy) = proxy::proxy(self) {
+            builder = builder.proxy(proxy);
+        }
+        #[cfg(feature = "http3")]
+        if self.http3 {
+            builder = builder.use_rustls_tls().http3_prior_knowledge();
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Works out whether `url` can be fetched over HTTP/3 and switches these
+    /// options to it if so, returning whether it did. Requests only go out
+    /// as HTTP/3 once [`http::use_http3`] is called as well.
+    ///
+    /// QUIC runs over UDP, which some networks block, so unless `mode` is
+    /// [`Http3Mode::Require`] a failed attempt falls back to HTTP/2 or 1.1.
+    #[cfg(feature = "http3")]
+    pub async fn negotiate_http3(
+        &mut self,
+        url: &url::Url,
+        mode: Http3Mode,
+    ) -> anyhow::Result<bool> {
+        if url.scheme() != "https" {
+            if mode == Http3Mode::Require {
+                bail!("HTTP/3 needs an https URL");
+            }
+            return Ok(false);
+        }
+        if mode == Http3Mode::AltSvc {
+            let client = self.build_async()?;
+            // A failure here is the download's to report.
+            let Ok(response) =
+                http::send(client.head(url.as_str()), None, self.auth_token.as_deref()).await
+            else {
+                return Ok(false);
+            };
+            let advertised = response
+                .headers()
+                .get_all(reqwest::header::ALT_SVC)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| http::advertises_h3(value, response.url()));
+            if !advertised {
+                return Ok(false);
+            }
+        }
+
+        let h3 = ClientOptions {
+            http3: true,
+            ..self.clone()
+        };
+        let probe = async {
+            let client = h3.build_async()?;
+            let request = client.head(url.as_str()).version(reqwest::Version::HTTP_3);
+            anyhow::Ok(http::send(request, None, h3.auth_token.as_deref()).await?)
+        };
+        let error = match tokio::time::timeout(HTTP3_PROBE_TIMEOUT, probe).await {
+            Ok(Ok(_)) => {
+                self.http3 = true;
+                return Ok(true);
+            }
+            Ok(Err(error)) => error,
+            Err(_) => anyhow::anyhow!("no QUIC response within {HTTP3_PROBE_TIMEOUT:?}"),
+        };
+        if mode == Http3Mode::Require {
+            return Err(error.context("HTTP/3 connection failed"));
+        }
+        tracing::info!("HTTP/3 connection failed ({error:#}), falling back to HTTP/2 or 1.1");
+        Ok(false)
+    }
+}
+
+/// reqwest's own policy, saying which redirect the download is following.
+fn follow_redirect(attempt: redirect::Attempt) -> redirect::Action {
+    // The URLs asked for so far, the first one included.
+    let redirect = attempt.previous().len();
+    if redirect > MAX_REDIRECTS {
+        return attempt.error(format!("too many redirects, stopped after {MAX_REDIRECTS}"));
+    }
+    progress_handle::report_preflight(PreflightStep::FollowingRedirect {
+        redirect,
+        max: MAX_REDIRECTS,
+    });
+    attempt.follow()
+}
+
+fn resolve_interface(interface: &str, family: Option<IpFamily>) -> anyhow::Result<IpAddr> {
+    if let Ok(addr) = interface.parse::<IpAddr>() {
+        if let Some(family) = family.filter(|family| !family.matches(&addr)) {
+            bail!("Source address {addr} does not match the requested {family:?} family");
+        }
+        return Ok(addr);
+    }
+
+    let candidates = interface_addresses(interface)?;
+    if candidates.is_empty() {
+        bail!("Interface '{interface}' not found or has no addresses");
+    }
+    // Link-local IPv6 addresses need a scope id that reqwest can't carry, so
+    // prefer anything routable.
+    candidates
+        .iter()
+        .filter(|addr| family.is_none_or(|family| family.matches(addr)))
+        .min_by_key(|addr| is_link_local(addr))
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("Interface '{interface}' has no {family:?} address"))
+}
+
+fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+#[cfg(unix)]
+fn interface_addresses(name: &str) -> anyhow::Result<Vec<IpAddr>> {
+    let addrs = nix::ifaddrs::getifaddrs().context("Could not list network interfaces")?;
+    Ok(addrs
+        .filter(|ifaddr| ifaddr.interface_name == name)
+        .filter_map(|ifaddr| {
+            let address = ifaddr.address?;
+            if let Some(v4) = address.as_sockaddr_in() {
+                Some(IpAddr::V4(v4.ip()))
+            } else {
+                address.as_sockaddr_in6().map(|v6| IpAddr::V6(v6.ip()))
+            }
+        })
+        .collect())
+}
+
+#[cfg(not(unix))]
+fn interface_addresses(name: &str) -> anyhow::Result<Vec<IpAddr>> {
+    bail!("Interface names are only supported on Unix, pass a source IP instead of '{name}'")
+}
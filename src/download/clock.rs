@@ -0,0 +1,114 @@
+//! How far this machine's clock is from the servers', going by the `Date`
+//! of every response. File times are set by the local clock and compared
+//! with the server's `Last-Modified`, so a clock that's off makes a file
+//! look newer or older than it is: one set to the future skips every
+//! update.
+
+use crate::download::diagnostics::{self, WarningId};
+use reqwest::header::{self, HeaderMap};
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Skews under this are left alone: `Date` only has whole seconds, and
+/// a response can take a while to arrive.
+pub const THRESHOLD: Duration = Duration::from_secs(5 * 60);
+
+/// The last skew measured.
+static SKEW: Mutex<Option<Skew>> = Mutex::new(None);
+
+/// Whether the skew was warned about yet.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// How far the server's clock is ahead of this machine's, in seconds;
+/// negative when it's behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Skew(i64);
+
+impl Skew {
+    /// The skew between a server saying it's `server` when it's `local` here,
+    /// to the nearest second.
+    pub fn between(server: SystemTime, local: SystemTime) -> Self {
+        let seconds = |by: Duration| ((by.as_millis() + 500) / 1000) as i64;
+        match server.duration_since(local) {
+            Ok(ahead) => Self(seconds(ahead)),
+            Err(behind) => Self(-seconds(behind.duration())),
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        self.0
+    }
+
+    /// Whether it's past [`THRESHOLD`], and so worth correcting for.
+    pub fn significant(self) -> bool {
+        self.0.unsigned_abs() > THRESHOLD.as_secs()
+    }
+
+    /// What the server's clock said at `local` on this machine's.
+    pub fn to_server(self, local: SystemTime) -> SystemTime {
+        let by = Duration::from_secs(self.0.unsigned_abs());
+        match self.0 >= 0 {
+            true => local + by,
+            false => local - by,
+        }
+    }
+}
+
+impl fmt::Display for Skew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.unsigned_abs();
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if hours > 0 {
+            write!(f, "{hours}h ")?;
+        }
+        if hours > 0 || minutes > 0 {
+            write!(f, "{minutes}m ")?;
+        }
+        let direction = match self.0 >= 0 {
+            true => "ahead of",
+            false => "behind",
+        };
+        write!(f, "{seconds}s {direction}")
+    }
+}
+
+/// Measures the skew from a response's `Date`, warning the first time it's
+/// significant.
+pub(crate) fn observe(headers: &HeaderMap) {
+    let Some(date) = headers
+        .get(header::DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+    else {
+        return;
+    };
+    let skew = Skew::between(date, SystemTime::now());
+    let previous = SKEW.lock().unwrap().replace(skew);
+    if previous.is_none() {
+        tracing::info!("The server's clock is {skew} this machine's");
+    }
+    if skew.significant() && !WARNED.swap(true, Ordering::Relaxed) {
+        diagnostics::warn(
+            WarningId::ClockSkew,
+            format!(
+                "The server's clock is {skew} this machine's; correcting local file times by as much"
+            ),
+        );
+    }
+}
+
+/// The last skew measured, if any response had a `Date`.
+pub fn skew() -> Option<Skew> {
+    *SKEW.lock().unwrap()
+}
+
+/// `local`, a time by this machine's clock, by the server's, when the two
+/// are far enough apart to matter.
+pub fn to_server_time(local: SystemTime) -> SystemTime {
+    match skew() {
+        Some(skew) if skew.significant() => skew.to_server(local),
+        _ => local,
+    }
+}
@@ -0,0 +1,311 @@
+use crate::download::async_range::fetch_range_bytes;
+use crate::download::auth_token::AuthToken;
+use crate::download::checksum::Algorithm;
+use crate::download::http;
+use crate::download::progress::TransferProgress;
+use crate::download::remote::probe_remote;
+use crate::download::retry_budget::RetryBudget;
+use anyhow::bail;
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use url::Url;
+
+/// How much of the two files [`compare_mirrors`] looks at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// `count` ranges of `size` bytes at random offsets, the first and last
+    /// bytes of the file always among them.
+    Ranges { count: usize, size: u64 },
+    /// Every byte, both files streamed side by side.
+    Full,
+}
+
+/// What one of the mirrors says about its copy.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq, Deserialize, JsonSchema)]
+pub struct Mirror {
+    /// The URL, with any credentials or signature redacted.
+    pub url: String,
+    pub size: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Whether it answers range requests.
+    pub ranges: bool,
+}
+
+/// Where two mirrors' copies first differ.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq, Deserialize, JsonSchema)]
+pub struct Divergence {
+    pub offset: u64,
+    pub reason: String,
+}
+
+/// What [`compare_mirrors`] found.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq, Deserialize, JsonSchema)]
+pub struct Comparison {
+    pub a: Mirror,
+    pub b: Mirror,
+    /// Whether every byte was compared rather than samples.
+    pub full: bool,
+    /// Bytes compared.
+    pub compared: u64,
+    pub identical: bool,
+    /// The first difference found, the lowest offset among the samples when
+    /// sampling.
+    pub divergence: Option<Divergence>,
+    /// SHA-256 of the file both serve, once every byte matched.
+    pub sha256: Option<String>,
+}
+
+/// Checks whether the mirrors at `a` and `b` serve the same file, reading
+/// only. The sizes, ETags and Last-Modified dates come from
+/// [`probe_remote`], then the bytes `sampling` picks are compared. A server
+/// without range support, or one not saying how big the file is, gets
+/// every byte compared instead. A 5xx is retried while `budget` allows.
+pub async fn compare_mirrors(
+    client: &reqwest::Client,
+    a: &Url,
+    b: &Url,
+    sampling: Sampling,
+    progress: TransferProgress,
+    budget: &Arc<RetryBudget>,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Comparison> {
+    progress.set_retry_budget(budget);
+    let reporter = progress.clone();
+    let result = compare(client, a, b, sampling, progress, budget, auth).await;
+    reporter.finish_with(&result);
+    result
+}
+
+async fn compare(
+    client: &reqwest::Client,
+    a: &Url,
+    b: &Url,
+    sampling: Sampling,
+    progress: TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Comparison> {
+    let (mirror_a, mirror_b) = tokio::try_join!(
+        probe(client, a, budget, auth),
+        probe(client, b, budget, auth)
+    )?;
+    let sizes = mirror_a.size.zip(mirror_b.size);
+    let sampled = match (sampling, sizes) {
+        (Sampling::Ranges { count, size }, Some((size_a, size_b)))
+            if mirror_a.ranges && mirror_b.ranges =>
+        {
+            Some((count, size, size_a, size_b))
+        }
+        (Sampling::Ranges { .. }, _) => {
+            tracing::info!(
+                "A mirror doesn't support range requests or didn't say how big the file is, comparing every byte"
+            );
+            None
+        }
+        (Sampling::Full, _) => None,
+    };
+
+    let (compared, divergence, sha256) = match sampled {
+        Some((count, sample_size, size_a, size_b)) => {
+            let (compared, divergence) = compare_samples(
+                client,
+                [a, b],
+                [size_a, size_b],
+                count,
+                sample_size,
+                &progress,
+                budget,
+                auth,
+            )
+            .await?;
+            (compared, divergence, None)
+        }
+        None => {
+            if let Some(size) = mirror_a.size.or(mirror_b.size) {
+                progress.set_total(size);
+            }
+            compare_streams(client, a, b, &progress, budget, auth).await?
+        }
+    };
+    Ok(Comparison {
+        a: mirror_a,
+        b: mirror_b,
+        full: sampled.is_none(),
+        compared,
+        identical: divergence.is_none(),
+        divergence,
+        sha256,
+    })
+}
+
+/// What the mirror at `url` says about the file.
+async fn probe(
+    client: &reqwest::Client,
+    url: &Url,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Mirror> {
+    let remote = probe_remote(client, url, budget, auth).await?;
+    Ok(Mirror {
+        url: http::redact_url(url),
+        size: remote.size,
+        etag: remote.etag,
+        last_modified: remote.last_modified,
+        ranges: remote.ranges,
+    })
+}
+
+/// Compares the ranges [`sample_offsets`] picks, up to the end of the
+/// shorter file, then the sizes. Returns the bytes compared and the first
+/// difference.
+#[allow(clippy::too_many_arguments)]
+async fn compare_samples(
+    client: &reqwest::Client,
+    [a, b]: [&Url; 2],
+    [size_a, size_b]: [u64; 2],
+    count: usize,
+    sample_size: u64,
+    progress: &TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<(u64, Option<Divergence>)> {
+    let size = size_a.min(size_b);
+    let sample_size = sample_size.max(1);
+    let offsets = sample_offsets(size, count, sample_size);
+    progress.set_total((offsets.len() as u64 * sample_size).min(size));
+    let mut compared = 0;
+    // Samples may overlap; each byte is only fetched once.
+    let mut next = 0;
+    for offset in offsets {
+        if progress.interrupted.load(Ordering::SeqCst) {
+            bail!("Comparison interrupted.");
+        }
+        let start = offset.max(next);
+        let end = offset.saturating_add(sample_size).min(size) - 1;
+        if start > end {
+            continue;
+        }
+        let (data_a, data_b) = tokio::try_join!(
+            fetch_range_bytes(client, a, start, end, budget, auth),
+            fetch_range_bytes(client, b, start, end, budget, auth)
+        )?;
+        if let Some(index) = first_difference(&data_a, &data_b) {
+            let offset = start + index as u64;
+            return Ok((
+                compared + index as u64,
+                Some(Divergence {
+                    offset,
+                    reason: "content differs".to_string(),
+                }),
+            ));
+        }
+        compared += end - start + 1;
+        next = end + 1;
+        progress.set_downloaded(compared as usize);
+    }
+    let divergence = (size_a != size_b).then(|| Divergence {
+        offset: size,
+        reason: format!("A is {size_a} bytes and B is {size_b}"),
+    });
+    Ok((compared, divergence))
+}
+
+/// Where `count` ranges of `sample_size` bytes start in a file of `size`,
+/// in order: the first and last range and random ones between.
+fn sample_offsets(size: u64, count: usize, sample_size: u64) -> Vec<u64> {
+    if size == 0 {
+        return Vec::new();
+    }
+    let last = size.saturating_sub(sample_size);
+    // Random enough for picking samples, without a crate for it.
+    let random = std::collections::hash_map::RandomState::new();
+    let mut offsets: Vec<u64> = [0, last]
+        .into_iter()
+        .chain((0..count.saturating_sub(2)).map(|index| random.hash_one(index) % (last + 1)))
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+/// Streams both files side by side until they differ or end. Returns the
+/// bytes compared, the first difference, and the SHA-256 of the file when
+/// there was none.
+async fn compare_streams(
+    client: &reqwest::Client,
+    a: &Url,
+    b: &Url,
+    progress: &TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<(u64, Option<Divergence>, Option<String>)> {
+    let get = |url: &Url| http::send_retrying(client.get(url.clone()), None, budget, auth);
+    let (response_a, response_b) = tokio::try_join!(get(a), get(b))?;
+    let (response_a, response_b) = tokio::try_join!(
+        http::check_status(response_a),
+        http::check_status(response_b)
+    )?;
+    let mut streams = [response_a.bytes_stream(), response_b.bytes_stream()];
+    let mut pending = [bytes::Bytes::new(), bytes::Bytes::new()];
+    let mut ended = [false, false];
+    let mut hasher = Algorithm::Sha256.hasher();
+    let mut offset = 0;
+    loop {
+        if progress.interrupted.load(Ordering::SeqCst) {
+            bail!("Comparison interrupted.");
+        }
+        for side in 0..2 {
+            while pending[side].is_empty() && !ended[side] {
+                match streams[side].next().await.transpose()? {
+                    Some(chunk) => pending[side] = chunk,
+                    None => ended[side] = true,
+                }
+            }
+        }
+        let divergence = |reason: String| Divergence { offset, reason };
+        match (pending[0].is_empty(), pending[1].is_empty()) {
+            (true, true) => return Ok((offset, None, Some(hex::encode(hasher.finalize())))),
+            (true, false) => {
+                return Ok((
+                    offset,
+                    Some(divergence(format!("A ends at {offset} bytes"))),
+                    None,
+                ));
+            }
+            (false, true) => {
+                return Ok((
+                    offset,
+                    Some(divergence(format!("B ends at {offset} bytes"))),
+                    None,
+                ));
+            }
+            (false, false) => {}
+        }
+        let length = pending[0].len().min(pending[1].len());
+        let [data_a, data_b] = [0, 1].map(|side| pending[side].split_to(length));
+        if let Some(index) = first_difference(&data_a, &data_b) {
+            let offset = offset + index as u64;
+            return Ok((
+                offset,
+                Some(Divergence {
+                    offset,
+                    reason: "content differs".to_string(),
+                }),
+                None,
+            ));
+        }
+        hasher.update(&data_a);
+        offset += length as u64;
+        progress.set_downloaded(offset as usize);
+    }
+}
+
+/// Index of the first byte where `a` and `b` differ.
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    a.iter().zip(b).position(|(a, b)| a != b)
+}
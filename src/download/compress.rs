@@ -0,0 +1,172 @@
+use crate::download::speed::Size;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// `--store-compressed`: the format, and level, a download is stored in
+/// while the server sends it as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub format: Format,
+    pub level: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Format {
+    /// The levels it takes, and the one used when none is given.
+    fn levels(self) -> (std::ops::RangeInclusive<i32>, i32) {
+        match self {
+            Format::Gzip => (0..=9, 6),
+            #[cfg(feature = "zstd")]
+            Format::Zstd => (1..=22, 3),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match value.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (value, None),
+        };
+        let format = match name {
+            "zstd" if !cfg!(feature = "zstd") => {
+                return Err("zstd needs dlm built with the zstd feature".to_string());
+            }
+            #[cfg(feature = "zstd")]
+            "zstd" => Format::Zstd,
+            "gzip" => Format::Gzip,
+            other => return Err(format!("unknown format '{other}', expected gzip or zstd")),
+        };
+        let (levels, default) = format.levels();
+        let level = match level {
+            Some(level) => level
+                .parse()
+                .ok()
+                .filter(|level| levels.contains(level))
+                .ok_or_else(|| {
+                    format!(
+                        "'{level}' is not a {name} level, expected {} to {}",
+                        levels.start(),
+                        levels.end()
+                    )
+                })?,
+            None => default,
+        };
+        Ok(Self { format, level })
+    }
+}
+
+impl Compression {
+    /// What the stored file's name gets, after its own extension.
+    pub fn extension(&self) -> &'static str {
+        match self.format {
+            Format::Gzip => "gz",
+            #[cfg(feature = "zstd")]
+            Format::Zstd => "zst",
+        }
+    }
+
+    pub fn compressor(&self) -> io::Result<Compressor> {
+        let encoder = match self.format {
+            Format::Gzip => Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(self.level as u32),
+            )),
+            #[cfg(feature = "zstd")]
+            Format::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), self.level)?)
+            }
+        };
+        Ok(Compressor {
+            encoder,
+            hasher: Sha256::new(),
+            stored: Stored::default(),
+        })
+    }
+}
+
+/// A streaming compressor that hands back what it produced, so the caller
+/// writes it to the file the way it writes anything else. It hashes what
+/// goes in, the file on disk being no use for that.
+pub struct Compressor {
+    encoder: Encoder,
+    hasher: Sha256,
+    stored: Stored,
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+    /// Compresses `raw`, returning whatever compressed bytes are ready,
+    /// often none.
+    pub fn compress(&mut self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match &mut self.encoder {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(raw)?;
+                std::mem::take(encoder.get_mut())
+            }
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(raw)?;
+                std::mem::take(encoder.get_mut())
+            }
+        };
+        self.hasher.update(raw);
+        self.stored.raw += raw.len() as u64;
+        self.stored.compressed += output.len() as u64;
+        Ok(output)
+    }
+
+    /// Ends the stream, returning its last bytes, the totals and the
+    /// SHA-256 of the content.
+    pub fn finish(self) -> io::Result<(Vec<u8>, Stored)> {
+        let output = match self.encoder {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        let mut stored = self.stored;
+        stored.compressed += output.len() as u64;
+        stored.sha256 = self.hasher.finalize().into();
+        Ok((output, stored))
+    }
+}
+
+/// How much went into a [`Compressor`] and how much came out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stored {
+    pub raw: u64,
+    pub compressed: u64,
+    /// Of the raw content, once finished.
+    pub sha256: [u8; 32],
+}
+
+impl fmt::Display for Stored {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} raw, stored as {}",
+            Size(self.raw),
+            Size(self.compressed)
+        )?;
+        if let Some(percent) = (self.compressed * 100).checked_div(self.raw) {
+            write!(f, " ({percent}%)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Two URLs in one batch that would be saved to the same file: both end in
+//! `/download`, or name the same file on different hosts. The batch is
+//! planned before anything is downloaded, so the later entry can be
+//! renamed, skipped, or the batch refused, per `--on-conflict`. Names are
+//! compared ignoring case, since `File.bin` and `file.bin` are the same
+//! file on a case-insensitive filesystem.
+//!
+//! The same goes for `--resume` onto a file that's already there: two
+//! versions of a release share a name, and carrying on from the older one
+//! would append the newer one's tail to it. [`resume_conflict`] checks the
+//! file can be the start of the URL's before it's resumed.
+
+use crate::download::async_range::fetch_range_bytes;
+use crate::download::auth_token::AuthToken;
+use crate::download::remote::probe_remote;
+use crate::download::retry_budget::RetryBudget;
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+/// How much of the end of a file [`resume_conflict`] compares.
+const COMPARED: u64 = 64 * 1024;
+
+/// `--on-conflict`: what to do with an entry whose destination an earlier
+/// entry already has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Save it as `name (2).ext`, or the next free number.
+    Rename,
+    /// Leave it out of the batch.
+    Skip,
+    /// Refuse the batch before downloading anything.
+    Error,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "rename" => Ok(OnConflict::Rename),
+            "skip" => Ok(OnConflict::Skip),
+            "error" => Ok(OnConflict::Error),
+            other => Err(format!(
+                "unknown action '{other}', expected rename, skip or error"
+            )),
+        }
+    }
+}
+
+/// What [`plan`] decided for one entry of a batch.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Planned {
+    /// Its destination is its own.
+    Keep,
+    /// Renamed to this path, its destination being that of an earlier
+    /// entry, by index.
+    Renamed {
+        path: PathBuf,
+        conflicts_with: usize,
+    },
+    /// Its destination is that of an earlier entry, by index, and it's
+    /// left out or refused.
+    Conflict { conflicts_with: usize },
+}
+
+/// Plans a batch whose entries would be saved to `destinations`, in order.
+/// The first entry to claim a destination keeps it; every later one is
+/// renamed with `on_conflict` [`OnConflict::Rename`], and otherwise marked
+/// a conflict. A new name is never one another entry would have.
+pub fn plan(destinations: &[PathBuf], on_conflict: OnConflict) -> Vec<Planned> {
+    let mut claimed: HashMap<String, usize> = HashMap::new();
+    for (index, destination) in destinations.iter().enumerate() {
+        claimed.entry(key(destination)).or_insert(index);
+    }
+    destinations
+        .iter()
+        .enumerate()
+        .map(|(index, destination)| {
+            let first = claimed[&key(destination)];
+            if first == index {
+                return Planned::Keep;
+            }
+            match on_conflict {
+                OnConflict::Rename => {
+                    let path = (2..)
+                        .map(|number| numbered(destination, number))
+                        .find(|path| !claimed.contains_key(&key(path)))
+                        .expect("a free number");
+                    claimed.insert(key(&path), index);
+                    Planned::Renamed {
+                        path,
+                        conflicts_with: first,
+                    }
+                }
+                OnConflict::Skip | OnConflict::Error => Planned::Conflict {
+                    conflicts_with: first,
+                },
+            }
+        })
+        .collect()
+}
+
+/// The first of `path`, `name (2).ext`, `name (3).ext`… nothing is saved
+/// at yet.
+pub fn unused(path: &Path) -> PathBuf {
+    (2..)
+        .map(|number| numbered(path, number))
+        .find(|path| !path.exists())
+        .expect("a free number")
+}
+
+/// Why `path` can't be the start of the file at `url`, or `None` if it can:
+/// its last 64 KiB match the same bytes of the remote file. One that can't
+/// be resumed from anyway, bigger than the remote file or from a server
+/// that can't send a range, isn't compared and is `None` too.
+pub async fn resume_conflict(
+    client: &reqwest::Client,
+    url: &Url,
+    path: &Path,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Option<String>> {
+    let size = tokio::fs::metadata(path).await?.len();
+    let remote = probe_remote(client, url, budget, auth).await?;
+    let (Some(total), true) = (remote.size, remote.ranges) else {
+        return Ok(None);
+    };
+    // A bigger one can't be resumed either way; that's refused, or
+    // restarted, like one whose server ignores ranges.
+    if size == 0 || size > total {
+        return Ok(None);
+    }
+    let start = size - COMPARED.min(size);
+    let expected = fetch_range_bytes(client, url, start, size - 1, budget, auth).await?;
+    let mut local = vec![0; (size - start) as usize];
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    file.read_exact(&mut local).await?;
+    Ok((local != expected)
+        .then(|| format!("bytes {start}-{} differ from the remote file", size - 1)))
+}
+
+/// What two paths naming the same file on a case-insensitive filesystem
+/// have in common.
+fn key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// `path` with ` (number)` before the extension: everything from the first
+/// dot of the file name, so `a.tar.gz` becomes `a (2).tar.gz`.
+fn numbered(path: &Path, number: u32) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, extension) = match name.find('.').filter(|dot| *dot > 0) {
+        Some(dot) => name.split_at(dot),
+        None => (name.as_ref(), ""),
+    };
+    path.with_file_name(format!("{stem} ({number}){extension}"))
+}
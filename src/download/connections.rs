@@ -0,0 +1,146 @@
+//! How many connections a run opened against how many requests it sent,
+//! and warming connections up for a worker download.
+//!
+//! Every connection either client opens is counted as it's established,
+//! through a connector layer, so a handshake (TCP, and TLS for https) is
+//! one opened connection; requests that didn't need one went out on a
+//! pooled connection. HTTP/3 connections aren't made by that connector,
+//! and aren't counted.
+
+use crate::download::auth_token::AuthToken;
+use crate::download::http;
+use crate::download::progress_handle::{self, PreflightStep};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+use url::Url;
+
+/// The most connections [`warm_up`] opens, however many workers there are.
+pub const MAX_WARM_UP: usize = 8;
+
+static OPENED: AtomicU64 = AtomicU64::new(0);
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Connections opened and requests sent so far in the run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionUsage {
+    pub opened: u64,
+    pub requests: u64,
+}
+
+impl ConnectionUsage {
+    /// Requests that went out on a connection an earlier one opened.
+    pub fn reused(&self) -> u64 {
+        self.requests.saturating_sub(self.opened)
+    }
+
+    /// [`reused`](Self::reused) as a percentage of the requests.
+    pub fn reuse_percent(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.reused() as f64 * 100.0 / requests as f64,
+        }
+    }
+}
+
+pub fn usage() -> ConnectionUsage {
+    ConnectionUsage {
+        opened: OPENED.load(Ordering::Relaxed),
+        requests: REQUESTS.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn count_request() {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Opens `min(workers, MAX_WARM_UP)` connections to `url` at once, with
+/// one-byte range requests, and leaves them in `client`'s pool, so every
+/// worker starts on an established connection instead of each waiting on
+/// its own handshake. A worker download never has more connections open
+/// to the host than it has workers, and this opens no more. Returns how
+/// many answered; one that fails only means a worker connects itself.
+pub(crate) async fn warm_up(
+    client: &reqwest::Client,
+    url: &Url,
+    workers: usize,
+    auth: Option<&AuthToken>,
+) -> usize {
+    let count = workers.min(MAX_WARM_UP);
+    if count < 2 {
+        return 0;
+    }
+    let requests = (0..count).map(|_| async {
+        let request = client
+            .get(url.clone())
+            .headers(http::range_headers("bytes=0-0"));
+        let response = http::send(request, None, auth).await?.error_for_status()?;
+        // Read to the end, or the connection can't go back to the pool;
+        // unless it's the whole file, when it's not worth keeping.
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => response.bytes().await.map(|_| true),
+            _ => Ok(false),
+        }
+    });
+    let warmed = futures::future::join_all(requests)
+        .await
+        .into_iter()
+        .filter(|result| match result {
+            Ok(warmed) => *warmed,
+            Err(error) => {
+                tracing::debug!("Warming up a connection failed: {error}");
+                false
+            }
+        })
+        .count();
+    tracing::info!("Warmed up {warmed} of {count} connections");
+    warmed
+}
+
+/// A connector layer counting the connections established through it.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CountConnections;
+
+impl<S> Layer<S> for CountConnections {
+    type Service = Counted<S>;
+
+    fn layer(&self, inner: S) -> Counted<S> {
+        Counted(inner)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Counted<S>(S);
+
+impl<S, R> Service<R> for Counted<S>
+where
+    S: Service<R>,
+    S::Response: 'static,
+    S::Error: 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        progress_handle::report_preflight(PreflightStep::Connecting);
+        let connecting = self.0.call(request);
+        Box::pin(async move {
+            let connection = connecting.await?;
+            OPENED.fetch_add(1, Ordering::Relaxed);
+            progress_handle::report_preflight(PreflightStep::AwaitingResponse);
+            Ok(connection)
+        })
+    }
+}
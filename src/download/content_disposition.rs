@@ -0,0 +1,84 @@
+//! The file name a server gives a download in `Content-Disposition`, which
+//! download scripts and presigned URLs rely on since their URLs end in
+//! `download?id=12345` or a UUID. Both forms are understood: `filename=`,
+//! quoted or not, and RFC 5987's `filename*=UTF-8''...`, which wins when a
+//! server sends both.
+
+use crate::download::utils;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{self, HeaderMap};
+
+/// The name `headers`' `Content-Disposition` gives the file, made safe to
+/// save inside the target directory, if it gives one.
+pub fn file_name(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::CONTENT_DISPOSITION)?;
+    let value = String::from_utf8_lossy(value.as_bytes());
+    let mut plain = None;
+    let mut extended = None;
+    // The first parameter is the disposition type itself.
+    for parameter in split(&value).into_iter().skip(1) {
+        let Some((name, value)) = parameter.split_once('=') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename" => plain = Some(unquote(value.trim())),
+            "filename*" => extended = decode_extended(value.trim()),
+            _ => {}
+        }
+    }
+    extended
+        .or(plain)
+        .and_then(|name| utils::safe_file_name(&name))
+}
+
+/// `value`'s parameters, split at the semicolons outside quotes.
+fn split(value: &str) -> Vec<&str> {
+    let mut parameters = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (at, char) in value.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parameters.push(&value[start..at]);
+                start = at + 1;
+            }
+            _ => {}
+        }
+    }
+    parameters.push(&value[start..]);
+    parameters
+}
+
+/// A quoted string's content, backslash escapes undone, or a token as is.
+fn unquote(value: &str) -> String {
+    let Some(quoted) = value
+        .strip_prefix('"')
+        .map(|value| value.strip_suffix('"').unwrap_or(value))
+    else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => unquoted.extend(chars.next()),
+            char => unquoted.push(char),
+        }
+    }
+    unquoted
+}
+
+/// An RFC 5987 value, `charset'language'percent-encoded`, in UTF-8 or
+/// ISO-8859-1; `None` in any other charset.
+fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes: Vec<u8> = percent_decode_str(encoded).collect();
+    match charset.to_ascii_lowercase().as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
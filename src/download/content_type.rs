@@ -0,0 +1,147 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::error::DownloadError;
+use crate::download::progress_handle::ProgressReporter;
+use reqwest::header::{self, HeaderMap};
+use std::path::Path;
+
+/// Content types each extension may be served as. A type that shows up
+/// here for another extension contradicts a name that isn't listed with it;
+/// types that aren't here at all are never held against a name.
+const TYPES: &[(&[&str], &[&str])] = &[
+    (&["7z"], &["application/x-7z-compressed"]),
+    (&["apk"], &["application/vnd.android.package-archive"]),
+    (&["bz2"], &["application/x-bzip2", "application/x-bzip"]),
+    (
+        &["deb"],
+        &[
+            "application/vnd.debian.binary-package",
+            "application/x-debian-package",
+        ],
+    ),
+    (&["dmg"], &["application/x-apple-diskimage"]),
+    (
+        &["exe", "msi"],
+        &[
+            "application/x-msdownload",
+            "application/x-msdos-program",
+            "application/x-msi",
+            "application/vnd.microsoft.portable-executable",
+        ],
+    ),
+    (&["gif"], &["image/gif"]),
+    (
+        &["gz", "tgz"],
+        &[
+            "application/gzip",
+            "application/x-gzip",
+            "application/x-tar",
+            "application/x-gtar",
+            "application/x-compressed-tar",
+        ],
+    ),
+    (&["htm", "html"], &["text/html", "application/xhtml+xml"]),
+    (
+        &["iso"],
+        &["application/x-iso9660-image", "application/x-cd-image"],
+    ),
+    (&["jar"], &["application/java-archive"]),
+    (&["jpg", "jpeg"], &["image/jpeg"]),
+    (&["json"], &["application/json"]),
+    (&["mkv"], &["video/x-matroska"]),
+    (&["mov"], &["video/quicktime"]),
+    (&["mp3"], &["audio/mpeg"]),
+    (&["mp4"], &["video/mp4"]),
+    (&["pdf"], &["application/pdf"]),
+    (&["png"], &["image/png"]),
+    (
+        &["rar"],
+        &["application/vnd.rar", "application/x-rar-compressed"],
+    ),
+    (
+        &["rpm"],
+        &["application/x-rpm", "application/x-redhat-package-manager"],
+    ),
+    (&["tar"], &["application/x-tar", "application/x-gtar"]),
+    (&["txt"], &["text/plain"]),
+    (&["csv"], &["text/csv", "text/plain"]),
+    (&["webm"], &["video/webm"]),
+    (&["webp"], &["image/webp"]),
+    (&["xml"], &["application/xml", "text/xml"]),
+    (&["xz"], &["application/x-xz"]),
+    (
+        &["zip", "whl"],
+        &["application/zip", "application/x-zip-compressed"],
+    ),
+    (&["zst"], &["application/zstd", "application/x-zstd"]),
+];
+
+/// The content types `path`'s extension may be served as, if it's one this
+/// knows.
+pub fn expected_types(path: &Path) -> Option<&'static [&'static str]> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    TYPES
+        .iter()
+        .find(|(extensions, _)| extensions.contains(&extension.as_str()))
+        .map(|(_, types)| *types)
+}
+
+/// Why a response served as `content_type` can't be the file `path` names,
+/// e.g. `text/html` for an `.iso`. Generic types like
+/// `application/octet-stream`, and types this doesn't know, never are.
+pub fn contradiction(path: &Path, content_type: &str) -> Option<String> {
+    let served = essence(content_type);
+    let expected = expected_types(path)?;
+    let known = TYPES
+        .iter()
+        .any(|(_, types)| types.contains(&served.as_str()));
+    if !known || expected.contains(&served.as_str()) {
+        return None;
+    }
+    let extension = path.extension()?.to_string_lossy();
+    Some(format!(
+        "served as {served}, but named like a .{extension} file"
+    ))
+}
+
+/// Compares the type a response was served as with `path`'s extension,
+/// before anything is written to `path`. A contradiction is logged and
+/// recorded on `reporter`, or with `strict` fails the download.
+pub(crate) fn check(
+    path: &Path,
+    headers: &HeaderMap,
+    strict: bool,
+    reporter: &ProgressReporter,
+) -> Result<(), DownloadError> {
+    let Some(mismatch) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| contradiction(path, content_type))
+    else {
+        return Ok(());
+    };
+    if strict || diagnostics::is_strict(WarningId::ContentTypeMismatch) {
+        return Err(DownloadError::ContentTypeMismatch {
+            path: path.to_path_buf(),
+            mismatch,
+        });
+    }
+    diagnostics::warn(
+        WarningId::ContentTypeMismatch,
+        format!(
+            "'{}' may not be the file you wanted: {mismatch}",
+            path.display()
+        ),
+    );
+    reporter.set_content_type_mismatch(mismatch);
+    Ok(())
+}
+
+/// `text/html; charset=utf-8` without its parameters, lowercased.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
@@ -0,0 +1,242 @@
+//! `--cosign-signature-url`: checking a download against its Sigstore
+//! signature once it's hashed, with the sigstore crates. The signature is
+//! fetched here, so a failure to get it is told apart from a signature
+//! that doesn't hold. A bare signature is checked against a public key;
+//! a keyless bundle against Sigstore's trust root (the Fulcio certificate
+//! chain and the Rekor entry the bundle carries) and the identity and
+//! issuer its certificate must name.
+
+#[cfg(feature = "sigstore")]
+use crate::download::client::ClientOptions;
+use crate::download::error::DownloadError;
+#[cfg(feature = "sigstore")]
+use crate::download::http;
+#[cfg(feature = "sigstore")]
+use crate::download::retry_budget::RetryBudget;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "sigstore")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "sigstore")]
+use url::Url;
+
+/// Who a signature must be from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Signer {
+    /// `--cosign-key`: a PEM public key file, as `cosign generate-key-pair`
+    /// writes.
+    Key(PathBuf),
+    /// `--cosign-certificate-identity` and `--cosign-oidc-issuer`: keyless
+    /// signing, by the identity the issuer vouched for.
+    Keyless { identity: String, issuer: String },
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signer::Key(key) => write!(f, "key {}", key.display()),
+            Signer::Keyless { identity, issuer } => write!(f, "{identity} (issued by {issuer})"),
+        }
+    }
+}
+
+/// What checking a download's signature came to, for `--progress json`
+/// and `usage.jsonl`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SignatureCheck {
+    /// Where the signature came from, with any credentials redacted.
+    pub url: String,
+    /// Who it had to be from: the key, or the identity and its issuer.
+    pub signer: String,
+    pub verified: bool,
+}
+
+/// The check `error` is the failure of, if it's a signature that didn't
+/// hold.
+pub fn failed(error: &anyhow::Error) -> Option<SignatureCheck> {
+    match error.downcast_ref() {
+        Some(DownloadError::SignatureInvalid { check, .. }) => Some(check.clone()),
+        _ => None,
+    }
+}
+
+/// The signature at `url`, and who it must be from.
+#[cfg(feature = "sigstore")]
+#[derive(Clone, Debug)]
+pub struct Cosign {
+    pub url: Url,
+    pub signer: Signer,
+}
+
+#[cfg(feature = "sigstore")]
+impl Cosign {
+    /// Fetches the signature, a bundle or a bare one, through a client
+    /// built from `client_options`, a 5xx retried while `budget` allows.
+    /// Whatever keeps it from arriving is a
+    /// [`DownloadError::SignatureUnavailable`].
+    pub fn fetch(
+        &self,
+        client_options: &ClientOptions,
+        budget: &RetryBudget,
+    ) -> Result<Vec<u8>, DownloadError> {
+        // The blocking client mustn't be built or dropped on an async
+        // worker thread, which a post-processing step may be running on.
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| self.fetch_blocking(client_options, budget))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    fn fetch_blocking(
+        &self,
+        client_options: &ClientOptions,
+        budget: &RetryBudget,
+    ) -> Result<Vec<u8>, DownloadError> {
+        let unavailable = |reason: String| DownloadError::SignatureUnavailable {
+            url: http::redact_url(&self.url),
+            reason,
+        };
+        let client = client_options
+            .build_blocking()
+            .map_err(|error| unavailable(format!("{error:#}")))?;
+        let request = client.get(self.url.clone());
+        let auth = client_options.auth_token.as_deref();
+        let response = http::send_retrying_blocking(request, None, budget, auth)
+            .map_err(|error| unavailable(error.to_string()))?;
+        let response = http::check_status_blocking(response)
+            .map_err(|error| unavailable(format!("{error:#}")))?;
+        let signature = response
+            .bytes()
+            .map_err(|error| unavailable(error.to_string()))?;
+        Ok(signature.to_vec())
+    }
+
+    /// Checks `signature` for the file at `path`: a bare signature (or the
+    /// `base64Signature` of a cosign bundle) against `--cosign-key`, a
+    /// Sigstore bundle against the identity and issuer. A signature that doesn't hold is a
+    /// [`DownloadError::SignatureInvalid`]; failing to get as far as
+    /// checking it, over a key that can't be loaded or a trust root that
+    /// can't be fetched, is a [`DownloadError::SignatureUnchecked`].
+    pub fn verify(&self, path: &Path, signature: &[u8]) -> anyhow::Result<SignatureCheck> {
+        // The blocking verifier runs a tokio runtime of its own, which
+        // mustn't be started on an async worker thread.
+        let verdict = std::thread::scope(|scope| {
+            scope
+                .spawn(|| match &self.signer {
+                    Signer::Key(key) => verify_with_key(key, path, signature),
+                    Signer::Keyless { identity, issuer } => {
+                        verify_keyless(identity, issuer, path, signature)
+                    }
+                })
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+        let check = SignatureCheck {
+            url: http::redact_url(&self.url),
+            signer: self.signer.to_string(),
+            verified: matches!(verdict, Verdict::Holds),
+        };
+        match verdict {
+            Verdict::Holds => Ok(check),
+            Verdict::Unchecked(reason) => Err(DownloadError::SignatureUnchecked {
+                path: path.to_path_buf(),
+                reason,
+            }
+            .into()),
+            Verdict::Invalid(reason) => Err(DownloadError::SignatureInvalid {
+                path: path.to_path_buf(),
+                reason,
+                check,
+            }
+            .into()),
+        }
+    }
+}
+
+/// What checking a signature came to.
+#[cfg(feature = "sigstore")]
+enum Verdict {
+    Holds,
+    /// It was checked, and doesn't hold.
+    Invalid(String),
+    /// It couldn't be checked.
+    Unchecked(String),
+}
+
+/// `signature` against the PEM public key at `key`, for the file at
+/// `path`. The key's algorithm decides the digest, so the file is read
+/// whole, as `cosign verify-blob` reads it.
+#[cfg(feature = "sigstore")]
+fn verify_with_key(key: &Path, path: &Path, signature: &[u8]) -> Verdict {
+    use sigstore::crypto::{CosignVerificationKey, Signature};
+
+    let loaded = std::fs::read(key)
+        .map_err(|error| error.to_string())
+        .and_then(|pem| {
+            CosignVerificationKey::try_from_pem(&pem).map_err(|error| error.to_string())
+        });
+    let key = match loaded {
+        Ok(key) => key,
+        Err(error) => {
+            return Verdict::Unchecked(format!("loading public key '{}': {error}", key.display()));
+        }
+    };
+    let Some(signature) = base64_signature(signature) else {
+        return Verdict::Invalid("the bundle has no base64Signature".to_string());
+    };
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) => return Verdict::Unchecked(format!("reading the download: {error}")),
+    };
+    match key.verify_signature(Signature::Base64Encoded(&signature), &contents) {
+        Ok(()) => Verdict::Holds,
+        Err(error) => Verdict::Invalid(error.to_string()),
+    }
+}
+
+/// The base64 signature in what `--cosign-signature-url` served: as it
+/// is, or from the `base64Signature` of a bundle `cosign sign-blob
+/// --bundle` wrote.
+#[cfg(feature = "sigstore")]
+fn base64_signature(signature: &[u8]) -> Option<Vec<u8>> {
+    if !signature.trim_ascii_start().starts_with(b"{") {
+        return Some(signature.trim_ascii().to_vec());
+    }
+    let bundle: serde_json::Value = serde_json::from_slice(signature).ok()?;
+    Some(bundle["base64Signature"].as_str()?.as_bytes().to_vec())
+}
+
+/// The Sigstore bundle `signature` for the file at `path`, against the
+/// public-good instance's trust root, which is fetched over TUF, and with
+/// a certificate issued to `identity` by `issuer`. The bundle carries its
+/// Rekor entry, so the log isn't asked.
+#[cfg(feature = "sigstore")]
+fn verify_keyless(identity: &str, issuer: &str, path: &Path, signature: &[u8]) -> Verdict {
+    use sigstore::bundle::Bundle;
+    use sigstore::bundle::verify::blocking::Verifier;
+    use sigstore::bundle::verify::policy::Identity;
+
+    let bundle: Bundle = match serde_json::from_slice(signature) {
+        Ok(bundle) => bundle,
+        Err(error) => return Verdict::Invalid(format!("not a Sigstore bundle: {error}")),
+    };
+    let verifier = match Verifier::production() {
+        Ok(verifier) => verifier,
+        Err(error) => {
+            return Verdict::Unchecked(format!("fetching the Sigstore trust root: {error}"));
+        }
+    };
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(error) => return Verdict::Unchecked(format!("reading the download: {error}")),
+    };
+    let policy = Identity::new(identity, issuer);
+    match verifier.verify(file, bundle, &policy, true) {
+        Ok(()) => Verdict::Holds,
+        Err(error) => Verdict::Invalid(error.to_string()),
+    }
+}
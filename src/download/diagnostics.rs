@@ -0,0 +1,320 @@
+use crate::download::error::DownloadError;
+use crate::download::transcript;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many retries are kept, the latest ones.
+const MAX_RETRIES: usize = 100;
+
+/// How many warnings are kept, the first ones.
+const MAX_WARNINGS: usize = 100;
+
+/// What went over the wire lately, and what was warned about, process-wide,
+/// for `--error-report` and the progress snapshots.
+static RECORD: Mutex<Record> = Mutex::new(Record {
+    last_response: None,
+    retries: VecDeque::new(),
+    warnings: Vec::new(),
+    strict: None,
+});
+
+struct Record {
+    last_response: Option<Exchange>,
+    retries: VecDeque<Retry>,
+    warnings: Vec<Warning>,
+    /// With `--strict`, the warnings `--strict-allow` exempts.
+    strict: Option<Vec<WarningId>>,
+}
+
+/// The status and headers of a response, secrets redacted as in `-vv`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Exchange {
+    /// The URL it answered, with any password redacted.
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+/// A request sent again: after a 5xx, a dropped connection, a stall or a
+/// short response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Retry {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    /// The worker-mode chunk, if it was one.
+    pub chunk: Option<usize>,
+    pub attempt: usize,
+    pub reason: String,
+}
+
+fn record() -> std::sync::MutexGuard<'static, Record> {
+    // A panic elsewhere mid-update leaves nothing worse than a stale entry.
+    RECORD.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn record_response(exchange: Exchange) {
+    record().last_response = Some(exchange);
+}
+
+pub(crate) fn record_retry(chunk: Option<usize>, attempt: usize, reason: &str) {
+    transcript::retry(chunk, attempt, reason);
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    let mut record = record();
+    if record.retries.len() == MAX_RETRIES {
+        record.retries.pop_front();
+    }
+    record.retries.push_back(Retry {
+        at,
+        chunk,
+        attempt,
+        reason: reason.to_string(),
+    });
+}
+
+/// The last response any request got.
+pub fn last_response() -> Option<Exchange> {
+    record().last_response.clone()
+}
+
+/// The latest retries, oldest first.
+pub fn retries() -> Vec<Retry> {
+    record().retries.iter().cloned().collect()
+}
+
+/// A condition `dlm` carries on past with a warning, by the stable ID
+/// `--strict-allow` and `dlm diagnostics list` use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningId {
+    ContentTypeMismatch,
+    NoContentLength,
+    ClockSkew,
+    Suspicious,
+    WeakEtagChanged,
+    WorkersCapped,
+    MemoryCapped,
+    TooLargeForFilesystem,
+    DirQuota,
+    UsageLimit,
+    ResumeUnchecked,
+    TrackingParams,
+    PinLost,
+    TargetBusy,
+    CacheCorrupt,
+    InterruptedCommit,
+    NameMismatch,
+    CleanupFailed,
+    NotRecorded,
+    StatusPagePublic,
+    MirrorRefused,
+}
+
+impl WarningId {
+    pub const ALL: [WarningId; 21] = [
+        WarningId::ContentTypeMismatch,
+        WarningId::NoContentLength,
+        WarningId::ClockSkew,
+        WarningId::Suspicious,
+        WarningId::WeakEtagChanged,
+        WarningId::WorkersCapped,
+        WarningId::MemoryCapped,
+        WarningId::TooLargeForFilesystem,
+        WarningId::DirQuota,
+        WarningId::UsageLimit,
+        WarningId::ResumeUnchecked,
+        WarningId::TrackingParams,
+        WarningId::PinLost,
+        WarningId::TargetBusy,
+        WarningId::CacheCorrupt,
+        WarningId::InterruptedCommit,
+        WarningId::NameMismatch,
+        WarningId::CleanupFailed,
+        WarningId::NotRecorded,
+        WarningId::StatusPagePublic,
+        WarningId::MirrorRefused,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarningId::ContentTypeMismatch => "content-type-mismatch",
+            WarningId::NoContentLength => "no-content-length",
+            WarningId::ClockSkew => "clock-skew",
+            WarningId::Suspicious => "suspicious",
+            WarningId::WeakEtagChanged => "weak-etag-changed",
+            WarningId::WorkersCapped => "workers-capped",
+            WarningId::MemoryCapped => "memory-capped",
+            WarningId::TooLargeForFilesystem => "too-large-for-filesystem",
+            WarningId::DirQuota => "dir-quota",
+            WarningId::UsageLimit => "usage-limit",
+            WarningId::ResumeUnchecked => "resume-unchecked",
+            WarningId::TrackingParams => "tracking-params",
+            WarningId::PinLost => "pin-lost",
+            WarningId::TargetBusy => "target-busy",
+            WarningId::CacheCorrupt => "cache-corrupt",
+            WarningId::InterruptedCommit => "interrupted-commit",
+            WarningId::NameMismatch => "name-mismatch",
+            WarningId::CleanupFailed => "cleanup-failed",
+            WarningId::NotRecorded => "not-recorded",
+            WarningId::StatusPagePublic => "status-page-public",
+            WarningId::MirrorRefused => "mirror-refused",
+        }
+    }
+
+    /// What it's warned about, for `dlm diagnostics list`.
+    pub fn description(&self) -> &'static str {
+        match self {
+            WarningId::ContentTypeMismatch => {
+                "The Content-Type contradicts the file's extension (--strict-content-type fails on it alone)"
+            }
+            WarningId::NoContentLength => {
+                "The server didn't say how big the file is, so a cut-short download can't be told apart"
+            }
+            WarningId::ClockSkew => "The server's clock is far from this machine's",
+            WarningId::Suspicious => {
+                "The file looks like an error page, and --allow-suspicious kept it"
+            }
+            WarningId::WeakEtagChanged => "A chunk came with another weak ETag than the probe",
+            WarningId::WorkersCapped => "The open-file limit left room for fewer workers",
+            WarningId::MemoryCapped => "Buffers were cut down or held back by --max-memory",
+            WarningId::TooLargeForFilesystem => {
+                "The filesystem can't hold a file that big, and --force went ahead"
+            }
+            WarningId::DirQuota => {
+                "The target directory is past --dir-quota, or --dir-quota-hard with --force"
+            }
+            WarningId::UsageLimit => "This day or month is past a `dlm usage --limit`",
+            WarningId::ResumeUnchecked => {
+                "Whether the local file is the start of the download couldn't be checked"
+            }
+            WarningId::TrackingParams => "The URL has tracking parameters left in it",
+            WarningId::PinLost => "The node the host was pinned to went away",
+            WarningId::TargetBusy => "The target was busy, and writing it was tried again",
+            WarningId::CacheCorrupt => "A cached copy was corrupt and fetched again",
+            WarningId::InterruptedCommit => "An interrupted --all-or-nothing commit was finished",
+            WarningId::NameMismatch => {
+                "A file named by --name-by-hash didn't match its name and was replaced"
+            }
+            WarningId::CleanupFailed => "A partial or stale file couldn't be removed",
+            WarningId::NotRecorded => {
+                "The download couldn't be recorded for `dlm status`, `dlm usage` or --chunk-log"
+            }
+            WarningId::StatusPagePublic => "--web-status listens beyond this machine",
+            WarningId::MirrorRefused => {
+                "A URL or --mirror was left out: it didn't answer, or disagreed about the file"
+            }
+        }
+    }
+
+    /// What `dlm` exits with when `--strict` fails on it: the code of the
+    /// error it stands in for, or 10.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            WarningId::ContentTypeMismatch => 4,
+            WarningId::Suspicious => 14,
+            WarningId::DirQuota | WarningId::UsageLimit => 6,
+            WarningId::WeakEtagChanged => 8,
+            _ => 10,
+        }
+    }
+}
+
+impl fmt::Display for WarningId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WarningId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        WarningId::ALL
+            .into_iter()
+            .find(|id| id.as_str() == value)
+            .ok_or_else(|| format!("unknown warning '{value}', `dlm diagnostics list` lists them"))
+    }
+}
+
+/// Something warned about during the run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Warning {
+    pub id: WarningId,
+    pub message: String,
+}
+
+/// Turns the warnings of the run into errors (`--strict`), but for the
+/// `allowed` ones.
+pub fn set_strict(strict: bool, allowed: &[WarningId]) {
+    record().strict = strict.then(|| allowed.to_vec());
+}
+
+/// Whether `--strict` fails on `id`.
+pub fn is_strict(id: WarningId) -> bool {
+    record()
+        .strict
+        .as_ref()
+        .is_some_and(|allowed| !allowed.contains(&id))
+}
+
+/// Logs a warning and records it. For where it can't stop what's under
+/// way: `--strict` fails the run once it's over, with [`check_strict`].
+pub fn warn(id: WarningId, message: impl Into<String>) {
+    let message = message.into();
+    tracing::warn!("{message}");
+    record_warning(id, message);
+}
+
+/// Logs a warning and records it, or under `--strict` fails with it
+/// straight away.
+pub fn warn_or_fail(id: WarningId, message: impl Into<String>) -> Result<(), DownloadError> {
+    let message = message.into();
+    record_warning(id, message.clone());
+    if is_strict(id) {
+        return Err(DownloadError::Strict { id, message });
+    }
+    tracing::warn!("{message}");
+    Ok(())
+}
+
+/// Records a warning that was shown some other way, or is about to fail
+/// what's under way anyway.
+pub fn record_warning(id: WarningId, message: impl Into<String>) {
+    let mut record = record();
+    if record.warnings.len() < MAX_WARNINGS {
+        record.warnings.push(Warning {
+            id,
+            message: message.into(),
+        });
+    }
+}
+
+/// The warnings of the run so far, in order.
+pub fn warnings() -> Vec<Warning> {
+    record().warnings.clone()
+}
+
+/// Fails with the first warning of the run `--strict` doesn't allow.
+pub fn check_strict() -> Result<(), DownloadError> {
+    let record = record();
+    let Some(allowed) = &record.strict else {
+        return Ok(());
+    };
+    match record
+        .warnings
+        .iter()
+        .find(|warning| !allowed.contains(&warning.id))
+    {
+        Some(warning) => Err(DownloadError::Strict {
+            id: warning.id,
+            message: warning.message.clone(),
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,135 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::progress_handle::{self, PreflightStep};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+/// How long a pinned node from an earlier run gets to accept a connection
+/// before it's given up on.
+const PIN_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The resolver behind every client of a run. It remembers what each host
+/// resolved to, so that when a connection dies mid-download the retry can
+/// forget it and look the host up afresh: DNS-balanced CDNs drain servers
+/// out from under long downloads, and the next lookup points elsewhere.
+/// Clones share the cache.
+///
+/// With a [`Pin`], every connection to its host goes to the one node
+/// instead.
+#[derive(Clone, Debug, Default)]
+pub struct DnsCache {
+    hosts: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+    pin: Arc<Mutex<Option<Pin>>>,
+}
+
+/// `--pin-ip`: the node that answered worker mode's probe, which every
+/// chunk then connects to, so all the bytes come from one CDN node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Pin {
+    pub host: String,
+    /// The port is the one the probe connected to, for checking the node
+    /// still answers when a later run picks the pin up.
+    pub address: SocketAddr,
+}
+
+impl Pin {
+    /// Whether the node still accepts connections.
+    pub async fn still_answers(&self) -> bool {
+        let connect = tokio::net::TcpStream::connect(self.address);
+        matches!(
+            tokio::time::timeout(PIN_CHECK_TIMEOUT, connect).await,
+            Ok(Ok(_))
+        )
+    }
+}
+
+impl DnsCache {
+    /// What `url`'s host last resolved to.
+    pub fn addresses(&self, url: &Url) -> Vec<IpAddr> {
+        let host = url.host_str().unwrap_or_default();
+        self.hosts
+            .lock()
+            .unwrap()
+            .get(host)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Forgets what `url`'s host resolved to, so the next connection to it
+    /// looks it up again. Returns the forgotten addresses.
+    ///
+    /// A node the host is pinned to is forgotten too: it's gone, and the
+    /// rest of the download is better off on other nodes than failing.
+    pub fn forget(&self, url: &Url) -> Vec<IpAddr> {
+        let host = url.host_str().unwrap_or_default();
+        let mut pin = self.pin.lock().unwrap();
+        if let Some(pinned) = pin.take_if(|pin| pin.host == host) {
+            diagnostics::warn(
+                WarningId::PinLost,
+                format!(
+                    "Node {} of {host} went away, the rest comes from whichever node {host} points to now",
+                    pinned.address.ip()
+                ),
+            );
+        }
+        self.hosts.lock().unwrap().remove(host).unwrap_or_default()
+    }
+
+    /// Sends every connection to `pin.host` to `pin.address`.
+    pub fn pin(&self, pin: Pin) {
+        *self.pin.lock().unwrap() = Some(pin);
+    }
+
+    /// The node connections are pinned to, if any.
+    pub fn pinned(&self) -> Option<Pin> {
+        self.pin.lock().unwrap().clone()
+    }
+
+    /// Logs where `url`'s host pointed before it was forgotten, and where
+    /// it points now.
+    pub fn log_change(&self, url: &Url, stale: &[IpAddr]) {
+        let host = url.host_str().unwrap_or_default();
+        let fresh = self.addresses(url);
+        tracing::debug!("Re-resolved {host}: {stale:?} -> {fresh:?}");
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let hosts = self.hosts.clone();
+        let pinned = self
+            .pinned()
+            .filter(|pin| pin.host == name.as_str())
+            .map(|pin| vec![pin.address.ip()]);
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let cached = pinned.or_else(|| hosts.lock().unwrap().get(&host).cloned());
+            let addresses = match cached {
+                Some(addresses) => addresses,
+                None => {
+                    progress_handle::report_preflight(PreflightStep::ResolvingDns {
+                        host: host.clone(),
+                    });
+                    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                        .await?
+                        .map(|address| address.ip())
+                        .collect();
+                    hosts.lock().unwrap().insert(host, addresses.clone());
+                    // Back to the connection the lookup was for.
+                    progress_handle::report_preflight(PreflightStep::Connecting);
+                    addresses
+                }
+            };
+            // reqwest fills in the port.
+            let addresses = addresses
+                .into_iter()
+                .map(|address| SocketAddr::new(address, 0));
+            Ok(Box::new(addresses) as Addrs)
+        })
+    }
+}
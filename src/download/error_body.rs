@@ -0,0 +1,141 @@
+//! Turning the body of an error response into something fit for an error
+//! message: S3's XML `<Code>`/`<Message>`, a JSON API's `error`/`message`,
+//! or the text of an HTML page.
+
+use serde_json::Value;
+
+/// How much of an error response's body is read.
+pub(crate) const MAX_ERROR_BODY: usize = 4096;
+
+/// How long the summary gets before it's cut off.
+const MAX_SUMMARY: usize = 500;
+
+/// JSON and XML fields that explain an error, in the order they're shown.
+const ERROR_FIELDS: [&str; 7] = [
+    "code",
+    "error",
+    "title",
+    "message",
+    "error_description",
+    "detail",
+    "reason",
+];
+
+/// A one-line summary of `body`, or `None` when it's empty or binary.
+pub(crate) fn summarize(content_type: Option<&str>, body: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(body) {
+        Ok(text) => text,
+        // A body cut off at MAX_ERROR_BODY may end mid-character.
+        Err(error) if error.error_len().is_none() => {
+            std::str::from_utf8(&body[..error.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !c.is_ascii_whitespace())
+    {
+        return None;
+    }
+    let content_type = content_type.unwrap_or_default().to_ascii_lowercase();
+    let trimmed = text.trim_start();
+    let summary = if content_type.contains("json") || trimmed.starts_with('{') {
+        json_fields(trimmed).unwrap_or_else(|| text.to_string())
+    } else if content_type.contains("html") || starts_with_ignore_case(trimmed, "<!doctype html") {
+        strip_tags(text)
+    } else if content_type.contains("xml") || trimmed.starts_with('<') {
+        xml_fields(text).unwrap_or_else(|| strip_tags(text))
+    } else {
+        text.to_string()
+    };
+    let summary = collapse_whitespace(&summary);
+    if summary.is_empty() {
+        return None;
+    }
+    Some(match summary.char_indices().nth(MAX_SUMMARY) {
+        Some((at, _)) => format!("{}...", &summary[..at]),
+        None => summary,
+    })
+}
+
+fn json_fields(text: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    let fields = describe(&value);
+    (!fields.is_empty()).then(|| fields.join(": "))
+}
+
+/// The error fields of a JSON object, looking inside a nested `error`
+/// object like `{"error": {"code": 403, "message": "..."}}`.
+fn describe(value: &Value) -> Vec<String> {
+    let Value::Object(object) = value else {
+        return Vec::new();
+    };
+    let mut fields = Vec::new();
+    for name in ERROR_FIELDS {
+        match object.get(name) {
+            Some(Value::String(text)) => fields.push(text.clone()),
+            Some(number @ Value::Number(_)) => fields.push(number.to_string()),
+            Some(nested @ Value::Object(_)) => fields.extend(describe(nested)),
+            _ => {}
+        }
+    }
+    fields.dedup();
+    fields
+}
+
+fn xml_fields(text: &str) -> Option<String> {
+    let fields: Vec<String> = ERROR_FIELDS
+        .iter()
+        .filter_map(|name| element_text(text, name))
+        .collect();
+    (!fields.is_empty()).then(|| fields.join(": "))
+}
+
+/// The text of the first `<name>...</name>` element, case-insensitively.
+pub(crate) fn element_text(text: &str, name: &str) -> Option<String> {
+    let lower = text.to_ascii_lowercase();
+    let start = lower.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + lower[start..].find(&format!("</{name}>"))?;
+    let inner = text[start..end].trim();
+    (!inner.is_empty() && !inner.contains('<')).then(|| inner.to_string())
+}
+
+/// The text of an HTML or XML document, without scripts and styles.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        out.push_str(&rest[..open]);
+        out.push(' ');
+        rest = &rest[open..];
+        let skip_to = ["script", "style"]
+            .into_iter()
+            .find(|tag| starts_with_ignore_case(&rest[1..], tag))
+            .and_then(|tag| {
+                let close = format!("</{tag}");
+                rest.to_ascii_lowercase().find(&close)
+            })
+            .unwrap_or(0);
+        rest = &rest[skip_to..];
+        match rest.find('>') {
+            Some(close) => rest = &rest[close + 1..],
+            None => rest = "",
+        }
+    }
+    out.push_str(rest);
+    out.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
@@ -0,0 +1,70 @@
+//! ETags as a cheap identity of what a server sends. Worker mode takes the
+//! one the probe got and compares every chunk's response with it: bytes of
+//! another version of the file would be merged in without any length
+//! changing. A strong ETag that changes fails the chunk before its bytes
+//! are written; a weak one (`W/"..."`) may change with the encoding or the
+//! node alone, so its change is only warned about.
+
+use reqwest::header::{self, HeaderMap};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A chunk's response whose ETag isn't the one the probe got.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EtagMismatch {
+    pub chunk: usize,
+    pub expected: String,
+    pub actual: String,
+    /// Either was weak, so the download carried on.
+    pub weak: bool,
+}
+
+impl fmt::Display for EtagMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {}: {} instead of {}",
+            self.chunk, self.actual, self.expected
+        )?;
+        if self.weak {
+            write!(f, " (weak, carried on)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The ETag in `headers`, if there's one.
+pub fn of(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|etag| !etag.is_empty())
+}
+
+/// Whether `etag` is weak, as in `W/"abc"`.
+pub fn is_weak(etag: &str) -> bool {
+    etag.starts_with("W/")
+}
+
+/// How the response to `chunk` differs from the `expected` ETag, `None`
+/// when it doesn't, or either side has none. Two ETags that differ only in
+/// being weak are the same by weak comparison.
+pub fn compare(chunk: usize, expected: Option<&str>, headers: &HeaderMap) -> Option<EtagMismatch> {
+    let (expected, actual) = (expected?, of(headers)?);
+    if opaque(expected) == opaque(actual) {
+        return None;
+    }
+    Some(EtagMismatch {
+        chunk,
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+        weak: is_weak(expected) || is_weak(actual),
+    })
+}
+
+/// `etag` without the weak indicator.
+fn opaque(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
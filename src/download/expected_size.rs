@@ -0,0 +1,111 @@
+//! `--expected-size`: the size a download must come to, for when a release
+//! manifest gives it but no digest. It's checked three times: against the
+//! size the server announces, before anything is written; against the
+//! bytes the transfer delivered; and against the finished file on disk.
+//! A partial file a resume would continue that's already past it is
+//! refused before the first request.
+
+use crate::download::error::DownloadError;
+use crate::download::options::TransferOptions;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// What the expected size was compared with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeCheck {
+    /// The partial file a resume would continue.
+    Partial,
+    /// The size the server announced, the resumed prefix included.
+    Announced,
+    /// The bytes the transfer delivered, the resumed prefix included.
+    Transferred,
+    /// The finished file's size on disk.
+    OnDisk,
+}
+
+impl SizeCheck {
+    /// What was `actual` bytes, for the error: `The server says 'x.iso'`.
+    pub fn subject(self, path: &Path) -> String {
+        let path = path.display();
+        match self {
+            SizeCheck::Partial => format!("The partial file '{path}' to resume"),
+            SizeCheck::Announced => format!("The server says '{path}'"),
+            SizeCheck::Transferred => format!("What was downloaded of '{path}'"),
+            SizeCheck::OnDisk => format!("'{path}' on disk"),
+        }
+    }
+}
+
+impl fmt::Display for SizeCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SizeCheck::Partial => "the partial file",
+            SizeCheck::Announced => "the Content-Length",
+            SizeCheck::Transferred => "the bytes downloaded",
+            SizeCheck::OnDisk => "the file on disk",
+        })
+    }
+}
+
+/// A check the expected size failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SizeMismatch {
+    pub check: SizeCheck,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Fails if `actual`, what `check` found for `path`, contradicts the
+/// expected size: a partial file past it, or anything else not equal to
+/// it.
+pub fn check(
+    path: &Path,
+    options: &TransferOptions,
+    check: SizeCheck,
+    actual: u64,
+) -> Result<(), DownloadError> {
+    let Some(expected) = options.expected_size else {
+        return Ok(());
+    };
+    let fits = match check {
+        SizeCheck::Partial => actual <= expected,
+        _ => actual == expected,
+    };
+    if fits {
+        return Ok(());
+    }
+    Err(DownloadError::SizeMismatch {
+        path: path.to_path_buf(),
+        mismatch: SizeMismatch {
+            check,
+            expected,
+            actual,
+        },
+    })
+}
+
+/// The checks once a transfer is over: the `transferred` bytes, then the
+/// file at `path`. A file stored compressed isn't the size of the content,
+/// so only the transfer is checked.
+pub fn check_done(path: &Path, options: &TransferOptions, transferred: u64) -> anyhow::Result<()> {
+    if options.expected_size.is_none() {
+        return Ok(());
+    }
+    check(path, options, SizeCheck::Transferred, transferred)?;
+    if options.store_compressed.is_none() {
+        let on_disk = std::fs::metadata(path)?.len();
+        check(path, options, SizeCheck::OnDisk, on_disk)?;
+    }
+    Ok(())
+}
+
+/// The check `error` is the failure of, if it's an `--expected-size` one.
+pub fn mismatch(error: &anyhow::Error) -> Option<SizeMismatch> {
+    match error.downcast_ref() {
+        Some(DownloadError::SizeMismatch { mismatch, .. }) => Some(*mismatch),
+        _ => None,
+    }
+}
@@ -0,0 +1,137 @@
+use crate::download::progress::{ChunkState, TransferProgress};
+use crate::download::speed::Rate;
+use crate::download::throttle::Throttle;
+use std::time::Duration;
+
+/// How often `--fair-workers` measures the chunks and reworks their caps.
+pub const REBALANCE_EVERY: Duration = Duration::from_secs(3);
+
+/// A chunk is capped past this many times its fair share, and only while
+/// another gets less than its share divided by it.
+const MAX_SHARE: u64 = 2;
+
+/// `--fair-workers`: splits the bandwidth into a share per downloading
+/// chunk and, while some chunk starves, caps the others at
+/// [`MAX_SHARE`] times theirs. The budget shared out is `--limit-rate`,
+/// or without one the speed the chunks reach together.
+///
+/// Capping is meant to even chunks out, not slow the download; if the
+/// chunks fall well below what they did together uncapped, the caps are
+/// lifted for good.
+#[derive(Debug, Default)]
+pub struct Balancer {
+    /// The combined speed when the caps went on, and how many chunks were
+    /// downloading then.
+    capped_at: Option<(u64, u64)>,
+    gave_up: bool,
+}
+
+impl Balancer {
+    /// The caps for the next interval, given each chunk's speed in bytes/s
+    /// over the last one (`None` for chunks not downloading) and the rate
+    /// limit.
+    pub fn rebalance(&mut self, speeds: &[Option<u64>], limit: Option<u64>) -> Vec<Option<u64>> {
+        let uncapped = vec![None; speeds.len()];
+        if self.gave_up {
+            return uncapped;
+        }
+        let active = speeds.iter().flatten().count() as u64;
+        let total: u64 = speeds.iter().flatten().sum();
+        // A chunk finishing or starting changes what the chunks can do
+        // together; start over rather than compare.
+        if self
+            .capped_at
+            .is_some_and(|(_, was_active)| was_active != active)
+        {
+            self.lift(total, "the chunks changed");
+        }
+        if let Some((before, _)) = self.capped_at
+            && total < before / 10 * 9
+        {
+            tracing::info!(
+                "Fair workers: chunks at {} together capped vs {} before, lifting the caps for good",
+                Rate(total),
+                Rate(before)
+            );
+            self.gave_up = true;
+            self.capped_at = None;
+            return uncapped;
+        }
+        if active < 2 {
+            self.lift(total, "under two chunks downloading");
+            return uncapped;
+        }
+
+        // Without a limit the budget is what the chunks managed; while
+        // capped, what they managed before, or the caps would ratchet down.
+        let before = self.capped_at.map_or(0, |(before, _)| before);
+        let budget = limit.unwrap_or(total.max(before));
+        let fair = budget / active;
+        let starved: Vec<usize> = chunks(speeds, |speed| speed < fair / MAX_SHARE);
+        let hogs: Vec<usize> = chunks(speeds, |speed| speed > fair * MAX_SHARE);
+        if starved.is_empty() || (hogs.is_empty() && self.capped_at.is_none()) {
+            self.lift(total, "no chunk starved");
+            return uncapped;
+        }
+
+        let cap = fair * MAX_SHARE;
+        if self.capped_at.is_none() {
+            self.capped_at = Some((total, active));
+            tracing::info!(
+                "Fair workers: chunk {} over twice the fair share of {} while chunk {} under half, capping each at {}",
+                list(&hogs),
+                Rate(fair),
+                list(&starved),
+                Rate(cap)
+            );
+        } else {
+            tracing::debug!("Fair workers: chunk {} still starved", list(&starved));
+        }
+        speeds.iter().map(|speed| speed.map(|_| cap)).collect()
+    }
+
+    fn lift(&mut self, total: u64, why: &str) {
+        if let Some((before, _)) = self.capped_at.take() {
+            tracing::info!(
+                "Fair workers: {why}, lifting the caps at {} together vs {} before",
+                Rate(total),
+                Rate(before)
+            );
+        }
+    }
+}
+
+fn chunks(speeds: &[Option<u64>], matches: impl Fn(u64) -> bool) -> Vec<usize> {
+    (0..speeds.len())
+        .filter(|&chunk| speeds[chunk].is_some_and(&matches))
+        .collect()
+}
+
+fn list(chunks: &[usize]) -> String {
+    let chunks: Vec<String> = chunks.iter().map(usize::to_string).collect();
+    chunks.join(", ")
+}
+
+/// Measures each chunk every [`REBALANCE_EVERY`] and hands the
+/// [`Balancer`]'s caps to the throttle, until aborted.
+pub(crate) async fn balance(progress: TransferProgress, throttle: Throttle) {
+    let mut balancer = Balancer::default();
+    let mut ticks = tokio::time::interval(REBALANCE_EVERY);
+    ticks.tick().await;
+    let mut before = progress.chunk_bytes();
+    loop {
+        ticks.tick().await;
+        let now = progress.chunk_bytes();
+        let speeds: Vec<Option<u64>> = progress
+            .chunk_states()
+            .iter()
+            .zip(now.iter().zip(&before))
+            .map(|(state, (now, before))| {
+                matches!(state, ChunkState::Downloading { .. })
+                    .then(|| now.saturating_sub(*before) / REBALANCE_EVERY.as_secs())
+            })
+            .collect();
+        throttle.cap_chunks(&balancer.rebalance(&speeds, throttle.rate()));
+        before = now;
+    }
+}
@@ -0,0 +1,45 @@
+use crate::download::diagnostics::{self, WarningId};
+
+/// Descriptors a worker holds at once: its connection and its part file.
+/// Part files are opened when the worker starts and closed when it's done,
+/// and the merge reads them one at a time.
+#[cfg(unix)]
+const PER_WORKER: u64 = 2;
+
+/// Left for everything else: stdio, the runtime, the probe's connection,
+/// log files and the control socket.
+#[cfg(unix)]
+const RESERVED: u64 = 32;
+
+/// How many workers fit in the open-file limit, if there is one to go by.
+#[cfg(unix)]
+pub fn max_workers() -> Option<u64> {
+    use nix::sys::resource::{Resource, getrlimit};
+    let (soft, _) = getrlimit(Resource::RLIMIT_NOFILE).ok()?;
+    Some(soft.saturating_sub(RESERVED) / PER_WORKER)
+}
+
+/// Windows has no per-process limit worth checking at these counts.
+#[cfg(not(unix))]
+pub fn max_workers() -> Option<u64> {
+    None
+}
+
+/// `workers`, or fewer if the open-file limit (`ulimit -n`) can't hold
+/// that many at once, with a warning saying so.
+pub fn cap_workers(workers: u8) -> u8 {
+    let Some(max) = max_workers() else {
+        return workers;
+    };
+    if u64::from(workers) <= max {
+        return workers;
+    }
+    let capped = max.clamp(1, u64::from(u8::MAX)) as u8;
+    diagnostics::warn(
+        WarningId::WorkersCapped,
+        format!(
+            "The open-file limit only leaves room for {capped} workers, using them instead of {workers}; raise it with `ulimit -n`"
+        ),
+    );
+    capped
+}
@@ -0,0 +1,98 @@
+//! Notices the file a single-stream download writes to being deleted,
+//! replaced or cut short by something else, like a cleanup script. Writes
+//! to a deleted file still succeed, into an inode nothing can reach, and
+//! the download would report success with nothing left to show for it.
+//!
+//! The file is opened to append on unix, so writes after it was truncated
+//! land at its new end and leave it shorter than what was written. On
+//! Windows it's opened to write, as a file opened to append can't be cut
+//! back there for a restart; it can't be deleted while open either.
+
+use anyhow::bail;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the file is looked at, at the most...
+pub const CHECK_EVERY: Duration = Duration::from_secs(2);
+/// ...unless this much was written since.
+pub const CHECK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// What's known about the file a download writes to.
+pub struct FileWatch {
+    path: PathBuf,
+    identity: Option<(u64, u64)>,
+    /// How big it should be.
+    size: u64,
+    checked: Instant,
+    since: u64,
+}
+
+impl FileWatch {
+    /// Watches `path`, open with `metadata` and `size` bytes long.
+    pub fn new(path: &Path, metadata: &Metadata, size: u64) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            identity: identity(metadata),
+            size,
+            checked: Instant::now(),
+            since: 0,
+        }
+    }
+
+    /// Records `bytes` more written to the end of the file.
+    pub fn wrote(&mut self, bytes: usize) {
+        self.size += bytes as u64;
+        self.since += bytes as u64;
+    }
+
+    /// Records the file cut to `size` bytes by the download itself.
+    pub fn reset(&mut self, size: u64) {
+        self.size = size;
+    }
+
+    /// Whether it's time to [`check`](Self::check) again.
+    pub fn due(&self) -> bool {
+        self.since >= CHECK_BYTES || self.checked.elapsed() >= CHECK_EVERY
+    }
+
+    /// Fails if the file at the path isn't the one being written anymore,
+    /// or isn't as big as what was written to it. Everything written must
+    /// have reached the file.
+    pub fn check(&mut self) -> anyhow::Result<()> {
+        self.checked = Instant::now();
+        self.since = 0;
+        let path = self.path.display();
+        let metadata = match std::fs::metadata(&self.path) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                bail!("'{path}' was deleted by something else while it was being downloaded")
+            }
+            result => result?,
+        };
+        if self.identity.is_some() && identity(&metadata) != self.identity {
+            bail!("'{path}' was replaced by another file while it was being downloaded");
+        }
+        if metadata.len() != self.size {
+            bail!(
+                "'{path}' changed underneath the download: it's {} bytes, {} were written to it",
+                metadata.len(),
+                self.size
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The device and inode of a file, to tell whether a path still names it.
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Not stable on Windows, where an open file can't be deleted anyway.
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -0,0 +1,175 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::options::TransferOptions;
+use crate::download::speed::Size;
+use anyhow::bail;
+use std::fmt;
+use std::path::Path;
+
+/// A filesystem the largest file it can hold is known for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filesystem {
+    /// FAT12, FAT16 and FAT32, which keep a file's size in 32 bits.
+    Fat,
+    ExFat,
+    Ntfs,
+    Ext4,
+    Btrfs,
+    Xfs,
+    Apfs,
+}
+
+impl Filesystem {
+    /// The largest file it holds, or `None` when nothing a download could
+    /// reach comes near it.
+    pub fn max_file_size(self) -> Option<u64> {
+        match self {
+            Filesystem::Fat => Some(u32::MAX as u64),
+            Filesystem::ExFat
+            | Filesystem::Ntfs
+            | Filesystem::Ext4
+            | Filesystem::Btrfs
+            | Filesystem::Xfs
+            | Filesystem::Apfs => None,
+        }
+    }
+
+    /// From the name Windows or macOS gives it, as in `FAT32` or `msdos`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let filesystem = match name.to_ascii_lowercase().as_str() {
+            "fat" | "fat12" | "fat16" | "fat32" | "vfat" | "msdos" => Filesystem::Fat,
+            "exfat" => Filesystem::ExFat,
+            "ntfs" => Filesystem::Ntfs,
+            "ext4" => Filesystem::Ext4,
+            "btrfs" => Filesystem::Btrfs,
+            "xfs" => Filesystem::Xfs,
+            "apfs" => Filesystem::Apfs,
+            _ => return None,
+        };
+        Some(filesystem)
+    }
+
+    /// From the magic number `statfs` gives it on Linux.
+    #[cfg(target_os = "linux")]
+    fn from_magic(magic: nix::sys::statfs::FsType) -> Option<Self> {
+        use nix::sys::statfs::{
+            BTRFS_SUPER_MAGIC, EXT4_SUPER_MAGIC, FsType, MSDOS_SUPER_MAGIC, XFS_SUPER_MAGIC,
+        };
+        // Not in libc.
+        const EXFAT_SUPER_MAGIC: FsType = FsType(0x2011_bab0);
+        const NTFS_SB_MAGIC: FsType = FsType(0x5346_544e);
+        // ext2 and ext3 share ext4's magic; their limits are in the
+        // terabytes too.
+        let filesystem = match magic {
+            MSDOS_SUPER_MAGIC => Filesystem::Fat,
+            EXFAT_SUPER_MAGIC => Filesystem::ExFat,
+            NTFS_SB_MAGIC => Filesystem::Ntfs,
+            EXT4_SUPER_MAGIC => Filesystem::Ext4,
+            BTRFS_SUPER_MAGIC => Filesystem::Btrfs,
+            XFS_SUPER_MAGIC => Filesystem::Xfs,
+            _ => return None,
+        };
+        Some(filesystem)
+    }
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Filesystem::Fat => "FAT",
+            Filesystem::ExFat => "exFAT",
+            Filesystem::Ntfs => "NTFS",
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Btrfs => "btrfs",
+            Filesystem::Xfs => "XFS",
+            Filesystem::Apfs => "APFS",
+        })
+    }
+}
+
+/// The filesystem holding `dir`, if it's one of the known ones.
+#[cfg(target_os = "linux")]
+pub fn detect(dir: &Path) -> Option<Filesystem> {
+    let stats = nix::sys::statfs::statfs(dir).ok()?;
+    Filesystem::from_magic(stats.filesystem_type())
+}
+
+#[cfg(target_os = "macos")]
+pub fn detect(dir: &Path) -> Option<Filesystem> {
+    let stats = nix::sys::statfs::statfs(dir).ok()?;
+    Filesystem::from_name(stats.filesystem_type_name())
+}
+
+#[cfg(windows)]
+pub fn detect(dir: &Path) -> Option<Filesystem> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::{GetVolumeInformationW, GetVolumePathNameW};
+
+    let dir: Vec<u16> = dir.as_os_str().encode_wide().chain([0]).collect();
+    let mut root = [0u16; 1024];
+    let mut name = [0u16; 64];
+    // SAFETY: both paths are NUL-terminated, and each buffer's length is
+    // passed along with it.
+    let found = unsafe {
+        GetVolumePathNameW(dir.as_ptr(), root.as_mut_ptr(), root.len() as u32) != 0
+            && GetVolumeInformationW(
+                root.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                name.as_mut_ptr(),
+                name.len() as u32,
+            ) != 0
+    };
+    if !found {
+        return None;
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Filesystem::from_name(&String::from_utf16_lossy(&name[..len]))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn detect(_dir: &Path) -> Option<Filesystem> {
+    None
+}
+
+/// Why a file of `size` bytes can't go in `dir` on `filesystem`, if it
+/// can't. An unknown filesystem is given the benefit of the doubt.
+pub fn too_large(dir: &Path, filesystem: Option<Filesystem>, size: u64) -> Option<String> {
+    let filesystem = filesystem?;
+    let max = filesystem.max_file_size()?;
+    (size > max).then(|| {
+        format!(
+            "'{}' is on {filesystem}, which can't hold a file over {}, and the download is {}",
+            dir.display(),
+            Size(max),
+            Size(size)
+        )
+    })
+}
+
+/// Fails if the filesystem `file` goes on can't hold `size` bytes in one
+/// file, so a download that would stop with "File too large" at 4 GiB on a
+/// FAT32 stick doesn't start. With `force` it only warns. A compressed
+/// file's size isn't known up front, so it isn't checked.
+pub fn check(file: &Path, size: u64, options: &TransferOptions) -> anyhow::Result<()> {
+    if options.store_compressed.is_some() {
+        return Ok(());
+    }
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(reason) = too_large(dir, detect(dir), size) else {
+        return Ok(());
+    };
+    if options.force {
+        diagnostics::warn_or_fail(
+            WarningId::TooLargeForFilesystem,
+            format!("{reason}, downloading anyway"),
+        )?;
+        return Ok(());
+    }
+    bail!("{reason}; save it somewhere else, or pass --force to try anyway")
+}
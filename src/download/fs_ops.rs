@@ -0,0 +1,175 @@
+//! Every change download code makes to the filesystem: creating, opening
+//! to write, renaming, copying, removing, truncating. They all go through
+//! here rather than calling `std::fs` or `tokio::fs` themselves, so there's
+//! one place that knows what a download does to the disk.
+//!
+//! `--dry-run` relies on it: after [`forbid`], every one of them is
+//! refused and recorded instead of done, and in a debug build it panics,
+//! since reaching one means some code path didn't stop where a dry run
+//! should. dlm's own files (logs, the state directory, the control socket)
+//! aren't downloads and don't go through it.
+
+use serde::Serialize;
+use std::fs::{File, OpenOptions, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// Whether changes are refused, for `--dry-run`.
+static FORBIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// What was refused, in order.
+static REFUSED: Mutex<Vec<Operation>> = Mutex::new(Vec::new());
+
+/// A change to the filesystem that [`forbid`] refused.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Operation {
+    /// What it would have done, as in "create" or "rename".
+    pub what: &'static str,
+    pub path: PathBuf,
+}
+
+/// Refuses every change to the filesystem from now on, for the rest of
+/// the run.
+pub fn forbid() {
+    FORBIDDEN.store(true, Ordering::SeqCst);
+}
+
+/// The changes refused since [`forbid`].
+pub fn refused() -> Vec<Operation> {
+    REFUSED.lock().unwrap().clone()
+}
+
+/// Lets a change to `path` through, unless changes are forbidden.
+pub(crate) fn permit(what: &'static str, path: &Path) -> io::Result<()> {
+    if !FORBIDDEN.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    REFUSED.lock().unwrap().push(Operation {
+        what,
+        path: path.to_path_buf(),
+    });
+    debug_assert!(false, "--dry-run tried to {what} '{}'", path.display());
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("--dry-run won't {what} '{}'", path.display()),
+    ))
+}
+
+/// What's in a directory, to tell whether anything changed in it: each
+/// entry's name, size and modification time, by name.
+pub fn snapshot(dir: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            let metadata = entry.metadata().ok();
+            (
+                entry.path(),
+                metadata.as_ref().map_or(0, |metadata| metadata.len()),
+                metadata.and_then(|metadata| metadata.modified().ok()),
+            )
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+pub fn create(path: &Path) -> io::Result<File> {
+    permit("create", path)?;
+    File::create(path)
+}
+
+/// Opens `path` with `options`, which write to it.
+pub fn open(options: &OpenOptions, path: &Path) -> io::Result<File> {
+    permit("open for writing", path)?;
+    options.open(path)
+}
+
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    permit("write", path)?;
+    std::fs::write(path, contents)
+}
+
+/// Cuts or extends `file`, open at `path`, to `size` bytes.
+pub fn set_len(file: &File, path: &Path, size: u64) -> io::Result<()> {
+    permit("truncate", path)?;
+    file.set_len(size)
+}
+
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    permit("rename", from)?;
+    std::fs::rename(from, to)
+}
+
+pub fn copy(from: &Path, to: &Path) -> io::Result<u64> {
+    permit("copy", to)?;
+    std::fs::copy(from, to)
+}
+
+pub fn hard_link(from: &Path, to: &Path) -> io::Result<()> {
+    permit("link", to)?;
+    std::fs::hard_link(from, to)
+}
+
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    permit("remove", path)?;
+    std::fs::remove_file(path)
+}
+
+pub fn remove_dir_all(path: &Path) -> io::Result<()> {
+    permit("remove", path)?;
+    std::fs::remove_dir_all(path)
+}
+
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    permit("create", path)?;
+    std::fs::create_dir_all(path)
+}
+
+pub fn set_permissions(path: &Path, permissions: Permissions) -> io::Result<()> {
+    permit("change the permissions of", path)?;
+    std::fs::set_permissions(path, permissions)
+}
+
+/// [`create`] for async code.
+pub async fn create_async(path: &Path) -> io::Result<tokio::fs::File> {
+    permit("create", path)?;
+    tokio::fs::File::create(path).await
+}
+
+/// [`open`] for async code.
+pub async fn open_async(
+    options: &tokio::fs::OpenOptions,
+    path: &Path,
+) -> io::Result<tokio::fs::File> {
+    permit("open for writing", path)?;
+    options.open(path).await
+}
+
+/// [`set_len`] for async code.
+pub async fn set_len_async(file: &tokio::fs::File, path: &Path, size: u64) -> io::Result<()> {
+    permit("truncate", path)?;
+    file.set_len(size).await
+}
+
+/// [`rename`] for async code.
+pub async fn rename_async(from: &Path, to: &Path) -> io::Result<()> {
+    permit("rename", from)?;
+    tokio::fs::rename(from, to).await
+}
+
+/// [`copy`] for async code.
+pub async fn copy_async(from: &Path, to: &Path) -> io::Result<u64> {
+    permit("copy", to)?;
+    tokio::fs::copy(from, to).await
+}
+
+/// [`remove_file`] for async code.
+pub async fn remove_file_async(path: &Path) -> io::Result<()> {
+    permit("remove", path)?;
+    tokio::fs::remove_file(path).await
+}
@@ -0,0 +1,168 @@
+use crate::download::error::DownloadError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Why an entry was left out by [`HostCircuits::admit`].
+pub const UNHEALTHY: &str = "host unhealthy";
+
+/// How long an open circuit skips its host's entries by default.
+pub const COOLDOWN: Duration = Duration::from_secs(60);
+
+/// A circuit breaker per host, for runs over many URLs: after `threshold`
+/// failures in a row that point at the host itself (DNS, connect, 5xx) the
+/// rest of its entries are skipped straight away, instead of each waiting
+/// out its own timeouts and retries. After `cooldown` one entry is let
+/// through as a trial; if it gets an answer the host is healthy again,
+/// otherwise it's skipped for another `cooldown`.
+///
+/// Time is passed in, so callers (and tests) decide what "now" is.
+pub struct HostCircuits {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: HashMap<String, Host>,
+}
+
+/// What happened with one host over the run, for the run's summary.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HostStats {
+    /// Entries let through to the host.
+    pub attempts: u64,
+    /// Attempts that failed because of the host.
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    /// Entries left out while the circuit was open.
+    pub skipped: u64,
+    /// How often the circuit opened.
+    pub tripped: u32,
+}
+
+#[derive(Default)]
+struct Host {
+    circuit: Circuit,
+    stats: HostStats,
+}
+
+#[derive(Default)]
+enum Circuit {
+    #[default]
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// One trial is in flight; the rest wait for its outcome.
+    HalfOpen,
+}
+
+impl fmt::Display for HostStats {
+    /// As in `5 attempted, 3 failed, 2 skipped, opened 1 time`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} attempted, {} failed, {} skipped",
+            self.attempts, self.failures, self.skipped
+        )?;
+        match self.tripped {
+            0 => Ok(()),
+            1 => write!(f, ", opened 1 time"),
+            tripped => write!(f, ", opened {tripped} times"),
+        }
+    }
+}
+
+impl Default for HostCircuits {
+    /// Three failures in a row open the circuit for a minute.
+    fn default() -> Self {
+        Self::new(3, COOLDOWN)
+    }
+}
+
+impl HostCircuits {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Whether to go ahead with `url` now. `false` means skip it, as
+    /// [`UNHEALTHY`].
+    pub fn admit(&mut self, url: &Url, now: Instant) -> bool {
+        let host = self.hosts.entry(host_key(url)).or_default();
+        match host.circuit {
+            Circuit::Open { until } if now >= until => host.circuit = Circuit::HalfOpen,
+            Circuit::Open { .. } | Circuit::HalfOpen => {
+                host.stats.skipped += 1;
+                return false;
+            }
+            Circuit::Closed => {}
+        }
+        host.stats.attempts += 1;
+        true
+    }
+
+    /// Records how an admitted `url` went: `None` for success. Errors that
+    /// say nothing about the host's health, like a 404 or a full disk,
+    /// count as an answer from it.
+    pub fn record(&mut self, url: &Url, error: Option<&anyhow::Error>, now: Instant) {
+        let host = self.hosts.entry(host_key(url)).or_default();
+        if !error.is_some_and(is_host_failure) {
+            host.stats.consecutive_failures = 0;
+            host.circuit = Circuit::Closed;
+            return;
+        }
+        host.stats.failures += 1;
+        host.stats.consecutive_failures += 1;
+        let trip = match host.circuit {
+            Circuit::HalfOpen => true,
+            Circuit::Closed => host.stats.consecutive_failures >= self.threshold,
+            Circuit::Open { .. } => false,
+        };
+        if trip {
+            host.stats.tripped += 1;
+            host.circuit = Circuit::Open {
+                until: now + self.cooldown,
+            };
+        }
+    }
+
+    /// Every host seen so far, by name.
+    pub fn stats(&self) -> BTreeMap<String, HostStats> {
+        self.hosts
+            .iter()
+            .map(|(name, host)| (name.clone(), host.stats.clone()))
+            .collect()
+    }
+}
+
+/// `host`, or `host:port` when the URL names a port, since two servers
+/// on one machine can be up and down separately.
+pub fn host_key(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+/// Whether `error` means the host couldn't be reached or couldn't serve:
+/// a failed DNS lookup or connect, a timeout, or a 5xx.
+pub fn is_host_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return error.is_connect()
+                || error.is_timeout()
+                || error
+                    .status()
+                    .is_some_and(|status| status.is_server_error());
+        }
+        matches!(
+            cause.downcast_ref::<DownloadError>(),
+            Some(DownloadError::ServerError { .. })
+        )
+    })
+}
@@ -0,0 +1,681 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::clock;
+use crate::download::connections;
+use crate::download::diagnostics::{self, Exchange};
+use crate::download::error::DownloadError;
+use crate::download::error_body;
+use crate::download::presigned;
+use crate::download::proxy;
+use crate::download::rate_limit;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::transcript;
+use crate::download::utils::{self, ContentRange};
+use anyhow::Context;
+use base64::prelude::{BASE64_STANDARD, Engine};
+use reqwest::header::{self, HeaderMap, HeaderName};
+use reqwest::{Method, StatusCode, Version};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Whether credential headers are dumped as is, set from `--show-secrets`.
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+const SECRET_HEADERS: [HeaderName; 4] = [
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    header::COOKIE,
+    header::SET_COOKIE,
+];
+
+/// Whether requests go out as HTTP/3, set once QUIC was negotiated.
+static HTTP3: AtomicBool = AtomicBool::new(false);
+
+/// Whether error statuses come with the gist of the response body, set from
+/// `--show-error-body`.
+static SHOW_ERROR_BODY: AtomicBool = AtomicBool::new(true);
+
+/// How long reading an error response's body may take.
+const ERROR_BODY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// With the `test-hooks` feature, a response with this header panics the
+/// run, for the tests of what a panic mid-download leaves behind.
+#[cfg(feature = "test-hooks")]
+pub const TEST_PANIC: &str = "x-test-panic";
+
+pub fn show_error_body(show: bool) {
+    SHOW_ERROR_BODY.store(show, Ordering::Relaxed);
+}
+
+pub fn show_secrets(show: bool) {
+    SHOW_SECRETS.store(show, Ordering::Relaxed);
+}
+
+/// Sends every request as HTTP/3 from now on. Only clients built with
+/// HTTP/3 prior knowledge can carry them.
+pub fn use_http3(on: bool) {
+    HTTP3.store(on, Ordering::Relaxed);
+}
+
+/// Whether an `Alt-Svc` header value offers HTTP/3 on the same host and port
+/// as `url`, which is all reqwest can connect to.
+pub fn advertises_h3(alt_svc: &str, url: &Url) -> bool {
+    alt_svc.split(',').any(|service| {
+        let service = service.split(';').next().unwrap_or_default().trim();
+        let Some(authority) = service.strip_prefix("h3=") else {
+            return false;
+        };
+        let Some((host, port)) = authority.trim_matches('"').rsplit_once(':') else {
+            return false;
+        };
+        (host.is_empty() || Some(host) == url.host_str())
+            && port.parse().ok() == url.port_or_known_default()
+    })
+}
+
+/// The `Authorization` value of Basic auth as `user` with `password`.
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        BASE64_STANDARD.encode(format!("{user}:{password}"))
+    )
+}
+
+/// `url` with any password replaced, unless `--show-secrets` was given.
+pub fn redact_url(url: &Url) -> String {
+    if url.password().is_none() || SHOW_SECRETS.load(Ordering::Relaxed) {
+        return url.to_string();
+    }
+    let mut url = url.clone();
+    let _ = url.set_password(Some("redacted"));
+    url.to_string()
+}
+
+/// Sends `request`, dumping its headers and the response's at debug level
+/// (`-vv`), like `curl -v`. Bodies are never dumped. `chunk` prefixes the
+/// lines in worker mode.
+///
+/// With `auth`, from `--auth-token-url`, it goes out with the token, and
+/// once more with a new one if that's refused.
+pub async fn send(
+    request: reqwest::RequestBuilder,
+    chunk: Option<usize>,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<reqwest::Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    if HTTP3.load(Ordering::Relaxed) {
+        *request.version_mut() = Version::HTTP_3;
+    }
+    let token = auth.and_then(|auth| auth.authorize(request.headers_mut()));
+    let again = token.and_then(|_| request.try_clone());
+    let response = execute(&client, request, chunk).await?;
+    match (auth, token, again) {
+        (Some(auth), Some(used), Some(mut again))
+            if response.status() == StatusCode::UNAUTHORIZED
+                && auth.refresh(&client, used).await =>
+        {
+            again.headers_mut().remove(header::AUTHORIZATION);
+            auth.authorize(again.headers_mut());
+            execute(&client, again, chunk).await
+        }
+        _ => Ok(response),
+    }
+}
+
+async fn execute(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    chunk: Option<usize>,
+) -> reqwest::Result<reqwest::Response> {
+    log_request(
+        request.method(),
+        request.url(),
+        request.version(),
+        request.headers(),
+        chunk,
+    );
+    let recorded = transcript::request(request.method(), request.url(), request.headers(), chunk);
+    connections::count_request();
+    let response = client
+        .execute(request)
+        .await
+        .inspect_err(|error| transcript::failed(recorded, error))?;
+    transcript::response(recorded, response.status(), response.headers());
+    log_response(
+        response.version(),
+        response.status(),
+        response.headers(),
+        chunk,
+    );
+    record_response(response.url(), response.status(), response.headers());
+    Ok(response)
+}
+
+/// [`send`] for the blocking client.
+#[cfg(feature = "blocking")]
+pub fn send_blocking(
+    request: reqwest::blocking::RequestBuilder,
+    chunk: Option<usize>,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    if HTTP3.load(Ordering::Relaxed) {
+        *request.version_mut() = Version::HTTP_3;
+    }
+    let token = auth.and_then(|auth| auth.authorize(request.headers_mut()));
+    let again = token.and_then(|_| request.try_clone());
+    let response = execute_blocking(&client, request, chunk)?;
+    match (auth, token, again) {
+        (Some(auth), Some(used), Some(mut again))
+            if response.status() == StatusCode::UNAUTHORIZED
+                && auth.refresh_blocking(&client, used) =>
+        {
+            again.headers_mut().remove(header::AUTHORIZATION);
+            auth.authorize(again.headers_mut());
+            execute_blocking(&client, again, chunk)
+        }
+        _ => Ok(response),
+    }
+}
+
+#[cfg(feature = "blocking")]
+fn execute_blocking(
+    client: &reqwest::blocking::Client,
+    request: reqwest::blocking::Request,
+    chunk: Option<usize>,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    log_request(
+        request.method(),
+        request.url(),
+        request.version(),
+        request.headers(),
+        chunk,
+    );
+    let recorded = transcript::request(request.method(), request.url(), request.headers(), chunk);
+    connections::count_request();
+    let response = client
+        .execute(request)
+        .inspect_err(|error| transcript::failed(recorded, error))?;
+    transcript::response(recorded, response.status(), response.headers());
+    log_response(
+        response.version(),
+        response.status(),
+        response.headers(),
+        chunk,
+    );
+    record_response(response.url(), response.status(), response.headers());
+    Ok(response)
+}
+
+/// How many times a request answered with a 5xx is sent again before the
+/// error is given up on.
+pub const MAX_SERVER_ERROR_RETRIES: u32 = 3;
+
+/// The wait before the first re-send after a 5xx, doubled for each one
+/// after that.
+const SERVER_ERROR_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The longest `Retry-After` that is waited out; a server asking for more
+/// gets the regular backoff.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// What a response's status means for a download.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusClass {
+    /// 200, 201, 203 and 206: the body is the file, or the requested part
+    /// of it.
+    Content,
+    /// 204 and 205: there is no body, so the file is empty.
+    Empty,
+    /// A 3xx the client's redirect policy didn't follow.
+    Redirect,
+    /// 401: the server wants credentials.
+    Unauthorized,
+    /// 407: the proxy wants credentials.
+    ProxyUnauthorized,
+    /// 416: the requested range is outside the file.
+    RangeNotSatisfiable,
+    /// Any other 4xx.
+    ClientError,
+    /// A 5xx, worth asking again.
+    ServerError,
+    /// 1xx, 202 and the rarer 2xx, none of which carry the file.
+    Other,
+}
+
+impl StatusClass {
+    pub fn of(status: StatusCode) -> Self {
+        match status.as_u16() {
+            200 | 201 | 203 | 206 => StatusClass::Content,
+            204 | 205 => StatusClass::Empty,
+            300..=399 => StatusClass::Redirect,
+            401 => StatusClass::Unauthorized,
+            407 => StatusClass::ProxyUnauthorized,
+            416 => StatusClass::RangeNotSatisfiable,
+            400..=499 => StatusClass::ClientError,
+            500..=599 => StatusClass::ServerError,
+            _ => StatusClass::Other,
+        }
+    }
+}
+
+/// [`send`], sending the request again up to [`MAX_SERVER_ERROR_RETRIES`]
+/// times while the server answers with a 5xx and `budget` allows. The
+/// waits back off exponentially, or follow the server's `Retry-After`. The
+/// last response is returned whatever its status, for the caller to deal
+/// with.
+pub async fn send_retrying(
+    request: reqwest::RequestBuilder,
+    chunk: Option<usize>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        // A streamed body can't be sent twice.
+        let Some(retry) = request.try_clone() else {
+            return send(request, chunk, auth).await;
+        };
+        let response = send(retry, chunk, auth).await?;
+        let Some(wait) = server_error_wait(&response, attempt, chunk, budget) else {
+            return Ok(response);
+        };
+        attempt += 1;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// [`send_retrying`] for the blocking client.
+#[cfg(feature = "blocking")]
+pub fn send_retrying_blocking(
+    request: reqwest::blocking::RequestBuilder,
+    chunk: Option<usize>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    let mut attempt = 0;
+    loop {
+        let Some(retry) = request.try_clone() else {
+            return send_blocking(request, chunk, auth);
+        };
+        let response = send_blocking(retry, chunk, auth)?;
+        let Some(wait) = server_error_wait(&response, attempt, chunk, budget) else {
+            return Ok(response);
+        };
+        attempt += 1;
+        std::thread::sleep(wait);
+    }
+}
+
+/// How long to wait before sending a request again that got `response`,
+/// or `None` when it isn't a 5xx or the retries, or `budget`, are used up.
+fn server_error_wait(
+    response: &impl StatusResponse,
+    attempt: u32,
+    chunk: Option<usize>,
+    budget: &RetryBudget,
+) -> Option<Duration> {
+    let status = response.status();
+    if StatusClass::of(status) != StatusClass::ServerError || attempt >= MAX_SERVER_ERROR_RETRIES {
+        return None;
+    }
+    let wait = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+        .filter(|wait| *wait <= MAX_RETRY_AFTER)
+        .unwrap_or(SERVER_ERROR_BACKOFF * 2u32.pow(attempt));
+    let reason = format!("server answered {status}");
+    budget.spend(chunk, attempt as usize + 1, &reason).ok()?;
+    eprintln!(
+        "Server answered {status}, retrying in {:.2}s ({}/{MAX_SERVER_ERROR_RETRIES})",
+        wait.as_secs_f64(),
+        attempt + 1
+    );
+    Some(wait)
+}
+
+/// The parts of a response, async or blocking, that decide a retry.
+trait StatusResponse {
+    fn status(&self) -> StatusCode;
+    fn headers(&self) -> &HeaderMap;
+}
+
+impl StatusResponse for reqwest::Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl StatusResponse for reqwest::blocking::Response {
+    fn status(&self) -> StatusCode {
+        self.status()
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+/// Passes on a response to a request without a `Range` header if it
+/// carries the file or says the file is empty, and fails with the
+/// [`DownloadError`] for its [`StatusClass`] otherwise. A 416 is the
+/// server's bug here, since nothing was asked for that could be out of
+/// range.
+pub async fn check_status(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    match StatusClass::of(response.status()) {
+        StatusClass::Content | StatusClass::Empty => Ok(response),
+        StatusClass::RangeNotSatisfiable => Err(DownloadError::RangeNotRequested.into()),
+        _ => Err(unexpected_status(response).await.into()),
+    }
+}
+
+/// [`check_status`] for the blocking client.
+#[cfg(feature = "blocking")]
+pub fn check_status_blocking(
+    response: reqwest::blocking::Response,
+) -> anyhow::Result<reqwest::blocking::Response> {
+    match StatusClass::of(response.status()) {
+        StatusClass::Content | StatusClass::Empty => Ok(response),
+        StatusClass::RangeNotSatisfiable => Err(DownloadError::RangeNotRequested.into()),
+        _ => Err(unexpected_status_blocking(response).into()),
+    }
+}
+
+/// The error for a response with a status the caller can't handle, with
+/// the gist of its body (`--show-error-body`). At most
+/// [`MAX_ERROR_BODY`](error_body::MAX_ERROR_BODY) bytes are read, and only
+/// for as long as [`ERROR_BODY_TIMEOUT`].
+pub async fn unexpected_status(response: reqwest::Response) -> DownloadError {
+    let status = response.status();
+    let (url, headers) = (response.url().clone(), response.headers().clone());
+    let show_body = SHOW_ERROR_BODY.load(Ordering::Relaxed);
+    if !show_body && !maybe_expired(status, &url) {
+        return status_error(status, &url, &headers, None);
+    }
+    let content_type = content_type(response.headers());
+    let mut response = response;
+    let mut body = Vec::new();
+    let read = async {
+        while body.len() < error_body::MAX_ERROR_BODY
+            && let Ok(Some(chunk)) = response.chunk().await
+        {
+            body.extend_from_slice(&chunk);
+        }
+    };
+    let _ = tokio::time::timeout(ERROR_BODY_TIMEOUT, read).await;
+    body.truncate(error_body::MAX_ERROR_BODY);
+    body_error(status, &url, &headers, content_type, &body, show_body)
+}
+
+/// [`unexpected_status`] for the blocking client, whose own timeout bounds
+/// the read.
+#[cfg(feature = "blocking")]
+pub fn unexpected_status_blocking(response: reqwest::blocking::Response) -> DownloadError {
+    use std::io::Read;
+
+    let status = response.status();
+    let (url, headers) = (response.url().clone(), response.headers().clone());
+    let show_body = SHOW_ERROR_BODY.load(Ordering::Relaxed);
+    if !show_body && !maybe_expired(status, &url) {
+        return status_error(status, &url, &headers, None);
+    }
+    let content_type = content_type(response.headers());
+    let mut body = Vec::new();
+    let _ = response
+        .take(error_body::MAX_ERROR_BODY as u64)
+        .read_to_end(&mut body);
+    body_error(status, &url, &headers, content_type, &body, show_body)
+}
+
+/// Whether `status` may be a presigned `url` that expired, which only its
+/// body tells, so it's read even without `--show-error-body`.
+fn maybe_expired(status: StatusCode, url: &Url) -> bool {
+    status == StatusCode::FORBIDDEN && presigned::is_presigned(url)
+}
+
+/// The error for a response with `body`, summarized in it if `show_body`.
+fn body_error(
+    status: StatusCode,
+    url: &Url,
+    headers: &HeaderMap,
+    content_type: Option<String>,
+    body: &[u8],
+    show_body: bool,
+) -> DownloadError {
+    if maybe_expired(status, url)
+        && let Some(error) = presigned::expired(url, body)
+    {
+        return error;
+    }
+    let body = show_body
+        .then(|| error_body::summarize(content_type.as_deref(), body))
+        .flatten();
+    status_error(status, url, headers, body)
+}
+
+/// The [`DownloadError`] for `status`'s [`StatusClass`], in answer to a
+/// request for `url`.
+fn status_error(
+    status: StatusCode,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Option<String>,
+) -> DownloadError {
+    match StatusClass::of(status) {
+        StatusClass::Unauthorized => DownloadError::Unauthorized { body },
+        StatusClass::ProxyUnauthorized => proxy::unauthorized(url, headers, body),
+        StatusClass::ServerError => DownloadError::ServerError { status, body },
+        StatusClass::ClientError if rate_limit::is_rate_limited(status, headers) => {
+            DownloadError::RateLimited {
+                status,
+                resets_in: rate_limit::Quota::parse(headers, SystemTime::now())
+                    .and_then(|quota| quota.resets_in(SystemTime::now())),
+                body,
+            }
+        }
+        StatusClass::Redirect => DownloadError::UnfollowedRedirect {
+            status,
+            location: location(headers),
+        },
+        _ => DownloadError::UnexpectedStatus { status, body },
+    }
+}
+
+fn location(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The headers of a request for `range` (`bytes=...`). It asks for the
+/// plain bytes with `Accept-Encoding: identity`: some CDNs compress on the
+/// fly, and offsets into one compressed response mean nothing in another.
+pub fn range_headers(range: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(range) = range.parse() {
+        headers.insert(header::RANGE, range);
+    }
+    headers.insert(
+        header::ACCEPT_ENCODING,
+        header::HeaderValue::from_static("identity"),
+    );
+    headers
+}
+
+/// The `Content-Encoding` a response came with, unless it's the plain bytes.
+/// Nothing decodes it: the body is saved as it's sent, and its
+/// `Content-Length` is of the encoded bytes.
+pub fn content_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty() && !encoding.eq_ignore_ascii_case("identity"))
+}
+
+/// Fails if a response to [`range_headers`] came encoded anyway.
+pub fn check_identity(headers: &HeaderMap) -> Result<(), DownloadError> {
+    match content_encoding(headers) {
+        Some(encoding) => Err(DownloadError::EncodedRange {
+            encoding: encoding.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks the encoding of a whole file's response before any of it is
+/// written to `path`: with `--no-compression` it must be the plain bytes,
+/// otherwise an encoded one is saved as it is, saying so.
+pub fn check_encoding(
+    headers: &HeaderMap,
+    path: &Path,
+    no_compression: bool,
+) -> Result<(), DownloadError> {
+    let Some(encoding) = content_encoding(headers) else {
+        return Ok(());
+    };
+    if no_compression {
+        return Err(DownloadError::Encoded {
+            encoding: encoding.to_string(),
+        });
+    }
+    eprintln!(
+        "The server sent '{}' with Content-Encoding: {encoding}, saving it as sent; --no-compression asks for the plain bytes",
+        path.display()
+    );
+    Ok(())
+}
+
+/// The `Content-Range` of a 206 response.
+pub(crate) fn content_range(response: &reqwest::Response) -> anyhow::Result<ContentRange> {
+    let header = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .context("206 response without a Content-Range header")?
+        .to_str()
+        .context("Content-Range header is not valid text")?;
+    utils::parse_content_range(header)
+        .with_context(|| format!("Malformed Content-Range header '{header}'"))
+}
+
+/// The remote size a 416 response gives in its `Content-Range`.
+pub(crate) fn unsatisfied_total(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(utils::unsatisfied_range_total)
+}
+
+/// `--restart-on-unresumable`: says why resuming at `offset` is impossible
+/// and that the bytes so far are thrown away.
+pub(crate) fn announce_restart(offset: usize, reason: &str) {
+    eprintln!(
+        "Cannot resume at byte {offset}: {reason}. Discarding the {} downloaded so far and starting over",
+        indicatif::HumanBytes(offset as u64)
+    );
+}
+
+/// Why a `bytes=<offset>-` request got a 416: either the file is already
+/// complete or the offset is past the end of the remote file.
+pub fn unsatisfiable(headers: &HeaderMap, offset: usize) -> String {
+    match unsatisfied_total(headers) {
+        Some(total) if offset as u64 > total => {
+            format!("Offset {offset} is beyond the remote size ({total} bytes)")
+        }
+        _ => "File already complete".to_string(),
+    }
+}
+
+fn log_request(
+    method: &Method,
+    url: &Url,
+    version: Version,
+    headers: &HeaderMap,
+    chunk: Option<usize>,
+) {
+    if !tracing::enabled!(target: "dlm::http", tracing::Level::DEBUG) {
+        return;
+    }
+    let prefix = prefix(chunk, '>');
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    tracing::debug!(target: "dlm::http", "{prefix}{method} {path} {version:?}");
+    if let Some(host) = url.host_str() {
+        match url.port() {
+            Some(port) => tracing::debug!(target: "dlm::http", "{prefix}host: {host}:{port}"),
+            None => tracing::debug!(target: "dlm::http", "{prefix}host: {host}"),
+        }
+    }
+    log_headers(&prefix, headers);
+}
+
+fn log_response(version: Version, status: StatusCode, headers: &HeaderMap, chunk: Option<usize>) {
+    if !tracing::enabled!(target: "dlm::http", tracing::Level::DEBUG) {
+        return;
+    }
+    let prefix = prefix(chunk, '<');
+    tracing::debug!(target: "dlm::http", "{prefix}{version:?} {status}");
+    log_headers(&prefix, headers);
+}
+
+fn log_headers(prefix: &str, headers: &HeaderMap) {
+    for (name, value) in headers {
+        let value = shown_value(name, value);
+        tracing::debug!(target: "dlm::http", "{prefix}{name}: {value}");
+    }
+}
+
+/// A header's value as it may be shown, credentials redacted unless
+/// `--show-secrets`.
+fn shown_value<'a>(name: &HeaderName, value: &'a header::HeaderValue) -> &'a str {
+    if !SHOW_SECRETS.load(Ordering::Relaxed) && SECRET_HEADERS.contains(name) {
+        "<redacted>"
+    } else {
+        value.to_str().unwrap_or("<binary>")
+    }
+}
+
+fn record_response(url: &Url, status: StatusCode, headers: &HeaderMap) {
+    #[cfg(feature = "test-hooks")]
+    if let Some(reason) = headers.get(TEST_PANIC) {
+        panic!("{TEST_PANIC}: {}", reason.to_str().unwrap_or_default());
+    }
+    clock::observe(headers);
+    rate_limit::observe(url, headers);
+    diagnostics::record_response(Exchange {
+        url: redact_url(url),
+        status: status.as_u16(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), shown_value(name, value).to_string()))
+            .collect(),
+    });
+}
+
+fn prefix(chunk: Option<usize>, direction: char) -> String {
+    match chunk {
+        Some(chunk) => format!("[chunk {chunk}] {direction} "),
+        None => format!("{direction} "),
+    }
+}
@@ -0,0 +1,140 @@
+//! `--hybrid-streaming`: worker mode for a file that's read while it
+//! downloads, like a video or a disk image. The first chunk streams
+//! straight into the file from byte zero, while the other workers fetch
+//! the later chunks into parts as usual. Once the first chunk is done, the
+//! frontier moves through the parts in order, appending each to the file
+//! as soon as the part has it, and following a part that's still
+//! downloading as its worker writes it. The file is a valid start of the
+//! download at all times, so it can be opened early, and `--resume`
+//! carries on from it like from any partial file. With `--chunk-order
+//! sequential` the file is split into more segments than workers, taken
+//! from its start on, so the frontier keeps close behind the workers.
+
+use crate::download::chunks::ChunkOrder;
+use crate::download::fs_ops;
+use crate::download::options::TransferOptions;
+use crate::download::progress::{ChunkState, TransferProgress};
+use crate::download::target_wait;
+use anyhow::bail;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How often the frontier looks for more of the part it's waiting on.
+const POLL: Duration = Duration::from_millis(50);
+
+/// Bytes copied from a part at a time.
+const COPY_BUFFER: usize = 256 * 1024;
+
+/// Refuses what would write anywhere but the end of the file, or needs
+/// something already there.
+pub(crate) fn check(workers: u8, options: &TransferOptions) -> anyhow::Result<()> {
+    let clashes = [
+        ("--resume", options.resume),
+        ("--resume-from", options.resume_from.is_some()),
+        ("--piece-hashes", options.pieces.is_some()),
+        ("--store-compressed", options.store_compressed.is_some()),
+        (
+            "--chunk-order spread",
+            options.chunk_order == Some(ChunkOrder::Spread),
+        ),
+    ];
+    if let Some((flag, _)) = clashes.iter().find(|(_, set)| *set) {
+        bail!(
+            "--hybrid-streaming writes the file from its start, in order, so it can't be used with {flag}"
+        );
+    }
+    if workers < 2 {
+        bail!("--hybrid-streaming needs --workers 2 or more, one to stream the start");
+    }
+    Ok(())
+}
+
+/// Appends `parts`, the parts of chunks 1 onwards with their lengths, to
+/// `final_path` once chunk 0 has streamed into it, each part as soon as
+/// its chunk has written it. Returns early when a chunk fails or the
+/// download is interrupted, leaving the error to the chunk's task.
+pub(crate) async fn advance_frontier(
+    final_path: PathBuf,
+    parts: Vec<(PathBuf, u64)>,
+    progress: TransferProgress,
+    no_cleanup: bool,
+) -> anyhow::Result<()> {
+    if !wait_for(&progress, 0).await {
+        return Ok(());
+    }
+    let mut open = OpenOptions::new();
+    open.append(true);
+    let mut file = target_wait::retry_async("open", &final_path, || {
+        fs_ops::open_async(&open, &final_path)
+    })
+    .await?;
+    let mut buffer = vec![0; COPY_BUFFER];
+    for (index, (part_path, length)) in parts.iter().enumerate() {
+        let chunk = index + 1;
+        // The part is created once its chunk starts.
+        let mut part = loop {
+            match tokio::fs::File::open(part_path).await {
+                Ok(part) => break part,
+                Err(_) if stopped(&progress, chunk) => return Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    tokio::time::sleep(POLL).await
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+        let mut copied = 0;
+        while copied < *length {
+            let done = progress.chunk_states()[chunk] == ChunkState::Completed;
+            let read = part.read(&mut buffer).await?;
+            if read > 0 {
+                file.write_all(&buffer[..read]).await?;
+                copied += read as u64;
+                continue;
+            }
+            if done {
+                bail!("The part of chunk {chunk} ended after {copied} of its {length} bytes",);
+            }
+            if stopped(&progress, chunk) {
+                return Ok(());
+            }
+            // Let whoever reads the file see what's there so far.
+            file.flush().await?;
+            tokio::time::sleep(POLL).await;
+        }
+        file.flush().await?;
+        tracing::debug!(
+            "Frontier at the end of chunk {chunk}, '{}' holds its first {} parts",
+            final_path.display(),
+            chunk + 1
+        );
+        drop(part);
+        if !no_cleanup {
+            fs_ops::remove_file_async(part_path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for `chunk` to complete; `false` if the download stopped instead.
+async fn wait_for(progress: &TransferProgress, chunk: usize) -> bool {
+    loop {
+        if progress.chunk_states()[chunk] == ChunkState::Completed {
+            return true;
+        }
+        if stopped(progress, chunk) {
+            return false;
+        }
+        tokio::time::sleep(POLL).await;
+    }
+}
+
+/// Whether `chunk` hasn't finished and won't: it failed, or the download
+/// was interrupted.
+fn stopped(progress: &TransferProgress, chunk: usize) -> bool {
+    progress.interrupted.load(Ordering::SeqCst)
+        || progress.chunk_states()[chunk] == ChunkState::Failed
+}
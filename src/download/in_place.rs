@@ -0,0 +1,286 @@
+//! How worker mode writes unless `--part-files` or a flag that needs them
+//! asks otherwise: without part files. The file, `<name>.part` until it's
+//! whole as with any download, is sized to the download up front and every
+//! chunk writes straight into it at its own offset, so there's no merge to
+//! wait for and no second copy of the file on disk. As an unfinished file
+//! is as long as a whole one, how much each chunk wrote is kept beside it
+//! in `<name>.download-state`, which `--resume` carries each chunk on from.
+
+use crate::download::file_watch::FileWatch;
+use crate::download::fs_ops;
+use crate::download::options::TransferOptions;
+use crate::download::parts::PartLayout;
+use crate::download::target_wait;
+use crate::download::utils::{self, MAX_FILE_NAME};
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Suffix of the sidecar beside the file.
+const SUFFIX: &str = ".download-state";
+
+/// How often the sidecar is rewritten while the chunks run, so a crash
+/// loses at most this much of what was written.
+const SAVE_EVERY: Duration = Duration::from_secs(1);
+
+/// What's in the sidecar.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Written {
+    /// The [`PartLayout`] id of the download, telling whether the file is
+    /// still being written for the same URL and size.
+    id: String,
+    /// The inclusive byte range of each chunk.
+    ranges: Vec<(u64, u64)>,
+    /// Bytes each chunk has written from the start of its range.
+    written: Vec<u64>,
+}
+
+/// How far each chunk of an in-place download got, shared by the chunks
+/// and saved to the sidecar.
+#[derive(Clone)]
+pub(crate) struct InPlace {
+    path: PathBuf,
+    state: Arc<Mutex<Written>>,
+    /// Notices the file deleted, replaced or cut short underneath it.
+    watch: Arc<Mutex<FileWatch>>,
+}
+
+/// Where a chunk records how much of its range it has written.
+#[derive(Clone)]
+pub(crate) struct Progress {
+    state: Arc<Mutex<Written>>,
+    watch: Arc<Mutex<FileWatch>>,
+    index: usize,
+    /// What an earlier run wrote of the range, before this one started.
+    base: u64,
+}
+
+/// The flag that needs part files, if one is set.
+pub(crate) fn unavailable(options: &TransferOptions) -> Option<&'static str> {
+    let needs_parts = [
+        ("--part-files", options.part_files),
+        ("--hybrid-streaming", options.hybrid_streaming),
+        ("--resume-from", options.resume_from.is_some()),
+        ("--store-compressed", options.store_compressed.is_some()),
+        ("--no-cleanup", options.no_cleanup),
+    ];
+    needs_parts
+        .into_iter()
+        .find_map(|(flag, set)| set.then_some(flag))
+}
+
+/// Refuses to carry on with a file left unfinished in place with a flag
+/// that needs part files.
+pub(crate) fn check(final_path: &Path, options: &TransferOptions) -> anyhow::Result<()> {
+    if let Some(flag) = unavailable(options) {
+        bail!(
+            "'{}' was left unfinished without part files, so it can't be carried on with {flag}; pass --overwrite to start over",
+            final_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// The sidecar of `final_path`. It's left behind by a download that didn't
+/// finish, whose file can't be told from a whole one by its size.
+pub fn state_path(final_path: &Path) -> PathBuf {
+    let name = final_path.file_name().unwrap_or_default().to_string_lossy();
+    let name = utils::truncate_file_name(&name, MAX_FILE_NAME - SUFFIX.len());
+    utils::long_path(final_path.with_file_name(format!("{name}{SUFFIX}")))
+}
+
+/// Where `file` is sized before it's given its name.
+fn sizing_path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let name = utils::truncate_file_name(&name, MAX_FILE_NAME - ".partial".len());
+    utils::long_path(file.with_file_name(format!("{name}.partial")))
+}
+
+/// Whether an in-place download into `final_path` was left unfinished.
+pub fn unfinished(final_path: &Path) -> bool {
+    state_path(final_path).is_file()
+}
+
+impl InPlace {
+    /// Carries on from the sidecar beside `final_path` if `--resume` and it
+    /// is of this download, or else starts `ranges` afresh after the
+    /// `prefix` already there, sizing `file`, which is renamed to
+    /// `final_path` once whole, to `content_length`. Returns the chunks,
+    /// each the rest of its range, and how much is already there.
+    pub(crate) async fn start(
+        layout: &PartLayout,
+        final_path: &Path,
+        file: &Path,
+        content_length: u64,
+        prefix: u64,
+        options: &TransferOptions,
+    ) -> anyhow::Result<(Self, PartLayout, u64)> {
+        let path = state_path(final_path);
+        let whole_length =
+            std::fs::metadata(file).is_ok_and(|metadata| metadata.len() == content_length);
+        let earlier = match options.resume && !options.overwrite && whole_length {
+            true => std::fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<Written>(&json).ok()),
+            false => None,
+        };
+        let written = match earlier {
+            Some(earlier) if earlier.id == layout.id => {
+                // A resumed chunk starts at a whole piece.
+                let alignment = options.chunk_alignment();
+                let written = earlier
+                    .written
+                    .iter()
+                    .map(|written| written - written % alignment)
+                    .collect();
+                Written { written, ..earlier }
+            }
+            Some(_) => bail!(
+                "'{}' was being written for another download, or the remote file changed since; pass --overwrite to start over",
+                file.display()
+            ),
+            None => {
+                // Sized under another name, so the file is never seen
+                // shorter than the download.
+                let sizing = sizing_path(file);
+                if prefix > 0 {
+                    fs_ops::rename_async(file, &sizing).await?;
+                }
+                let mut open = tokio::fs::OpenOptions::new();
+                open.create(true).write(true).truncate(false);
+                let sized = target_wait::retry_async("create", &sizing, || {
+                    fs_ops::open_async(&open, &sizing)
+                })
+                .await?;
+                // Anything past what's kept is of no use.
+                fs_ops::set_len_async(&sized, &sizing, prefix).await?;
+                fs_ops::set_len_async(&sized, &sizing, content_length).await?;
+                drop(sized);
+                fs_ops::rename_async(&sizing, file).await?;
+                Written {
+                    id: layout.id.clone(),
+                    ranges: layout.ranges.clone(),
+                    written: vec![0; layout.ranges.len()],
+                }
+            }
+        };
+        // The ranges follow on from what was kept ahead of them.
+        let kept = written
+            .ranges
+            .first()
+            .map_or(content_length, |(start, _)| *start)
+            + written.written.iter().sum::<u64>();
+        let mut rest = PartLayout {
+            id: written.id.clone(),
+            ranges: Vec::new(),
+            paths: Vec::new(),
+            sha256: Vec::new(),
+        };
+        for ((start, end), written) in written.ranges.iter().zip(&written.written) {
+            rest.ranges.push((start + written, *end));
+            rest.paths.push(file.to_path_buf());
+        }
+        let metadata = std::fs::metadata(file)?;
+        let in_place = Self {
+            path,
+            state: Arc::new(Mutex::new(written)),
+            watch: Arc::new(Mutex::new(FileWatch::new(file, &metadata, content_length))),
+        };
+        in_place.save()?;
+        Ok((in_place, rest, kept))
+    }
+
+    /// The chunks of [`start`](Self::start)'s layout that have anything
+    /// left to write.
+    pub(crate) fn unfinished(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+        (0..state.ranges.len())
+            .filter(|index| {
+                let (start, end) = state.ranges[*index];
+                start + state.written[*index] <= end
+            })
+            .collect()
+    }
+
+    /// The bytes chunk `index` had written of its range when this run
+    /// started.
+    pub(crate) fn written(&self, index: usize) -> u64 {
+        self.progress(index).base
+    }
+
+    /// Where chunk `index` records what it writes.
+    pub(crate) fn progress(&self, index: usize) -> Progress {
+        let base = self.state.lock().unwrap().written[index];
+        Progress {
+            state: self.state.clone(),
+            watch: self.watch.clone(),
+            index,
+            base,
+        }
+    }
+
+    /// Hands bytes `at` on of chunk `index`'s range to a new chunk, as
+    /// [`PartLayout::split`] does with the layout, which it must be called
+    /// along with so the two agree on the new chunk's index.
+    pub(crate) fn split(&self, index: usize, at: u64) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let end = state.ranges[index].1;
+        state.ranges[index].1 = at - 1;
+        state.ranges.push((at, end));
+        state.written.push(0);
+        state.ranges.len() - 1
+    }
+
+    /// Fails if the file isn't the one being written anymore, or isn't as
+    /// long as the download.
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        self.watch.lock().unwrap().check()
+    }
+
+    /// Rewrites the sidecar every [`SAVE_EVERY`] until aborted.
+    pub(crate) fn keep_saving(&self) -> JoinHandle<()> {
+        let in_place = self.clone();
+        tokio::spawn(async move {
+            let mut every = tokio::time::interval(SAVE_EVERY);
+            loop {
+                every.tick().await;
+                if let Err(error) = in_place.save() {
+                    tracing::warn!("{error:#}");
+                }
+            }
+        })
+    }
+
+    /// Writes the sidecar through a rename, so a crash never leaves half
+    /// of one.
+    pub(crate) fn save(&self) -> anyhow::Result<()> {
+        let json = serde_json::to_vec(&*self.state.lock().unwrap())?;
+        let partial = self.path.with_extension("download-state.partial");
+        fs_ops::write(&partial, json)
+            .and_then(|()| fs_ops::rename(&partial, &self.path))
+            .with_context(|| format!("Cannot write '{}'", self.path.display()))
+    }
+
+    /// Removes the sidecar, once the file is whole.
+    pub(crate) fn finish(&self) -> anyhow::Result<()> {
+        fs_ops::remove_file(&self.path)
+            .with_context(|| format!("Cannot remove '{}'", self.path.display()))
+    }
+}
+
+impl Progress {
+    /// Records that the first `bytes` of what this run fetched for the
+    /// chunk are in the file, and looks at the file now and then.
+    pub(crate) fn wrote(&self, bytes: u64) -> io::Result<()> {
+        self.state.lock().unwrap().written[self.index] = self.base + bytes;
+        let mut watch = self.watch.lock().unwrap();
+        match watch.due() {
+            true => watch.check().map_err(io::Error::other),
+            false => Ok(()),
+        }
+    }
+}
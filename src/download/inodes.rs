@@ -0,0 +1,37 @@
+use anyhow::bail;
+use std::path::Path;
+
+/// Files that can still be created on the filesystem holding `path`, if it
+/// has a fixed number of inodes. Filesystems that allocate them as needed,
+/// like btrfs, report none and aren't checked.
+#[cfg(unix)]
+pub fn free_inodes(path: &Path) -> Option<u64> {
+    let stats = nix::sys::statvfs::statvfs(path).ok()?;
+    (stats.files() > 0).then(|| stats.files_available() as u64)
+}
+
+/// NTFS has no fixed number of files to run out of.
+#[cfg(not(unix))]
+pub fn free_inodes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Fails if the filesystem `file` goes on can't take `files` more files,
+/// so worker mode stops before fetching anything rather than with ENOSPC
+/// on the last part.
+pub fn check(file: &Path, files: u64) -> anyhow::Result<()> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(free) = free_inodes(dir) else {
+        return Ok(());
+    };
+    if free < files {
+        bail!(
+            "'{}' has room for {free} more files (inodes) but the download needs {files}, one per part; free some up or use fewer workers",
+            dir.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::http;
+use anyhow::bail;
+use futures::StreamExt;
+use reqwest::header;
+use url::Url;
+
+/// Pages larger than this aren't treated as landing pages.
+pub const MAX_LANDING_PAGE: u64 = 1024 * 1024;
+
+/// `--follow-landing-page`: when `url` serves a small HTML page instead of
+/// the file (SourceForge-style "your download will start shortly"), finds
+/// the real file's URL on it.
+///
+/// A meta refresh wins; otherwise the page needs exactly one link whose file
+/// name is `expected`, or failing that, one link with the same extension.
+/// Several candidates are an error listing them, so the user can pick one.
+/// Returns `None` when `url` isn't a landing page. The resolved URL is never
+/// looked at again, so this only ever goes one level deep.
+pub async fn resolve_landing_page(
+    client: &reqwest::Client,
+    url: &Url,
+    expected: Option<&str>,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Option<Url>> {
+    let response = http::send(client.get(url.as_str()), None, auth).await?;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim_start().starts_with("text/html"));
+    if !response.status().is_success()
+        || !is_html
+        || response.content_length().unwrap_or(0) > MAX_LANDING_PAGE
+    {
+        return Ok(None);
+    }
+
+    let base = response.url().clone();
+    let mut page = Vec::new();
+    let mut body = response.bytes_stream();
+    while let Some(data) = body.next().await {
+        page.extend_from_slice(&data?);
+        if page.len() as u64 > MAX_LANDING_PAGE {
+            return Ok(None);
+        }
+    }
+    let page = String::from_utf8_lossy(&page);
+
+    if let Some(target) = meta_refresh(&page).and_then(|target| base.join(&target).ok()) {
+        return Ok(Some(target));
+    }
+    let Some(expected) = expected else {
+        bail!(
+            "'{url}' is an HTML page without a meta refresh, and the URL has no file name to look for on it"
+        );
+    };
+    let links: Vec<Url> = tags(&page, "a")
+        .filter_map(|tag| attribute(tag, "href"))
+        .filter_map(|href| base.join(&href).ok())
+        .filter(|link| link.scheme() == "http" || link.scheme() == "https")
+        .collect();
+    let by_name = candidates(&links, |name| name == expected);
+    let found = match (by_name.is_empty(), extension(expected)) {
+        (true, Some(wanted)) => candidates(&links, |name| extension(name) == Some(wanted)),
+        _ => by_name,
+    };
+    match found.as_slice() {
+        [] => bail!("No link to '{expected}' found on the landing page '{url}'"),
+        [link] => Ok(Some(link.clone())),
+        several => {
+            let list: Vec<String> = several.iter().map(|link| format!("  {link}")).collect();
+            bail!(
+                "The landing page '{url}' links to several candidates, pass the right one instead:\n{}",
+                list.join("\n")
+            )
+        }
+    }
+}
+
+/// The file name `--follow-landing-page` looks for: the last path segment
+/// that has an extension, so `.../files/tool-1.2.tar.gz/download` expects
+/// `tool-1.2.tar.gz`.
+pub fn expected_file_name(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .rev()
+        .find(|segment| segment.contains('.'))
+        .map(str::to_string)
+}
+
+/// A file name's extension, keeping `.tar` in front of a compression one.
+fn extension(name: &str) -> Option<&str> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    match stem.len().checked_sub(4) {
+        Some(at)
+            if stem
+                .get(at..)
+                .is_some_and(|tar| tar.eq_ignore_ascii_case(".tar")) =>
+        {
+            Some(&name[at + 1..])
+        }
+        _ => Some(extension),
+    }
+}
+
+/// Distinct links whose file name passes `matches`, in page order.
+fn candidates(links: &[Url], matches: impl Fn(&str) -> bool) -> Vec<Url> {
+    let mut found: Vec<Url> = Vec::new();
+    for link in links {
+        let name = link
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default();
+        if matches(name) && !found.contains(link) {
+            found.push(link.clone());
+        }
+    }
+    found
+}
+
+/// The target of a `<meta http-equiv="refresh" content="0; url=...">`.
+fn meta_refresh(page: &str) -> Option<String> {
+    tags(page, "meta")
+        .filter(|tag| {
+            attribute(tag, "http-equiv").is_some_and(|value| value.eq_ignore_ascii_case("refresh"))
+        })
+        .find_map(|tag| {
+            let content = attribute(tag, "content")?;
+            let (_, target) = content.split_once(';')?;
+            let target = target.trim();
+            let (key, target) = target.split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("url")
+                .then(|| target.trim().trim_matches(['\'', '"']).to_string())
+        })
+}
+
+/// The insides of every `<name ...>` tag in `page`, case-insensitively.
+fn tags<'a>(page: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let lower = page.to_ascii_lowercase();
+    let open = format!("<{name}");
+    let mut starts = Vec::new();
+    let mut from = 0;
+    while let Some(at) = lower[from..].find(&open) {
+        let start = from + at + open.len();
+        from = start;
+        // `<a` must not match `<abbr` or `<area`.
+        if lower[start..].starts_with(|c: char| c.is_ascii_whitespace()) {
+            starts.push(start);
+        }
+    }
+    starts.into_iter().filter_map(move |start| {
+        let end = page[start..].find('>')?;
+        Some(&page[start..start + end])
+    })
+}
+
+/// The value of attribute `name` inside a tag, with `&amp;` decoded.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(at) = lower[from..].find(name) {
+        let start = from + at;
+        from = start + name.len();
+        let preceded = lower[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let rest = lower[from..].trim_start();
+        if !preceded || !rest.starts_with('=') {
+            continue;
+        }
+        let value = tag[tag.len() - rest.len() + 1..].trim_start();
+        let value = match value.chars().next()? {
+            quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+            _ => value.split(|c: char| c.is_ascii_whitespace()).next()?,
+        };
+        return Some(value.replace("&amp;", "&"));
+    }
+    None
+}
@@ -0,0 +1,185 @@
+//! Accounting of the big buffers (worker mode's write-behind, merging and
+//! hashing), and `--max-memory`: near the cap, new buffers come out smaller
+//! and chunks, merging and hashing wait for room rather than the downloads
+//! sharing it going past it.
+
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::speed::Size;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How often a chunk held back by the cap checks for room.
+const ROOM_POLL: Duration = Duration::from_millis(50);
+
+/// The smallest read buffer worth having: merging and hashing wait for
+/// this much room under the cap, or the whole cap if it's smaller.
+pub(crate) const MIN_BUFFER: usize = 64 * 1024;
+
+/// The buffer memory of every download that shares it, through an `Arc`
+/// in their [`TransferOptions`](crate::download::options::TransferOptions),
+/// and the cap on it.
+#[derive(Debug, Default)]
+pub struct MemoryLimit {
+    /// Bytes of buffers held right now.
+    in_use: AtomicU64,
+    /// The most `in_use` has been.
+    peak: AtomicU64,
+    /// `--max-memory`, `None` for no cap.
+    limit: Option<u64>,
+}
+
+impl MemoryLimit {
+    /// Caps buffer memory at `limit`, `None` for no cap.
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// The cap, if any.
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Bytes of buffers held right now; zero once every download is done.
+    pub fn in_use(&self) -> u64 {
+        self.in_use.load(Ordering::SeqCst)
+    }
+
+    /// The most buffer memory held at once so far.
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    /// Counts a `wanted`-byte buffer for `what`. Near the cap it gets what's
+    /// left, down to nothing, and the cut is logged.
+    pub(crate) fn reserve(self: &Arc<Self>, what: &str, wanted: usize) -> Reservation {
+        self.try_reserve(what, wanted, 0)
+            .expect("nothing is always left")
+    }
+
+    /// [`reserve`](Self::reserve) for a read buffer, which merging and
+    /// hashing can't do without: near the cap it gets what's left, but
+    /// waits until that's at least [`MIN_BUFFER`] rather than go past the
+    /// cap.
+    pub(crate) async fn reserve_buffer(self: &Arc<Self>, what: &str, wanted: usize) -> Reservation {
+        if let Some(reservation) = self.try_reserve(what, wanted, self.least()) {
+            return reservation;
+        }
+        self.warn_held_back(what);
+        loop {
+            tokio::time::sleep(ROOM_POLL).await;
+            if let Some(reservation) = self.try_reserve(what, wanted, self.least()) {
+                return reservation;
+            }
+        }
+    }
+
+    /// [`reserve_buffer`](Self::reserve_buffer) for blocking code.
+    pub(crate) fn reserve_buffer_blocking(
+        self: &Arc<Self>,
+        what: &str,
+        wanted: usize,
+    ) -> Reservation {
+        if let Some(reservation) = self.try_reserve(what, wanted, self.least()) {
+            return reservation;
+        }
+        self.warn_held_back(what);
+        loop {
+            std::thread::sleep(ROOM_POLL);
+            if let Some(reservation) = self.try_reserve(what, wanted, self.least()) {
+                return reservation;
+            }
+        }
+    }
+
+    /// The least a read buffer waits for.
+    fn least(&self) -> usize {
+        self.limit
+            .map_or(MIN_BUFFER, |limit| MIN_BUFFER.min(limit as usize))
+    }
+
+    /// Counts what's left of a `wanted`-byte buffer under the cap, unless
+    /// that's less than `least`.
+    fn try_reserve(
+        self: &Arc<Self>,
+        what: &str,
+        wanted: usize,
+        least: usize,
+    ) -> Option<Reservation> {
+        let wanted = wanted as u64;
+        let least = (least as u64).min(wanted);
+        let mut granted = wanted;
+        let held = self
+            .in_use
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |held| {
+                granted = match self.limit {
+                    Some(limit) => limit.saturating_sub(held).min(wanted),
+                    None => wanted,
+                };
+                (granted >= least).then_some(held + granted)
+            })
+            .ok()?;
+        self.peak.fetch_max(held + granted, Ordering::SeqCst);
+        if granted < wanted {
+            diagnostics::warn(
+                WarningId::MemoryCapped,
+                format!(
+                    "Buffers are near --max-memory ({} held), {what} gets {} instead of {}",
+                    Size(held),
+                    Size(granted),
+                    Size(wanted)
+                ),
+            );
+        }
+        Some(Reservation {
+            memory: self.clone(),
+            bytes: granted,
+        })
+    }
+
+    fn warn_held_back(&self, what: &str) {
+        diagnostics::warn(
+            WarningId::MemoryCapped,
+            format!(
+                "Buffers are at --max-memory ({} held), holding {what} back until some are freed",
+                Size(self.in_use())
+            ),
+        );
+    }
+
+    /// Waits until buffers are under the cap, for `what` to start. Logs the
+    /// wait, if there is one.
+    pub(crate) async fn wait_for_room(&self, what: &str) {
+        let full = || self.limit.is_some_and(|limit| self.in_use() >= limit);
+        if !full() {
+            return;
+        }
+        self.warn_held_back(what);
+        while full() {
+            tokio::time::sleep(ROOM_POLL).await;
+        }
+    }
+}
+
+/// Buffer memory counted in [`MemoryLimit::in_use`] until dropped.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    memory: Arc<MemoryLimit>,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// The buffer size granted, which may be less than asked for.
+    pub(crate) fn size(&self) -> usize {
+        self.bytes as usize
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.memory.in_use.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
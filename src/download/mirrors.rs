@@ -0,0 +1,171 @@
+//! `--mirror`: other URLs serving the same file, as distro images and
+//! datasets are published. They're probed alongside the URL given, and any
+//! that can't be reached, says the file is another size, or can't send
+//! ranges where the others can is left out. The download starts from the
+//! first that answered. A chunk that still fails once its retries are used
+//! up carries on from the next mirror, as does a single-stream download,
+//! from the bytes it has. Worker mode starts each chunk on the next mirror
+//! in turn, so a per-connection cap on one mirror holds up only its share.
+//!
+//! The file is named after the URL given, or `--output`, whatever a
+//! mirror's path or `Content-Disposition` say.
+
+use crate::download::async_range::get_content_length;
+use crate::download::auth_token::AuthToken;
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::error::DownloadError;
+use crate::download::http;
+use crate::download::options::TransferOptions;
+use crate::download::presigned;
+use crate::download::remote::{RemoteInfo, probe_remote};
+use crate::download::retry;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::utils;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// A URL the file is downloaded from, with the ETag its probe got, which
+/// the chunks it serves are compared with.
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub url: Url,
+    pub etag: Option<String>,
+}
+
+impl Source {
+    /// `url`, which answered its probe with `remote`. A presigned one is
+    /// replaced by where it redirected to, rather than each chunk following
+    /// the redirect again.
+    pub fn of(url: &Url, remote: &RemoteInfo) -> Self {
+        Source {
+            url: match presigned::is_presigned(url) {
+                true => remote.final_url.clone(),
+                false => url.clone(),
+            },
+            etag: remote.etag.clone(),
+        }
+    }
+}
+
+/// The URLs a download goes to, the one it started from first.
+pub type Sources = Arc<[Source]>;
+
+/// Probes `url` and `mirrors` at once, returning what the first to answer
+/// says about the file, and every source that agrees with it.
+pub async fn probe(
+    client: &reqwest::Client,
+    url: &Url,
+    mirrors: &[Url],
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<(RemoteInfo, Sources)> {
+    let urls: Vec<_> = std::iter::once(url).chain(mirrors).collect();
+    let probes = futures::future::join_all(
+        urls.iter()
+            .map(|url| probe_remote(client, url, budget, auth)),
+    )
+    .await;
+    let mut chosen: Option<RemoteInfo> = None;
+    let mut sources = Vec::new();
+    let mut failed = None;
+    for (url, probe) in urls.into_iter().zip(probes) {
+        let shown = http::redact_url(url);
+        let remote = match probe {
+            Ok(remote) => remote,
+            Err(error) => {
+                refuse(&shown, &format!("{error:#}"))?;
+                failed.get_or_insert(error);
+                continue;
+            }
+        };
+        let Some(first) = &chosen else {
+            sources.push(Source::of(url, &remote));
+            chosen = Some(remote);
+            continue;
+        };
+        if remote.size != first.size {
+            let size = |size: Option<u64>| {
+                size.map_or("of unknown size".to_string(), |size| {
+                    format!("{size} bytes")
+                })
+            };
+            let why = format!(
+                "it says the file is {}, not {}",
+                size(remote.size),
+                size(first.size)
+            );
+            refuse(&shown, &why)?;
+        } else if first.ranges && !remote.ranges {
+            refuse(&shown, "it doesn't accept range requests")?;
+        } else {
+            sources.push(Source::of(url, &remote));
+        }
+    }
+    match chosen {
+        Some(remote) => Ok((remote, sources.into())),
+        None => Err(failed.expect("a probe either failed or answered")),
+    }
+}
+
+/// The size of the file at `url`, or at the first of `mirrors` to answer
+/// when it doesn't.
+pub async fn content_length(
+    client: &reqwest::Client,
+    url: &Url,
+    mirrors: &[Url],
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<u64> {
+    let mut size = get_content_length(client, url, budget, auth).await;
+    for mirror in mirrors {
+        if size.is_ok() {
+            break;
+        }
+        size = get_content_length(client, mirror, budget, auth).await;
+    }
+    size
+}
+
+fn refuse(url: &str, why: &str) -> anyhow::Result<()> {
+    diagnostics::warn_or_fail(
+        WarningId::MirrorRefused,
+        format!("Leaving out {url}: {why}"),
+    )?;
+    Ok(())
+}
+
+/// Whether a download that failed with `error` may get further from
+/// another mirror: the server gave up on it, refused it, or couldn't be
+/// reached, rather than the download being stopped or the file at fault.
+pub fn falls_back(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<DownloadError>() {
+        Some(
+            DownloadError::RetriesExhausted { .. }
+            | DownloadError::ServerError { .. }
+            | DownloadError::RateLimited { .. }
+            | DownloadError::UnexpectedStatus { .. }
+            | DownloadError::Unauthorized { .. }
+            | DownloadError::PresignedExpired { .. }
+            | DownloadError::UnfollowedRedirect { .. }
+            | DownloadError::EncodedRange { .. }
+            | DownloadError::Encoded { .. }
+            | DownloadError::EtagChanged { .. }
+            | DownloadError::RangeNotRequested,
+        ) => true,
+        Some(_) => false,
+        None => retry::is_transient(error.as_ref()),
+    }
+}
+
+/// `options` for a download of `url` with mirrors: the file is named after
+/// `url` unless `--output` names it.
+pub fn named_after(url: &Url, target_dir: &Path, options: &TransferOptions) -> TransferOptions {
+    let name = utils::build_download_path(url, target_dir)
+        .file_name()
+        .map(PathBuf::from);
+    TransferOptions {
+        output: options.output.clone().or(name),
+        ..options.clone()
+    }
+}
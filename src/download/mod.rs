@@ -1,9 +1,98 @@
+pub mod adopt;
 mod async_download;
 mod async_range;
-mod blocking;
+pub mod auth_token;
+#[cfg(feature = "torrent")]
+pub(crate) mod bencode;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
+pub mod checksum;
+pub mod chunk_log;
+pub(crate) mod chunk_url;
+pub mod chunks;
+pub mod client;
+pub mod clock;
+pub mod compare;
+pub mod compress;
+pub mod conflicts;
+pub mod connections;
+pub(crate) mod content_disposition;
+pub(crate) mod content_type;
+pub mod cosign;
+pub mod diagnostics;
+pub mod dns;
+pub mod error;
+mod error_body;
+pub mod etag;
+pub mod expected_size;
+pub mod fairness;
+pub mod fd_limit;
+pub(crate) mod file_watch;
+pub mod filesystem;
+pub mod fs_ops;
+pub mod host_health;
+pub mod http;
+mod hybrid;
+pub mod in_place;
+pub mod inodes;
+pub mod landing;
+pub mod memory;
+pub mod mirrors;
+pub mod naming;
+pub mod network_wait;
+pub mod newer;
+pub mod one_connection;
+pub mod options;
+pub mod pacing;
+pub(crate) mod part_check;
+pub mod partial;
+pub mod parts;
+pub mod pieces;
+pub mod plan;
+pub mod pool;
+pub mod postprocess;
+pub mod preflight;
+pub mod presigned;
 pub mod progress;
+pub mod progress_handle;
+pub mod proxy;
+pub mod quota;
+pub mod rate_limit;
+pub mod releases;
+pub mod remote;
+#[cfg(feature = "blocking")]
+mod remote_zip;
+pub mod removal;
+pub mod render;
+mod repair;
+pub mod retry;
+pub mod retry_budget;
+pub mod schema;
+pub mod segment_tuning;
+pub mod speed;
+pub mod stall;
+mod stream;
+pub mod suspicious;
+mod tail;
+pub mod target_wait;
+pub mod throttle;
+pub mod tls;
+pub mod torrent;
+pub mod tracking;
+pub mod transaction;
+pub mod transcript;
 pub mod utils;
+pub mod validator;
+mod writer;
+pub(crate) mod zero_copy;
 
 pub use async_download::download_file_async;
 pub use async_range::{download_with_workers, get_content_length};
-pub use blocking::download_file_blocking;
+pub use landing::resolve_landing_page;
+pub use plan::plan_download;
+#[cfg(feature = "blocking")]
+pub use remote_zip::extract_zip_member;
+pub use repair::{RepairSummary, repair_file};
+pub use stream::{DownloadStream, Downloader};
+pub use tail::download_tail;
@@ -0,0 +1,135 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::fs_ops;
+use crate::download::memory::MemoryLimit;
+use crate::download::target_wait;
+use crate::download::utils;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How `--name-by-hash` names a finished download. Placeholders:
+/// `{sha256}`, `{sha256_short}` (its first 12 characters), `{name}` (the
+/// file name the download would have had), `{stem}` and `{ext}` (that
+/// name's extension with its dot, empty if it has none).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+const PLACEHOLDERS: [&str; 5] = ["sha256", "sha256_short", "name", "stem", "ext"];
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        NameTemplate("{sha256}{ext}".to_string())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut rest = value;
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}') else {
+                return Err(format!("'{value}' has an unclosed '{{'"));
+            };
+            let placeholder = &rest[open + 1..open + close];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "'{{{placeholder}}}' isn't one of {{{}}}",
+                    PLACEHOLDERS.join("}, {")
+                ));
+            }
+            rest = &rest[open + close + 1..];
+        }
+        if !value.contains("{sha256") {
+            return Err(format!("'{value}' has no {{sha256}} or {{sha256_short}}"));
+        }
+        if value.contains(['/', '\\']) {
+            return Err(format!("'{value}' must be a file name, not a path"));
+        }
+        Ok(NameTemplate(value.to_string()))
+    }
+}
+
+impl NameTemplate {
+    /// The file name for content hashing to `sha256`, first downloaded as
+    /// `name`.
+    pub fn render(&self, sha256: &[u8; 32], name: &str) -> String {
+        let hex = hex::encode(sha256);
+        let path = Path::new(name);
+        let stem = path
+            .file_stem()
+            .map_or(name.into(), |stem| stem.to_string_lossy());
+        let ext = path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+        self.0
+            .replace("{sha256_short}", &hex[..12])
+            .replace("{sha256}", &hex)
+            .replace("{name}", name)
+            .replace("{stem}", &stem)
+            .replace("{ext}", &ext)
+    }
+}
+
+/// Where a `--name-by-hash` download goes until its hash is known: next to
+/// `destination`, hidden, keeping the extension so content checks still
+/// see it. The same URL always gets the same name, so `--resume` works.
+pub fn temporary_path(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    destination.with_file_name(format!(".dlm-{name}"))
+}
+
+/// Where [`settle`] left a download.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Settled {
+    Renamed(PathBuf),
+    /// A file with the same name and content was already there.
+    Duplicate(PathBuf),
+}
+
+impl Settled {
+    pub fn path(&self) -> &Path {
+        match self {
+            Settled::Renamed(path) | Settled::Duplicate(path) => path,
+        }
+    }
+}
+
+/// Renames the download at `temporary` to its name under `template`. When
+/// a file already has that name and the same content, hashed within
+/// `memory`, the download is dropped instead; one with other content is
+/// replaced.
+pub fn settle(
+    temporary: &Path,
+    name: &str,
+    sha256: &[u8; 32],
+    template: &NameTemplate,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<Settled> {
+    let path = temporary.with_file_name(template.render(sha256, name));
+    if path.is_file() {
+        if utils::hash_file(&path, memory)? == *sha256 {
+            fs_ops::remove_file(temporary)?;
+            return Ok(Settled::Duplicate(path));
+        }
+        diagnostics::warn_or_fail(
+            WarningId::NameMismatch,
+            format!("'{}' doesn't match its name, replacing it", path.display()),
+        )?;
+    }
+    target_wait::retry("rename", temporary, || fs_ops::rename(temporary, &path)).with_context(
+        || {
+            format!(
+                "Cannot rename '{}' to '{}'",
+                temporary.display(),
+                path.display()
+            )
+        },
+    )?;
+    Ok(Settled::Renamed(path))
+}
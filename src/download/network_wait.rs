@@ -0,0 +1,250 @@
+//! `--wait-for-network`: a download queued while offline waits for the
+//! network rather than failing. When its first connection fails because the
+//! host couldn't be resolved or reached, the host is checked again with a
+//! backoff, and the request made again once it answers, carrying on from
+//! any partial file as usual. A refused connection or an error from the
+//! server is an answer, and fails as ever.
+//!
+//! Whether there's a network at all is told by pointing a UDP socket at an
+//! outside address, which sends nothing but fails without a route, so a
+//! host that's down isn't blamed on the network.
+
+use crate::download::error::DownloadError;
+use crate::download::options::TransferOptions;
+use crate::download::pacing;
+use crate::download::progress::TransferProgress;
+use crate::download::progress_handle::PreflightStep;
+use std::future::Future;
+use std::io;
+use std::net::{TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The first pause before the host is checked again, doubled after each
+/// check up to [`MAX_PAUSE`].
+const FIRST_PAUSE: Duration = Duration::from_secs(1);
+
+const MAX_PAUSE: Duration = Duration::from_secs(30);
+
+/// How long a check's connection may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The wait in milliseconds: zero for none, `u64::MAX` for no limit.
+static WAIT: AtomicU64 = AtomicU64::new(0);
+
+/// Sets how long to wait: `None` to fail at once, as by default, zero for
+/// as long as it takes.
+pub fn set_wait(wait: Option<Duration>) {
+    let millis = match wait {
+        None => 0,
+        Some(wait) if wait.is_zero() => u64::MAX,
+        Some(wait) => (wait.as_millis() as u64).max(1),
+    };
+    WAIT.store(millis, Ordering::Relaxed);
+}
+
+/// Whether `--wait-for-network` is on.
+pub fn enabled() -> bool {
+    WAIT.load(Ordering::Relaxed) > 0
+}
+
+fn limit() -> Duration {
+    match WAIT.load(Ordering::Relaxed) {
+        u64::MAX => Duration::MAX,
+        millis => Duration::from_millis(millis),
+    }
+}
+
+/// Runs `connect` again once `url`'s host answers, or the proxy it goes
+/// through with `options`, while it fails for want of a network and
+/// `--wait-for-network` allows.
+pub async fn retry<T, F>(
+    url: &Url,
+    options: &TransferOptions,
+    progress: &TransferProgress,
+    mut connect: impl FnMut() -> F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut wait = Wait::new(url, options);
+    loop {
+        let error = match connect().await {
+            Err(error) => error,
+            result => return result,
+        };
+        wait.offline(error, progress)?;
+        loop {
+            let pause = wait.pause()?;
+            if !pacing::wait(pause, &progress.interrupted).await {
+                return Err(DownloadError::Interrupted.into());
+            }
+            let checked = wait.checked.clone();
+            if tokio::task::spawn_blocking(move || reachable(&checked)).await? {
+                break;
+            }
+        }
+        wait.back(progress);
+    }
+}
+
+/// [`retry`] for the blocking client.
+pub(crate) fn retry_blocking<T>(
+    url: &Url,
+    options: &TransferOptions,
+    progress: &TransferProgress,
+    mut connect: impl FnMut() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut wait = Wait::new(url, options);
+    loop {
+        let error = match connect() {
+            Err(error) => error,
+            result => return result,
+        };
+        wait.offline(error, progress)?;
+        loop {
+            let pause = wait.pause()?;
+            if !pacing::wait_blocking(pause, &progress.interrupted) {
+                return Err(DownloadError::Interrupted.into());
+            }
+            if reachable(&wait.checked) {
+                break;
+            }
+        }
+        wait.back(progress);
+    }
+}
+
+/// A wait for the network, from the first connection that failed for
+/// want of one.
+struct Wait {
+    /// What's checked: the proxy, when requests go through one.
+    checked: Url,
+    started: Option<Instant>,
+    pause: Duration,
+    /// What the last connection failed with, to give up with.
+    error: Option<anyhow::Error>,
+}
+
+impl Wait {
+    fn new(url: &Url, options: &TransferOptions) -> Self {
+        Wait {
+            checked: options.proxy_for(url).unwrap_or_else(|| url.clone()),
+            started: None,
+            pause: FIRST_PAUSE,
+            error: None,
+        }
+    }
+
+    /// Starts waiting after `error`, saying what for, unless it's not for
+    /// want of a network or there's no waiting.
+    fn offline(&mut self, error: anyhow::Error, progress: &TransferProgress) -> anyhow::Result<()> {
+        if !enabled() || !could_not_connect(&error) {
+            return Err(error);
+        }
+        let host = self.checked.host_str().unwrap_or_default().to_string();
+        let (mut message, step) = match network_up() {
+            false => (
+                format!("No network ({error:#}), waiting for one"),
+                PreflightStep::WaitingForNetwork,
+            ),
+            true => (
+                format!(
+                    "The network is up but {host} can't be resolved or reached ({error:#}), waiting for it"
+                ),
+                PreflightStep::WaitingForHost { host },
+            ),
+        };
+        if self.started.is_none() {
+            match limit() {
+                Duration::MAX => message.push_str(" (until Ctrl+C)"),
+                limit => message.push_str(&format!(" (up to {})", indicatif::HumanDuration(limit))),
+            }
+        }
+        progress.println(&message);
+        progress.reporter().set_preflight(step);
+        self.started.get_or_insert_with(Instant::now);
+        self.error = Some(error);
+        Ok(())
+    }
+
+    /// How long to wait before the next check, failing with the last error
+    /// once `--wait-for-network` is up.
+    fn pause(&mut self) -> anyhow::Result<Duration> {
+        let waited = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        let left = limit().saturating_sub(waited);
+        if left.is_zero() {
+            let error = self.error.take().expect("a wait starts with an error");
+            return Err(error.context(format!(
+                "Gave up waiting for the network after {}",
+                indicatif::HumanDuration(waited)
+            )));
+        }
+        let pause = self.pause.min(left);
+        self.pause = (self.pause * 2).min(MAX_PAUSE);
+        Ok(pause)
+    }
+
+    /// The host answered: the request goes out again.
+    fn back(&mut self, progress: &TransferProgress) {
+        let waited = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        progress.println(&format!(
+            "{} answers after {}, carrying on",
+            self.checked.host_str().unwrap_or_default(),
+            indicatif::HumanDuration(waited)
+        ));
+        progress.reporter().set_preflight(PreflightStep::Connecting);
+        self.pause = FIRST_PAUSE;
+    }
+}
+
+/// Whether `error` is a lookup or connection that failed, rather than an
+/// answer: a refused connection means the host is there.
+fn could_not_connect(error: &anyhow::Error) -> bool {
+    let mut failed = false;
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            failed |= error.is_connect();
+        }
+        if let Some(error) = cause.downcast_ref::<io::Error>() {
+            match error.kind() {
+                io::ErrorKind::ConnectionRefused => return false,
+                io::ErrorKind::NetworkUnreachable
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkDown => failed = true,
+                _ => {}
+            }
+        }
+    }
+    failed
+}
+
+/// Whether there's a route off the machine, to documentation addresses
+/// that are never answered: connecting a UDP socket only looks one up.
+fn network_up() -> bool {
+    let routed = |local: &str, remote: &str| {
+        UdpSocket::bind(local)
+            .and_then(|socket| socket.connect(remote))
+            .is_ok()
+    };
+    routed("0.0.0.0:0", "198.51.100.1:9") || routed("[::]:0", "[2001:db8::1]:9")
+}
+
+/// Whether `url`'s host resolves and answers a connection, if only to
+/// refuse it.
+fn reachable(url: &Url) -> bool {
+    let Ok(addresses) = url.socket_addrs(|| None) else {
+        return false;
+    };
+    addresses.iter().any(
+        |address| match TcpStream::connect_timeout(address, CHECK_TIMEOUT) {
+            Ok(_) => true,
+            Err(error) => error.kind() == io::ErrorKind::ConnectionRefused,
+        },
+    )
+}
@@ -0,0 +1,84 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::clock;
+use crate::download::http;
+use crate::download::retry_budget::RetryBudget;
+use reqwest::StatusCode;
+use reqwest::header::{self, HeaderMap};
+use std::time::SystemTime;
+use url::Url;
+
+/// `--newer-than`: the time a remote file must have changed after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NewerThan {
+    /// A timestamp given as such, the same by any clock.
+    Time(SystemTime),
+    /// A local file's modification time, by this machine's clock.
+    Modified(SystemTime),
+}
+
+impl NewerThan {
+    /// The time by the server's clock, as far as it's been measured.
+    pub fn server_time(self) -> SystemTime {
+        match self {
+            NewerThan::Time(time) => time,
+            NewerThan::Modified(time) => clock::to_server_time(time),
+        }
+    }
+}
+
+/// Asks the server whether `url` changed after `since`, with
+/// `If-Modified-Since`. Returns why it's not worth downloading, or `None`
+/// when it is, including when the server doesn't say when it changed. A
+/// 5xx is retried while `budget` allows.
+pub async fn not_newer(
+    client: &reqwest::Client,
+    url: &Url,
+    since: NewerThan,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Option<String>> {
+    let asked = since.server_time();
+    let mut response = ask_since(client, url, asked, budget, auth).await?;
+    // The answer was the first to say how far off the local clock is.
+    let since = since.server_time();
+    if since != asked {
+        response = ask_since(client, url, since, budget, auth).await?;
+    }
+    match response.status() {
+        StatusCode::NOT_MODIFIED => Ok(Some(format!(
+            "not newer than {}",
+            httpdate::fmt_http_date(since)
+        ))),
+        status if status.is_success() => Ok(not_newer_by_headers(response.headers(), since)),
+        _ => Err(http::unexpected_status(response).await.into()),
+    }
+}
+
+async fn ask_since(
+    client: &reqwest::Client,
+    url: &Url,
+    since: SystemTime,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<reqwest::Response> {
+    let request = client
+        .get(url.clone())
+        .header(header::IF_MODIFIED_SINCE, httpdate::fmt_http_date(since));
+    // Only the headers are wanted: a newer file is downloaded the usual way
+    // afterwards, and dropping the response closes it.
+    http::send_retrying(request, None, budget, auth).await
+}
+
+/// Why a response with these headers isn't newer than `since`, by the
+/// server's clock, going by its `Last-Modified`, for servers that ignore
+/// `If-Modified-Since`.
+pub fn not_newer_by_headers(headers: &HeaderMap, since: SystemTime) -> Option<String> {
+    let last_modified = headers.get(header::LAST_MODIFIED)?.to_str().ok()?;
+    let modified = httpdate::parse_http_date(last_modified).ok()?;
+    (modified <= since).then(|| {
+        format!(
+            "not newer than {} (last modified {last_modified})",
+            httpdate::fmt_http_date(since)
+        )
+    })
+}
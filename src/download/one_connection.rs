@@ -0,0 +1,74 @@
+//! Servers that allow a client one connection at a time, as some academic
+//! mirrors do: worker mode's first chunk downloads fine while the others
+//! are refused with a 503, or a connection reset, the moment they connect.
+//! Rather than fail, the download carries on with those chunks one after
+//! another over a single connection, keeping its parts and plan, so it's
+//! still resumed chunk by chunk.
+//!
+//! `--one-connection` does that from the start, as do the process's later
+//! downloads from a host one of them found out about, such as the rest of
+//! a batch's entries.
+
+use crate::download::error::DownloadError;
+use crate::download::host_health;
+use crate::download::retry;
+use reqwest::StatusCode;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Mutex;
+use url::Url;
+
+/// What the notice of a download that found out says after the host.
+const NOTICE: &str = " allows one connection at a time";
+
+/// The hosts found to allow one connection at a time, by
+/// [`host_key`](host_health::host_key).
+static HOSTS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// A chunk's connection was refused while another chunk had one open. It's
+/// downloaded again once the others are done.
+#[derive(Debug)]
+pub(crate) struct Refused;
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the server refused another connection")
+    }
+}
+
+/// Whether `error` is how a server refuses a connection it won't allow: a
+/// 503, or a connection refused, reset or closed before an answer.
+pub fn is_refusal(error: &anyhow::Error) -> bool {
+    let unavailable = error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<DownloadError>(),
+            Some(DownloadError::ServerError { status, .. }) if *status == StatusCode::SERVICE_UNAVAILABLE
+        )
+    });
+    unavailable || retry::is_transient(error.as_ref())
+}
+
+/// Whether `error` ended a chunk as [`Refused`].
+pub(crate) fn is_refused(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Refused>().is_some()
+}
+
+/// What a download that found out prints: "`host` allows one connection at
+/// a time, downloading the other `chunks` chunks one after another".
+pub fn notice(url: &Url, chunks: usize) -> String {
+    format!(
+        "{}{NOTICE}, downloading the other {chunks} chunks one after another",
+        host_health::host_key(url)
+    )
+}
+
+/// Keeps in mind that `url`'s host allows one connection at a time.
+pub(crate) fn remember(url: &Url) {
+    HOSTS.lock().unwrap().insert(host_health::host_key(url));
+}
+
+/// Whether a download of this process found that `url`'s host allows one
+/// connection at a time.
+pub fn known(url: &Url) -> bool {
+    HOSTS.lock().unwrap().contains(&host_health::host_key(url))
+}
@@ -0,0 +1,326 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::chunk_log::ChunkLog;
+use crate::download::chunks::{self, ChunkOrder, ResumeOrder};
+use crate::download::compress::Compression;
+use crate::download::content_disposition;
+use crate::download::dns::DnsCache;
+use crate::download::memory::MemoryLimit;
+use crate::download::newer::NewerThan;
+use crate::download::pacing::Pacing;
+use crate::download::parts::ResumeFrom;
+use crate::download::pieces::PieceHashes;
+use crate::download::proxy;
+use crate::download::retry::RetryPolicy;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::segment_tuning::SegmentBounds;
+use crate::download::stall::{ChunkFloor, StallPolicy};
+use crate::download::throttle::Throttle;
+use crate::download::tls::SessionLog;
+use crate::download::utils;
+use crate::download::validator::IfChanged;
+use anyhow::bail;
+use bytes::Bytes;
+use reqwest::Method;
+use reqwest::header::{self, HeaderMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use url::Url;
+
+/// Behaviour knobs shared by every download path.
+#[derive(Clone, Debug, Default)]
+pub struct TransferOptions {
+    /// Resume if the file already exists and isn't complete.
+    pub resume: bool,
+    /// Overwrite an existing file.
+    pub overwrite: bool,
+    /// Keep part files around after merging.
+    pub no_cleanup: bool,
+    /// Remove the `.part` file of a download that fails rather than leave
+    /// it to resume.
+    pub no_partial: bool,
+    pub stall: StallPolicy,
+    /// How a lost connection or a timeout is retried, from `--retries`.
+    pub retry: RetryPolicy,
+    /// Worker mode: re-assign a chunk that stays below this speed while
+    /// others are done to a new connection.
+    pub chunk_floor: Option<ChunkFloor>,
+    /// File name to save as instead of the one derived from the URL.
+    pub output: Option<PathBuf>,
+    /// Where worker mode records chunk lifecycle events.
+    pub chunk_log: Option<ChunkLog>,
+    /// Resume from this offset instead of the end of the local file.
+    pub continue_at: Option<ContinueAt>,
+    /// Carry on from the parts a worker-mode download left elsewhere,
+    /// downloading only what they're missing.
+    pub resume_from: Option<ResumeFrom>,
+    /// Let `continue_at` go past the end of the local file, zero-filling
+    /// the gap.
+    pub sparse_fill: bool,
+    /// Bytes each worker buffers before writing to its part file; zero
+    /// writes every piece as it arrives.
+    pub write_buffer: usize,
+    /// Funnel all workers' writes through one writer thread.
+    pub serial_writes: bool,
+    /// Per-piece SHA-256s worker mode checks chunks against.
+    pub pieces: Option<Arc<PieceHashes>>,
+    /// Pause and rate limit, shared by every worker.
+    pub throttle: Throttle,
+    /// Ramp-up, jitter and the cap on requests a second, shared by every
+    /// download of the run.
+    pub pacing: Pacing,
+    /// Keep worker mode's chunks near an even share of the bandwidth,
+    /// capping the fast ones while others starve.
+    pub fair_workers: bool,
+    /// Worker mode: don't open a connection per worker before the chunks
+    /// start.
+    pub no_warm_up: bool,
+    /// Worker mode: stream the first chunk into the file and append the
+    /// others in order as they come, so the file is always a valid start of
+    /// the download, for `--hybrid-streaming`.
+    pub hybrid_streaming: bool,
+    /// Worker mode: write every chunk to a part file merged into the file
+    /// at the end, rather than into the file, sized up front, at its
+    /// offset, for `--part-files`.
+    pub part_files: bool,
+    /// Worker mode: split the file into more segments than workers, which
+    /// take them in this order as they finish the last.
+    pub chunk_order: Option<ChunkOrder>,
+    /// Worker mode: the order the segments a resumed download has left are
+    /// taken in, for `--resume-order`.
+    pub resume_order: ResumeOrder,
+    /// Worker mode: split a file bigger than a segment of this size per
+    /// worker into segments of about this size, which the workers take one
+    /// after another, rather than into one per worker.
+    pub segment_size: Option<u64>,
+    /// Worker mode: with `--segment-size auto`, how small and large the
+    /// segments after the first may be made, `segment_size` being the size
+    /// of the first ones.
+    pub segment_tuning: Option<SegmentBounds>,
+    /// Worker mode: once no segment is left to start, an idle worker takes
+    /// the second half of what's left of the slowest one, as long as both
+    /// halves are at least this long.
+    pub min_steal: Option<u64>,
+    /// Worker mode: split a download between fewer workers than asked for
+    /// rather than into parts shorter than this.
+    pub min_split_size: Option<u64>,
+    /// Worker mode: download the chunks one after another over a single
+    /// connection, for a server that refuses a second.
+    pub one_connection: bool,
+    /// Method of the request that starts the download; anything but `GET`
+    /// rules out ranges, so resuming and workers.
+    pub method: Method,
+    /// Sent with a non-`GET` method.
+    pub body: Bytes,
+    /// Content-Type of `body`, if any.
+    pub content_type: Option<String>,
+    /// Start over from byte zero when the server can't resume, instead of
+    /// failing.
+    pub restart_on_unresumable: bool,
+    /// What a single-stream resume does when `If-Range` says the remote
+    /// file changed since the download started.
+    pub if_changed: IfChanged,
+    /// Fail before writing anything when the response's Content-Type
+    /// contradicts the file's extension, instead of warning.
+    pub strict_content_type: bool,
+    /// Start even when the destination's filesystem can't hold a file as
+    /// big as the download, from `--force`.
+    pub force: bool,
+    /// Write a response served as a torrent instead of refusing it, from
+    /// `--torrent save`.
+    pub save_torrent: bool,
+    /// Only download if the remote file changed after this.
+    pub newer_than: Option<NewerThan>,
+    /// Store the file compressed, named with the format's extension, and
+    /// report the SHA-256 of the content rather than of the file.
+    pub store_compressed: Option<Compression>,
+    /// Fail on a response that's compressed in transit rather than save it
+    /// compressed, from `--no-compression`, which also asks for the plain
+    /// bytes with `Accept-Encoding: identity`.
+    pub no_compression: bool,
+    /// The clients' resolver, to look a host up again before retrying a
+    /// connection that died mid-transfer.
+    pub dns: DnsCache,
+    /// What the clients' connections negotiated, for the snapshot.
+    pub tls: SessionLog,
+    /// The retries every kind of retry takes from, shared with the other
+    /// downloads of the run; see [`retry_budget`](crate::download::retry_budget).
+    pub retry_budget: Arc<RetryBudget>,
+    /// The buffer memory counted against `--max-memory`, shared with the
+    /// other downloads of the run; see [`MemoryLimit`].
+    pub memory: Arc<MemoryLimit>,
+    /// The clients' `--proxy` and `--proxy-all`, as in
+    /// [`ClientOptions`](crate::download::client::ClientOptions): a host
+    /// reached through a proxy isn't pinned, and waiting for the network
+    /// checks the proxy rather than the host.
+    pub proxy: Option<Url>,
+    pub proxy_all: bool,
+    /// `--auth-token-url`'s tokens, sent with every request and fetched
+    /// again when one is refused.
+    pub auth_token: Option<Arc<AuthToken>>,
+    /// Worker mode: compare the parts kept from an earlier run with the
+    /// server before carrying on with them, by their recorded SHA-256 or a
+    /// sample of their bytes, for `--verify-parts`.
+    pub verify_parts: bool,
+    /// Worker mode: connect every chunk to the node that answered the probe,
+    /// through [`dns`](Self::dns).
+    pub pin_ip: bool,
+    /// The size the file must come to, from `--expected-size`; see
+    /// [`expected_size`](crate::download::expected_size).
+    pub expected_size: Option<u64>,
+    /// Other URLs serving the same file, from `--mirror`; see
+    /// [`mirrors`](crate::download::mirrors).
+    pub mirrors: Vec<Url>,
+}
+
+/// Where `--continue-at` resumes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContinueAt {
+    Offset(u64),
+    /// `-`: the local file's current size, like `--resume`.
+    FileSize,
+}
+
+impl FromStr for ContinueAt {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "-" => Ok(ContinueAt::FileSize),
+            offset => offset
+                .parse()
+                .map(ContinueAt::Offset)
+                .map_err(|_| format!("'{value}' is not a byte offset or '-'")),
+        }
+    }
+}
+
+impl TransferOptions {
+    /// What chunk boundaries are rounded to: whole pieces when piece hashes
+    /// are given, so every piece is verified by the worker that fetched it.
+    pub fn chunk_alignment(&self) -> u64 {
+        self.pieces.as_ref().map_or(1, |pieces| pieces.piece_size)
+    }
+
+    /// How many parts worker mode splits the `length` bytes it downloads
+    /// into: one per worker, or with a [`chunk_order`](Self::chunk_order),
+    /// [`SEGMENTS_PER_WORKER`](chunks::SEGMENTS_PER_WORKER) each. More, if
+    /// that makes them longer than the [`segment_size`](Self::segment_size).
+    pub fn segments(&self, workers: u8, length: u64) -> u16 {
+        let per_worker = match self.chunk_order {
+            Some(_) => u16::from(workers) * chunks::SEGMENTS_PER_WORKER,
+            None => workers.into(),
+        };
+        // --hybrid-streaming appends the parts a worker each.
+        let by_size = match self.segment_size {
+            Some(size) if size > 0 && !self.hybrid_streaming => length.div_ceil(size),
+            _ => 0,
+        };
+        by_size.clamp(per_worker.into(), u16::MAX.into()) as u16
+    }
+
+    /// The proxy the clients send a request for `url` through, if any.
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        proxy::for_url(url, self.proxy.as_ref(), self.proxy_all)
+    }
+
+    /// Whether the download can be picked up with a range request.
+    pub fn can_resume(&self) -> bool {
+        self.method == Method::GET
+    }
+
+    /// The request that starts the download of `url`.
+    pub fn request(&self, client: &reqwest::Client, url: &Url) -> reqwest::RequestBuilder {
+        let request = client.request(self.method.clone(), url.clone());
+        if self.method == Method::GET {
+            return request;
+        }
+        match &self.content_type {
+            Some(content_type) => request.header(header::CONTENT_TYPE, content_type),
+            None => request,
+        }
+        .body(self.body.clone())
+    }
+
+    /// [`request`](Self::request) on the blocking client.
+    #[cfg(feature = "blocking")]
+    pub fn request_blocking(
+        &self,
+        client: &reqwest::blocking::Client,
+        url: &Url,
+    ) -> reqwest::blocking::RequestBuilder {
+        let request = client.request(self.method.clone(), url.clone());
+        if self.method == Method::GET {
+            return request;
+        }
+        match &self.content_type {
+            Some(content_type) => request.header(header::CONTENT_TYPE, content_type),
+            None => request,
+        }
+        .body(self.body.clone())
+    }
+
+    /// Where the download of `url` ends up. A relative `--output` is placed
+    /// inside `target_dir`, an absolute one is used as is.
+    /// With `store_compressed`, the format's extension is added.
+    pub fn destination(&self, url: &Url, target_dir: &Path) -> PathBuf {
+        let path = match &self.output {
+            Some(output) => utils::long_path(target_dir.join(output)),
+            None => utils::build_download_path(url, target_dir),
+        };
+        self.stored(path)
+    }
+
+    /// [`TransferOptions::destination`], once the server has answered with
+    /// `headers`: without an `--output`, a name their `Content-Disposition`
+    /// gives the file goes before the URL's.
+    pub fn served_destination(&self, url: &Url, target_dir: &Path, headers: &HeaderMap) -> PathBuf {
+        match content_disposition::file_name(headers) {
+            Some(name) if self.output.is_none() => self.stored(utils::file_path(&name, target_dir)),
+            _ => self.destination(url, target_dir),
+        }
+    }
+
+    /// `path`, with the `store_compressed` format's extension if any.
+    fn stored(&self, path: PathBuf) -> PathBuf {
+        match &self.store_compressed {
+            Some(compression) => {
+                let mut name = path.into_os_string();
+                name.push(".");
+                name.push(compression.extension());
+                PathBuf::from(name)
+            }
+            None => path,
+        }
+    }
+
+    /// Refuses to resume into a compressed file: offsets in the content
+    /// don't map to offsets in it.
+    pub fn check_compressed_resume(&self) -> anyhow::Result<()> {
+        if self.store_compressed.is_some() && (self.resume || self.continue_at.is_some()) {
+            bail!(
+                "A file stored compressed can't be resumed, offsets in the download don't map to offsets in it; drop --resume and --continue-at to download it again from the start"
+            );
+        }
+        Ok(())
+    }
+
+    /// The offset `--continue-at` resumes the file at `path` from, if given.
+    pub fn continue_offset(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+        let Some(continue_at) = self.continue_at else {
+            return Ok(None);
+        };
+        let local = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let offset = match continue_at {
+            ContinueAt::Offset(offset) => offset,
+            ContinueAt::FileSize => local,
+        };
+        if offset > local && !self.sparse_fill {
+            bail!(
+                "--continue-at {offset} is past the end of '{}' ({local} bytes), pass --sparse-fill to zero-fill the gap",
+                path.display()
+            );
+        }
+        Ok(Some(offset))
+    }
+}
@@ -0,0 +1,129 @@
+//! Going easy on small servers, which fail2ban and the like may otherwise
+//! take a burst of range requests for an attack. `--ramp-up` spreads worker
+//! mode's chunks over an interval instead of starting them all at once,
+//! `--request-jitter` waits a random while before each new request, and
+//! `--max-requests-per-second` caps how fast requests go out over every
+//! download sharing the [`Pacing`]. All are off by default.
+//!
+//! The waits are taken where downloads schedule their requests, in naps
+//! short enough that a cancel ends them right away.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a wait sleeps before looking at the interrupt flag again.
+const NAP: Duration = Duration::from_millis(100);
+
+/// When downloads may send their requests. Clones share the rate cap.
+#[derive(Clone, Debug, Default)]
+pub struct Pacing {
+    /// Worker mode's chunks start spread evenly over this long.
+    pub ramp_up: Duration,
+    /// Each new request waits a random while of up to this long.
+    pub jitter: Duration,
+    rate: Option<Arc<RateCap>>,
+}
+
+/// Requests a second, as the next free slot to send one in.
+#[derive(Debug)]
+struct RateCap {
+    every: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacing {
+    /// `max_per_second` requests a second at most, `None` for no cap.
+    pub fn new(ramp_up: Duration, jitter: Duration, max_per_second: Option<f64>) -> Self {
+        let rate = max_per_second.filter(|rate| *rate > 0.0).map(|rate| {
+            Arc::new(RateCap {
+                every: Duration::from_secs_f64(1.0 / rate),
+                next: Mutex::new(Instant::now()),
+            })
+        });
+        Self {
+            ramp_up,
+            jitter,
+            rate,
+        }
+    }
+
+    /// The cap on requests a second, if any.
+    pub fn max_per_second(&self) -> Option<f64> {
+        self.rate
+            .as_ref()
+            .map(|rate| 1.0 / rate.every.as_secs_f64())
+    }
+
+    /// How long the `index`th of `count` chunks starting together waits
+    /// before it starts; any after those start when a worker is free.
+    pub fn ramp_up_delay(&self, index: usize, count: usize) -> Duration {
+        match index < count {
+            true => self.ramp_up.mul_f64(index as f64 / count as f64),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Waits out the jitter and a free slot under the rate cap before a new
+    /// request. False if `interrupted` was raised meanwhile.
+    pub async fn before_request(&self, interrupted: &AtomicBool) -> bool {
+        wait(self.request_delay(), interrupted).await
+    }
+
+    /// [`Pacing::before_request`] for the blocking client.
+    pub fn before_request_blocking(&self, interrupted: &AtomicBool) -> bool {
+        wait_blocking(self.request_delay(), interrupted)
+    }
+
+    /// The jitter, then what's left until the slot this request takes.
+    fn request_delay(&self) -> Duration {
+        let jitter = self.jitter.mul_f64(random_fraction());
+        let Some(rate) = &self.rate else {
+            return jitter;
+        };
+        let ready = Instant::now() + jitter;
+        let mut next = rate.next.lock().unwrap();
+        let slot = (*next).max(ready);
+        *next = slot + rate.every;
+        slot.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Sleeps for `delay`, in naps, unless `interrupted` is raised first.
+/// False if it was.
+pub async fn wait(delay: Duration, interrupted: &AtomicBool) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        tokio::time::sleep(left.min(NAP)).await;
+    }
+}
+
+/// [`wait`] for the blocking client.
+pub fn wait_blocking(delay: Duration, interrupted: &AtomicBool) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        std::thread::sleep(left.min(NAP));
+    }
+}
+
+/// A number in `[0, 1)`, random enough to keep requests from lining up.
+pub(crate) fn random_fraction() -> f64 {
+    let bits = RandomState::new().hash_one(Instant::now());
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,194 @@
+//! Checks of worker mode's part files before they're trusted. A part an
+//! earlier run left, and that `--resume` carries on with, must hold no more
+//! than its range; every part must be exactly as long as its range before
+//! it's merged. With `--verify-parts` a kept part is also compared with the
+//! server: by the SHA-256 recorded when it was completed, or else by a few
+//! small ranges fetched again. A part that fails is downloaded again rather
+//! than merged.
+
+use crate::download::auth_token::AuthToken;
+use crate::download::checksum::{self, Algorithm};
+use crate::download::http;
+use crate::download::memory::MemoryLimit;
+use crate::download::options::TransferOptions;
+use crate::download::pacing;
+use crate::download::parts::PartLayout;
+use crate::download::retry_budget::RetryBudget;
+use anyhow::{Context, bail};
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+/// How many ranges of a kept part without a recorded SHA-256
+/// `--verify-parts` fetches again.
+const SAMPLES: usize = 4;
+
+/// How long each of those ranges is.
+const SAMPLE_BYTES: u64 = 4096;
+
+/// How a part was checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckedBy {
+    /// Its length against its range's.
+    Size,
+    /// Its SHA-256 against the one recorded when it was completed.
+    Sha256,
+    /// A few of its bytes against the server's.
+    Sample,
+}
+
+impl fmt::Display for CheckedBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckedBy::Size => "size",
+            CheckedBy::Sha256 => "SHA-256",
+            CheckedBy::Sample => "sample",
+        })
+    }
+}
+
+/// A part's check, and what came of it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PartCheck {
+    pub path: PathBuf,
+    /// The inclusive byte range the part holds.
+    pub start: u64,
+    pub end: u64,
+    pub by: CheckedBy,
+    /// Why it's downloaded again, if it failed.
+    pub failed: Option<String>,
+}
+
+impl fmt::Display for PartCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' (bytes {}-{}) ",
+            self.path.display(),
+            self.start,
+            self.end
+        )?;
+        match &self.failed {
+            Some(reason) => write!(f, "failed its {} check: {reason}", self.by),
+            None => write!(f, "passed its {} check", self.by),
+        }
+    }
+}
+
+/// Whether the part of range `index` is as long as the range; why not if
+/// it isn't.
+pub fn check_size(layout: &PartLayout, index: usize) -> anyhow::Result<Option<String>> {
+    let (start, end) = layout.ranges[index];
+    let path = &layout.paths[index];
+    let len = std::fs::metadata(path)
+        .with_context(|| format!("Cannot read '{}'", path.display()))?
+        .len();
+    let expected = end + 1 - start;
+    Ok((len != expected).then(|| format!("it holds {len} bytes where its range has {expected}")))
+}
+
+/// Checks the part of range `index`, kept from an earlier run: its size,
+/// then with `--verify-parts` in `options` its recorded SHA-256, or a
+/// sample of its bytes against `url`'s, its requests retried while the
+/// options' budget allows.
+pub async fn check_kept(
+    client: &reqwest::Client,
+    url: &Url,
+    layout: &PartLayout,
+    index: usize,
+    options: &TransferOptions,
+) -> anyhow::Result<PartCheck> {
+    let (start, end) = layout.ranges[index];
+    let path = layout.paths[index].clone();
+    let mut check = PartCheck {
+        path,
+        start,
+        end,
+        by: CheckedBy::Size,
+        failed: check_size(layout, index)?,
+    };
+    if check.failed.is_some() || !options.verify_parts {
+        return Ok(check);
+    }
+    check.failed = match layout.recorded_sha256(index) {
+        Some(recorded) => {
+            check.by = CheckedBy::Sha256;
+            let actual = sha256(&check.path, &options.memory).await?;
+            (actual != recorded).then(|| format!("its SHA-256 is {actual}, not {recorded}"))
+        }
+        None => {
+            check.by = CheckedBy::Sample;
+            let auth = options.auth_token.as_deref();
+            sample(
+                client,
+                url,
+                &check.path,
+                (start, end),
+                &options.retry_budget,
+                auth,
+            )
+            .await
+            .with_context(|| format!("Cannot check '{}'", check.path.display()))?
+        }
+    };
+    Ok(check)
+}
+
+/// The SHA-256 of the part at `path`, in hex, its read buffer counted
+/// against `memory`.
+pub async fn sha256(path: &Path, memory: &Arc<MemoryLimit>) -> anyhow::Result<String> {
+    let path = path.to_path_buf();
+    let memory = memory.clone();
+    let sha256 = tokio::task::spawn_blocking(move || {
+        checksum::hash_file(&path, Algorithm::Sha256, None, &memory, |_| {})
+    })
+    .await??;
+    Ok(hex::encode(sha256))
+}
+
+/// Compares [`SAMPLES`] ranges of the part at `path`, which holds bytes
+/// `start` to `end` of `url`, with the server's. Why not, if they differ.
+async fn sample(
+    client: &reqwest::Client,
+    url: &Url,
+    path: &Path,
+    (start, end): (u64, u64),
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<Option<String>> {
+    let len = end + 1 - start;
+    let window = SAMPLE_BYTES.min(len);
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut local = vec![0; window as usize];
+    for _ in 0..SAMPLES {
+        let offset = ((len - window) as f64 * pacing::random_fraction()) as u64;
+        let (first, last) = (start + offset, start + offset + window - 1);
+        let request = client
+            .get(url.clone())
+            .headers(http::range_headers(&format!("bytes={first}-{last}")));
+        let response = http::send_retrying(request, None, budget, auth).await?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            bail!(
+                "the server answered {} to a range request",
+                response.status()
+            );
+        }
+        http::check_identity(response.headers())?;
+        let remote = response.bytes().await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut local).await?;
+        if remote[..] != local[..] {
+            return Ok(Some(format!(
+                "bytes {first}-{last} differ from the server's"
+            )));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,86 @@
+//! Downloads are written to `<name>.part` beside their destination and
+//! renamed over it once the transfer is over, so a file at the destination
+//! is never one cut short. `--resume` carries on from the `.part`, or from a
+//! file at the destination itself, as earlier versions left them.
+//!
+//! `--hybrid-streaming` still writes the start of the file to the
+//! destination, which is what it's for. Whatever the mode, a file already
+//! at the destination is only replaced with `--overwrite`, or carried on
+//! from with `--resume`.
+
+use crate::download::fs_ops;
+use crate::download::utils::{self, MAX_FILE_NAME};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SUFFIX: &str = ".part";
+
+/// The partial file of a download to `destination`. The name is cut short
+/// to keep the suffix.
+pub fn path(destination: &Path) -> PathBuf {
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let name = utils::truncate_file_name(&name, MAX_FILE_NAME - SUFFIX.len());
+    destination.with_file_name(format!("{name}{SUFFIX}"))
+}
+
+/// The file a download to `destination` is written to: its partial file,
+/// unless `resuming` and there's none but there's a file at `destination`
+/// to carry on from.
+pub fn target(destination: &Path, resuming: bool) -> PathBuf {
+    let partial = path(destination);
+    if resuming && !partial.is_file() && destination.is_file() {
+        return destination.to_path_buf();
+    }
+    partial
+}
+
+/// Moves the finished download at `target` to `destination`, replacing
+/// whatever was there in one step.
+pub fn finish(target: &Path, destination: &Path) -> io::Result<()> {
+    if target == destination {
+        return Ok(());
+    }
+    fs_ops::rename(target, destination)
+}
+
+/// [`finish`] for async code.
+pub async fn finish_async(target: &Path, destination: &Path) -> io::Result<()> {
+    if target == destination {
+        return Ok(());
+    }
+    fs_ops::rename_async(target, destination).await
+}
+
+/// `--no-partial`: removes the partial file when dropped before
+/// [`Discard::keep`], as when the download fails or is interrupted. A file
+/// carried on from at the destination itself is never removed.
+pub struct Discard {
+    path: Option<PathBuf>,
+}
+
+impl Discard {
+    pub fn new(target: &Path, destination: &Path, no_partial: bool) -> Self {
+        Discard {
+            path: (no_partial && target != destination).then(|| target.to_path_buf()),
+        }
+    }
+
+    /// The download got through: there's nothing to remove.
+    pub fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for Discard {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take()
+            && path.is_file()
+        {
+            tracing::info!("Removing '{}', --no-partial", path.display());
+            let _ = fs_ops::remove_file(&path);
+        }
+    }
+}
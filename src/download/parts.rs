@@ -0,0 +1,504 @@
+use crate::download::fs_ops;
+use crate::download::partial;
+use crate::download::presigned;
+use crate::download::target_wait;
+use crate::download::utils::{self, MAX_FILE_NAME};
+use anyhow::{Context, bail};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Hex digits of the hash that make up a download id.
+const ID_LEN: usize = 8;
+
+/// How worker mode splits a download into part files, kept in the
+/// download's manifest. Parts are named `<name>.<id>.p<index>`, numbered
+/// with four digits, next to the file they're merged into, unless
+/// `--resume-from` carries on with some in another directory.
+///
+/// The id is a short hash of the URL and its length, so the same download
+/// always gets the same names, while two different ones saved under the
+/// same name don't write over each other's parts.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PartLayout {
+    pub id: String,
+    /// The inclusive byte range each part holds, in the order they're
+    /// merged.
+    pub ranges: Vec<(u64, u64)>,
+    /// Where each range's part is. Manifests from before these were
+    /// recorded have none, their parts all being beside the file.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// The hex SHA-256 of each range's part once it was complete, which
+    /// `--verify-parts` checks a resumed one by. Left out of layouts from
+    /// before these were recorded, and for parts that aren't complete.
+    #[serde(default)]
+    pub sha256: Vec<Option<String>>,
+}
+
+impl PartLayout {
+    /// Ranges of the download of `url` to be placed, with
+    /// [`beside`](Self::beside).
+    pub fn new(url: &Url, content_length: u64, ranges: Vec<(u64, u64)>) -> Self {
+        Self {
+            id: download_id(url, content_length),
+            ranges,
+            paths: Vec::new(),
+            sha256: Vec::new(),
+        }
+    }
+
+    /// The SHA-256 recorded for the complete part of range `index`.
+    pub fn recorded_sha256(&self, index: usize) -> Option<&str> {
+        self.sha256.get(index)?.as_deref()
+    }
+
+    /// Records the SHA-256 of the complete part of range `index`.
+    pub fn record_sha256(&mut self, index: usize, sha256: String) {
+        if self.sha256.len() < self.ranges.len() {
+            self.sha256.resize(self.ranges.len(), None);
+        }
+        self.sha256[index] = Some(sha256);
+    }
+
+    /// Puts every part beside `final_path`.
+    pub fn beside(mut self, final_path: &Path) -> anyhow::Result<Self> {
+        self.paths = (0..self.ranges.len())
+            .map(|index| self.part_path(final_path, index))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(self)
+    }
+
+    /// The part of range `index` beside `final_path`. The name is cut short
+    /// to keep the suffix, which tells the parts apart.
+    fn part_path(&self, final_path: &Path, index: usize) -> anyhow::Result<PathBuf> {
+        let suffix = format!(".{}.p{index:04}", self.id);
+        let base_name = base_name(final_path)?;
+        let base_name = utils::truncate_file_name(&base_name, MAX_FILE_NAME - suffix.len());
+        let path = final_path.with_file_name(format!("{base_name}{suffix}"));
+        let max_path = utils::MAX_SYSTEM_PATH.unwrap_or(usize::MAX);
+        if path.as_os_str().len() > max_path {
+            bail!(
+                "Part file '{}' would be {} bytes long, more than the {max_path} the system allows; download to a shallower directory",
+                path.display(),
+                path.as_os_str().len()
+            );
+        }
+        Ok(utils::long_path(path))
+    }
+
+    /// The plan beside `final_path` that `--resume` picks the parts up
+    /// with, `<name>.<id>.plan`, so a run with other `--workers` still
+    /// splits the file the way the parts were.
+    fn plan_path(&self, final_path: &Path) -> anyhow::Result<PathBuf> {
+        let suffix = format!(".{}.plan", self.id);
+        let base_name = base_name(final_path)?;
+        let base_name = utils::truncate_file_name(&base_name, MAX_FILE_NAME - suffix.len());
+        Ok(utils::long_path(
+            final_path.with_file_name(format!("{base_name}{suffix}")),
+        ))
+    }
+
+    /// Records the layout beside `final_path`, before any part is written.
+    /// Waits for a target that isn't there yet, as the parts do.
+    pub fn save_plan(&self, final_path: &Path) -> anyhow::Result<()> {
+        let path = self.plan_path(final_path)?;
+        let json = serde_json::to_vec(self)?;
+        target_wait::retry("create", &path, || fs_ops::write(&path, &json))
+            .with_context(|| format!("Cannot write '{}'", path.display()))
+    }
+
+    /// The layout an earlier run of the download of `url` into
+    /// `final_path` saved, if it left one that can be read.
+    pub fn load_plan(url: &Url, content_length: u64, final_path: &Path) -> Option<Self> {
+        let layout = Self::new(url, content_length, Vec::new());
+        let json = fs::read(layout.plan_path(final_path).ok()?).ok()?;
+        serde_json::from_slice::<Self>(&json)
+            .ok()
+            .filter(|plan| plan.id == layout.id && plan.paths.len() == plan.ranges.len())
+    }
+
+    /// Removes the plan beside `final_path`, once the parts are merged.
+    pub fn remove_plan(&self, final_path: &Path) -> anyhow::Result<()> {
+        match fs_ops::remove_file(&self.plan_path(final_path)?) {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// The plans worker-mode downloads left in `dir`, with the file each
+    /// was going to be merged into. Ones that can't be read are skipped.
+    pub fn find_plans(dir: &Path) -> std::io::Result<Vec<(PathBuf, Self)>> {
+        let mut plans = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+                continue;
+            };
+            let Some((base_name, id)) = name
+                .strip_suffix(".plan")
+                .and_then(|stem| stem.rsplit_once('.'))
+            else {
+                continue;
+            };
+            if id.len() != ID_LEN || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                continue;
+            }
+            if let Ok(json) = fs::read(&path)
+                && let Ok(plan) = serde_json::from_slice::<Self>(&json)
+                && plan.id == id
+            {
+                plans.push((dir.join(base_name), plan));
+            }
+        }
+        plans.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(plans)
+    }
+
+    /// Bytes the parts hold so far, and the size of the whole download.
+    pub fn progress(&self, final_path: &Path) -> (u64, u64) {
+        let held = self
+            .ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (start, end))| {
+                let path = self.path(final_path, index).ok()?;
+                Some(fs::metadata(path).ok()?.len().min(end + 1 - start))
+            })
+            .sum();
+        let size = self.ranges.last().map_or(0, |(_, end)| end + 1);
+        (held, size)
+    }
+
+    /// Gives range `index` a new part beside `final_path`, named like no
+    /// other part of the layout or file already there, for a part that
+    /// failed its check to be downloaded again into.
+    pub fn move_part(&mut self, index: usize, final_path: &Path) -> anyhow::Result<()> {
+        self.paths[index] = self.unused_path(final_path)?;
+        if let Some(sha256) = self.sha256.get_mut(index) {
+            *sha256 = None;
+        }
+        Ok(())
+    }
+
+    /// Splits range `index` at byte `at`, the bytes from there on going to
+    /// a new part beside `final_path`, named like [`move_part`]'s. The new
+    /// range comes last, out of order until [`sort`](Self::sort): its index.
+    ///
+    /// [`move_part`]: Self::move_part
+    pub fn split(&mut self, index: usize, at: u64, final_path: &Path) -> anyhow::Result<usize> {
+        let path = self.unused_path(final_path)?;
+        let end = self.ranges[index].1;
+        self.ranges[index].1 = at - 1;
+        self.ranges.push((at, end));
+        self.paths.push(path);
+        if !self.sha256.is_empty() {
+            self.sha256.resize(self.ranges.len(), None);
+        }
+        Ok(self.ranges.len() - 1)
+    }
+
+    /// Puts the ranges back in the order they're merged in after a
+    /// [`split`](Self::split): the index each had, in their new order.
+    pub fn sort(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.ranges.len()).collect();
+        order.sort_by_key(|index| self.ranges[*index].0);
+        self.ranges = order.iter().map(|index| self.ranges[*index]).collect();
+        self.paths = order
+            .iter()
+            .map(|index| self.paths[*index].clone())
+            .collect();
+        if !self.sha256.is_empty() {
+            self.sha256.resize(order.len(), None);
+            self.sha256 = order
+                .iter()
+                .map(|index| self.sha256[*index].clone())
+                .collect();
+        }
+        order
+    }
+
+    /// A part path beside `final_path` no range of the layout has, and no
+    /// file is at.
+    fn unused_path(&self, final_path: &Path) -> anyhow::Result<PathBuf> {
+        let mut unused = 0;
+        loop {
+            let path = self.part_path(final_path, unused)?;
+            if !self.paths.contains(&path) && !path.exists() {
+                return Ok(path);
+            }
+            unused += 1;
+        }
+    }
+
+    /// Where range `index` of a download into `final_path` is.
+    fn path(&self, final_path: &Path, index: usize) -> anyhow::Result<PathBuf> {
+        match self.paths.get(index) {
+            Some(path) => Ok(path.clone()),
+            None => self.part_path(final_path, index),
+        }
+    }
+
+    /// Whether `path` is one of this download's parts that the layout
+    /// doesn't use, as when an earlier run had more workers.
+    fn is_extra(&self, base_name: &str, path: &Path) -> bool {
+        let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+            return false;
+        };
+        let Some((_, index)) = name.rsplit_once(&format!(".{}.p", self.id)) else {
+            return false;
+        };
+        let Some(index) = digits(index).then(|| index.parse::<usize>().ok()).flatten() else {
+            return false;
+        };
+        let suffix = format!(".{}.p{index:04}", self.id);
+        // The same directory may be spelled differently.
+        let dir = path.parent().and_then(|dir| dir.canonicalize().ok());
+        !self.paths.iter().any(|part| {
+            part.file_name() == path.file_name()
+                && part.parent().and_then(|dir| dir.canonicalize().ok()) == dir
+        }) && name
+            == format!(
+                "{}{suffix}",
+                utils::truncate_file_name(base_name, MAX_FILE_NAME - suffix.len())
+            )
+    }
+}
+
+/// A short hash of `url` and `content_length`, telling one download's parts
+/// from another's. A presigned URL's query is left out, so the parts are
+/// picked up again with a URL signed anew.
+fn download_id(url: &Url, content_length: u64) -> String {
+    let mut url = url.clone();
+    if presigned::is_presigned(&url) {
+        url.set_query(None);
+    }
+    let mut id = hex::encode(Sha256::digest(format!("{url}\n{content_length}")));
+    id.truncate(ID_LEN);
+    id
+}
+
+/// A worker-mode download that stopped with parts left: in another
+/// directory for `--resume-from`, as its manifest recorded it, or beside
+/// the file for `--resume`, as its plan did.
+#[derive(Clone, Debug)]
+pub struct ResumeFrom {
+    /// The file the parts were going to be merged into.
+    pub file: PathBuf,
+    pub layout: PartLayout,
+}
+
+/// What's left of a [`ResumeFrom`] download, carried on into a new file.
+#[derive(Debug)]
+pub struct Resumption {
+    /// The parts already there, in place, and new ones for the rest.
+    pub layout: PartLayout,
+    /// The indices in `layout` of the new parts, left to download.
+    pub fetch: Vec<usize>,
+    /// For each range of `layout`, the bytes of the earlier run's range
+    /// already there ahead of it: what its part holds, for a new part.
+    pub done: Vec<u64>,
+    /// Bytes already there.
+    pub kept: u64,
+    /// Of `kept`, the bytes the file being carried on into already holds,
+    /// which the parts are merged after.
+    pub prefix: u64,
+}
+
+impl ResumeFrom {
+    /// The file holding the bytes the earlier run kept ahead of its parts:
+    /// the `.part` of [`file`](Self::file), or the file itself as earlier
+    /// versions left it.
+    pub fn ahead(&self) -> PathBuf {
+        partial::target(&self.file, true)
+    }
+
+    /// Keeps whatever the parts hold, each being the start of its range,
+    /// and plans a new part beside `final_path` for the rest of each range.
+    /// The bytes the earlier run had kept ahead of its parts come from the
+    /// file it was merging into, or stay where they are when that's
+    /// `final_path`.
+    pub fn carry_on(
+        &self,
+        url: &Url,
+        content_length: u64,
+        final_path: &Path,
+    ) -> anyhow::Result<Resumption> {
+        let earlier = &self.layout;
+        if earlier.id != download_id(url, content_length) {
+            bail!(
+                "The parts next to '{}' are of another download, or the remote file changed since; start over without --resume-from",
+                self.file.display()
+            );
+        }
+        let mut layout = PartLayout {
+            id: earlier.id.clone(),
+            ranges: Vec::new(),
+            paths: Vec::new(),
+            sha256: Vec::new(),
+        };
+        let in_place = self.file == final_path;
+        let mut fetch = Vec::new();
+        let mut done = Vec::new();
+        let mut kept = 0;
+        let first = earlier
+            .ranges
+            .first()
+            .map_or(content_length, |(start, _)| *start);
+        if first > 0 {
+            let ahead = self.ahead();
+            let len = fs::metadata(&ahead).map_or(0, |metadata| metadata.len());
+            if len < first {
+                bail!(
+                    "'{}' has {len} bytes, but the earlier run had kept {first}; start over without --resume-from",
+                    ahead.display()
+                );
+            }
+            if !in_place {
+                layout.ranges.push((0, first - 1));
+                layout.paths.push(ahead);
+                layout.sha256.push(None);
+                done.push(0);
+            }
+            kept += first;
+        }
+        // In place, the rest of a range goes to a part no range used yet.
+        let mut unused = (0..).filter(|index| {
+            earlier
+                .part_path(final_path, *index)
+                .is_ok_and(|path| !earlier.paths.contains(&path))
+        });
+        for (index, &(start, end)) in earlier.ranges.iter().enumerate() {
+            let part = earlier.path(&self.file, index)?;
+            let have = fs::metadata(&part)
+                .map_or(0, |metadata| metadata.len())
+                .min(end + 1 - start);
+            if have > 0 {
+                let complete = start + have > end;
+                layout.ranges.push((start, start + have - 1));
+                layout.paths.push(part);
+                layout.sha256.push(
+                    earlier
+                        .recorded_sha256(index)
+                        .filter(|_| complete)
+                        .map(str::to_string),
+                );
+                done.push(0);
+                kept += have;
+            }
+            if start + have <= end {
+                let new = match in_place {
+                    true => layout.part_path(final_path, unused.next().unwrap_or(index))?,
+                    false => layout.part_path(final_path, index)?,
+                };
+                if earlier.paths.contains(&new) {
+                    bail!(
+                        "'{}' would take the place of a part of the earlier run; carry on into another directory",
+                        new.display()
+                    );
+                }
+                fetch.push(layout.ranges.len());
+                layout.ranges.push((start + have, end));
+                layout.paths.push(new);
+                layout.sha256.push(None);
+                done.push(have);
+            }
+        }
+        Ok(Resumption {
+            layout,
+            fetch,
+            done,
+            kept,
+            prefix: if in_place { first } else { 0 },
+        })
+    }
+}
+
+impl Resumption {
+    /// The bytes `(done, left)` of the range of each part left to
+    /// download, in the order of [`fetch`](Self::fetch), for
+    /// [`resume_schedule`](crate::download::chunks::resume_schedule).
+    pub fn segments(&self) -> Vec<(u64, u64)> {
+        self.fetch
+            .iter()
+            .map(|index| {
+                let (start, end) = self.layout.ranges[*index];
+                (self.done[*index], end + 1 - start)
+            })
+            .collect()
+    }
+
+    /// Whether range `index` of the layout is a part kept from the earlier
+    /// run, rather than one left to download or the file ahead of them.
+    pub fn is_kept_part(&self, index: usize, earlier: &ResumeFrom) -> bool {
+        !self.fetch.contains(&index) && self.layout.paths[index] != earlier.ahead()
+    }
+
+    /// Downloads range `index`, a part kept from the earlier run that
+    /// failed its check, again into a new part beside `final_path`.
+    pub fn refetch(&mut self, index: usize, final_path: &Path) -> anyhow::Result<()> {
+        self.layout.move_part(index, final_path)?;
+        let (start, end) = self.layout.ranges[index];
+        self.kept -= end + 1 - start;
+        self.fetch.push(index);
+        self.fetch.sort_unstable();
+        Ok(())
+    }
+}
+
+/// Whether `name` is a part of `final_path` in the naming used before
+/// [`PartLayout`], `<name>.part.<start>-<end>`. Those are still cleaned up
+/// for a release.
+fn is_by_range(base_name: &str, name: &str) -> bool {
+    let Some((_, range)) = name.rsplit_once(".part.") else {
+        return false;
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return false;
+    };
+    let suffix = format!(".part.{range}");
+    digits(start)
+        && digits(end)
+        && name
+            == format!(
+                "{}{suffix}",
+                utils::truncate_file_name(base_name, MAX_FILE_NAME - suffix.len())
+            )
+}
+
+fn digits(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn base_name(final_path: &Path) -> anyhow::Result<String> {
+    Ok(final_path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Removes parts beside `final_path` that `layout` won't merge: ones named
+/// by byte range by earlier versions, and this download's own that it
+/// doesn't use. Returns how many went.
+pub fn remove_stale(final_path: &Path, layout: &PartLayout) -> anyhow::Result<usize> {
+    let base_name = base_name(final_path)?;
+    let dir = match final_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut removed = 0;
+    for entry in fs::read_dir(utils::long_path(dir.to_path_buf()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_by_range(&base_name, &name) || layout.is_extra(&base_name, &entry.path()) {
+            tracing::info!("Removing a stale part, '{name}'");
+            fs_ops::remove_file(&entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,132 @@
+use crate::download::utils;
+use anyhow::{Context, bail};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Expected SHA-256 of every fixed-size piece of the file, from
+/// `--piece-hashes`.
+///
+/// The file holds the piece size (e.g. `1M` or `1048576`) on the first line,
+/// then one hex SHA-256 per line, in order. Blank lines and `#` comments are
+/// ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceHashes {
+    pub piece_size: u64,
+    pub hashes: Vec<[u8; 32]>,
+}
+
+impl PieceHashes {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read piece hashes '{}'", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid piece hashes '{}'", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let piece_size = lines
+            .next()
+            .context("missing the piece size")
+            .and_then(|line| utils::parse_byte_size(line).map_err(anyhow::Error::msg))?;
+        if piece_size == 0 {
+            bail!("the piece size can't be zero");
+        }
+        let hashes = lines
+            .enumerate()
+            .map(|(index, line)| {
+                let mut hash = [0; 32];
+                hex::decode_to_slice(line, &mut hash)
+                    .with_context(|| format!("piece {index}: '{line}' is not a SHA-256"))?;
+                Ok(hash)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { piece_size, hashes })
+    }
+
+    /// Checks the list covers a file of `content_length` bytes exactly.
+    pub fn check_length(&self, content_length: u64) -> anyhow::Result<()> {
+        let expected = content_length.div_ceil(self.piece_size);
+        if self.hashes.len() as u64 != expected {
+            bail!(
+                "{} piece hashes given, but {content_length} bytes in pieces of {} make {expected}",
+                self.hashes.len(),
+                self.piece_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Inclusive byte range of piece `index` in a file of `content_length`.
+    pub fn range(&self, index: usize, content_length: u64) -> (u64, u64) {
+        let start = index as u64 * self.piece_size;
+        (start, (start + self.piece_size).min(content_length) - 1)
+    }
+
+    pub fn matches(&self, index: usize, data: &[u8]) -> bool {
+        self.hashes[index] == <[u8; 32]>::from(Sha256::digest(data))
+    }
+}
+
+/// How a chunk's pieces fared.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PieceTally {
+    pub verified: usize,
+    /// Pieces that failed verification and were fetched again.
+    pub repaired: usize,
+}
+
+impl std::ops::AddAssign for PieceTally {
+    fn add_assign(&mut self, other: Self) {
+        self.verified += other.verified;
+        self.repaired += other.repaired;
+    }
+}
+
+/// Hashes a chunk's bytes as they stream in and notes every piece that
+/// doesn't match. Chunks start on a piece boundary and only the last one may
+/// end inside a piece, which is then the file's short last piece.
+pub(crate) struct PieceVerifier<'a> {
+    pieces: &'a PieceHashes,
+    /// One past the chunk's last byte.
+    limit: u64,
+    /// Absolute offset of the next byte.
+    offset: u64,
+    hasher: Sha256,
+    pub(crate) verified: usize,
+    pub(crate) failed: Vec<usize>,
+}
+
+impl<'a> PieceVerifier<'a> {
+    pub(crate) fn new(pieces: &'a PieceHashes, (start, end): (u64, u64)) -> Self {
+        Self {
+            pieces,
+            limit: end + 1,
+            offset: start,
+            hasher: Sha256::new(),
+            verified: 0,
+            failed: Vec::new(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() && self.offset < self.limit {
+            let index = (self.offset / self.pieces.piece_size) as usize;
+            let (_, piece_end) = self.pieces.range(index, self.limit);
+            let take = data.len().min((piece_end + 1 - self.offset) as usize);
+            self.hasher.update(&data[..take]);
+            self.offset += take as u64;
+            data = &data[take..];
+            if self.offset == piece_end + 1 {
+                let hash: [u8; 32] = self.hasher.finalize_reset().into();
+                if hash == self.pieces.hashes[index] {
+                    self.verified += 1;
+                } else {
+                    self.failed.push(index);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::download::chunks::{self, Workers};
+use crate::download::client::ClientOptions;
+use crate::download::http;
+use crate::download::in_place;
+use crate::download::newer;
+use crate::download::options::TransferOptions;
+use crate::download::partial;
+use crate::download::parts::PartLayout;
+use crate::download::remote::{ProbeMethod, probe_remote};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// What a download would do, worked out from a single preflight request
+/// without writing anything.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Plan {
+    pub url: String,
+    /// Where redirects end up.
+    pub final_url: String,
+    /// The request the server answered usefully.
+    pub probed_with: ProbeMethod,
+    pub destination: PathBuf,
+    pub action: Action,
+    /// `None` when the server doesn't send a length.
+    pub size: Option<u64>,
+    /// Whether the server advertises `Accept-Ranges: bytes`.
+    pub accepts_ranges: bool,
+    /// Workers that would run, 1 for a single stream.
+    pub workers: u8,
+    /// Byte ranges that would be requested: one per worker, or more with
+    /// `--chunk-order` or `--segment-size`.
+    pub segments: Vec<Segment>,
+    /// Disk space needed at the peak, counting part files. `None` when the
+    /// size is unknown.
+    pub disk_usage: Option<u64>,
+    pub network: Network,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Overwrite,
+    Resume {
+        from: u64,
+    },
+    /// The download would stop without transferring anything.
+    Skip {
+        reason: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Segment {
+    /// Inclusive byte offsets.
+    pub start: u64,
+    pub end: u64,
+    /// With part files, the one worker mode writes the segment to before
+    /// merging.
+    pub part_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Network {
+    /// Address outgoing connections are bound to, from `--interface`,
+    /// `-4` or `-6`.
+    pub source_address: Option<IpAddr>,
+    /// Proxy picked up from the environment, with its password redacted.
+    pub proxy: Option<String>,
+    /// User name sent as basic auth, taken from the URL.
+    pub user: Option<String>,
+}
+
+/// Plans the download of `url` with `workers` workers (one for a single
+/// stream), the same way the download itself would decide.
+pub async fn plan_download(
+    client: &reqwest::Client,
+    client_options: &ClientOptions,
+    url: &Url,
+    target_dir: &Path,
+    workers: impl Into<Workers>,
+    options: &TransferOptions,
+) -> anyhow::Result<Plan> {
+    let remote = probe_remote(
+        client,
+        url,
+        &options.retry_budget,
+        options.auth_token.as_deref(),
+    )
+    .await?;
+    let (size, accepts_ranges) = (remote.size, remote.ranges);
+
+    let destination = options.served_destination(url, target_dir, &remote.headers);
+    // What a resumed download carries on from, which its `.part` holds.
+    let resumed = match (options.resume && !options.overwrite) || options.continue_at.is_some() {
+        true => partial::target(&destination, true),
+        false => destination.clone(),
+    };
+    let existing = std::fs::metadata(&resumed)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len());
+    // As worker mode splits it once it knows the size.
+    let workers: Workers = workers.into();
+    let workers = match size {
+        Some(size) if accepts_ranges => {
+            chunks::split(workers, size, options.min_split_size.unwrap_or(0)).workers
+        }
+        _ => workers.most().max(1),
+    };
+    let action = match options.continue_offset(&resumed) {
+        _ if workers > 1 && options.continue_at.is_some() => {
+            skip("--continue-at only works for single-stream downloads")
+        }
+        Err(error) => Action::Skip {
+            reason: format!("{error:#}"),
+        },
+        Ok(Some(from)) if size.is_some_and(|size| from > size) => {
+            skip("offset is beyond the remote size")
+        }
+        Ok(Some(from)) => Action::Resume { from },
+        Ok(None) => match (existing, size) {
+            (_, None) if workers > 1 => skip("content length not available for worker mode"),
+            _ if workers > 1 && !accepts_ranges => skip("server doesn't support range requests"),
+            (None, _) => Action::Create,
+            // Worker mode replaces the file unless resuming.
+            (Some(_), _) if options.overwrite || (workers > 1 && !options.resume) => {
+                Action::Overwrite
+            }
+            // --restart-on-unresumable starts over when resuming can't work.
+            (Some(len), Some(size))
+                if options.resume && len > size && options.restart_on_unresumable =>
+            {
+                Action::Overwrite
+            }
+            (Some(len), Some(size)) if options.resume && len >= size => skip("already complete"),
+            (Some(_), _) if options.resume && !accepts_ranges && options.restart_on_unresumable => {
+                Action::Overwrite
+            }
+            (Some(_), _) if options.resume && !accepts_ranges => {
+                skip("server doesn't support resume, try --overwrite")
+            }
+            // Worker mode resumes at a whole piece.
+            (Some(len), _) if options.resume => Action::Resume {
+                from: len - len % options.chunk_alignment(),
+            },
+            (Some(_), _) => skip("file exists, pass --resume or --overwrite"),
+        },
+    };
+    // Nothing else matters if the file hasn't changed.
+    let action = match options
+        .newer_than
+        .and_then(|since| newer::not_newer_by_headers(&remote.headers, since.server_time()))
+    {
+        Some(reason) => Action::Skip { reason },
+        None => action,
+    };
+
+    let (segments, disk_usage) = match (&action, size) {
+        (Action::Skip { .. }, _) => (Vec::new(), Some(0)),
+        (_, None) => (Vec::new(), None),
+        (_, Some(0)) => (Vec::new(), Some(0)),
+        (action, Some(size)) if workers == 1 => {
+            let start = match action {
+                Action::Resume { from } => *from,
+                _ => 0,
+            };
+            let segment = Segment {
+                start,
+                end: size - 1,
+                part_file: None,
+            };
+            (vec![segment], Some(size - start))
+        }
+        (action, Some(size)) => {
+            let from = match action {
+                Action::Resume { from } => *from,
+                _ => 0,
+            };
+            let ranges = chunks::part_ranges(
+                size,
+                from,
+                options.segments(workers, size - from),
+                options.chunk_alignment(),
+            );
+            // As worker mode decides: parts an earlier run left are carried
+            // on from as parts, and a file left unfinished in place in it.
+            let carrying_on = options.resume && !options.overwrite;
+            let in_place = (carrying_on && in_place::unfinished(&destination))
+                || (in_place::unavailable(options).is_none()
+                    && !(carrying_on && PartLayout::load_plan(url, size, &destination).is_some()));
+            let segments = ranges
+                .iter()
+                .zip(
+                    PartLayout::new(url, size, ranges.clone())
+                        .beside(&destination)?
+                        .paths,
+                )
+                .map(|(&(start, end), part)| Segment {
+                    start,
+                    end,
+                    part_file: (!in_place).then_some(part),
+                })
+                .collect::<Vec<_>>();
+            // Sized up front, with nothing to merge.
+            let merge = match in_place {
+                true => 0,
+                // Parts are removed one by one as they're merged, so the peak
+                // is every part plus the final file short of the last part.
+                false if options.no_cleanup => size - from,
+                false => segments
+                    .iter()
+                    .map(|segment| segment.end - segment.start + 1)
+                    .max()
+                    .unwrap_or(0),
+            };
+            (segments, Some(size - from + merge))
+        }
+    };
+
+    let user = Some(url.username())
+        .filter(|user| !user.is_empty())
+        .map(str::to_string);
+    Ok(Plan {
+        url: http::redact_url(url),
+        final_url: http::redact_url(&remote.final_url),
+        probed_with: remote.method,
+        destination,
+        action,
+        size,
+        accepts_ranges,
+        workers,
+        segments,
+        disk_usage,
+        network: Network {
+            source_address: client_options.local_address()?,
+            proxy: client_options
+                .proxy_for(url)
+                .map(|proxy| http::redact_url(&proxy)),
+            user,
+        },
+    })
+}
+
+fn skip(reason: &str) -> Action {
+    Action::Skip {
+        reason: reason.to_string(),
+    }
+}
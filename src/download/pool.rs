@@ -0,0 +1,410 @@
+//! Running several downloads at once from a library, under limits they
+//! share. A [`DownloadPool`] starts what's submitted to it as its limits
+//! allow: so many downloads at a time, so many connections to a host, one
+//! rate limit over all of them and the run's retry budget. Every download
+//! gets a [`PoolHandle`] of its own, and the pool sends what happens to
+//! all of them as one stream of [`PoolEvent`]s, with [`PoolTotals`] adding
+//! their progress up for a combined readout.
+
+use crate::download::Downloader;
+use crate::download::chunks::Workers;
+use crate::download::error::{self, DownloadError};
+use crate::download::memory::MemoryLimit;
+use crate::download::options::TransferOptions;
+use crate::download::progress::TransferProgress;
+use crate::download::progress_handle::{ProgressHandle, ProgressSnapshot, TransferState};
+use crate::download::retry_budget::RetryBudget;
+use crate::download::throttle::Throttle;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Semaphore, broadcast, watch};
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// How many events a receiver that doesn't keep up may fall behind by
+/// before it misses some.
+const EVENT_CAPACITY: usize = 1024;
+
+/// What a download [`PoolHandle::cancel`] stopped fails with.
+const CANCELLED: &str = "Download cancelled.";
+
+/// The limits every download of a pool shares.
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// Downloads running at a time; the rest wait their turn.
+    pub parallelism: usize,
+    /// Connections open to one host at a time, across every download. A
+    /// worker-mode download takes one per worker, and has its workers cut
+    /// down to this many.
+    pub per_host: usize,
+    /// Bytes per second over every download, `None` for no limit.
+    pub rate_limit: Option<u64>,
+    /// Retries allowed over every download, which replaces the budget in
+    /// their options; see [`retry_budget`](crate::download::retry_budget).
+    pub retry_budget: Arc<RetryBudget>,
+    /// Buffer memory over every download, which replaces the accounting in
+    /// their options; `None` leaves each download its own, and its own
+    /// `--max-memory`. See [`MemoryLimit`].
+    pub memory: Option<Arc<MemoryLimit>>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            parallelism: 4,
+            per_host: 8,
+            rate_limit: None,
+            retry_budget: Arc::default(),
+            memory: None,
+        }
+    }
+}
+
+/// One download to submit to a pool.
+#[derive(Clone, Debug)]
+pub struct DownloadRequest {
+    pub url: Url,
+    pub target_dir: PathBuf,
+    /// Workers in worker mode, or 1 for a single stream.
+    pub workers: Workers,
+    /// The pool's rate limit replaces `throttle`.
+    pub options: TransferOptions,
+    /// Sends the requests instead of the pool's client, for headers or a
+    /// proxy of the download's own.
+    pub client: Option<reqwest::Client>,
+    /// Stops the download once set, the way an interrupted download stops,
+    /// as for Ctrl+C. [`PoolHandle::cancel`] sets it too, and takes a
+    /// queued download off the queue as well.
+    pub interrupted: Option<Arc<AtomicBool>>,
+}
+
+impl DownloadRequest {
+    /// A single-stream download of `url` into `target_dir`.
+    pub fn new(url: Url, target_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            url,
+            target_dir: target_dir.into(),
+            workers: Workers::Count(1),
+            options: TransferOptions::default(),
+            client: None,
+            interrupted: None,
+        }
+    }
+
+    pub fn workers(mut self, workers: impl Into<Workers>) -> Self {
+        self.workers = workers.into();
+        self
+    }
+
+    pub fn options(mut self, options: TransferOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn interrupted(mut self, interrupted: Arc<AtomicBool>) -> Self {
+        self.interrupted = Some(interrupted);
+        self
+    }
+}
+
+/// What happened to one of a pool's downloads, identified by the id of
+/// its [`PoolHandle`].
+#[derive(Clone, Debug)]
+pub enum PoolEvent {
+    /// Submitted, waiting for a free slot and connections to its host.
+    Queued {
+        id: usize,
+        url: Url,
+    },
+    Started {
+        id: usize,
+    },
+    Progress {
+        id: usize,
+        snapshot: Box<ProgressSnapshot>,
+    },
+    Finished {
+        id: usize,
+        path: PathBuf,
+    },
+    /// Failed or cancelled, with the error.
+    Failed {
+        id: usize,
+        error: String,
+    },
+}
+
+/// Every download of a pool added up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolTotals {
+    pub downloaded: u64,
+    /// Of the downloads whose size is known.
+    pub total: u64,
+    /// Bytes/s of the running downloads together.
+    pub speed: u64,
+    pub queued: usize,
+    pub running: usize,
+    pub completed: usize,
+    /// Failed or cancelled.
+    pub failed: usize,
+}
+
+/// Runs submitted downloads concurrently under [`PoolOptions`]. Must be
+/// used from within a tokio runtime.
+pub struct DownloadPool {
+    client: reqwest::Client,
+    options: PoolOptions,
+    slots: Arc<Semaphore>,
+    /// One semaphore of `per_host` permits for every host seen.
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    throttle: Throttle,
+    events: broadcast::Sender<PoolEvent>,
+    downloads: Mutex<BTreeMap<usize, Tracked>>,
+}
+
+/// What the pool keeps of a download for [`DownloadPool::totals`].
+struct Tracked {
+    progress: ProgressHandle,
+    started: Arc<AtomicBool>,
+}
+
+impl DownloadPool {
+    pub fn new(client: reqwest::Client, options: PoolOptions) -> Self {
+        Self {
+            client,
+            slots: Arc::new(Semaphore::new(options.parallelism.max(1))),
+            hosts: Mutex::default(),
+            throttle: Throttle::new(options.rate_limit),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            downloads: Mutex::default(),
+            options,
+        }
+    }
+
+    /// Every download's events, from the ones submitted after this call.
+    pub fn events(&self) -> broadcast::Receiver<PoolEvent> {
+        self.events.subscribe()
+    }
+
+    /// The rate limit shared by every download, which can be changed or
+    /// paused while they run.
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Queues `request`, to start once the limits allow.
+    pub fn submit(&self, request: DownloadRequest) -> PoolHandle {
+        let per_host = self.per_host() as u8;
+        let workers = match request.workers {
+            Workers::Count(count) => Workers::Count(count.clamp(1, per_host)),
+            // It could come to more than the host allows.
+            Workers::Auto if Workers::Auto.most() > per_host => Workers::Count(per_host),
+            Workers::Auto => Workers::Auto,
+        };
+        let interrupted = request.interrupted.unwrap_or_default();
+        // The size isn't known yet: the download adds the chunks a bigger
+        // file is split into, and drops those a smaller one isn't.
+        let progress = match workers {
+            Workers::Count(1) => TransferProgress::new(interrupted.clone()),
+            Workers::Count(first) => TransferProgress::chunked(
+                request.options.segments(first, 0).into(),
+                0,
+                interrupted.clone(),
+            ),
+            Workers::Auto => TransferProgress::chunked(
+                request.options.segments(1, 0).into(),
+                0,
+                interrupted.clone(),
+            ),
+        };
+        let started = Arc::new(AtomicBool::new(false));
+        let (cancel, mut cancelled) = watch::channel(false);
+        let id = {
+            let mut downloads = self.downloads.lock().unwrap();
+            let id = downloads.len();
+            downloads.insert(
+                id,
+                Tracked {
+                    progress: progress.handle(),
+                    started: started.clone(),
+                },
+            );
+            id
+        };
+        let _ = self.events.send(PoolEvent::Queued {
+            id,
+            url: request.url.clone(),
+        });
+
+        let handle = progress.handle();
+        let host = self.host(&request.url);
+        let slots = self.slots.clone();
+        let events = self.events.clone();
+        let client = request.client.unwrap_or_else(|| self.client.clone());
+        let downloader = Downloader::with_client(client)
+            .with_target_dir(request.target_dir)
+            .with_workers(workers)
+            .with_options(TransferOptions {
+                throttle: self.throttle.clone(),
+                retry_budget: self.options.retry_budget.clone(),
+                memory: self
+                    .options
+                    .memory
+                    .clone()
+                    .unwrap_or(request.options.memory),
+                ..request.options
+            });
+        let task = tokio::spawn(async move {
+            // Connections to the host first, so a download waiting for
+            // them doesn't hold a slot another host could use.
+            let queued = async {
+                let connections = host.acquire_many_owned(workers.most().into()).await?;
+                let slot = slots.acquire_owned().await?;
+                anyhow::Ok((connections, slot))
+            };
+            let (_connections, _slot) = tokio::select! {
+                acquired = queued => acquired?,
+                // Taken off the queue: there's nothing to stop or clean up.
+                _ = cancelled.wait_for(|cancelled| *cancelled) => {
+                    let result = Err(DownloadError::Interrupted.into());
+                    progress.finish_with(&result);
+                    let _ = events.send(PoolEvent::Failed {
+                        id,
+                        error: CANCELLED.to_string(),
+                    });
+                    return result;
+                }
+            };
+            started.store(true, Ordering::Relaxed);
+            let _ = events.send(PoolEvent::Started { id });
+
+            let forward = tokio::spawn(forward_progress(id, progress.handle(), events.clone()));
+            let result = downloader.download(request.url, progress).await;
+            let _ = forward.await;
+            let _ = events.send(match &result {
+                Ok(path) => PoolEvent::Finished {
+                    id,
+                    path: path.clone(),
+                },
+                Err(error) => PoolEvent::Failed {
+                    id,
+                    error: format!("{error:#}"),
+                },
+            });
+            result
+        });
+        PoolHandle {
+            id,
+            progress: handle,
+            interrupted,
+            cancel,
+            task,
+        }
+    }
+
+    /// What every download submitted so far adds up to.
+    pub fn totals(&self) -> PoolTotals {
+        let mut totals = PoolTotals::default();
+        for tracked in self.downloads.lock().unwrap().values() {
+            let snapshot = tracked.progress.snapshot();
+            totals.downloaded += snapshot.downloaded;
+            totals.total += snapshot.total;
+            match snapshot.state {
+                TransferState::Running if !tracked.started.load(Ordering::Relaxed) => {
+                    totals.queued += 1
+                }
+                TransferState::Running => {
+                    totals.running += 1;
+                    totals.speed += snapshot.speed;
+                }
+                TransferState::Completed => totals.completed += 1,
+                TransferState::Failed(_) | TransferState::Interrupted => totals.failed += 1,
+            }
+        }
+        totals
+    }
+
+    fn per_host(&self) -> usize {
+        self.options.per_host.clamp(1, u8::MAX.into())
+    }
+
+    fn host(&self, url: &Url) -> Arc<Semaphore> {
+        let key = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        );
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_host())))
+            .clone()
+    }
+}
+
+/// Sends `handle`'s updates as [`PoolEvent::Progress`] until the transfer
+/// is over.
+async fn forward_progress(
+    id: usize,
+    mut handle: ProgressHandle,
+    events: broadcast::Sender<PoolEvent>,
+) {
+    while handle.changed().await {
+        let snapshot = Box::new(handle.snapshot());
+        let over = snapshot.state.is_terminal();
+        let _ = events.send(PoolEvent::Progress { id, snapshot });
+        if over {
+            break;
+        }
+    }
+}
+
+/// One download of a pool.
+pub struct PoolHandle {
+    id: usize,
+    progress: ProgressHandle,
+    interrupted: Arc<AtomicBool>,
+    cancel: watch::Sender<bool>,
+    task: JoinHandle<anyhow::Result<PathBuf>>,
+}
+
+impl PoolHandle {
+    /// What the pool's events call this download.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn progress(&self) -> ProgressHandle {
+        self.progress.clone()
+    }
+
+    /// Stops the download, or takes it off the queue. It winds down the
+    /// way an interrupted download does, its partial file dealt with as its
+    /// options say; [`wait`](Self::wait) returns once it has.
+    pub fn cancel(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.cancel.send_replace(true);
+    }
+
+    /// Waits for the download to end, and returns where it was saved.
+    pub async fn wait(self) -> anyhow::Result<PathBuf> {
+        let result = match self.task.await {
+            Ok(result) => result,
+            Err(error) => Err(error.into()),
+        };
+        match result {
+            Err(error) if *self.cancel.borrow() && error::is_interrupted(&error) => {
+                Err(error.context(CANCELLED))
+            }
+            result => result,
+        }
+    }
+}
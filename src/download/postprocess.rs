@@ -0,0 +1,171 @@
+//! What happens to a download once its bytes have landed: hashing it,
+//! checking it, renaming it. Each is a [`PostProcessor`] step, run in
+//! order by a [`Pipeline`]; `dlm` builds one from its flags, and library
+//! callers can add their own to [`Downloader`]'s or [`BlockingDownloader`]'s.
+//!
+//! [`Downloader`]: crate::download::Downloader
+//! [`BlockingDownloader`]: crate::download::blocking::BlockingDownloader
+
+use crate::download::cosign::SignatureCheck;
+use crate::download::memory::MemoryLimit;
+use crate::download::progress_handle::ProgressSnapshot;
+use crate::download::utils;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// A download whose bytes have all landed, as the steps after it see it.
+#[derive(Clone, Debug)]
+pub struct DownloadOutcome {
+    pub url: Url,
+    /// Where it is; a step that moves it updates this.
+    pub path: PathBuf,
+    /// SHA-256 of the content, once the transfer or the `hash` step worked
+    /// it out.
+    pub sha256: Option<[u8; 32]>,
+    /// The progress at the end of the transfer.
+    pub snapshot: ProgressSnapshot,
+    /// What `--cosign-signature-url`'s check came to, once it passed.
+    pub signature: Option<SignatureCheck>,
+    /// The buffer memory the transfer counted against, which hashing in
+    /// the steps counts against too.
+    pub memory: Arc<MemoryLimit>,
+}
+
+impl DownloadOutcome {
+    /// The download of `url` to `path`, with the SHA-256 the transfer
+    /// worked out, if it did, and its buffer `memory`.
+    pub fn new(
+        url: Url,
+        path: PathBuf,
+        snapshot: ProgressSnapshot,
+        memory: Arc<MemoryLimit>,
+    ) -> Self {
+        let sha256 = snapshot
+            .sha256
+            .as_ref()
+            .and_then(|sha256| hex::decode(sha256).ok()?.try_into().ok());
+        Self {
+            url,
+            path,
+            sha256,
+            snapshot,
+            signature: None,
+            memory,
+        }
+    }
+}
+
+/// A step after a download.
+pub trait PostProcessor: Send + Sync {
+    /// What it's called in timings and errors, as in `checksum`.
+    fn name(&self) -> &str;
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()>;
+}
+
+/// The steps after a download, in the order they run.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    steps: Vec<Arc<dyn PostProcessor>>,
+}
+
+/// How long a step took.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StepTiming {
+    pub step: String,
+    pub elapsed: Duration,
+}
+
+/// What a step's error is wrapped in: the download itself succeeded.
+#[derive(Debug)]
+pub struct StepFailed {
+    pub step: String,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for StepFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Downloaded '{}', but post-processing step '{}' failed",
+            self.path.display(),
+            self.step
+        )
+    }
+}
+
+impl Pipeline {
+    /// Adds `step` after the others.
+    pub fn push(&mut self, step: impl PostProcessor + 'static) {
+        self.steps.push(Arc::new(step));
+    }
+
+    /// [`push`](Self::push), for building one up.
+    pub fn with_step(mut self, step: impl PostProcessor + 'static) -> Self {
+        self.push(step);
+        self
+    }
+
+    /// The steps' names, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.steps.iter().map(|step| step.name()).collect()
+    }
+
+    /// Runs every step on `outcome`, stopping at the first that fails, its
+    /// error wrapped in [`StepFailed`].
+    pub fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<Vec<StepTiming>> {
+        let mut timings = Vec::with_capacity(self.steps.len());
+        for step in &self.steps {
+            let started = Instant::now();
+            if let Err(error) = step.run(outcome) {
+                return Err(error.context(StepFailed {
+                    step: step.name().to_string(),
+                    path: outcome.path.clone(),
+                }));
+            }
+            let elapsed = started.elapsed();
+            tracing::debug!("Post-processing step '{}' took {elapsed:?}", step.name());
+            timings.push(StepTiming {
+                step: step.name().to_string(),
+                elapsed,
+            });
+        }
+        Ok(timings)
+    }
+}
+
+/// The first step: the SHA-256 of the file, unless the transfer already
+/// worked it out.
+pub struct Hash;
+
+impl PostProcessor for Hash {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        if outcome.sha256.is_none() {
+            outcome.sha256 = Some(utils::hash_file(&outcome.path, &outcome.memory)?);
+        }
+        Ok(())
+    }
+}
+
+/// How long each step took, as in `hash 0.12s, checksum 0.03s`.
+pub struct Timings<'a>(pub &'a [StepTiming]);
+
+impl fmt::Display for Timings<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, timing) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {:.2}s", timing.step, timing.elapsed.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::content_disposition;
+use crate::download::http;
+use crate::download::remote::{self, RemoteInfo};
+use crate::download::retry_budget::RetryBudget;
+use crate::download::speed::Size;
+use crate::download::utils;
+use anyhow::Context;
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// What a HEAD request found out about one URL of a run over many, before
+/// any of them is downloaded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Entry {
+    /// The URL, with any password redacted.
+    pub url: String,
+    /// The name it would be saved under: the one the server gives it, or
+    /// the one at the end of where redirects end up.
+    pub file_name: String,
+    /// `None` when the server doesn't send a length.
+    pub size: Option<u64>,
+    /// Whether the server advertises `Accept-Ranges: bytes`.
+    pub resumable: bool,
+    /// `None` when no answer came.
+    pub status: Option<u16>,
+    /// Why the entry would fail, if it would.
+    pub error: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub checked_at: u64,
+}
+
+impl Entry {
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// A 404 or 410: a dead link.
+    pub fn missing(&self) -> bool {
+        matches!(self.status, Some(404 | 410))
+    }
+
+    /// A 401 or 403: there, but not to us.
+    pub fn forbidden(&self) -> bool {
+        matches!(self.status, Some(401 | 403))
+    }
+}
+
+/// Asks the server about `url` the way [`probe_remote`](remote::probe_remote)
+/// does, without reading a body. Failures are part of the entry rather
+/// than an error.
+pub async fn check(
+    client: &reqwest::Client,
+    url: &Url,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> Entry {
+    let mut entry = Entry {
+        url: http::redact_url(url),
+        file_name: file_name(url),
+        size: None,
+        resumable: false,
+        status: None,
+        error: None,
+        checked_at: now_millis(),
+    };
+    let (response, method) = match remote::probe(client, url, budget, auth).await {
+        Ok(probed) => probed,
+        Err(error) => {
+            entry.error = Some(format!("{:#}", anyhow::Error::new(error)));
+            return entry;
+        }
+    };
+    let status = response.status();
+    entry.status = Some(status.as_u16());
+    if !status.is_success() {
+        entry.error = Some(status.to_string());
+        return entry;
+    }
+    match RemoteInfo::new(&response, method) {
+        Ok(remote) => {
+            entry.size = remote.size;
+            entry.resumable = remote.ranges;
+            entry.file_name = content_disposition::file_name(&remote.headers)
+                .unwrap_or_else(|| file_name(&remote.final_url));
+        }
+        Err(error) => entry.error = Some(format!("{error:#}")),
+    }
+    entry
+}
+
+/// [`check`]s every URL, `concurrency` at a time, in their order. Those
+/// `cache` has a fresh entry for aren't asked again, and what's found is
+/// kept in it; failures aren't, being worth another look.
+pub async fn check_all(
+    client: &reqwest::Client,
+    urls: &[Url],
+    concurrency: usize,
+    cache: Option<&mut PreflightCache>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> Vec<Entry> {
+    let checks: Vec<_> = urls.iter().map(|url| (client, url)).collect();
+    check_each(&checks, concurrency, cache, budget, auth).await
+}
+
+/// [`check_all`] with a client of each URL's own, for the headers it's
+/// downloaded with.
+pub async fn check_each(
+    checks: &[(&reqwest::Client, &Url)],
+    concurrency: usize,
+    cache: Option<&mut PreflightCache>,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> Vec<Entry> {
+    let cached: Vec<_> = checks
+        .iter()
+        .map(|(_, url)| cache.as_ref().and_then(|cache| cache.fresh(url).cloned()))
+        .collect();
+    let entries: Vec<Entry> = futures::stream::iter(checks.iter().zip(cached))
+        .map(|((client, url), cached)| async move {
+            match cached {
+                Some(entry) => entry,
+                None => check(client, url, budget, auth).await,
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    if let Some(cache) = cache {
+        for entry in entries.iter().filter(|entry| !entry.failed()) {
+            cache.entries.insert(entry.url.clone(), entry.clone());
+        }
+    }
+    entries
+}
+
+fn file_name(url: &Url) -> String {
+    utils::build_download_path(url, Path::new(""))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// The preflight of a whole run: every entry, the failed ones first, and
+/// what they add up to. Prints as a table, and serializes for other tools.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub entries: Vec<Entry>,
+    pub totals: Totals,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Totals {
+    pub entries: usize,
+    /// Bytes of the entries that would succeed and send a length.
+    pub bytes: u64,
+    /// Entries that would succeed without saying how big they are.
+    pub unknown_size: usize,
+    pub failed: usize,
+    pub missing: usize,
+    pub forbidden: usize,
+}
+
+impl Report {
+    pub fn new(mut entries: Vec<Entry>) -> Self {
+        // Stable, so each group keeps the input's order.
+        entries.sort_by_key(|entry| !entry.failed());
+        let mut totals = Totals {
+            entries: entries.len(),
+            ..Totals::default()
+        };
+        for entry in &entries {
+            match entry.size {
+                _ if entry.failed() => totals.failed += 1,
+                Some(size) => totals.bytes += size,
+                None => totals.unknown_size += 1,
+            }
+            totals.missing += entry.missing() as usize;
+            totals.forbidden += entry.forbidden() as usize;
+        }
+        Self { entries, totals }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| entry.file_name.chars().count())
+            .chain([4])
+            .max()
+            .unwrap_or(4);
+        writeln!(
+            f,
+            "{:<width$}  {:>10}  {:<9}  STATUS",
+            "FILE", "SIZE", "RESUMABLE"
+        )?;
+        for entry in &self.entries {
+            let size = entry
+                .size
+                .map_or("unknown".to_string(), |size| Size(size).to_string());
+            let status = match (&entry.error, entry.status) {
+                (Some(error), _) => format!("{error}: {}", entry.url),
+                (None, Some(status)) => status.to_string(),
+                (None, None) => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<width$}  {:>10}  {:<9}  {status}",
+                entry.file_name,
+                size,
+                if entry.resumable { "yes" } else { "no" }
+            )?;
+        }
+        write!(f, "{}", self.totals)
+    }
+}
+
+/// As in `Total: 1.2 GiB in 300 entries, 2 of unknown size; 3 failing (2
+/// missing, 1 forbidden)`.
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Total: {} in {} entries", Size(self.bytes), self.entries)?;
+        if self.unknown_size > 0 {
+            write!(f, ", {} of unknown size", self.unknown_size)?;
+        }
+        if self.failed > 0 {
+            write!(
+                f,
+                "; {} failing ({} missing, {} forbidden)",
+                self.failed, self.missing, self.forbidden
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Preflight entries kept in a JSON file by URL, so a run right after a dry
+/// run doesn't ask the server for them all again. Entries older than
+/// `max_age` are asked for again.
+#[derive(Debug)]
+pub struct PreflightCache {
+    path: PathBuf,
+    max_age: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl PreflightCache {
+    /// The cache in `path`, empty if there's no such file yet.
+    pub fn load(path: &Path, max_age: Duration) -> anyhow::Result<Self> {
+        let entries = match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Reading the preflight cache '{}'", path.display()))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(error) => return Err(error.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_age,
+            entries,
+        })
+    }
+
+    /// The entry for `url`, unless there's none or it's gone stale.
+    pub fn fresh(&self, url: &Url) -> Option<&Entry> {
+        let entry = self.entries.get(&http::redact_url(url))?;
+        let age = now_millis().saturating_sub(entry.checked_at);
+        (u128::from(age) <= self.max_age.as_millis()).then_some(entry)
+    }
+
+    /// Writes the cache back. It's what a `--dry-run` keeps for the next
+    /// run, so it's written past the guard that stops a dry run writing.
+    pub fn save(&self) -> anyhow::Result<()> {
+        fs::write(&self.path, serde_json::to_vec_pretty(&self.entries)?)
+            .with_context(|| format!("Writing the preflight cache '{}'", self.path.display()))
+    }
+}
@@ -0,0 +1,114 @@
+//! Presigned object-store URLs, as S3, GCS and the stores copying their
+//! signing (MinIO, R2) hand out. The signature in the query covers the
+//! method, so only GET is allowed, and often not HEAD: sizes are asked for
+//! with a one-byte range instead. An expired one answers 403 with an XML
+//! `<Code>`, which is reported as such rather than as a bare 403.
+
+use crate::download::error::DownloadError;
+use crate::download::error_body;
+use crate::download::http;
+use crate::download::utils;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Set by `--presigned`, for signing that isn't recognized.
+static FORCED: AtomicBool = AtomicBool::new(false);
+
+/// Query parameters holding a signature whatever the host.
+const SIGNATURES: [&str; 2] = ["x-amz-signature", "x-goog-signature"];
+
+/// Hosts whose URLs with a bare `Signature` parameter, the older signing,
+/// are presigned.
+const OBJECT_STORES: [&str; 2] = ["amazonaws.com", "googleapis.com"];
+
+/// Treats every URL as presigned.
+pub fn force(presigned: bool) {
+    FORCED.store(presigned, Ordering::Relaxed);
+}
+
+/// Whether `url` is presigned: `--presigned`, or a signature in its query.
+pub fn is_presigned(url: &Url) -> bool {
+    if FORCED.load(Ordering::Relaxed) {
+        return true;
+    }
+    let object_store = url.host_str().is_some_and(|host| {
+        OBJECT_STORES
+            .iter()
+            .any(|store| host == *store || host.ends_with(&format!(".{store}")))
+    });
+    url.query_pairs().any(|(name, _)| {
+        SIGNATURES
+            .iter()
+            .any(|signature| name.eq_ignore_ascii_case(signature))
+            || (object_store && name == "Signature")
+    })
+}
+
+/// When `url` stops working, from `X-Amz-Date` and `X-Amz-Expires` (or
+/// their `X-Goog-` twins), or the older signing's `Expires`.
+pub fn expires_at(url: &Url) -> Option<SystemTime> {
+    let param = |wanted: &str| {
+        url.query_pairs()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.into_owned())
+    };
+    if let Some(expires) = param("Expires") {
+        let seconds = expires.parse().ok()?;
+        return SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds));
+    }
+    let (date, lifetime) = param("X-Amz-Date")
+        .zip(param("X-Amz-Expires"))
+        .or_else(|| param("X-Goog-Date").zip(param("X-Goog-Expires")))?;
+    // 20240501T120000Z
+    let (day, time) = date.strip_suffix('Z')?.split_once('T')?;
+    if day.len() != 8 || time.len() != 6 {
+        return None;
+    }
+    let signed = utils::parse_rfc3339(&format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &day[..4],
+        &day[4..6],
+        &day[6..],
+        &time[..2],
+        &time[2..4],
+        &time[4..]
+    ))?;
+    signed.checked_add(Duration::from_secs(lifetime.parse().ok()?))
+}
+
+/// The error for a 403 answering a presigned `url` with `body`, when it
+/// says the URL expired: S3 and GCS's `ExpiredToken`, or an `AccessDenied`
+/// past the URL's expiry or saying it has expired.
+pub(crate) fn expired(url: &Url, body: &[u8]) -> Option<DownloadError> {
+    if !is_presigned(url) {
+        return None;
+    }
+    let body = String::from_utf8_lossy(body);
+    let code = error_body::element_text(&body, "code")?;
+    let message = error_body::element_text(&body, "message");
+    let expires_at = expires_at(url);
+    let expired = match code.as_str() {
+        "ExpiredToken" => true,
+        "AccessDenied" => {
+            expires_at.is_some_and(|expires_at| expires_at <= SystemTime::now())
+                || message
+                    .as_ref()
+                    .is_some_and(|message| message.to_ascii_lowercase().contains("expired"))
+        }
+        _ => false,
+    };
+    expired.then_some(DownloadError::PresignedExpired {
+        code,
+        message,
+        expired_at: expires_at.map(httpdate::fmt_http_date),
+    })
+}
+
+/// A GET of the first byte of `url`, standing in for the HEAD a presigned
+/// URL usually isn't signed for. The size is in its `Content-Range`.
+pub(crate) fn first_byte(client: &reqwest::Client, url: &Url) -> reqwest::RequestBuilder {
+    client
+        .get(url.clone())
+        .headers(http::range_headers("bytes=0-0"))
+}
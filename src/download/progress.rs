@@ -1,158 +1,473 @@
 use std::sync::{
-    Arc, Mutex,
-    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock, RwLockReadGuard,
+    atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
 };
 
-use colored::Colorize;
-use std::time::Instant;
-
-// Trait to homogenize the progress tracking, so we are not dependent on indicatif.
-pub trait ProgressTracker: Send + Sync + Clone {
-    fn interrupted(&self) -> Arc<AtomicBool>;
-    fn update_progress(&self, bytes: usize);
-    fn render(&self);
-    fn finish(&self, msg: &str);
-    fn abandon(&self, msg: &str);
-}
+use crate::download::progress_handle::{
+    self, ChunkPhase, ChunkSummary, MergeProgress, PreflightStep, ProgressHandle, ProgressReporter,
+    ProgressSnapshot,
+};
+use crate::download::retry_budget::RetryBudget;
+use crate::download::speed::TimeSplit;
+use crate::download::stall::STUCK_CHUNK;
+use crate::download::tls::TlsSession;
+use std::time::{Duration, Instant};
 
+/// How a transfer is going, with no opinion on how that's shown: the total,
+/// a byte counter and a state per range, bytes kept from an earlier run and
+/// whether the user asked to stop. Downloads only ever write to it, and a
+/// [`Renderer`](crate::download::render::Renderer) draws it.
+///
+/// Single-stream downloads have one range; worker mode has one per chunk.
 #[derive(Clone)]
-pub struct DownloadProgress {
-    pub bytes_downloaded: Arc<AtomicUsize>,
-    pub total_bytes: Arc<AtomicU64>,
+pub struct TransferProgress {
+    shared: Arc<Shared>,
     pub interrupted: Arc<AtomicBool>,
+    /// Time spent awaiting the network vs the disk, summed over ranges.
+    pub time_split: TimeSplit,
+    reporter: ProgressReporter,
 }
 
-impl DownloadProgress {
-    pub fn new(interrupted: Arc<AtomicBool>) -> Self {
-        Self {
-            bytes_downloaded: Arc::new(AtomicUsize::new(0)),
-            total_bytes: Arc::new(AtomicU64::new(0)),
-            interrupted,
+struct Shared {
+    start_time: Instant,
+    total: AtomicU64,
+    /// Bytes of the file kept from an earlier run, ahead of the ranges.
+    prefix: AtomicU64,
+    /// Bytes downloaded for nothing, see [`TransferProgress::add_wasted`].
+    wasted: AtomicU64,
+    /// Only ever grown, when worker mode has more chunks than it started
+    /// with.
+    ranges: RwLock<Vec<Range>>,
+    /// Lines for the renderer to print above the progress, oldest first.
+    notes: Mutex<Vec<String>>,
+}
+
+// Range states are stored as one `AtomicU8` per range, so workers never
+// contend with the renderer: the lock around them is only written to add
+// chunks. The worker id lives next to it.
+const PENDING: u8 = 0;
+const DOWNLOADING: u8 = 1;
+const COMPLETED: u8 = 2;
+const FAILED: u8 = 3;
+const RETRYING: u8 = 4;
+
+#[derive(Default)]
+struct Range {
+    bytes: AtomicU64,
+    state: AtomicU8,
+    worker_id: AtomicUsize,
+    /// Milliseconds since `start_time` at which it last received data, or
+    /// started downloading.
+    last_update_ms: AtomicU64,
+    /// The bytes of the file it downloads, first and last; `None` until the
+    /// download says.
+    span: Mutex<Option<(u64, u64)>>,
+}
+
+impl Range {
+    fn state(&self) -> ChunkState {
+        match self.state.load(Ordering::Acquire) {
+            PENDING => ChunkState::Pending,
+            DOWNLOADING => ChunkState::Downloading {
+                worker_id: self.worker_id.load(Ordering::Relaxed),
+            },
+            COMPLETED => ChunkState::Completed,
+            RETRYING => ChunkState::Retrying,
+            _ => ChunkState::Failed,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ChunkState {
     Pending,
-    Downloading { worker_id: usize },
+    Downloading {
+        worker_id: usize,
+    },
     Completed,
     Failed,
+    /// Lost its connection, and waiting to ask for the rest again.
+    Retrying,
 }
 
-#[derive(Clone)]
-pub struct ChunkProgressBar {
-    bar: indicatif::ProgressBar,
-    chunks: Arc<Mutex<Vec<ChunkState>>>,
-    bytes_per_chunk: Vec<Arc<AtomicUsize>>,
-    total_bytes: u64,
-    start_time: Instant,
-    pub interrupted: Arc<AtomicBool>,
+/// A chunk as [`TransferProgress::chunks`] sees it, for drawing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkView {
+    pub state: ChunkState,
+    /// Bytes it has received so far.
+    pub bytes: u64,
+    /// Bytes it's to receive; 0 until its range is known.
+    pub len: u64,
+    /// Downloading, but without data for [`STUCK_CHUNK`] or longer.
+    pub stuck: bool,
 }
 
-impl ChunkProgressBar {
-    pub fn new(num_chunks: usize, total_bytes: u64, interrupted: Arc<AtomicBool>) -> Self {
-        let bar = indicatif::ProgressBar::new_spinner();
-        bar.enable_steady_tick(std::time::Duration::from_millis(100));
-        let chunks = vec![ChunkState::Pending; num_chunks];
-        let bytes_per_chunk = (0..num_chunks)
-            .map(|_| Arc::new(AtomicUsize::new(0)))
-            .collect();
+impl TransferProgress {
+    /// A single-stream transfer: one range, downloading from the start.
+    pub fn new(interrupted: Arc<AtomicBool>) -> Self {
+        let progress = Self::with_ranges(1, interrupted);
+        progress.ranges()[0]
+            .state
+            .store(DOWNLOADING, Ordering::Release);
+        progress
+    }
+
+    /// A worker-mode transfer of `total` bytes in `chunks` ranges, all
+    /// pending.
+    pub fn chunked(chunks: usize, total: u64, interrupted: Arc<AtomicBool>) -> Self {
+        let progress = Self::with_ranges(chunks, interrupted);
+        progress.set_total(total);
+        progress.reporter.set_chunks(
+            ChunkSummary {
+                pending: chunks,
+                ..ChunkSummary::default()
+            },
+            vec![ChunkPhase::Pending; chunks],
+        );
+        progress
+    }
+
+    fn with_ranges(ranges: usize, interrupted: Arc<AtomicBool>) -> Self {
         Self {
-            bar,
-            chunks: Arc::new(Mutex::new(chunks)),
-            bytes_per_chunk,
-            total_bytes,
-            start_time: Instant::now(),
+            shared: Arc::new(Shared {
+                start_time: Instant::now(),
+                total: AtomicU64::new(0),
+                prefix: AtomicU64::new(0),
+                wasted: AtomicU64::new(0),
+                ranges: RwLock::new((0..ranges).map(|_| Range::default()).collect()),
+                notes: Mutex::default(),
+            }),
             interrupted,
+            time_split: TimeSplit::default(),
+            reporter: ProgressReporter::new(),
         }
     }
 
+    /// A watch-based view of this download's progress.
+    pub fn handle(&self) -> ProgressHandle {
+        self.reporter.handle()
+    }
+
+    /// What [`handle`](Self::handle) would show right now.
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.reporter.snapshot()
+    }
+
+    pub(crate) fn finish_with<T>(&self, result: &anyhow::Result<T>) {
+        let interrupted = self.interrupted.load(Ordering::SeqCst);
+        self.reporter.finish_with(result, interrupted);
+    }
+
+    pub(crate) fn reporter(&self) -> &ProgressReporter {
+        &self.reporter
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.shared.total.store(total, Ordering::Relaxed);
+        self.reporter.set_total(total);
+    }
+
+    /// Zero until the size is known.
+    pub fn total(&self) -> u64 {
+        self.shared.total.load(Ordering::Relaxed)
+    }
+
+    /// Counts `bytes` as downloaded for nothing: thrown away after failing
+    /// a check, or about to be downloaded again after a restart.
+    pub(crate) fn add_wasted(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let wasted = self.shared.wasted.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.reporter.set_wasted(wasted);
+    }
+
+    /// Bytes downloaded more than once so far.
+    pub fn wasted(&self) -> u64 {
+        self.shared.wasted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_content_type(&self, content_type: Option<&str>) {
+        self.reporter.set_content_type(content_type);
+    }
+
+    /// Reports how much of `budget` is used in the snapshots.
+    pub fn set_retry_budget(&self, budget: &Arc<RetryBudget>) {
+        self.reporter.set_retry_budget(budget);
+    }
+
+    pub fn set_tls(&self, tls: Option<TlsSession>) {
+        self.reporter.set_tls(tls);
+    }
+
+    /// Records how long a response took to start; the snapshot keeps the
+    /// quickest, see
+    /// [`ProgressSnapshot::ttfb_ms`](crate::download::progress_handle::ProgressSnapshot::ttfb_ms).
+    pub fn set_first_byte(&self, ttfb: Duration) {
+        self.reporter.set_first_byte(ttfb);
+    }
+
+    /// Counts `bytes` already on disk, which the ranges carry on after.
+    pub fn set_prefix(&self, bytes: u64) {
+        self.shared.prefix.store(bytes, Ordering::Relaxed);
+        self.reporter.set_prefix(bytes);
+        self.reporter.set_downloaded(self.downloaded());
+    }
+
+    /// Records that the file now holds `bytes`, counting the prefix; for
+    /// single-stream downloads.
+    pub fn set_downloaded(&self, bytes: usize) {
+        let prefix = self.shared.prefix.load(Ordering::Relaxed);
+        self.update_chunk_bytes(0, (bytes as u64).saturating_sub(prefix) as usize);
+    }
+
+    /// Records that chunk `chunk_id` has received `bytes` so far.
     pub fn update_chunk_bytes(&self, chunk_id: usize, bytes: usize) {
-        if chunk_id < self.bytes_per_chunk.len() {
-            self.bytes_per_chunk[chunk_id].store(bytes, Ordering::Relaxed);
+        {
+            let ranges = self.ranges();
+            let Some(range) = ranges.get(chunk_id) else {
+                return;
+            };
+            range.bytes.store(bytes as u64, Ordering::Relaxed);
+            range
+                .last_update_ms
+                .store(self.elapsed_ms(), Ordering::Relaxed);
         }
+        self.reporter.set_downloaded(self.downloaded());
+    }
+
+    /// Bytes in the file so far, the prefix included.
+    pub fn downloaded(&self) -> u64 {
+        self.shared.prefix.load(Ordering::Relaxed)
+            + self
+                .ranges()
+                .iter()
+                .map(|range| range.bytes.load(Ordering::Relaxed))
+                .sum::<u64>()
+    }
+
+    /// Bytes each range has received, in order.
+    pub fn chunk_bytes(&self) -> Vec<u64> {
+        self.ranges()
+            .iter()
+            .map(|range| range.bytes.load(Ordering::Relaxed))
+            .collect()
     }
 
     pub fn set_chunk_state(&self, chunk_id: usize, state: ChunkState) {
-        if let Ok(mut chunks) = self.chunks.lock() {
-            if chunk_id < chunks.len() {
-                chunks[chunk_id] = state;
-            }
+        {
+            let ranges = self.ranges();
+            let Some(range) = ranges.get(chunk_id) else {
+                return;
+            };
+            let code = match state {
+                ChunkState::Pending => PENDING,
+                ChunkState::Downloading { worker_id } => {
+                    range.worker_id.store(worker_id, Ordering::Relaxed);
+                    range
+                        .last_update_ms
+                        .store(self.elapsed_ms(), Ordering::Relaxed);
+                    DOWNLOADING
+                }
+                ChunkState::Completed => COMPLETED,
+                ChunkState::Failed => FAILED,
+                ChunkState::Retrying => RETRYING,
+            };
+            range.state.store(code, Ordering::Release);
+        }
+        self.report_chunks();
+    }
+
+    /// Records that chunk `chunk_id` downloads the bytes `start` to `end` of
+    /// the file, both included, so it can be drawn as a share of them. Set
+    /// again once it's split.
+    pub fn set_chunk_range(&self, chunk_id: usize, (start, end): (u64, u64)) {
+        if let Some(range) = self.ranges().get(chunk_id) {
+            *range.span.lock().unwrap() = Some((start, end));
         }
     }
 
-    pub fn get_total_downloaded(&self) -> usize {
-        self.bytes_per_chunk
+    /// The range [`set_chunk_range`](Self::set_chunk_range) recorded for
+    /// every chunk, in order.
+    pub fn chunk_ranges(&self) -> Vec<Option<(u64, u64)>> {
+        self.ranges()
             .iter()
-            .map(|bytes| bytes.load(Ordering::Relaxed))
-            .sum()
-    }
-    fn render_chunks(&self) -> String {
-        const PROGRESS_CHAR: &str = "█";
-        const WIP_CHAR: &str = "░";
-        if let Ok(chunks) = self.chunks.lock() {
-            let mut output = String::from("[");
-            for chunk in chunks.iter() {
-                let symbol = match chunk {
-                    ChunkState::Completed => PROGRESS_CHAR.green(),
-                    ChunkState::Downloading { worker_id } => {
-                        // Use different colors for different workers
-                        // FIXME: I'm not happy with this implementation, how would
-                        // this be useful?
-                        match worker_id % 3 {
-                            0 => PROGRESS_CHAR.yellow(),
-                            1 => PROGRESS_CHAR.cyan(),
-                            _ => PROGRESS_CHAR.magenta(),
-                        }
-                    }
-                    // Black? What about a light mode?
-                    ChunkState::Pending => WIP_CHAR.bright_black(),
-                    ChunkState::Failed => PROGRESS_CHAR.red(),
-                };
-                output.push_str(&symbol.to_string());
+            .map(|range| *range.span.lock().unwrap())
+            .collect()
+    }
+
+    /// Every chunk's state, bytes and size, in order.
+    pub fn chunks(&self) -> Vec<ChunkView> {
+        let now = self.elapsed();
+        self.ranges()
+            .iter()
+            .map(|range| {
+                let state = range.state();
+                let idle = now.saturating_sub(Duration::from_millis(
+                    range.last_update_ms.load(Ordering::Relaxed),
+                ));
+                ChunkView {
+                    stuck: matches!(state, ChunkState::Downloading { .. }) && idle >= STUCK_CHUNK,
+                    state,
+                    bytes: range.bytes.load(Ordering::Relaxed),
+                    len: range
+                        .span
+                        .lock()
+                        .unwrap()
+                        .map_or(0, |(start, end)| end + 1 - start),
+                }
+            })
+            .collect()
+    }
+
+    /// Adds pending chunks until there are at least `chunks`, as when a
+    /// download turns out to be split into more than it was expected to.
+    pub(crate) fn add_chunks(&self, chunks: usize) {
+        {
+            let mut ranges = self.shared.ranges.write().unwrap();
+            if ranges.len() >= chunks {
+                return;
             }
+            ranges.resize_with(chunks, Range::default);
+        }
+        self.report_chunks();
+    }
+
+    /// Sets how many chunks there are once the download is split, dropping
+    /// any of those it started with that it didn't need.
+    pub(crate) fn set_chunk_count(&self, chunks: usize) {
+        self.shared
+            .ranges
+            .write()
+            .unwrap()
+            .resize_with(chunks, Range::default);
+        self.report_chunks();
+    }
 
-            output.push(']');
-            output
-        } else {
-            String::from("[?]")
+    /// Adds a pending chunk, for a range split off another: its id.
+    pub(crate) fn add_chunk(&self) -> usize {
+        let chunk_id = self.ranges().len();
+        self.add_chunks(chunk_id + 1);
+        chunk_id
+    }
+
+    /// Hands the reporter the chunks' states, tallied.
+    fn report_chunks(&self) {
+        let states = self.chunk_states();
+        let mut summary = ChunkSummary::default();
+        let mut map = Vec::with_capacity(states.len());
+        for state in states {
+            let phase = match state {
+                ChunkState::Pending => {
+                    summary.pending += 1;
+                    ChunkPhase::Pending
+                }
+                ChunkState::Downloading { .. } => {
+                    summary.downloading += 1;
+                    ChunkPhase::Downloading
+                }
+                ChunkState::Completed => {
+                    summary.completed += 1;
+                    ChunkPhase::Completed
+                }
+                ChunkState::Failed => {
+                    summary.failed += 1;
+                    ChunkPhase::Failed
+                }
+                ChunkState::Retrying => {
+                    summary.retrying += 1;
+                    ChunkPhase::Retrying
+                }
+            };
+            map.push(phase);
         }
+        self.reporter.set_chunks(summary, map);
     }
-}
 
-impl ProgressTracker for ChunkProgressBar {
-    fn update_progress(&self, bytes: usize) {
-        todo!()
+    /// The state of every range, in order.
+    pub fn chunk_states(&self) -> Vec<ChunkState> {
+        self.ranges().iter().map(Range::state).collect()
     }
-    fn interrupted(&self) -> Arc<AtomicBool> {
-        todo!()
+
+    fn ranges(&self) -> RwLockReadGuard<'_, Vec<Range>> {
+        self.shared.ranges.read().unwrap()
     }
 
-    fn render(&self) {
-        let total_downloaded = self.get_total_downloaded();
-        let elapsed = self.start_time.elapsed().as_secs().max(1);
-        let speed = total_downloaded as u64 / elapsed;
+    /// Set while worker mode merges the parts into the file.
+    pub fn merging(&self) -> Option<MergeProgress> {
+        self.reporter.merging()
+    }
 
-        let chunks_viz = self.render_chunks();
+    /// Runs `setup`, part of this download before its first byte, showing
+    /// which step it's at: resolving, connecting, following redirects,
+    /// probing. A timeout in it says which step timed out.
+    pub async fn preflight<T>(
+        &self,
+        setup: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        progress_handle::track_preflight(&self.reporter, setup).await
+    }
 
-        // Build the message
-        let message = format!(
-            "{} Downloaded: {} / {} @ {}/s",
-            chunks_viz,
-            indicatif::HumanBytes(total_downloaded as u64),
-            indicatif::HumanBytes(self.total_bytes),
-            indicatif::HumanBytes(speed),
-        );
+    /// The step before the first byte the download is at, and how long it's
+    /// been at it; `None` once bytes arrive.
+    pub fn preflight_step(&self) -> Option<(PreflightStep, Duration)> {
+        self.reporter.preflight()
+    }
 
-        self.bar.set_message(message);
+    pub fn elapsed(&self) -> Duration {
+        self.shared.start_time.elapsed()
     }
 
-    fn finish(&self, msg: &str) {
-        self.bar.finish_with_message(msg.to_string());
+    fn elapsed_ms(&self) -> u64 {
+        self.elapsed().as_millis() as u64
+    }
+
+    /// Average speed since the start, in bytes/s, counting the prefix.
+    pub fn speed(&self) -> u64 {
+        self.downloaded() / self.elapsed().as_secs().max(1)
+    }
+
+    /// How long the rest should take at this run's speed so far, not
+    /// counting the prefix; `None` until the total is known and the run is
+    /// a second in.
+    pub fn eta(&self) -> Option<Duration> {
+        let (total, downloaded, elapsed) = (self.total(), self.downloaded(), self.elapsed());
+        let fetched = downloaded.saturating_sub(self.shared.prefix.load(Ordering::Relaxed));
+        if total == 0 || fetched == 0 || elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let left = total.saturating_sub(downloaded);
+        Some(elapsed.mul_f64(left as f64 / fetched as f64))
+    }
+
+    /// Longest time any downloading range has gone without data.
+    pub fn idle(&self) -> Duration {
+        let now = self.elapsed();
+        self.ranges()
+            .iter()
+            .filter(|range| range.state.load(Ordering::Relaxed) == DOWNLOADING)
+            .map(|range| {
+                now.saturating_sub(Duration::from_millis(
+                    range.last_update_ms.load(Ordering::Relaxed),
+                ))
+            })
+            .max()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Leaves a line for the renderer to print above the progress.
+    pub fn println(&self, message: &str) {
+        if let Ok(mut notes) = self.shared.notes.lock() {
+            notes.push(message.to_string());
+        }
     }
 
-    fn abandon(&self, msg: &str) {
-        self.bar.abandon_with_message(msg.to_string());
+    /// The lines left by [`println`](Self::println) since the last call.
+    pub fn take_notes(&self) -> Vec<String> {
+        self.shared
+            .notes
+            .lock()
+            .map(|mut notes| std::mem::take(&mut *notes))
+            .unwrap_or_default()
     }
 }
@@ -0,0 +1,625 @@
+use crate::download::chunk_url::ChunkRedirects;
+use crate::download::diagnostics::{self, Warning};
+use crate::download::etag::EtagMismatch;
+use crate::download::part_check::PartCheck;
+use crate::download::parts::PartLayout;
+use crate::download::retry_budget::{RetryBudget, RetryUsage};
+use crate::download::segment_tuning::SegmentTuning;
+use crate::download::speed::TtfbSpread;
+use crate::download::tls::TlsSession;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+tokio::task_local! {
+    /// The download the current task is setting up, for the client's
+    /// resolver, connector and redirect policy to say what they're doing.
+    static PREFLIGHT: ProgressReporter;
+}
+
+/// Where a transfer is in its lifecycle.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    #[default]
+    Running,
+    Completed,
+    Failed(String),
+    /// Cancelled by the user, or the download future was dropped.
+    Interrupted,
+}
+
+impl TransferState {
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, TransferState::Running)
+    }
+}
+
+/// How many chunks are in each state, in worker mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChunkSummary {
+    pub pending: usize,
+    pub downloading: usize,
+    pub completed: usize,
+    pub failed: usize,
+    #[serde(default)]
+    pub retrying: usize,
+}
+
+/// Where one chunk of worker mode is, for the chunk map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkPhase {
+    Pending,
+    Downloading,
+    Completed,
+    Failed,
+    Retrying,
+}
+
+/// A step before a download's first byte, so a slow server doesn't look
+/// like a hung download.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum PreflightStep {
+    ResolvingDns {
+        host: String,
+    },
+    /// Opening a connection, TLS included.
+    Connecting,
+    /// The `redirect`th redirect, of at most `max`.
+    FollowingRedirect {
+        redirect: usize,
+        max: usize,
+    },
+    /// Asking for the size, and whether ranges are served.
+    ProbingRanges,
+    /// The request is out, the answer hasn't started.
+    AwaitingResponse,
+    /// `--wait-for-network`: there's no route off the machine.
+    WaitingForNetwork,
+    /// `--wait-for-network`: the network is up, but `host` can't be
+    /// resolved or reached.
+    WaitingForHost {
+        host: String,
+    },
+}
+
+impl fmt::Display for PreflightStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightStep::ResolvingDns { host } => write!(f, "resolving {host}"),
+            PreflightStep::Connecting => write!(f, "connecting"),
+            PreflightStep::FollowingRedirect { redirect, max } => {
+                write!(f, "following redirect {redirect} of at most {max}")
+            }
+            PreflightStep::ProbingRanges => write!(f, "probing the size and ranges"),
+            PreflightStep::AwaitingResponse => write!(f, "waiting for the server"),
+            PreflightStep::WaitingForNetwork => write!(f, "waiting for the network"),
+            PreflightStep::WaitingForHost { host } => write!(f, "waiting for {host}"),
+        }
+    }
+}
+
+/// The step a download is at before its first byte.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Preflight {
+    #[serde(flatten)]
+    pub step: PreflightStep,
+    /// When it started, in milliseconds since the download did.
+    pub started_ms: u64,
+}
+
+/// A step before the first byte, once it's over.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PreflightTiming {
+    #[serde(flatten)]
+    pub step: PreflightStep,
+    pub ms: u64,
+}
+
+/// How far worker mode is through merging the parts, once they're all in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MergeProgress {
+    /// The part being copied, counting from 1.
+    pub part: usize,
+    pub parts: usize,
+    pub copied: u64,
+    pub total: u64,
+}
+
+/// A point-in-time view of a transfer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ProgressSnapshot {
+    pub downloaded: u64,
+    /// Of `downloaded`, how much was already on disk from an earlier run
+    /// this one resumed.
+    pub prefix: u64,
+    /// Zero until the size is known.
+    pub total: u64,
+    /// Average speed since the start, in bytes/s.
+    pub speed: u64,
+    pub state: TransferState,
+    /// Empty outside of worker mode.
+    pub chunks: ChunkSummary,
+    /// Every chunk's phase, in order; empty outside of worker mode.
+    pub chunk_map: Vec<ChunkPhase>,
+    /// What the server said it's sending, in single-stream mode.
+    pub content_type: Option<String>,
+    /// Milliseconds from sending the request to the first body byte, once
+    /// it arrived; the quickest chunk's in worker mode.
+    pub ttfb_ms: Option<u64>,
+    /// How time to first byte varied across the chunks, once worker mode
+    /// has them all.
+    pub ttfb_spread: Option<TtfbSpread>,
+    /// What the download is doing until the first byte arrives.
+    pub preflight: Option<Preflight>,
+    /// The steps before the first byte that are over, in order.
+    pub preflight_steps: Vec<PreflightTiming>,
+    /// Why the Content-Type contradicts the file's extension, if it does.
+    pub content_type_mismatch: Option<String>,
+    /// Set while worker mode merges the parts into the file.
+    pub merging: Option<MergeProgress>,
+    /// Whether the server said the file is empty: it answered 204 or 205,
+    /// or sent the whole file with a `Content-Length` of 0.
+    pub no_content: bool,
+    /// Hex SHA-256 of the finished file, when it was worked out on the way
+    /// (worker mode hashes the parts as it merges them).
+    pub sha256: Option<String>,
+    /// Bytes downloaded for nothing: thrown away after failing a check, or
+    /// downloaded again after a restart.
+    pub wasted: u64,
+    /// `wasted` as a percentage of `total`; zero until the size is known.
+    pub wasted_percent: f64,
+    /// How much of the run's `--retry-budget` is used, across every
+    /// download of it.
+    pub retries: RetryUsage,
+    /// How many times a chunk under `--chunk-min-speed` was re-assigned to
+    /// a new connection.
+    pub reassignments: u64,
+    /// Bytes the re-assigned chunks received on their new connections.
+    pub reassigned_bytes: u64,
+    /// How `--segment-size auto` sized the segments, once worker mode is
+    /// done with them.
+    #[serde(default)]
+    pub segment_tuning: Option<SegmentTuning>,
+    /// The chunks whose requests were redirected, and how often, in chunk
+    /// order.
+    pub chunk_redirects: Vec<ChunkRedirects>,
+    /// The ETag the probe got in worker mode, which every chunk's response
+    /// is compared with.
+    pub etag: Option<String>,
+    /// The chunks whose response came with another ETag.
+    pub etag_mismatches: Vec<EtagMismatch>,
+    /// How worker mode checked the parts it kept from an earlier run, and
+    /// any that failed a check before the merge.
+    pub part_checks: Vec<PartCheck>,
+    /// What the run warned about so far, across every download of it.
+    pub warnings: Vec<Warning>,
+    /// The part files of a worker-mode download, once it's split. Kept in
+    /// the download's manifest rather than the progress lines.
+    #[serde(skip)]
+    pub parts: Option<PartLayout>,
+    /// What TLS the download's connections negotiated, once the first
+    /// response is in. Kept for the summary and the `completed` event
+    /// rather than the progress lines.
+    #[serde(skip)]
+    pub tls: Option<TlsSession>,
+}
+
+/// Cheaply cloneable view of a transfer's progress, for UIs that would
+/// rather await changes than poll atomics.
+///
+/// It outlives the download: once the transfer is over, the last snapshot
+/// stays available with a terminal [`TransferState`].
+#[derive(Clone, Debug)]
+pub struct ProgressHandle {
+    receiver: watch::Receiver<ProgressSnapshot>,
+    budget: Budget,
+}
+
+/// The retry budget the download spends, once it says which.
+type Budget = Arc<OnceLock<Arc<RetryBudget>>>;
+
+fn retries(budget: &Budget) -> RetryUsage {
+    budget
+        .get()
+        .map(|budget| budget.usage())
+        .unwrap_or_default()
+}
+
+impl ProgressHandle {
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            retries: retries(&self.budget),
+            warnings: diagnostics::warnings(),
+            ..self.receiver.borrow().clone()
+        }
+    }
+
+    /// Waits for the next update. Returns `false` once no more updates can
+    /// come, i.e. the transfer is over and its reporter is gone.
+    pub async fn changed(&mut self) -> bool {
+        self.receiver.changed().await.is_ok()
+    }
+
+    /// Waits for the transfer to reach a terminal state and returns the
+    /// final snapshot.
+    pub async fn finished(&mut self) -> ProgressSnapshot {
+        // The reporter always publishes a terminal state before it goes, so
+        // the latest value is final even if the wait errors out.
+        let _ = self.receiver.wait_for(|s| s.state.is_terminal()).await;
+        self.snapshot()
+    }
+}
+
+/// The writing side of a [`ProgressHandle`], shared by the download tasks.
+/// When the last clone goes away without a terminal state, the transfer is
+/// marked [`TransferState::Interrupted`].
+#[derive(Clone, Debug)]
+pub(crate) struct ProgressReporter {
+    inner: Arc<Reporter>,
+}
+
+#[derive(Debug)]
+struct Reporter {
+    sender: watch::Sender<ProgressSnapshot>,
+    start_time: Instant,
+    budget: Budget,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = watch::channel(ProgressSnapshot::default());
+        Self {
+            inner: Arc::new(Reporter {
+                sender,
+                start_time: Instant::now(),
+                budget: Budget::default(),
+            }),
+        }
+    }
+
+    pub(crate) fn handle(&self) -> ProgressHandle {
+        ProgressHandle {
+            receiver: self.inner.sender.subscribe(),
+            budget: self.inner.budget.clone(),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            retries: retries(&self.inner.budget),
+            warnings: diagnostics::warnings(),
+            ..self.inner.sender.borrow().clone()
+        }
+    }
+
+    pub(crate) fn set_downloaded(&self, downloaded: u64) {
+        let elapsed = self.inner.start_time.elapsed().as_secs_f64().max(0.001);
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.downloaded != downloaded;
+            snapshot.downloaded = downloaded;
+            snapshot.speed = (downloaded as f64 / elapsed) as u64;
+            changed
+        });
+    }
+
+    pub(crate) fn set_prefix(&self, prefix: u64) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.prefix != prefix;
+            snapshot.prefix = prefix;
+            changed
+        });
+    }
+
+    pub(crate) fn set_no_content(&self) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = !snapshot.no_content;
+            snapshot.no_content = true;
+            changed
+        });
+    }
+
+    pub(crate) fn set_total(&self, total: u64) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.total != total;
+            snapshot.total = total;
+            snapshot.wasted_percent = wasted_percent(snapshot.wasted, total);
+            changed
+        });
+    }
+
+    pub(crate) fn set_wasted(&self, wasted: u64) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.wasted != wasted;
+            snapshot.wasted = wasted;
+            snapshot.wasted_percent = wasted_percent(wasted, snapshot.total);
+            changed
+        });
+    }
+
+    pub(crate) fn add_reassignment(&self) {
+        self.inner
+            .sender
+            .send_modify(|snapshot| snapshot.reassignments += 1);
+    }
+
+    pub(crate) fn add_reassigned_bytes(&self, bytes: u64) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            snapshot.reassigned_bytes += bytes;
+            bytes > 0
+        });
+    }
+
+    pub(crate) fn set_segment_tuning(&self, tuning: SegmentTuning) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.segment_tuning = Some(tuning);
+        });
+    }
+
+    /// Counts a redirect chunk `chunk` followed, or with `expired`, one
+    /// it went back to and found refused.
+    pub(crate) fn add_redirect(&self, chunk: usize, expired: bool) {
+        self.inner.sender.send_modify(|snapshot| {
+            let redirects = &mut snapshot.chunk_redirects;
+            // In chunk order, whichever got redirected first.
+            let index = match redirects.binary_search_by_key(&chunk, |entry| entry.chunk) {
+                Ok(index) => index,
+                Err(index) => {
+                    let entry = ChunkRedirects {
+                        chunk,
+                        ..ChunkRedirects::default()
+                    };
+                    redirects.insert(index, entry);
+                    index
+                }
+            };
+            match expired {
+                true => redirects[index].expired += 1,
+                false => redirects[index].followed += 1,
+            }
+        });
+    }
+
+    pub(crate) fn set_content_type(&self, content_type: Option<&str>) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.content_type.as_deref() != content_type;
+            snapshot.content_type = content_type.map(str::to_string);
+            changed
+        });
+    }
+
+    pub(crate) fn set_etag(&self, etag: Option<&str>) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.etag.as_deref() != etag;
+            snapshot.etag = etag.map(str::to_string);
+            changed
+        });
+    }
+
+    /// The budget the snapshots report; the first one set stays.
+    pub(crate) fn set_retry_budget(&self, budget: &Arc<RetryBudget>) {
+        let _ = self.inner.budget.set(budget.clone());
+    }
+
+    pub(crate) fn set_tls(&self, tls: Option<TlsSession>) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.tls != tls;
+            snapshot.tls = tls;
+            changed
+        });
+    }
+
+    pub(crate) fn add_etag_mismatch(&self, mismatch: EtagMismatch) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.etag_mismatches.push(mismatch);
+        });
+    }
+
+    pub(crate) fn add_part_check(&self, check: PartCheck) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.part_checks.push(check);
+        });
+    }
+
+    pub(crate) fn set_content_type_mismatch(&self, mismatch: String) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.content_type_mismatch = Some(mismatch);
+        });
+    }
+
+    pub(crate) fn set_merging(&self, merging: Option<MergeProgress>) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.merging != merging;
+            snapshot.merging = merging;
+            changed
+        });
+    }
+
+    pub(crate) fn merging(&self) -> Option<MergeProgress> {
+        self.inner.sender.borrow().merging
+    }
+
+    pub(crate) fn set_parts(&self, parts: PartLayout) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.parts = Some(parts);
+        });
+    }
+
+    pub(crate) fn set_sha256(&self, sha256: [u8; 32]) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.sha256 = Some(hex::encode(sha256));
+        });
+    }
+
+    pub(crate) fn set_first_byte(&self, ttfb: Duration) {
+        let ttfb = ttfb.as_millis() as u64;
+        let now = self.elapsed_ms();
+        self.inner.sender.send_if_modified(|snapshot| {
+            let quicker = snapshot.ttfb_ms.is_none_or(|known| ttfb < known);
+            if quicker {
+                snapshot.ttfb_ms = Some(ttfb);
+            }
+            end_preflight(snapshot, now) || quicker
+        });
+    }
+
+    pub(crate) fn set_ttfb_spread(&self, spread: TtfbSpread) {
+        self.inner.sender.send_modify(|snapshot| {
+            snapshot.ttfb_spread = Some(spread);
+        });
+    }
+
+    /// Moves the download on to `step`, until its first byte arrives.
+    pub(crate) fn set_preflight(&self, step: PreflightStep) {
+        let now = self.elapsed_ms();
+        self.inner.sender.send_if_modified(|snapshot| {
+            let over = snapshot.ttfb_ms.is_some() || snapshot.state.is_terminal();
+            if over
+                || snapshot
+                    .preflight
+                    .as_ref()
+                    .is_some_and(|at| at.step == step)
+            {
+                return false;
+            }
+            close_step(snapshot, now);
+            snapshot.preflight = Some(Preflight {
+                step,
+                started_ms: now,
+            });
+            true
+        });
+    }
+
+    /// Names the step before the first byte that `error` timed out at, if
+    /// it is a timeout there.
+    pub(crate) fn name_timeout(&self, error: anyhow::Error) -> anyhow::Error {
+        let timed_out = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_timeout)
+                || cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|error| error.kind() == io::ErrorKind::TimedOut)
+        });
+        let preflight = self.inner.sender.borrow().preflight.clone();
+        match preflight {
+            Some(preflight) if timed_out => error.context(format!("Timed out {}", preflight.step)),
+            _ => error,
+        }
+    }
+
+    /// The step before the first byte the download is at, and how long
+    /// it's been at it.
+    pub(crate) fn preflight(&self) -> Option<(PreflightStep, Duration)> {
+        let preflight = self.inner.sender.borrow().preflight.clone()?;
+        let elapsed = self.elapsed_ms().saturating_sub(preflight.started_ms);
+        Some((preflight.step, Duration::from_millis(elapsed)))
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.inner.start_time.elapsed().as_millis() as u64
+    }
+
+    pub(crate) fn set_chunks(&self, chunks: ChunkSummary, map: Vec<ChunkPhase>) {
+        self.inner.sender.send_if_modified(|snapshot| {
+            let changed = snapshot.chunks != chunks || snapshot.chunk_map != map;
+            snapshot.chunks = chunks;
+            snapshot.chunk_map = map;
+            changed
+        });
+    }
+
+    /// Records how the download ended. Errors count as an interruption when
+    /// the user asked to stop.
+    pub(crate) fn finish_with<T>(&self, result: &anyhow::Result<T>, interrupted: bool) {
+        self.finish(match result {
+            Ok(_) => TransferState::Completed,
+            Err(_) if interrupted => TransferState::Interrupted,
+            Err(error) => TransferState::Failed(format!("{error:#}")),
+        });
+    }
+
+    /// Records the final state. Only the first terminal state sticks.
+    pub(crate) fn finish(&self, state: TransferState) {
+        let now = self.elapsed_ms();
+        self.inner.sender.send_if_modified(|snapshot| {
+            if snapshot.state.is_terminal() {
+                return false;
+            }
+            end_preflight(snapshot, now);
+            snapshot.state = state;
+            true
+        });
+    }
+}
+
+/// Runs `download` with the steps before its first byte reported to
+/// `reporter`, naming the step a timeout cut it short at.
+pub(crate) async fn track_preflight<T>(
+    reporter: &ProgressReporter,
+    download: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    PREFLIGHT
+        .scope(reporter.clone(), download)
+        .await
+        .map_err(|error| reporter.name_timeout(error))
+}
+
+/// Reports `step` to the download the current task is setting up, if any.
+pub(crate) fn report_preflight(step: PreflightStep) {
+    let _ = PREFLIGHT.try_with(|reporter| reporter.set_preflight(step));
+}
+
+/// Ends the steps before the first byte, logging how long each took.
+/// Returns whether there were any.
+fn end_preflight(snapshot: &mut ProgressSnapshot, now: u64) -> bool {
+    if !close_step(snapshot, now) {
+        return false;
+    }
+    let steps: Vec<_> = snapshot
+        .preflight_steps
+        .iter()
+        .map(|timing| format!("{} {}ms", timing.step, timing.ms))
+        .collect();
+    tracing::info!("Before the first byte: {}", steps.join(", "));
+    true
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        self.sender.send_if_modified(|snapshot| {
+            if snapshot.state.is_terminal() {
+                return false;
+            }
+            snapshot.state = TransferState::Interrupted;
+            true
+        });
+    }
+}
+
+fn wasted_percent(wasted: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        total => wasted as f64 * 100.0 / total as f64,
+    }
+}
+
+/// Moves the step the download is at to the ones that are over, returning
+/// whether there was one.
+fn close_step(snapshot: &mut ProgressSnapshot, now: u64) -> bool {
+    let Some(done) = snapshot.preflight.take() else {
+        return false;
+    };
+    snapshot.preflight_steps.push(PreflightTiming {
+        step: done.step,
+        ms: now.saturating_sub(done.started_ms),
+    });
+    true
+}
@@ -0,0 +1,96 @@
+use crate::download::error::DownloadError;
+use crate::download::speed::Size;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How many directories deep [`dir_usage`] looks, so a shared download area
+/// with a deep tree under it doesn't take long to size up.
+pub const MAX_DEPTH: usize = 8;
+
+/// Bytes taken by the files in `dir` and the directories under it, up to
+/// [`MAX_DEPTH`] levels down. Symlinks aren't followed, so a file linked
+/// from elsewhere in the tree isn't counted twice; neither is a file with
+/// several hard links. Subdirectories that can't be read are left out.
+pub fn dir_usage(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    let mut seen = HashSet::new();
+    let mut pending = vec![(dir.to_path_buf(), 0)];
+    while let Some((path, depth)) = pending.pop() {
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(error) if depth == 0 => return Err(error),
+            Err(error) => {
+                tracing::debug!("Not counting '{}': {error}", path.display());
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() && depth < MAX_DEPTH {
+                pending.push((entry.path(), depth + 1));
+            } else if metadata.is_file() && first_link(&metadata, &mut seen) {
+                total += metadata.len();
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Whether this is the first of a file's hard links to be counted.
+#[cfg(unix)]
+fn first_link(metadata: &fs::Metadata, seen: &mut HashSet<(u64, u64)>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() < 2 || seen.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn first_link(_metadata: &fs::Metadata, _seen: &mut HashSet<(u64, u64)>) -> bool {
+    true
+}
+
+/// `--dir-quota` and `--dir-quota-hard`: how big the target directory may
+/// grow, warning past the soft one and refusing past the hard one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirQuota {
+    pub soft: Option<u64>,
+    pub hard: Option<u64>,
+}
+
+impl DirQuota {
+    pub fn is_set(&self) -> bool {
+        self.soft.is_some() || self.hard.is_some()
+    }
+
+    /// Checks that `dir`, holding `usage` bytes, has room for `incoming`
+    /// more. Returns a warning past the soft quota.
+    pub fn check(
+        &self,
+        dir: &Path,
+        usage: u64,
+        incoming: u64,
+    ) -> Result<Option<String>, DownloadError> {
+        let after = usage + incoming;
+        if let Some(quota) = self.hard.filter(|quota| after > *quota) {
+            return Err(DownloadError::OverQuota {
+                dir: dir.to_path_buf(),
+                usage,
+                incoming,
+                quota,
+            });
+        }
+        Ok(self.soft.filter(|quota| after > *quota).map(|quota| {
+            format!(
+                "'{}' holds {} and will hold {} after this download, over its quota of {}",
+                dir.display(),
+                Size(usage),
+                Size(after),
+                Size(quota)
+            )
+        }))
+    }
+}
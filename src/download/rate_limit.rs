@@ -0,0 +1,235 @@
+//! Rate limits APIs announce with every response: GitHub's
+//! `X-RateLimit-Remaining` and `X-RateLimit-Reset`, the IETF draft's
+//! `RateLimit-Remaining` and `RateLimit-Reset`, or its `RateLimit` on its
+//! own. The last a host sent is kept, and once fewer than [`LOW`] requests
+//! are left, requests to the host wait for the limit to reset, for up to
+//! `--max-rate-limit-wait`, rather than run into 403s halfway through a
+//! batch of release assets.
+//!
+//! A batch runs each download as a process of its own, whose first request
+//! finds out where the limit is; [`LOW`] leaves room for it.
+
+use crate::download::host_health;
+use crate::download::pacing;
+use crate::download::progress::TransferProgress;
+use reqwest::StatusCode;
+use reqwest::header::{self, HeaderMap};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Requests to a host wait for its limit to reset once fewer than this many
+/// are left: enough for the ones a download makes before its own quota is
+/// known, a few at a time.
+pub const LOW: u64 = 5;
+
+/// `--max-rate-limit-wait` unless given.
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// A reset this large is a Unix time rather than the seconds until it, as
+/// GitHub sends it.
+const EPOCH_FROM: f64 = 1_000_000_000.0;
+
+/// The longest wait in milliseconds, for [`set_max_wait`].
+static MAX_WAIT: AtomicU64 = AtomicU64::new(DEFAULT_MAX_WAIT.as_millis() as u64);
+
+/// The last quota of each host, by [`host_health::host_key`].
+static HOSTS: Mutex<BTreeMap<String, Host>> = Mutex::new(BTreeMap::new());
+
+struct Host {
+    quota: Quota,
+    /// Until when its requests wait, once one found the quota low.
+    until: Option<SystemTime>,
+}
+
+/// How many requests a server says are left, and until when.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    /// When the limit resets; `None` if the server doesn't say.
+    pub reset: Option<SystemTime>,
+}
+
+impl Quota {
+    /// The quota in `headers`, received at `now`; `None` without a
+    /// remaining count. A 403 or 429's `Retry-After` stands in for a reset
+    /// it doesn't give.
+    pub fn parse(headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        let combined = headers
+            .get("ratelimit")
+            .and_then(|value| value.to_str().ok())
+            .map(combined)
+            .unwrap_or_default();
+        let field = |names: [&str; 2], key: &str| {
+            names
+                .iter()
+                .find_map(|name| number(headers.get(*name)?.to_str().ok()?))
+                .or_else(|| combined.get(key).copied())
+        };
+        let remaining = field(
+            ["x-ratelimit-remaining", "ratelimit-remaining"],
+            "remaining",
+        )?;
+        let limit = field(["x-ratelimit-limit", "ratelimit-limit"], "limit");
+        let reset = field(["x-ratelimit-reset", "ratelimit-reset"], "reset")
+            .map(|reset| match reset >= EPOCH_FROM {
+                true => UNIX_EPOCH + Duration::from_secs_f64(reset),
+                false => now + Duration::from_secs_f64(reset),
+            })
+            .or_else(|| {
+                let retry_after = number(headers.get(header::RETRY_AFTER)?.to_str().ok()?)?;
+                Some(now + Duration::from_secs_f64(retry_after))
+            });
+        Some(Self {
+            limit: limit.map(|limit| limit as u64),
+            remaining: remaining as u64,
+            reset,
+        })
+    }
+
+    /// How long a request at `now` waits: until the reset, but no longer
+    /// than `max`, once fewer than [`LOW`] are left. `None` when there's no
+    /// need, or no saying how long.
+    pub fn pause(&self, now: SystemTime, max: Duration) -> Option<Duration> {
+        if self.remaining >= LOW || max.is_zero() {
+            return None;
+        }
+        let left = self.reset?.duration_since(now).ok()?;
+        Some(left.min(max)).filter(|wait| !wait.is_zero())
+    }
+
+    /// Whether it's spent, as a rate-limited 403 says.
+    pub fn exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Whole seconds until the reset after `now`, if it's still to come.
+    pub fn resets_in(&self, now: SystemTime) -> Option<u64> {
+        let left = self.reset?.duration_since(now).ok()?;
+        Some(left.as_secs_f64().ceil() as u64)
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.remaining)?;
+        if let Some(limit) = self.limit {
+            write!(f, " of {limit}")?;
+        }
+        f.write_str(" requests left")?;
+        match self.resets_in(SystemTime::now()) {
+            Some(seconds) => write!(f, ", resetting in {seconds}s"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The `key=value` items of a `RateLimit` header, as in
+/// `limit=100, remaining=50, reset=30`, or `"default";r=50;t=30` in later
+/// drafts.
+fn combined(value: &str) -> BTreeMap<&'static str, f64> {
+    value
+        .split([',', ';'])
+        .filter_map(|item| item.split_once('='))
+        .filter_map(|(key, value)| {
+            let key = match key.trim().to_ascii_lowercase().as_str() {
+                "limit" | "q" => "limit",
+                "remaining" | "r" => "remaining",
+                "reset" | "t" => "reset",
+                _ => return None,
+            };
+            Some((key, number(value)?))
+        })
+        .collect()
+}
+
+/// The number a header's value starts with, before any `;w=60` policy.
+fn number(value: &str) -> Option<f64> {
+    let number: f64 = value.split([',', ';']).next()?.trim().parse().ok()?;
+    (number.is_finite() && number >= 0.0).then_some(number)
+}
+
+/// Whether a response with `status` and `headers` refuses a request for
+/// the rate limit: any 429, or a 403 with no requests left or a
+/// `Retry-After`.
+pub fn is_rate_limited(status: StatusCode, headers: &HeaderMap) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::FORBIDDEN => {
+            headers.contains_key(header::RETRY_AFTER)
+                || Quota::parse(headers, SystemTime::now()).is_some_and(|quota| quota.exhausted())
+        }
+        _ => false,
+    }
+}
+
+/// Sets `--max-rate-limit-wait`; zero never waits.
+pub fn set_max_wait(wait: Duration) {
+    MAX_WAIT.store(wait.as_millis() as u64, Ordering::Relaxed);
+}
+
+fn max_wait() -> Duration {
+    Duration::from_millis(MAX_WAIT.load(Ordering::Relaxed))
+}
+
+/// Keeps the quota a response from `url` carries, if any, for the requests
+/// after it. Shown with `-v`.
+pub(crate) fn observe(url: &Url, headers: &HeaderMap) {
+    let Some(quota) = Quota::parse(headers, SystemTime::now()) else {
+        return;
+    };
+    let host = host_health::host_key(url);
+    tracing::info!("Rate limit of {host}: {quota}");
+    HOSTS
+        .lock()
+        .unwrap()
+        .insert(host, Host { quota, until: None });
+}
+
+/// How long a request to `url` waits for its host's limit, and the note
+/// saying so for the first request to wait.
+fn pause(url: &Url) -> Option<(Duration, Option<String>)> {
+    let now = SystemTime::now();
+    let key = host_health::host_key(url);
+    let mut hosts = HOSTS.lock().unwrap();
+    let host = hosts.get_mut(&key)?;
+    let wait = host.quota.pause(now, max_wait())?;
+    let note = host.until.is_none().then(|| {
+        format!(
+            "{key} has {}, waiting {}s before the next request (--max-rate-limit-wait)",
+            host.quota,
+            wait.as_secs_f64().ceil() as u64
+        )
+    });
+    let until = *host.until.get_or_insert(now + wait);
+    let wait = until.duration_since(now).ok()?;
+    Some((wait, note))
+}
+
+/// Waits before a request to `url` while its host has fewer than [`LOW`]
+/// requests left, noting the wait in `progress`. False if the download was
+/// interrupted meanwhile.
+pub async fn before_request(url: &Url, progress: &TransferProgress) -> bool {
+    let Some((wait, note)) = pause(url) else {
+        return true;
+    };
+    if let Some(note) = note {
+        progress.println(&note);
+    }
+    pacing::wait(wait, &progress.interrupted).await
+}
+
+/// [`before_request`] for the blocking client.
+pub fn before_request_blocking(url: &Url, progress: &TransferProgress) -> bool {
+    let Some((wait, note)) = pause(url) else {
+        return true;
+    };
+    if let Some(note) = note {
+        progress.println(&note);
+    }
+    pacing::wait_blocking(wait, &progress.interrupted)
+}
@@ -0,0 +1,301 @@
+//! `github://` and `gitlab://` URLs, which stand for an asset of a
+//! project's latest release: `github://owner/repo/tool-*-linux-x86_64.tar.gz`
+//! is whichever asset of `owner/repo`'s latest release matches the pattern.
+//! The releases API says which that is (behind the `releases` feature), and
+//! its HTTPS URL is downloaded as any other.
+
+use std::fmt;
+use url::Url;
+
+#[cfg(feature = "releases")]
+use crate::download::auth_token::AuthToken;
+#[cfg(feature = "releases")]
+use crate::download::http;
+#[cfg(feature = "releases")]
+use crate::download::retry_budget::RetryBudget;
+#[cfg(feature = "releases")]
+use anyhow::{Context, bail};
+#[cfg(feature = "releases")]
+use reqwest::{StatusCode, header};
+#[cfg(feature = "releases")]
+use serde::Deserialize;
+
+/// Where the API is unless `--releases-api` says otherwise.
+pub const GITHUB_API: &str = "https://api.github.com";
+pub const GITLAB_API: &str = "https://gitlab.com/api/v4";
+
+/// The forges whose releases can be resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+/// An asset of a project's latest release, by a pattern of its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Release {
+    pub forge: Forge,
+    /// `owner/repo` on GitHub, `group/subgroup/project` on GitLab.
+    pub project: String,
+    /// A file name where `*` is any run of characters and `?` any one.
+    pub pattern: String,
+}
+
+/// The asset a [`Release`] resolved to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Asset {
+    /// The release's tag.
+    pub tag: String,
+    pub name: String,
+    pub url: Url,
+}
+
+impl Forge {
+    pub fn scheme(self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::GitLab => "gitlab",
+        }
+    }
+
+    /// The root of the forge's own releases API.
+    pub fn api(self) -> &'static str {
+        match self {
+            Forge::GitHub => GITHUB_API,
+            Forge::GitLab => GITLAB_API,
+        }
+    }
+}
+
+impl fmt::Display for Forge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Forge::GitHub => "GitHub",
+            Forge::GitLab => "GitLab",
+        })
+    }
+}
+
+impl Release {
+    /// Whether `url` is a `github://` or `gitlab://` URL, which only
+    /// [`Release::parse`] makes sense of.
+    pub fn is_release_url(url: &Url) -> bool {
+        [Forge::GitHub, Forge::GitLab]
+            .iter()
+            .any(|forge| url.scheme() == forge.scheme())
+    }
+
+    /// `github://owner/repo/PATTERN` or `gitlab://group/…/project/PATTERN`.
+    pub fn parse(url: &Url) -> anyhow::Result<Self> {
+        let forge = match url.scheme() {
+            "github" => Forge::GitHub,
+            "gitlab" => Forge::GitLab,
+            scheme => anyhow::bail!("'{scheme}://' is not a release URL"),
+        };
+        let mut parts: Vec<String> = url.host_str().into_iter().map(str::to_string).collect();
+        parts.extend(
+            url.path_segments()
+                .into_iter()
+                .flatten()
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| {
+                    percent_encoding::percent_decode_str(segment)
+                        .decode_utf8_lossy()
+                        .into_owned()
+                }),
+        );
+        let enough = match forge {
+            Forge::GitHub => parts.len() == 3,
+            Forge::GitLab => parts.len() >= 3,
+        };
+        if !enough {
+            anyhow::bail!(
+                "'{url}' should be {}://{}/ASSET-PATTERN",
+                forge.scheme(),
+                match forge {
+                    Forge::GitHub => "OWNER/REPO",
+                    Forge::GitLab => "GROUP/PROJECT",
+                }
+            );
+        }
+        let pattern = parts.pop().expect("checked above");
+        Ok(Self {
+            forge,
+            project: parts.join("/"),
+            pattern,
+        })
+    }
+
+    /// The URL standing for this release asset, as [`parse`](Self::parse)
+    /// reads it.
+    pub fn to_url(&self) -> Url {
+        let mut url = Url::parse(&format!("{}://{}", self.forge.scheme(), self.project))
+            .expect("a project is a valid host and path");
+        url.path_segments_mut()
+            .expect("the URL has a host")
+            .pop_if_empty()
+            .push(&self.pattern);
+        url
+    }
+
+    /// Whether the asset called `name` is the one wanted.
+    pub fn matches(&self, name: &str) -> bool {
+        glob(&self.pattern, name)
+    }
+
+    /// Asks the API at `api`, or the forge's own, for the latest release,
+    /// sending `token` as a bearer token if given, and picks the one asset
+    /// that matches. None or several matching is an error listing them all.
+    /// A 5xx is retried while `budget` allows.
+    #[cfg(feature = "releases")]
+    pub async fn resolve(
+        &self,
+        client: &reqwest::Client,
+        api: Option<&Url>,
+        token: Option<&str>,
+        budget: &RetryBudget,
+        auth: Option<&AuthToken>,
+    ) -> anyhow::Result<Asset> {
+        let api = api.map_or(self.forge.api(), |api| api.as_str());
+        let api = api.trim_end_matches('/');
+        let latest = match self.forge {
+            Forge::GitHub => format!("{api}/repos/{}/releases/latest", self.project),
+            Forge::GitLab => format!(
+                "{api}/projects/{}/releases/permalink/latest",
+                percent_encoding::utf8_percent_encode(
+                    &self.project,
+                    percent_encoding::NON_ALPHANUMERIC
+                )
+            ),
+        };
+        let mut request = client
+            .get(&latest)
+            .header(header::ACCEPT, "application/json");
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = http::send_retrying(request, None, budget, auth).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!(
+                "{} has no release of '{}', or it's private and needs --bearer-token",
+                self.forge,
+                self.project
+            );
+        }
+        let response = http::check_status(response).await?;
+        let release: ApiRelease = serde_json::from_slice(&response.bytes().await?)
+            .with_context(|| format!("Cannot read the latest release from {latest}"))?;
+        let assets = match release.assets {
+            ApiAssets::GitHub(assets) => assets,
+            ApiAssets::GitLab { links } => links,
+        };
+        let found: Vec<&ApiAsset> = assets
+            .iter()
+            .filter(|asset| self.matches(&asset.name))
+            .collect();
+        let asset = match found.as_slice() {
+            [asset] => asset,
+            found => {
+                let (what, listed) = match found {
+                    [] => ("none", assets.iter().collect()),
+                    several => ("several", several.to_vec()),
+                };
+                let list: Vec<String> = listed
+                    .iter()
+                    .map(|asset| format!("  {}", asset.name))
+                    .collect();
+                bail!(
+                    "Release {} of '{}' has {what} of its {} assets matching '{}', pass a pattern that matches one:\n{}",
+                    release.tag_name,
+                    self.project,
+                    assets.len(),
+                    self.pattern,
+                    list.join("\n")
+                );
+            }
+        };
+        let url = asset
+            .url()
+            .with_context(|| format!("The asset '{}' has no valid URL", asset.name))?;
+        Ok(Asset {
+            tag: release.tag_name.clone(),
+            name: asset.name.clone(),
+            url,
+        })
+    }
+}
+
+/// What the releases API says of a release, of both forges.
+#[cfg(feature = "releases")]
+#[derive(Deserialize)]
+struct ApiRelease {
+    tag_name: String,
+    assets: ApiAssets,
+}
+
+/// GitHub lists a release's assets, GitLab its links.
+#[cfg(feature = "releases")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ApiAssets {
+    GitHub(Vec<ApiAsset>),
+    GitLab { links: Vec<ApiAsset> },
+}
+
+#[cfg(feature = "releases")]
+#[derive(Deserialize)]
+struct ApiAsset {
+    name: String,
+    /// GitHub's.
+    browser_download_url: Option<String>,
+    /// GitLab's: the permanent one if there is one, or else where the link
+    /// points.
+    direct_asset_url: Option<String>,
+    url: Option<String>,
+}
+
+#[cfg(feature = "releases")]
+impl ApiAsset {
+    fn url(&self) -> Option<Url> {
+        [
+            &self.browser_download_url,
+            &self.direct_asset_url,
+            &self.url,
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|url| Url::parse(url).ok())
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters
+/// and `?` any one.
+fn glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // The last `*` seen, and where in the name what it takes ends, to take
+    // one more character from there when the rest doesn't match.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&wanted) if wanted == '?' || wanted == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((at, taken)) => {
+                    star = Some((at, taken + 1));
+                    p = at + 1;
+                    n = taken + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&wanted| wanted == '*')
+}
@@ -0,0 +1,184 @@
+//! What the server says about a URL before downloading it, asked the same
+//! way by everything that needs to know: HEAD, then a GET of the first
+//! byte, then a plain GET whose body is never read. Some servers (PHP
+//! download scripts, mostly) refuse HEAD or answer it with nonsense.
+
+use crate::download::auth_token::AuthToken;
+use crate::download::http::{self, StatusClass};
+use crate::download::presigned;
+use crate::download::progress_handle::{self, PreflightStep};
+use crate::download::retry_budget::RetryBudget;
+use reqwest::StatusCode;
+use reqwest::header::{self, HeaderMap};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use url::Url;
+
+/// The request that got [`RemoteInfo`] its answer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeMethod {
+    Head,
+    /// A GET of `bytes=0-0`.
+    RangeGet,
+    /// A GET whose body was dropped unread.
+    Get,
+}
+
+impl fmt::Display for ProbeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProbeMethod::Head => "HEAD",
+            ProbeMethod::RangeGet => "a one-byte range GET",
+            ProbeMethod::Get => "GET",
+        })
+    }
+}
+
+/// What [`probe_remote`] found out about a URL.
+#[derive(Clone, Debug)]
+pub struct RemoteInfo {
+    pub status: StatusCode,
+    /// `None` when the server doesn't send a length. Zero for a 204 or 205.
+    pub size: Option<u64>,
+    /// Whether the server answered a range, or advertises
+    /// `Accept-Ranges: bytes` when none was asked for.
+    pub ranges: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    /// The `Content-Encoding` of the answer, if it isn't the plain bytes:
+    /// its length is of the encoded ones, which ranges of the plain bytes
+    /// don't add up to.
+    pub encoding: Option<String>,
+    /// Where redirects ended up.
+    pub final_url: Url,
+    pub method: ProbeMethod,
+    pub headers: HeaderMap,
+    /// The node that answered, when connected directly.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl RemoteInfo {
+    /// The info in `response`, a usable answer to `method`.
+    pub(crate) fn new(response: &reqwest::Response, method: ProbeMethod) -> anyhow::Result<Self> {
+        let headers = response.headers();
+        let text = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let advertised = headers
+            .get(header::ACCEPT_RANGES)
+            .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"bytes"));
+        let (size, ranges) = match (response.status(), method) {
+            (status, _) if StatusClass::of(status) == StatusClass::Empty => (Some(0), advertised),
+            (StatusCode::PARTIAL_CONTENT, _) => (http::content_range(response)?.total, true),
+            // `content_length()` is zero for HEAD responses, read the header
+            // instead.
+            (_, ProbeMethod::Head) => (header_length(headers), advertised),
+            // The range was ignored.
+            (_, ProbeMethod::RangeGet) => (response.content_length(), false),
+            (_, ProbeMethod::Get) => (response.content_length(), advertised),
+        };
+        Ok(Self {
+            status: response.status(),
+            size,
+            ranges,
+            etag: text(header::ETAG),
+            last_modified: text(header::LAST_MODIFIED),
+            content_type: text(header::CONTENT_TYPE),
+            encoding: http::content_encoding(headers).map(str::to_string),
+            final_url: response.url().clone(),
+            method,
+            headers: headers.clone(),
+            remote_addr: response.remote_addr(),
+        })
+    }
+}
+
+/// Asks the server about `url`, failing with the status error of the last
+/// answer when none of the requests gets a usable one. A presigned URL
+/// skips HEAD, not being signed for it. A 5xx is retried while `budget`
+/// allows.
+pub async fn probe_remote(
+    client: &reqwest::Client,
+    url: &Url,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<RemoteInfo> {
+    let (response, method) = probe(client, url, budget, auth).await?;
+    let response = match method {
+        ProbeMethod::Get => http::check_status(response).await?,
+        _ if usable(response.status()) => response,
+        _ => return Err(http::unexpected_status(response).await.into()),
+    };
+    RemoteInfo::new(&response, method)
+}
+
+/// The first usable answer about `url`, or the last one, whatever its
+/// status, and the request that got it. A GET's body is left unread.
+pub(crate) async fn probe(
+    client: &reqwest::Client,
+    url: &Url,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> reqwest::Result<(reqwest::Response, ProbeMethod)> {
+    progress_handle::report_preflight(PreflightStep::ProbingRanges);
+    if !presigned::is_presigned(url) {
+        let response = http::send(client.head(url.clone()), None, auth).await?;
+        if usable(response.status()) && informative_head(&response) {
+            return Ok(found(response, ProbeMethod::Head));
+        }
+        tracing::debug!(
+            "HEAD {} answered {}, asking with a GET",
+            http::redact_url(url),
+            response.status()
+        );
+    }
+    let response =
+        http::send_retrying(presigned::first_byte(client, url), None, budget, auth).await?;
+    if usable(response.status()) || conclusive(response.status()) {
+        return Ok(found(response, ProbeMethod::RangeGet));
+    }
+    let response = http::send_retrying(client.get(url.clone()), None, budget, auth).await?;
+    Ok(found(response, ProbeMethod::Get))
+}
+
+fn found(response: reqwest::Response, method: ProbeMethod) -> (reqwest::Response, ProbeMethod) {
+    tracing::debug!(
+        "Probed {} with {method}: {}",
+        http::redact_url(response.url()),
+        response.status()
+    );
+    (response, method)
+}
+
+fn usable(status: StatusCode) -> bool {
+    matches!(
+        StatusClass::of(status),
+        StatusClass::Content | StatusClass::Empty
+    )
+}
+
+/// Answers asking another way won't change: the file isn't there, or not
+/// for us.
+fn conclusive(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 401 | 403 | 404 | 407 | 410)
+}
+
+/// Whether a successful HEAD says how big the file is. A `Content-Length`
+/// of zero is as likely a script that didn't bother, so it's asked again.
+fn informative_head(response: &reqwest::Response) -> bool {
+    StatusClass::of(response.status()) == StatusClass::Empty
+        || header_length(response.headers()).is_some_and(|length| length > 0)
+}
+
+fn header_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+}
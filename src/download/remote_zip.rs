@@ -0,0 +1,388 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::error::DownloadError;
+use crate::download::fs_ops;
+use crate::download::http;
+use crate::download::options::TransferOptions;
+use crate::download::progress::TransferProgress;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::target_wait;
+use crate::download::utils::{self, ContentRange};
+use anyhow::{Context, bail};
+use flate2::read::DeflateDecoder;
+use std::borrow::Cow;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use url::Url;
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+
+const EOCD_LEN: usize = 22;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_EOCD_LEN: usize = 56;
+const CENTRAL_HEADER_LEN: usize = 46;
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// Enough of the archive's end to hold the end-of-central-directory record
+/// with the longest possible comment, plus the zip64 locator before it.
+const TAIL_LEN: u64 = (EOCD_LEN + 0xFFFF + ZIP64_LOCATOR_LEN) as u64;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// A central directory entry, with any zip64 sizes already applied.
+#[derive(Debug)]
+struct Member {
+    flags: u16,
+    method: u16,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+}
+
+/// Extracts a single member of a remote zip archive using range requests
+/// only: the end of central directory, the central directory itself, the
+/// member's local header and finally its compressed data.
+///
+/// The member is written to the target directory under its own file name
+/// (or `--output`). Progress counts compressed bytes fetched for the member.
+pub fn extract_zip_member(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    member_name: &str,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    progress.set_retry_budget(&options.retry_budget);
+    let reporter = progress.clone();
+    let result = extract(client, url, member_name, target_dir, progress, options);
+    reporter.finish_with(&result);
+    result
+}
+
+fn extract(
+    client: &reqwest::blocking::Client,
+    url: Url,
+    member_name: &str,
+    target_dir: &Path,
+    progress: TransferProgress,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    let archive = RemoteArchive::open(
+        client,
+        url,
+        &options.retry_budget,
+        options.auth_token.as_deref(),
+    )?;
+    let member = archive.find_member(member_name)?;
+    if member.flags & 1 != 0 {
+        bail!("'{member_name}' is encrypted, which is not supported");
+    }
+    if member.method != STORED && member.method != DEFLATED {
+        bail!(
+            "'{member_name}' uses compression method {}, only stored and deflate are supported",
+            member.method
+        );
+    }
+
+    let fname = match &options.output {
+        Some(output) => target_dir.join(output),
+        None => target_dir.join(
+            member_name
+                .rsplit('/')
+                .find(|part| !part.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("'{member_name}' is not a file"))?,
+        ),
+    };
+    if fname.exists() && !options.overwrite {
+        return Err(DownloadError::FileExists { path: fname }.into());
+    }
+
+    let header = archive.read_at(member.local_header_offset, LOCAL_HEADER_LEN)?;
+    if read_u32(&header, 0)? != LOCAL_HEADER_SIGNATURE {
+        bail!(
+            "No local file header at offset {}",
+            member.local_header_offset
+        );
+    }
+    let data_start = member.local_header_offset
+        + LOCAL_HEADER_LEN as u64
+        + read_u16(&header, 26)? as u64
+        + read_u16(&header, 28)? as u64;
+
+    progress.set_total(member.compressed_size);
+    let compressed: Box<dyn Read> = if member.compressed_size == 0 {
+        Box::new(std::io::empty())
+    } else {
+        let end = data_start + member.compressed_size - 1;
+        Box::new(archive.request(&format!("bytes={data_start}-{end}"))?.0)
+    };
+    let compressed = ProgressReader {
+        inner: compressed,
+        progress: &progress,
+        read: 0,
+    };
+    let mut reader: Box<dyn Read> = match member.method {
+        DEFLATED => Box::new(DeflateDecoder::new(compressed)),
+        _ => Box::new(compressed),
+    };
+
+    let mut dest = target_wait::retry("create", &fname, || {
+        fs_ops::open(
+            OpenOptions::new().create(true).write(true).truncate(true),
+            &fname,
+        )
+    })?;
+    let mut crc = crc32fast::Hasher::new();
+    let mut written = 0u64;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        if progress.interrupted.load(Ordering::SeqCst) {
+            bail!("Extraction interrupted.");
+        }
+        let read = reader
+            .read(&mut buffer)
+            .with_context(|| format!("Could not inflate '{member_name}'"))?;
+        if read == 0 {
+            break;
+        }
+        options.throttle.take_blocking(read);
+        crc.update(&buffer[..read]);
+        dest.write_all(&buffer[..read])?;
+        written += read as u64;
+    }
+    dest.sync_all()?;
+
+    if written != member.uncompressed_size {
+        bail!(
+            "'{member_name}' inflated to {written} bytes, the archive says {}",
+            member.uncompressed_size
+        );
+    }
+    if crc.finalize() != member.crc32 {
+        bail!("CRC32 mismatch for '{member_name}'");
+    }
+    println!(
+        "Extracted {} ({} compressed) from offset {data_start}",
+        indicatif::HumanBytes(written),
+        indicatif::HumanBytes(member.compressed_size)
+    );
+    Ok(fname)
+}
+
+/// The end of a remote archive, kept around so later reads that fall inside
+/// it (often the whole central directory) don't need another request.
+struct RemoteArchive<'a> {
+    client: &'a reqwest::blocking::Client,
+    url: Url,
+    budget: &'a RetryBudget,
+    auth: Option<&'a AuthToken>,
+    tail: Vec<u8>,
+    tail_start: u64,
+}
+
+impl<'a> RemoteArchive<'a> {
+    fn open(
+        client: &'a reqwest::blocking::Client,
+        url: Url,
+        budget: &'a RetryBudget,
+        auth: Option<&'a AuthToken>,
+    ) -> anyhow::Result<Self> {
+        let mut archive = Self {
+            client,
+            url,
+            budget,
+            auth,
+            tail: Vec::new(),
+            tail_start: 0,
+        };
+        let (response, range) = archive.request(&format!("bytes=-{TAIL_LEN}"))?;
+        if let Some(total) = range.total {
+            println!("Archive size: {}", indicatif::HumanBytes(total));
+        }
+        archive.tail = response.bytes()?.to_vec();
+        archive.tail_start = range.first;
+        Ok(archive)
+    }
+
+    /// Issues a range request and insists on a 206, since anything else
+    /// means the server would send the whole archive.
+    fn request(&self, range: &str) -> anyhow::Result<(reqwest::blocking::Response, ContentRange)> {
+        let request = self
+            .client
+            .get(self.url.clone())
+            .headers(http::range_headers(range));
+        let response = http::send_retrying_blocking(request, None, self.budget, self.auth)?;
+        match response.status().as_u16() {
+            206 => http::check_identity(response.headers())?,
+            200 => {
+                return Err(DownloadError::RangesUnsupported {
+                    needed_for: "extract from a remote zip",
+                }
+                .into());
+            }
+            _ => return Err(http::unexpected_status_blocking(response).into()),
+        }
+        let content_range = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(utils::parse_content_range)
+            .context("206 response without a valid Content-Range header")?;
+        Ok((response, content_range))
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> anyhow::Result<Cow<'_, [u8]>> {
+        if let Some(start) = offset.checked_sub(self.tail_start)
+            && let Some(bytes) = self.tail.get(start as usize..start as usize + len)
+        {
+            return Ok(Cow::Borrowed(bytes));
+        }
+        let end = offset + len as u64 - 1;
+        let (response, _) = self.request(&format!("bytes={offset}-{end}"))?;
+        let bytes = response.bytes()?;
+        if bytes.len() != len {
+            bail!(
+                "Expected {len} bytes at offset {offset}, got {}",
+                bytes.len()
+            );
+        }
+        Ok(Cow::Owned(bytes.to_vec()))
+    }
+
+    /// Returns the offset and size of the central directory.
+    fn central_directory(&self) -> anyhow::Result<(u64, u64)> {
+        let eocd = (0..=self.tail.len().saturating_sub(EOCD_LEN))
+            .rev()
+            .find(|&pos| {
+                read_u32(&self.tail, pos).ok() == Some(EOCD_SIGNATURE)
+                    && read_u16(&self.tail, pos + 20)
+                        .is_ok_and(|comment| pos + EOCD_LEN + comment as usize == self.tail.len())
+            })
+            .context("Not a zip archive, no end of central directory record found")?;
+        let size = read_u32(&self.tail, eocd + 12)?;
+        let offset = read_u32(&self.tail, eocd + 16)?;
+        if size != u32::MAX && offset != u32::MAX {
+            return Ok((offset as u64, size as u64));
+        }
+
+        let locator = eocd
+            .checked_sub(ZIP64_LOCATOR_LEN)
+            .filter(|&pos| read_u32(&self.tail, pos).ok() == Some(ZIP64_LOCATOR_SIGNATURE))
+            .context("Zip64 archive without a zip64 end of central directory locator")?;
+        let record_offset = read_u64(&self.tail, locator + 8)?;
+        let record = self.read_at(record_offset, ZIP64_EOCD_LEN)?;
+        if read_u32(&record, 0)? != ZIP64_EOCD_SIGNATURE {
+            bail!("No zip64 end of central directory record at offset {record_offset}");
+        }
+        Ok((read_u64(&record, 48)?, read_u64(&record, 40)?))
+    }
+
+    fn find_member(&self, name: &str) -> anyhow::Result<Member> {
+        let (offset, size) = self.central_directory()?;
+        let directory = self.read_at(offset, size as usize)?;
+        let wanted = name.trim_start_matches('/');
+        let mut pos = 0;
+        let mut entries = 0;
+        while pos + CENTRAL_HEADER_LEN <= directory.len() {
+            if read_u32(&directory, pos)? != CENTRAL_HEADER_SIGNATURE {
+                bail!(
+                    "Corrupt central directory at offset {}",
+                    offset + pos as u64
+                );
+            }
+            let name_len = read_u16(&directory, pos + 28)? as usize;
+            let extra_len = read_u16(&directory, pos + 30)? as usize;
+            let comment_len = read_u16(&directory, pos + 32)? as usize;
+            let name_start = pos + CENTRAL_HEADER_LEN;
+            let extra_start = name_start + name_len;
+            let entry_name = directory
+                .get(name_start..extra_start)
+                .context("Truncated central directory")?;
+            entries += 1;
+            if entry_name == wanted.as_bytes() {
+                let extra = directory
+                    .get(extra_start..extra_start + extra_len)
+                    .context("Truncated central directory")?;
+                return parse_member(&directory[pos..], extra);
+            }
+            pos = extra_start + extra_len + comment_len;
+        }
+        bail!("No member '{name}' in the archive ({entries} entries)")
+    }
+}
+
+fn parse_member(header: &[u8], extra: &[u8]) -> anyhow::Result<Member> {
+    let mut member = Member {
+        flags: read_u16(header, 8)?,
+        method: read_u16(header, 10)?,
+        crc32: read_u32(header, 16)?,
+        compressed_size: read_u32(header, 20)? as u64,
+        uncompressed_size: read_u32(header, 24)? as u64,
+        local_header_offset: read_u32(header, 42)? as u64,
+    };
+
+    // Fields that overflowed 32 bits are stored in the zip64 extra field, in
+    // this order, and only if they overflowed.
+    let mut pos = 0;
+    while pos + 4 <= extra.len() {
+        let id = read_u16(extra, pos)?;
+        let len = read_u16(extra, pos + 2)? as usize;
+        if id == 0x0001 {
+            let mut field = pos + 4;
+            for value in [
+                &mut member.uncompressed_size,
+                &mut member.compressed_size,
+                &mut member.local_header_offset,
+            ] {
+                if *value == u32::MAX as u64 {
+                    *value = read_u64(extra, field)?;
+                    field += 8;
+                }
+            }
+        }
+        pos += 4 + len;
+    }
+    Ok(member)
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> anyhow::Result<u16> {
+    Ok(u16::from_le_bytes(read_array(buf, pos)?))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> anyhow::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(buf, pos)?))
+}
+
+fn read_u64(buf: &[u8], pos: usize) -> anyhow::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(buf, pos)?))
+}
+
+fn read_array<const N: usize>(buf: &[u8], pos: usize) -> anyhow::Result<[u8; N]> {
+    buf.get(pos..pos + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Unexpected end of zip structure")
+}
+
+/// Reports bytes as they're read from the network.
+struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a TransferProgress,
+    read: usize,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read;
+        self.progress.set_downloaded(self.read);
+        Ok(read)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::fs_ops;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How files a download replaces or cleans up are removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Removal {
+    /// Gone for good.
+    #[default]
+    Delete,
+    /// Into the desktop's trash, from `--use-trash`, deleting instead where
+    /// there's none (a network mount, a headless box).
+    Trash,
+}
+
+/// Where [`Removal`] put a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Removed {
+    Trashed,
+    Deleted,
+}
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Removed::Trashed => "moved to the trash",
+            Removed::Deleted => "deleted",
+        })
+    }
+}
+
+impl Removal {
+    /// Removes the file at `path`.
+    pub fn remove_file(self, path: &Path) -> io::Result<Removed> {
+        self.remove(path, fs_ops::remove_file)
+    }
+
+    /// Removes the directory at `path` with everything in it.
+    pub fn remove_dir_all(self, path: &Path) -> io::Result<Removed> {
+        self.remove(path, fs_ops::remove_dir_all)
+    }
+
+    fn remove(self, path: &Path, delete: fn(&Path) -> io::Result<()>) -> io::Result<Removed> {
+        if self == Removal::Trash {
+            fs_ops::permit("move to the trash", path)?;
+            match trash::delete(path) {
+                Ok(()) => return Ok(Removed::Trashed),
+                Err(error) => diagnostics::warn(
+                    WarningId::CleanupFailed,
+                    format!(
+                        "Cannot move '{}' to the trash ({error}), deleting it instead",
+                        path.display()
+                    ),
+                ),
+            }
+        }
+        delete(path).map(|()| Removed::Deleted)
+    }
+}
+
+/// A download's partial files, removed by the remove-on-error policy when
+/// dropped, as when a `?` returns early or the run panics, unless
+/// [`disarm`](Self::disarm)ed once they're whole.
+#[derive(Debug)]
+pub struct PartialFileGuard {
+    path: PathBuf,
+    removal: Option<Removal>,
+}
+
+impl PartialFileGuard {
+    /// Guards the file or directory at `path`; `removal` is `None` when the
+    /// policy is to keep it, to be resumed.
+    pub fn new(path: &Path, removal: Option<Removal>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            removal,
+        }
+    }
+
+    /// Leaves the files be.
+    pub fn disarm(mut self) {
+        self.removal = None;
+    }
+
+    /// Applies the policy now, returning where the files went if they were
+    /// removed.
+    pub fn apply(mut self) -> io::Result<Option<Removed>> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> io::Result<Option<Removed>> {
+        let Some(removal) = self.removal.take() else {
+            return Ok(None);
+        };
+        match fs::symlink_metadata(&self.path) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+            Ok(metadata) if metadata.is_dir() => removal.remove_dir_all(&self.path).map(Some),
+            Ok(_) => removal.remove_file(&self.path).map(Some),
+        }
+    }
+}
+
+impl Drop for PartialFileGuard {
+    fn drop(&mut self) {
+        match self.remove() {
+            Ok(Some(removed)) => tracing::info!("'{}' was {removed}", self.path.display()),
+            Ok(None) => {}
+            Err(error) => diagnostics::warn(
+                WarningId::CleanupFailed,
+                format!("Cannot remove '{}': {error}", self.path.display()),
+            ),
+        }
+    }
+}
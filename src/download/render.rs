@@ -0,0 +1,900 @@
+use crate::download::progress::{ChunkState, ChunkView, TransferProgress};
+use crate::download::progress_handle::ChunkPhase;
+use crate::download::progress_handle::MergeProgress;
+use crate::download::schema::{ProgressEvent, ProgressLine, Versioned};
+use crate::download::speed::{Rate, Size, SpeedEstimator};
+use crate::download::stall;
+use colored::Colorize;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The interactive bars drawn, for [`clear_bars`].
+static BARS: Mutex<Vec<indicatif::WeakProgressBar>> = Mutex::new(Vec::new());
+
+/// Has [`clear_bars`] take `bar` off the screen should the run panic.
+pub fn track(bar: &indicatif::ProgressBar) {
+    if let Ok(mut bars) = BARS.lock() {
+        bars.retain(|bar| bar.upgrade().is_some());
+        bars.push(bar.downgrade());
+    }
+}
+
+/// Stops every bar still drawn and takes it off the screen, so a panic's
+/// message isn't drawn over. For the panic hook, which has no renderer to
+/// [`clear`](Renderer::clear).
+pub fn clear_bars() {
+    let Ok(bars) = BARS.try_lock() else {
+        return;
+    };
+    for bar in bars.iter().filter_map(indicatif::WeakProgressBar::upgrade) {
+        bar.finish_and_clear();
+    }
+}
+
+/// Draws a [`TransferProgress`]. The CLI calls [`render`](Self::render)
+/// whenever the download reports progress, then `finish` or `clear` once.
+///
+/// Each also prints the lines downloads leave with
+/// [`TransferProgress::println`].
+pub trait Renderer: Send + Sync {
+    fn render(&self, progress: &TransferProgress);
+    /// Draws the last state with `message` in place of the progress.
+    fn finish(&self, progress: &TransferProgress, message: &str);
+    /// Takes the progress off the screen.
+    fn clear(&self, progress: &TransferProgress);
+}
+
+impl<R: Renderer + ?Sized> Renderer for Box<R> {
+    fn render(&self, progress: &TransferProgress) {
+        (**self).render(progress);
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        (**self).finish(progress, message);
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        (**self).clear(progress);
+    }
+}
+
+/// `--progress`: how a download shows its progress.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// The interactive [`Spinner`] or [`ChunkBar`].
+    #[default]
+    Bar,
+    /// [`PlainText`] lines, a few seconds apart.
+    Plain,
+    /// [`JsonEvents`] lines.
+    Json,
+    /// Nothing, notes included.
+    None,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bar" => Ok(ProgressMode::Bar),
+            "plain" => Ok(ProgressMode::Plain),
+            "json" => Ok(ProgressMode::Json),
+            "none" => Ok(ProgressMode::None),
+            other => Err(format!(
+                "unknown progress mode '{other}', expected bar, plain, json or none"
+            )),
+        }
+    }
+}
+
+/// Draws nothing, for `--progress none`; the notes are dropped so they
+/// don't pile up.
+pub struct Silent;
+
+impl Renderer for Silent {
+    fn render(&self, progress: &TransferProgress) {
+        progress.take_notes();
+    }
+
+    fn finish(&self, progress: &TransferProgress, _message: &str) {
+        progress.take_notes();
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        progress.take_notes();
+    }
+}
+
+/// The characters the interactive lines are drawn with. Only how the
+/// progress looks depends on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Glyphs {
+    /// Block characters, and a braille spinner.
+    #[default]
+    Unicode,
+    /// `#`, `-` and `.`, with the percentage spelled out, for terminals
+    /// that show block characters as mojibake.
+    Ascii,
+}
+
+/// Cells in [`Spinner`]'s bar.
+const BAR_WIDTH: usize = 20;
+
+impl Glyphs {
+    /// [`Glyphs::Ascii`] when the terminal doesn't look like it can show
+    /// Unicode: a locale that isn't UTF-8, a `TERM` whose fonts lack block
+    /// characters, or a legacy Windows console not set to UTF-8.
+    pub fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        if ["dumb", "linux", "vt100", "vt220"].contains(&term.as_str()) {
+            return Glyphs::Ascii;
+        }
+        match unicode_console() {
+            true => Glyphs::Unicode,
+            false => Glyphs::Ascii,
+        }
+    }
+
+    /// A glyph per chunk, colored by what it's doing. A downloading chunk
+    /// fills up an eighth of a block at a time, and turns yellow once it's
+    /// [stuck](crate::download::progress::ChunkView::stuck).
+    pub fn chunks(self, chunks: &[ChunkView]) -> String {
+        let (done, downloading, waiting, failed, retrying) = match self {
+            Glyphs::Unicode => ("█", "█", "░", "█", "░"),
+            Glyphs::Ascii => ("#", "-", ".", "x", "!"),
+        };
+        let mut output = String::from("[");
+        for chunk in chunks {
+            let symbol = match chunk.state {
+                ChunkState::Completed => done.green(),
+                ChunkState::Downloading { .. } => {
+                    let glyph = match chunk.len {
+                        0 => downloading.to_string(),
+                        len => self.fill(chunk.bytes, len).to_string(),
+                    };
+                    match chunk.stuck {
+                        true => glyph.yellow(),
+                        false => glyph.cyan(),
+                    }
+                }
+                // Black? What about a light mode?
+                ChunkState::Pending => waiting.bright_black(),
+                ChunkState::Failed => failed.red(),
+                ChunkState::Retrying => retrying.red(),
+            };
+            output.push_str(&symbol.to_string());
+        }
+        output.push(']');
+        output
+    }
+
+    /// The glyph of a chunk `bytes` into `len`: a whole block once it's
+    /// done, the lowest eighth until it's an eighth in.
+    fn fill(self, bytes: u64, len: u64) -> char {
+        let glyphs = match self {
+            Glyphs::Unicode => SPARK_GLYPHS,
+            Glyphs::Ascii => ASCII_SPARK_GLYPHS,
+        };
+        let eighths = u128::from(bytes.min(len)) * 8 / u128::from(len);
+        glyphs[(eighths as usize).clamp(1, 8) - 1]
+    }
+
+    /// The glyph for a speed `sample` on a scale up to `max`.
+    pub fn spark(self, sample: u64, max: u64) -> char {
+        let glyphs = match self {
+            Glyphs::Unicode => SPARK_GLYPHS,
+            Glyphs::Ascii => ASCII_SPARK_GLYPHS,
+        };
+        if max == 0 {
+            return glyphs[0];
+        }
+        let top = glyphs.len() as u128 - 1;
+        let index = (sample.min(max) as u128 * top + max as u128 / 2) / max as u128;
+        glyphs[index as usize]
+    }
+
+    /// A glyph per sample, scaled so the fastest is the top one: stable
+    /// speed is a flat line, a stall a drop to the bottom.
+    pub fn sparkline(self, samples: &[u64]) -> String {
+        let max = samples.iter().copied().max().unwrap_or(0);
+        samples
+            .iter()
+            .map(|&sample| self.spark(sample, max))
+            .collect()
+    }
+
+    /// How far along `progress` is, as ` 42%` when the glyphs can't show
+    /// it and the total is known.
+    fn percent(self, progress: &TransferProgress) -> String {
+        match (self, progress.total()) {
+            (Glyphs::Unicode, _) | (_, 0) => String::new(),
+            (Glyphs::Ascii, total) => format!(" {}%", progress.downloaded() * 100 / total),
+        }
+    }
+
+    /// A bar of how far along `progress` is, with the percentage; empty
+    /// until the total is known.
+    fn bar(self, progress: &TransferProgress) -> String {
+        let total = progress.total();
+        if total == 0 {
+            return String::new();
+        }
+        let (done, left) = match self {
+            Glyphs::Unicode => ('█', '░'),
+            Glyphs::Ascii => ('#', '-'),
+        };
+        let downloaded = progress.downloaded().min(total);
+        let filled = (downloaded * BAR_WIDTH as u64 / total) as usize;
+        let mut bar = String::from("[");
+        bar.extend(std::iter::repeat_n(done, filled));
+        bar.extend(std::iter::repeat_n(left, BAR_WIDTH - filled));
+        format!("{bar}] {}% ", downloaded * 100 / total)
+    }
+
+    /// A spinner in the glyphs.
+    fn spinner(self) -> indicatif::ProgressBar {
+        let bar = indicatif::ProgressBar::new_spinner();
+        if self == Glyphs::Ascii {
+            bar.set_style(indicatif::ProgressStyle::default_spinner().tick_chars("-\\|/ "));
+        }
+        bar.enable_steady_tick(Duration::from_millis(100));
+        track(&bar);
+        bar
+    }
+}
+
+/// Whether the locale, or on Windows the console, is UTF-8. Windows
+/// Terminal always is.
+#[cfg(not(windows))]
+fn unicode_console() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+#[cfg(windows)]
+fn unicode_console() -> bool {
+    const CP_UTF8: u32 = 65001;
+    std::env::var_os("WT_SESSION").is_some()
+        // SAFETY: takes no arguments and only reads the console's state.
+        || unsafe { windows_sys::Win32::System::Console::GetConsoleOutputCP() } == CP_UTF8
+}
+
+/// The line [`Spinner`] shows for `progress` once it's underway, before
+/// any stall hint or sparkline.
+pub fn spinner_line(progress: &TransferProgress, glyphs: Glyphs) -> String {
+    let total = match progress.total() {
+        0 => String::new(),
+        total => format!(" / {}", Size(total)),
+    };
+    format!(
+        "{}Downloaded: {}{total} @ {}{}",
+        glyphs.bar(progress),
+        Size(progress.downloaded()),
+        Rate(progress.speed()),
+        eta(progress)
+    )
+}
+
+/// The line [`ChunkBar`] shows for `progress` once it's underway, before
+/// any stall hint or sparkline.
+pub fn chunk_line(progress: &TransferProgress, glyphs: Glyphs) -> String {
+    format!(
+        "{}{} Downloaded: {} / {} @ {}{}",
+        glyphs.chunks(&progress.chunks()),
+        glyphs.percent(progress),
+        Size(progress.downloaded()),
+        Size(progress.total()),
+        Rate(progress.speed()),
+        eta(progress)
+    )
+}
+
+/// `, 3 minutes left` once [`TransferProgress::eta`] can tell.
+fn eta(progress: &TransferProgress) -> String {
+    progress.eta().map_or(String::new(), |eta| {
+        format!(", {} left", indicatif::HumanDuration(eta))
+    })
+}
+
+/// One spinner line of bytes and speed, for single-stream downloads: a
+/// bar with the percentage and time left too, once the size is known.
+pub struct Spinner {
+    bar: indicatif::ProgressBar,
+    stall_timeout: Duration,
+    glyphs: Glyphs,
+    sparkline: Sparkline,
+}
+
+impl Spinner {
+    pub fn new(stall_timeout: Duration) -> Self {
+        Self {
+            bar: Glyphs::Unicode.spinner(),
+            stall_timeout,
+            glyphs: Glyphs::Unicode,
+            sparkline: Sparkline::default(),
+        }
+    }
+
+    /// Whether to end the line with a sparkline of the recent speed; on by
+    /// default.
+    pub fn with_sparkline(mut self, on: bool) -> Self {
+        self.sparkline.off = !on;
+        self
+    }
+
+    /// The characters to draw with; Unicode by default.
+    pub fn with_glyphs(mut self, glyphs: Glyphs) -> Self {
+        self.bar = glyphs.spinner();
+        self.glyphs = glyphs;
+        self.sparkline.glyphs = glyphs;
+        self
+    }
+}
+
+impl Renderer for Spinner {
+    fn render(&self, progress: &TransferProgress) {
+        print_notes(&self.bar, progress);
+        if let Some(line) = preflight_line(progress) {
+            self.bar.set_message(line);
+            return;
+        }
+        let mut message = spinner_line(progress, self.glyphs);
+        if let Some(hint) = stall::stall_hint(progress.idle(), self.stall_timeout) {
+            message.push_str(&format!(" ({hint})"));
+        }
+        append_if_room(&mut message, &self.sparkline.draw(progress));
+        self.bar.set_message(message);
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        print_notes(&self.bar, progress);
+        self.bar.finish_with_message(message.to_string());
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        print_notes(&self.bar, progress);
+        self.bar.finish_and_clear();
+    }
+}
+
+/// A spinner line with a glyph per chunk, filling up as it downloads, for
+/// worker mode.
+pub struct ChunkBar {
+    bar: indicatif::ProgressBar,
+    stall_timeout: Duration,
+    glyphs: Glyphs,
+    sparkline: Sparkline,
+    /// What was last handed to the bar, to skip frames where nothing
+    /// changed.
+    last_frame: Mutex<Frame>,
+}
+
+#[derive(Default, PartialEq)]
+struct Frame {
+    downloaded: u64,
+    chunks: Vec<ChunkView>,
+    hint: Option<String>,
+    merging: Option<MergeProgress>,
+    preflight: Option<String>,
+    sparkline: String,
+}
+
+impl ChunkBar {
+    pub fn new(stall_timeout: Duration) -> Self {
+        Self {
+            bar: Glyphs::Unicode.spinner(),
+            stall_timeout,
+            glyphs: Glyphs::Unicode,
+            sparkline: Sparkline::default(),
+            last_frame: Mutex::default(),
+        }
+    }
+
+    /// Whether to end the line with a sparkline of the recent speed; on by
+    /// default.
+    pub fn with_sparkline(mut self, on: bool) -> Self {
+        self.sparkline.off = !on;
+        self
+    }
+
+    /// The characters to draw with; Unicode by default.
+    pub fn with_glyphs(mut self, glyphs: Glyphs) -> Self {
+        self.bar = glyphs.spinner();
+        self.glyphs = glyphs;
+        self.sparkline.glyphs = glyphs;
+        self
+    }
+}
+
+impl Renderer for ChunkBar {
+    fn render(&self, progress: &TransferProgress) {
+        print_notes(&self.bar, progress);
+        let frame = Frame {
+            downloaded: progress.downloaded(),
+            chunks: progress.chunks(),
+            hint: stall::stall_hint(progress.idle(), self.stall_timeout),
+            merging: progress.merging(),
+            preflight: preflight_line(progress),
+            sparkline: self.sparkline.draw(progress),
+        };
+        let Ok(mut last_frame) = self.last_frame.lock() else {
+            return;
+        };
+        if *last_frame == frame {
+            return;
+        }
+        let message = match (frame.merging, &frame.preflight) {
+            (Some(merging), _) => merging_line(merging),
+            (None, Some(preflight)) => {
+                format!("{} {preflight}", self.glyphs.chunks(&frame.chunks))
+            }
+            (None, None) => {
+                let mut message = chunk_line(progress, self.glyphs);
+                if let Some(hint) = &frame.hint {
+                    message.push_str(&format!(" ({hint})"));
+                }
+                append_if_room(&mut message, &frame.sparkline);
+                message
+            }
+        };
+        self.bar.set_message(message);
+        *last_frame = frame;
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        print_notes(&self.bar, progress);
+        self.bar.finish_with_message(message.to_string());
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        print_notes(&self.bar, progress);
+        self.bar.finish_and_clear();
+    }
+}
+
+/// What the download is doing before its first byte and for how long, as
+/// in "Connecting (2.1s)".
+fn preflight_line(progress: &TransferProgress) -> Option<String> {
+    let (step, elapsed) = progress.preflight_step()?;
+    let step = step.to_string();
+    let mut letters = step.chars();
+    let first = letters.next()?.to_uppercase();
+    Some(format!(
+        "{first}{} ({:.1}s)",
+        letters.as_str(),
+        elapsed.as_secs_f64()
+    ))
+}
+
+/// Eighths of a block, slowest first, for the speed sparkline.
+const SPARK_GLYPHS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// [`SPARK_GLYPHS`] in ASCII, by how much of a cell each covers.
+const ASCII_SPARK_GLYPHS: [char; 8] = ['_', '.', ',', '-', '~', '=', '*', '#'];
+
+/// How often the sparkline samples the speed, so the
+/// [`HISTORY`](crate::download::speed::HISTORY) it draws covers the last
+/// half minute.
+const SPARK_EVERY: Duration = Duration::from_secs(1);
+
+/// The block for a speed `sample` on a scale up to `max`.
+pub fn spark_glyph(sample: u64, max: u64) -> char {
+    Glyphs::Unicode.spark(sample, max)
+}
+
+/// [`Glyphs::sparkline`] in blocks.
+pub fn sparkline(samples: &[u64]) -> String {
+    Glyphs::Unicode.sparkline(samples)
+}
+
+/// The speed history an interactive line draws its sparkline from.
+#[derive(Default)]
+struct Sparkline {
+    off: bool,
+    glyphs: Glyphs,
+    /// The estimator, and when it last took a sample.
+    speed: Mutex<Option<(SpeedEstimator, Instant)>>,
+}
+
+impl Sparkline {
+    /// Samples the speed if a sample is due, and draws the history. Starts
+    /// on the first call, so bytes resumed from an earlier run don't show
+    /// as a burst of speed.
+    fn draw(&self, progress: &TransferProgress) -> String {
+        let Ok(mut speed) = self.speed.lock() else {
+            return String::new();
+        };
+        if self.off {
+            return String::new();
+        }
+        let downloaded = progress.downloaded();
+        let (estimator, sampled) =
+            speed.get_or_insert_with(|| (SpeedEstimator::new(downloaded), Instant::now()));
+        if sampled.elapsed() >= SPARK_EVERY {
+            estimator.sample(downloaded);
+            *sampled = Instant::now();
+        }
+        self.glyphs.sparkline(&estimator.history())
+    }
+}
+
+/// Adds `extra` to the end of `message`, unless stderr isn't a terminal
+/// or is too narrow to fit both next to the spinner.
+fn append_if_room(message: &mut String, extra: &str) {
+    if extra.is_empty() {
+        return;
+    }
+    let Some((_, columns)) = console::Term::stderr().size_checked() else {
+        return;
+    };
+    // The spinner and the space after it.
+    let width = 2 + console::measure_text_width(message) + 1 + extra.chars().count();
+    if width <= columns as usize {
+        message.push(' ');
+        message.push_str(extra);
+    }
+}
+
+/// Prints the notes above `bar`.
+fn print_notes(bar: &indicatif::ProgressBar, progress: &TransferProgress) {
+    for note in progress.take_notes() {
+        bar.println(note);
+    }
+}
+
+fn merging_line(merging: MergeProgress) -> String {
+    format!(
+        "Merging parts {}/{}: {} / {} ({}%)",
+        merging.part,
+        merging.parts,
+        Size(merging.copied),
+        Size(merging.total),
+        (merging.copied * 100)
+            .checked_div(merging.total)
+            .unwrap_or(100)
+    )
+}
+
+/// A line of text whenever the status changes, with no cursor movement or
+/// colors, for logs and dumb terminals.
+pub struct PlainText<W> {
+    output: Mutex<Output<W>>,
+    stall_timeout: Duration,
+    /// For keepalive lines: how often one is written, changed or not.
+    every: Option<Duration>,
+    /// The least time between two status lines, for `--progress plain`.
+    interval: Option<Duration>,
+}
+
+/// A writer and the status line last written to it.
+struct Output<W> {
+    writer: W,
+    last_line: String,
+    written_at: Instant,
+}
+
+impl<W> Output<W> {
+    fn new(writer: W) -> Mutex<Self> {
+        Mutex::new(Self {
+            writer,
+            last_line: String::new(),
+            written_at: Instant::now(),
+        })
+    }
+
+    fn into_writer(output: Mutex<Self>) -> W {
+        match output.into_inner() {
+            Ok(output) => output.writer,
+            Err(poisoned) => poisoned.into_inner().writer,
+        }
+    }
+}
+
+impl<W: Write + Send> PlainText<W> {
+    pub fn new(writer: W, stall_timeout: Duration) -> Self {
+        Self {
+            output: Output::new(writer),
+            stall_timeout,
+            every: None,
+            interval: None,
+        }
+    }
+
+    /// Writes a changed status line only once `interval` has passed since
+    /// the last; notes and the finishing message still go out at once.
+    pub fn with_interval(self, interval: Duration) -> Self {
+        Self {
+            interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Writes the status line once `every` has passed since the last, even
+    /// if nothing changed, and nothing else; for `--keepalive-output`, to
+    /// keep CI watchdogs that kill silent jobs happy next to a progress
+    /// bar nobody sees. Notes are left to that bar.
+    pub fn keepalive(writer: W, stall_timeout: Duration, every: Duration) -> Self {
+        Self {
+            every: Some(every),
+            ..Self::new(writer, stall_timeout)
+        }
+    }
+
+    /// The writer, once done rendering.
+    pub fn into_inner(self) -> W {
+        Output::into_writer(self.output)
+    }
+
+    fn status_line(&self, progress: &TransferProgress) -> String {
+        if let Some(merging) = progress.merging() {
+            return merging_line(merging);
+        }
+        if let Some(preflight) = preflight_line(progress) {
+            return preflight;
+        }
+        let downloaded = progress.downloaded();
+        let mut line = match progress.total() {
+            0 => format!("Downloaded: {}", Size(downloaded)),
+            total => format!(
+                "Downloaded: {} / {} ({}%)",
+                Size(downloaded),
+                Size(total),
+                downloaded * 100 / total
+            ),
+        };
+        line.push_str(&format!(" @ {}", Rate(progress.speed())));
+        if let Some(hint) = stall::stall_hint(progress.idle(), self.stall_timeout) {
+            line.push_str(&format!(" ({hint})"));
+        }
+        line
+    }
+
+    /// Writes the notes, then `line` unless it's what was written last, or
+    /// a status line due only after the `interval`.
+    fn write(&self, progress: &TransferProgress, line: Option<String>, status: bool) {
+        let Ok(mut output) = self.output.lock() else {
+            return;
+        };
+        if let Some(every) = self.every {
+            if let Some(line) = line.filter(|_| output.written_at.elapsed() >= every) {
+                let _ = writeln!(output.writer, "{line}");
+                let _ = output.writer.flush();
+                output.written_at = Instant::now();
+            }
+            return;
+        }
+        for note in progress.take_notes() {
+            let _ = writeln!(output.writer, "{note}");
+        }
+        let due = match self.interval {
+            Some(interval) if status && !output.last_line.is_empty() => {
+                output.written_at.elapsed() >= interval
+            }
+            _ => true,
+        };
+        if let Some(line) = line.filter(|line| due && *line != output.last_line) {
+            let _ = writeln!(output.writer, "{line}");
+            output.last_line = line;
+            output.written_at = Instant::now();
+        }
+        let _ = output.writer.flush();
+    }
+}
+
+impl<W: Write + Send> Renderer for PlainText<W> {
+    fn render(&self, progress: &TransferProgress) {
+        self.write(progress, Some(self.status_line(progress)), true);
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        self.write(progress, Some(message.to_string()), false);
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        self.write(progress, None, false);
+    }
+}
+
+/// A [`ProgressSnapshot`](crate::download::progress_handle::ProgressSnapshot)
+/// as a line of JSON whenever it changes, and `{"message": ...}` lines for
+/// notes, for other programs to read; each carries the
+/// [`SCHEMA_VERSION`](crate::download::schema::SCHEMA_VERSION).
+pub struct JsonLines<W> {
+    output: Mutex<Output<W>>,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            output: Output::new(writer),
+        }
+    }
+
+    /// The writer, once done rendering.
+    pub fn into_inner(self) -> W {
+        Output::into_writer(self.output)
+    }
+
+    fn write(&self, progress: &TransferProgress, message: Option<&str>) {
+        let Ok(mut output) = self.output.lock() else {
+            return;
+        };
+        let line = |line: ProgressLine| {
+            serde_json::to_string(&Versioned::new(line)).expect("a progress line serializes")
+        };
+        let mut lines: Vec<String> = progress
+            .take_notes()
+            .into_iter()
+            .map(|message| line(ProgressLine::Message { message }))
+            .collect();
+        let snapshot = line(ProgressLine::Snapshot(Box::new(progress.snapshot())));
+        if snapshot != output.last_line {
+            output.last_line = snapshot.clone();
+            lines.push(snapshot);
+        }
+        if let Some(message) = message {
+            lines.push(line(ProgressLine::Message {
+                message: message.to_string(),
+            }));
+        }
+        for line in lines {
+            let _ = writeln!(output.writer, "{line}");
+        }
+        let _ = output.writer.flush();
+    }
+}
+
+impl<W: Write + Send> Renderer for JsonLines<W> {
+    fn render(&self, progress: &TransferProgress) {
+        self.write(progress, None);
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        self.write(progress, Some(message));
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        self.write(progress, None);
+    }
+}
+
+/// A [`ProgressEvent`] line for each thing that happened since the last
+/// render, for `--progress json`: `started` first, then `progress` when
+/// the counts change, `chunk_completed` as worker mode's chunks finish,
+/// and `message` for notes. The CLI ends the stream with `completed` or
+/// `error`, through [`write_event`].
+pub struct JsonEvents<W> {
+    output: Mutex<Events<W>>,
+    url: String,
+    start: Instant,
+    /// Leave out the events that come as the network allows, and the notes,
+    /// which carry timings, for `--json-deterministic`.
+    deterministic: bool,
+}
+
+/// A writer and what of the download was written to it.
+struct Events<W> {
+    writer: W,
+    started: bool,
+    /// The last `progress` event's downloaded and total bytes.
+    counts: Option<(u64, u64)>,
+    /// The chunks a `chunk_completed` event was written for.
+    completed: Vec<bool>,
+}
+
+impl<W: Write + Send> JsonEvents<W> {
+    /// Events for the download of `url`, timed from now.
+    pub fn new(writer: W, url: impl Into<String>) -> Self {
+        Self {
+            output: Mutex::new(Events {
+                writer,
+                started: false,
+                counts: None,
+                completed: Vec::new(),
+            }),
+            url: url.into(),
+            start: Instant::now(),
+            deterministic: false,
+        }
+    }
+
+    /// Writes only what's the same on every run of the same download:
+    /// `started`, and the CLI's `completed` or `error`.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// The writer, once done rendering.
+    pub fn into_inner(self) -> W {
+        match self.output.into_inner() {
+            Ok(output) => output.writer,
+            Err(poisoned) => poisoned.into_inner().writer,
+        }
+    }
+
+    fn write(&self, progress: &TransferProgress, message: Option<&str>) {
+        let Ok(mut output) = self.output.lock() else {
+            return;
+        };
+        let mut events = Vec::new();
+        if !output.started {
+            output.started = true;
+            events.push(ProgressEvent::Started {
+                url: self.url.clone(),
+            });
+        }
+        let notes = progress.take_notes();
+        if self.deterministic {
+            for event in &events {
+                write_event(&mut output.writer, event);
+            }
+            return;
+        }
+        events.extend(
+            notes
+                .into_iter()
+                .map(|message| ProgressEvent::Message { message }),
+        );
+        let snapshot = progress.snapshot();
+        let chunks = snapshot.chunk_map.len();
+        output.completed.resize(chunks, false);
+        for (chunk, phase) in snapshot.chunk_map.iter().enumerate() {
+            if *phase == ChunkPhase::Completed && !output.completed[chunk] {
+                output.completed[chunk] = true;
+                events.push(ProgressEvent::ChunkCompleted { chunk, chunks });
+            }
+        }
+        let counts = (snapshot.downloaded, snapshot.total);
+        if output.counts != Some(counts) {
+            output.counts = Some(counts);
+            events.push(ProgressEvent::Progress {
+                downloaded: snapshot.downloaded,
+                total: snapshot.total,
+                speed: snapshot.speed,
+                elapsed_ms: self.start.elapsed().as_millis() as u64,
+            });
+        }
+        if let Some(message) = message {
+            events.push(ProgressEvent::Message {
+                message: message.to_string(),
+            });
+        }
+        for event in &events {
+            write_event(&mut output.writer, event);
+        }
+    }
+}
+
+impl<W: Write + Send> Renderer for JsonEvents<W> {
+    fn render(&self, progress: &TransferProgress) {
+        self.write(progress, None);
+    }
+
+    fn finish(&self, progress: &TransferProgress, message: &str) {
+        self.write(progress, Some(message));
+    }
+
+    fn clear(&self, progress: &TransferProgress) {
+        self.write(progress, None);
+    }
+}
+
+/// Writes `event` to `writer` as a line of JSON, with the
+/// [`SCHEMA_VERSION`](crate::download::schema::SCHEMA_VERSION), in one
+/// write so lines from several writers to the same stream don't mix.
+pub fn write_event(mut writer: impl Write, event: &ProgressEvent) {
+    let mut line =
+        serde_json::to_string(&Versioned::new(event)).expect("a progress event serializes");
+    line.push('\n');
+    let _ = writer.write_all(line.as_bytes());
+    let _ = writer.flush();
+}
@@ -0,0 +1,165 @@
+use crate::download::async_range::{fetch_range, fetch_range_bytes};
+use crate::download::auth_token::AuthToken;
+use crate::download::fs_ops;
+use crate::download::http;
+use crate::download::pieces::PieceHashes;
+use crate::download::progress::TransferProgress;
+use crate::download::retry_budget::RetryBudget;
+use anyhow::{Context, bail};
+use std::io::SeekFrom;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use url::Url;
+
+/// What [`repair_file`] did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairSummary {
+    /// Size of the remote file, and so of the repaired one.
+    pub size: u64,
+    /// Bytes of the local file that were kept.
+    pub reused: u64,
+    /// Bytes fetched again and written over the local file.
+    pub refetched: u64,
+    /// The byte ranges that were written, adjacent ones merged.
+    pub repaired: Vec<RangeInclusive<u64>>,
+}
+
+/// Fixes the local copy of `url` at `path` in place, one range at a time,
+/// instead of downloading it again.
+///
+/// With `pieces`, each piece of the local file is checked against its hash
+/// and only the ones that fail are fetched. Without, every `sample_size`
+/// range is fetched and compared, and written only where it differs. Bytes
+/// the local file is missing are fetched either way, and anything past the
+/// remote size is cut off. Servers without range support are refused before
+/// the file is touched. A 5xx is retried while `budget` allows.
+#[allow(clippy::too_many_arguments)]
+pub async fn repair_file(
+    client: &reqwest::Client,
+    url: &Url,
+    path: &Path,
+    pieces: Option<&PieceHashes>,
+    sample_size: u64,
+    progress: TransferProgress,
+    budget: &Arc<RetryBudget>,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<RepairSummary> {
+    progress.set_retry_budget(budget);
+    let reporter = progress.clone();
+    let result = repair(
+        client,
+        url,
+        path,
+        pieces,
+        sample_size,
+        progress,
+        budget,
+        auth,
+    )
+    .await;
+    reporter.finish_with(&result);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn repair(
+    client: &reqwest::Client,
+    url: &Url,
+    path: &Path,
+    pieces: Option<&PieceHashes>,
+    sample_size: u64,
+    progress: TransferProgress,
+    budget: &RetryBudget,
+    auth: Option<&AuthToken>,
+) -> anyhow::Result<RepairSummary> {
+    let probe = fetch_range(client, url, 0, 0, None, budget, auth)
+        .await
+        .context("Repairing in place needs a server that supports range requests")?;
+    let size = http::content_range(&probe)?
+        .total
+        .context("Server didn't say how big the file is, can't repair it")?;
+    drop(probe);
+    if let Some(pieces) = pieces {
+        pieces.check_length(size)?;
+    }
+
+    let mut file = fs_ops::open_async(OpenOptions::new().read(true).write(true), path)
+        .await
+        .with_context(|| format!("Nothing to repair at '{}'", path.display()))?;
+    let local = file.metadata().await?.len();
+    if local > size {
+        fs_ops::set_len_async(&file, path, size).await?;
+    }
+    progress.set_total(size);
+
+    let ranges: Vec<(u64, u64)> = match pieces {
+        Some(pieces) => (0..pieces.hashes.len())
+            .map(|index| pieces.range(index, size))
+            .collect(),
+        None => (0..size)
+            .step_by(sample_size.max(1) as usize)
+            .map(|start| (start, (start + sample_size.max(1)).min(size) - 1))
+            .collect(),
+    };
+    let mut summary = RepairSummary {
+        size,
+        ..RepairSummary::default()
+    };
+    for (index, (start, end)) in ranges.into_iter().enumerate() {
+        if progress.interrupted.load(Ordering::SeqCst) {
+            bail!("Repair interrupted.");
+        }
+        let length = end - start + 1;
+        let mut existing = Vec::new();
+        if start < local {
+            file.seek(SeekFrom::Start(start)).await?;
+            (&mut file)
+                .take(length.min(local - start))
+                .read_to_end(&mut existing)
+                .await?;
+        }
+        let complete = existing.len() as u64 == length;
+
+        let fetched = match pieces {
+            Some(pieces) if complete && pieces.matches(index, &existing) => None,
+            Some(pieces) => {
+                let data = fetch_range_bytes(client, url, start, end, budget, auth).await?;
+                if !pieces.matches(index, &data) {
+                    bail!(
+                        "Piece {index} (bytes {start}-{end}) from the server doesn't match its hash either"
+                    );
+                }
+                Some(data)
+            }
+            None => {
+                let data = fetch_range_bytes(client, url, start, end, budget, auth).await?;
+                let differs = !complete || data != existing;
+                // Only fetched to compare.
+                if !differs {
+                    progress.add_wasted(length);
+                }
+                differs.then_some(data)
+            }
+        };
+        match fetched {
+            Some(data) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                file.write_all(&data).await?;
+                summary.refetched += length;
+                match summary.repaired.last_mut() {
+                    Some(last) if *last.end() + 1 == start => *last = *last.start()..=end,
+                    _ => summary.repaired.push(start..=end),
+                }
+            }
+            None => summary.reused += length,
+        }
+        progress.set_downloaded((end + 1) as usize);
+    }
+    file.flush().await?;
+    file.sync_all().await?;
+    Ok(summary)
+}
@@ -0,0 +1,79 @@
+//! `--retries`: how many times a transfer that lost its connection or
+//! timed out asks again for what it's missing, and how long it waits in
+//! between. Only failures that asking again can fix are retried: a 404 or
+//! a 403 would be answered the same way, and 5xx answers have their own
+//! retries in [`http`](crate::download::http). Every retry also comes out
+//! of the run's [`retry_budget`](crate::download::retry_budget).
+
+use crate::download::tls;
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+
+/// The CLI's retries when `--retries` isn't given, and the library's.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The longest wait between two retries, however many came before.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a chunk or a single-stream download retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries before the download fails; 0 fails on the first error.
+    pub retries: u32,
+    /// The wait before the first retry, doubled for each one after that.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retry `attempt`, counting from 1.
+    pub fn wait(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// Whether `error`, or anything that caused it, is a dropped, refused or
+/// reset connection or a timeout, and not a TLS handshake refused.
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    if tls::refusal(error).is_some() {
+        return false;
+    }
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if let Some(error) = error.downcast_ref::<reqwest::Error>()
+            && (error.is_timeout() || error.is_connect() || error.is_request() || error.is_body())
+        {
+            return true;
+        }
+        // An io::Error's `source` skips the error it wraps.
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            if matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+            ) {
+                return true;
+            }
+            if let Some(inner) = error.get_ref() {
+                return is_transient(inner);
+            }
+        }
+        cause = error.source();
+    }
+    false
+}
@@ -0,0 +1,90 @@
+//! `--retry-budget`: one allowance of retries for the whole run, shared by
+//! every kind of retry (5xx answers, dropped connections, stalls, short
+//! responses, piece re-fetches) in every chunk of every download. Each
+//! kind is bounded on its own, but together, over a long batch against a
+//! dead server, they can keep a run busy for hours; once the budget is
+//! spent, whatever would have retried fails instead.
+
+use crate::download::diagnostics;
+use crate::download::error::DownloadError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// The CLI's budget when `--retry-budget` isn't given. Library callers
+/// have none unless they give their downloads a [`RetryBudget`].
+pub const DEFAULT: u64 = 50;
+
+/// Retries allowed to every download that shares it, through an `Arc` in
+/// their [`TransferOptions`](crate::download::options::TransferOptions).
+#[derive(Debug)]
+pub struct RetryBudget {
+    /// Retries allowed, `u64::MAX` for no limit.
+    budget: u64,
+    used: AtomicU64,
+    /// Whether running out was warned about yet.
+    warned: AtomicBool,
+}
+
+/// How much of the budget the run has used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RetryUsage {
+    /// Retries made so far.
+    pub used: u64,
+    /// `None` when there's no limit.
+    pub budget: Option<u64>,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl RetryBudget {
+    /// Allows `budget` retries, `None` for no limit.
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget: budget.unwrap_or(u64::MAX),
+            used: AtomicU64::new(0),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    pub fn usage(&self) -> RetryUsage {
+        RetryUsage {
+            used: self.used.load(Ordering::Relaxed),
+            budget: (self.budget != u64::MAX).then_some(self.budget),
+        }
+    }
+
+    /// Takes one retry out of the budget and records it for
+    /// `--error-report`, or fails with `reason` once the budget is spent.
+    pub(crate) fn spend(
+        &self,
+        chunk: Option<usize>,
+        attempt: usize,
+        reason: &str,
+    ) -> Result<(), DownloadError> {
+        let budget = self.budget;
+        let spent = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < budget).then_some(used + 1)
+            })
+            .is_err();
+        if spent {
+            if !self.warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "The retry budget of {budget} for this run is spent; failing instead of retrying from now on"
+                );
+            }
+            return Err(DownloadError::RetryBudgetExhausted {
+                reason: reason.to_string(),
+                budget,
+            });
+        }
+        diagnostics::record_retry(chunk, attempt, reason);
+        Ok(())
+    }
+}
@@ -0,0 +1,740 @@
+//! The JSON dlm writes for other tools to read, in one place: the types of
+//! every [`Artifact`], for Rust callers to deserialize them with, and their
+//! JSON Schemas, which `dlm schema dump` prints.
+//!
+//! Every artifact carries the [`SCHEMA_VERSION`] it was written under, on
+//! each line of the JSON Lines ones. Any change to what they serialize has
+//! to bump it: `tests/schema.rs` holds the schemas of each version and
+//! fails when the generated ones stop matching.
+
+use crate::download::chunk_log::ChunkRecord;
+use crate::download::compare::Comparison;
+use crate::download::cosign::SignatureCheck;
+use crate::download::diagnostics::{Exchange, Retry, Warning};
+use crate::download::dns::Pin;
+use crate::download::etag::EtagMismatch;
+use crate::download::expected_size::SizeMismatch;
+use crate::download::host_health::HostStats;
+use crate::download::memory::MemoryLimit;
+use crate::download::part_check::PartCheck;
+use crate::download::parts::PartLayout;
+use crate::download::plan::Plan;
+use crate::download::preflight::{self, Totals};
+use crate::download::progress_handle::{ChunkSummary, ProgressHandle, ProgressSnapshot};
+use crate::download::retry_budget::RetryUsage;
+use crate::download::segment_tuning::SegmentTuning;
+use crate::download::throttle::Throttle;
+use crate::download::tls::TlsSession;
+use crate::download::transcript;
+use chrono::{DateTime, Local, TimeZone};
+use schemars::{JsonSchema, Schema, schema_for};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The version of every artifact's schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A manifest this long without a rewrite belongs to a download that's no
+/// longer running.
+const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// An artifact as written: its fields, and the schema version beside them.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub data: T,
+}
+
+impl<T> Versioned<T> {
+    /// `data` under this build's [`SCHEMA_VERSION`].
+    pub fn new(data: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            data,
+        }
+    }
+}
+
+/// The JSON dlm writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Artifact {
+    /// A line of the [`JsonLines`](crate::download::render::JsonLines)
+    /// renderer.
+    Progress,
+    /// A line of `--progress json`.
+    ProgressEvent,
+    /// `dlm ctl status`, and the control socket's answers.
+    Status,
+    /// `--web-status`'s `/status.json`.
+    WebStatus,
+    /// `--dry-run --json`.
+    Plan,
+    /// `--dry-run --json` with `--input-file`.
+    BatchPlan,
+    /// `--json` with `--input-file`.
+    BatchSummary,
+    /// A line of `--chunk-log`.
+    ChunkLog,
+    /// A line of `--record`'s transcript.
+    Transcript,
+    /// `--error-report`.
+    ErrorReport,
+    /// A download in flight, in the state directory's `active/`.
+    Manifest,
+    /// A line of `usage.jsonl`.
+    Usage,
+    /// `dlm version --json`.
+    Version,
+    /// `dlm compare --json`.
+    Compare,
+    /// `dlm audit --json`.
+    Audit,
+}
+
+impl Artifact {
+    pub const ALL: [Artifact; 15] = [
+        Artifact::Progress,
+        Artifact::ProgressEvent,
+        Artifact::Status,
+        Artifact::WebStatus,
+        Artifact::Plan,
+        Artifact::BatchPlan,
+        Artifact::BatchSummary,
+        Artifact::ChunkLog,
+        Artifact::Transcript,
+        Artifact::ErrorReport,
+        Artifact::Manifest,
+        Artifact::Usage,
+        Artifact::Version,
+        Artifact::Compare,
+        Artifact::Audit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Artifact::Progress => "progress",
+            Artifact::ProgressEvent => "progress-event",
+            Artifact::Status => "status",
+            Artifact::WebStatus => "web-status",
+            Artifact::Plan => "plan",
+            Artifact::BatchPlan => "batch-plan",
+            Artifact::BatchSummary => "batch-summary",
+            Artifact::ChunkLog => "chunk-log",
+            Artifact::Transcript => "transcript",
+            Artifact::ErrorReport => "error-report",
+            Artifact::Manifest => "manifest",
+            Artifact::Usage => "usage",
+            Artifact::Version => "version",
+            Artifact::Compare => "compare",
+            Artifact::Audit => "audit",
+        }
+    }
+
+    /// The artifact called `name`.
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|artifact| artifact.name() == name)
+    }
+
+    /// The JSON Schema of the artifact as written, version and all.
+    pub fn schema(self) -> Schema {
+        let mut schema = match self {
+            Artifact::Progress => schema_for!(Versioned<ProgressLine>),
+            Artifact::ProgressEvent => schema_for!(Versioned<ProgressEvent>),
+            Artifact::Status => schema_for!(Versioned<Status>),
+            Artifact::WebStatus => schema_for!(Versioned<DownloadStatus>),
+            Artifact::Plan => schema_for!(Versioned<Plan>),
+            Artifact::BatchPlan => schema_for!(Versioned<BatchPlan>),
+            Artifact::BatchSummary => schema_for!(Versioned<BatchSummary>),
+            Artifact::ChunkLog => schema_for!(Versioned<ChunkRecord>),
+            Artifact::Transcript => schema_for!(Versioned<transcript::Entry>),
+            Artifact::ErrorReport => schema_for!(Versioned<ErrorReport>),
+            Artifact::Manifest => schema_for!(Versioned<Manifest>),
+            Artifact::Usage => schema_for!(Versioned<Transfer>),
+            Artifact::Version => schema_for!(Versioned<VersionReport>),
+            Artifact::Compare => schema_for!(Versioned<Comparison>),
+            Artifact::Audit => schema_for!(Versioned<Audit>),
+        };
+        schema.insert("title".to_string(), self.name().into());
+        schema
+    }
+}
+
+/// A line of the [`JsonLines`](crate::download::render::JsonLines)
+/// renderer: the transfer's progress, or a message about it.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ProgressLine {
+    Message { message: String },
+    Snapshot(Box<ProgressSnapshot>),
+}
+
+/// A line of the [`JsonEvents`](crate::download::render::JsonEvents)
+/// renderer, for `--progress json`: what happened, under `event`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// The first line of a download.
+    Started { url: String },
+    Progress {
+        downloaded: u64,
+        /// Zero until the size is known.
+        total: u64,
+        /// Average speed since the start, in bytes/s.
+        speed: u64,
+        elapsed_ms: u64,
+    },
+    /// Worker mode finished the `chunk`th of `chunks`.
+    ChunkCompleted { chunk: usize, chunks: usize },
+    /// A note a human would have seen above the progress bar.
+    Message { message: String },
+    /// The last line of a download that succeeded, with what it printed.
+    Completed {
+        path: PathBuf,
+        bytes: u64,
+        /// Left out with `--json-deterministic`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u64>,
+        sha256: String,
+        /// `--expected-size`, which `bytes` matched.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expected_size: Option<u64>,
+        /// How `--segment-size auto` sized the segments. Left out with
+        /// `--json-deterministic`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        segment_tuning: Option<SegmentTuning>,
+        /// What the host's TLS handshake negotiated, for `https://`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tls: Option<TlsSession>,
+        /// The `--cosign-signature-url` check, which passed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<SignatureCheck>,
+    },
+    /// The last line of a run that failed, and the code it exits with.
+    Error {
+        message: String,
+        exit_code: u8,
+        /// The `--expected-size` check that failed, if that's what failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size_mismatch: Option<SizeMismatch>,
+        /// The `--cosign-signature-url` check that failed, if that's what
+        /// failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<SignatureCheck>,
+    },
+}
+
+/// What `status` reports: the same fields as a progress snapshot, plus the
+/// throttle's settings and buffer memory.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Status {
+    #[serde(flatten)]
+    pub progress: ProgressSnapshot,
+    pub paused: bool,
+    /// Bytes/s, `null` when unlimited.
+    pub rate_limit: Option<u64>,
+    /// Bytes of buffers held, and `--max-memory`.
+    pub buffered: u64,
+    pub max_memory: Option<u64>,
+    /// Seconds left at the average speed so far, `null` until the size and
+    /// the speed are known or once the transfer is over.
+    #[serde(default)]
+    pub eta_secs: Option<u64>,
+}
+
+impl Status {
+    /// The status of the transfer `progress` follows, if one is attached
+    /// yet, under `throttle`, with buffers held in `memory`.
+    pub fn of(
+        progress: Option<&ProgressHandle>,
+        throttle: &Throttle,
+        memory: &MemoryLimit,
+    ) -> Self {
+        let progress = progress.map(ProgressHandle::snapshot).unwrap_or_default();
+        let eta_secs = (progress.total > 0 && progress.speed > 0 && !progress.state.is_terminal())
+            .then(|| progress.total.saturating_sub(progress.downloaded) / progress.speed);
+        Status {
+            progress,
+            paused: throttle.is_paused(),
+            rate_limit: throttle.rate(),
+            buffered: memory.in_use(),
+            max_memory: memory.limit(),
+            eta_secs,
+        }
+    }
+}
+
+/// What `/status.json` answers, and `--status-file` holds.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DownloadStatus {
+    /// The file's name.
+    pub name: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+/// What a `--dry-run` of an `--input-file` batch found.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchPlan {
+    /// Those that would fail first, then the rest, each in the file's order.
+    pub entries: Vec<BatchEntry>,
+    /// What the entries checked add up to.
+    pub totals: Totals,
+    /// Lines that aren't URLs.
+    pub invalid: Vec<InvalidLine>,
+    /// Lines listing a URL again.
+    pub repeats: Vec<RepeatedLine>,
+}
+
+/// An entry of a [`BatchPlan`]: exactly one of `preflight`, `skipped`,
+/// `not_newer` and `error` is set.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchEntry {
+    pub line: usize,
+    /// With any credentials or signature redacted.
+    pub url: String,
+    pub destination: PathBuf,
+    /// What the entry is downloaded with.
+    pub settings: EntrySettings,
+    /// What asking the server about it found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preflight: Option<preflight::Entry>,
+    /// Why it's left out of the batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    /// Why `--newer-than` leaves it out: the server says it hasn't changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_newer: Option<String>,
+    /// Why the server couldn't be asked about it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The settings an input file gave an entry, over its defaults.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EntrySettings {
+    pub output: Option<String>,
+    pub checksum: Option<String>,
+    pub workers: u8,
+    /// The names of the headers sent; their values may be credentials.
+    pub headers: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct InvalidLine {
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RepeatedLine {
+    pub line: usize,
+    /// The line that listed the URL first.
+    pub same_as: usize,
+}
+
+/// How an `--input-file` run went, printed at its end with `--json`.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchSummary {
+    /// Entries and lines that aren't URLs.
+    pub total: usize,
+    pub completed: usize,
+    /// Including the lines that aren't URLs.
+    pub failed: usize,
+    pub interrupted: usize,
+    /// Entries left out, in the order they were.
+    pub skipped: Vec<SkippedEntry>,
+    /// Of those skipped, the ones `--newer-than` left out.
+    pub not_newer: usize,
+    /// How much of the `--retry-budget` the run used.
+    pub retry_budget: RetryUsage,
+    /// How each host fared, by name; empty with `--no-host-circuit-breaker`.
+    pub hosts: BTreeMap<String, HostStats>,
+    /// The target directory's size before and after, with a `--dir-quota`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_directory: Option<DirUsage>,
+}
+
+/// An entry of a [`BatchSummary`] that wasn't downloaded.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SkippedEntry {
+    /// With any credentials or signature redacted.
+    pub url: String,
+    /// As in `not newer than ...` or `host unhealthy`.
+    pub reason: String,
+}
+
+/// Bytes under a directory.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DirUsage {
+    pub before: u64,
+    pub after: u64,
+}
+
+/// `--error-report`: what's known about a failed run, written as JSON for a
+/// bug report or an alert, and printed back by `dlm report`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorReport {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    /// Stopped with Ctrl+C, a signal or the control socket's cancel.
+    pub cancelled: bool,
+    pub exit_code: u8,
+    /// The error, then what caused it, outermost first.
+    pub errors: Vec<String>,
+    pub url: Option<String>,
+    pub destination: Option<PathBuf>,
+    pub downloaded: Option<u64>,
+    /// Zero until the size was known.
+    pub total: Option<u64>,
+    /// How many chunks were in each state, in worker mode.
+    pub chunks: Option<ChunkSummary>,
+    pub last_response: Option<Exchange>,
+    /// The latest requests sent again, and why.
+    pub retries: Vec<Retry>,
+    /// How much of the `--retry-budget` the run used.
+    #[serde(default)]
+    pub retry_budget: RetryUsage,
+    /// What the run warned about before it failed.
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// Chunks whose response came with another ETag than the probe's.
+    #[serde(default)]
+    pub etag_mismatches: Vec<EtagMismatch>,
+    /// The `--expected-size` check that failed, if that's what failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_mismatch: Option<SizeMismatch>,
+    pub environment: Environment,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Environment {
+    pub version: String,
+    pub commit: String,
+    pub target: String,
+    pub os: String,
+    pub arch: String,
+    pub features: Vec<String>,
+}
+
+/// What's kept about a download in flight.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Manifest {
+    pub id: String,
+    pub url: String,
+    pub destination: PathBuf,
+    /// Where the command ran, so relative paths in `args` still work.
+    pub cwd: PathBuf,
+    /// The command line, without the `dlm` in front, with credentials
+    /// redacted. It's what's shown.
+    pub args: Vec<String>,
+    /// The command line as given, when redacting `args` took out something
+    /// resuming the download needs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_args: Option<Vec<String>>,
+    /// Milliseconds since the Unix epoch.
+    pub started: u64,
+    pub updated: u64,
+    pub downloaded: u64,
+    pub total: u64,
+    /// Of `downloaded`, how much an earlier run had already left on disk;
+    /// worker mode splits only what comes after it between the workers.
+    #[serde(default)]
+    pub prefix: u64,
+    #[serde(default)]
+    pub chunks: ChunkSummary,
+    /// How worker mode split the download into part files.
+    #[serde(default)]
+    pub parts: Option<PartLayout>,
+    /// The node worker mode pinned the chunks to (`--pin-ip`), for the run
+    /// that resumes it to carry on with.
+    #[serde(default)]
+    pub pinned: Option<Pin>,
+    /// The ETag worker mode got for the file, and the chunks whose response
+    /// came with another.
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub etag_mismatches: Vec<EtagMismatch>,
+    /// How worker mode checked the parts it kept from an earlier run, and
+    /// any that failed a check before the merge.
+    #[serde(default)]
+    pub part_checks: Vec<PartCheck>,
+}
+
+impl Manifest {
+    /// A manifest for a new download run as `args`, shown as `redacted`,
+    /// or one carrying on from `resumed`, whose command still runs where
+    /// it did.
+    pub fn new(
+        url: &str,
+        destination: &Path,
+        args: Vec<String>,
+        redacted: Vec<String>,
+        resumed: Option<&Manifest>,
+    ) -> Self {
+        let cwd = match resumed {
+            Some(resumed) => resumed.cwd.clone(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        let started = now();
+        let id = match resumed {
+            Some(resumed) => resumed.id.clone(),
+            None => {
+                let key = format!(
+                    "{url}\n{}\n{started}\n{}",
+                    destination.display(),
+                    std::process::id()
+                );
+                hex::encode(Sha256::digest(key))[..12].to_string()
+            }
+        };
+        Self {
+            id,
+            url: url.to_string(),
+            destination: cwd.join(destination),
+            args: match resumed {
+                Some(resumed) => resumed.args.clone(),
+                None => redacted.clone(),
+            },
+            secret_args: match resumed {
+                Some(resumed) => resumed.secret_args.clone(),
+                None => (args != redacted).then_some(args),
+            },
+            cwd,
+            started,
+            updated: started,
+            downloaded: 0,
+            total: 0,
+            prefix: 0,
+            chunks: ChunkSummary::default(),
+            parts: None,
+            pinned: None,
+            etag: None,
+            etag_mismatches: Vec::new(),
+            part_checks: Vec::new(),
+        }
+    }
+
+    /// Whether the download is still being worked on by some process.
+    pub fn is_running(&self) -> bool {
+        now().saturating_sub(self.updated) < STALE_AFTER.as_millis() as u64
+    }
+
+    /// The command line that carries on with the download, credentials
+    /// and all.
+    pub fn resume_args(&self) -> &[String] {
+        self.secret_args.as_deref().unwrap_or(&self.args)
+    }
+
+    /// The command that started the download, ready to paste into a shell,
+    /// with its credentials redacted.
+    pub fn command(&self) -> String {
+        let mut words = vec!["dlm".to_string()];
+        words.extend(self.args.iter().map(|arg| shell_quote(arg)));
+        format!(
+            "cd {} && {}",
+            shell_quote(&self.cwd.to_string_lossy()),
+            words.join(" ")
+        )
+    }
+}
+
+/// How a transfer ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,
+    Failed,
+    Interrupted,
+}
+
+/// One line of `usage.jsonl`: what one download transferred.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Transfer {
+    /// When it ended, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// With any credentials or signature redacted.
+    pub url: String,
+    /// Bytes of the file received, not counting what an earlier run had
+    /// left on disk.
+    pub bytes: u64,
+    /// Bytes received for nothing: thrown away or received again.
+    pub wasted: u64,
+    pub outcome: Outcome,
+    /// The file `--overwrite` replaced, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced: Option<Replaced>,
+    /// The `github://` or `gitlab://` URL `url` is the release asset of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    /// The download's `--tag`s.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// What `--cosign-signature-url`'s check came to, if it was made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureCheck>,
+}
+
+impl Transfer {
+    /// What the transfer in `snapshot` received.
+    pub fn new(url: String, snapshot: &ProgressSnapshot, outcome: Outcome) -> Self {
+        Self {
+            time: now(),
+            url,
+            bytes: snapshot.downloaded.saturating_sub(snapshot.prefix),
+            wasted: snapshot.wasted,
+            outcome,
+            replaced: None,
+            release: None,
+            tags: Vec::new(),
+            signature: None,
+        }
+    }
+
+    /// Everything that came over the connection.
+    pub fn total(&self) -> u64 {
+        self.bytes + self.wasted
+    }
+
+    pub fn local_time(&self) -> DateTime<Local> {
+        Local
+            .timestamp_millis_opt(self.time as i64)
+            .single()
+            .unwrap_or_default()
+    }
+}
+
+/// The file `--overwrite` replaced.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Replaced {
+    pub size: u64,
+    /// When it was last modified, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// Only hashed with `--diff-hash`, as it means reading all of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Moved to the trash by `--use-trash` rather than truncated.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trashed: bool,
+}
+
+/// What this build is and what it was built with, for `dlm version`.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct VersionReport {
+    pub version: String,
+    pub commit: String,
+    /// UTC date of the build, `YYYY-MM-DD`.
+    pub build_date: String,
+    pub target: String,
+    pub profile: String,
+    /// Optional cargo features compiled in.
+    pub features: Vec<String>,
+    /// TLS implementations reqwest was built with, and what they're for.
+    pub tls: Vec<String>,
+    /// Flags taken from `DM_*` variables, secrets' values left out.
+    pub environment: Vec<EnvFlag>,
+}
+
+impl VersionReport {
+    pub fn current() -> Self {
+        let features = [
+            ("otel", cfg!(feature = "otel")),
+            ("http3", cfg!(feature = "http3")),
+            ("systemd", cfg!(feature = "systemd")),
+            ("clipboard", cfg!(feature = "clipboard")),
+            ("torrent", cfg!(feature = "torrent")),
+            ("sigstore", cfg!(feature = "sigstore")),
+            ("zstd", cfg!(feature = "zstd")),
+        ];
+        let tls = vec!["rustls (ring)".to_string()];
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            commit: env!("DLM_GIT_COMMIT").to_string(),
+            build_date: env!("DLM_BUILD_DATE").to_string(),
+            target: env!("DLM_TARGET").to_string(),
+            profile: match cfg!(debug_assertions) {
+                true => "debug",
+                false => "release",
+            }
+            .to_string(),
+            features: features
+                .into_iter()
+                .filter(|(_, on)| *on)
+                .map(|(feature, _)| feature.to_string())
+                .collect(),
+            tls,
+            environment: Vec::new(),
+        }
+    }
+}
+
+/// A flag that was taken from its variable, not the command line.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EnvFlag {
+    pub variable: String,
+    /// `None` when it's a secret.
+    pub value: Option<String>,
+}
+
+impl fmt::Display for EnvFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value.as_deref().unwrap_or("<redacted>");
+        write!(f, "{}={value}", self.variable)
+    }
+}
+
+/// `dlm audit`'s findings.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Audit {
+    pub directory: PathBuf,
+    pub algorithm: String,
+    pub baseline: PathBuf,
+    pub updated: bool,
+    pub files: Vec<AuditEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    pub path: PathBuf,
+    pub status: AuditStatus,
+    /// The hash now, for files that could be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How a file compares with the baseline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditStatus {
+    Verified,
+    Modified,
+    New,
+    Missing,
+    Unreadable,
+}
+
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
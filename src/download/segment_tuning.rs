@@ -0,0 +1,154 @@
+//! `--segment-size auto`: worker mode starts with segments of
+//! [`INITIAL`] and sizes the ones after them by how the finished ones went.
+//! A segment's time to first byte is the overhead of its request, paid
+//! again by every segment; the rest is the transfer. Segments are made as
+//! small as keeps the overhead under [`MAX_OVERHEAD`] of the transfer, so a
+//! high-latency link gets long ones, while a fast one keeps them short and
+//! loses little to a failure.
+
+use crate::download::speed::Size;
+use crate::download::utils;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The size of the segments before any has finished, within the bounds.
+pub const INITIAL: u64 = 8 << 20;
+
+/// `--segment-size-min` unless given.
+pub const DEFAULT_MIN: u64 = 1 << 20;
+
+/// `--segment-size-max` unless given.
+pub const DEFAULT_MAX: u64 = 256 << 20;
+
+/// The share of a segment's transfer time its request's overhead may take.
+pub const MAX_OVERHEAD: f64 = 0.05;
+
+/// A size this close to the one segments have is not worth a change.
+const HYSTERESIS: f64 = 0.1;
+
+/// `--segment-size`: a size, or `auto`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentSize {
+    Fixed(u64),
+    Auto,
+}
+
+impl FromStr for SegmentSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "auto" => Ok(SegmentSize::Auto),
+            size => utils::parse_byte_size(size).map(SegmentSize::Fixed),
+        }
+    }
+}
+
+/// How small and how large `--segment-size auto` makes segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentBounds {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl SegmentBounds {
+    /// The size of the first segments.
+    pub fn initial(&self) -> u64 {
+        INITIAL.clamp(self.min, self.max)
+    }
+}
+
+/// What `--segment-size auto` did over a download.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SegmentTuning {
+    /// Bytes of the first segments.
+    pub initial: u64,
+    /// Bytes of the last segments sized.
+    #[serde(rename = "final")]
+    pub last: u64,
+    /// How many times the size changed.
+    pub adjustments: u32,
+}
+
+impl fmt::Display for SegmentTuning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let times = match self.adjustments {
+            1 => "time",
+            _ => "times",
+        };
+        write!(
+            f,
+            "from {} to {}, adjusted {} {times}",
+            Size(self.initial),
+            Size(self.last),
+            self.adjustments
+        )
+    }
+}
+
+/// Sizes the segments of one download as they finish.
+#[derive(Debug)]
+pub struct SegmentTuner {
+    bounds: SegmentBounds,
+    /// What sizes are rounded up to, as the chunks' boundaries are.
+    alignment: u64,
+    tuning: SegmentTuning,
+}
+
+impl SegmentTuner {
+    pub fn new(bounds: SegmentBounds, alignment: u64) -> Self {
+        let initial = bounds.initial().next_multiple_of(alignment.max(1));
+        Self {
+            bounds,
+            alignment: alignment.max(1),
+            tuning: SegmentTuning {
+                initial,
+                last: initial,
+                adjustments: 0,
+            },
+        }
+    }
+
+    /// The size of the next segment.
+    pub fn size(&self) -> u64 {
+        self.tuning.last
+    }
+
+    pub fn tuning(&self) -> SegmentTuning {
+        self.tuning
+    }
+
+    /// Takes a segment of `bytes` that took `elapsed`, `ttfb` of it until
+    /// the first byte, into account. The size moves towards the one whose
+    /// overhead is [`MAX_OVERHEAD`] of its transfer, by half or double at
+    /// most at a time; returns it if it changed.
+    pub fn record(&mut self, bytes: u64, elapsed: Duration, ttfb: Duration) -> Option<u64> {
+        let transfer = elapsed.saturating_sub(ttfb).as_secs_f64();
+        if bytes == 0 || transfer <= 0.0 {
+            return None;
+        }
+        let overhead = ttfb.as_secs_f64() / transfer;
+        let wanted = bytes as f64 * overhead / MAX_OVERHEAD;
+        let size = self.size();
+        let next = (wanted as u64)
+            .clamp(size / 2, size.saturating_mul(2))
+            .clamp(self.bounds.min, self.bounds.max)
+            .next_multiple_of(self.alignment);
+        if next.abs_diff(size) as f64 <= size as f64 * HYSTERESIS {
+            return None;
+        }
+        tracing::info!(
+            "A segment of {} spent {:.1}% of its time waiting for its first byte ({}ms), sizing the next ones {}",
+            Size(bytes),
+            overhead * 100.0,
+            ttfb.as_millis(),
+            Size(next)
+        );
+        self.tuning.last = next;
+        self.tuning.adjustments += 1;
+        Some(next)
+    }
+}
@@ -0,0 +1,346 @@
+use crate::download::error::DownloadError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Time constant of the exponential smoothing.
+const SMOOTHING: Duration = Duration::from_secs(5);
+
+/// How many smoothed rates a [`SpeedEstimator`] keeps, newest last.
+pub const HISTORY: usize = 30;
+
+/// How rates and sizes are shown, set from `--speed-units`.
+static SPEED_UNITS: AtomicU8 = AtomicU8::new(SpeedUnits::BytesBinary as u8);
+
+/// Units for showing transfer rates: KiB/MiB (the default), kB/MB, or
+/// kbit/Mbit for comparing with what the line is sold as. Only what's
+/// printed changes; JSON stays in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpeedUnits {
+    #[default]
+    BytesBinary,
+    BytesSi,
+    Bits,
+}
+
+impl SpeedUnits {
+    /// The units chosen for this run.
+    pub fn current() -> Self {
+        match SPEED_UNITS.load(Ordering::Relaxed) {
+            1 => SpeedUnits::BytesSi,
+            2 => SpeedUnits::Bits,
+            _ => SpeedUnits::BytesBinary,
+        }
+    }
+
+    /// `bytes_per_sec` in these units, e.g. `1.00 MiB/s`, `1.05 MB/s` or
+    /// `8.39 Mbit/s`.
+    pub fn rate(self, bytes_per_sec: u64) -> String {
+        match self {
+            SpeedUnits::BytesBinary => format!("{}/s", indicatif::HumanBytes(bytes_per_sec)),
+            SpeedUnits::BytesSi => format!("{}/s", si(bytes_per_sec as f64, "B")),
+            SpeedUnits::Bits => format!("{}/s", si(bytes_per_sec as f64 * 8.0, "bit")),
+        }
+    }
+
+    /// A size to show next to rates in these units: with binary prefixes,
+    /// or SI ones for either of the others. Sizes are always in bytes.
+    pub fn size(self, bytes: u64) -> String {
+        match self {
+            SpeedUnits::BytesBinary => indicatif::HumanBytes(bytes).to_string(),
+            SpeedUnits::BytesSi | SpeedUnits::Bits => si(bytes as f64, "B"),
+        }
+    }
+}
+
+impl FromStr for SpeedUnits {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "bytes-binary" => Ok(SpeedUnits::BytesBinary),
+            "bytes-si" => Ok(SpeedUnits::BytesSi),
+            "bits" => Ok(SpeedUnits::Bits),
+            _ => Err(format!(
+                "unknown speed units '{value}', expected bytes-binary, bytes-si or bits"
+            )),
+        }
+    }
+}
+
+/// Shows rates and sizes in `units` from now on.
+pub fn use_speed_units(units: SpeedUnits) {
+    SPEED_UNITS.store(units as u8, Ordering::Relaxed);
+}
+
+/// `value` with an SI prefix and two decimals, moving up a prefix rather
+/// than rounding to `1000.00`.
+fn si(value: f64, unit: &str) -> String {
+    const PREFIXES: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+    if value < 1000.0 {
+        return format!("{value:.0} {unit}");
+    }
+    let mut scaled = value / 1000.0;
+    let mut prefix = 0;
+    while scaled >= 999.995 && prefix < PREFIXES.len() - 1 {
+        scaled /= 1000.0;
+        prefix += 1;
+    }
+    format!("{scaled:.2} {}{unit}", PREFIXES[prefix])
+}
+
+/// A rate in bytes/s, shown in the run's [`SpeedUnits`].
+#[derive(Clone, Copy, Debug)]
+pub struct Rate(pub u64);
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&SpeedUnits::current().rate(self.0))
+    }
+}
+
+/// A size in bytes, shown to match the run's [`SpeedUnits`].
+#[derive(Clone, Copy, Debug)]
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&SpeedUnits::current().size(self.0))
+    }
+}
+
+/// Exponentially weighted moving average of the transfer rate, fed with the
+/// cumulative byte count at irregular intervals.
+pub struct SpeedEstimator {
+    rate: Option<f64>,
+    last_bytes: u64,
+    last_sample: Instant,
+    /// The last [`HISTORY`] rates, in bytes/s.
+    history: VecDeque<u64>,
+}
+
+impl SpeedEstimator {
+    pub fn new(bytes: u64) -> Self {
+        Self {
+            rate: None,
+            last_bytes: bytes,
+            last_sample: Instant::now(),
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    /// Records the cumulative byte count and returns the smoothed rate in
+    /// bytes/s.
+    pub fn sample(&mut self, bytes: u64) -> f64 {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_sample).as_secs_f64();
+        if dt <= 0.0 {
+            return self.rate.unwrap_or(0.0);
+        }
+        let instant_rate = bytes.saturating_sub(self.last_bytes) as f64 / dt;
+        let alpha = 1.0 - (-dt / SMOOTHING.as_secs_f64()).exp();
+        let rate = match self.rate {
+            Some(rate) => rate + alpha * (instant_rate - rate),
+            None => instant_rate,
+        };
+        self.rate = Some(rate);
+        self.last_bytes = bytes;
+        self.last_sample = now;
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(rate as u64);
+        rate
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate.unwrap_or(0.0)
+    }
+
+    /// The rates [`sample`](Self::sample) returned lately, oldest first.
+    pub fn history(&self) -> Vec<u64> {
+        self.history.iter().copied().collect()
+    }
+}
+
+/// Aborts a transfer whose smoothed speed stays below a floor.
+#[derive(Clone, Copy, Debug)]
+pub struct MinSpeedPolicy {
+    /// Minimum smoothed speed in bytes/s.
+    pub threshold: u64,
+    /// How long the speed must stay below the threshold before aborting.
+    pub window: Duration,
+    /// Initial period during which the speed isn't evaluated at all, since
+    /// connection setup and TCP slow start always look slow.
+    pub ramp_up: Duration,
+}
+
+/// Samples `downloaded` once a second and resolves with
+/// [`DownloadError::TooSlow`] once the policy is violated. Never resolves
+/// otherwise, so it's meant to be raced against the download itself.
+pub async fn watch_min_speed(
+    policy: MinSpeedPolicy,
+    downloaded: impl Fn() -> u64,
+) -> DownloadError {
+    tokio::time::sleep(policy.ramp_up).await;
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    // The first tick completes immediately.
+    interval.tick().await;
+    let mut estimator = SpeedEstimator::new(downloaded());
+    let mut below_since: Option<Instant> = None;
+    loop {
+        interval.tick().await;
+        let speed = estimator.sample(downloaded());
+        if speed >= policy.threshold as f64 {
+            below_since = None;
+            continue;
+        }
+        let since = *below_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= policy.window {
+            return DownloadError::TooSlow {
+                speed: speed as u64,
+                threshold: policy.threshold,
+                window: policy.window,
+            };
+        }
+    }
+}
+
+/// Times a response from its request to its first body byte.
+pub(crate) struct FirstByte {
+    requested: Instant,
+    ttfb: Option<Duration>,
+}
+
+impl FirstByte {
+    /// Starts the clock; call right before sending the request.
+    pub(crate) fn new() -> Self {
+        Self {
+            requested: Instant::now(),
+            ttfb: None,
+        }
+    }
+
+    /// Notes that body bytes arrived, returning the time to first byte the
+    /// first time only.
+    pub(crate) fn arrived(&mut self) -> Option<Duration> {
+        if self.ttfb.is_some() {
+            return None;
+        }
+        let ttfb = self.requested.elapsed();
+        self.ttfb = Some(ttfb);
+        Some(ttfb)
+    }
+
+    pub(crate) fn ttfb(&self) -> Option<Duration> {
+        self.ttfb
+    }
+}
+
+/// Bytes per second over `elapsed` less the wait for the first byte, so a
+/// slow start doesn't make a short download look like a slow transfer.
+pub fn transfer_rate(bytes: u64, elapsed: Duration, ttfb: Option<Duration>) -> u64 {
+    let transfer = elapsed.saturating_sub(ttfb.unwrap_or_default());
+    (bytes as f64 / transfer.as_secs_f64().max(0.001)) as u64
+}
+
+/// How time to first byte varied across worker chunks, in milliseconds. A
+/// wide spread on one server hints at it limiting each connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TtfbSpread {
+    pub min_ms: u64,
+    pub median_ms: u64,
+    pub max_ms: u64,
+    pub chunks: usize,
+}
+
+impl TtfbSpread {
+    pub fn of(mut samples: Vec<Duration>) -> Option<Self> {
+        samples.sort();
+        let ms = |sample: &Duration| sample.as_millis() as u64;
+        Some(Self {
+            min_ms: ms(samples.first()?),
+            median_ms: ms(&samples[samples.len() / 2]),
+            max_ms: ms(samples.last()?),
+            chunks: samples.len(),
+        })
+    }
+}
+
+impl fmt::Display for TtfbSpread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {}ms, median {}ms, max {}ms over {} chunks",
+            self.min_ms, self.median_ms, self.max_ms, self.chunks
+        )
+    }
+}
+
+/// Where a transfer's time went: awaiting the next piece from the network,
+/// or awaiting the disk to take it. Clones share the counters, so workers
+/// add up into one split; time spent throttled is in neither.
+#[derive(Clone, Debug, Default)]
+pub struct TimeSplit {
+    network_ns: Arc<AtomicU64>,
+    disk_ns: Arc<AtomicU64>,
+}
+
+impl TimeSplit {
+    pub fn add_network(&self, waited: Duration) {
+        self.network_ns
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_disk(&self, waited: Duration) {
+        self.disk_ns
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Adds another split's totals to this one.
+    pub fn add(&self, other: &TimeSplit) {
+        self.add_network(other.network());
+        self.add_disk(other.disk());
+    }
+
+    pub fn network(&self) -> Duration {
+        Duration::from_nanos(self.network_ns.load(Ordering::Relaxed))
+    }
+
+    pub fn disk(&self) -> Duration {
+        Duration::from_nanos(self.disk_ns.load(Ordering::Relaxed))
+    }
+
+    /// What's holding the transfer back, once one side took at least twice
+    /// as long as the other.
+    pub fn bound(&self) -> Option<&'static str> {
+        let (network, disk) = (self.network(), self.disk());
+        if network >= disk * 2 && !network.is_zero() {
+            Some("network-bound")
+        } else if disk >= network * 2 && !disk.is_zero() {
+            Some("disk-bound")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TimeSplit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}s awaiting the network, {:.2}s awaiting the disk",
+            self.network().as_secs_f64(),
+            self.disk().as_secs_f64()
+        )?;
+        if let Some(bound) = self.bound() {
+            write!(f, " ({bound})")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::download::speed::Rate;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many times a stalled stream is re-requested before giving up.
+pub const MAX_STALL_RESTARTS: usize = 3;
+
+/// How many times one chunk is re-assigned for being under
+/// `--chunk-min-speed` before it's left to finish where it is.
+pub const MAX_REASSIGNMENTS: usize = 3;
+
+/// How long a downloading chunk goes without data before the chunk bar
+/// shows it as stuck.
+pub const STUCK_CHUNK: Duration = Duration::from_secs(10);
+
+/// When a transfer counts as stalled or too slow.
+#[derive(Clone, Copy, Debug)]
+pub struct StallPolicy {
+    /// Maximum time without receiving a single byte.
+    pub stall_timeout: Duration,
+    /// Minimum average speed in bytes/s that must be held over the window,
+    /// like curl's `--speed-limit`/`--speed-time` pair.
+    pub min_speed: Option<(u64, Duration)>,
+}
+
+impl Default for StallPolicy {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(30),
+            min_speed: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stall {
+    /// No bytes at all for the given time.
+    NoData(Duration),
+    /// Bytes are trickling in, but below the minimum speed for the window.
+    TooSlow { speed: u64, window: Duration },
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stall::NoData(idle) => write!(f, "Transfer stalled, no data for {}s", idle.as_secs()),
+            Stall::TooSlow { speed, window } => write!(
+                f,
+                "Transfer too slow, {} over the last {}s",
+                Rate(*speed),
+                window.as_secs()
+            ),
+        }
+    }
+}
+
+/// Tracks a single stream's byte arrivals against a [`StallPolicy`].
+pub struct StallMonitor {
+    policy: StallPolicy,
+    last_byte: Instant,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl StallMonitor {
+    pub fn new(policy: StallPolicy) -> Self {
+        let now = Instant::now();
+        Self {
+            policy,
+            last_byte: now,
+            window_start: now,
+            window_bytes: 0,
+        }
+    }
+
+    pub fn record(&mut self, bytes: usize) {
+        if bytes > 0 {
+            self.last_byte = Instant::now();
+            self.window_bytes += bytes as u64;
+        }
+    }
+
+    /// Forget everything seen so far, e.g. after re-issuing the request.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.policy);
+    }
+
+    pub fn check(&mut self) -> Option<Stall> {
+        let idle = self.last_byte.elapsed();
+        if idle >= self.policy.stall_timeout {
+            return Some(Stall::NoData(idle));
+        }
+        if let Some((min_speed, window)) = self.policy.min_speed {
+            let elapsed = self.window_start.elapsed();
+            if elapsed >= window {
+                let speed = self.window_bytes / elapsed.as_secs().max(1);
+                if speed < min_speed {
+                    return Some(Stall::TooSlow { speed, window });
+                }
+                self.window_start = Instant::now();
+                self.window_bytes = 0;
+            }
+        }
+        None
+    }
+}
+
+/// `--chunk-min-speed`: when worker mode gives up on a chunk's connection
+/// and asks for the rest of it again, on a new one.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkFloor {
+    /// Minimum average speed of one chunk in bytes/s.
+    pub speed: u64,
+    /// How long a chunk may stay below `speed` before it's re-assigned.
+    pub grace: Duration,
+}
+
+/// Tracks one chunk's average speed over each grace period against a
+/// [`ChunkFloor`].
+pub struct FloorMonitor {
+    floor: ChunkFloor,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl FloorMonitor {
+    /// Starts watching a chunk that has `downloaded` bytes so far.
+    pub fn new(floor: ChunkFloor, downloaded: u64) -> Self {
+        Self {
+            floor,
+            window_start: Instant::now(),
+            window_bytes: downloaded,
+        }
+    }
+
+    /// Checks the chunk at `downloaded` bytes. Returns its average speed
+    /// once a whole grace period went by below the floor.
+    pub fn check(&mut self, downloaded: u64) -> Option<u64> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.floor.grace {
+            return None;
+        }
+        let bytes = downloaded.saturating_sub(self.window_bytes);
+        let speed = (bytes as f64 / elapsed.as_secs_f64()) as u64;
+        if speed < self.floor.speed {
+            return Some(speed);
+        }
+        *self = Self::new(self.floor, downloaded);
+        None
+    }
+
+    /// Starts over at `downloaded` bytes, after re-issuing the request: a
+    /// new connection gets the whole grace period to get up to speed.
+    pub fn reset(&mut self, downloaded: u64) {
+        *self = Self::new(self.floor, downloaded);
+    }
+}
+
+/// Progress message suffix for a stream that hasn't received data for
+/// `idle`, shown once it's past half of the stall timeout.
+pub fn stall_hint(idle: Duration, stall_timeout: Duration) -> Option<String> {
+    (idle >= stall_timeout / 2).then(|| format!("stalled {}s", idle.as_secs()))
+}
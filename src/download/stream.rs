@@ -0,0 +1,403 @@
+use crate::download::auth_token::AuthToken;
+use crate::download::chunks::Workers;
+use crate::download::client::ClientOptions;
+use crate::download::error::DownloadError;
+use crate::download::fd_limit;
+use crate::download::http;
+use crate::download::mirrors;
+use crate::download::network_wait;
+use crate::download::options::TransferOptions;
+use crate::download::postprocess::{DownloadOutcome, Pipeline, PostProcessor};
+use crate::download::progress::TransferProgress;
+use crate::download::progress_handle::TransferState;
+use crate::download::retry_budget::RetryBudget;
+use crate::download::stall::MAX_STALL_RESTARTS;
+use crate::download::utils;
+use crate::download::zero_copy;
+use crate::download::{download_file_async, download_with_workers};
+use bytes::Bytes;
+use futures::Stream;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, ReadBuf};
+use tracing::Instrument;
+use url::Url;
+
+/// Entry point for embedding the download engine.
+#[derive(Clone)]
+pub struct Downloader {
+    client: reqwest::Client,
+    /// What `client` was built from, if it was; zero-copy needs to know.
+    client_options: Option<ClientOptions>,
+    target_dir: PathBuf,
+    workers: Workers,
+    zero_copy: bool,
+    options: TransferOptions,
+    post_processing: Pipeline,
+}
+
+impl Downloader {
+    /// A downloader with a default client, saving to the current directory
+    /// over a single stream.
+    pub fn new() -> anyhow::Result<Self> {
+        Self::with_client_options(ClientOptions::default())
+    }
+
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            client_options: None,
+            target_dir: PathBuf::from("."),
+            workers: Workers::Count(1),
+            zero_copy: false,
+            options: TransferOptions::default(),
+            post_processing: Pipeline::default(),
+        }
+    }
+
+    /// A downloader with the client `client_options` builds.
+    pub fn with_client_options(client_options: ClientOptions) -> anyhow::Result<Self> {
+        Ok(Self {
+            client_options: Some(client_options.clone()),
+            ..Self::with_client(client_options.build_async()?)
+        })
+    }
+
+    /// Where [`download`](Self::download) saves, created if needed.
+    pub fn with_target_dir(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = target_dir.into();
+        self
+    }
+
+    /// Workers in worker mode, or 1 for a single stream. A count is cut
+    /// down to what the open-file limit leaves room for.
+    pub fn with_workers(mut self, workers: impl Into<Workers>) -> Self {
+        self.workers = workers.into();
+        self
+    }
+
+    /// Downloads over a single stream with `splice(2)` where it can: plain
+    /// HTTP GETs on Linux, without a proxy or options that need the usual
+    /// client. It needs a downloader made
+    /// [`with_client_options`](Self::with_client_options).
+    pub fn with_zero_copy(mut self, zero_copy: bool) -> Self {
+        self.zero_copy = zero_copy;
+        self
+    }
+
+    /// Resuming, overwriting, the file name, stall handling and the rest.
+    pub fn with_options(mut self, options: TransferOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Runs `step` on every finished download, after the steps added
+    /// before. Unlike [`BlockingDownloader`](crate::BlockingDownloader)'s,
+    /// they don't start with [`Hash`](crate::Hash); add it first if they
+    /// need the SHA-256.
+    pub fn with_post_processor(mut self, step: impl PostProcessor + 'static) -> Self {
+        self.post_processing.push(step);
+        self
+    }
+
+    /// Downloads `url` into the target directory, reporting to `progress`,
+    /// runs the steps added with
+    /// [`with_post_processor`](Self::with_post_processor) on it, and returns
+    /// where it ended up. Draw `progress` with a
+    /// [`Renderer`](crate::download::render::Renderer) or watch its
+    /// [`handle`](TransferProgress::handle); setting its interrupt flag
+    /// cancels the download with [`DownloadError::Interrupted`].
+    ///
+    /// ```no_run
+    /// use download_manager::Downloader;
+    /// use download_manager::{TransferOptions, TransferProgress};
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let path = Downloader::new()?
+    ///     .with_target_dir("downloads")
+    ///     .with_workers(8)
+    ///     .with_options(TransferOptions {
+    ///         resume: true,
+    ///         ..TransferOptions::default()
+    ///     })
+    ///     .download(
+    ///         "https://example.com/file.bin".parse()?,
+    ///         TransferProgress::new(Default::default()),
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download(&self, url: Url, progress: TransferProgress) -> anyhow::Result<PathBuf> {
+        let path = self.transfer(url.clone(), progress.clone()).await?;
+        let mut outcome =
+            DownloadOutcome::new(url, path, progress.snapshot(), self.options.memory.clone());
+        let post_processing = self.post_processing.clone();
+        // The steps hash and move files, which would hold up the runtime.
+        tokio::task::spawn_blocking(move || {
+            post_processing.run(&mut outcome)?;
+            Ok(outcome.path)
+        })
+        .await?
+    }
+
+    /// [`download`](Self::download) without the steps after the transfer.
+    async fn transfer(&self, url: Url, progress: TransferProgress) -> anyhow::Result<PathBuf> {
+        utils::prepare_target_dir(&self.target_dir)?;
+        progress.set_retry_budget(&self.options.retry_budget);
+        let workers = match self.workers {
+            Workers::Count(count) if count > 1 => Workers::Count(fd_limit::cap_workers(count)),
+            workers => workers,
+        };
+        if !workers.splits() {
+            if let Some(path) = self.download_zero_copy(&url, &progress).await? {
+                return Ok(path);
+            }
+            return download_file_async(
+                &self.client,
+                url,
+                &self.target_dir,
+                progress,
+                &self.options,
+            )
+            .await;
+        }
+        // The length is probed before the workers start, so the progress
+        // shows each step.
+        let probe = network_wait::retry(&url, &self.options, &progress, || {
+            mirrors::content_length(
+                &self.client,
+                &url,
+                &self.options.mirrors,
+                &self.options.retry_budget,
+                self.options.auth_token.as_deref(),
+            )
+        });
+        progress.set_total(progress.preflight(probe).await?);
+        download_with_workers(
+            &self.client,
+            url,
+            &self.target_dir,
+            workers,
+            progress,
+            &self.options,
+        )
+        .await
+    }
+
+    /// Downloads `url` with `splice(2)` if asked to and it can be, or
+    /// returns `None` for the usual download to take over.
+    async fn download_zero_copy(
+        &self,
+        url: &Url,
+        progress: &TransferProgress,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        if !self.zero_copy {
+            return Ok(None);
+        }
+        let Some(client_options) = &self.client_options else {
+            tracing::info!(
+                "Not using --zero-copy: it needs to know what the client was built from"
+            );
+            return Ok(None);
+        };
+        if let Some(reason) = zero_copy::unavailable(url, client_options, &self.options) {
+            tracing::info!("Not using --zero-copy: {reason}");
+            return Ok(None);
+        }
+        let download = zero_copy::download_zero_copy(
+            url.clone(),
+            &self.target_dir,
+            progress.clone(),
+            client_options,
+            &self.options,
+        );
+        download.await
+    }
+
+    /// Exposes `url` as an [`AsyncRead`], for feeding a download straight
+    /// into another consumer without touching the filesystem.
+    ///
+    /// Nothing is requested until the first read. When the connection fails
+    /// mid-body, the remainder is re-requested with a `Range` at the current
+    /// offset, transparently to the reader. Dropping the reader cancels the
+    /// transfer.
+    ///
+    /// Copying into a file gives the same result as `download_file_async`:
+    ///
+    /// ```no_run
+    /// use download_manager::Downloader;
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let url = "https://example.com/file.bin".parse()?;
+    /// let mut reader = Downloader::new()?.open_stream(url);
+    /// let mut file = tokio::fs::File::create("file.bin").await?;
+    /// tokio::io::copy(&mut reader, &mut file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open_stream(&self, url: Url) -> DownloadStream {
+        let progress = TransferProgress::new(Arc::new(AtomicBool::new(false)));
+        progress.set_retry_budget(&self.options.retry_budget);
+        DownloadStream {
+            client: self.client.clone(),
+            url,
+            budget: self.options.retry_budget.clone(),
+            auth: self.options.auth_token.clone(),
+            progress,
+            state: State::Idle,
+            buffer: Bytes::new(),
+            received: 0,
+            total: None,
+            restarts: 0,
+        }
+    }
+}
+
+type Connecting = Pin<Box<dyn Future<Output = anyhow::Result<reqwest::Response>> + Send>>;
+type Body = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+enum State {
+    Idle,
+    Connecting(Connecting),
+    Streaming(Body),
+    Done,
+}
+
+/// A download exposed as an [`AsyncRead`], see [`Downloader::open_stream`].
+pub struct DownloadStream {
+    client: reqwest::Client,
+    url: Url,
+    budget: Arc<RetryBudget>,
+    auth: Option<Arc<AuthToken>>,
+    progress: TransferProgress,
+    state: State,
+    /// Received but not yet read.
+    buffer: Bytes,
+    /// Bytes received from the network, i.e. where a restart resumes.
+    received: u64,
+    total: Option<u64>,
+    restarts: usize,
+}
+
+impl DownloadStream {
+    /// Progress counters, updated as bytes arrive. Use
+    /// [`TransferProgress::handle`] to await changes instead of polling.
+    pub fn progress(&self) -> TransferProgress {
+        self.progress.clone()
+    }
+
+    fn connect(&self) -> Connecting {
+        let offset = self.received;
+        let mut request = self.client.get(self.url.clone());
+        if offset > 0 {
+            request = request.headers(http::range_headers(&format!("bytes={offset}-")));
+        }
+        let budget = self.budget.clone();
+        let auth = self.auth.clone();
+        Box::pin(async move {
+            let response = http::send_retrying(request, None, &budget, auth.as_deref()).await?;
+            if offset == 0 {
+                return http::check_status(response).await;
+            }
+            match response.status() {
+                reqwest::StatusCode::PARTIAL_CONTENT => {
+                    http::check_identity(response.headers())?;
+                    Ok(response)
+                }
+                reqwest::StatusCode::OK => Err(DownloadError::RangesUnsupported {
+                    needed_for: "restart the stream",
+                }
+                .into()),
+                _ => Err(http::unexpected_status(response).await.into()),
+            }
+        })
+    }
+
+    fn fail(&mut self, error: impl std::fmt::Display) -> io::Error {
+        self.state = State::Done;
+        let error = error.to_string();
+        self.progress
+            .reporter()
+            .finish(TransferState::Failed(error.clone()));
+        io::Error::other(error)
+    }
+
+    /// Re-requests from the current offset after a failure, or gives up
+    /// with `error` once the restarts are used up.
+    fn restart(&mut self, error: impl std::fmt::Display) -> io::Result<()> {
+        self.restarts += 1;
+        if self.restarts > MAX_STALL_RESTARTS {
+            return Err(self.fail(format!(
+                "{error}, giving up after {MAX_STALL_RESTARTS} restarts"
+            )));
+        }
+        if let Err(exhausted) = self.budget.spend(None, self.restarts, &error.to_string()) {
+            return Err(self.fail(exhausted));
+        }
+        tracing::info!("{error}, re-requesting from byte {}", self.received);
+        let retry = tracing::trace_span!(
+            "retry",
+            attempt = self.restarts,
+            reason = %error,
+            resume_at = self.received
+        );
+        self.state = State::Connecting(Box::pin(self.connect().instrument(retry)));
+        Ok(())
+    }
+}
+
+impl AsyncRead for DownloadStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if !this.buffer.is_empty() {
+                let len = this.buffer.len().min(buf.remaining());
+                buf.put_slice(&this.buffer.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+            match &mut this.state {
+                State::Idle => this.state = State::Connecting(this.connect()),
+                State::Connecting(connecting) => match ready!(connecting.as_mut().poll(cx)) {
+                    Ok(response) => {
+                        if this.total.is_none() {
+                            this.total = response.content_length();
+                            this.progress.set_total(this.total.unwrap_or(0));
+                        }
+                        this.state = State::Streaming(Box::pin(response.bytes_stream()));
+                    }
+                    // Failing to connect at all isn't worth retrying.
+                    Err(error) if this.received == 0 => {
+                        return Poll::Ready(Err(this.fail(format!("{error:#}"))));
+                    }
+                    Err(error) => this.restart(error)?,
+                },
+                State::Streaming(body) => match ready!(body.as_mut().poll_next(cx)) {
+                    Some(Ok(bytes)) => {
+                        this.received += bytes.len() as u64;
+                        this.progress.set_downloaded(this.received as usize);
+                        this.buffer = bytes;
+                    }
+                    Some(Err(error)) => this.restart(error)?,
+                    None if this.total.is_some_and(|total| this.received < total) => {
+                        this.restart("Connection closed early")?
+                    }
+                    None => {
+                        this.state = State::Done;
+                        this.progress.reporter().finish(TransferState::Completed);
+                    }
+                },
+                State::Done => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
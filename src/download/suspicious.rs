@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Files smaller than this are suspicious when their name promises a binary
+/// and the server didn't say they'd be this small.
+pub const MIN_BINARY_SIZE: u64 = 4096;
+
+/// How much of a suspicious file [`preview`] shows.
+pub const PREVIEW_BYTES: usize = 300;
+
+/// Extensions of files that are never HTML and rarely this small.
+const BINARY_EXTENSIONS: [&str; 29] = [
+    "7z", "apk", "bin", "bz2", "deb", "dmg", "exe", "gz", "img", "iso", "jar", "jpg", "jpeg",
+    "mkv", "mov", "mp3", "mp4", "msi", "parquet", "pdf", "pkg", "png", "qcow2", "rar", "rpm",
+    "tar", "tgz", "whl", "xz",
+];
+
+/// Checks whether a finished download looks like an error or login page
+/// served with a 200 rather than the file, returning why if so.
+///
+/// `expected` is the size the server announced and `content_type` the type
+/// it sent, when known.
+pub fn inspect(
+    path: &Path,
+    expected: Option<u64>,
+    content_type: Option<&str>,
+) -> io::Result<Option<String>> {
+    let size = std::fs::metadata(path)?.len();
+    if let Some(expected) = expected
+        && size.saturating_mul(2) < expected
+    {
+        return Ok(Some(format!(
+            "got {size} bytes, but the server announced {expected}"
+        )));
+    }
+
+    let Some(extension) = binary_extension(path) else {
+        return Ok(None);
+    };
+    let served_html =
+        content_type.is_some_and(|content_type| content_type.trim_start().starts_with("text/html"));
+    if served_html {
+        return Ok(Some(format!(
+            "served as text/html, but named like a .{extension} file"
+        )));
+    }
+    if looks_like_html(&head(path, 64)?) {
+        return Ok(Some(format!(
+            "starts like an HTML page, but named like a .{extension} file"
+        )));
+    }
+    if size < MIN_BINARY_SIZE && expected.is_none_or(|expected| expected > size) {
+        return Ok(Some(format!("only {size} bytes for a .{extension} file")));
+    }
+    Ok(None)
+}
+
+/// The first [`PREVIEW_BYTES`] of `path`, with anything unprintable escaped.
+pub fn preview(path: &Path) -> io::Result<String> {
+    Ok(head(path, PREVIEW_BYTES)?.escape_ascii().to_string())
+}
+
+fn binary_extension(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    BINARY_EXTENSIONS
+        .contains(&extension.as_str())
+        .then_some(extension)
+}
+
+fn looks_like_html(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(head)
+        .trim_start()
+        .to_ascii_lowercase();
+    text.starts_with("<!doctype html") || text.starts_with("<html")
+}
+
+fn head(path: &Path, length: usize) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(length);
+    File::open(path)?
+        .take(length as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
@@ -0,0 +1,106 @@
+use crate::download::error::DownloadError;
+use crate::download::fs_ops;
+use crate::download::http;
+use crate::download::options::TransferOptions;
+use crate::download::target_wait;
+use crate::download::utils;
+use anyhow::bail;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// Downloads only the last `length` bytes of `url` with a suffix range
+/// (`Range: bytes=-N`), e.g. to inspect a zip central directory or a parquet
+/// footer without fetching the whole file.
+///
+/// The result goes to `<name>.tail` unless `--output` names a file. The
+/// total size of the remote file is taken from the `Content-Range` header
+/// and printed. Servers that ignore the range are rejected before the body
+/// is read, rather than silently downloading everything.
+pub async fn download_tail(
+    client: &reqwest::Client,
+    url: Url,
+    target_dir: &Path,
+    length: u64,
+    options: &TransferOptions,
+) -> anyhow::Result<PathBuf> {
+    if length == 0 {
+        bail!("--tail needs at least one byte");
+    }
+    let fname = match &options.output {
+        Some(output) => utils::long_path(target_dir.join(output)),
+        None => {
+            let mut fname = utils::build_download_path(&url, target_dir).into_os_string();
+            fname.push(".tail");
+            utils::long_path(PathBuf::from(fname))
+        }
+    };
+    if fname.exists() && !options.overwrite {
+        return Err(DownloadError::FileExists { path: fname }.into());
+    }
+
+    let request = client
+        .get(url.clone())
+        .headers(http::range_headers(&format!("bytes=-{length}")));
+    let response = http::send_retrying(
+        request,
+        None,
+        &options.retry_budget,
+        options.auth_token.as_deref(),
+    )
+    .await?;
+    let range = match response.status().as_u16() {
+        206 => {
+            http::check_identity(response.headers())?;
+            http::content_range(&response)?
+        }
+        200 => bail!(
+            "Server ignored the suffix range and would send the whole file, refusing to download it"
+        ),
+        416 => bail!("Server cannot serve the last {length} bytes (416 Range Not Satisfiable)"),
+        _ => return Err(http::unexpected_status(response).await.into()),
+    };
+    // A suffix range always ends at the last byte, and covers the whole file
+    // when it's shorter than requested.
+    if let Some(total) = range.total
+        && (range.last + 1 != total || range.size() != length.min(total))
+    {
+        bail!(
+            "Server answered with bytes {}-{}/{total}, which is not the last {length} bytes",
+            range.first,
+            range.last
+        );
+    }
+    match range.total {
+        Some(total) => println!(
+            "Remote file size: {} ({total} bytes)",
+            indicatif::HumanBytes(total)
+        ),
+        None => println!("Remote file size: unknown"),
+    }
+
+    let mut open = OpenOptions::new();
+    open.create(true).write(true).truncate(true);
+    let mut dest =
+        target_wait::retry_async("create", &fname, || fs_ops::open_async(&open, &fname)).await?;
+    let mut downloaded = 0;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        dest.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+    }
+    dest.flush().await?;
+    if downloaded != range.size() {
+        bail!("Expected {} bytes but received {downloaded}", range.size());
+    }
+    println!(
+        "Downloaded the last {} (bytes {}-{})",
+        indicatif::HumanBytes(downloaded),
+        range.first,
+        range.last
+    );
+    Ok(fname)
+}
@@ -0,0 +1,96 @@
+//! `--wait-for-target`: retrying opens and renames in a target directory
+//! that's still appearing. An NFS automount or a container volume can be
+//! created before it's ready, answering ENOENT, EACCES or ESTALE for a few
+//! seconds after `create_dir_all` succeeded.
+
+use crate::download::diagnostics::{self, WarningId};
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Tries made, spread evenly over the wait.
+pub const ATTEMPTS: u32 = 5;
+
+/// The wait in milliseconds, zero for none.
+static WAIT: AtomicU64 = AtomicU64::new(0);
+
+/// Sets how long to keep retrying, `None` to fail at once, as by default.
+pub fn set_wait(wait: Option<Duration>) {
+    let millis = wait.map_or(0, |wait| wait.as_millis() as u64);
+    WAIT.store(millis, Ordering::Relaxed);
+}
+
+/// Runs `operation` on `path`, waiting and running it again while it fails
+/// with an error a mount that isn't ready gives. `what` says what it does,
+/// as in "open".
+pub(crate) fn retry<T>(
+    what: &str,
+    path: &Path,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        let error = match operation() {
+            Err(error) => error,
+            result => return result,
+        };
+        match pause(what, path, &error, attempt) {
+            Some(pause) => std::thread::sleep(pause),
+            None => return Err(error),
+        }
+        attempt += 1;
+    }
+}
+
+/// [`retry`] for async operations.
+pub(crate) async fn retry_async<T, F>(
+    what: &str,
+    path: &Path,
+    mut operation: impl FnMut() -> F,
+) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match operation().await {
+            Err(error) => error,
+            result => return result,
+        };
+        match pause(what, path, &error, attempt) {
+            Some(pause) => tokio::time::sleep(pause).await,
+            None => return Err(error),
+        }
+        attempt += 1;
+    }
+}
+
+/// How long to wait before trying again after `attempt` failed with
+/// `error`, or `None` to give up.
+fn pause(what: &str, path: &Path, error: &io::Error, attempt: u32) -> Option<Duration> {
+    let wait = Duration::from_millis(WAIT.load(Ordering::Relaxed));
+    if wait.is_zero() || attempt >= ATTEMPTS || !transient(error) {
+        return None;
+    }
+    let pause = wait / (ATTEMPTS - 1);
+    diagnostics::warn(
+        WarningId::TargetBusy,
+        format!(
+            "Cannot {what} '{}' ({error}), trying again in {:.1}s ({attempt}/{ATTEMPTS})",
+            path.display(),
+            pause.as_secs_f64()
+        ),
+    );
+    Some(pause)
+}
+
+fn transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
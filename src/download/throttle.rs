@@ -0,0 +1,212 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a paused transfer checks whether it was resumed.
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+/// Pauses and rate-limits a download, shared by all its workers and
+/// adjustable while it runs (e.g. from `--control-socket`).
+///
+/// The rate limit is a token bucket holding at most one second's worth of
+/// bytes, so a resumed or newly limited transfer can't burst past it.
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    paused: AtomicBool,
+    /// Bytes per second, zero for unlimited.
+    rate: AtomicU64,
+    /// Bumped on every change, so waits worked out under old settings end.
+    generation: AtomicU64,
+    bucket: Mutex<Bucket>,
+    /// Per-chunk caps from `--fair-workers`, on top of the rate limit.
+    caps: Mutex<Vec<Option<Cap>>>,
+}
+
+#[derive(Debug)]
+struct Cap {
+    /// Bytes per second.
+    rate: f64,
+    bucket: Bucket,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative when workers have taken more than the rate allows so far.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            tokens: 0.0,
+            refilled: Instant::now(),
+        }
+    }
+}
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Self {
+        let throttle = Self::default();
+        throttle.set_rate(rate);
+        throttle
+    }
+
+    pub fn pause(&self) {
+        self.inner.paused.store(true, Ordering::SeqCst);
+        self.changed();
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.store(false, Ordering::SeqCst);
+        self.changed();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::SeqCst)
+    }
+
+    /// Limits the transfer to `rate` bytes/s, or lifts the limit.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        *self.inner.bucket.lock().unwrap() = Bucket::default();
+        self.inner.rate.store(rate.unwrap_or(0), Ordering::SeqCst);
+        self.changed();
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        Some(self.inner.rate.load(Ordering::SeqCst)).filter(|rate| *rate > 0)
+    }
+
+    /// Waits while paused, then for as long as passing `bytes` on has to be
+    /// held back to stay under the rate limit.
+    pub async fn take(&self, bytes: usize) {
+        let mut wait = Wait::new(self, bytes);
+        while let Some(nap) = wait.next() {
+            tokio::time::sleep(nap).await;
+        }
+    }
+
+    /// [`Throttle::take`] for the blocking client.
+    pub fn take_blocking(&self, bytes: usize) {
+        let mut wait = Wait::new(self, bytes);
+        while let Some(nap) = wait.next() {
+            std::thread::sleep(nap);
+        }
+    }
+
+    /// Caps each chunk at its rate in bytes/s, `None` leaving it uncapped;
+    /// an empty slice lifts every cap.
+    pub fn cap_chunks(&self, caps: &[Option<u64>]) {
+        *self.inner.caps.lock().unwrap() = caps
+            .iter()
+            .map(|rate| {
+                rate.map(|rate| Cap {
+                    rate: rate.max(1) as f64,
+                    bucket: Bucket::default(),
+                })
+            })
+            .collect();
+        self.changed();
+    }
+
+    /// The chunk caps in force, see [`cap_chunks`](Self::cap_chunks).
+    pub fn chunk_caps(&self) -> Vec<Option<u64>> {
+        self.inner
+            .caps
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|cap| cap.as_ref().map(|cap| cap.rate as u64))
+            .collect()
+    }
+
+    /// [`Throttle::take`] for chunk `chunk_id`, then for as long as its own
+    /// cap holds it back.
+    pub async fn take_chunk(&self, chunk_id: usize, bytes: usize) {
+        self.take(bytes).await;
+        let Some(nap) = self.reserve_chunk(chunk_id, bytes) else {
+            return;
+        };
+        let deadline = Instant::now() + nap;
+        let generation = self.generation();
+        // Caps are reworked every few seconds; stop waiting on an old one.
+        while self.generation() == generation {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            tokio::time::sleep(left.min(PAUSE_POLL)).await;
+        }
+    }
+
+    fn reserve_chunk(&self, chunk_id: usize, bytes: usize) -> Option<Duration> {
+        let mut caps = self.inner.caps.lock().unwrap();
+        let cap = caps.get_mut(chunk_id)?.as_mut()?;
+        let now = Instant::now();
+        let refill = now.duration_since(cap.bucket.refilled).as_secs_f64() * cap.rate;
+        cap.bucket.tokens = (cap.bucket.tokens + refill).min(cap.rate) - bytes as f64;
+        cap.bucket.refilled = now;
+        (cap.bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-cap.bucket.tokens / cap.rate))
+    }
+
+    fn changed(&self) {
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::SeqCst)
+    }
+
+    fn reserve(&self, bytes: usize) -> Option<Duration> {
+        let rate = self.rate()? as f64;
+        let mut bucket = self.inner.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate) - bytes as f64;
+        bucket.refilled = now;
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / rate))
+    }
+}
+
+/// One [`Throttle::take`], as naps of at most [`PAUSE_POLL`] so a resume,
+/// a new rate or a cancel takes effect right away.
+struct Wait<'a> {
+    throttle: &'a Throttle,
+    bytes: usize,
+    deadline: Option<Instant>,
+    generation: u64,
+}
+
+impl<'a> Wait<'a> {
+    fn new(throttle: &'a Throttle, bytes: usize) -> Self {
+        Self {
+            throttle,
+            bytes,
+            deadline: None,
+            generation: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<Duration> {
+        if self.throttle.is_paused() {
+            return Some(PAUSE_POLL);
+        }
+        let deadline = match self.deadline {
+            // Don't sit out a delay worked out under the old settings.
+            Some(_) if self.throttle.generation() != self.generation => return None,
+            Some(deadline) => deadline,
+            None => {
+                self.generation = self.throttle.generation();
+                let deadline = Instant::now() + self.throttle.reserve(self.bytes)?;
+                *self.deadline.insert(deadline)
+            }
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        (!left.is_zero()).then(|| left.min(PAUSE_POLL))
+    }
+}
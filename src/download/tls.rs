@@ -0,0 +1,427 @@
+//! `--min-tls`, and what TLS a download's host negotiates. The HTTP
+//! clients speak TLS through rustls as [`client_config`] sets it up: they
+//! offer nothing older than `--min-tls`, check certificates against the
+//! system's trusted ones, and record the version and certificate chain
+//! their connections get in the download's [`SessionLog`], for the
+//! summary. rustls doesn't tell reqwest which cipher suite it agreed on,
+//! so that isn't reported.
+//!
+//! A host that can't meet `--min-tls`, or whose certificate isn't trusted,
+//! fails the download's first connection, before any request; [`explain`]
+//! turns that into an error that says why.
+
+use crate::download::error::DownloadError;
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{AlertDescription, ClientConfig, DigitallySignedStruct, RootCertStore};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The system's trusted certificates, loaded the first time.
+static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+
+/// `--min-tls`: the oldest TLS version a download may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    V1_2,
+    V1_3,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "1.2" => Ok(TlsVersion::V1_2),
+            "1.3" => Ok(TlsVersion::V1_3),
+            _ => Err("it's 1.2 or 1.3".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TlsVersion::V1_2 => "TLS 1.2",
+            TlsVersion::V1_3 => "TLS 1.3",
+        })
+    }
+}
+
+impl TlsVersion {
+    /// This version, as reqwest's client builders take it.
+    pub fn client_minimum(self) -> reqwest::tls::Version {
+        match self {
+            TlsVersion::V1_2 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::V1_3 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+
+    /// The versions rustls offers with this one as the oldest.
+    fn and_newer(self) -> &'static [&'static rustls::SupportedProtocolVersion] {
+        static FROM_1_2: [&rustls::SupportedProtocolVersion; 2] =
+            [&rustls::version::TLS13, &rustls::version::TLS12];
+        static FROM_1_3: [&rustls::SupportedProtocolVersion; 1] = [&rustls::version::TLS13];
+        match self {
+            TlsVersion::V1_2 => &FROM_1_2,
+            TlsVersion::V1_3 => &FROM_1_3,
+        }
+    }
+}
+
+/// What a host's TLS handshake negotiated.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TlsSession {
+    pub host: String,
+    /// As in `TLS 1.3`.
+    pub version: String,
+    /// The certificates the server sent, its own first.
+    pub chain: Vec<Certificate>,
+}
+
+/// One certificate of a [`TlsSession`]'s chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Certificate {
+    /// Its subject's common name, or organization without one.
+    pub subject: String,
+    /// Its issuer's, likewise.
+    pub issuer: String,
+    /// The day it expires, as `2026-10-16`.
+    pub not_after: String,
+}
+
+impl fmt::Display for TlsSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.version)?;
+        if let Some(leaf) = self.chain.first() {
+            write!(
+                f,
+                "; certificate for {} issued by {}, expiring {}",
+                leaf.subject, leaf.issuer, leaf.not_after
+            )?;
+        }
+        match self.chain.len() {
+            0 | 1 => Ok(()),
+            n => write!(f, " ({n} certificates in the chain)"),
+        }
+    }
+}
+
+/// What the connections of a download's HTTP clients negotiated. Its
+/// [`ClientOptions`] and [`TransferOptions`] share it, as they do the
+/// resolver, so the transfer can report it.
+///
+/// [`ClientOptions`]: crate::download::client::ClientOptions
+/// [`TransferOptions`]: crate::download::options::TransferOptions
+#[derive(Clone, Debug, Default)]
+pub struct SessionLog {
+    inner: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    /// The chains connections were shown, until their signature shows
+    /// which version they speak.
+    presented: Vec<Presented>,
+    negotiated: Option<TlsSession>,
+}
+
+impl SessionLog {
+    /// The session the last full handshake negotiated. A resumed one
+    /// shows no chain, so it leaves the session it resumed as it is.
+    pub fn negotiated(&self) -> Option<TlsSession> {
+        self.inner.lock().unwrap().negotiated.clone()
+    }
+
+    /// Keeps a chain a connection was shown, until
+    /// [`record`](Self::record) knows its version.
+    fn present(&self, presented: Presented) {
+        self.inner.lock().unwrap().presented.push(presented);
+    }
+
+    /// Makes the connection shown the chain `leaf` starts, now it's known
+    /// to speak `version`, the one [`negotiated`](Self::negotiated)
+    /// reports.
+    fn record(&self, leaf: &[u8], version: TlsVersion) {
+        let mut recorded = self.inner.lock().unwrap();
+        let Some(index) = recorded
+            .presented
+            .iter()
+            .position(|shown| shown.leaf == leaf)
+        else {
+            return;
+        };
+        let Presented { host, chain, .. } = recorded.presented.swap_remove(index);
+        recorded.negotiated = Some(TlsSession {
+            host,
+            version: version.to_string(),
+            chain,
+        });
+    }
+}
+
+/// The TLS the HTTP clients speak: `min` and newer, or TLS 1.2 and newer
+/// without it, trusting what the system does. Each connection's version
+/// and chain are recorded in `log`.
+pub fn client_config(min: Option<TlsVersion>, log: &SessionLog) -> io::Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let verifier = Recording(verifier(provider.clone())?, log.clone());
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(min.unwrap_or(TlsVersion::V1_2).and_newer())
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// Checks certificates against the system's trusted ones, as
+/// `SSL_CERT_FILE` and `SSL_CERT_DIR` can point elsewhere.
+fn verifier(provider: Arc<CryptoProvider>) -> io::Result<Arc<WebPkiServerVerifier>> {
+    let roots = match ROOTS.get() {
+        Some(roots) => roots.clone(),
+        None => {
+            let found = rustls_native_certs::load_native_certs();
+            for error in &found.errors {
+                tracing::debug!("Cannot load the trusted certificates: {error}");
+            }
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(found.certs);
+            if roots.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no trusted certificates found on this system",
+                ));
+            }
+            ROOTS.get_or_init(|| Arc::new(roots)).clone()
+        }
+    };
+    WebPkiServerVerifier::builder_with_provider(roots, provider)
+        .build()
+        .map_err(io::Error::other)
+}
+
+/// The handshake rustls refused that `error` comes down to, if it does.
+/// Asking again gets the same answer, so it isn't retried.
+pub fn refusal<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a rustls::Error> {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if let Some(refused) = error.downcast_ref::<rustls::Error>() {
+            return Some(refused);
+        }
+        // An io::Error's `source` skips the error it wraps.
+        if let Some(refused) = error
+            .downcast_ref::<io::Error>()
+            .and_then(|error| error.get_ref())
+            .and_then(|inner| refusal(inner))
+        {
+            return Some(refused);
+        }
+        cause = error.source();
+    }
+    None
+}
+
+/// With `--min-tls`, turns a download `error` that comes down to a refused
+/// handshake into [`DownloadError::TlsPolicy`], naming the host and why.
+pub fn explain(error: anyhow::Error, min: Option<TlsVersion>) -> anyhow::Error {
+    let Some(min) = min else {
+        return error;
+    };
+    if error.downcast_ref::<DownloadError>().is_some() {
+        return error;
+    }
+    let Some(refused) = error.chain().find_map(refusal) else {
+        return error;
+    };
+    let host = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .and_then(|request| request.url())
+        .and_then(|url| url.host_str())
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let reason = match refused_version(refused) {
+        true => format!("it speaks no version that new ({refused})"),
+        false => format!("the handshake failed: {refused}"),
+    };
+    let policy = DownloadError::TlsPolicy { host, min, reason };
+    error.context(policy)
+}
+
+/// Whether the handshake failed for want of a version both sides speak.
+fn refused_version(error: &rustls::Error) -> bool {
+    matches!(
+        error,
+        rustls::Error::PeerIncompatible(_)
+            | rustls::Error::AlertReceived(
+                AlertDescription::ProtocolVersion | AlertDescription::HandshakeFailure
+            )
+    )
+}
+
+/// Checks what a connection of the HTTP clients is shown as webpki does,
+/// and records it in the log: the chain once it's trusted, and the version
+/// by which of the two signature checks the handshake asks for.
+#[derive(Debug)]
+struct Recording(Arc<WebPkiServerVerifier>, SessionLog);
+
+impl ServerCertVerifier for Recording {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.0.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let chain = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|certificate| summarize(certificate))
+            .collect();
+        self.1.present(Presented {
+            leaf: end_entity.to_vec(),
+            host: server_name.to_str().into_owned(),
+            chain,
+        });
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let valid = self.0.verify_tls12_signature(message, cert, dss)?;
+        self.1.record(cert, TlsVersion::V1_2);
+        Ok(valid)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let valid = self.0.verify_tls13_signature(message, cert, dss)?;
+        self.1.record(cert, TlsVersion::V1_3);
+        Ok(valid)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// A chain a connection was shown.
+#[derive(Debug)]
+struct Presented {
+    /// Its first certificate, as DER.
+    leaf: Vec<u8>,
+    host: String,
+    chain: Vec<Certificate>,
+}
+
+/// The subject, issuer and expiry of a DER certificate.
+fn summarize(certificate: &[u8]) -> Option<Certificate> {
+    let (_, certificate, _) = der(certificate)?;
+    let (_, mut tbs, _) = der(certificate)?;
+    // The version, when it's there, is tagged [0].
+    if tbs.first() == Some(&0xA0) {
+        tbs = der(tbs)?.2;
+    }
+    let (_, _serial, rest) = der(tbs)?;
+    let (_, _algorithm, rest) = der(rest)?;
+    let (_, issuer, rest) = der(rest)?;
+    let (_, validity, rest) = der(rest)?;
+    let (_, subject, _) = der(rest)?;
+    let (_, _not_before, validity) = der(validity)?;
+    let (tag, not_after, _) = der(validity)?;
+    Some(Certificate {
+        subject: name(subject),
+        issuer: name(issuer),
+        not_after: date(tag, not_after)?,
+    })
+}
+
+/// The tag, contents and what follows of the DER value `input` starts
+/// with.
+fn der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let length = match first {
+        length @ 0..=0x7F => length as usize,
+        0x81..=0x84 => {
+            let bytes = (first & 0x7F) as usize;
+            let (length, after) = rest.split_at_checked(bytes)?;
+            rest = after;
+            length
+                .iter()
+                .fold(0usize, |length, byte| length << 8 | *byte as usize)
+        }
+        _ => return None,
+    };
+    let (contents, rest) = rest.split_at_checked(length)?;
+    Some((tag, contents, rest))
+}
+
+/// The common name in an X.509 name, or else its organization.
+fn name(mut name: &[u8]) -> String {
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    const ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0A];
+    let mut attributes = Vec::new();
+    while let Some((_, set, rest)) = der(name) {
+        name = rest;
+        let mut set = set;
+        while let Some((_, attribute, rest)) = der(set) {
+            set = rest;
+            if let Some((_, oid, value)) = der(attribute)
+                && let Some((_, value, _)) = der(value)
+            {
+                attributes.push((oid, String::from_utf8_lossy(value).into_owned()));
+            }
+        }
+    }
+    [COMMON_NAME, ORGANIZATION]
+        .iter()
+        .find_map(|wanted| {
+            attributes
+                .iter()
+                .find(|(oid, _)| oid == wanted)
+                .map(|(_, value)| value.clone())
+        })
+        .unwrap_or_else(|| "(no name)".to_string())
+}
+
+/// `2026-10-16`, from a UTCTime (tag 0x17) or GeneralizedTime (0x18).
+fn date(tag: u8, time: &[u8]) -> Option<String> {
+    let time = std::str::from_utf8(time).ok()?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: u32 = time.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        0x18 => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    Some(format!("{year}-{}-{}", rest.get(..2)?, rest.get(2..4)?))
+}
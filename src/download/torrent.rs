@@ -0,0 +1,314 @@
+use crate::download::error::DownloadError;
+use crate::download::http;
+use reqwest::header::{self, HeaderMap};
+use std::path::Path;
+use std::str::FromStr;
+use url::Url;
+
+#[cfg(feature = "torrent")]
+use crate::download::auth_token::AuthToken;
+#[cfg(feature = "torrent")]
+use crate::download::retry_budget::RetryBudget;
+#[cfg(feature = "torrent")]
+use crate::download::{bencode, speed::Size};
+#[cfg(feature = "torrent")]
+use anyhow::{Context, bail};
+#[cfg(feature = "torrent")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What servers send `.torrent` files as.
+pub const CONTENT_TYPE: &str = "application/x-bittorrent";
+
+/// The largest `.torrent` file read for `--torrent webseeds`. Piece hashes
+/// for a 100 GiB file in 256 KiB pieces take 8 MiB.
+#[cfg(feature = "torrent")]
+pub const MAX_TORRENT: u64 = 16 * 1024 * 1024;
+
+/// `--torrent`: what to do with a URL that points at a torrent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TorrentMode {
+    /// Download the content from the torrent's HTTP seeds instead.
+    #[cfg(feature = "torrent")]
+    Webseeds,
+    /// Keep the `.torrent` file itself.
+    Save,
+}
+
+impl FromStr for TorrentMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "webseeds" if !cfg!(feature = "torrent") => {
+                Err("webseeds needs dlm built with the torrent feature".to_string())
+            }
+            #[cfg(feature = "torrent")]
+            "webseeds" => Ok(TorrentMode::Webseeds),
+            "save" => Ok(TorrentMode::Save),
+            other => Err(format!("unknown mode '{other}', expected webseeds or save")),
+        }
+    }
+}
+
+pub fn is_magnet(url: &Url) -> bool {
+    url.scheme() == "magnet"
+}
+
+/// Whether `url`'s path names a `.torrent` file.
+pub fn is_torrent_url(url: &Url) -> bool {
+    url.path().to_ascii_lowercase().ends_with(".torrent")
+}
+
+/// The refusal for a magnet link, pointing at the HTTP seeds (`ws=`) it
+/// names, if any, which can be downloaded directly.
+pub fn refuse_magnet(url: &Url) -> DownloadError {
+    let seeds: Vec<_> = url
+        .query_pairs()
+        .filter(|(key, _)| key == "ws")
+        .map(|(_, seed)| seed.into_owned())
+        .collect();
+    let message = match seeds.first() {
+        Some(seed) => format!(
+            "Magnet links need a BitTorrent client, which dlm is not. This one names an HTTP seed, which can be downloaded directly, unverified: dlm '{seed}' download-async"
+        ),
+        None => "Magnet links need a BitTorrent client, which dlm is not".to_string(),
+    };
+    DownloadError::BitTorrent { message }
+}
+
+/// The refusal for a `.torrent` URL given without `--torrent`, when what's
+/// in it isn't known.
+pub fn refuse_torrent_url(url: &Url) -> DownloadError {
+    DownloadError::BitTorrent {
+        message: format!(
+            "'{}' is a .torrent file, and dlm is not a BitTorrent client. Open it in one, or {}",
+            http::redact_url(url),
+            flags_hint()
+        ),
+    }
+}
+
+/// Refuses a response served as a torrent before anything is written to
+/// `path`, unless `--torrent save` asked for it.
+pub fn check_content_type(path: &Path, headers: &HeaderMap, save: bool) -> anyhow::Result<()> {
+    let is_torrent = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case(CONTENT_TYPE));
+    if !is_torrent || save {
+        return Ok(());
+    }
+    Err(DownloadError::BitTorrent {
+        message: format!(
+            "Not writing '{}': the server sent a torrent ({CONTENT_TYPE}), and dlm is not a BitTorrent client. Open it in one, or {}",
+            path.display(),
+            flags_hint()
+        ),
+    }
+    .into())
+}
+
+fn flags_hint() -> &'static str {
+    if cfg!(feature = "torrent") {
+        "pass --torrent webseeds to download its content from the HTTP seeds it lists, or --torrent save to keep the .torrent file itself"
+    } else {
+        "pass --torrent save to keep the .torrent file itself"
+    }
+}
+
+/// The parts of a single-file torrent that downloading it over HTTP needs.
+#[cfg(feature = "torrent")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Torrent {
+    /// The file's name, checked to be a plain name with no directories.
+    pub name: String,
+    pub length: u64,
+    pub piece_length: u64,
+    /// SHA-1 of every piece, in order.
+    pub pieces: Vec<[u8; 20]>,
+    /// The `url-list` HTTP seeds (BEP 19). Only http and https ones are kept.
+    pub webseeds: Vec<Url>,
+}
+
+#[cfg(feature = "torrent")]
+impl Torrent {
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let root = bencode::parse(bytes)?;
+        let info = root.get("info").context("no info dictionary")?;
+        let name = info
+            .get("name")
+            .and_then(|name| name.as_str())
+            .context("no name")?;
+        if !is_plain_name(name) {
+            bail!("'{}' is not a plain file name", name.escape_debug());
+        }
+        if let Some(files) = info.get("files").and_then(|files| files.as_list()) {
+            bail!(
+                "'{name}' holds {} files, and only single-file torrents can be downloaded from HTTP seeds",
+                files.len()
+            );
+        }
+        let length = non_negative(info, "length")?;
+        let piece_length = non_negative(info, "piece length")?;
+        if piece_length == 0 {
+            bail!("the piece length is zero");
+        }
+        let pieces = info
+            .get("pieces")
+            .and_then(|pieces| pieces.as_bytes())
+            .context("no pieces")?;
+        if pieces.len() % 20 != 0 {
+            bail!("the pieces aren't a whole number of SHA-1 hashes");
+        }
+        let pieces: Vec<[u8; 20]> = pieces
+            .chunks_exact(20)
+            .map(|hash| hash.try_into().expect("chunks of 20"))
+            .collect();
+        let expected = length.div_ceil(piece_length);
+        if pieces.len() as u64 != expected {
+            bail!(
+                "{} piece hashes, but {length} bytes in pieces of {piece_length} make {expected}",
+                pieces.len()
+            );
+        }
+        let urls = match root.get("url-list") {
+            Some(bencode::Value::List(urls)) => {
+                urls.iter().filter_map(|url| url.as_str()).collect()
+            }
+            Some(url) => url.as_str().into_iter().collect(),
+            None => Vec::new(),
+        };
+        let webseeds = urls
+            .into_iter()
+            .filter_map(|url| match Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+                _ => {
+                    tracing::debug!("Skipping HTTP seed '{url}'");
+                    None
+                }
+            })
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            length,
+            piece_length,
+            pieces,
+            webseeds,
+        })
+    }
+
+    /// Downloads and parses the torrent at `url`, up to [`MAX_TORRENT`].
+    pub async fn fetch(
+        client: &reqwest::Client,
+        url: &Url,
+        budget: &RetryBudget,
+        auth: Option<&AuthToken>,
+    ) -> anyhow::Result<Self> {
+        let response = http::send_retrying(client.get(url.clone()), None, budget, auth).await?;
+        let mut response = http::check_status(response).await?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() as u64 > MAX_TORRENT {
+                bail!(
+                    "'{}' is larger than {}, too large for a torrent",
+                    http::redact_url(url),
+                    Size(MAX_TORRENT)
+                );
+            }
+        }
+        Self::parse(&bytes)
+            .with_context(|| format!("'{}' is not a valid torrent", http::redact_url(url)))
+    }
+
+    /// The refusal for `url`, this torrent, given without `--torrent`,
+    /// offering its HTTP seeds if it has any.
+    pub fn refusal(&self, url: &Url) -> DownloadError {
+        let url = http::redact_url(url);
+        let message = match self.webseeds.len() {
+            0 => format!(
+                "'{url}' is a .torrent file, and dlm is not a BitTorrent client. It lists no HTTP seeds, so open it in one, or pass --torrent save to keep the .torrent file itself"
+            ),
+            seeds => format!(
+                "'{url}' is a .torrent file, and dlm is not a BitTorrent client. It lists {seeds} HTTP seed(s), though: pass --torrent webseeds to download '{}' ({}) from them, or --torrent save to keep the .torrent file itself",
+                self.name,
+                Size(self.length)
+            ),
+        };
+        DownloadError::BitTorrent { message }
+    }
+
+    /// Where `seed` serves the file: a seed ending in `/` is a directory
+    /// the file is in, any other is the file itself.
+    pub fn file_url(&self, seed: &Url) -> Url {
+        let mut url = seed.clone();
+        if url.path().ends_with('/') {
+            url.path_segments_mut()
+                .expect("http URLs have a path")
+                .pop_if_empty()
+                .push(&self.name);
+        }
+        url
+    }
+
+    /// Checks every piece of the downloaded file against its SHA-1,
+    /// returning how many there are.
+    pub fn verify(&self, path: &Path, interrupted: Option<&AtomicBool>) -> anyhow::Result<usize> {
+        use sha1::{Digest, Sha1};
+        use std::io::Read;
+
+        let size = std::fs::metadata(path)?.len();
+        if size != self.length {
+            bail!(
+                "'{}' is {size} bytes, but the torrent says {}",
+                path.display(),
+                self.length
+            );
+        }
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = vec![0; self.piece_length as usize];
+        let mut bad = Vec::new();
+        for (index, hash) in self.pieces.iter().enumerate() {
+            if interrupted.is_some_and(|interrupted| interrupted.load(Ordering::SeqCst)) {
+                bail!("Interrupted while checking the torrent's pieces");
+            }
+            let start = index as u64 * self.piece_length;
+            let piece = &mut buffer[..(self.length - start).min(self.piece_length) as usize];
+            file.read_exact(piece)?;
+            if <[u8; 20]>::from(Sha1::digest(&piece[..])) != *hash {
+                bad.push(index);
+            }
+        }
+        if !bad.is_empty() {
+            let listed: Vec<_> = bad.iter().take(5).map(usize::to_string).collect();
+            bail!(
+                "{} of {} pieces don't match the torrent's hashes: {}{}",
+                bad.len(),
+                self.pieces.len(),
+                listed.join(", "),
+                if bad.len() > listed.len() {
+                    ", ..."
+                } else {
+                    ""
+                }
+            );
+        }
+        Ok(self.pieces.len())
+    }
+}
+
+#[cfg(feature = "torrent")]
+fn non_negative(dict: &bencode::Value, key: &str) -> anyhow::Result<u64> {
+    dict.get(key)
+        .and_then(|value| value.as_integer())
+        .and_then(|value| u64::try_from(value).ok())
+        .with_context(|| format!("no {key}, or a negative one"))
+}
+
+/// Whether a torrent's name can be a file name in the target directory as
+/// is, without reaching outside it.
+#[cfg(feature = "torrent")]
+fn is_plain_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains(['/', '\\', ':', '\0'])
+}
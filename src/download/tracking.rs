@@ -0,0 +1,118 @@
+//! Tracking parameters in URLs copied from a browser: `utm_source`,
+//! `fbclid` and the like. They say nothing about the file, only where the
+//! link was clicked, so `--strip-tracking-params` drops them before the
+//! first request. Anything that could be part of a signature is left
+//! alone, and so is every parameter of a presigned URL: a signed query
+//! breaks when a byte of it changes.
+
+use crate::download::presigned;
+use url::Url;
+
+/// Parameters only ever used for tracking.
+pub const TRACKING: [&str; 22] = [
+    "fbclid",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "twclid",
+    "ttclid",
+    "li_fat_id",
+    "yclid",
+    "igshid",
+    "mc_cid",
+    "mc_eid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "vero_id",
+    "oly_anon_id",
+    "oly_enc_id",
+    "_ga",
+    "_gl",
+];
+
+/// Prefixes of families of tracking parameters.
+const TRACKING_PREFIXES: [&str; 2] = ["utm_", "pk_"];
+
+/// Parameters that sign or authorize a URL, never stripped even when asked
+/// to with `--tracking-param`.
+const PROTECTED: [&str; 10] = [
+    "sig",
+    "signature",
+    "token",
+    "expires",
+    "key-pair-id",
+    "policy",
+    "hmac",
+    "hash",
+    "auth",
+    "access_token",
+];
+
+/// Prefixes of signing parameters, as S3's `X-Amz-Signature`.
+const PROTECTED_PREFIXES: [&str; 3] = ["x-amz-", "x-goog-", "x-ms-"];
+
+/// Whether the parameter `name` may be part of a signature.
+pub fn is_protected(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    PROTECTED.contains(&name.as_str())
+        || PROTECTED_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Whether `name` is a tracking parameter, built in or one of `extra`.
+pub fn is_tracking(name: &str, extra: &[String]) -> bool {
+    let lower = name.to_ascii_lowercase();
+    if is_protected(&lower) {
+        return false;
+    }
+    TRACKING.contains(&lower.as_str())
+        || TRACKING_PREFIXES
+            .iter()
+            .any(|prefix| lower.starts_with(prefix))
+        || extra.iter().any(|extra| extra.eq_ignore_ascii_case(name))
+}
+
+/// The tracking parameters in `url`'s query, by name, in order. None for a
+/// presigned URL.
+pub fn tracking_params(url: &Url, extra: &[String]) -> Vec<String> {
+    if presigned::is_presigned(url) {
+        return Vec::new();
+    }
+    url.query_pairs()
+        .map(|(name, _)| name.into_owned())
+        .filter(|name| is_tracking(name, extra))
+        .collect()
+}
+
+/// `url` without its tracking parameters, and their names. The rest of
+/// the query is kept byte for byte, in its order.
+pub fn strip(url: &Url, extra: &[String]) -> (Url, Vec<String>) {
+    let removed = tracking_params(url, extra);
+    let Some(query) = url.query().filter(|_| !removed.is_empty()) else {
+        return (url.clone(), removed);
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !is_tracking(&decoded_name(pair), extra))
+        .collect();
+    let mut stripped = url.clone();
+    match kept.is_empty() {
+        true => stripped.set_query(None),
+        false => stripped.set_query(Some(&kept.join("&"))),
+    }
+    (stripped, removed)
+}
+
+/// The name in a `name=value` pair of a query, decoded.
+fn decoded_name(pair: &str) -> String {
+    let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+    url::form_urlencoded::parse(name.as_bytes())
+        .next()
+        .map(|(name, _)| name.into_owned())
+        .unwrap_or_default()
+}
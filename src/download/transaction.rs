@@ -0,0 +1,177 @@
+use crate::download::diagnostics::{self, WarningId};
+use crate::download::fs_ops;
+use crate::download::removal::{PartialFileGuard, Removal, Removed};
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The directory inside the target that a transaction's files are
+/// downloaded into.
+pub const STAGING: &str = ".dlm-transaction";
+
+/// The moves a commit is making, kept in the staging directory until they're
+/// all done.
+const JOURNAL: &str = "commit.json";
+
+/// `--all-or-nothing`: a set of downloads that only make sense together.
+/// Each goes into a staging directory inside the target; once all have
+/// succeeded, [`commit`](Self::commit) moves them into the target in one
+/// pass, and if any fails, [`roll_back`](Self::roll_back) leaves the target
+/// as it was.
+///
+/// Staging inside the target keeps the moves renames on the same
+/// filesystem. A commit writes down its moves before making any, so one
+/// that's interrupted is finished by the next [`begin`](Self::begin).
+#[derive(Debug)]
+pub struct Transaction {
+    target: PathBuf,
+    staging: PathBuf,
+    removal: Removal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// Staged file and where it goes.
+    moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Transaction {
+    /// Starts a transaction into `target`, first finishing any commit an
+    /// earlier run was interrupted in. Files a rolled-back run kept in the
+    /// staging directory stay, to be resumed.
+    pub fn begin(target: &Path) -> anyhow::Result<Self> {
+        let transaction = Self {
+            target: target.to_path_buf(),
+            staging: target.join(STAGING),
+            removal: Removal::default(),
+        };
+        let finished = transaction.finish_commit()?;
+        if !finished.is_empty() {
+            diagnostics::warn_or_fail(
+                WarningId::InterruptedCommit,
+                format!(
+                    "Finished an interrupted commit, moving {} files into '{}'",
+                    finished.len(),
+                    target.display()
+                ),
+            )?;
+        }
+        fs_ops::create_dir_all(&transaction.staging).with_context(|| {
+            format!(
+                "Cannot create the staging directory '{}'",
+                transaction.staging.display()
+            )
+        })?;
+        Ok(transaction)
+    }
+
+    /// How files a rollback removes, or a commit replaces, go.
+    pub fn with_removal(mut self, removal: Removal) -> Self {
+        self.removal = removal;
+        self
+    }
+
+    /// Where the downloads go until the commit.
+    pub fn staging_dir(&self) -> &Path {
+        &self.staging
+    }
+
+    /// Moves every staged file into the target and removes the staging
+    /// directory, returning where the files went. Unless `overwrite`, fails
+    /// before moving anything if one of them is already there.
+    pub fn commit(self, overwrite: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let mut moves = Vec::new();
+        for entry in fs::read_dir(&self.staging)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && entry.file_name() != JOURNAL {
+                moves.push((entry.path(), self.target.join(entry.file_name())));
+            }
+        }
+        moves.sort();
+        if !overwrite && let Some((_, existing)) = moves.iter().find(|(_, to)| to.exists()) {
+            bail!(
+                "'{}' already exists, so nothing was moved; pass --overwrite to replace it",
+                existing.display()
+            );
+        }
+        let journal = serde_json::to_vec_pretty(&Journal {
+            moves: moves.clone(),
+        })?;
+        let pending = self.staging.join(format!("{JOURNAL}.tmp"));
+        fs_ops::write(&pending, journal)?;
+        fs_ops::rename(&pending, &self.staging.join(JOURNAL))?;
+        // A rename would replace them for good. Once the journal is down, an
+        // interrupted commit still ends with the new files in place.
+        if self.removal == Removal::Trash {
+            for (_, existing) in moves.iter().filter(|(_, to)| to.is_file()) {
+                let removed = self.removal.remove_file(existing)?;
+                println!(
+                    "Replaced '{}', the old one was {removed}",
+                    existing.display()
+                );
+            }
+        }
+        self.finish_commit()
+    }
+
+    /// Ends a failed transaction, leaving the target untouched. The staged
+    /// files are kept for the next run to resume, or removed with `remove`,
+    /// in which case it returns where they went.
+    pub fn roll_back(self, remove: bool) -> anyhow::Result<Option<Removed>> {
+        Ok(self.staged(remove).apply()?)
+    }
+
+    /// The staged files, guarded until the commit: dropped before it, they're
+    /// kept, or removed with `remove`, as by [`roll_back`](Self::roll_back).
+    pub fn staged(&self, remove: bool) -> PartialFileGuard {
+        PartialFileGuard::new(&self.staging, remove.then_some(self.removal))
+    }
+
+    /// Makes the moves the journal lists, if there is one, skipping those
+    /// already made, then removes the journal and the staging directory.
+    fn finish_commit(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let path = self.staging.join(JOURNAL);
+        let journal: Journal = match fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Reading the commit journal '{}'", path.display()))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+        for (from, to) in &journal.moves {
+            if from.exists() {
+                move_file(from, to).with_context(|| {
+                    format!(
+                        "Cannot move '{}' to '{}'; run again to finish the commit",
+                        from.display(),
+                        to.display()
+                    )
+                })?;
+            }
+        }
+        fs_ops::remove_file(&path)?;
+        // Only directories, which no download makes, can be left.
+        fs_ops::remove_dir_all(&self.staging).or_else(ignore_missing)?;
+        Ok(journal.moves.into_iter().map(|(_, to)| to).collect())
+    }
+}
+
+/// A rename, or a copy and remove when the two are on different
+/// filesystems, as when the target holds a mount point.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs_ops::rename(from, to) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            fs_ops::copy(from, to)?;
+            fs_ops::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+fn ignore_missing(error: io::Error) -> io::Result<()> {
+    match error.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(error),
+    }
+}
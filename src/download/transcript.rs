@@ -0,0 +1,225 @@
+//! `--record`: a transcript of a run, as JSON lines, for `dlm replay` to
+//! run the download again against. It has what the decisions were made
+//! on: every request and the status and headers it got, the chunks
+//! scheduled, the retries and the exit code, each with when it happened.
+//! Bodies aren't kept, and credentials in headers and URLs always read
+//! `<redacted>`, whatever `--show-secrets` says.
+
+use crate::download::fs_ops;
+use crate::download::schema::Versioned;
+use anyhow::Context;
+use reqwest::header::{self, HeaderMap, HeaderName};
+use reqwest::{Method, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+use url::Url;
+
+/// Headers whose values never make it into a transcript.
+const SECRET_HEADERS: [HeaderName; 4] = [
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    header::COOKIE,
+    header::SET_COOKIE,
+];
+
+/// The transcript being written, if `--record` asked for one.
+static TRANSCRIPT: Mutex<Option<Transcript>> = Mutex::new(None);
+
+struct Transcript {
+    file: File,
+    started: Instant,
+    /// The id the next request gets.
+    next_request: u64,
+}
+
+/// One line of a transcript.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Entry {
+    /// Milliseconds since the run started.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The command line, with the values of flags that may be secrets
+    /// redacted, and the flags taken from `DM_*` variables, the secret
+    /// ones left out.
+    Start {
+        version: String,
+        args: Vec<String>,
+        #[serde(default)]
+        env: Vec<(String, String)>,
+    },
+    Request {
+        /// Pairs the request with its [`Event::Response`] or
+        /// [`Event::Failed`].
+        id: u64,
+        /// The worker-mode chunk, if it was one.
+        chunk: Option<usize>,
+        method: String,
+        url: String,
+        headers: Vec<(String, String)>,
+    },
+    Response {
+        id: u64,
+        status: u16,
+        headers: Vec<(String, String)>,
+    },
+    /// No response came: the connection failed or timed out.
+    Failed { id: u64, error: String },
+    /// A chunk of the plan, its inclusive byte range.
+    Scheduled { chunk: usize, start: u64, end: u64 },
+    Retry {
+        chunk: Option<usize>,
+        attempt: usize,
+        reason: String,
+    },
+    /// How the run ended.
+    Outcome {
+        exit_code: u8,
+        error: Option<String>,
+    },
+}
+
+/// Starts writing a transcript to `path`, replacing any there, with the
+/// command line `args` and the variables `env` as its first line.
+pub fn record_to(path: &Path, args: Vec<String>, env: Vec<(String, String)>) -> anyhow::Result<()> {
+    let file = fs_ops::create(path)
+        .with_context(|| format!("Cannot write transcript '{}'", path.display()))?;
+    *transcript() = Some(Transcript {
+        file,
+        started: Instant::now(),
+        next_request: 0,
+    });
+    write(Event::Start {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        args,
+        env,
+    });
+    Ok(())
+}
+
+/// Reads the transcript at `path`.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read transcript '{}'", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("'{}' line {}", path.display(), index + 1))
+        })
+        .collect()
+}
+
+/// Records the run's exit code, and its error if it failed.
+pub fn outcome(exit_code: u8, error: Option<String>) {
+    write(Event::Outcome { exit_code, error });
+}
+
+/// Records a request about to go out, returning the id its response is
+/// recorded under.
+pub(crate) fn request(
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    chunk: Option<usize>,
+) -> Option<u64> {
+    let id = {
+        let mut transcript = transcript();
+        let transcript = transcript.as_mut()?;
+        transcript.next_request += 1;
+        transcript.next_request - 1
+    };
+    write(Event::Request {
+        id,
+        chunk,
+        method: method.to_string(),
+        url: redacted(url),
+        headers: shown(headers),
+    });
+    Some(id)
+}
+
+pub(crate) fn response(id: Option<u64>, status: StatusCode, headers: &HeaderMap) {
+    if let Some(id) = id {
+        write(Event::Response {
+            id,
+            status: status.as_u16(),
+            headers: shown(headers),
+        });
+    }
+}
+
+pub(crate) fn failed(id: Option<u64>, error: &reqwest::Error) {
+    if let Some(id) = id {
+        write(Event::Failed {
+            id,
+            error: error.to_string(),
+        });
+    }
+}
+
+pub(crate) fn scheduled(chunk: usize, start: u64, end: u64) {
+    write(Event::Scheduled { chunk, start, end });
+}
+
+pub(crate) fn retry(chunk: Option<usize>, attempt: usize, reason: &str) {
+    write(Event::Retry {
+        chunk,
+        attempt,
+        reason: reason.to_string(),
+    });
+}
+
+fn transcript() -> std::sync::MutexGuard<'static, Option<Transcript>> {
+    TRANSCRIPT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Appends `event`, if a transcript is being written. Each line goes out
+/// in one write, so what came before a crash is all there.
+fn write(event: Event) {
+    let mut transcript = transcript();
+    let Some(transcript) = transcript.as_mut() else {
+        return;
+    };
+    let entry = Entry {
+        at_ms: transcript.started.elapsed().as_millis() as u64,
+        event,
+    };
+    let mut line = serde_json::to_vec(&Versioned::new(entry)).expect("an entry serializes");
+    line.push(b'\n');
+    if let Err(error) = transcript.file.write_all(&line) {
+        tracing::warn!("Cannot write the transcript: {error}");
+    }
+}
+
+fn shown(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match SECRET_HEADERS.contains(name) {
+                true => "<redacted>",
+                false => value.to_str().unwrap_or("<binary>"),
+            };
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+fn redacted(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("redacted"));
+    }
+    url.to_string()
+}
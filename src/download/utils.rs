@@ -1,29 +1,515 @@
+use crate::download::checksum::{self, Algorithm};
+use crate::download::error::DownloadError;
+use crate::download::fs_ops;
+use crate::download::memory::MemoryLimit;
+use crate::download::newer::NewerThan;
+use crate::download::target_wait;
+use crate::download::torrent;
 use anyhow::Result;
+use percent_encoding::percent_decode_str;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use url::Url;
 
+/// Longest file name, in bytes, that common filesystems accept. NTFS counts
+/// UTF-16 units instead, which are never more than the UTF-8 bytes.
+pub const MAX_FILE_NAME: usize = 255;
+
+/// Windows' `MAX_PATH`, past which paths need the `\\?\` prefix.
+const MAX_PATH: usize = 260;
+
+/// Longest path, in bytes, a Unix system call takes (`PATH_MAX` less the
+/// NUL). Windows has no limit past [`long_path`].
+#[cfg(unix)]
+pub const MAX_SYSTEM_PATH: Option<usize> = Some(nix::libc::PATH_MAX as usize - 1);
+#[cfg(not(unix))]
+pub const MAX_SYSTEM_PATH: Option<usize> = None;
+
+/// Device names Windows reserves in every directory, whatever the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Where `url` is saved in `target_dir`: under its last path segment,
+/// percent-decoded.
 pub fn build_download_path(url: &Url, target_dir: &Path) -> PathBuf {
-    target_dir.join(
-        url.path_segments()
-            .and_then(|segments| segments.last())
-            .unwrap_or("tmp.bin"),
-    )
-}
-
-pub fn hash_file(path: &Path, chunk_size: usize) -> Result<[u8; 32]> {
-    use sha2::{Digest, Sha256};
-    use std::fs::File;
-    use std::io::Read;
-
-    let mut file = File::open(path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = vec![0; chunk_size];
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+    let segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default();
+    file_path(&percent_decode_str(segment).decode_utf8_lossy(), target_dir)
+}
+
+/// Where a file called `name` is saved in `target_dir`, once
+/// [`safe_file_name`] has had its way with the name; `tmp.bin` when it
+/// leaves nothing.
+pub fn file_path(name: &str, target_dir: &Path) -> PathBuf {
+    let name = safe_file_name(name).unwrap_or_else(|| "tmp.bin".to_string());
+    let name = if cfg!(windows) {
+        windows_file_name(&name)
+    } else {
+        name
+    };
+    long_path(target_dir.join(name))
+}
+
+/// `name` as a file name that stays inside the directory it's saved in:
+/// only what follows its last slash or backslash, without control
+/// characters or surrounding whitespace. `None` when that leaves nothing,
+/// `.` or `..`.
+pub fn safe_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|char| !char.is_control()).collect();
+    let name = name.trim();
+    match name {
+        "" | "." | ".." => None,
+        name => Some(name.to_string()),
+    }
+}
+
+/// Makes `name` safe to create on Windows: trailing dots and spaces, which
+/// Windows silently drops, are stripped, and reserved device names such as
+/// `aux.log` get an underscore (`aux_.log`).
+fn windows_file_name(name: &str) -> String {
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return "tmp.bin".to_string();
+    }
+    let (stem, extension) = match name.find('.') {
+        Some(dot) => name.split_at(dot),
+        None => (name, ""),
+    };
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.trim_end().eq_ignore_ascii_case(reserved));
+    if reserved {
+        format!("{stem}_{extension}")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Prefixes `path` with `\\?\` on Windows when it's longer than `MAX_PATH`,
+/// lifting the limit. Elsewhere, and for short paths, it's returned as is.
+pub fn long_path(path: PathBuf) -> PathBuf {
+    let verbatim = path.as_os_str().to_string_lossy().starts_with(r"\\?\");
+    if !cfg!(windows) || path.as_os_str().len() < MAX_PATH || verbatim {
+        return path;
+    }
+    // The prefix turns off all normalization, so the path has to be
+    // absolute with only backslashes first.
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    let absolute = absolute.to_string_lossy().into_owned();
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{unc}")),
+        None => PathBuf::from(format!(r"\\?\{absolute}")),
+    }
+}
+
+/// Cuts `name` down to at most `max` bytes on a character boundary.
+pub fn truncate_file_name(name: &str, max: usize) -> &str {
+    if name.len() <= max {
+        return name;
+    }
+    let mut end = max;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// URL schemes dlm downloads from.
+pub const SUPPORTED_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// Fails if dlm can't download from `url`'s scheme, naming the tool that
+/// can for well-known ones, before anything is created on disk. Magnet
+/// links get the BitTorrent refusal, which points at their HTTP seeds.
+pub fn validate_url(url: &Url) -> Result<(), DownloadError> {
+    let scheme = url.scheme();
+    if SUPPORTED_SCHEMES.contains(&scheme) {
+        return Ok(());
+    }
+    if torrent::is_magnet(url) {
+        return Err(torrent::refuse_magnet(url));
+    }
+    let hint = match scheme {
+        "s3" => Some("Use `aws s3 cp`, or a presigned https:// URL"),
+        "gs" => Some("Use `gcloud storage cp`, or a signed https:// URL"),
+        "ssh" | "sftp" | "scp" => Some("Use scp or sftp"),
+        "ftp" | "ftps" => Some("Use curl or wget"),
+        "file" => Some("Copy local files with cp"),
+        "github" | "gitlab" => Some("Release URLs need dlm built with the releases feature"),
+        _ => None,
+    };
+    Err(DownloadError::UnsupportedScheme {
+        scheme: scheme.to_string(),
+        hint,
+    })
+}
+
+/// Creates `dir` if needed and checks that downloads can be written to it,
+/// turning the raw OS errors into ones that point at the actual problem.
+pub fn prepare_target_dir(dir: &Path) -> Result<(), DownloadError> {
+    // Any existing ancestor that is a file blocks the whole path.
+    if let Some(file) = dir.ancestors().find(|ancestor| ancestor.is_file()) {
+        return Err(DownloadError::TargetIsFile {
+            path: file.to_path_buf(),
+        });
+    }
+    if let Err(source) = fs_ops::create_dir_all(dir) {
+        // Report the first component that's missing, which is the one the
+        // OS refused to create.
+        let component = dir
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .last()
+            .unwrap_or(dir)
+            .to_path_buf();
+        return Err(DownloadError::TargetNotCreatable {
+            path: dir.to_path_buf(),
+            component,
+            source,
+        });
+    }
+
+    let probe = dir.join(format!(".dlm-write-test-{}", std::process::id()));
+    match target_wait::retry("create", &probe, || fs_ops::create(&probe)) {
+        Ok(_) => {
+            let _ = fs_ops::remove_file(&probe);
+            Ok(())
+        }
+        Err(source) => Err(DownloadError::TargetNotWritable {
+            path: dir.to_path_buf(),
+            owner: owner(dir),
+            source,
+        }),
+    }
+}
+
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = std::fs::metadata(path).ok()?.uid();
+    let name = nix::unistd::User::from_uid(uid.into()).ok().flatten();
+    Some(name.map_or_else(|| format!("uid {uid}"), |user| user.name))
+}
+
+#[cfg(not(unix))]
+fn owner(_path: &Path) -> Option<String> {
+    None
+}
+
+/// SHA-256 of the file at `path`, its read buffer counted against
+/// `memory`.
+pub fn hash_file(path: &Path, memory: &Arc<MemoryLimit>) -> Result<[u8; 32]> {
+    let hash = checksum::hash_file(path, Algorithm::Sha256, None, memory, |_| {})?;
+    Ok(hash.try_into().expect("a SHA-256 is 32 bytes"))
+}
+
+/// Parses a human byte count like `500k`, `2M`, `1.5GiB` or a plain number.
+/// Suffixes are binary multiples, as in curl and wget.
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{value}' is not a size like 500k, 2M or 1048576"))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size suffix '{other}' in '{value}'")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration like `500ms`, `30s`, `5m`, `1h` or plain seconds.
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    use std::time::Duration;
+
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{value}' is not a duration like 500ms, 30s or 5m"))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        other => return Err(format!("unknown duration unit '{other}' in '{value}'")),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses a request header given as `Name: value`, as in curl.
+pub fn parse_header(
+    value: &str,
+) -> Result<(reqwest::header::HeaderName, reqwest::header::HeaderValue), String> {
+    let Some((name, header_value)) = value.split_once(':') else {
+        return Err(format!("'{value}' is not a header like 'Name: value'"));
+    };
+    let name = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("'{}' is not a header name", name.trim()))?;
+    let header_value = reqwest::header::HeaderValue::from_str(header_value.trim())
+        .map_err(|_| format!("the value of {name} has characters a header can't hold"))?;
+    Ok((name, header_value))
+}
+
+/// Parses a header value on its own, as `--user-agent` takes one.
+pub fn parse_header_value(value: &str) -> Result<reqwest::header::HeaderValue, String> {
+    reqwest::header::HeaderValue::from_str(value.trim())
+        .map_err(|_| "it has characters a header can't hold".to_string())
+}
+
+/// Parses `--user`: `user:password`, split at the first colon, so the
+/// password may have colons of its own.
+pub fn parse_user(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((user, password)) if !user.is_empty() => Ok((user.to_string(), password.to_string())),
+        _ => Err("expected credentials like 'user:password'".to_string()),
+    }
+}
+
+/// Parses `--bearer-token`, which has to fit in an `Authorization` header.
+pub fn parse_token(value: &str) -> Result<String, String> {
+    match reqwest::header::HeaderValue::from_str(&format!("Bearer {value}")) {
+        Ok(_) if !value.is_empty() => Ok(value.to_string()),
+        _ => Err("it's empty or has characters a header can't hold".to_string()),
+    }
+}
+
+/// Parses `--proxy`, taking `proxy:3128` to mean plain HTTP as the proxy
+/// variables do.
+pub fn parse_proxy(value: &str) -> Result<Url, String> {
+    Url::parse(value)
+        .ok()
+        .filter(|proxy| proxy.has_host())
+        .or_else(|| Url::parse(&format!("http://{value}")).ok())
+        .filter(|proxy| matches!(proxy.scheme(), "http" | "https" | "socks5" | "socks5h"))
+        .ok_or_else(|| format!("'{value}' is not a proxy URL like http://proxy:3128"))
+}
+
+/// Splits pasted text into the URLs it lists, one per line or separated by
+/// any whitespace. Every word has to be an http(s) or ftp URL, so a stray
+/// sentence isn't mistaken for a list.
+pub fn parse_url_list(text: &str) -> Result<Vec<Url>, String> {
+    let urls = text
+        .split_whitespace()
+        .map(|word| match Url::parse(word) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "ftp") => Ok(url),
+            _ => Err(format!("'{}' is not a URL", truncate_file_name(word, 60))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if urls.is_empty() {
+        return Err("there are no URLs in it".to_string());
+    }
+    Ok(urls)
+}
+
+/// Parses `--newer-than`: an RFC 3339 timestamp, or a file whose
+/// modification time to use.
+pub fn parse_newer_than(value: &str) -> Result<NewerThan, String> {
+    if let Some(time) = parse_rfc3339(value) {
+        return Ok(NewerThan::Time(time));
+    }
+    std::fs::metadata(value)
+        .and_then(|metadata| metadata.modified())
+        .map(NewerThan::Modified)
+        .map_err(|error| {
+            format!(
+                "'{value}' is neither a timestamp like 2024-05-01T12:00:00Z nor a file ({error})"
+            )
+        })
+}
+
+/// Parses an RFC 3339 timestamp like `2024-05-01T12:00:00Z` or
+/// `2024-05-01 14:00:00.5+02:00`, from 1970 on.
+pub fn parse_rfc3339(value: &str) -> Option<std::time::SystemTime> {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let value = value.trim();
+    let number = |digits: &str| -> Option<i64> {
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let (date, rest) = value.split_at_checked(10)?;
+    let (separator, rest) = rest.split_at_checked(1)?;
+    let (time, rest) = rest.split_at_checked(8)?;
+    if !matches!(separator, "T" | "t" | " ") || &date[4..5] != "-" || &date[7..8] != "-" {
+        return None;
+    }
+    let (year, month, day) = (
+        number(&date[..4])?,
+        number(&date[5..7])?,
+        number(&date[8..])?,
+    );
+    if &time[2..3] != ":" || &time[5..6] != ":" {
+        return None;
+    }
+    let (hour, minute, second) = (
+        number(&time[..2])?,
+        number(&time[3..5])?,
+        number(&time[6..])?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // A leap second is the same instant as the next one, close enough.
+    if second > 60 {
+        return None;
+    }
+
+    let (fraction, offset) = match rest.strip_prefix('.') {
+        Some(rest) => {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let (fraction, offset) = rest.split_at(digits);
+            let nanos: String = fraction
+                .chars()
+                .chain("000000000".chars())
+                .take(9)
+                .collect();
+            (number(&nanos).filter(|_| digits > 0)?, offset)
         }
-        hasher.update(&buffer[..bytes_read]);
+        None => (0, rest),
+    };
+    let offset = match offset {
+        "Z" | "z" => 0,
+        offset => {
+            let sign = match offset.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            if hours.len() != 2 || minutes.len() != 2 {
+                return None;
+            }
+            let (hours, minutes) = (number(hours)?, number(minutes)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    // Howard Hinnant's days-from-civil algorithm.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    // Nothing on a web server predates 1970.
+    UNIX_EPOCH.checked_add(Duration::new(seconds.try_into().ok()?, fraction as u32))
+}
+
+/// A parsed `Content-Range: bytes <first>-<last>/<total>` header. The total
+/// is `None` when the server sent `*`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    pub first: u64,
+    pub last: u64,
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn size(&self) -> u64 {
+        self.last - self.first + 1
+    }
+}
+
+/// The remote size from a 416 response's `Content-Range: bytes */<total>`.
+pub fn unsatisfied_range_total(value: &str) -> Option<u64> {
+    value.trim().strip_prefix("bytes */")?.parse().ok()
+}
+
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+    if last < first {
+        return None;
+    }
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some(ContentRange { first, last, total })
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+    use crate::download::parts::PartLayout;
+
+    #[test]
+    fn reserved_names_get_an_underscore() {
+        assert_eq!(windows_file_name("aux.log"), "aux_.log");
+        assert_eq!(windows_file_name("CON"), "CON_");
+        assert_eq!(windows_file_name("com1.tar.gz"), "com1_.tar.gz");
+        assert_eq!(windows_file_name("console.log"), "console.log");
+        assert_eq!(windows_file_name("lpt10"), "lpt10");
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces_are_stripped() {
+        assert_eq!(windows_file_name("report. . "), "report");
+        assert_eq!(windows_file_name("nul. "), "nul_");
+        assert_eq!(windows_file_name("..."), "tmp.bin");
+    }
+
+    #[test]
+    fn long_paths_are_prefixed() {
+        let url = Url::parse(&format!("https://example.com/{}.bin", "a".repeat(240))).unwrap();
+        let path = build_download_path(&url, Path::new(r"C:\downloads\nested\deeper\still"));
+        assert!(path.to_string_lossy().starts_with(r"\\?\C:\downloads\"));
+
+        let url = Url::parse("https://example.com/short.bin").unwrap();
+        let short = build_download_path(&url, Path::new(r"C:\d"));
+        assert_eq!(short, Path::new(r"C:\d\short.bin"));
+
+        let unc = long_path(PathBuf::from(format!(
+            r"\\server\share\{}",
+            "b".repeat(260)
+        )));
+        assert!(unc.to_string_lossy().starts_with(r"\\?\UNC\server\share\"));
+    }
+
+    #[test]
+    fn part_names_stay_under_the_limit() {
+        let url = Url::parse("https://example.com/file.iso").unwrap();
+        let name = format!("{}.iso", "é".repeat(200));
+        let layout = PartLayout::new(
+            &url,
+            2_000_000_000,
+            vec![(0, 999_999_999), (1_000_000_000, 1_999_999_999)],
+        )
+        .beside(&Path::new(r"C:\d").join(&name))
+        .unwrap();
+        let part = layout.paths[1]
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(part.len() <= MAX_FILE_NAME, "{}", part.len());
+        assert!(part.ends_with(&format!(".{}.p0001", layout.id)), "{part}");
     }
-    Ok(hasher.finalize().into())
 }
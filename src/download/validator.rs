@@ -0,0 +1,128 @@
+//! What a single-stream download checks a resumed response against. When
+//! the download starts, the response's `ETag`, `Last-Modified` and size are
+//! kept beside the file in `<name>.meta`; resuming sends one of them as
+//! `If-Range`, so a server whose file changed since answers with all of the
+//! new one (200) rather than a range of it (206) that would be appended to
+//! the old bytes. The sidecar is removed once the file is whole.
+
+use crate::download::etag;
+use crate::download::utils::{self, MAX_FILE_NAME};
+use reqwest::StatusCode;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Suffix of the sidecar beside the file.
+const SUFFIX: &str = ".meta";
+
+/// `--if-changed`: what to do when resuming finds the remote file changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IfChanged {
+    /// Throw away what's there and download the new file from the start.
+    Restart,
+    /// Fail, leaving the partial file as it is.
+    #[default]
+    Abort,
+}
+
+impl FromStr for IfChanged {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "restart" => Ok(IfChanged::Restart),
+            "abort" => Ok(IfChanged::Abort),
+            other => Err(format!(
+                "unknown action '{other}', expected restart or abort"
+            )),
+        }
+    }
+}
+
+/// What the server said about the file when its download started.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validator {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Of the whole file.
+    pub size: Option<u64>,
+}
+
+impl Validator {
+    /// Of the response in `headers` to a request for the whole file, `size`
+    /// bytes long.
+    pub fn of(headers: &HeaderMap, size: Option<u64>) -> Self {
+        Self {
+            etag: etag::of(headers).map(str::to_string),
+            last_modified: headers
+                .get(header::LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty()),
+            size,
+        }
+    }
+
+    /// The validator `If-Range` can carry: a strong ETag, or else the
+    /// `Last-Modified` date. A weak ETag isn't allowed there.
+    pub fn if_range(&self) -> Option<HeaderValue> {
+        let etag = self.etag.as_deref().filter(|etag| !etag::is_weak(etag));
+        etag.or(self.last_modified.as_deref())
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// How the answer to a resumed request sent with this says the remote
+    /// file changed, `None` if it doesn't: a 200 to `If-Range`, or a 206
+    /// whose `Content-Range` gives another size than the one recorded.
+    pub fn changed(&self, status: StatusCode, headers: &HeaderMap) -> Option<String> {
+        if status == StatusCode::OK {
+            let sent = self.if_range()?;
+            let sent = sent.to_str().unwrap_or_default();
+            return Some(format!("it no longer matches {sent}"));
+        }
+        let expected = self
+            .size
+            .filter(|_| status == StatusCode::PARTIAL_CONTENT)?;
+        let total = headers
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(utils::parse_content_range)?
+            .total?;
+        (total != expected).then(|| format!("it's {total} bytes now, not {expected}"))
+    }
+
+    /// The sidecar of `final_path`, if there's a readable one.
+    pub fn load(final_path: &Path) -> Option<Self> {
+        let json = std::fs::read(meta_path(final_path)).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// Keeps this beside `final_path`, or removes what's there if it says
+    /// nothing a resumed response could be checked against.
+    pub fn save(&self, final_path: &Path) -> anyhow::Result<()> {
+        if *self == Self::default() {
+            remove(final_path);
+            return Ok(());
+        }
+        std::fs::write(meta_path(final_path), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// The sidecar of `final_path`.
+pub fn meta_path(final_path: &Path) -> PathBuf {
+    let name = final_path.file_name().unwrap_or_default().to_string_lossy();
+    let name = utils::truncate_file_name(&name, MAX_FILE_NAME - SUFFIX.len());
+    utils::long_path(final_path.with_file_name(format!("{name}{SUFFIX}")))
+}
+
+/// Removes the sidecar of `final_path`, once the file is whole.
+pub fn remove(final_path: &Path) {
+    let path = meta_path(final_path);
+    if let Err(error) = std::fs::remove_file(&path)
+        && error.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("Cannot remove '{}': {error}", path.display());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::download::fs_ops;
+use crate::download::in_place;
+use crate::download::memory::Reservation;
+use crate::download::target_wait;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+/// Buffers a chunk's bytes and writes them to its range of the file
+/// itself, or with part files to its own, in blocks of up to
+/// `capacity` bytes, either directly or through a shared [`DiskWriter`].
+///
+/// A chunk holds a single buffer, so worker mode's write-behind memory stays
+/// around workers × capacity, whichever way the writes go.
+pub(crate) struct ChunkWriter {
+    path: PathBuf,
+    buffer: Vec<u8>,
+    capacity: usize,
+    target: Target,
+    /// Where in the file the chunk's first byte goes.
+    offset: u64,
+    /// Bytes handed to the file so far.
+    written: u64,
+    /// Written in place, where that's recorded for `--resume`.
+    progress: Option<in_place::Progress>,
+    /// Counts the buffer against `--max-memory` until the chunk is done.
+    _memory: Reservation,
+}
+
+enum Target {
+    Direct(tokio::fs::File),
+    Serial { file: Arc<File>, writer: DiskWriter },
+}
+
+impl ChunkWriter {
+    /// Creates (truncating) the part file at `path`, buffering as much as
+    /// `memory` was granted. Zero writes every piece straight through.
+    pub(crate) async fn create(
+        path: PathBuf,
+        memory: Reservation,
+        writer: Option<DiskWriter>,
+    ) -> io::Result<Self> {
+        let capacity = memory.size();
+        let target = match writer {
+            Some(writer) => Target::Serial {
+                file: Arc::new(target_wait::retry("create", &path, || {
+                    fs_ops::create(&path)
+                })?),
+                writer,
+            },
+            None => Target::Direct(
+                target_wait::retry_async("create", &path, || fs_ops::create_async(&path)).await?,
+            ),
+        };
+        Ok(Self {
+            path,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            target,
+            offset: 0,
+            written: 0,
+            progress: None,
+            _memory: memory,
+        })
+    }
+
+    /// Opens the file at `path`, already sized, to write a chunk into it
+    /// from `offset` on, recording what reaches it in `progress`.
+    pub(crate) async fn in_place(
+        path: PathBuf,
+        offset: u64,
+        memory: Reservation,
+        writer: Option<DiskWriter>,
+        progress: in_place::Progress,
+    ) -> io::Result<Self> {
+        let capacity = memory.size();
+        let target = match writer {
+            Some(writer) => {
+                let mut file = target_wait::retry("open", &path, || {
+                    fs_ops::open(OpenOptions::new().write(true), &path)
+                })?;
+                file.seek(SeekFrom::Start(offset))?;
+                Target::Serial {
+                    file: Arc::new(file),
+                    writer,
+                }
+            }
+            None => {
+                let mut open = tokio::fs::OpenOptions::new();
+                open.write(true);
+                let mut file =
+                    target_wait::retry_async("open", &path, || fs_ops::open_async(&open, &path))
+                        .await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                Target::Direct(file)
+            }
+        };
+        Ok(Self {
+            path,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            target,
+            offset,
+            written: 0,
+            progress: Some(progress),
+            _memory: memory,
+        })
+    }
+
+    /// Where in the file the chunk's first byte goes: zero for a part file.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The part file, or written in place the file itself.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= self.capacity {
+            self.write_buffer().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever is buffered and makes sure it reached the file.
+    pub(crate) async fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer().await?;
+        if let Target::Direct(file) = &mut self.target {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever is buffered and syncs the file, for a chunk
+    /// stopped part-way to be carried on from what's on disk.
+    pub(crate) async fn sync(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        match &self.target {
+            Target::Direct(file) => file.sync_all().await,
+            Target::Serial { file, .. } => file.sync_all(),
+        }
+    }
+
+    async fn write_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let length = self.buffer.len() as u64;
+        match &mut self.target {
+            Target::Direct(file) => {
+                file.write_all(&self.buffer).await?;
+                self.buffer.clear();
+            }
+            Target::Serial { file, writer } => {
+                let data = std::mem::take(&mut self.buffer);
+                // The buffer comes back empty, to be reused.
+                self.buffer = writer.write(file.clone(), data).await?;
+            }
+        }
+        self.written += length;
+        if let Some(progress) = &self.progress {
+            // Only what the file has is recorded as written.
+            if let Target::Direct(file) = &mut self.target {
+                file.flush().await?;
+            }
+            progress.wrote(self.written)?;
+        }
+        Ok(())
+    }
+}
+
+struct Job {
+    file: Arc<File>,
+    data: Vec<u8>,
+    done: oneshot::Sender<io::Result<Vec<u8>>>,
+}
+
+/// A single thread doing every chunk's disk writes one after the other, so
+/// a spinning disk sees large sequential writes instead of a seek storm
+/// from workers writing concurrently. Network reads stay parallel: each
+/// worker only waits for its own write.
+#[derive(Clone)]
+pub(crate) struct DiskWriter {
+    sender: mpsc::Sender<Job>,
+}
+
+impl DiskWriter {
+    /// Starts the writer thread, which exits once every clone is dropped.
+    pub(crate) fn spawn(workers: usize) -> io::Result<Self> {
+        let (sender, mut receiver) = mpsc::channel::<Job>(workers.max(1));
+        std::thread::Builder::new()
+            .name("dlm-disk-writer".to_string())
+            .spawn(move || {
+                while let Some(Job {
+                    file,
+                    mut data,
+                    done,
+                }) = receiver.blocking_recv()
+                {
+                    let result = (&*file).write_all(&data).map(|()| {
+                        data.clear();
+                        data
+                    });
+                    let _ = done.send(result);
+                }
+            })?;
+        Ok(Self { sender })
+    }
+
+    async fn write(&self, file: Arc<File>, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let gone = || io::Error::other("disk writer stopped");
+        let (done, written) = oneshot::channel();
+        self.sender
+            .send(Job { file, data, done })
+            .await
+            .map_err(|_| gone())?;
+        written.await.map_err(|_| gone())?
+    }
+}
@@ -0,0 +1,431 @@
+//! `--zero-copy`: plain-HTTP downloads on Linux that move the body from the
+//! socket to the file with `splice(2)`, through a pipe, without copying it
+//! into userspace. reqwest doesn't give up its sockets, so this mode speaks
+//! just enough HTTP/1.1 itself: one GET, a `200` with an identity body, and
+//! nothing else. Anything more (TLS, proxies, redirects, resuming, chunked
+//! bodies, a name for the file in `Content-Disposition`) falls back to the
+//! usual download, as does a body cut short, for its retries to fetch.
+//! The answer's headers get the same checks as the usual download's, and
+//! the file is watched the same way while it's written.
+
+use crate::download::client::ClientOptions;
+use crate::download::network_wait;
+use crate::download::options::TransferOptions;
+use crate::download::partial;
+use crate::download::progress::TransferProgress;
+use reqwest::Method;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Why `url` can't be downloaded with `--zero-copy`, if it can't.
+pub fn unavailable(
+    url: &Url,
+    client: &ClientOptions,
+    options: &TransferOptions,
+) -> Option<&'static str> {
+    if !cfg!(target_os = "linux") {
+        return Some("splice(2) is Linux only");
+    }
+    if url.scheme() != "http" {
+        return Some("the body of a TLS connection has to be decrypted");
+    }
+    if client.proxy_for(url).is_some() {
+        return Some("requests go through a proxy");
+    }
+    if !url.username().is_empty() {
+        return Some("the URL has credentials");
+    }
+    if client.interface.is_some() || client.ip_family.is_some() {
+        return Some("--interface, -4 and -6 need the usual client");
+    }
+    if !client.headers.is_empty() || client.user_agent.is_some() {
+        return Some("--header, --user, --bearer-token and --user-agent need the usual client");
+    }
+    if options.method != Method::GET {
+        return Some("only GET is supported");
+    }
+    if options.auth_token.is_some() {
+        return Some("--auth-token-url authorizes through the usual client");
+    }
+    if network_wait::enabled() {
+        return Some("--wait-for-network waits through the usual client");
+    }
+    if !options.mirrors.is_empty() {
+        return Some("--mirror falls back through the usual client");
+    }
+    if options.resume || options.continue_at.is_some() || options.resume_from.is_some() {
+        return Some("resuming needs ranges");
+    }
+    if options.store_compressed.is_some() || options.pieces.is_some() {
+        return Some("the bytes have to be looked at on the way");
+    }
+    if options.throttle.rate().is_some() {
+        return Some("the rate limit is applied to reads");
+    }
+    if options.expected_size.is_some() {
+        return Some("--expected-size is checked before anything is written");
+    }
+    if options.strict_content_type {
+        return Some("--strict-content-type fails through the usual download");
+    }
+    None
+}
+
+/// Downloads `url` into `target_dir` with `splice(2)`, or returns `None`,
+/// having written nothing, when the server's answer needs the usual
+/// download: anything but a `200` with an identity body, which includes
+/// redirects and errors. Call [`unavailable`] first, with the same
+/// `client`.
+pub async fn download_zero_copy(
+    url: Url,
+    target_dir: &Path,
+    progress: TransferProgress,
+    client: &ClientOptions,
+    options: &TransferOptions,
+) -> anyhow::Result<Option<PathBuf>> {
+    let destination = options.destination(&url, target_dir);
+    #[cfg(target_os = "linux")]
+    {
+        let connect_timeout = client.connect_timeout;
+        let transfer = options.clone();
+        let reporter = progress.clone();
+        let discard = partial::Discard::new(
+            &partial::path(&destination),
+            &destination,
+            options.no_partial,
+        );
+        let result = tokio::task::spawn_blocking(move || {
+            linux::download(&url, &destination, connect_timeout, &transfer, &progress)
+        })
+        .await?;
+        if !matches!(result, Ok(None)) {
+            reporter.finish_with(&result);
+        }
+        if result.is_ok() {
+            discard.keep();
+        }
+        result
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (url, destination, progress, client, options);
+        Ok(None)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::download::client::USER_AGENT;
+    use crate::download::content_type;
+    use crate::download::error::DownloadError;
+    use crate::download::file_watch::FileWatch;
+    use crate::download::fs_ops;
+    use crate::download::http;
+    use crate::download::options::TransferOptions;
+    use crate::download::partial;
+    use crate::download::progress::TransferProgress;
+    use crate::download::stall::Stall;
+    use crate::download::target_wait;
+    use crate::download::torrent;
+    use anyhow::{Context, bail};
+    use nix::errno::Errno;
+    use nix::fcntl::{OFlag, SpliceFFlags, splice};
+    use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use url::Url;
+
+    /// Bytes moved per `splice`: what a pipe holds by default.
+    const PIPE_SIZE: usize = 64 * 1024;
+
+    /// Response headers bigger than this are refused.
+    const MAX_HEADERS: usize = 64 * 1024;
+
+    /// The connection gave out partway through the body.
+    #[derive(Debug, thiserror::Error)]
+    #[error("the connection dropped after {written} bytes: {reason}")]
+    struct Dropped {
+        written: u64,
+        reason: String,
+    }
+
+    pub(super) fn download(
+        url: &Url,
+        destination: &Path,
+        connect_timeout: Option<Duration>,
+        options: &TransferOptions,
+        progress: &TransferProgress,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        if destination.exists() && !options.overwrite {
+            return Err(DownloadError::FileExists {
+                path: destination.to_path_buf(),
+            }
+            .into());
+        }
+        let host = url.host_str().context("The URL has no host")?;
+        let addresses = url
+            .socket_addrs(|| Some(80))
+            .with_context(|| format!("Cannot resolve {host}"))?;
+        let mut socket = connect(&addresses, connect_timeout)?;
+        let stall_timeout = options.stall.stall_timeout;
+        socket.set_read_timeout(Some(stall_timeout))?;
+        socket.set_nodelay(true)?;
+
+        let mut target = url[url::Position::BeforePath..url::Position::AfterQuery].to_string();
+        if target.is_empty() {
+            target.push('/');
+        }
+        let authority = &url[url::Position::BeforeHost..url::Position::AfterPort];
+        write!(
+            socket,
+            "GET {target} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: {USER_AGENT}\r\nAccept: */*\r\nAccept-Encoding: identity\r\nConnection: close\r\n\r\n"
+        )?;
+        tracing::debug!("> GET {} HTTP/1.1 (zero-copy)", http::redact_url(url));
+
+        let (head, body_start) = read_head(&mut socket)?;
+        let Some(length) = usable_length(&head, options.output.is_some()) else {
+            tracing::info!(
+                "The server's answer needs the usual download: {}",
+                head.lines().next().unwrap_or_default()
+            );
+            return Ok(None);
+        };
+        // Nothing is written to the file until the answer passed the
+        // usual download's checks.
+        let headers = headers(&head);
+        torrent::check_content_type(destination, &headers, options.save_torrent)?;
+        content_type::check(
+            destination,
+            &headers,
+            options.strict_content_type,
+            progress.reporter(),
+        )?;
+        tracing::info!("Moving the body to the file with splice(2)");
+
+        let partial = partial::path(destination);
+        let file = target_wait::retry("create", &partial, || fs_ops::create(&partial))?;
+        let watch = FileWatch::new(&partial, &file.metadata()?, 0);
+        if let Some(length) = length {
+            progress.set_total(length);
+        }
+        match transfer(socket, file, watch, &body_start, length, options, progress) {
+            Ok(()) => {
+                partial::finish(&partial, destination)?;
+                Ok(Some(destination.to_path_buf()))
+            }
+            Err(error) => match error.downcast::<Dropped>() {
+                Ok(dropped) => {
+                    tracing::info!("{dropped}, the usual download takes over");
+                    fs_ops::remove_file(&partial)?;
+                    progress.add_wasted(dropped.written);
+                    progress.set_downloaded(0);
+                    Ok(None)
+                }
+                Err(error) => Err(error),
+            },
+        }
+    }
+
+    /// Writes the body to `file`: what came with the headers, then the rest
+    /// spliced from `socket`.
+    fn transfer(
+        mut socket: TcpStream,
+        mut file: File,
+        mut watch: FileWatch,
+        body_start: &[u8],
+        length: Option<u64>,
+        options: &TransferOptions,
+        progress: &TransferProgress,
+    ) -> anyhow::Result<()> {
+        let stall_timeout = options.stall.stall_timeout;
+        file.write_all(body_start)?;
+        watch.wrote(body_start.len());
+        let mut written = body_start.len() as u64;
+        progress.set_downloaded(written as usize);
+
+        let (pipe_out, pipe_in) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mut spliced = false;
+        while length.is_none_or(|length| written < length) {
+            if progress.interrupted.load(Ordering::SeqCst) {
+                return Err(DownloadError::Interrupted.into());
+            }
+            while options.throttle.is_paused() {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            let wanted = length.map_or(PIPE_SIZE, |length| {
+                (length - written).min(PIPE_SIZE as u64) as usize
+            });
+            let flags = SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_MORE;
+            let moved = match splice(&socket, None, &pipe_in, None, wanted, flags) {
+                Ok(0) => break,
+                Ok(moved) => moved,
+                Err(Errno::EAGAIN) => {
+                    let reason = Stall::NoData(stall_timeout).to_string();
+                    return Err(Dropped { written, reason }.into());
+                }
+                Err(Errno::EINTR) => continue,
+                // Not every socket or filesystem splices.
+                Err(Errno::EINVAL) if !spliced => {
+                    tracing::info!("splice(2) isn't supported here, copying instead");
+                    return copy(&mut socket, file, watch, length, written, progress);
+                }
+                Err(error) => {
+                    let reason = error.to_string();
+                    return Err(Dropped { written, reason }.into());
+                }
+            };
+            let mut drained = 0;
+            while drained < moved {
+                match splice(&pipe_out, None, &file, None, moved - drained, flags) {
+                    Ok(n) => drained += n,
+                    Err(Errno::EINTR) => continue,
+                    Err(Errno::EINVAL) if !spliced => {
+                        tracing::info!("splice(2) isn't supported here, copying instead");
+                        let mut pending = vec![0; moved - drained];
+                        File::from(pipe_out).read_exact(&mut pending)?;
+                        file.write_all(&pending)?;
+                        watch.wrote(moved);
+                        written += moved as u64;
+                        return copy(&mut socket, file, watch, length, written, progress);
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            spliced = true;
+            watch.wrote(moved);
+            if watch.due() {
+                watch.check()?;
+            }
+            written += moved as u64;
+            progress.set_downloaded(written as usize);
+        }
+        finish(written, length, &mut watch)
+    }
+
+    /// Connects to the first of `addresses` that answers, giving each
+    /// `--connect-timeout` if set.
+    fn connect(addresses: &[SocketAddr], timeout: Option<Duration>) -> anyhow::Result<TcpStream> {
+        let mut last = None;
+        for address in addresses {
+            let connected = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(address, timeout),
+                None => TcpStream::connect(address),
+            };
+            match connected {
+                Ok(socket) => return Ok(socket),
+                Err(error) => {
+                    last = Some(
+                        anyhow::Error::new(error).context(format!("Cannot connect to {address}")),
+                    )
+                }
+            }
+        }
+        Err(last.unwrap_or_else(|| anyhow::anyhow!("The host has no addresses")))
+    }
+
+    /// Reads the status line and headers, returning them and any of the body
+    /// that came with them.
+    fn read_head(socket: &mut TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+        let mut buffer = Vec::with_capacity(4096);
+        let mut chunk = [0; 4096];
+        loop {
+            let read = socket.read(&mut chunk)?;
+            if read == 0 {
+                bail!("The server closed the connection before answering");
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+            if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                let body = buffer.split_off(end + 4);
+                let head = String::from_utf8_lossy(&buffer).into_owned();
+                return Ok((head, body));
+            }
+            if buffer.len() > MAX_HEADERS {
+                bail!("The server's response headers are over {MAX_HEADERS} bytes");
+            }
+        }
+    }
+
+    /// The response headers in `head`, as the usual download's checks take
+    /// them. Lines that aren't headers are left out.
+    fn headers(head: &str) -> HeaderMap {
+        head.lines()
+            .skip(1)
+            .filter_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                let name = HeaderName::from_bytes(name.trim().as_bytes()).ok()?;
+                let value = HeaderValue::from_str(value.trim()).ok()?;
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// The body's length, `Some(None)` when it runs to the end of the
+    /// connection, or `None` when the answer isn't a plain `200`, or names
+    /// a file that isn't `named` already.
+    fn usable_length(head: &str, named: bool) -> Option<Option<u64>> {
+        let mut lines = head.lines();
+        let status = lines.next()?.split_whitespace().nth(1)?;
+        if status != "200" {
+            return None;
+        }
+        let mut length = None;
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => length = Some(value.parse().ok()?),
+                "transfer-encoding" if !value.eq_ignore_ascii_case("identity") => return None,
+                "content-encoding" if !value.eq_ignore_ascii_case("identity") => return None,
+                "content-disposition" if !named => return None,
+                _ => {}
+            }
+        }
+        Some(length)
+    }
+
+    /// The rest of the body the usual way, through a buffer.
+    fn copy(
+        socket: &mut TcpStream,
+        mut file: File,
+        mut watch: FileWatch,
+        length: Option<u64>,
+        mut written: u64,
+        progress: &TransferProgress,
+    ) -> anyhow::Result<()> {
+        let mut buffer = vec![0; PIPE_SIZE];
+        while length.is_none_or(|length| written < length) {
+            let read = match socket.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    let reason = error.to_string();
+                    return Err(Dropped { written, reason }.into());
+                }
+            };
+            file.write_all(&buffer[..read])?;
+            watch.wrote(read);
+            if watch.due() {
+                watch.check()?;
+            }
+            written += read as u64;
+            progress.set_downloaded(written as usize);
+        }
+        finish(written, length, &mut watch)
+    }
+
+    /// Checks the body is all there, and all of it in the file.
+    fn finish(written: u64, length: Option<u64>, watch: &mut FileWatch) -> anyhow::Result<()> {
+        if let Some(length) = length
+            && written < length
+        {
+            let reason = format!("it closed {} bytes short", length - written);
+            return Err(Dropped { written, reason }.into());
+        }
+        watch.check()
+    }
+}
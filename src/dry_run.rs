@@ -0,0 +1,246 @@
+use crate::batch::{Listing, Preview};
+use download_manager::__private::http;
+use download_manager::__private::plan::{Action, Plan};
+use download_manager::__private::preflight::{Report, Totals};
+use download_manager::schema::{
+    BatchEntry, BatchPlan, EntrySettings, InvalidLine, RepeatedLine, Versioned,
+};
+use indicatif::HumanBytes;
+use std::path::PathBuf;
+
+/// Prints a `--dry-run` plan, as JSON when `json` is set.
+pub fn print_plan(plan: &Plan, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&Versioned::new(plan))?);
+        return Ok(());
+    }
+
+    let bytes = |size: Option<u64>| match size {
+        Some(size) => format!("{} ({size} bytes)", HumanBytes(size)),
+        None => "unknown".to_string(),
+    };
+    println!("Dry run, nothing will be downloaded or written.");
+    println!("URL:         {}", plan.url);
+    if plan.final_url != plan.url {
+        println!("Redirected:  {}", plan.final_url);
+    }
+    println!("Destination: {}", plan.destination.display());
+    println!("Action:      {}", describe(&plan.action));
+    println!("Size:        {}", bytes(plan.size));
+    println!(
+        "Ranges:      {}",
+        if plan.accepts_ranges {
+            "supported"
+        } else {
+            "not advertised"
+        }
+    );
+    println!("Disk usage:  {}", bytes(plan.disk_usage));
+    println!("Asked with:  {}", plan.probed_with);
+
+    let network = &plan.network;
+    println!(
+        "Source:      {}",
+        network
+            .source_address
+            .map_or("default".to_string(), |addr| addr.to_string())
+    );
+    println!(
+        "Proxy:       {}",
+        network.proxy.as_deref().unwrap_or("none")
+    );
+    match &network.user {
+        Some(user) => println!("Credentials: basic auth as '{user}'"),
+        None => println!("Credentials: none"),
+    }
+
+    if !plan.segments.is_empty() {
+        println!();
+        println!("Segments:");
+        println!(
+            "  {:>5}  {:>12}  {:>12}  {:>10}  FILE",
+            "CHUNK", "START", "END", "SIZE"
+        );
+        for (id, segment) in plan.segments.iter().enumerate() {
+            let file = segment.part_file.as_ref().unwrap_or(&plan.destination);
+            println!(
+                "  {:>5}  {:>12}  {:>12}  {:>10}  {}",
+                id,
+                segment.start,
+                segment.end,
+                HumanBytes(segment.end - segment.start + 1).to_string(),
+                file.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints what a `--dry-run` of an `--input-file` batch found: for each of
+/// `listing`'s entries, the destination and preview in `previews`, under
+/// the settings it'd be downloaded with, those that would fail first, and
+/// what they add up to. `workers` is the command's own.
+pub fn print_batch(
+    listing: &Listing,
+    previews: &[(PathBuf, Preview)],
+    workers: u8,
+    json: bool,
+) -> anyhow::Result<()> {
+    let mut entries: Vec<_> = listing.entries.iter().zip(previews).collect();
+    // Stable, so each group keeps the file's order.
+    entries.sort_by_key(|(_, (_, preview))| !preview.fails());
+    let totals = totals(previews);
+    if json {
+        let entries = entries
+            .into_iter()
+            .map(|(entry, (destination, preview))| {
+                let settings = &entry.settings;
+                let (preflight, skipped, not_newer, error) = match preview {
+                    Preview::Checked(checked) => (Some(checked.clone()), None, None, None),
+                    Preview::Skipped(reason) => (None, Some(reason.clone()), None, None),
+                    Preview::NotNewer(reason) => (None, None, Some(reason.clone()), None),
+                    Preview::Failed(error) => (None, None, None, Some(error.clone())),
+                };
+                BatchEntry {
+                    line: entry.line,
+                    url: http::redact_url(&entry.url),
+                    destination: destination.clone(),
+                    settings: EntrySettings {
+                        output: settings.output.clone(),
+                        checksum: settings.checksum.clone(),
+                        workers: settings.workers.unwrap_or(workers),
+                        headers: settings.header_names(),
+                        tags: settings.tags.clone(),
+                    },
+                    preflight,
+                    skipped,
+                    not_newer,
+                    error,
+                }
+            })
+            .collect();
+        let invalid = listing
+            .invalid
+            .iter()
+            .map(|(line, text)| InvalidLine {
+                line: *line,
+                text: text.clone(),
+            })
+            .collect();
+        let repeats = listing
+            .repeats
+            .iter()
+            .map(|(line, first)| RepeatedLine {
+                line: *line,
+                same_as: *first,
+            })
+            .collect();
+        let batch = BatchPlan {
+            entries,
+            totals,
+            invalid,
+            repeats,
+        };
+        println!("{}", serde_json::to_string_pretty(&Versioned::new(batch))?);
+        return Ok(());
+    }
+
+    println!("Dry run, nothing will be downloaded or written.");
+    for (line, text) in &listing.invalid {
+        println!("Line {line}: not a URL: {text}");
+    }
+    for (line, first) in &listing.repeats {
+        println!("Line {line}: the same URL as line {first}");
+    }
+    println!();
+    println!(
+        "  {:>5}  {:<28}  {:>10}  {:<9}  {:>7}  DESTINATION",
+        "LINE", "STATUS", "SIZE", "RESUMABLE", "WORKERS"
+    );
+    for (entry, (destination, preview)) in entries {
+        let settings = &entry.settings;
+        let (status, size, resumable) = match preview {
+            Preview::Checked(checked) => (
+                match (&checked.error, checked.status) {
+                    (Some(_), Some(status)) => format!("fail ({status})"),
+                    (Some(_), None) => "fail".to_string(),
+                    (None, status) => status.map_or("-".to_string(), |status| status.to_string()),
+                },
+                checked
+                    .size
+                    .map_or("unknown".to_string(), |size| HumanBytes(size).to_string()),
+                if checked.resumable { "yes" } else { "no" },
+            ),
+            Preview::Skipped(reason) => (format!("skip ({reason})"), String::new(), ""),
+            Preview::NotNewer(_) => ("skip (not newer)".to_string(), String::new(), ""),
+            Preview::Failed(_) => ("fail".to_string(), String::new(), ""),
+        };
+        println!(
+            "  {:>5}  {:<28}  {:>10}  {:<9}  {:>7}  {}",
+            entry.line,
+            status,
+            size,
+            resumable,
+            settings.workers.unwrap_or(workers),
+            destination.display()
+        );
+        let mut own = Vec::new();
+        match preview {
+            Preview::Checked(checked) => own.extend(
+                checked
+                    .error
+                    .as_ref()
+                    .map(|error| format!("{error}: {}", checked.url)),
+            ),
+            Preview::Failed(error) => own.push(error.clone()),
+            Preview::NotNewer(reason) => own.push(reason.clone()),
+            Preview::Skipped(_) => {}
+        }
+        if let Some(checksum) = &settings.checksum {
+            own.push(format!("checksum {checksum}"));
+        }
+        if !settings.headers.is_empty() {
+            own.push(format!("headers {}", settings.header_names().join(", ")));
+        }
+        if !settings.tags.is_empty() {
+            own.push(format!("tags {}", settings.tags.join(", ")));
+        }
+        // The entry's own settings, and why it would fail.
+        if !own.is_empty() {
+            println!("  {:>5}  {}", "", own.join("; "));
+        }
+    }
+    println!();
+    println!("{totals}");
+    Ok(())
+}
+
+/// What the entries asked about add up to, counting those that couldn't
+/// be as failing.
+fn totals(previews: &[(PathBuf, Preview)]) -> Totals {
+    let checked = previews
+        .iter()
+        .filter_map(|(_, preview)| match preview {
+            Preview::Checked(checked) => Some(checked.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut totals = Report::new(checked).totals;
+    let unchecked = previews
+        .iter()
+        .filter(|(_, preview)| matches!(preview, Preview::Failed(_)))
+        .count();
+    totals.entries += unchecked;
+    totals.failed += unchecked;
+    totals
+}
+
+/// What a plan's action does, in a few words.
+fn describe(action: &Action) -> String {
+    match action {
+        Action::Create => "create".to_string(),
+        Action::Overwrite => "overwrite the existing file".to_string(),
+        Action::Resume { from } => format!("resume from byte {from}"),
+        Action::Skip { reason } => format!("skip ({reason})"),
+    }
+}
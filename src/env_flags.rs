@@ -0,0 +1,220 @@
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use download_manager::__private::http;
+use download_manager::schema::EnvFlag;
+use std::fmt;
+use url::Url;
+
+/// What every flag's variable starts with: `DM_TARGET_DIRECTORY` stands
+/// in for `--target-directory`, `DM_WORKERS` for `download-async
+/// --workers`.
+pub const PREFIX: &str = "DM_";
+
+/// Flags whose values may be credentials; their variables' values are
+/// never shown.
+const SECRETS: &[&str] = &[
+    "proxy-password",
+    "bearer-token",
+    "auth-token-body",
+    "user",
+    "data",
+    "header",
+];
+
+/// Repeatable flags whose values may hold commas, so their variable takes
+/// a single value rather than a list.
+const WHOLE: &[&str] = &["header"];
+
+/// The variable for the flag `--long`.
+fn variable(long: &str) -> String {
+    format!("{PREFIX}{}", long.replace('-', "_").to_uppercase())
+}
+
+/// `command` with a variable for each of its flags, and its subcommands',
+/// that doesn't have one already. The command line still wins over them.
+/// A repeatable flag's variable takes a comma-separated list, but for
+/// those in [`WHOLE`].
+///
+/// A subcommand's flag named like one of dlm's own has the subcommand in
+/// its variable, as `DM_AUDIT_JSON` for `audit --json`.
+pub fn with_env(command: Command) -> Command {
+    let own: Vec<String> = command
+        .get_arguments()
+        .filter_map(|arg| arg.get_long().map(str::to_string))
+        .collect();
+    add_env(command, &[]).mut_subcommands(|subcommand| under(subcommand, &own))
+}
+
+/// [`add_env`] for a subcommand and its own, under flags named `taken`.
+fn under(command: Command, taken: &[String]) -> Command {
+    add_env(command, taken).mut_subcommands(|subcommand| under(subcommand, taken))
+}
+
+/// Gives `command`'s own flags their variables, with the command's name in
+/// those named like one in `taken`.
+fn add_env(command: Command, taken: &[String]) -> Command {
+    let name = command.get_name().to_string();
+    command.mut_args(|arg| {
+        let Some(long) = arg.get_long().filter(|_| arg.get_env().is_none()) else {
+            return arg;
+        };
+        // A URL may carry a password.
+        let hidden = SECRETS.contains(&long) || takes_url(&arg);
+        let repeated = matches!(arg.get_action(), ArgAction::Append)
+            && arg.get_value_delimiter().is_none()
+            && !WHOLE.contains(&long);
+        let switch = matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse);
+        let variable = match taken.iter().any(|taken| taken == long) {
+            true => variable(&format!("{name}-{long}")),
+            false => variable(long),
+        };
+        let mut arg = arg.env(variable).hide_env_values(hidden);
+        if repeated {
+            arg = arg.value_delimiter(',');
+        }
+        // DM_RESUME=1 or =yes, not only =true.
+        if switch {
+            arg = arg.value_parser(BoolishValueParser::new());
+        }
+        arg
+    })
+}
+
+/// The flags `matches` took from their variables, in the order `command`
+/// (as given to [`with_env`]) declares them, subcommands' last.
+pub fn from_env(command: &Command, matches: &ArgMatches) -> Vec<EnvFlag> {
+    let mut flags: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::EnvVariable))
+        .filter_map(|arg| {
+            let variable = arg.get_env()?.to_string_lossy().into_owned();
+            let secret = arg.get_long().is_some_and(|long| SECRETS.contains(&long));
+            Some(EnvFlag {
+                value: match secret {
+                    true => None,
+                    false => std::env::var(&variable).ok().map(|value| redact(&value)),
+                },
+                variable,
+            })
+        })
+        .collect();
+    if let Some((name, matches)) = matches.subcommand()
+        && let Some(subcommand) = command.find_subcommand(name)
+    {
+        flags.extend(from_env(subcommand, matches));
+    }
+    flags
+}
+
+/// The command line `args` with the values of flags that may be
+/// credentials, and passwords in URLs, redacted, for `--record` and what
+/// `dlm status` shows.
+pub fn redacted_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut secret_next = false;
+    args.into_iter()
+        .map(|arg| {
+            if std::mem::take(&mut secret_next) {
+                return "<redacted>".to_string();
+            }
+            if let Some(flag) = arg.strip_prefix("--") {
+                match flag.split_once('=') {
+                    Some((long, _)) if SECRETS.contains(&long) => {
+                        return format!("--{long}=<redacted>");
+                    }
+                    Some((long, value)) => return format!("--{long}={}", redact(value)),
+                    None if SECRETS.contains(&flag) => secret_next = true,
+                    None => {}
+                }
+            }
+            redact(&arg)
+        })
+        .collect()
+}
+
+/// Whether `arg` takes a URL.
+fn takes_url(arg: &clap::Arg) -> bool {
+    arg.get_value_names()
+        .is_some_and(|names| names.iter().any(|name| name.as_str() == "URL"))
+}
+
+/// `value` with the password of any URL in it redacted, each of a
+/// comma-separated list's too.
+fn redact(value: &str) -> String {
+    value
+        .split(',')
+        .map(|part| match Url::parse(part) {
+            Ok(url) if url.password().is_some() => http::redact_url(&url),
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Where a flag's value came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    /// The variable it was taken from.
+    Environment(String),
+    CommandLine,
+}
+
+/// A flag as the command line, `DM_*` variables and defaults settle it,
+/// for `dlm config show`.
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub flag: String,
+    /// `None` when it's a secret.
+    pub value: Option<String>,
+    pub source: Source,
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value.as_deref().unwrap_or("<redacted>");
+        write!(f, "--{}={value}", self.flag)?;
+        match &self.source {
+            Source::Default => write!(f, " (default)"),
+            Source::Environment(variable) => write!(f, " (from {variable})"),
+            Source::CommandLine => write!(f, " (from the command line)"),
+        }
+    }
+}
+
+/// dlm's own flags that have a value in `matches`, in the order `command`
+/// (as given to [`with_env`]) declares them. Switches left off are left
+/// out.
+pub fn settings(command: &Command, matches: &ArgMatches) -> Vec<Setting> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let flag = arg.get_long()?.to_string();
+            let id = arg.get_id().as_str();
+            let source = match matches.value_source(id)? {
+                ValueSource::DefaultValue => Source::Default,
+                ValueSource::EnvVariable => {
+                    Source::Environment(arg.get_env()?.to_string_lossy().into_owned())
+                }
+                _ => Source::CommandLine,
+            };
+            let values: Vec<_> = matches
+                .get_raw(id)?
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect();
+            let switch = matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse);
+            if switch && source == Source::Default {
+                return None;
+            }
+            let value = match SECRETS.contains(&flag.as_str()) {
+                true => None,
+                false => Some(redact(&values.join(","))),
+            };
+            Some(Setting {
+                flag,
+                value,
+                source,
+            })
+        })
+        .collect()
+}
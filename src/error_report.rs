@@ -0,0 +1,199 @@
+use download_manager::__private::diagnostics;
+use download_manager::__private::etag::EtagMismatch;
+use download_manager::__private::expected_size;
+use download_manager::__private::http;
+use download_manager::__private::progress_handle::ChunkSummary;
+use download_manager::schema::{Environment, ErrorReport, VersionReport, Versioned};
+use download_manager::{ProgressHandle, TransferState};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// The download the run is on, for the report if it fails.
+static CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+
+struct Current {
+    url: String,
+    destination: Option<PathBuf>,
+    progress: Option<ProgressHandle>,
+}
+
+/// Notes the URL the run is downloading.
+pub fn track(url: &Url, destination: Option<&Path>) {
+    *current() = Some(Current {
+        url: http::redact_url(url),
+        destination: destination.map(Path::to_path_buf),
+        progress: None,
+    });
+}
+
+/// Follows the transfer of the download being tracked.
+pub fn attach(handle: ProgressHandle) {
+    if let Some(current) = current().as_mut() {
+        current.progress = Some(handle);
+    }
+}
+
+fn current() -> std::sync::MutexGuard<'static, Option<Current>> {
+    CURRENT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The report of `error`, with what's known about the run.
+pub fn report(error: &anyhow::Error, exit_code: u8, signalled: bool) -> ErrorReport {
+    let current = current();
+    let current = current.as_ref();
+    let snapshot = current
+        .and_then(|current| current.progress.as_ref())
+        .map(ProgressHandle::snapshot);
+    let stopped = snapshot
+        .as_ref()
+        .is_some_and(|snapshot| snapshot.state == TransferState::Interrupted);
+    let version = VersionReport::current();
+    ErrorReport {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64),
+        // The minimum speed guard stops a download the same way, exiting
+        // with 3.
+        cancelled: signalled || (stopped && exit_code != 3),
+        exit_code,
+        errors: error.chain().map(ToString::to_string).collect(),
+        url: current.map(|current| current.url.clone()),
+        destination: current.and_then(|current| current.destination.clone()),
+        downloaded: snapshot.as_ref().map(|snapshot| snapshot.downloaded),
+        total: snapshot.as_ref().map(|snapshot| snapshot.total),
+        chunks: snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.chunks)
+            .filter(|chunks| *chunks != ChunkSummary::default()),
+        last_response: diagnostics::last_response(),
+        retries: diagnostics::retries(),
+        retry_budget: snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.retries)
+            .unwrap_or_default(),
+        warnings: diagnostics::warnings(),
+        etag_mismatches: snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.etag_mismatches.clone())
+            .unwrap_or_default(),
+        size_mismatch: expected_size::mismatch(error),
+        environment: Environment {
+            version: version.version.to_string(),
+            commit: version.commit.to_string(),
+            target: version.target.to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            features: version.features.iter().map(ToString::to_string).collect(),
+        },
+    }
+}
+
+/// Reads a report back, `None` if `text` isn't one.
+pub fn parse(text: &str) -> Option<ErrorReport> {
+    serde_json::from_str(text).ok()
+}
+
+/// Prints `report` for a person.
+pub fn print(report: &ErrorReport) {
+    let status = if report.cancelled {
+        "cancelled"
+    } else {
+        "failed"
+    };
+    println!("Download {status}, exit code {}", report.exit_code);
+    if let Some(url) = &report.url {
+        println!("URL:          {url}");
+    }
+    if let Some(destination) = &report.destination {
+        println!("Destination:  {}", destination.display());
+    }
+    if let Some(downloaded) = report.downloaded {
+        match report.total.filter(|total| *total > 0) {
+            Some(total) => println!("Downloaded:   {downloaded} of {total} bytes"),
+            None => println!("Downloaded:   {downloaded} bytes"),
+        }
+    }
+    if let Some(chunks) = &report.chunks {
+        println!(
+            "Chunks:       {} completed, {} downloading, {} retrying, {} pending, {} failed",
+            chunks.completed, chunks.downloading, chunks.retrying, chunks.pending, chunks.failed
+        );
+    }
+    if let Some(mismatch) = &report.size_mismatch {
+        println!(
+            "Size:         {} bytes by {}, --expected-size {}",
+            mismatch.actual, mismatch.check, mismatch.expected
+        );
+    }
+    if let Some(budget) = report.retry_budget.budget {
+        println!(
+            "Retry budget: {} of {budget} used",
+            report.retry_budget.used
+        );
+    }
+    let environment = &report.environment;
+    println!(
+        "dlm:          {} ({}) on {} {}",
+        environment.version, environment.commit, environment.os, environment.arch
+    );
+    println!();
+    println!("Error:");
+    for (depth, error) in report.errors.iter().enumerate() {
+        match depth {
+            0 => println!("  {error}"),
+            _ => println!("  caused by: {error}"),
+        }
+    }
+    if let Some(response) = &report.last_response {
+        println!();
+        println!("Last response: {} from {}", response.status, response.url);
+        for (name, value) in &response.headers {
+            println!("  {name}: {value}");
+        }
+    }
+    if !report.warnings.is_empty() {
+        println!();
+        println!("Warnings:");
+        for warning in &report.warnings {
+            println!("  [{}] {}", warning.id, warning.message);
+        }
+    }
+    if !report.etag_mismatches.is_empty() {
+        println!();
+        println!("ETag mismatches:");
+        for mismatch in &report.etag_mismatches {
+            println!("  {mismatch}");
+        }
+    }
+    if !report.retries.is_empty() {
+        println!();
+        println!("Retries:");
+        for retry in &report.retries {
+            match retry.chunk {
+                Some(chunk) => println!(
+                    "  chunk {chunk}, attempt {}: {}",
+                    retry.attempt, retry.reason
+                ),
+                None => println!("  attempt {}: {}", retry.attempt, retry.reason),
+            }
+        }
+    }
+}
+
+/// Writes the report of `error` to `path`. Failing to is only warned
+/// about, so the download's own error is the one that's reported.
+pub fn write(path: &Path, error: &anyhow::Error, exit_code: u8, signalled: bool) {
+    let report = Versioned::new(report(error, exit_code, signalled));
+    let written = serde_json::to_vec_pretty(&report)
+        .map_err(std::io::Error::from)
+        .and_then(|json| std::fs::write(path, json));
+    if let Err(write_error) = written {
+        tracing::warn!(
+            "Cannot write the error report to '{}': {write_error}",
+            path.display()
+        );
+    }
+}
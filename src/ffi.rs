@@ -0,0 +1,268 @@
+//! A C ABI for callers outside Rust, behind the `ffi` feature: Python over
+//! ctypes, mostly. `include/download_manager.h` declares it and
+//! `examples/ffi_download.py` uses it.
+//!
+//! [`dm_download`] takes the JSON `dlm --dry-run --json` prints, so a plan
+//! worked out by the CLI can be handed over as is, and blocks until the
+//! download is over, answering with the CLI's exit codes. Its progress
+//! callback gets one JSON object per event:
+//!
+//! - `{"event": "started", "handle": 1}`, the handle [`dm_cancel`] takes,
+//!   also written to `dm_download`'s `handle` before it blocks;
+//! - `{"event": "progress", "downloaded": ..., "total": ..., ...}`, the
+//!   fields of a [`ProgressSnapshot`](crate::ProgressSnapshot);
+//! - `{"event": "finished", "path": "..."}`, or `{"event": "skipped",
+//!   "reason": "..."}` for a plan that skips;
+//! - `{"event": "failed", "error": "...", "code": 1}`.
+//!
+//! Panics become [`DM_PANIC`] rather than unwinding into the caller.
+
+use crate::download::error;
+use crate::download::plan::Action;
+use crate::download::progress_handle::ProgressSnapshot;
+use crate::download::schema::Versioned;
+use crate::{ClientOptions, TransferOptions, TransferProgress};
+use crate::{download_file_async, download_with_workers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use url::Url;
+
+/// Returned when the download panicked, as a Rust program exits with.
+pub const DM_PANIC: i32 = 101;
+
+/// Called with each progress event, a NUL-terminated JSON object only valid
+/// for the call.
+type ProgressCallback = extern "C" fn(event: *const c_char);
+
+/// What the progress callback gets, under `event`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Started { handle: u64 },
+    Progress(Versioned<&'a ProgressSnapshot>),
+    Finished { path: &'a Path },
+    Skipped { reason: &'a str },
+    Failed { error: &'a str, code: i32 },
+}
+
+/// The downloads [`dm_download`] is running, by handle.
+static RUNNING: Mutex<BTreeMap<u64, Arc<AtomicBool>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// A download's entry in [`RUNNING`], removed when dropped, panicking or
+/// not.
+struct Running {
+    handle: u64,
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Running {
+    fn register() -> Self {
+        let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+        let interrupted = Arc::new(AtomicBool::new(false));
+        lock_running().insert(handle, interrupted.clone());
+        Self {
+            handle,
+            interrupted,
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        lock_running().remove(&self.handle);
+    }
+}
+
+/// Nothing panics holding the lock, but a poisoned map is still usable.
+fn lock_running() -> MutexGuard<'static, BTreeMap<u64, Arc<AtomicBool>>> {
+    RUNNING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How often progress is reported at most.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The part of a plan a download goes by.
+#[derive(Deserialize)]
+struct Config {
+    url: Url,
+    /// Relative to the working directory when not absolute. Derived from
+    /// the URL when missing.
+    destination: Option<PathBuf>,
+    action: Option<Action>,
+    size: Option<u64>,
+    /// A single stream when missing.
+    workers: Option<u8>,
+}
+
+/// Downloads what `config_json`, a plan as `dlm --dry-run --json` prints it,
+/// describes, blocking until it's done, and returns `dlm`'s exit code for
+/// how it went. `progress_cb`, if not null, is called on this thread with
+/// every event. `handle`, if not null, is set to the handle [`dm_cancel`]
+/// takes before anything else happens, atomically, so another thread can
+/// poll it: handles are never 0.
+///
+/// # Safety
+///
+/// `config_json` must be a valid NUL-terminated string, and `handle` null
+/// or a `uint64_t` aligned to 8 bytes that outlives the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dm_download(
+    config_json: *const c_char,
+    progress_cb: Option<extern "C" fn(event: *const c_char)>,
+    handle: *mut u64,
+) -> i32 {
+    let running = Running::register();
+    if !handle.is_null() {
+        // SAFETY: the caller passes an aligned uint64_t that outlives the
+        // call, which only this thread writes to.
+        unsafe { AtomicU64::from_ptr(handle) }.store(running.handle, Ordering::SeqCst);
+    }
+    if config_json.is_null() {
+        emit_failure(progress_cb, "The config is null", 2);
+        return 2;
+    }
+    // SAFETY: the caller passes a valid NUL-terminated string.
+    let config = unsafe { CStr::from_ptr(config_json) };
+    panic::catch_unwind(AssertUnwindSafe(|| download(config, progress_cb, &running)))
+        .unwrap_or_else(|_| {
+            emit_failure(progress_cb, "The download panicked", DM_PANIC);
+            DM_PANIC
+        })
+}
+
+/// Stops the download `handle` names, making its [`dm_download`] return.
+/// Returns 0, or 1 when no download has that handle (any more).
+#[unsafe(no_mangle)]
+pub extern "C" fn dm_cancel(handle: u64) -> i32 {
+    let cancelled = panic::catch_unwind(|| {
+        lock_running()
+            .get(&handle)
+            .map(|interrupted| interrupted.store(true, Ordering::SeqCst))
+            .is_some()
+    });
+    match cancelled {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(_) => DM_PANIC,
+    }
+}
+
+fn download(config: &CStr, progress_cb: Option<ProgressCallback>, running: &Running) -> i32 {
+    let config: Config = match serde_json::from_slice(config.to_bytes()) {
+        Ok(config) => config,
+        Err(error) => {
+            emit_failure(progress_cb, &format!("Invalid config: {error}"), 2);
+            return 2;
+        }
+    };
+    let mut options = TransferOptions {
+        output: config.destination.clone(),
+        ..TransferOptions::default()
+    };
+    match config.action {
+        Some(Action::Skip { reason }) => {
+            emit(progress_cb, &Event::Skipped { reason: &reason });
+            return 0;
+        }
+        Some(Action::Resume { .. }) => options.resume = true,
+        Some(Action::Overwrite) => options.overwrite = true,
+        Some(Action::Create) | None => {}
+    }
+
+    emit(
+        progress_cb,
+        &Event::Started {
+            handle: running.handle,
+        },
+    );
+    let result = run(config, &options, running.interrupted.clone(), progress_cb);
+
+    match result {
+        Ok(path) => {
+            emit(progress_cb, &Event::Finished { path: &path });
+            0
+        }
+        Err(error) => {
+            let code = i32::from(error::exit_code(&error));
+            emit_failure(progress_cb, &format!("{error:#}"), code);
+            code
+        }
+    }
+}
+
+fn run(
+    config: Config,
+    options: &TransferOptions,
+    interrupted: Arc<AtomicBool>,
+    progress_cb: Option<ProgressCallback>,
+) -> anyhow::Result<PathBuf> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let client = ClientOptions::default().build_async()?;
+    let workers = config.workers.unwrap_or(1).max(1);
+    let progress = match workers {
+        1 => TransferProgress::new(interrupted),
+        _ => TransferProgress::chunked(
+            workers as usize,
+            config.size.unwrap_or_default(),
+            interrupted,
+        ),
+    };
+    let mut handle = progress.handle();
+    let target_dir = Path::new(".");
+
+    // Both run on this thread, so the callback is only ever called from
+    // the caller's.
+    runtime.block_on(async {
+        let download = async {
+            match workers {
+                1 => download_file_async(&client, config.url, target_dir, progress, options).await,
+                _ => {
+                    download_with_workers(
+                        &client, config.url, target_dir, workers, progress, options,
+                    )
+                    .await
+                }
+            }
+        };
+        let report = async {
+            let Some(progress_cb) = progress_cb else {
+                return;
+            };
+            while handle.changed().await {
+                let snapshot = handle.snapshot();
+                emit(
+                    Some(progress_cb),
+                    &Event::Progress(Versioned::new(&snapshot)),
+                );
+                if snapshot.state.is_terminal() {
+                    break;
+                }
+                tokio::time::sleep(PROGRESS_INTERVAL).await;
+            }
+        };
+        let (result, ()) = tokio::join!(download, report);
+        result
+    })
+}
+
+fn emit(progress_cb: Option<ProgressCallback>, event: &Event) {
+    let Some(progress_cb) = progress_cb else {
+        return;
+    };
+    // JSON escapes any NUL in a string.
+    let event = serde_json::to_string(event).expect("an event serializes");
+    let event = CString::new(event).expect("JSON has no NUL bytes");
+    progress_cb(event.as_ptr());
+}
+
+fn emit_failure(progress_cb: Option<ProgressCallback>, error: &str, code: i32) {
+    emit(progress_cb, &Event::Failed { error, code });
+}
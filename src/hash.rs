@@ -0,0 +1,160 @@
+use anyhow::{Context, bail};
+use download_manager::__private::checksum::{self, Algorithm, ChecksumOf, SumsLine};
+use download_manager::__private::speed::{Rate, Size};
+use download_manager::MemoryLimit;
+use indicatif::ProgressState;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+/// Files at least this big get a progress bar while they're hashed.
+const LARGE_FILE: u64 = 64 * 1024 * 1024;
+
+/// Prints a `sha256sum`-style line for each of `paths`. Unreadable files
+/// are reported and skipped, failing the command at the end.
+pub fn print_sums(
+    paths: &[impl AsRef<Path>],
+    algorithm: Algorithm,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<()> {
+    let mut unreadable = 0;
+    for path in paths {
+        let path = path.as_ref();
+        match hash_with_progress(path, ChecksumOf::File, algorithm, "Hashing", None, memory) {
+            Ok(hash) => {
+                let line = SumsLine {
+                    hash: hex::encode(hash),
+                    path: path.to_path_buf(),
+                };
+                println!("{line}");
+            }
+            Err(error) => {
+                eprintln!("{}: {error:#}", path.display());
+                unreadable += 1;
+            }
+        }
+    }
+    if unreadable > 0 {
+        bail!("{unreadable} of {} files could not be read", paths.len());
+    }
+    Ok(())
+}
+
+/// Checks the files listed in the sums file at `sums`, printing `OK` or
+/// `FAILED` for each, and fails if any of them didn't match. Paths are
+/// taken relative to the current directory, as with `sha256sum --check`.
+pub fn check_sums(
+    sums: &Path,
+    algorithm: Algorithm,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(sums)
+        .with_context(|| format!("Cannot read sums file '{}'", sums.display()))?;
+    let (mut checked, mut failed, mut unreadable, mut improper) = (0, 0, 0, 0);
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some(expected) = SumsLine::parse(line, algorithm) else {
+            improper += 1;
+            continue;
+        };
+        checked += 1;
+        let name = expected.path.display();
+        match hash_with_progress(
+            &expected.path,
+            ChecksumOf::File,
+            algorithm,
+            "Checking",
+            None,
+            memory,
+        ) {
+            Ok(hash) if hex::encode(&hash) == expected.hash => println!("{name}: OK"),
+            Ok(_) => {
+                println!("{name}: FAILED");
+                failed += 1;
+            }
+            Err(error) => {
+                println!("{name}: FAILED open or read");
+                eprintln!("{name}: {error:#}");
+                unreadable += 1;
+            }
+        }
+    }
+
+    if improper > 0 {
+        eprintln!("WARNING: {improper} lines are improperly formatted for {algorithm}");
+    }
+    if checked == 0 {
+        bail!("No {algorithm} sums found in '{}'", sums.display());
+    }
+    if unreadable > 0 {
+        eprintln!("WARNING: {unreadable} listed files could not be read");
+    }
+    if failed > 0 {
+        eprintln!("WARNING: {failed} computed checksums did NOT match");
+    }
+    if failed + unreadable > 0 {
+        bail!(
+            "{} of {checked} files failed the check against '{}'",
+            failed + unreadable,
+            sums.display()
+        );
+    }
+    Ok(())
+}
+
+/// Hashes `path`, or what it decompresses to, with a progress bar for
+/// large files labelled with what the hash is for, until `interrupted` is
+/// set. The read buffer counts against `memory`.
+pub fn hash_with_progress(
+    path: &Path,
+    of: ChecksumOf,
+    algorithm: Algorithm,
+    label: &str,
+    interrupted: Option<&AtomicBool>,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<Vec<u8>> {
+    let size = std::fs::metadata(path)?.len();
+    if size < LARGE_FILE {
+        return Ok(checksum::hash_of(
+            path,
+            of,
+            algorithm,
+            interrupted,
+            memory,
+            |_| {},
+        )?);
+    }
+    let bar = progress_bar(size, format!("{label} {algorithm} of {}", path.display()))?;
+    let result = checksum::hash_of(path, of, algorithm, interrupted, memory, |hashed| {
+        bar.set_position(hashed)
+    });
+    bar.finish_and_clear();
+    Ok(result?)
+}
+
+/// A bar for `len` bytes of hashing, showing `message`.
+pub fn progress_bar(len: u64, message: String) -> anyhow::Result<indicatif::ProgressBar> {
+    Ok(indicatif::ProgressBar::new(len)
+        .with_style(
+            indicatif::ProgressStyle::with_template(
+                "{msg} {percent}% [{wide_bar}] {size}/{total_size} ({rate}, {eta})",
+            )?
+            .with_key("size", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                let _ = write!(w, "{}", Size(state.pos()));
+            })
+            .with_key(
+                "total_size",
+                |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    let _ = write!(w, "{}", Size(state.len().unwrap_or(0)));
+                },
+            )
+            .with_key("rate", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                let _ = write!(w, "{}", Rate(state.per_sec() as u64));
+            })
+            .progress_chars("=> "),
+        )
+        .with_message(message))
+}
@@ -0,0 +1,70 @@
+//! Download engine behind the `dlm` binary, usable on its own.
+//!
+//! Most embedders want [`Downloader`], which saves a file over one stream
+//! or several workers, or streams it into any async consumer without
+//! touching the filesystem. Callers without a tokio runtime can use
+//! [`BlockingDownloader`] instead, behind the default `blocking` feature.
+//! Failures worth telling apart, like the file being in the way or the
+//! download being cancelled, are [`DownloadError`]s to downcast the
+//! `anyhow::Error` to. Either downloader runs [`PostProcessor`] steps of the
+//! caller's on a finished download.
+//!
+//! To show progress their own way, callers hand a download a
+//! [`TransferProgress`] and draw it with a [`Renderer`] of theirs, or watch
+//! its [`ProgressHandle`]; `examples/` has both a plain download and one
+//! with a progress line of its own. Several downloads at once go through a
+//! [`DownloadPool`], which shares limits between them and adds their
+//! progress up.
+//!
+//! Everything `dlm` writes as JSON, from `--json` output to the manifests
+//! and logs it keeps, is typed in [`schema`] and carries a `schema_version`
+//! that goes up whenever one of those shapes changes.
+//!
+//! Other languages can call a C ABI over it, behind the `ffi` feature.
+
+// The modules `dlm` is built from. What the library offers is re-exported
+// below; the binary reaches the rest through `__private`.
+pub(crate) mod download;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub use download::auth_token::{AuthToken, TokenPath, TokenSource};
+#[cfg(feature = "blocking")]
+pub use download::blocking::{BlockingDownloader, DownloadResult};
+pub use download::chunks::Workers;
+pub use download::client::ClientOptions;
+pub use download::error::DownloadError;
+pub use download::memory::MemoryLimit;
+pub use download::options::TransferOptions;
+pub use download::pool::{
+    DownloadPool, DownloadRequest, PoolEvent, PoolHandle, PoolOptions, PoolTotals,
+};
+pub use download::postprocess::{
+    DownloadOutcome, Hash, Pipeline, PostProcessor, StepFailed, StepTiming,
+};
+pub use download::progress::TransferProgress;
+pub use download::progress_handle::{ProgressHandle, ProgressSnapshot, TransferState};
+pub use download::render::{JsonLines, PlainText, Renderer, Silent};
+pub use download::retry_budget::{RetryBudget, RetryUsage};
+pub use download::schema;
+pub use download::{DownloadStream, Downloader, download_file_async, download_with_workers};
+
+/// What `dlm` is built from beyond the library: its flags, some of which
+/// are kept process-wide, and the steps of its commands. Not part of the
+/// library, and free to change in any release.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "blocking")]
+    pub use crate::download::extract_zip_member;
+    pub use crate::download::{
+        RepairSummary, adopt, cache, checksum, chunk_log, chunks, client, clock, compare, compress,
+        conflicts, connections, cosign, diagnostics, dns, download_tail, error, etag,
+        expected_size, fairness, fd_limit, filesystem, fs_ops, get_content_length, host_health,
+        http, in_place, inodes, landing, mirrors, naming, network_wait, newer, one_connection,
+        options, pacing, partial, parts, pieces, plan, plan_download, postprocess, preflight,
+        presigned, progress, progress_handle, proxy, quota, rate_limit, releases, remote, removal,
+        render, repair_file, resolve_landing_page, retry, retry_budget, segment_tuning, speed,
+        stall, suspicious, target_wait, throttle, tls, torrent, tracking, transaction, transcript,
+        utils, validator,
+    };
+}
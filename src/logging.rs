@@ -0,0 +1,201 @@
+use anyhow::Context;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(feature = "otel")]
+use tracing::Level;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+#[cfg(feature = "otel")]
+use tracing_subscriber::filter::{FilterExt, filter_fn};
+use tracing_subscriber::prelude::*;
+use url::Url;
+
+/// Keeps the trace exporter alive until the end of the run.
+pub struct Guard {
+    #[cfg(feature = "otel")]
+    _exporter: Option<crate::otel::Exporter>,
+}
+
+/// `--log-max-size` and `--log-keep`: once the log file reaches
+/// `max_size` bytes it's renamed to `.1`, the previous `.1` to `.2` and so
+/// on, keeping `keep` of them, and a fresh file is started.
+#[derive(Clone, Copy, Debug)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+/// Sets up the global tracing subscriber.
+///
+/// `verbosity` is the number of `-v` flags: warnings only by default, info
+/// at `-v`, request/response headers at `-vv` and everything at `-vvv`. Only
+/// our own events are raised, dependencies stay at warnings. The log file,
+/// if any, gets at least debug so a `--log-file` run is always useful, and
+/// is rotated as it starts and whenever it outgrows `rotation`.
+///
+/// Download and chunk spans are at trace level, so they cost nothing unless
+/// `-vvv` or an `--otel-endpoint` (with the `otel` feature) asks for them.
+pub fn init(
+    verbosity: u8,
+    log_file: Option<&Path>,
+    rotation: Option<Rotation>,
+    otel_endpoint: Option<&Url>,
+) -> anyhow::Result<Guard> {
+    let level = match verbosity {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_target(false)
+        .with_filter(targets(level));
+
+    let file = match log_file {
+        Some(path) => {
+            let file = LogFile::open(path, rotation)
+                .with_context(|| format!("Cannot open log file '{}'", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(targets(level.max(LevelFilter::DEBUG)));
+            Some(layer)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "otel")]
+    let (otel, exporter) = match otel_endpoint {
+        Some(endpoint) => {
+            let (layer, exporter) = crate::otel::layer(endpoint)?;
+            // Spans, plus the events worth seeing next to them.
+            let filter = targets(LevelFilter::TRACE).and(filter_fn(|metadata| {
+                metadata.is_span() || *metadata.level() <= Level::INFO
+            }));
+            (Some(layer.with_filter(filter)), Some(exporter))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel: Option<tracing_subscriber::layer::Identity> = {
+        // The flag only exists with the feature.
+        let _ = otel_endpoint;
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .with(otel)
+        .try_init()
+        .context("Could not set up logging")?;
+    Ok(Guard {
+        #[cfg(feature = "otel")]
+        _exporter: exporter,
+    })
+}
+
+fn targets(level: LevelFilter) -> Targets {
+    Targets::new()
+        .with_default(LevelFilter::WARN)
+        .with_target("dlm", level)
+        .with_target("download_manager", level)
+}
+
+/// The `--log-file`, rotated by size if asked to. Every event is written
+/// straight through, so a run that dies leaves all it logged behind.
+struct LogFile {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    file: File,
+    /// Roughly how big the file at `path` is: what it held when opened plus
+    /// what this process wrote since. Others may be appending too.
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: Option<Rotation>) -> io::Result<Self> {
+        let mut log = Self {
+            path: path.to_path_buf(),
+            rotation,
+            file: append(path)?,
+            size: 0,
+        };
+        log.size = log.file.metadata()?.len();
+        if log
+            .rotation
+            .is_some_and(|rotation| log.size >= rotation.max_size)
+        {
+            log.rotate(0)?;
+        }
+        Ok(log)
+    }
+
+    /// Renames the files along and starts a fresh one if the file at `path`
+    /// is full, or can't take `incoming` more bytes; another `dlm` logging
+    /// to the same path may have got there first. The renames happen under
+    /// a lock on `<path>.lock`, so runs sharing the file don't trip over
+    /// each other; either way this reopens `path`, which is where later
+    /// lines belong.
+    fn rotate(&mut self, incoming: u64) -> io::Result<()> {
+        let Some(rotation) = self.rotation else {
+            return Ok(());
+        };
+        let lock = append(&suffixed(&self.path, "lock"))?;
+        lock.lock()?;
+        let size = fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+        if size >= rotation.max_size || (size > 0 && size + incoming > rotation.max_size) {
+            match rotation.keep {
+                0 => fs::remove_file(&self.path)?,
+                keep => {
+                    for n in (1..keep).rev() {
+                        let from = suffixed(&self.path, &n.to_string());
+                        if from.exists() {
+                            fs::rename(from, suffixed(&self.path, &(n + 1).to_string()))?;
+                        }
+                    }
+                    fs::rename(&self.path, suffixed(&self.path, "1"))?;
+                }
+            }
+        }
+        self.file = append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        lock.unlock()
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A line that doesn't fit starts the next file, rather than being
+        // split between two.
+        if let Some(rotation) = self.rotation
+            && self.size > 0
+            && self.size + buf.len() as u64 > rotation.max_size
+        {
+            self.rotate(buf.len() as u64)?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `path` with `.suffix` added, e.g. `dlm.log.1`.
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
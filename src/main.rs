@@ -1,11 +1,87 @@
 #![allow(unused)]
 
 use clap::Parser;
+use download_manager::__private::cosign;
+use download_manager::__private::expected_size;
+use download_manager::__private::render;
+use download_manager::__private::transcript;
+use download_manager::schema::ProgressEvent;
+use std::process::ExitCode;
+mod audit;
+mod batch;
 mod cli;
-mod download;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod control;
+mod dry_run;
+mod env_flags;
+mod error_report;
+mod hash;
+mod logging;
+#[cfg(feature = "otel")]
+mod otel;
+mod panic_hook;
+mod phases;
+mod post_steps;
+mod replaced;
+mod replay;
+mod report;
+mod resume_all;
+mod shutdown;
+mod snapshot;
+mod state;
+mod stats;
+mod status_file;
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+mod systemd;
+mod title;
+mod usage;
+mod version;
+mod web_status;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = cli::Cli::parse();
-    cli.execute().await
+async fn main() -> ExitCode {
+    let cli = cli::Cli::parse_with_env();
+    panic_hook::install();
+    let shutdown = match shutdown::Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(error) => {
+            eprintln!("Error: Cannot catch Ctrl+C: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = snapshot::install() {
+        eprintln!("Warning: SIGUSR1 won't print a snapshot: {error}");
+    }
+    let error_report = cli.error_report();
+    let progress_events = cli.progress_events();
+    if let Err(error) = cli.record() {
+        eprintln!("Error: {error:#}");
+        return ExitCode::FAILURE;
+    }
+    match cli.execute(&shutdown).await {
+        Ok(()) => {
+            transcript::outcome(0, None);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            let code = download_manager::__private::error::exit_code(&error);
+            transcript::outcome(code, Some(format!("{error:#}")));
+            if let Some(events) = progress_events {
+                let message = format!("{error:#}");
+                let event = ProgressEvent::Error {
+                    message,
+                    exit_code: code,
+                    size_mismatch: expected_size::mismatch(&error),
+                    signature: cosign::failed(&error),
+                };
+                render::write_event(events, &event);
+            }
+            if let Some(path) = error_report {
+                error_report::write(&path, &error, code, shutdown.is_requested());
+            }
+            ExitCode::from(code)
+        }
+    }
 }
@@ -0,0 +1,48 @@
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+use url::Url;
+
+/// Flushes and stops the exporter when dropped, so spans of a download that
+/// just finished still make it out before the process exits.
+pub struct Exporter {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(error) = self.provider.shutdown() {
+            eprintln!("Could not export traces: {error}");
+        }
+    }
+}
+
+/// A layer turning our spans into OTLP spans sent to `endpoint` over HTTP.
+/// A bare collector URL like `http://collector:4318` gets the standard
+/// `/v1/traces` path.
+pub fn layer<S>(endpoint: &Url) -> anyhow::Result<(OpenTelemetryLayer<S, SdkTracer>, Exporter)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let mut endpoint = endpoint.clone();
+    if endpoint.path() == "/" {
+        endpoint.set_path("/v1/traces");
+    }
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.as_str())
+        .build()
+        .context("Could not set up the OTLP exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("dlm").build())
+        .build();
+    let tracer = provider.tracer("dlm");
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    Ok((layer, Exporter { provider }))
+}
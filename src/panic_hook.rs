@@ -0,0 +1,34 @@
+//! What a panic leaves behind. Left to the default hook, one mid-download
+//! has its message drawn over by the progress, which goes on spinning while
+//! the other threads carry on, and no tracker gets to write the download's
+//! manifest down.
+
+use crate::{state, title};
+use download_manager::__private::{render, transcript};
+use std::io::{IsTerminal, Write};
+
+/// Exit code after a panic, as Rust programs exit with, and as the C API's
+/// `DM_PANIC` is.
+const PANICKED: u8 = 101;
+
+/// Replaces the panic hook for the rest of the run: a panic on any thread
+/// takes the progress off the screen, gives the terminal its cursor and
+/// title back, prints the message below, writes down how far every running
+/// download got so it can be resumed, and exits with code 101.
+pub fn install() {
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        render::clear_bars();
+        let mut stderr = std::io::stderr();
+        if stderr.is_terminal() {
+            // Show the cursor.
+            let _ = stderr.write_all(b"\x1b[?25h");
+            let _ = stderr.flush();
+        }
+        title::restore();
+        report(info);
+        state::flush_running();
+        transcript::outcome(PANICKED, Some(format!("panicked: {info}")));
+        std::process::exit(PANICKED.into());
+    }));
+}
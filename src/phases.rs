@@ -0,0 +1,186 @@
+//! What a download goes through around its transfer, the same whether it's
+//! the run's only one or an entry of an `--input-file` batch: checking it
+//! fits `--dir-quota`, asking the server whether it's `--newer-than` the
+//! file wanted, and reusing or filling `--cache-dir`. How the bytes are
+//! transferred is the caller's, as are the steps after them.
+
+use anyhow::{Context, bail};
+use download_manager::__private::cache::{Cache, Lookup};
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::get_content_length;
+use download_manager::__private::newer::{self, NewerThan};
+use download_manager::__private::quota::{self, DirQuota};
+use download_manager::__private::removal::{Removal, Removed};
+use download_manager::ClientOptions;
+use download_manager::MemoryLimit;
+use download_manager::ProgressSnapshot;
+use download_manager::RetryBudget;
+use download_manager::{DownloadOutcome, Pipeline, StepTiming};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// The flags each phase goes by, for one download.
+#[derive(Clone)]
+pub struct Phases {
+    pub client_options: ClientOptions,
+    /// What the phases' requests retry within, the run's `--retry-budget`.
+    pub retry_budget: Arc<RetryBudget>,
+    /// The buffers `--max-memory` caps, which hashing counts against.
+    pub memory: Arc<MemoryLimit>,
+    pub target_directory: PathBuf,
+    pub quota: DirQuota,
+    /// `--resume` or `--continue-at`: only what's missing is downloaded.
+    pub resuming: bool,
+    /// Download past `--dir-quota-hard` anyway.
+    pub force: bool,
+    pub newer_than: Option<NewerThan>,
+    pub cache: Option<Cache>,
+    pub overwrite: bool,
+    pub removal: Removal,
+}
+
+impl Phases {
+    /// Checks that the download of `url` to `destination` fits
+    /// `--dir-quota` and `--dir-quota-hard`, returning what the target
+    /// directory held before it.
+    pub async fn check_quota(&self, url: &Url, destination: &Path) -> anyhow::Result<Option<u64>> {
+        let quota = self.quota;
+        if !quota.is_set() {
+            return Ok(None);
+        }
+        let dir = &self.target_directory;
+        let usage =
+            quota::dir_usage(dir).with_context(|| format!("Cannot size up '{}'", dir.display()))?;
+        let client = self.client_options.build_async()?;
+        let remote = match get_content_length(
+            &client,
+            url,
+            &self.retry_budget,
+            self.client_options.auth_token.as_deref(),
+        )
+        .await
+        {
+            Ok(remote) => remote,
+            Err(error) => {
+                diagnostics::warn_or_fail(
+                    WarningId::DirQuota,
+                    format!("Counting the download as empty for the quota: {error:#}"),
+                )?;
+                0
+            }
+        };
+        // Resuming only adds what's missing.
+        let local = match self.resuming {
+            true => fs::metadata(destination).map_or(0, |metadata| metadata.len()),
+            false => 0,
+        };
+        match quota.check(dir, usage, remote.saturating_sub(local)) {
+            Ok(None) => {}
+            Ok(Some(warning)) => diagnostics::warn_or_fail(WarningId::DirQuota, warning)?,
+            Err(error) if self.force => diagnostics::warn_or_fail(
+                WarningId::DirQuota,
+                format!("{error}, downloading anyway"),
+            )?,
+            Err(error) => {
+                return Err(anyhow::Error::new(error).context("Pass --force to download anyway"));
+            }
+        }
+        Ok(Some(usage))
+    }
+
+    /// Asks the server whether `url` changed after `--newer-than`. Returns
+    /// why it's not worth downloading, or `None` when it is.
+    pub async fn not_newer(&self, url: &Url) -> anyhow::Result<Option<String>> {
+        let Some(since) = self.newer_than else {
+            return Ok(None);
+        };
+        let client = self.client_options.build_async()?;
+        newer::not_newer(
+            &client,
+            url,
+            since,
+            &self.retry_budget,
+            self.client_options.auth_token.as_deref(),
+        )
+        .await
+    }
+
+    /// Gets `url` to `destination` and post-processes it. Through
+    /// `--cache-dir`, the cached copy is reused if the server says it's
+    /// current; otherwise `transfer` downloads it, returning where it
+    /// landed and how it went, and the result is kept.
+    pub async fn transfer<F, T>(
+        &self,
+        url: &Url,
+        destination: &Path,
+        post_processing: &Pipeline,
+        transfer: F,
+    ) -> anyhow::Result<(DownloadOutcome, Vec<StepTiming>)>
+    where
+        F: FnOnce() -> T,
+        T: Future<Output = anyhow::Result<(PathBuf, ProgressSnapshot)>>,
+    {
+        let Some(cache) = &self.cache else {
+            let (path, snapshot) = transfer().await?;
+            let mut outcome =
+                DownloadOutcome::new(url.clone(), path, snapshot, self.memory.clone());
+            let timings = post_processing.run(&mut outcome)?;
+            return Ok((outcome, timings));
+        };
+        let client = self.client_options.build_async()?;
+        let validators = match cache
+            .revalidate(
+                &client,
+                url,
+                &self.retry_budget,
+                self.client_options.auth_token.as_deref(),
+                &self.memory,
+            )
+            .await?
+        {
+            Lookup::Fresh(entry) => {
+                if destination.exists() && !self.overwrite {
+                    bail!("File exists at '{}'", destination.display());
+                }
+                cache.restore(url, &entry, destination)?;
+                println!("Not modified, reused the cached copy");
+                let hash = entry.hash().context("Cache entry has a malformed hash")?;
+                let mut outcome = DownloadOutcome::new(
+                    url.clone(),
+                    destination.to_path_buf(),
+                    Default::default(),
+                    self.memory.clone(),
+                );
+                outcome.sha256 = Some(hash);
+                return Ok((outcome, Vec::new()));
+            }
+            Lookup::Stale(validators) => validators,
+        };
+        // The old file may be a link to the cached copy; overwriting it in
+        // place would change that too.
+        if self.overwrite && destination.is_file() {
+            replace(self.removal, destination)?;
+        }
+        let (path, snapshot) = transfer().await?;
+        let mut outcome = DownloadOutcome::new(url.clone(), path, snapshot, self.memory.clone());
+        let timings = post_processing.run(&mut outcome)?;
+        let hash = outcome.sha256.expect("the hash step runs first");
+        cache.store(url, &outcome.path, validators, hash)?;
+        Ok((outcome, timings))
+    }
+}
+
+/// Removes the file at `path` before `--overwrite` replaces it, saying
+/// where it went if not deleted for good.
+pub fn replace(removal: Removal, path: &Path) -> anyhow::Result<Removed> {
+    let removed = removal
+        .remove_file(path)
+        .with_context(|| format!("Cannot remove '{}'", path.display()))?;
+    if removed == Removed::Trashed {
+        println!("The existing '{}' was {removed}", path.display());
+    }
+    Ok(removed)
+}
@@ -0,0 +1,349 @@
+use crate::hash;
+use anyhow::Context;
+use colored::Colorize;
+use download_manager::__private::checksum::{Algorithm, Checksum, ChecksumOf};
+#[cfg(feature = "sigstore")]
+use download_manager::__private::cosign::{self, Cosign};
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::fs_ops;
+use download_manager::__private::http;
+use download_manager::__private::naming::{self, NameTemplate, Settled};
+use download_manager::__private::suspicious;
+#[cfg(feature = "torrent")]
+use download_manager::__private::torrent::Torrent;
+#[cfg(feature = "sigstore")]
+use download_manager::ClientOptions;
+use download_manager::DownloadError;
+#[cfg(feature = "sigstore")]
+use download_manager::RetryBudget;
+use download_manager::{DownloadOutcome, PostProcessor};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+
+/// The SHA-256 of a finished download, with a progress bar, unless the
+/// transfer already worked it out. Ctrl+C stops it, leaving the file in
+/// place but unverified.
+pub struct Hash {
+    pub interrupted: Arc<AtomicBool>,
+}
+
+/// Warns about a download that looks like an error page rather than the
+/// file, failing unless `--allow-suspicious`.
+pub struct Suspicious {
+    pub allow: bool,
+}
+
+/// `--checksum`, `--sha256`, `--sha1` or `--md5`. Only a digest of
+/// something else is computed: of the decompressed contents, or with
+/// another algorithm. A download that doesn't match is renamed to
+/// `<name>.corrupt` unless `keep`.
+pub struct Verify {
+    pub expected: Expected,
+    pub of: ChecksumOf,
+    pub keep: bool,
+    pub interrupted: Arc<AtomicBool>,
+}
+
+/// The digest a download should have.
+#[derive(Clone, Debug)]
+pub enum Expected {
+    /// Given on the command line, to `flag`.
+    Digest {
+        checksum: Checksum,
+        flag: &'static str,
+    },
+    /// Listed for the download's name in a `sha256sum`-style file.
+    Listed { sums: PathBuf, algorithm: Algorithm },
+}
+
+impl Expected {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Expected::Digest { checksum, .. } => checksum.algorithm,
+            Expected::Listed { algorithm, .. } => *algorithm,
+        }
+    }
+
+    /// `--sha256` and the like: a digest as long as `algorithm`'s, or else
+    /// the path of a sums file.
+    pub fn parse(value: &str, algorithm: Algorithm, flag: &'static str) -> Result<Self, String> {
+        if value.len() == algorithm.hex_len() && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let checksum = format!("{algorithm}:{value}").parse()?;
+            return Ok(Expected::Digest { checksum, flag });
+        }
+        let sums = PathBuf::from(value);
+        match sums.is_file() {
+            true => Ok(Expected::Listed { sums, algorithm }),
+            false => Err(format!(
+                "'{value}' is neither a {algorithm} digest ({} hex digits) nor a sums file",
+                algorithm.hex_len()
+            )),
+        }
+    }
+
+    /// The digest for the download at `path`, and who expects it: the
+    /// flag, or the sums file.
+    fn resolve(&self, path: &Path) -> anyhow::Result<(Checksum, String)> {
+        match self {
+            Expected::Digest { checksum, flag } => Ok((checksum.clone(), flag.to_string())),
+            Expected::Listed { sums, algorithm } => {
+                let text = std::fs::read_to_string(sums)
+                    .with_context(|| format!("Cannot read sums file '{}'", sums.display()))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let checksum = Checksum::listed(&text, *algorithm, &name).with_context(|| {
+                    format!("'{}' lists no {algorithm} for '{name}'", sums.display())
+                })?;
+                Ok((checksum, format!("'{}'", sums.display())))
+            }
+        }
+    }
+}
+
+/// `--cosign-signature-url`: the download's signature, fetched through a
+/// client like the download's and checked with the sigstore crates.
+#[cfg(feature = "sigstore")]
+pub struct Signature {
+    pub cosign: Cosign,
+    pub client_options: ClientOptions,
+    pub retry_budget: Arc<RetryBudget>,
+}
+
+/// `--torrent webseeds`: every piece against its SHA-1.
+#[cfg(feature = "torrent")]
+pub struct TorrentPieces {
+    pub torrent: Torrent,
+    pub interrupted: Arc<AtomicBool>,
+}
+
+/// `--name-by-hash`: moves the download to the name `template` gives it.
+pub struct NameByHash {
+    pub name: String,
+    pub template: NameTemplate,
+}
+
+impl PostProcessor for Hash {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        if outcome.sha256.is_some() {
+            return Ok(());
+        }
+        let path = &outcome.path;
+        tracing::info!("Hashing '{}' after the download", path.display());
+        let hashing = Instant::now();
+        let hash = hash::hash_with_progress(
+            path,
+            ChecksumOf::File,
+            Algorithm::Sha256,
+            "Verifying",
+            Some(&self.interrupted),
+            &outcome.memory,
+        )
+        .map_err(|error| unverified(error, path))?;
+        println!("Hashed in {:.2}s", hashing.elapsed().as_secs_f64());
+        outcome.sha256 = Some(hash.try_into().expect("SHA-256 is 32 bytes"));
+        Ok(())
+    }
+}
+
+impl PostProcessor for Suspicious {
+    fn name(&self) -> &str {
+        "suspicious"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let (path, snapshot) = (&outcome.path, &outcome.snapshot);
+        // The server said outright that there's nothing to it.
+        if snapshot.no_content {
+            return Ok(());
+        }
+        let expected = (snapshot.total > 0).then_some(snapshot.total);
+        let Some(reason) = suspicious::inspect(path, expected, snapshot.content_type.as_deref())?
+        else {
+            return Ok(());
+        };
+        eprintln!(
+            "{} '{}' looks like an error page rather than the file: {reason}",
+            "WARNING:".red().bold(),
+            path.display()
+        );
+        eprintln!(
+            "First {} bytes of '{}':",
+            suspicious::PREVIEW_BYTES,
+            path.display()
+        );
+        eprintln!("{}", suspicious::preview(path)?);
+        diagnostics::record_warning(
+            WarningId::Suspicious,
+            format!(
+                "'{}' looks like an error page rather than the file: {reason}",
+                path.display()
+            ),
+        );
+        if self.allow && !diagnostics::is_strict(WarningId::Suspicious) {
+            return Ok(());
+        }
+        Err(DownloadError::Suspicious {
+            path: path.to_path_buf(),
+            reason,
+        }
+        .into())
+    }
+}
+
+impl PostProcessor for Verify {
+    fn name(&self) -> &str {
+        "checksum"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let path = &outcome.path;
+        let (checksum, by) = self.expected.resolve(path)?;
+        let algorithm = checksum.algorithm;
+        let actual = match (self.of, algorithm, outcome.sha256) {
+            (ChecksumOf::File, Algorithm::Sha256, Some(sha256)) => sha256.to_vec(),
+            _ => {
+                let label = match self.of {
+                    ChecksumOf::File => "Verifying",
+                    ChecksumOf::Decompressed => "Decompressing for",
+                };
+                hash::hash_with_progress(
+                    path,
+                    self.of,
+                    algorithm,
+                    label,
+                    Some(&self.interrupted),
+                    &outcome.memory,
+                )
+                .map_err(|error| match error.downcast_ref::<io::Error>() {
+                    Some(io) if io.kind() == io::ErrorKind::InvalidData => DownloadError::Corrupt {
+                        path: path.to_path_buf(),
+                        reason: io.to_string(),
+                    }
+                    .into(),
+                    _ => unverified(error, path),
+                })?
+            }
+        };
+        let what = match self.of {
+            ChecksumOf::File => algorithm.to_string(),
+            ChecksumOf::Decompressed => format!("{algorithm} of the decompressed contents"),
+        };
+        if actual != checksum.digest {
+            println!("Checksum FAIL: the {what} doesn't match");
+            if !self.keep {
+                outcome.path = set_aside(path)?;
+            }
+            return Err(DownloadError::ChecksumMismatch {
+                path: outcome.path.clone(),
+                what,
+                by: by.into(),
+                expected: hex::encode(&checksum.digest),
+                actual: hex::encode(actual),
+            }
+            .into());
+        }
+        println!("Checksum PASS: the {what} matches");
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sigstore")]
+impl PostProcessor for Signature {
+    fn name(&self) -> &str {
+        "cosign"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let signature = self
+            .cosign
+            .fetch(&self.client_options, &self.retry_budget)?;
+        match self.cosign.verify(&outcome.path, &signature) {
+            Ok(check) => {
+                println!("Signature PASS: verified for {}", check.signer);
+                outcome.signature = Some(check);
+                Ok(())
+            }
+            Err(error) => {
+                if let Some(check) = cosign::failed(&error) {
+                    println!("Signature FAIL: not verified for {}", check.signer);
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "torrent")]
+impl PostProcessor for TorrentPieces {
+    fn name(&self) -> &str {
+        "torrent"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let pieces = self
+            .torrent
+            .verify(&outcome.path, Some(&self.interrupted))?;
+        println!("Checked {pieces} pieces against the torrent");
+        Ok(())
+    }
+}
+
+impl PostProcessor for NameByHash {
+    fn name(&self) -> &str {
+        "name-by-hash"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let sha256 = outcome.sha256.expect("the hash step runs first");
+        let settled = naming::settle(
+            &outcome.path,
+            &self.name,
+            &sha256,
+            &self.template,
+            &outcome.memory,
+        )?;
+        if let Settled::Duplicate(path) = &settled {
+            println!("Already have '{}', dropped the download", path.display());
+        }
+        println!(
+            "{} -> {}",
+            http::redact_url(&outcome.url),
+            settled.path().display()
+        );
+        outcome.path = settled.path().to_path_buf();
+        Ok(())
+    }
+}
+
+/// A hash Ctrl+C stopped leaves the file unverified.
+/// Renames a download that doesn't match to `<name>.corrupt`, so nothing
+/// takes it for the file, replacing one left by an earlier try.
+fn set_aside(path: &Path) -> anyhow::Result<PathBuf> {
+    let mut corrupt = path.as_os_str().to_owned();
+    corrupt.push(".corrupt");
+    let corrupt = PathBuf::from(corrupt);
+    fs_ops::rename(path, &corrupt).with_context(|| {
+        format!(
+            "Cannot rename '{}' to '{}'",
+            path.display(),
+            corrupt.display()
+        )
+    })?;
+    Ok(corrupt)
+}
+
+fn unverified(error: anyhow::Error, path: &Path) -> anyhow::Error {
+    match error.downcast_ref::<io::Error>() {
+        Some(io) if io.kind() == io::ErrorKind::Interrupted => DownloadError::Unverified {
+            path: path.to_path_buf(),
+        }
+        .into(),
+        _ => error,
+    }
+}
@@ -0,0 +1,106 @@
+//! What `--overwrite` replaced: the old file's size, modification time and,
+//! with `--diff-hash`, hash, recorded before it's truncated or removed, so
+//! the download can end by saying how the new file compares, and
+//! `usage.jsonl` keeps a trace of the overwrite.
+
+use crate::hash;
+use chrono::{DateTime, Local, TimeZone};
+use download_manager::__private::checksum::{Algorithm, ChecksumOf};
+use download_manager::__private::speed::Size;
+use download_manager::MemoryLimit;
+use download_manager::schema::Replaced;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::UNIX_EPOCH;
+
+/// Hex digits of a hash shown in the comparison.
+const SHORT_HASH: usize = 8;
+
+/// Records the file at `path`, hashing it too if `hash`, within `memory`,
+/// until `interrupted` is set.
+pub fn record(
+    path: &Path,
+    hash: bool,
+    interrupted: &AtomicBool,
+    memory: &Arc<MemoryLimit>,
+) -> anyhow::Result<Replaced> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_millis() as u64);
+    let sha256 = match hash {
+        true => Some(hex::encode(hash::hash_with_progress(
+            path,
+            ChecksumOf::File,
+            Algorithm::Sha256,
+            "Hashing the file to replace,",
+            Some(interrupted),
+            memory,
+        )?)),
+        false => None,
+    };
+    Ok(Replaced {
+        size: metadata.len(),
+        modified,
+        sha256,
+        trashed: false,
+    })
+}
+
+/// How the new file, `size` bytes hashing to `sha256`, compares with
+/// `old`, as in `Replaced 1.2 GiB (sha256 ab12cd34…) from 2024-11-02 with
+/// 1.3 GiB (sha256 cd34ef56…)`.
+pub fn compare<'a>(old: &'a Replaced, size: u64, sha256: &str) -> Comparison<'a> {
+    Comparison {
+        old,
+        size,
+        sha256: sha256.to_string(),
+    }
+}
+
+/// When `old` was last modified, in local time.
+fn local_time(old: &Replaced) -> Option<DateTime<Local>> {
+    old.modified
+        .and_then(|millis| Local.timestamp_millis_opt(millis as i64).single())
+}
+
+/// The line [`compare`] prints.
+pub struct Comparison<'a> {
+    old: &'a Replaced,
+    size: u64,
+    sha256: String,
+}
+
+impl fmt::Display for Comparison<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let old = self.old;
+        write!(f, "Replaced {}", Size(old.size))?;
+        if let Some(sha256) = &old.sha256 {
+            write!(f, " (sha256 {}…)", short(sha256))?;
+        }
+        if let Some(modified) = local_time(old) {
+            write!(f, " from {}", modified.format("%Y-%m-%d"))?;
+        }
+        write!(
+            f,
+            " with {} (sha256 {}…)",
+            Size(self.size),
+            short(&self.sha256)
+        )?;
+        if old.sha256.as_deref() == Some(self.sha256.as_str()) {
+            f.write_str(", the same content")?;
+        }
+        if old.trashed {
+            f.write_str("; the old file is in the trash")?;
+        }
+        Ok(())
+    }
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..SHORT_HASH.min(hash.len())]
+}
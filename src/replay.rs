@@ -0,0 +1,509 @@
+//! `dlm replay`: runs the download a `--record` transcript is of again,
+//! against a stand-in server on localhost that answers each request with
+//! the status and headers the real one did, and checks that the same
+//! decisions come out of it: the same chunk plan, the same requests for
+//! each chunk in the same order (retries and resumes included), and the
+//! same exit code.
+//!
+//! Bodies aren't in a transcript, so zeros of the recorded length stand in
+//! for them. A body that was cut off shows in the transcript as a later
+//! request for the rest of its range, and is cut off at the same byte.
+
+use anyhow::{Context, bail};
+use download_manager::__private::transcript::{self, Entry, Event};
+use download_manager::__private::utils;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex, PoisonError};
+use url::Url;
+
+/// Flags that would point the replay at the recording's files, and are
+/// replaced with its own.
+const OWN_FLAGS: [&str; 6] = [
+    "-t",
+    "--target-directory",
+    "--state-dir",
+    "--record",
+    "--error-report",
+    "--chunk-log",
+];
+
+/// A recorded request and what answered it.
+#[derive(Clone, Debug)]
+struct Exchange {
+    chunk: Option<usize>,
+    method: String,
+    /// Path and query.
+    target: String,
+    range: Option<String>,
+    /// `None` when no response came.
+    response: Option<Response>,
+}
+
+#[derive(Clone, Debug)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// Body bytes to send: all of them, or where the recorded one was cut
+    /// off.
+    body: u64,
+}
+
+/// What one run of the transcript's command did, to compare.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Decisions {
+    /// Each chunk's inclusive byte range.
+    plan: BTreeSet<(usize, u64, u64)>,
+    /// The requests of each chunk, `None` for those outside worker mode,
+    /// as `GET /file Range: bytes=0-99`.
+    requests: BTreeMap<Option<usize>, Vec<String>>,
+    exit_code: Option<u8>,
+}
+
+/// Replays the transcript at `path`, failing if the replay decided
+/// anything differently.
+pub fn replay(path: &Path) -> anyhow::Result<()> {
+    let entries = transcript::read(path)?;
+    let Some((args, env)) = entries.iter().find_map(|entry| match &entry.event {
+        Event::Start { args, env, .. } => Some((args.clone(), env.clone())),
+        _ => None,
+    }) else {
+        bail!("'{}' has no command line to replay", path.display());
+    };
+    let recorded = Decisions::of(&entries);
+    if recorded.exit_code.is_none() {
+        bail!(
+            "'{}' has no outcome, the recorded run never finished",
+            path.display()
+        );
+    }
+
+    let exchanges = exchanges(&entries);
+    let origins: BTreeSet<String> = exchanges
+        .iter()
+        .map(|exchange| exchange.origin.clone())
+        .collect();
+    let server = Server::start(exchanges.into_iter().map(|exchange| exchange.exchange))?;
+    let dir = std::env::temp_dir().join(format!("dlm-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).with_context(|| format!("Cannot create '{}'", dir.display()))?;
+    let replayed = dir.join("transcript.jsonl");
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg("-t")
+        .arg(dir.join("files"))
+        .arg("--state-dir")
+        .arg(dir.join("state"))
+        .arg("--record")
+        .arg(&replayed)
+        .args(own_args(&args, &origins, &Url::parse(&server.origin)?));
+    for (variable, _) in
+        std::env::vars().filter(|(variable, _)| variable.starts_with(crate::env_flags::PREFIX))
+    {
+        command.env_remove(variable);
+    }
+    command.envs(env);
+    println!(
+        "Replaying {} requests of '{}' against {}",
+        server.len(),
+        path.display(),
+        server.origin
+    );
+    let status = command
+        .status()
+        .context("Cannot run dlm to replay the transcript")?;
+    if !replayed.exists() {
+        bail!("The replay of '{}' didn't start: {status}", path.display());
+    }
+    let replay = Decisions::of(&transcript::read(&replayed)?);
+    let unexpected = server.unexpected();
+    let _ = std::fs::remove_dir_all(&dir);
+    tracing::debug!("The replay exited with {status}");
+
+    let differences = recorded.differences(&replay);
+    if differences.is_empty() && unexpected.is_empty() {
+        println!(
+            "Replay matched the recording: {} requests over {} chunks, exit code {}",
+            recorded.requests.values().map(Vec::len).sum::<usize>(),
+            recorded.plan.len(),
+            recorded.exit_code.unwrap_or_default()
+        );
+        return Ok(());
+    }
+    for difference in differences {
+        println!("  {difference}");
+    }
+    for request in unexpected {
+        println!("  not in the recording: {request}");
+    }
+    bail!(
+        "The replay of '{}' diverged from the recording",
+        path.display()
+    )
+}
+
+/// `args`, the recorded command line, with [`OWN_FLAGS`] dropped and URLs
+/// at `origins` moved to `to`.
+fn own_args(args: &[String], origins: &BTreeSet<String>, to: &Url) -> Vec<String> {
+    let mut own = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if OWN_FLAGS.contains(&arg.as_str()) {
+            args.next();
+            continue;
+        }
+        if OWN_FLAGS
+            .iter()
+            .any(|flag| arg.starts_with(&format!("{flag}=")))
+        {
+            continue;
+        }
+        let moved = Url::parse(arg).ok().and_then(|mut url| {
+            if !origins.contains(&url.origin().ascii_serialization()) {
+                return None;
+            }
+            url.set_scheme(to.scheme()).ok()?;
+            url.set_host(to.host_str()).ok()?;
+            url.set_port(to.port()).ok()?;
+            Some(url.to_string())
+        });
+        own.push(moved.unwrap_or_else(|| arg.clone()));
+    }
+    own
+}
+
+/// An [`Exchange`] and the origin it went to.
+struct Recorded {
+    exchange: Exchange,
+    origin: String,
+}
+
+/// The requests of `entries` with their responses, the bodies cut off
+/// where a later request of the chunk picks the range up.
+fn exchanges(entries: &[Entry]) -> Vec<Recorded> {
+    let mut responses = BTreeMap::new();
+    for entry in entries {
+        if let Event::Response {
+            id,
+            status,
+            headers,
+        } = &entry.event
+        {
+            responses.insert(*id, (*status, headers.clone()));
+        }
+    }
+    let mut recorded: Vec<Recorded> = entries
+        .iter()
+        .filter_map(|entry| match &entry.event {
+            Event::Request {
+                id,
+                chunk,
+                method,
+                url,
+                headers,
+            } => {
+                let url = Url::parse(url).ok()?;
+                let response = responses.get(id).map(|(status, headers)| Response {
+                    status: *status,
+                    headers: headers.clone(),
+                    body: match method.as_str() {
+                        "HEAD" => 0,
+                        _ => header(headers, "content-length")
+                            .and_then(|length| length.parse().ok())
+                            .unwrap_or(0),
+                    },
+                });
+                Some(Recorded {
+                    exchange: Exchange {
+                        chunk: *chunk,
+                        method: method.clone(),
+                        target: target(&url),
+                        range: header(headers, "range").map(str::to_string),
+                        response,
+                    },
+                    origin: url.origin().ascii_serialization(),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    for index in 0..recorded.len() {
+        let exchange = &recorded[index].exchange;
+        let Some(response) = &exchange.response else {
+            continue;
+        };
+        let start = match header(&response.headers, "content-range") {
+            Some(range) => match utils::parse_content_range(range) {
+                Some(range) => range.first,
+                None => continue,
+            },
+            None => 0,
+        };
+        let cut = recorded[index + 1..]
+            .iter()
+            .filter(|later| {
+                later.exchange.chunk == exchange.chunk && later.exchange.target == exchange.target
+            })
+            .filter_map(|later| range_start(later.exchange.range.as_deref()?))
+            .find(|resumed| *resumed > start && *resumed < start + response.body);
+        if let (Some(cut), Some(response)) = (cut, &mut recorded[index].exchange.response) {
+            response.body = cut - start;
+        }
+    }
+    recorded
+}
+
+impl Decisions {
+    fn of(entries: &[Entry]) -> Self {
+        let mut decisions = Decisions::default();
+        for entry in entries {
+            match &entry.event {
+                Event::Scheduled { chunk, start, end } => {
+                    decisions.plan.insert((*chunk, *start, *end));
+                }
+                Event::Request {
+                    chunk,
+                    method,
+                    url,
+                    headers,
+                    ..
+                } => {
+                    let target = Url::parse(url).map_or_else(|_| url.clone(), |url| target(&url));
+                    let request = match header(headers, "range") {
+                        Some(range) => format!("{method} {target} Range: {range}"),
+                        None => format!("{method} {target}"),
+                    };
+                    decisions.requests.entry(*chunk).or_default().push(request);
+                }
+                Event::Outcome { exit_code, .. } => decisions.exit_code = Some(*exit_code),
+                _ => {}
+            }
+        }
+        decisions
+    }
+
+    /// How `replay` differs from this recording, a line for each.
+    fn differences(&self, replay: &Decisions) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.plan != replay.plan {
+            differences.push(format!(
+                "chunk plan: recorded {:?}, replayed {:?}",
+                self.plan, replay.plan
+            ));
+        }
+        let chunks: BTreeSet<_> = self.requests.keys().chain(replay.requests.keys()).collect();
+        for chunk in chunks {
+            let (recorded, replayed) = (
+                self.requests
+                    .get(chunk)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                replay
+                    .requests
+                    .get(chunk)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            );
+            if recorded == replayed {
+                continue;
+            }
+            let at = recorded
+                .iter()
+                .zip(replayed)
+                .take_while(|(recorded, replayed)| recorded == replayed)
+                .count();
+            let what = match chunk {
+                Some(chunk) => format!("chunk {chunk}"),
+                None => "requests".to_string(),
+            };
+            differences.push(format!(
+                "{what}, request {}: recorded {}, replayed {}",
+                at + 1,
+                recorded.get(at).map_or("nothing", String::as_str),
+                replayed.get(at).map_or("nothing", String::as_str)
+            ));
+        }
+        if self.exit_code != replay.exit_code {
+            differences.push(format!(
+                "exit code: recorded {}, replayed {}",
+                code(self.exit_code),
+                code(replay.exit_code)
+            ));
+        }
+        differences
+    }
+}
+
+/// The stand-in server, answering from the recording.
+struct Server {
+    /// `http://127.0.0.1:PORT`.
+    origin: String,
+    state: Arc<ServerState>,
+}
+
+struct ServerState {
+    /// The recorded exchanges, and whether each was replayed yet.
+    exchanges: Mutex<Vec<(Exchange, bool)>>,
+    /// Requests the recording has nothing for.
+    unexpected: Mutex<Vec<String>>,
+}
+
+impl Server {
+    fn start(exchanges: impl Iterator<Item = Exchange>) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").context("Cannot listen for the replay")?;
+        let origin = format!("http://{}", listener.local_addr()?);
+        let state = Arc::new(ServerState {
+            exchanges: Mutex::new(exchanges.map(|exchange| (exchange, false)).collect()),
+            unexpected: Mutex::new(Vec::new()),
+        });
+        let serving = (state.clone(), origin.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (state, origin) = (serving.0.clone(), serving.1.clone());
+                std::thread::spawn(move || {
+                    if let Err(error) = state.answer(stream, &origin) {
+                        tracing::debug!("Replay connection: {error}");
+                    }
+                });
+            }
+        });
+        Ok(Self { origin, state })
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.state.exchanges).len()
+    }
+
+    fn unexpected(&self) -> Vec<String> {
+        lock(&self.state.unexpected).clone()
+    }
+}
+
+impl ServerState {
+    /// Answers the requests on `stream`, one per connection.
+    fn answer(&self, stream: TcpStream, origin: &str) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Ok(());
+        };
+        let (method, target) = (method.to_string(), target.to_string());
+        let (mut range, mut length) = (None, 0);
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "range" => range = Some(value.trim().to_string()),
+                    "content-length" => length = value.trim().parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+        std::io::copy(&mut (&mut reader).take(length), &mut std::io::sink())?;
+
+        let found = {
+            let mut exchanges = lock(&self.exchanges);
+            exchanges
+                .iter_mut()
+                .find(|(exchange, replayed)| {
+                    !*replayed
+                        && exchange.method == method
+                        && exchange.target == target
+                        && exchange.range == range
+                })
+                .map(|(exchange, replayed)| {
+                    *replayed = true;
+                    exchange.response.clone()
+                })
+        };
+        let mut stream = stream;
+        let response = match found {
+            // The recorded request got no response either.
+            Some(None) => return Ok(()),
+            Some(Some(response)) => response,
+            None => {
+                let request = match &range {
+                    Some(range) => format!("{method} {target} Range: {range}"),
+                    None => format!("{method} {target}"),
+                };
+                lock(&self.unexpected).push(request);
+                return stream.write_all(
+                    b"HTTP/1.1 400 Not in the recording\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        };
+        let mut head = format!("HTTP/1.1 {} Replayed\r\n", response.status);
+        for (name, value) in &response.headers {
+            match name.as_str() {
+                "content-length" | "transfer-encoding" | "connection" => continue,
+                "location" => {
+                    let value = Url::parse(value).map_or_else(
+                        |_| value.clone(),
+                        |url| {
+                            let from = url.origin().ascii_serialization();
+                            format!("{origin}{}", &url.as_str()[from.len()..])
+                        },
+                    );
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                _ => head.push_str(&format!("{name}: {value}\r\n")),
+            }
+        }
+        let announced = header(&response.headers, "content-length").unwrap_or("0");
+        head.push_str(&format!(
+            "content-length: {announced}\r\nconnection: close\r\n\r\n"
+        ));
+        stream.write_all(head.as_bytes())?;
+        let zeros = [0; 64 * 1024];
+        let mut left = response.body;
+        while left > 0 {
+            let now = left.min(zeros.len() as u64) as usize;
+            stream.write_all(&zeros[..now])?;
+            left -= now as u64;
+        }
+        stream.flush()
+    }
+}
+
+/// An exit code, or `none` for a run that never got to one.
+fn code(exit_code: Option<u8>) -> String {
+    exit_code.map_or_else(|| "none".to_string(), |code| code.to_string())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The value of the header `name`, which transcripts keep in lowercase.
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn target(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    }
+}
+
+/// Where `bytes=START-` or `bytes=START-END` starts.
+fn range_start(range: &str) -> Option<u64> {
+    range
+        .strip_prefix("bytes=")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,187 @@
+use crate::error_report;
+use anyhow::Context;
+use download_manager::__private::chunk_log::{ChunkEvent, ChunkRecord};
+use download_manager::__private::speed::{Rate, TimeSplit, TtfbSpread};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+/// How many of the slowest chunks to list.
+const SLOWEST: usize = 5;
+
+#[derive(Default)]
+struct ChunkSummary {
+    size: Option<u64>,
+    ttfb_ms: Option<u64>,
+    duration_ms: Option<u64>,
+    avg_speed: Option<u64>,
+    network_ms: u64,
+    disk_ms: u64,
+    retries: usize,
+    error: Option<String>,
+}
+
+/// Prints an `--error-report`, or a human summary of a `--chunk-log` file:
+/// the slowest chunks, failures, retry counts, how long chunks waited for
+/// their first byte and on the network vs the disk, piece hash mismatches
+/// and how often each error came up.
+pub fn print_report(path: &Path) -> anyhow::Result<()> {
+    // An --error-report is one JSON object rather than a line per event.
+    if let Some(report) = std::fs::read_to_string(path)
+        .ok()
+        .as_deref()
+        .and_then(error_report::parse)
+    {
+        error_report::print(&report);
+        return Ok(());
+    }
+    let file =
+        File::open(path).with_context(|| format!("Cannot open chunk log '{}'", path.display()))?;
+
+    // Keyed by (run, chunk) so appended runs don't mix.
+    let mut chunks: BTreeMap<(u64, usize), ChunkSummary> = BTreeMap::new();
+    let mut errors: HashMap<String, usize> = HashMap::new();
+    let mut unreadable = 0;
+    let mut mismatches = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(record) = serde_json::from_str::<ChunkRecord>(&line) else {
+            unreadable += 1;
+            continue;
+        };
+        let chunk = chunks.entry((record.run, record.chunk)).or_default();
+        match record.event {
+            ChunkEvent::Scheduled { start, end } => chunk.size = Some(end - start + 1),
+            ChunkEvent::Started { .. } | ChunkEvent::Bytes { .. } => {}
+            ChunkEvent::FirstByte { ttfb_ms } => chunk.ttfb_ms = Some(ttfb_ms),
+            ChunkEvent::Retry { error, .. } => {
+                chunk.retries += 1;
+                *errors.entry(error).or_default() += 1;
+            }
+            ChunkEvent::Completed {
+                duration_ms,
+                avg_speed,
+                network_ms,
+                disk_ms,
+            } => {
+                chunk.duration_ms = Some(duration_ms);
+                chunk.avg_speed = Some(avg_speed);
+                chunk.network_ms = network_ms;
+                chunk.disk_ms = disk_ms;
+            }
+            ChunkEvent::Failed { error } => {
+                *errors.entry(error.clone()).or_default() += 1;
+                chunk.error = Some(error);
+            }
+            ChunkEvent::PieceMismatch { mirror, .. } => {
+                mismatches += 1;
+                *errors
+                    .entry(format!("piece hash mismatch from {mirror}"))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    let runs: Vec<u64> = {
+        let mut runs: Vec<u64> = chunks.keys().map(|(run, _)| *run).collect();
+        runs.dedup();
+        runs
+    };
+    let run_number = |run: u64| runs.iter().position(|r| *r == run).unwrap_or(0) + 1;
+    let completed = chunks.values().filter(|c| c.avg_speed.is_some()).count();
+    let failed = chunks.values().filter(|c| c.error.is_some()).count();
+    let retries: usize = chunks.values().map(|c| c.retries).sum();
+
+    println!("Chunk log: {}", path.display());
+    println!(
+        "Runs: {}, chunks: {} ({completed} completed, {failed} failed), retries: {retries}",
+        runs.len(),
+        chunks.len()
+    );
+    if mismatches > 0 {
+        println!("Pieces re-downloaded after a hash mismatch: {mismatches}");
+    }
+    let first_bytes = chunks
+        .values()
+        .filter_map(|c| c.ttfb_ms.map(Duration::from_millis))
+        .collect();
+    if let Some(spread) = TtfbSpread::of(first_bytes) {
+        println!("Time to first byte: {spread}");
+    }
+    let split = TimeSplit::default();
+    for chunk in chunks.values() {
+        split.add_network(Duration::from_millis(chunk.network_ms));
+        split.add_disk(Duration::from_millis(chunk.disk_ms));
+    }
+    if !(split.network() + split.disk()).is_zero() {
+        println!("Time split, over all chunks: {split}");
+    }
+    if unreadable > 0 {
+        println!("Skipped {unreadable} unreadable lines");
+    }
+
+    let mut slowest: Vec<_> = chunks
+        .iter()
+        .filter(|(_, chunk)| chunk.avg_speed.is_some())
+        .collect();
+    slowest.sort_by_key(|(_, chunk)| chunk.avg_speed);
+    if !slowest.is_empty() {
+        println!();
+        println!("Slowest chunks:");
+        println!(
+            "  {:>3}  {:>5}  {:>10}  {:>8}  {:>10}  {:>8}  {:>8}  {:>12}  {:>7}",
+            "RUN", "CHUNK", "SIZE", "TTFB", "DURATION", "NETWORK", "DISK", "AVG SPEED", "RETRIES"
+        );
+        for ((run, id), chunk) in slowest.into_iter().take(SLOWEST) {
+            let duration = Duration::from_millis(chunk.duration_ms.unwrap_or(0));
+            println!(
+                "  {:>3}  {:>5}  {:>10}  {:>8}  {:>10}  {:>8}  {:>8}  {:>12}  {:>7}",
+                run_number(*run),
+                id,
+                chunk
+                    .size
+                    .map_or("?".to_string(), |size| indicatif::HumanBytes(size)
+                        .to_string()),
+                chunk
+                    .ttfb_ms
+                    .map_or("?".to_string(), |ttfb| format!("{ttfb}ms")),
+                format!("{:.1}s", duration.as_secs_f64()),
+                format!("{:.1}s", chunk.network_ms as f64 / 1000.0),
+                format!("{:.1}s", chunk.disk_ms as f64 / 1000.0),
+                Rate(chunk.avg_speed.unwrap_or(0)).to_string(),
+                chunk.retries
+            );
+        }
+    }
+
+    if failed > 0 {
+        println!();
+        println!("Failed chunks:");
+        for ((run, id), chunk) in chunks.iter() {
+            if let Some(error) = &chunk.error {
+                println!(
+                    "  run {} chunk {id} after {} retries: {error}",
+                    run_number(*run),
+                    chunk.retries
+                );
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        let mut errors: Vec<_> = errors.into_iter().collect();
+        errors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        println!();
+        println!("Errors:");
+        println!("  {:>5}  ERROR", "COUNT");
+        for (error, count) in errors {
+            println!("  {count:>5}  {error}");
+        }
+    }
+    Ok(())
+}
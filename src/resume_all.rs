@@ -0,0 +1,176 @@
+//! `dlm resume-all`: after a crash or a power cut, carries on with every
+//! download `dlm status` lists as cut off, the way `dlm resume <id>` does
+//! with each. Parts worker mode left behind that no manifest accounts for
+//! are listed too, but can't be resumed, as only a manifest has the URL.
+
+use crate::state::{self, ActiveDownloads};
+use anyhow::Context;
+use download_manager::__private::parts::PartLayout;
+use download_manager::schema::Manifest;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What there is to resume.
+pub struct Found {
+    /// The downloads no process is working on, oldest first.
+    pub manifests: Vec<Manifest>,
+    /// Files worker mode was downloading into, by their plan, that no
+    /// manifest is for.
+    pub orphans: Vec<Orphan>,
+}
+
+pub struct Orphan {
+    pub destination: PathBuf,
+    /// Bytes the parts hold.
+    pub downloaded: u64,
+    pub total: u64,
+}
+
+/// How resuming one download, or one of a batch, went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    Failed,
+    /// Left alone: not resumed as the remote file changed, or not
+    /// downloaded in a batch as its file is there.
+    Skipped,
+    /// Skipped in a batch as `--newer-than` found it hadn't changed.
+    NotNewer,
+    /// Not started, or stopped, by Ctrl+C.
+    Interrupted,
+}
+
+/// How many downloads ended each way.
+#[derive(Default)]
+pub struct Summary {
+    pub completed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Of those skipped, the ones `--newer-than` left out.
+    pub not_newer: usize,
+    pub interrupted: usize,
+}
+
+/// The downloads in `downloads` that were cut off, only the ones saved
+/// under `under` if given, and the plans in `dir` no manifest is for.
+pub fn find(
+    downloads: &ActiveDownloads,
+    under: Option<&Path>,
+    dir: &Path,
+) -> anyhow::Result<Found> {
+    let manifests = downloads
+        .list()
+        .with_context(|| format!("Cannot read '{}'", downloads.dir().display()))?;
+    let under = under.map(canonical);
+    let manifests: Vec<_> = manifests
+        .into_iter()
+        .filter(|manifest| !manifest.is_running())
+        .filter(|manifest| {
+            under
+                .as_ref()
+                .is_none_or(|under| canonical(&manifest.destination).starts_with(under))
+        })
+        .collect();
+    let plans = match PartLayout::find_plans(dir) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+        plans => plans.with_context(|| format!("Cannot list '{}'", dir.display()))?,
+    };
+    let orphans = plans
+        .into_iter()
+        .filter(|(destination, plan)| {
+            !manifests.iter().any(|manifest| {
+                manifest
+                    .parts
+                    .as_ref()
+                    .is_some_and(|parts| parts.id == plan.id)
+                    || canonical(&manifest.destination) == canonical(destination)
+            })
+        })
+        .map(|(destination, plan)| {
+            let (downloaded, total) = plan.progress(&destination);
+            Orphan {
+                destination,
+                downloaded,
+                total,
+            }
+        })
+        .collect();
+    Ok(Found { manifests, orphans })
+}
+
+/// `path` with its directory resolved, as the file itself may not be there.
+fn canonical(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => dir
+            .canonicalize()
+            .map_or_else(|_| path.to_path_buf(), |dir| dir.join(name)),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl Found {
+    pub fn is_empty(&self) -> bool {
+        self.manifests.is_empty() && self.orphans.is_empty()
+    }
+
+    /// Lists what was found, with how far along each download is.
+    pub fn print(&self) {
+        println!(
+            "Found {} cut-off downloads, and parts of {} more without a manifest",
+            self.manifests.len(),
+            self.orphans.len()
+        );
+        println!("{:<12}  {:>4}  DESTINATION", "ID", "DONE");
+        for manifest in &self.manifests {
+            println!(
+                "{:<12}  {:>4}  {} ({})",
+                manifest.id,
+                state::done(manifest.downloaded, manifest.total),
+                manifest.destination.display(),
+                state::of(manifest.downloaded, manifest.total)
+            );
+        }
+        for orphan in &self.orphans {
+            println!(
+                "{:<12}  {:>4}  {} ({}, no manifest has its URL, can't be resumed)",
+                "-",
+                state::done(orphan.downloaded, orphan.total),
+                orphan.destination.display(),
+                state::of(orphan.downloaded, orphan.total)
+            );
+        }
+    }
+}
+
+impl Summary {
+    pub fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Completed => self.completed += 1,
+            Outcome::Failed => self.failed += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::NotNewer => {
+                self.skipped += 1;
+                self.not_newer += 1;
+            }
+            Outcome::Interrupted => self.interrupted += 1,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} completed, {} failed, {} skipped",
+            self.completed, self.failed, self.skipped
+        )?;
+        if self.not_newer > 0 {
+            write!(f, " ({} not newer)", self.not_newer)?;
+        }
+        if self.interrupted > 0 {
+            write!(f, ", {} interrupted", self.interrupted)?;
+        }
+        Ok(())
+    }
+}
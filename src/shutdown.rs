@@ -0,0 +1,116 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Exit code after a forced exit, as shells report a process killed by
+/// SIGINT.
+const FORCED_EXIT: i32 = 130;
+
+/// Ctrl+C handling for the whole process, installed once in `main`.
+///
+/// Each download gets its own interrupt flag from [`Shutdown::child`]. The
+/// first Ctrl+C raises all of them so the downloads wind down and clean up,
+/// and any started later begin with theirs raised; a second one exits right
+/// away with code 130.
+pub struct Shutdown {
+    requested: AtomicBool,
+    downloads: Mutex<Vec<Weak<AtomicBool>>>,
+}
+
+impl Shutdown {
+    /// Starts listening for Ctrl+C, and on Unix SIGTERM and SIGHUP as well.
+    pub fn install() -> io::Result<Arc<Self>> {
+        let shutdown = Arc::new(Self {
+            requested: AtomicBool::new(false),
+            downloads: Mutex::default(),
+        });
+        let listener = shutdown.clone();
+        let mut signals = Signals::new()?;
+        tokio::spawn(async move {
+            loop {
+                signals.recv().await;
+                listener.signalled();
+            }
+        });
+        Ok(shutdown)
+    }
+
+    /// Whether Ctrl+C was pressed, so nothing new should be started.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// An interrupt flag for one download. Ctrl+C raises it, but raising it
+    /// otherwise (say with the control socket's cancel) leaves other
+    /// downloads alone.
+    pub fn child(&self) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(self.is_requested()));
+        let mut downloads = self.downloads.lock().unwrap();
+        downloads.retain(|download| download.strong_count() > 0);
+        downloads.push(Arc::downgrade(&flag));
+        flag
+    }
+
+    fn signalled(&self) {
+        let again = self.requested.swap(true, Ordering::SeqCst);
+        let downloads: Vec<_> = self
+            .downloads
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        if again {
+            eprintln!("Interrupted again, exiting");
+            std::process::exit(FORCED_EXIT);
+        }
+        // The downloads say how they wound down; nothing else would.
+        if downloads.is_empty() {
+            eprintln!("Interrupted, stopping; press Ctrl+C again to exit at once");
+        }
+        for download in downloads {
+            download.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> io::Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {}
+            _ = self.terminate.recv() => {}
+            _ = self.hangup.recv() => {}
+        }
+    }
+}
+
+#[cfg(windows)]
+struct Signals(tokio::signal::windows::CtrlC);
+
+#[cfg(windows)]
+impl Signals {
+    fn new() -> io::Result<Self> {
+        tokio::signal::windows::ctrl_c().map(Self)
+    }
+
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
@@ -0,0 +1,119 @@
+//! SIGUSR1: prints where the run is to stderr without stopping it, as `dd`
+//! does, for a download left running in the background or under a
+//! supervisor: each download in flight and how far it got, the buffer
+//! memory it holds and the retries it made.
+
+use crate::batch;
+use download_manager::__private::http;
+use download_manager::__private::speed::Size;
+use download_manager::MemoryLimit;
+use download_manager::ProgressHandle;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use url::Url;
+
+/// The downloads in flight, by redacted URL, with the buffers they count
+/// against.
+static RUNNING: Mutex<Vec<Running>> = Mutex::new(Vec::new());
+
+struct Running {
+    url: String,
+    handle: ProgressHandle,
+    memory: Arc<MemoryLimit>,
+}
+
+/// Prints a snapshot on every SIGUSR1 for the rest of the run.
+#[cfg(unix)]
+pub fn install() -> io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut signals = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            eprint!("{}", snapshot());
+        }
+    });
+    Ok(())
+}
+
+/// Windows has no SIGUSR1.
+#[cfg(not(unix))]
+pub fn install() -> io::Result<()> {
+    Ok(())
+}
+
+/// Follows the transfer `handle` of `url`, and the buffers in `memory`, in
+/// the snapshots until it's over.
+pub fn attach(url: &Url, handle: ProgressHandle, memory: Arc<MemoryLimit>) {
+    let mut running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    running.retain(|running| !running.handle.snapshot().state.is_terminal());
+    running.push(Running {
+        url: http::redact_url(url),
+        handle,
+        memory,
+    });
+}
+
+/// What SIGUSR1 prints, as in:
+///
+/// ```text
+/// Snapshot:
+///   https://example.com/a.iso: Downloaded: 2 MiB / 8 MiB (25%) @ 1 MiB/s
+///   Buffers: 4 MiB held, 8 MiB at most, of --max-memory 64 MiB
+///   Retries: 3 of --retry-budget 50
+/// ```
+fn snapshot() -> String {
+    let mut lines = vec!["Snapshot:".to_string()];
+    let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    // Every download of the run spends the same budget.
+    let retries = running
+        .last()
+        .map(|running| running.handle.snapshot().retries);
+    let downloads: Vec<_> = running
+        .iter()
+        .map(|running| (&running.url, running.handle.snapshot()))
+        .filter(|(_, snapshot)| !snapshot.state.is_terminal())
+        .collect();
+    if downloads.is_empty() {
+        lines.push("  No download in flight".to_string());
+    }
+    for (url, snapshot) in downloads {
+        let mut line = format!("  {url}: {}", batch::progress_line(&snapshot));
+        let chunks = snapshot.chunks;
+        if chunks != Default::default() {
+            line += &format!(
+                ", chunks {} done, {} running, {} to go",
+                chunks.completed,
+                chunks.downloading + chunks.retrying,
+                chunks.pending
+            );
+        }
+        lines.push(line);
+    }
+    // Downloads share their buffers' cap unless `dlm resume-all` carried
+    // on with ones that had caps of their own.
+    let mut memories: Vec<&Arc<MemoryLimit>> = Vec::new();
+    for running in running.iter() {
+        if !memories
+            .iter()
+            .any(|memory| Arc::ptr_eq(memory, &running.memory))
+        {
+            memories.push(&running.memory);
+        }
+    }
+    for memory in memories {
+        let mut buffers = format!(
+            "  Buffers: {} held, {} at most",
+            Size(memory.in_use()),
+            Size(memory.peak())
+        );
+        if let Some(limit) = memory.limit() {
+            buffers += &format!(", of --max-memory {}", Size(limit));
+        }
+        lines.push(buffers);
+    }
+    lines.extend(retries.map(|retries| match retries.budget {
+        Some(budget) => format!("  Retries: {} of --retry-budget {budget}", retries.used),
+        None => format!("  Retries: {}", retries.used),
+    }));
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,309 @@
+use anyhow::{Context, bail};
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::dns::DnsCache;
+use download_manager::ProgressHandle;
+use download_manager::schema::{Manifest, Versioned};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// How often a running download rewrites its manifest.
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// Manifests of the downloads in flight, one `<id>.json` [`Manifest`] each
+/// in `<state dir>/active`, so the ones cut off by a crash or a reboot can
+/// be listed with `dlm status` and continued with `dlm resume <id>`.
+///
+/// A running download rewrites its manifest every second and removes it
+/// once it succeeds; one that failed or was interrupted leaves it behind.
+pub struct ActiveDownloads {
+    dir: PathBuf,
+}
+
+/// Keeps a download's manifest current while it runs.
+pub struct Tracker {
+    tracked: Arc<Tracked>,
+    task: JoinHandle<()>,
+    finished: bool,
+}
+
+/// A running download's manifest, and what it's brought up to date from.
+struct Tracked {
+    path: PathBuf,
+    manifest: Mutex<Manifest>,
+    progress: Mutex<Option<ProgressHandle>>,
+    dns: DnsCache,
+}
+
+/// The downloads being tracked, for [`flush_running`].
+static RUNNING: Mutex<Vec<Weak<Tracked>>> = Mutex::new(Vec::new());
+
+impl ActiveDownloads {
+    /// `dir`, or by default `$XDG_STATE_HOME/download-manager` (falling back
+    /// to `~/.local/state`), or `%LOCALAPPDATA%\download-manager` on
+    /// Windows. `None` when there's no home directory to put it in.
+    pub fn open(dir: Option<&Path>) -> io::Result<Option<Self>> {
+        let Some(dir) = dir.map(Path::to_path_buf).or_else(default_dir) else {
+            return Ok(None);
+        };
+        let dir = dir.join("active");
+        fs::create_dir_all(&dir)?;
+        Ok(Some(Self { dir }))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Every manifest, oldest first. Unreadable ones are skipped.
+    pub fn list(&self) -> io::Result<Vec<Manifest>> {
+        let mut manifests = Vec::new();
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && let Ok(text) = fs::read(&path)
+                && let Ok(manifest) = serde_json::from_slice::<Manifest>(&text)
+            {
+                manifests.push(manifest);
+            }
+        }
+        manifests.sort_by_key(|manifest| manifest.started);
+        Ok(manifests)
+    }
+
+    /// Removes the manifest of download `id`, which another carried on.
+    pub fn forget(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.dir.join(format!("{id}.json")))
+    }
+
+    /// The manifest whose ID is or starts with `id`.
+    pub fn find(&self, id: &str) -> anyhow::Result<Manifest> {
+        let mut matches: Vec<_> = self
+            .list()?
+            .into_iter()
+            .filter(|manifest| manifest.id.starts_with(id))
+            .collect();
+        match matches.len() {
+            0 => bail!("No download '{id}' in '{}'", self.dir.display()),
+            1 => Ok(matches.remove(0)),
+            n => bail!("'{id}' matches {n} downloads, give more of the ID"),
+        }
+    }
+
+    /// Writes `manifest` and keeps it up to date with the transfer attached
+    /// to the returned tracker, and the node `dns` pins it to.
+    pub fn track(&self, manifest: Manifest, dns: DnsCache) -> Tracker {
+        let tracked = Arc::new(Tracked {
+            path: self.dir.join(format!("{}.json", manifest.id)),
+            manifest: Mutex::new(manifest),
+            progress: Mutex::default(),
+            dns,
+        });
+        if let Ok(mut running) = RUNNING.lock() {
+            running.retain(|tracked| tracked.strong_count() > 0);
+            running.push(Arc::downgrade(&tracked));
+        }
+        let task = tokio::spawn({
+            let tracked = tracked.clone();
+            async move {
+                let mut heartbeat = tokio::time::interval(HEARTBEAT);
+                loop {
+                    heartbeat.tick().await;
+                    if let Err(error) = tracked.update() {
+                        diagnostics::warn(
+                            WarningId::NotRecorded,
+                            format!(
+                                "Could not update '{}', `dlm status` won't list this download: {error}",
+                                tracked.path.display()
+                            ),
+                        );
+                        return;
+                    }
+                }
+            }
+        });
+        Tracker {
+            tracked,
+            task,
+            finished: false,
+        }
+    }
+}
+
+impl Tracker {
+    /// Makes the manifest follow this transfer.
+    pub fn attach(&self, handle: ProgressHandle) {
+        *self.tracked.progress.lock().unwrap() = Some(handle);
+    }
+
+    /// Removes the manifest if the download succeeded, and otherwise
+    /// leaves it for `dlm status`, with how far the download got.
+    pub async fn finish(mut self, succeeded: bool) {
+        // Wait for it, so a write in progress can't bring the file back.
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        self.finished = true;
+        if succeeded {
+            let _ = fs::remove_file(&self.tracked.path);
+        } else {
+            let _ = self.tracked.update();
+        }
+    }
+}
+
+/// A tracker dropped without [`finish`](Tracker::finish), as when the run
+/// fails before the download does, writes down how far it got all the same.
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.task.abort();
+        if !self.finished {
+            let _ = self.tracked.update();
+        }
+    }
+}
+
+/// Writes down how far every running download got, so each stays
+/// resumable when the run panics and no tracker gets to. A manifest the
+/// panicking thread holds the lock of is skipped rather than waited on.
+pub fn flush_running() {
+    let Ok(running) = RUNNING.try_lock() else {
+        return;
+    };
+    for tracked in running.iter().filter_map(Weak::upgrade) {
+        if let (Ok(mut manifest), Ok(progress)) =
+            (tracked.manifest.try_lock(), tracked.progress.try_lock())
+        {
+            let _ = update(
+                &tracked.path,
+                &mut manifest,
+                progress.as_ref(),
+                &tracked.dns,
+            );
+        }
+    }
+}
+
+impl Tracked {
+    fn update(&self) -> io::Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        let progress = self.progress.lock().unwrap();
+        update(&self.path, &mut manifest, progress.as_ref(), &self.dns)
+    }
+}
+
+/// Brings the manifest at `path` up to date with the transfer, if one is
+/// attached yet.
+fn update(
+    path: &Path,
+    manifest: &mut Manifest,
+    progress: Option<&ProgressHandle>,
+    dns: &DnsCache,
+) -> io::Result<()> {
+    manifest.pinned = dns.pinned();
+    if let Some(handle) = progress {
+        let snapshot = handle.snapshot();
+        manifest.downloaded = snapshot.downloaded;
+        manifest.total = snapshot.total;
+        manifest.prefix = snapshot.prefix;
+        manifest.chunks = snapshot.chunks;
+        manifest.parts = snapshot.parts;
+        manifest.etag = snapshot.etag;
+        manifest.etag_mismatches = snapshot.etag_mismatches;
+        manifest.part_checks = snapshot.part_checks;
+    }
+    manifest.updated = now();
+    write(path, manifest)
+}
+
+/// Writes through a rename so a crash never leaves half a manifest. They
+/// hold the full command line, headers and all, so only the owner can read
+/// them.
+fn write(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let partial = path.with_extension("json.partial");
+    fs::write(
+        &partial,
+        serde_json::to_vec_pretty(&Versioned::new(manifest))?,
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&partial, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(partial, path)
+}
+
+/// The state directory without `--state-dir`, `None` without a home.
+pub fn default_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".local/state")));
+    Some(base?.join("download-manager"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Prints the downloads in `downloads` with how far along each is and the
+/// commands that continue it.
+pub fn print_status(downloads: &ActiveDownloads) -> anyhow::Result<()> {
+    let manifests = downloads
+        .list()
+        .with_context(|| format!("Cannot read '{}'", downloads.dir().display()))?;
+    if manifests.is_empty() {
+        println!("No downloads in flight ({})", downloads.dir().display());
+        return Ok(());
+    }
+    println!("{:<12}  {:<8}  {:>4}  DESTINATION", "ID", "STATE", "DONE");
+    for manifest in manifests {
+        println!(
+            "{:<12}  {:<8}  {:>4}  {} ({})",
+            manifest.id,
+            if manifest.is_running() {
+                "running"
+            } else {
+                "orphaned"
+            },
+            done(manifest.downloaded, manifest.total),
+            manifest.destination.display(),
+            of(manifest.downloaded, manifest.total)
+        );
+        if !manifest.is_running() {
+            println!("    dlm resume {}", manifest.id);
+            println!("    {}", manifest.command());
+        }
+    }
+    Ok(())
+}
+
+/// How much of `total` bytes `downloaded` is, as a percentage, `?` when
+/// the size isn't known.
+pub fn done(downloaded: u64, total: u64) -> String {
+    match (downloaded.min(total) * 100).checked_div(total) {
+        Some(percent) => format!("{percent}%"),
+        None => "?".to_string(),
+    }
+}
+
+/// `downloaded` of `total` bytes, as in `1.2 MiB of 3 MiB`.
+pub fn of(downloaded: u64, total: u64) -> String {
+    format!(
+        "{} of {}",
+        indicatif::HumanBytes(downloaded),
+        match total {
+            0 => "unknown".to_string(),
+            total => indicatif::HumanBytes(total).to_string(),
+        }
+    )
+}
@@ -0,0 +1,188 @@
+//! `--stats` and `--write-out`: how a finished download went, as a table
+//! or as curl-style `%{variable}` text for scripts. Time to first byte is
+//! reported apart from the transfer and left out of the speed, so a short
+//! download from a server slow to answer isn't taken for a slow link.
+
+use download_manager::__private::speed::{self, Rate, Size, TtfbSpread};
+use download_manager::ProgressSnapshot;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What `--stats` and `--write-out` report on.
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+    pub url: String,
+    pub filename_effective: String,
+    /// Bytes this run downloaded, without what an earlier one left.
+    pub size_download: u64,
+    /// Bytes/s, time to first byte left out.
+    pub speed_download: u64,
+    /// Seconds from the start of the run to the end of the transfer.
+    pub time_total: f64,
+    /// Seconds from sending the request to the first body byte.
+    pub time_starttransfer: Option<f64>,
+    pub ttfb_spread: Option<TtfbSpread>,
+    pub sha256: String,
+    pub num_retries: u64,
+}
+
+impl Stats {
+    /// `elapsed` is the run's time up to the end of the transfer.
+    pub fn new(
+        url: String,
+        path: &Path,
+        sha256: String,
+        elapsed: Duration,
+        snapshot: &ProgressSnapshot,
+    ) -> Self {
+        let ttfb = snapshot.ttfb_ms.map(Duration::from_millis);
+        let size = snapshot.downloaded.saturating_sub(snapshot.prefix);
+        Self {
+            url,
+            filename_effective: path.display().to_string(),
+            size_download: size,
+            speed_download: speed::transfer_rate(size, elapsed, ttfb),
+            time_total: elapsed.as_secs_f64(),
+            time_starttransfer: ttfb.map(|ttfb| ttfb.as_secs_f64()),
+            ttfb_spread: snapshot.ttfb_spread,
+            sha256,
+            num_retries: snapshot.retries.used,
+        }
+    }
+
+    /// What `--stats` prints.
+    pub fn table(&self) -> String {
+        let mut rows = vec![
+            ("Size", Size(self.size_download).to_string()),
+            ("Time", format!("{:.2}s", self.time_total)),
+            (
+                "First byte",
+                self.time_starttransfer
+                    .map_or("?".to_string(), |ttfb| format!("{:.0}ms", ttfb * 1000.0)),
+            ),
+        ];
+        if let Some(spread) = self.ttfb_spread {
+            rows.push(("First byte spread", spread.to_string()));
+        }
+        rows.push((
+            "Speed",
+            format!("{} after the first byte", Rate(self.speed_download)),
+        ));
+        rows.push(("Retries", self.num_retries.to_string()));
+        let mut table = "Stats:\n".to_string();
+        for (label, value) in rows {
+            table += &format!("  {:<18} {value}\n", format!("{label}:"));
+        }
+        table
+    }
+
+    fn variable(&self, name: &str) -> String {
+        let seconds = |value: Option<f64>| value.map_or(String::new(), |s| format!("{s:.6}"));
+        let spread_ms = |ms: fn(&TtfbSpread) -> u64| {
+            self.ttfb_spread
+                .as_ref()
+                .map_or(String::new(), |spread| ms(spread).to_string())
+        };
+        match name {
+            "url" => self.url.clone(),
+            "filename_effective" => self.filename_effective.clone(),
+            "size_download" => self.size_download.to_string(),
+            "speed_download" => self.speed_download.to_string(),
+            "time_total" => seconds(Some(self.time_total)),
+            "time_starttransfer" => seconds(self.time_starttransfer),
+            "ttfb_min_ms" => spread_ms(|spread| spread.min_ms),
+            "ttfb_median_ms" => spread_ms(|spread| spread.median_ms),
+            "ttfb_max_ms" => spread_ms(|spread| spread.max_ms),
+            "sha256" => self.sha256.clone(),
+            "num_retries" => self.num_retries.to_string(),
+            "json" => serde_json::to_string(self).expect("stats serialize"),
+            _ => unreachable!("checked when parsed"),
+        }
+    }
+}
+
+/// The variables `--write-out` knows.
+const VARIABLES: &[&str] = &[
+    "url",
+    "filename_effective",
+    "size_download",
+    "speed_download",
+    "time_total",
+    "time_starttransfer",
+    "ttfb_min_ms",
+    "ttfb_median_ms",
+    "ttfb_max_ms",
+    "sha256",
+    "num_retries",
+    "json",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Variable(&'static str),
+}
+
+/// A `--write-out` format: text with `%{variable}`s and `\n`, `\t` or `\\`
+/// escapes, as curl takes it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteOut(Vec<Piece>);
+
+impl FromStr for WriteOut {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = value;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("%{") {
+                let end = after
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed '%{{' in '{value}'"))?;
+                let name = &after[..end];
+                let variable = VARIABLES
+                    .iter()
+                    .find(|variable| **variable == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown variable '{name}', expected one of {}",
+                            VARIABLES.join(", ")
+                        )
+                    })?;
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+                pieces.push(Piece::Variable(variable));
+                rest = &after[end + 1..];
+                continue;
+            }
+            match (c, rest[c.len_utf8()..].chars().next()) {
+                ('\\', Some('n')) => text.push('\n'),
+                ('\\', Some('t')) => text.push('\t'),
+                ('\\', Some('\\')) => text.push('\\'),
+                _ => {
+                    text.push(c);
+                    rest = &rest[c.len_utf8()..];
+                    continue;
+                }
+            }
+            rest = &rest[2..];
+        }
+        pieces.push(Piece::Text(text));
+        pieces.retain(|piece| *piece != Piece::Text(String::new()));
+        Ok(WriteOut(pieces))
+    }
+}
+
+impl WriteOut {
+    pub fn render(&self, stats: &Stats) -> String {
+        self.0
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Variable(name) => stats.variable(name),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,145 @@
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::http;
+use download_manager::__private::throttle::Throttle;
+use download_manager::MemoryLimit;
+use download_manager::schema::{DownloadStatus, Status, Versioned};
+use download_manager::{ProgressHandle, TransferState};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// `--status-file`: what `/status.json` of `--web-status` answers, kept in
+/// a file for whatever can read files but not open sockets. It's replaced
+/// through a rename, so a reader sees the old document or the new one and
+/// never half of either.
+pub struct StatusFile {
+    written: Arc<Written>,
+    task: JoinHandle<()>,
+    finished: bool,
+}
+
+/// The file, and what it's brought up to date from.
+struct Written {
+    path: PathBuf,
+    progress: Mutex<Option<ProgressHandle>>,
+    throttle: Throttle,
+    memory: Arc<MemoryLimit>,
+    name: String,
+    url: String,
+}
+
+impl StatusFile {
+    /// Writes the status of the download of `url` to `destination` to
+    /// `path`, and again every `interval` until [`finish`](Self::finish).
+    pub fn start(
+        path: &Path,
+        interval: Duration,
+        throttle: Throttle,
+        memory: Arc<MemoryLimit>,
+        url: &Url,
+        destination: &Path,
+    ) -> Self {
+        // Its query may hold a signature, and the file may be read by
+        // anyone who can see the directory.
+        let mut shown = url.clone();
+        shown.set_query(None);
+        let written = Arc::new(Written {
+            path: path.to_path_buf(),
+            progress: Mutex::default(),
+            throttle,
+            memory,
+            name: destination
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            url: http::redact_url(&shown),
+        });
+        let task = tokio::spawn({
+            let written = written.clone();
+            async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    ticks.tick().await;
+                    if let Err(error) = written.write(None) {
+                        diagnostics::warn(
+                            WarningId::NotRecorded,
+                            format!(
+                                "Could not write the status file '{}': {error}",
+                                written.path.display()
+                            ),
+                        );
+                        return;
+                    }
+                }
+            }
+        });
+        StatusFile {
+            written,
+            task,
+            finished: false,
+        }
+    }
+
+    /// Makes the file follow this transfer.
+    pub fn attach(&self, handle: ProgressHandle) {
+        *self.written.progress.lock().unwrap() = Some(handle);
+    }
+
+    /// Writes the run's final `state`, which is the download's rather than
+    /// the transfer's: a file that failed its checksum transferred fine.
+    /// Then removes the file if the download succeeded, unless `keep`.
+    pub async fn finish(mut self, state: TransferState, keep: bool) {
+        // Wait for it, so a write in progress can't follow this one.
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        self.finished = true;
+        let succeeded = state == TransferState::Completed;
+        if succeeded && !keep {
+            let _ = fs::remove_file(&self.written.path);
+        } else {
+            let _ = self.written.write(Some(state));
+        }
+    }
+}
+
+/// A status file dropped without [`finish`](StatusFile::finish), as when
+/// the run fails before the download does, is written one last time all
+/// the same.
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        self.task.abort();
+        if !self.finished {
+            let _ = self.written.write(None);
+        }
+    }
+}
+
+impl Written {
+    /// Replaces the file with the current status, in `state` if given.
+    fn write(&self, state: Option<TransferState>) -> io::Result<()> {
+        let mut status = Status::of(
+            self.progress.lock().unwrap().as_ref(),
+            &self.throttle,
+            &self.memory,
+        );
+        if let Some(state) = state {
+            status.progress.state = state;
+            status.eta_secs = None;
+        }
+        let download = DownloadStatus {
+            name: self.name.clone(),
+            url: self.url.clone(),
+            status,
+        };
+        let mut partial = OsString::from(self.path.as_os_str());
+        partial.push(".partial");
+        fs::write(&partial, serde_json::to_vec(&Versioned::new(download))?)?;
+        fs::rename(partial, &self.path)
+    }
+}
@@ -0,0 +1,181 @@
+//! Running as a systemd service: sockets passed in by socket activation
+//! (`LISTEN_FDS`), and readiness and progress reported over `NOTIFY_SOCKET`
+//! so `systemctl status` shows how far along the download is. Both are the
+//! protocols `sd_listen_fds(3)` and `sd_notify(3)` speak; outside systemd
+//! neither variable is set and none of this does anything.
+
+use download_manager::__private::speed::Size;
+use download_manager::ProgressHandle;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// The first descriptor systemd passes, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// How often `STATUS=` is brought up to date while downloading.
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sockets passed in and not taken yet, by name.
+static INHERITED: OnceLock<Mutex<Vec<(String, OwnedFd)>>> = OnceLock::new();
+
+/// Takes the inherited socket named `name` (`FileDescriptorName=` in the
+/// `.socket` unit), or the only one passed if they have no names. `None`
+/// without socket activation.
+pub fn listener(name: &str) -> anyhow::Result<Option<UnixListener>> {
+    let mut inherited = INHERITED
+        .get_or_init(|| Mutex::new(inherited_fds()))
+        .lock()
+        .unwrap();
+    let position = match inherited.iter().position(|(fd_name, _)| fd_name == name) {
+        Some(position) => position,
+        None if inherited.len() == 1 && inherited[0].0 == "unknown" => 0,
+        None => return Ok(None),
+    };
+    let (_, fd) = inherited.remove(position);
+    let listener = UnixListener::from(fd);
+    // A TCP socket would get this far too.
+    if listener.local_addr().is_err() {
+        anyhow::bail!("The socket systemd passed as '{name}' isn't a Unix socket");
+    }
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// The descriptors `LISTEN_FDS` says were passed to this process, named
+/// after `LISTEN_FDNAMES`, or "unknown" as `sd_listen_fds_with_names` does.
+fn inherited_fds() -> Vec<(String, OwnedFd)> {
+    let ours = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|_| ours)
+        .unwrap_or(0);
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names.next().filter(|name| !name.is_empty());
+            // SAFETY: systemd hands these over for this process to own,
+            // and LISTEN_PID says they're meant for this one.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            (name.unwrap_or("unknown").to_string(), fd)
+        })
+        .collect()
+}
+
+/// Where `sd_notify` messages go.
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+}
+
+impl Notifier {
+    /// The socket `NOTIFY_SOCKET` names, a path or an abstract `@name`.
+    /// `None` when not started by systemd with `Type=notify`.
+    pub fn from_env() -> Option<Self> {
+        let target = std::env::var_os("NOTIFY_SOCKET")?;
+        let target = target.to_string_lossy();
+        let address = match target.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(&*target),
+        };
+        let notifier = Self {
+            socket: UnixDatagram::unbound().ok()?,
+            address: address.ok()?,
+        };
+        Some(notifier)
+    }
+
+    /// Sends `state`, newline-separated `KEY=value` assignments. Failing to
+    /// is logged and otherwise ignored, as the download doesn't depend on it.
+    pub fn notify(&self, state: &str) {
+        if let Err(error) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            tracing::debug!("Could not notify systemd: {error}");
+        }
+    }
+}
+
+/// Tells systemd the download started and keeps its `STATUS=` current.
+pub struct Status {
+    notifier: Arc<Notifier>,
+    name: String,
+    progress: Arc<Mutex<Option<ProgressHandle>>>,
+    task: JoinHandle<()>,
+}
+
+impl Status {
+    /// Sends `READY=1` for the download of `name`. `None` outside systemd.
+    pub fn start(name: &str) -> Option<Self> {
+        let notifier = Arc::new(Notifier::from_env()?);
+        notifier.notify(&format!("READY=1\nSTATUS=Starting {name}"));
+        let progress: Arc<Mutex<Option<ProgressHandle>>> = Arc::default();
+        let task = tokio::spawn({
+            let notifier = notifier.clone();
+            let progress = progress.clone();
+            let name = name.to_string();
+            async move {
+                let mut interval = tokio::time::interval(STATUS_INTERVAL);
+                let mut last = String::new();
+                loop {
+                    interval.tick().await;
+                    let Some(snapshot) = progress.lock().unwrap().as_ref().map(|h| h.snapshot())
+                    else {
+                        continue;
+                    };
+                    let downloaded = Size(snapshot.downloaded);
+                    let status = match (snapshot.downloaded.min(snapshot.total) * 100)
+                        .checked_div(snapshot.total)
+                    {
+                        Some(percent) => format!(
+                            "Downloading {name}: {percent}% ({downloaded} of {})",
+                            Size(snapshot.total)
+                        ),
+                        None => format!("Downloading {name}: {downloaded}"),
+                    };
+                    if status != last {
+                        notifier.notify(&format!("STATUS={status}"));
+                        last = status;
+                    }
+                }
+            }
+        });
+        Some(Self {
+            notifier,
+            name: name.to_string(),
+            progress,
+            task,
+        })
+    }
+
+    /// Makes `STATUS=` follow this transfer.
+    pub fn attach(&self, handle: ProgressHandle) {
+        *self.progress.lock().unwrap() = Some(handle);
+    }
+
+    /// Reports how the download ended.
+    pub async fn finish(mut self, succeeded: bool) {
+        // Wait for it, so a late update can't overwrite the outcome.
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        let outcome = if succeeded {
+            "Downloaded"
+        } else {
+            "Failed to download"
+        };
+        self.notifier
+            .notify(&format!("STOPPING=1\nSTATUS={outcome} {}", self.name));
+    }
+}
+
+impl Drop for Status {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
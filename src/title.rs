@@ -0,0 +1,78 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Whether the title before ours is saved on the terminal's stack, for
+/// [`restore`] to pop.
+static SAVED: AtomicBool = AtomicBool::new(false);
+
+/// Shows download progress in the terminal title (OSC 0), so it's visible
+/// from a tmux status line or window list.
+#[derive(Clone)]
+pub struct TerminalTitle {
+    name: Arc<str>,
+    /// Last title written, to avoid re-sending identical escapes.
+    last: Arc<Mutex<String>>,
+}
+
+/// Restores the previous title when dropped, whether the download finished,
+/// failed or was interrupted.
+pub struct TitleGuard;
+
+impl TerminalTitle {
+    /// Saves the current title and returns a handle to update it, unless
+    /// `enabled` is false or stderr isn't a terminal.
+    pub fn start(name: &str, enabled: bool) -> Option<(Self, TitleGuard)> {
+        if !enabled || !std::io::stderr().is_terminal() {
+            return None;
+        }
+        // XTWINOPS: push the current title onto the terminal's stack.
+        write_escape("\x1b[22;0t");
+        SAVED.store(true, Ordering::SeqCst);
+        let title = Self {
+            name: name.into(),
+            last: Arc::default(),
+        };
+        Some((title, TitleGuard))
+    }
+
+    /// Shows the percentage done, or the byte count when the total is
+    /// unknown. Worker mode passes the aggregate of all chunks.
+    pub fn update(&self, downloaded: u64, total: u64) {
+        let progress = match (downloaded.min(total) * 100).checked_div(total) {
+            Some(percent) => format!("{percent}%"),
+            None => indicatif::HumanBytes(downloaded).to_string(),
+        };
+        let title = format!("⬇ {progress} {} — download-manager", self.name);
+        let Ok(mut last) = self.last.lock() else {
+            return;
+        };
+        if *last != title {
+            write_escape(&format!("\x1b]0;{title}\x07"));
+            *last = title;
+        }
+    }
+}
+
+impl Drop for TitleGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Gives the terminal back the title it had before the download, if it's
+/// still showing ours: when the guard drops, or when the run panics and it
+/// won't.
+pub fn restore() {
+    if SAVED.swap(false, Ordering::SeqCst) {
+        // Clear ours first, for terminals that don't keep a title stack,
+        // then pop the saved one.
+        write_escape("\x1b]0;\x07\x1b[23;0t");
+    }
+}
+
+fn write_escape(escape: &str) {
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(escape.as_bytes());
+    let _ = stderr.flush();
+}
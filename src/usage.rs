@@ -0,0 +1,280 @@
+//! `dlm usage`: how much every run transferred, for a metered connection.
+//! Each download appends a line to `usage.jsonl` in the state directory
+//! once it's over, failed and interrupted ones too, as they use up the
+//! quota just the same. Times are kept in UTC and shown in local time.
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Local, TimeZone};
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::fs_ops;
+use download_manager::__private::speed::Size;
+use download_manager::__private::utils;
+use download_manager::DownloadError;
+use download_manager::schema::{Outcome, Transfer, Versioned};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What `dlm usage` totals transfers by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            other => Err(format!(
+                "unknown period '{other}', expected day, week or month"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        })
+    }
+}
+
+impl Period {
+    /// The local day, ISO week or month `time` falls in, as in
+    /// `2026-10-15`, `2026-W42` or `2026-10`.
+    fn label(&self, time: DateTime<Local>) -> String {
+        match self {
+            Period::Day => time.format("%Y-%m-%d").to_string(),
+            Period::Week => {
+                let week = time.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Period::Month => time.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// `--limit day=5G`: how much a day or month may transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub period: Period,
+    pub bytes: u64,
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (period, bytes) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected day=SIZE or month=SIZE, got '{value}'"))?;
+        let period = match period.parse()? {
+            Period::Week => return Err("limits are per day or per month".to_string()),
+            period => period,
+        };
+        Ok(Limit {
+            period,
+            bytes: utils::parse_byte_size(bytes)?,
+        })
+    }
+}
+
+/// The limits `dlm usage --limit` set, in `usage-limits.json`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Limits {
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+    /// Refuse to start a download past a limit, rather than warn.
+    pub enforce: bool,
+}
+
+/// The usage log and limits in the state directory.
+pub struct UsageLog {
+    dir: PathBuf,
+}
+
+impl UsageLog {
+    /// The log in `dir`, or in the default state directory (see
+    /// [`ActiveDownloads::open`](crate::state::ActiveDownloads::open)).
+    /// `None` when there's no home directory to put it in.
+    pub fn open(dir: Option<&Path>) -> io::Result<Option<Self>> {
+        let Some(dir) = dir
+            .map(Path::to_path_buf)
+            .or_else(crate::state::default_dir)
+        else {
+            return Ok(None);
+        };
+        fs::create_dir_all(&dir)?;
+        Ok(Some(Self { dir }))
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join("usage.jsonl")
+    }
+
+    fn limits_path(&self) -> PathBuf {
+        self.dir.join("usage-limits.json")
+    }
+
+    /// Appends `transfer` with a single write, so runs ending at once don't
+    /// interleave their lines.
+    pub fn record(&self, transfer: &Transfer) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&Versioned::new(transfer))?;
+        line.push(b'\n');
+        let mut file = fs_ops::open(
+            OpenOptions::new().create(true).append(true),
+            &self.log_path(),
+        )?;
+        file.write_all(&line)
+    }
+
+    /// Every transfer recorded, oldest first. Unreadable lines are skipped.
+    pub fn transfers(&self) -> io::Result<Vec<Transfer>> {
+        let file = match fs::File::open(self.log_path()) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            file => file?,
+        };
+        let mut transfers = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(transfer) = serde_json::from_str(&line?) {
+                transfers.push(transfer);
+            }
+        }
+        Ok(transfers)
+    }
+
+    pub fn limits(&self) -> anyhow::Result<Limits> {
+        match fs::read(self.limits_path()) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Limits::default()),
+            Err(error) => Err(error.into()),
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Cannot read '{}'", self.limits_path().display())),
+        }
+    }
+
+    pub fn set_limits(&self, limits: &Limits) -> anyhow::Result<()> {
+        fs::write(self.limits_path(), serde_json::to_vec_pretty(limits)?)
+            .with_context(|| format!("Cannot write '{}'", self.limits_path().display()))
+    }
+
+    /// Before a download: warns once today's or this month's transfers are
+    /// past a limit, or fails with [`DownloadError::OverUsageLimit`] if
+    /// it's enforced and `force` isn't given.
+    pub fn check(&self, force: bool) -> anyhow::Result<()> {
+        let limits = self.limits()?;
+        if limits.daily.is_none() && limits.monthly.is_none() {
+            return Ok(());
+        }
+        let transfers = self.transfers()?;
+        let now = Local::now();
+        for (period, limit) in [(Period::Day, limits.daily), (Period::Month, limits.monthly)] {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let used = used_in(&transfers, period, now);
+            if used < limit {
+                continue;
+            }
+            let error = DownloadError::OverUsageLimit {
+                period: period.to_string(),
+                used,
+                limit,
+            };
+            match limits.enforce && !force {
+                true => {
+                    return Err(
+                        anyhow::Error::new(error).context("Pass --force to download anyway")
+                    );
+                }
+                false => diagnostics::warn_or_fail(WarningId::UsageLimit, error.to_string())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the transfers in the local day or month of `now` add up to.
+fn used_in(transfers: &[Transfer], period: Period, now: DateTime<Local>) -> u64 {
+    let current = period.label(now);
+    transfers
+        .iter()
+        .filter(|transfer| period.label(transfer.local_time()) == current)
+        .map(Transfer::total)
+        .sum()
+}
+
+/// The totals of one day, week or month.
+#[derive(Default)]
+struct Totals {
+    bytes: u64,
+    wasted: u64,
+    transfers: usize,
+    failed: usize,
+}
+
+/// Prints what every day, week or month transferred, and the limits.
+pub fn print_usage(log: &UsageLog, by: Period) -> anyhow::Result<()> {
+    let transfers = log.transfers()?;
+    let limits = log.limits()?;
+    if transfers.is_empty() {
+        println!("Nothing downloaded yet ({})", log.log_path().display());
+    } else {
+        let mut periods: BTreeMap<String, Totals> = BTreeMap::new();
+        for transfer in &transfers {
+            let totals = periods.entry(by.label(transfer.local_time())).or_default();
+            totals.bytes += transfer.bytes;
+            totals.wasted += transfer.wasted;
+            totals.transfers += 1;
+            if transfer.outcome != Outcome::Completed {
+                totals.failed += 1;
+            }
+        }
+        println!(
+            "{:<10}  {:>10}  {:>10}  {:>10}  {:>9}  FAILED",
+            by.to_string().to_uppercase(),
+            "TOTAL",
+            "USEFUL",
+            "WASTED",
+            "DOWNLOADS"
+        );
+        for (label, totals) in periods {
+            println!(
+                "{label:<10}  {:>10}  {:>10}  {:>10}  {:>9}  {}",
+                Size(totals.bytes + totals.wasted).to_string(),
+                Size(totals.bytes).to_string(),
+                Size(totals.wasted).to_string(),
+                totals.transfers,
+                totals.failed
+            );
+        }
+    }
+    let now = Local::now();
+    for (period, limit) in [(Period::Day, limits.daily), (Period::Month, limits.monthly)] {
+        if let Some(limit) = limit {
+            println!(
+                "Limit per {period}: {} ({} used, {})",
+                Size(limit),
+                Size(used_in(&transfers, period, now)),
+                match limits.enforce {
+                    true => "enforced",
+                    false => "warns only",
+                }
+            );
+        }
+    }
+    Ok(())
+}
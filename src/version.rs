@@ -0,0 +1,33 @@
+use download_manager::schema::{EnvFlag, VersionReport, Versioned};
+
+/// Prints the report, as JSON when `json` is set.
+pub fn print_version(json: bool, environment: &[EnvFlag]) -> anyhow::Result<()> {
+    let report = VersionReport {
+        environment: environment.to_vec(),
+        ..VersionReport::current()
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&Versioned::new(&report))?
+        );
+        return Ok(());
+    }
+
+    println!("dlm {}", report.version);
+    println!("Commit:    {}", report.commit);
+    println!("Built:     {} ({})", report.build_date, report.profile);
+    println!("Target:    {}", report.target);
+    println!(
+        "Features:  {}",
+        match report.features.is_empty() {
+            true => "none".to_string(),
+            false => report.features.join(", "),
+        }
+    );
+    println!("TLS:       {}", report.tls.join(", "));
+    for flag in &report.environment {
+        println!("Env:       {flag}");
+    }
+    Ok(())
+}
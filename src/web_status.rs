@@ -0,0 +1,183 @@
+use anyhow::Context;
+use download_manager::__private::diagnostics::{self, WarningId};
+use download_manager::__private::http;
+use download_manager::__private::throttle::Throttle;
+use download_manager::MemoryLimit;
+use download_manager::ProgressHandle;
+use download_manager::schema::{DownloadStatus, Status, Versioned};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// The page: no external assets, it polls `/status.json` once a second.
+const PAGE: &str = include_str!("web_status.html");
+
+/// The most of a request read before giving up on it.
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `--web-status`: a status page for a browser elsewhere, at `/`, and the
+/// JSON it polls, at `/status.json`. That's the control socket's `status`
+/// plus what's being downloaded. Strictly read-only: nothing it serves
+/// changes the download.
+pub struct WebStatus {
+    progress: Arc<Mutex<Option<ProgressHandle>>>,
+}
+
+/// Stops serving when dropped, i.e. when the download is over.
+pub struct WebStatusGuard {
+    task: JoinHandle<()>,
+}
+
+struct State {
+    progress: Arc<Mutex<Option<ProgressHandle>>>,
+    throttle: Throttle,
+    memory: Arc<MemoryLimit>,
+    name: String,
+    url: String,
+}
+
+/// Parses `--web-status`: an address and port, or a bare port to serve on
+/// localhost.
+pub fn parse_address(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = value.parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    }
+    value
+        .parse()
+        .map_err(|_| format!("'{value}' is neither a port nor an address like 0.0.0.0:8080"))
+}
+
+impl WebStatus {
+    /// Serves the status of the download of `url` to `destination` on
+    /// `address`.
+    pub async fn start(
+        address: SocketAddr,
+        throttle: Throttle,
+        memory: Arc<MemoryLimit>,
+        url: &Url,
+        destination: &Path,
+    ) -> anyhow::Result<(Self, WebStatusGuard)> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Cannot serve the status page on {address}"))?;
+        let address = listener.local_addr()?;
+        if !address.ip().is_loopback() {
+            diagnostics::warn(
+                WarningId::StatusPagePublic,
+                format!(
+                    "The status page on {address} shows the download to anyone who can reach it"
+                ),
+            );
+        }
+        println!("Status page: http://{address}/");
+        // Its query may hold a signature, and the page may be seen from
+        // anywhere.
+        let mut shown = url.clone();
+        shown.set_query(None);
+        let progress = Arc::default();
+        let state = Arc::new(State {
+            progress: Arc::clone(&progress),
+            throttle,
+            memory,
+            name: destination
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            url: http::redact_url(&shown),
+        });
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, Arc::clone(&state)));
+            }
+        });
+        Ok((Self { progress }, WebStatusGuard { task }))
+    }
+
+    /// Makes the page show this transfer.
+    pub fn attach(&self, handle: ProgressHandle) {
+        *self.progress.lock().unwrap() = Some(handle);
+    }
+}
+
+impl Drop for WebStatusGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers one request and closes the connection.
+async fn serve(mut stream: TcpStream, state: Arc<State>) {
+    let Ok(Some(head)) = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await else {
+        return;
+    };
+    let mut words = head.split_whitespace();
+    let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let response = match (method, path) {
+        ("GET" | "HEAD", "/") => response("200 OK", "text/html; charset=utf-8", PAGE.into()),
+        ("GET" | "HEAD", "/status.json") => {
+            let status = Status::of(
+                state.progress.lock().unwrap().as_ref(),
+                &state.throttle,
+                &state.memory,
+            );
+            let download = DownloadStatus {
+                name: state.name.clone(),
+                url: state.url.clone(),
+                status,
+            };
+            let body = serde_json::to_string(&Versioned::new(download)).expect("status serializes");
+            response("200 OK", "application/json", body)
+        }
+        ("GET" | "HEAD", _) => response("404 Not Found", "text/plain", "Not found\n".into()),
+        _ => response(
+            "405 Method Not Allowed",
+            "text/plain",
+            "Read-only: only GET and HEAD\n".into(),
+        ),
+    };
+    let response = match method {
+        // The same head, without the body.
+        "HEAD" => response.split_inclusive("\r\n\r\n").next().unwrap_or(""),
+        _ => &response,
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Reads a request up to the blank line after its headers. None if the
+/// client goes away first or sends more than [`MAX_REQUEST`].
+async fn read_head(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 || head.len() + read > MAX_REQUEST {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn response(status: &str, content_type: &str, body: String) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         X-Content-Type-Options: nosniff\r\n\
+         Content-Security-Policy: default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
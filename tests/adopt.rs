@@ -0,0 +1,94 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+#[test]
+fn adopted_partial_file_is_continued() {
+    let data = payload(200_000);
+    for command in ["download-blocking", "download-async"] {
+        let server = TestServer::builder(data.clone()).start();
+        let dir = scratch_dir(&format!("adopted_partial_file_is_continued_{command}"));
+        let partial = dir.join("file.bin.crdownload");
+        std::fs::write(&partial, &data[..120_000]).unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--adopt",
+            partial.to_str().unwrap(),
+            "--adopt-verify",
+            "4k",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(!partial.exists());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Reused: 117.19 KiB"), "{stdout}");
+        let ranges: Vec<_> = server
+            .requests()
+            .iter()
+            .filter_map(|request| request.header("Range").map(str::to_string))
+            .collect();
+        assert!(
+            ranges.contains(&"bytes=115904-119999".to_string()),
+            "{ranges:?}"
+        );
+        assert!(
+            ranges.last().unwrap().starts_with("bytes=120000-"),
+            "{ranges:?}"
+        );
+    }
+}
+
+#[test]
+fn partial_file_that_does_not_fit_is_refused() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("partial_file_that_does_not_fit_is_refused");
+    let partial = dir.join("file.bin.part");
+    let url = server.url("/file.bin");
+
+    let mut other = data[..50_000].to_vec();
+    other[49_999] ^= 0xff;
+    for (contents, flags, error) in [
+        (
+            payload(150_000),
+            &[][..],
+            "more than the 100000 of the remote file",
+        ),
+        (
+            other,
+            &["--adopt-verify", "1k"][..],
+            "differ from the remote file",
+        ),
+    ] {
+        std::fs::write(&partial, contents).unwrap();
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--adopt",
+            partial.to_str().unwrap(),
+        ];
+        args.extend(flags);
+        args.extend([url.as_str(), "download-async"]);
+        let output = run_dlm(&args);
+
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{stderr}");
+        assert!(partial.exists());
+        assert!(!dir.join("file.bin").exists());
+    }
+
+    let output = run_dlm(&[
+        "--adopt",
+        partial.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{run_dlm, scratch_dir, sha256_hex};
+
+#[test]
+fn audit_reports_new_missing_and_modified_files_against_the_baseline() {
+    let dir = scratch_dir("audit_reports_changes");
+    let target = dir.to_str().unwrap();
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("kept.bin"), "kept").unwrap();
+    std::fs::write(dir.join("sub/changed.bin"), "before").unwrap();
+    std::fs::write(dir.join("gone.bin"), "gone").unwrap();
+
+    let output = run_dlm(&["-t", target, "audit"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("audit --update"),
+        "{output:?}"
+    );
+
+    let output = run_dlm(&["-t", target, "audit", "--update"]);
+    assert!(output.status.success(), "{output:?}");
+    let baseline = std::fs::read_to_string(dir.join(".dlm-audit.sha256")).unwrap();
+    assert!(
+        baseline.contains(&format!("{}  kept.bin\n", sha256_hex(b"kept"))),
+        "{baseline}"
+    );
+
+    let output = run_dlm(&["-t", target, "audit"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "3 verified, 0 modified, 0 new, 0 missing, 0 unreadable\n"
+    );
+
+    std::fs::write(dir.join("sub/changed.bin"), "after").unwrap();
+    std::fs::remove_file(dir.join("gone.bin")).unwrap();
+    std::fs::write(dir.join("added.bin"), "added").unwrap();
+    let output = run_dlm(&["-t", target, "audit"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "NEW         added.bin",
+        "MISSING     gone.bin",
+        &format!(
+            "MODIFIED    {}",
+            std::path::Path::new("sub/changed.bin").display()
+        ),
+        "1 verified, 1 modified, 1 new, 1 missing, 0 unreadable",
+    ] {
+        assert!(stdout.contains(line), "{line} in {stdout}");
+    }
+
+    let output = run_dlm(&["-t", target, "audit", "--update"]);
+    assert!(output.status.success(), "{output:?}");
+    let output = run_dlm(&["-t", target, "audit"]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn audit_json_lists_every_file_with_its_hash() {
+    let dir = scratch_dir("audit_json");
+    let target = dir.to_str().unwrap();
+    std::fs::write(dir.join("a.bin"), "a").unwrap();
+    assert!(
+        run_dlm(&["-t", target, "audit", "--update", "--algo", "md5"])
+            .status
+            .success()
+    );
+    std::fs::write(dir.join("a.bin"), "b").unwrap();
+
+    let output = run_dlm(&["-t", target, "audit", "--algo", "md5", "--json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let audit: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(audit["algorithm"], "md5");
+    assert_eq!(
+        audit["files"],
+        serde_json::json!([{
+            "path": "a.bin",
+            "status": "modified",
+            "hash": "92eb5ffee6ae2fec3ad71c777531578f",
+        }])
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn audit_hashes_hard_links_once_and_skips_symlinks() {
+    let dir = scratch_dir("audit_links");
+    let target = dir.to_str().unwrap();
+    std::fs::write(dir.join("file.bin"), "linked").unwrap();
+    std::fs::hard_link(dir.join("file.bin"), dir.join("hard.bin")).unwrap();
+    std::os::unix::fs::symlink("file.bin", dir.join("soft.bin")).unwrap();
+    std::os::unix::fs::symlink("/etc", dir.join("outside")).unwrap();
+
+    let output = run_dlm(&["-t", target, "audit", "--update", "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let audit: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let hash = sha256_hex(b"linked");
+    assert_eq!(
+        audit["files"],
+        serde_json::json!([
+            { "path": "file.bin", "status": "new", "hash": hash },
+            { "path": "hard.bin", "status": "new", "hash": hash },
+        ])
+    );
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, dlm, payload, run_dlm, scratch_dir};
+
+#[test]
+fn headers_basic_auth_and_the_user_agent_reach_every_chunk() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("headers_basic_auth_and_the_user_agent_reach_every_chunk");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--header",
+        "X-Mirror: one",
+        "--user",
+        "alice:s3:cret",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let requests = server.requests();
+    assert!(requests.len() >= 3, "{requests:?}");
+    for request in &requests {
+        assert_eq!(request.header("x-mirror"), Some("one"));
+        // `alice:s3:cret`, Basic-encoded.
+        assert_eq!(
+            request.header("authorization"),
+            Some("Basic YWxpY2U6czM6Y3JldA==")
+        );
+        assert_eq!(
+            request.header("user-agent"),
+            Some(concat!("dlm/", env!("CARGO_PKG_VERSION")))
+        );
+    }
+}
+
+#[test]
+fn a_bearer_token_from_the_environment_and_a_user_agent_are_sent() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_bearer_token_from_the_environment_and_a_user_agent_are_sent");
+
+    let output = dlm()
+        .env("DM_BEARER_TOKEN", "t0ken")
+        .args(["-t", dir.to_str().unwrap(), "--user-agent", "mirror-sync/2"])
+        .args([&server.url("/file.bin"), "download-blocking"])
+        .output()
+        .unwrap();
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    for request in server.requests() {
+        assert_eq!(request.header("authorization"), Some("Bearer t0ken"));
+        assert_eq!(request.header("user-agent"), Some("mirror-sync/2"));
+    }
+}
+
+#[test]
+fn a_header_flag_wins_over_the_user_agent_and_credentials() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_header_flag_wins_over_the_user_agent_and_credentials");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--header",
+        "User-Agent: curl/8",
+        "--header",
+        "Authorization: Token abc",
+        "--bearer-token",
+        "t0ken",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    for request in server.requests() {
+        assert_eq!(request.header("authorization"), Some("Token abc"));
+        assert_eq!(request.header("user-agent"), Some("curl/8"));
+    }
+}
+
+#[test]
+fn malformed_headers_and_credentials_are_usage_errors() {
+    for (flag, value, message) in [
+        (
+            "--header",
+            "no colon",
+            "'no colon' is not a header like 'Name: value'",
+        ),
+        (
+            "--user",
+            "alice",
+            "expected credentials like 'user:password'",
+        ),
+        (
+            "--user-agent",
+            "two\nlines",
+            "it has characters a header can't hold",
+        ),
+        ("--proxy", "ftp://proxy", "'ftp://proxy' is not a proxy URL"),
+    ] {
+        let output = run_dlm(&[flag, value, "http://example.com/", "download-async"]);
+        assert_eq!(output.status.code(), Some(2), "{flag}: {output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(message),
+            "{flag}: {output:?}"
+        );
+    }
+}
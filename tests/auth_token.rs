@@ -0,0 +1,240 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::{AuthToken, Downloader, TokenSource, TransferOptions, TransferProgress};
+use std::sync::{Arc, Mutex};
+
+/// A server handing out tokens at `/auth/token` that are good for `uses`
+/// requests each, and answering 401 to anything without the latest one. It
+/// drops the first connections partway, so even a single stream outlives
+/// its token.
+fn expiring_tokens(data: Vec<u8>, uses: usize) -> TestServer {
+    // The number of the latest token and how often it's been used.
+    let token = Arc::new(Mutex::new((0usize, 0usize)));
+    TestServer::builder(data)
+        .fail_after(50_000, 3)
+        .handler(move |request, _| {
+            let mut token = token.lock().unwrap();
+            if request.path == "/auth/token" {
+                *token = (token.0 + 1, 0);
+                let body = format!(r#"{{"data":{{"token":"secret-token-{}"}}}}"#, token.0);
+                return Some(Response::new(200, body).header("Content-Type", "application/json"));
+            }
+            let expected = format!("Bearer secret-token-{}", token.0);
+            if request.header("Authorization") != Some(&expected) || token.1 >= uses {
+                return Some(Response::new(401, "token expired"));
+            }
+            token.1 += 1;
+            None
+        })
+        .start()
+}
+
+#[test]
+fn a_token_that_expires_mid_download_is_fetched_again() {
+    let data = payload(400_000);
+    // Worker mode's tokens outlast a request per worker, so a fresh one
+    // isn't used up by the others before the request refused gets to it.
+    let modes: [(&[&str], usize); 3] = [
+        (&["download-blocking"], 2),
+        (&["download-async"], 2),
+        (&["download-async", "--workers", "4"], 6),
+    ];
+    for (index, (mode, uses)) in modes.iter().enumerate() {
+        let server = expiring_tokens(data.clone(), *uses);
+        let dir = scratch_dir(&format!(
+            "a_token_that_expires_mid_download_is_fetched_again_{index}"
+        ));
+        let token_url = server.url("/auth/token");
+        let file_url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "-vv",
+            "--auth-token-url",
+            &token_url,
+            "--auth-token-body",
+            r#"{"client_id":"dlm","client_secret":"hunter2"}"#,
+            "--auth-token-jsonpath",
+            "$.data.token",
+            &file_url,
+        ];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+        let requests = server.requests();
+        let fetches: Vec<_> = requests
+            .iter()
+            .filter(|request| request.path == "/auth/token")
+            .collect();
+        assert!(fetches.len() >= 2, "{mode:?}: {requests:?}");
+        assert!(fetches.iter().all(|request| request.method == "POST"
+            && request.header("Content-Type") == Some("application/json")
+            && request.body == br#"{"client_id":"dlm","client_secret":"hunter2"}"#));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        for secret in ["secret-token", "hunter2"] {
+            assert!(!stdout.contains(secret), "{mode:?}: {stdout}");
+            assert!(!stderr.contains(secret), "{mode:?}: {stderr}");
+        }
+    }
+}
+
+#[test]
+fn a_form_body_is_sent_as_a_form() {
+    let data = payload(10_000);
+    let server = expiring_tokens(data.clone(), 2);
+    let dir = scratch_dir("a_form_body_is_sent_as_a_form");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--auth-token-url",
+        &server.url("/auth/token"),
+        "--auth-token-body",
+        "grant_type=client_credentials",
+        "--auth-token-jsonpath",
+        "data.token",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let requests = server.requests();
+    assert_eq!(
+        requests[0].header("Content-Type"),
+        Some("application/x-www-form-urlencoded")
+    );
+}
+
+#[test]
+fn a_token_url_that_refuses_fails_before_the_download() {
+    let server = TestServer::builder(payload(10_000))
+        .handler(|request, _| {
+            (request.path == "/auth/token").then(|| Response::new(403, "secret-token-0"))
+        })
+        .start();
+    let dir = scratch_dir("a_token_url_that_refuses_fails_before_the_download");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--auth-token-url",
+        &server.url("/auth/token"),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("answered 403 Forbidden"), "{stderr}");
+    assert!(!stderr.contains("secret-token"), "{stderr}");
+    assert!(
+        server
+            .requests()
+            .iter()
+            .all(|request| request.path == "/auth/token")
+    );
+}
+
+#[test]
+fn a_token_missing_from_the_answer_is_an_error() {
+    let data = payload(10_000);
+    let server = expiring_tokens(data, 2);
+    let dir = scratch_dir("a_token_missing_from_the_answer_is_an_error");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--auth-token-url",
+        &server.url("/auth/token"),
+        "--auth-token-jsonpath",
+        "$.access_token",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("answered without a string at $.access_token"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("secret-token"), "{stderr}");
+}
+
+#[test]
+fn a_bad_token_path_is_refused() {
+    let output = run_dlm(&[
+        "--auth-token-url",
+        "http://127.0.0.1:9/auth/token",
+        "--auth-token-jsonpath",
+        "$.data[first]",
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("bad token path '$.data[first]', expected one like $.data.token"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn clients_send_tokens_of_their_own() {
+    let data = payload(20_000);
+    let servers = ["first", "second"].map(|name| {
+        TestServer::builder(data.clone())
+            .handler(move |request, _| {
+                if request.path == "/auth/token" {
+                    let body = format!(r#"{{"token":"token-{name}"}}"#);
+                    return Some(Response::new(200, body));
+                }
+                let expected = format!("Bearer token-{name}");
+                (request.header("Authorization") != Some(&expected))
+                    .then(|| Response::new(401, "not our token"))
+            })
+            .start()
+    });
+    let dir = scratch_dir("clients_send_tokens_of_their_own");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    runtime.block_on(async {
+        let [first, second] = &servers;
+        let downloads = [(first, "first"), (second, "second")].map(|(server, name)| {
+            let dir = dir.join(name);
+            async move {
+                let auth_token = Arc::new(AuthToken::new(TokenSource {
+                    url: server.url("/auth/token").parse().unwrap(),
+                    body: None,
+                    path: "$.token".parse().unwrap(),
+                }));
+                auth_token.fetch(&reqwest::Client::new()).await.unwrap();
+                Downloader::new()
+                    .unwrap()
+                    .with_target_dir(&dir)
+                    .with_options(TransferOptions {
+                        auth_token: Some(auth_token),
+                        ..TransferOptions::default()
+                    })
+                    .download(
+                        server.url("/file.bin").parse().unwrap(),
+                        TransferProgress::new(Default::default()),
+                    )
+                    .await
+            }
+        });
+        let [first, second] = downloads;
+        let (first, second) = tokio::join!(first, second);
+        first.unwrap();
+        second.unwrap();
+    });
+
+    for (server, name) in servers.iter().zip(["first", "second"]) {
+        assert_eq!(
+            std::fs::read(dir.join(name).join("file.bin")).unwrap(),
+            data
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2, "{requests:?}");
+    }
+}
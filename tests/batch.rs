@@ -0,0 +1,838 @@
+mod common;
+
+use common::{Response, TestServer, dlm, payload, run_dlm, scratch_dir, sha256_hex};
+use serde_json::Value;
+use std::io::Write;
+use std::process::Stdio;
+use std::time::Duration;
+
+#[test]
+fn every_listed_url_is_tried_and_summed_up() {
+    let data = payload(80_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| (request.path == "/missing.bin").then(|| Response::new(404, "gone")))
+        .start();
+    let dir = scratch_dir("every_listed_url_is_tried_and_summed_up");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "# nightly mirrors\n\n{a}\n{missing}\n  {b}  \nnot a url\n{a}\n{there}\n",
+            a = server.url("/a.bin"),
+            b = server.url("/b.bin"),
+            missing = server.url("/missing.bin"),
+            there = server.url("/there.bin"),
+        ),
+    )
+    .unwrap();
+    let target = dir.join("files");
+    std::fs::create_dir_all(&target).unwrap();
+    std::fs::write(target.join("there.bin"), b"already here").unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--input-file",
+        list.to_str().unwrap(),
+        "--parallel-downloads",
+        "2",
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "Line 6: failed, not a URL: not a url",
+        "Line 7: skipped, the same URL as line 3",
+        "URL 4 of 4: skipped, '",
+        "URL 1 of 4: saved to '",
+        "URL 3 of 4: saved to '",
+        "URL 2 of 4: failed (exit code 1): ",
+        "Downloaded 5 URLs: 2 completed, 2 failed, 1 skipped",
+    ] {
+        assert!(stdout.contains(line), "no {line:?} in {stdout}");
+    }
+    let failed = stdout
+        .lines()
+        .find(|line| line.starts_with("URL 2 of 4: failed"))
+        .unwrap();
+    assert!(failed.contains("404"), "{failed}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("2 of 5 URLs failed"),
+        "{output:?}"
+    );
+    assert_eq!(std::fs::read(target.join("a.bin")).unwrap(), data);
+    assert_eq!(std::fs::read(target.join("b.bin")).unwrap(), data);
+    assert_eq!(
+        std::fs::read(target.join("there.bin")).unwrap(),
+        b"already here"
+    );
+    // The repeat wasn't downloaded again.
+    let requested_a = server
+        .requests()
+        .iter()
+        .filter(|request| request.path == "/a.bin" && request.method == "GET")
+        .filter(|request| request.header("Range") != Some("bytes=0-0"))
+        .count();
+    assert_eq!(requested_a, 2, "{:?}", server.requests());
+}
+
+#[test]
+fn urls_saved_to_the_same_file_are_settled_before_downloading() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("urls_saved_to_the_same_file_are_settled_before_downloading");
+    let urls = format!(
+        "{}\n{}\n",
+        server.url("/x/file.bin"),
+        server.url("/y/file.bin")
+    );
+
+    let mut child = dlm()
+        .args(["-t", dir.to_str().unwrap(), "--input-file", "-"])
+        .args(["--on-conflict", "error", "download-async"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(urls.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("URL 2: '"),
+        "{output:?}"
+    );
+    assert!(server.requests().is_empty());
+
+    let list = dir.join("urls.txt");
+    std::fs::write(&list, urls).unwrap();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--input-file",
+        list.to_str().unwrap(),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("URL 2 of 2: saving as 'file (2).bin', its name is taken by URL 1"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("2 completed, 0 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    assert_eq!(std::fs::read(dir.join("file (2).bin")).unwrap(), data);
+}
+
+/// A list with defaults at the top and settings of its own under each URL.
+fn list_with_settings(server: &TestServer, data: &[u8]) -> String {
+    format!(
+        "tags = nightly\nheader = X-Mirror: one\n\n{a}\n  output = renamed.bin\n  workers = 2\n  header = X-Token: secret\n{b}\n  checksum = sha256:{good}\n  tags = extra\n{c}\n  checksum = sha256:{bad}\n",
+        a = server.url("/a.bin"),
+        b = server.url("/b.bin"),
+        c = server.url("/c.bin"),
+        good = sha256_hex(data),
+        bad = sha256_hex(b"something else"),
+    )
+}
+
+#[test]
+fn entries_are_downloaded_with_their_own_settings() {
+    let data = payload(80_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("entries_are_downloaded_with_their_own_settings");
+    let list = dir.join("urls.txt");
+    std::fs::write(&list, list_with_settings(&server, &data)).unwrap();
+    let target = dir.join("files");
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--state-dir",
+        dir.join("state").to_str().unwrap(),
+        "--input-file",
+        list.to_str().unwrap(),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("2 completed, 1 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(std::fs::read(target.join("renamed.bin")).unwrap(), data);
+    assert_eq!(std::fs::read(target.join("b.bin")).unwrap(), data);
+    // The wrong checksum failed its entry only.
+    let failed = stdout
+        .lines()
+        .find(|line| line.starts_with("URL 3 of 3: failed"))
+        .unwrap();
+    assert!(failed.contains("exit code 4"), "{failed}");
+
+    let requests = server.requests();
+    let of = |path: &str| {
+        requests
+            .iter()
+            .filter(|request| request.path == path && request.method == "GET")
+            .collect::<Vec<_>>()
+    };
+    for request in of("/a.bin") {
+        assert_eq!(request.header("X-Token"), Some("secret"));
+        assert_eq!(request.header("X-Mirror"), Some("one"));
+    }
+    for request in of("/b.bin") {
+        assert_eq!(request.header("X-Token"), None);
+        assert_eq!(request.header("X-Mirror"), Some("one"));
+    }
+    // Two workers for a.bin, one for b.bin.
+    let ranged = |path: &str| {
+        of(path)
+            .iter()
+            .filter(|request| {
+                request
+                    .header("Range")
+                    .is_some_and(|range| range != "bytes=0-0")
+            })
+            .count()
+    };
+    assert_eq!(ranged("/a.bin"), 2, "{requests:?}");
+    assert_eq!(ranged("/b.bin"), 0, "{requests:?}");
+
+    let usage = std::fs::read_to_string(dir.join("state/usage.jsonl")).unwrap();
+    let tags: Vec<(String, Value)> = usage
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .map(|transfer| {
+            (
+                transfer["url"].as_str().unwrap().to_string(),
+                transfer["tags"].clone(),
+            )
+        })
+        .collect();
+    for (url, tags) in tags {
+        let expected = match url.ends_with("/b.bin") {
+            true => serde_json::json!(["nightly", "extra"]),
+            false => serde_json::json!(["nightly"]),
+        };
+        assert_eq!(tags, expected, "{url}");
+    }
+}
+
+#[test]
+fn a_setting_that_cant_be_used_names_its_entry_and_key() {
+    let dir = scratch_dir("a_setting_that_cant_be_used_names_its_entry_and_key");
+    for (text, message) in [
+        (
+            "https://example.com/a.bin\nhttps://example.com/b.bin\n  workers = many\n",
+            "Entry 2 (line 3), workers: 'many' isn't a number of workers",
+        ),
+        (
+            "https://example.com/a.bin\n  colour = blue\n",
+            "Entry 1 (line 2), colour: unknown key",
+        ),
+        (
+            "output = all.bin\nhttps://example.com/a.bin\n",
+            "Defaults (line 1), output: ",
+        ),
+        (
+            "https://example.com/a.bin\n  header = no colon\n",
+            "Entry 1 (line 2), header: 'no colon' is not a header",
+        ),
+    ] {
+        let list = dir.join("urls.txt");
+        std::fs::write(&list, text).unwrap();
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--input-file",
+            list.to_str().unwrap(),
+            "download-async",
+        ]);
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "no {message:?} in {stderr}");
+    }
+}
+
+#[test]
+fn a_dry_run_shows_each_entrys_settings() {
+    let data = payload(80_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_dry_run_shows_each_entrys_settings");
+    let list = dir.join("urls.txt");
+    std::fs::write(&list, list_with_settings(&server, &data)).unwrap();
+    let target = dir.join("files");
+    let args = [
+        "-t",
+        target.to_str().unwrap(),
+        "--dry-run",
+        "--input-file",
+        list.to_str().unwrap(),
+    ];
+
+    let output = run_dlm(&[&args[..], &["--json", "download-async"]].concat());
+    assert!(output.status.success(), "{output:?}");
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let entries = plan["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 3, "{plan}");
+    assert_eq!(entries[0]["settings"]["workers"], 2);
+    assert_eq!(
+        entries[0]["settings"]["headers"],
+        serde_json::json!(["X-Mirror", "X-Token"])
+    );
+    assert_eq!(entries[1]["settings"]["workers"], 1);
+    assert_eq!(
+        entries[1]["settings"]["tags"],
+        serde_json::json!(["nightly", "extra"])
+    );
+    assert!(
+        entries[0]["destination"]
+            .as_str()
+            .unwrap()
+            .ends_with("renamed.bin"),
+        "{plan}"
+    );
+    assert_eq!(entries[0]["preflight"]["size"], 80_000, "{plan}");
+    assert_eq!(entries[0]["preflight"]["resumable"], true, "{plan}");
+    assert_eq!(entries[2]["preflight"]["status"], 200, "{plan}");
+    assert_eq!(plan["totals"]["bytes"], 240_000, "{plan}");
+    // The entry's own header went with its question.
+    let heads: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|request| request.path == "/a.bin")
+        .collect();
+    assert!(!heads.is_empty());
+    for request in heads {
+        assert_eq!(request.header("X-Token"), Some("secret"));
+    }
+
+    let output = run_dlm(&[&args[..], &["download-async"]].concat());
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for text in [
+        "renamed.bin",
+        "headers X-Mirror, X-Token",
+        "tags nightly, extra",
+        "checksum sha256:",
+        "Total: 234.38 KiB in 3 entries",
+    ] {
+        assert!(stdout.contains(text), "no {text:?} in {stdout}");
+    }
+    assert!(!stdout.contains("secret"), "{stdout}");
+    assert!(!target.exists());
+}
+
+#[test]
+fn a_dry_run_lists_the_failing_entries_first_and_caches_the_rest() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data)
+        .handler(|request, _| match request.path.as_str() {
+            "/gone.bin" => Some(Response::new(404, "gone")),
+            "/private.bin" => Some(Response::new(403, "no")),
+            _ => None,
+        })
+        .start();
+    let dir = scratch_dir("a_dry_run_lists_the_failing_entries_first_and_caches_the_rest");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "{}\n{}\n{}\n",
+            server.url("/a.bin"),
+            server.url("/gone.bin"),
+            server.url("/private.bin")
+        ),
+    )
+    .unwrap();
+    let (target, cache) = (dir.join("files"), dir.join("preflight.json"));
+    let args = [
+        "-t",
+        target.to_str().unwrap(),
+        "--dry-run",
+        "--json",
+        "--input-file",
+        list.to_str().unwrap(),
+        "--preflight-cache",
+        cache.to_str().unwrap(),
+        "download-async",
+    ];
+
+    let output = run_dlm(&args);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("2 of 3 URLs would fail"),
+        "{output:?}"
+    );
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let lines: Vec<_> = plan["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, [2, 3, 1], "{plan}");
+    assert_eq!(plan["totals"]["failed"], 2, "{plan}");
+    assert_eq!(plan["totals"]["missing"], 1, "{plan}");
+    assert_eq!(plan["totals"]["forbidden"], 1, "{plan}");
+    assert_eq!(plan["totals"]["bytes"], 10_000, "{plan}");
+
+    // Only the failures are asked about again.
+    let asked = server.requests().len();
+    run_dlm(&args);
+    let again: Vec<_> = server.requests()[asked..]
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    assert!(!again.is_empty());
+    assert!(!again.iter().any(|path| path == "/a.bin"), "{again:?}");
+}
+
+#[test]
+fn the_entries_share_one_rate_limit() {
+    let data = payload(150_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("the_entries_share_one_rate_limit");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", server.url("/a.bin"), server.url("/b.bin")),
+    )
+    .unwrap();
+    let target = dir.join("files");
+
+    let started = std::time::Instant::now();
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--limit-rate",
+        "150k",
+        "--input-file",
+        list.to_str().unwrap(),
+        "--parallel-downloads",
+        "2",
+        "download-async",
+    ]);
+    let took = started.elapsed();
+    assert!(output.status.success(), "{output:?}");
+    for name in ["a.bin", "b.bin"] {
+        assert_eq!(std::fs::read(target.join(name)).unwrap(), data, "{name}");
+    }
+    // Two seconds at 150 KiB/s; a limit each would take one.
+    assert!(took >= std::time::Duration::from_millis(1_500), "{took:?}");
+
+    // Each of these is about one download.
+    for flag in [
+        &["--control-socket", "ctl.sock"][..],
+        &["--web-status", "0"],
+        &["--status-file", "status.json"],
+    ] {
+        let output = run_dlm(
+            &[
+                flag,
+                &["--input-file", list.to_str().unwrap(), "download-async"],
+            ]
+            .concat(),
+        );
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("cannot be used with"),
+            "{output:?}"
+        );
+    }
+}
+
+#[test]
+fn all_or_nothing_moves_the_entries_only_once_every_one_succeeded() {
+    let data = payload(40_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| (request.path == "/missing.bin").then(|| Response::new(404, "gone")))
+        .start();
+    let dir = scratch_dir("all_or_nothing_moves_the_entries_only_once_every_one_succeeded");
+    let target = dir.join("files");
+    let list = dir.join("urls.txt");
+    let batch = |list: &str, extra: &[&str]| {
+        let mut args = vec![
+            "-t",
+            target.to_str().unwrap(),
+            "--input-file",
+            list,
+            "--all-or-nothing",
+        ];
+        args.extend(extra);
+        args.push("download-async");
+        run_dlm(&args)
+    };
+
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", server.url("/a.bin"), server.url("/missing.bin")),
+    )
+    .unwrap();
+    let output = batch(list.to_str().unwrap(), &["--remove-on-error"]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Transaction rolled back, nothing was moved"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("1 of 2 URLs failed"), "{stderr}");
+    assert!(!target.join("a.bin").exists());
+
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", server.url("/a.bin"), server.url("/b.bin")),
+    )
+    .unwrap();
+    let output = batch(list.to_str().unwrap(), &[]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Transaction committed: moved 2 files into"),
+        "{stdout}"
+    );
+    assert_eq!(std::fs::read(target.join("a.bin")).unwrap(), data);
+    assert_eq!(std::fs::read(target.join("b.bin")).unwrap(), data);
+}
+
+#[test]
+fn a_failing_hosts_entries_are_skipped_once_its_circuit_opens() {
+    let data = payload(20_000);
+    let down = TestServer::builder(data.clone())
+        .handler(|_, _| Some(Response::new(503, "down for maintenance")))
+        .start();
+    let up = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_failing_hosts_entries_are_skipped_once_its_circuit_opens");
+    let target = dir.join("files");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            down.url("/1.bin"),
+            down.url("/2.bin"),
+            down.url("/3.bin"),
+            up.url("/up.bin"),
+            down.url("/4.bin"),
+        ),
+    )
+    .unwrap();
+    let batch = |extra: &[&str]| {
+        let mut args = vec![
+            "-t",
+            target.to_str().unwrap(),
+            "--input-file",
+            list.to_str().unwrap(),
+            "--parallel-downloads",
+            "1",
+            "--retries",
+            "0",
+        ];
+        args.extend(extra);
+        args.push("download-async");
+        run_dlm(&args)
+    };
+
+    let output = batch(&["--host-failure-threshold", "2"]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let down_url = down.url("/");
+    let down_host = down_url.trim_start_matches("http://").trim_end_matches('/');
+    for line in [
+        "URL 3 of 5: skipped: host unhealthy: ",
+        "URL 4 of 5: saved to '",
+        "URL 5 of 5: skipped: host unhealthy: ",
+        "Downloaded 5 URLs: 1 completed, 2 failed, 2 skipped",
+        &format!("  {down_host}: 2 attempted, 2 failed, 2 skipped, opened 1 time"),
+    ] {
+        assert!(stdout.contains(line), "no {line:?} in {stdout}");
+    }
+    assert_eq!(std::fs::read(target.join("up.bin")).unwrap(), data);
+
+    let output = batch(&["--no-host-circuit-breaker", "--overwrite"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Downloaded 5 URLs: 1 completed, 4 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("host unhealthy"), "{stdout}");
+
+    let output = batch(&["--host-failure-threshold", "2", "--overwrite", "--json"]);
+    assert!(!output.status.success(), "{output:?}");
+    let summary: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        (&summary["total"], &summary["completed"], &summary["failed"]),
+        (&5.into(), &1.into(), &2.into()),
+        "{summary}"
+    );
+    let skipped: Vec<_> = summary["skipped"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["url"].as_str().unwrap(),
+                entry["reason"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        skipped,
+        [
+            (down.url("/3.bin").as_str(), "host unhealthy"),
+            (down.url("/4.bin").as_str(), "host unhealthy"),
+        ]
+    );
+    assert_eq!(summary["retry_budget"]["budget"], 50, "{summary}");
+    let host = &summary["hosts"][down_host];
+    assert_eq!(
+        (
+            &host["attempts"],
+            &host["failures"],
+            &host["skipped"],
+            &host["tripped"]
+        ),
+        (&2.into(), &2.into(), &2.into(), &1.into()),
+        "{summary}"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("URL 4 of 5: saved to '"), "{stderr}");
+}
+
+/// Writes a list of `paths` on `server` to `urls.txt` in `dir`, and returns
+/// a batch over it into `dir/files`, with `extra` flags.
+fn batch_of<'a>(
+    server: &'a TestServer,
+    dir: &'a std::path::Path,
+    paths: &[&str],
+) -> impl Fn(&[&str]) -> std::process::Output + 'a {
+    let list = dir.join("urls.txt");
+    let urls: Vec<_> = paths.iter().map(|path| server.url(path)).collect();
+    std::fs::write(&list, urls.join("\n")).unwrap();
+    move |extra| {
+        let target = dir.join("files");
+        let mut args = vec![
+            "-t",
+            target.to_str().unwrap(),
+            "--input-file",
+            list.to_str().unwrap(),
+            "--parallel-downloads",
+            "1",
+        ];
+        args.extend(extra);
+        args.push("download-async");
+        run_dlm(&args)
+    }
+}
+
+#[test]
+fn entries_are_held_to_the_hard_quota() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("entries_are_held_to_the_hard_quota");
+    let batch = batch_of(&server, &dir, &["/a.bin", "/b.bin"]);
+
+    let output = batch(&["--dir-quota-hard", "1000"]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("URL 1 of 2: failed (exit code 6): "),
+        "{stdout}"
+    );
+    assert!(stdout.contains("past its hard quota"), "{stdout}");
+    assert!(!dir.join("files/a.bin").exists());
+    assert!(!dir.join("files/b.bin").exists());
+}
+
+#[test]
+fn entries_past_the_hard_quota_stop_the_rest() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("entries_past_the_hard_quota_stop_the_rest");
+    let batch = batch_of(&server, &dir, &["/a.bin", "/b.bin", "/c.bin"]);
+
+    let output = batch(&["--dir-quota-hard", "30k"]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "URL 1 of 3: saved to '",
+        "URL 2 of 3: failed (exit code 6): ",
+        "URL 3 of 3: skipped, over --dir-quota-hard: ",
+        "Downloaded 3 URLs: 1 completed, 1 failed, 1 skipped",
+        "Target directory: 0 B before, 19.53 KiB after",
+    ] {
+        assert!(stdout.contains(line), "no {line:?} in {stdout}");
+    }
+    assert_eq!(std::fs::read(dir.join("files/a.bin")).unwrap(), data);
+    assert!(!dir.join("files/b.bin").exists());
+    assert!(!dir.join("files/c.bin").exists());
+}
+
+#[test]
+fn entries_not_newer_than_asked_are_skipped() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone())
+        .header("Last-Modified", "Wed, 01 May 2024 12:00:00 GMT")
+        .start();
+    let dir = scratch_dir("entries_not_newer_than_asked_are_skipped");
+    let batch = batch_of(&server, &dir, &["/a.bin", "/b.bin"]);
+
+    let output = batch(&["--newer-than", "2099-01-01T00:00:00Z"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "URL 1 of 2: skipped, not newer than ",
+        "URL 2 of 2: skipped, not newer than ",
+    ] {
+        assert!(stdout.contains(line), "no {line:?} in {stdout}");
+    }
+    assert!(
+        stdout.contains("Downloaded 2 URLs: 0 completed, 0 failed, 2 skipped (2 not newer)"),
+        "{stdout}"
+    );
+    assert!(!dir.join("files/a.bin").exists());
+    assert!(
+        server
+            .requests()
+            .iter()
+            .all(|request| request.header("If-Modified-Since").is_some()),
+        "{:?}",
+        server.requests()
+    );
+
+    let output = batch(&[
+        "--newer-than",
+        "2099-01-01T00:00:00Z",
+        "--dry-run",
+        "--json",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    for entry in plan["entries"].as_array().unwrap() {
+        let reason = entry["not_newer"].as_str().unwrap_or_default();
+        assert!(reason.starts_with("not newer than "), "{plan}");
+        assert!(entry.get("preflight").is_none(), "{plan}");
+    }
+
+    let output = batch(&["--newer-than", "2099-01-01T00:00:00Z", "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let summary: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["total"], 2, "{summary}");
+    let skipped = summary["skipped"].as_array().unwrap();
+    let mut urls: Vec<_> = skipped.iter().map(|entry| &entry["url"]).collect();
+    urls.sort_by_key(|url| url.as_str());
+    assert_eq!(
+        urls,
+        [&server.url("/a.bin"), &server.url("/b.bin")],
+        "{summary}"
+    );
+    for entry in skipped {
+        let reason = entry["reason"].as_str().unwrap();
+        assert!(reason.starts_with("not newer than "), "{summary}");
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("URL 1 of 2: skipped, not newer than "),
+        "{stderr}"
+    );
+
+    let output = batch(&["--newer-than", "2024-01-01T00:00:00Z"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("files/a.bin")).unwrap(), data);
+    assert_eq!(std::fs::read(dir.join("files/b.bin")).unwrap(), data);
+}
+
+#[test]
+fn entries_fill_and_reuse_the_cache() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            Some(match request.header("If-None-Match") {
+                Some("\"v1\"") => Response::new(304, Vec::new()),
+                _ => Response::new(200, data.clone()).header("ETag", "\"v1\""),
+            })
+        })
+        .start();
+    let dir = scratch_dir("entries_fill_and_reuse_the_cache");
+    let cache = dir.join("cache");
+    let batch = batch_of(&server, &dir, &["/a.bin", "/b.bin"]);
+    let flags = ["--cache-dir", cache.to_str().unwrap(), "--overwrite"];
+
+    let output = batch(&flags);
+    assert!(output.status.success(), "{output:?}");
+    let cached = |extension: &str| {
+        std::fs::read_dir(&cache)
+            .unwrap()
+            .filter(|file| {
+                let path = file.as_ref().unwrap().path();
+                path.extension().is_some_and(|found| found == extension)
+            })
+            .count()
+    };
+    assert_eq!(cached("data"), 2);
+
+    let output = batch(&flags);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout
+            .matches("Not modified, reused the cached copy")
+            .count(),
+        2,
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Downloaded 2 URLs: 2 completed, 0 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(
+        std::fs::read(dir.join("files/a.bin")).unwrap(),
+        payload(20_000)
+    );
+}
+
+#[test]
+fn an_interrupted_batch_exits_as_an_interrupted_download_does() {
+    let server = TestServer::builder(payload(200_000))
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("an_interrupted_batch_exits_as_an_interrupted_download_does");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", server.url("/a.bin"), server.url("/b.bin")),
+    )
+    .unwrap();
+
+    let child = dlm()
+        .args(["-t", dir.join("files").to_str().unwrap()])
+        .args(["--input-file", list.to_str().unwrap()])
+        .args(["--parallel-downloads", "1", "download-async"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(700));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Downloaded 2 URLs: 0 completed, 0 failed, 0 skipped, 2 interrupted"),
+        "{stdout}"
+    );
+}
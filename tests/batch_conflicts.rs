@@ -0,0 +1,72 @@
+use download_manager::__private::conflicts::{self, OnConflict, Planned};
+use std::path::PathBuf;
+
+fn destinations(names: &[&str]) -> Vec<PathBuf> {
+    names
+        .iter()
+        .map(|name| PathBuf::from("dl").join(name))
+        .collect()
+}
+
+#[test]
+fn later_entries_with_a_taken_name_are_renamed() {
+    let plan = conflicts::plan(
+        &destinations(&[
+            "download",
+            "app.tar.gz",
+            "download",
+            "App.TAR.gz",
+            "download (2)",
+            "download",
+        ]),
+        OnConflict::Rename,
+    );
+    assert_eq!(
+        plan,
+        [
+            Planned::Keep,
+            Planned::Keep,
+            Planned::Renamed {
+                path: PathBuf::from("dl").join("download (3)"),
+                conflicts_with: 0,
+            },
+            Planned::Renamed {
+                path: PathBuf::from("dl").join("App (2).TAR.gz"),
+                conflicts_with: 1,
+            },
+            Planned::Keep,
+            Planned::Renamed {
+                path: PathBuf::from("dl").join("download (4)"),
+                conflicts_with: 0,
+            },
+        ]
+    );
+}
+
+#[test]
+fn skip_and_error_mark_the_conflicting_entries() {
+    let names = destinations(&["a.iso", "b.iso", "A.iso", ".hidden", ".hidden"]);
+    for on_conflict in [OnConflict::Skip, OnConflict::Error] {
+        assert_eq!(
+            conflicts::plan(&names, on_conflict),
+            [
+                Planned::Keep,
+                Planned::Keep,
+                Planned::Conflict { conflicts_with: 0 },
+                Planned::Keep,
+                Planned::Conflict { conflicts_with: 3 },
+            ]
+        );
+    }
+    assert_eq!(
+        conflicts::plan(&destinations(&[".hidden", ".hidden"]), OnConflict::Rename)[1],
+        Planned::Renamed {
+            path: PathBuf::from("dl").join(".hidden (2)"),
+            conflicts_with: 0,
+        }
+    );
+    assert_eq!(
+        "keep".parse::<OnConflict>().unwrap_err(),
+        "unknown action 'keep', expected rename, skip or error"
+    );
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use common::{TestServer, payload, scratch_dir, sha256_hex};
+use download_manager::__private::error;
+use download_manager::{
+    BlockingDownloader, DownloadOutcome, PostProcessor, TransferOptions, TransferState,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+#[test]
+fn blocking_downloader_reports_progress_on_the_calling_thread() {
+    let data = payload(300_001);
+    let server = TestServer::builder(data.clone())
+        .drip(30_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("blocking_downloader_reports_progress");
+    let caller = std::thread::current().id();
+
+    let mut snapshots = Vec::new();
+    let result = BlockingDownloader::new()
+        .unwrap()
+        .with_target_dir(&dir)
+        .with_progress_interval(Duration::from_millis(10))
+        .download_with_progress(server.url("/file.bin").parse().unwrap(), |snapshot| {
+            assert_eq!(std::thread::current().id(), caller);
+            snapshots.push(snapshot.clone());
+        })
+        .unwrap();
+
+    assert_eq!(result.path, dir.join("file.bin"));
+    assert_eq!(result.size, 300_001);
+    assert_eq!(hex::encode(result.sha256), sha256_hex(&data));
+    assert_eq!(std::fs::read(&result.path).unwrap(), data);
+    assert!(snapshots.len() > 2, "{snapshots:?}");
+    let last = snapshots.last().unwrap();
+    assert_eq!(last.downloaded, 300_001);
+    assert_eq!(last.state, TransferState::Completed);
+}
+
+#[test]
+fn blocking_downloader_takes_transfer_options() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("blocking_downloader_takes_transfer_options");
+    std::fs::write(dir.join("renamed.bin"), &data[..50_000]).unwrap();
+    let options = TransferOptions {
+        resume: true,
+        output: Some(PathBuf::from("renamed.bin")),
+        ..TransferOptions::default()
+    };
+
+    let result = BlockingDownloader::new()
+        .unwrap()
+        .with_target_dir(&dir)
+        .with_options(options)
+        .download(server.url("/file.bin").parse().unwrap())
+        .unwrap();
+
+    assert_eq!(result.path, dir.join("renamed.bin"));
+    assert_eq!(std::fs::read(&result.path).unwrap(), data);
+    assert_eq!(
+        server.requests().last().unwrap().header("Range"),
+        Some("bytes=50000-")
+    );
+
+    // Already set, so it stops at the first read.
+    let error = BlockingDownloader::new()
+        .unwrap()
+        .with_target_dir(&dir)
+        .with_interrupt(Arc::new(AtomicBool::new(true)))
+        .download(server.url("/other.bin").parse().unwrap())
+        .unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref(),
+            Some(error::DownloadError::Interrupted)
+        ),
+        "{error:#}"
+    );
+}
+
+/// Moves the download into `quarantine`, or refuses it when that's unset.
+struct Quarantine(Option<PathBuf>);
+
+impl PostProcessor for Quarantine {
+    fn name(&self) -> &str {
+        "quarantine"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let Some(dir) = &self.0 else {
+            anyhow::bail!("no quarantine for {}", outcome.url);
+        };
+        assert!(outcome.sha256.is_some(), "hashed first");
+        let moved = dir.join(outcome.path.file_name().unwrap());
+        std::fs::rename(&outcome.path, &moved)?;
+        outcome.path = moved;
+        Ok(())
+    }
+}
+
+#[test]
+fn blocking_downloader_runs_added_steps_after_hashing() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("blocking_downloader_post_processing");
+    let quarantine = dir.join("quarantine");
+    std::fs::create_dir(&quarantine).unwrap();
+
+    let result = BlockingDownloader::new()
+        .unwrap()
+        .with_target_dir(&dir)
+        .with_post_processor(Quarantine(Some(quarantine.clone())))
+        .download(server.url("/file.bin").parse().unwrap())
+        .unwrap();
+    assert_eq!(result.path, quarantine.join("file.bin"));
+    assert_eq!(hex::encode(result.sha256), sha256_hex(&data));
+    let steps: Vec<_> = result
+        .post_processing
+        .iter()
+        .map(|timing| timing.step.as_str())
+        .collect();
+    assert_eq!(steps, ["hash", "quarantine"]);
+
+    let error = BlockingDownloader::new()
+        .unwrap()
+        .with_target_dir(&dir)
+        .with_post_processor(Quarantine(None))
+        .download(server.url("/other.bin").parse().unwrap())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Downloaded '{}', but post-processing step 'quarantine' failed",
+            dir.join("other.bin").display()
+        )
+    );
+    assert!(format!("{error:#}").contains("no quarantine for"));
+    assert_eq!(error::exit_code(&error), 9);
+    assert!(dir.join("other.bin").is_file());
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{
+    Response, TestServer, assert_downloaded, payload, printed_sha256, run_dlm, scratch_dir,
+    sha256_hex,
+};
+use std::fs;
+use std::path::Path;
+
+const ETAG: &str = "\"v1\"";
+
+/// Serves `data` at every path with an ETag, answering 304 when it matches.
+fn etag_server(data: Vec<u8>) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            Some(match request.header("If-None-Match") {
+                Some(ETAG) => Response::new(304, Vec::new()),
+                _ => Response::new(200, data.clone()).header("ETag", ETAG),
+            })
+        })
+        .start()
+}
+
+fn cached_files(cache: &Path, extension: &str) -> usize {
+    fs::read_dir(cache)
+        .unwrap()
+        .filter(|file| {
+            file.as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|found| found == extension)
+        })
+        .count()
+}
+
+#[test]
+fn unchanged_download_comes_from_the_cache() {
+    let data = payload(80_000);
+    let server = etag_server(data.clone());
+    let dir = scratch_dir("unchanged_download_comes_from_the_cache");
+    let cache = dir.join("cache");
+    let target = dir.join("files");
+    let run = || {
+        run_dlm(&[
+            "-t",
+            target.to_str().unwrap(),
+            "--cache-dir",
+            cache.to_str().unwrap(),
+            "--overwrite",
+            &server.url("/index.tar"),
+            "download-async",
+        ])
+    };
+
+    assert_downloaded(&run(), &target.join("index.tar"), &data);
+    let output = run();
+    assert_downloaded(&output, &target.join("index.tar"), &data);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Not modified"));
+    assert_eq!(printed_sha256(&output).unwrap(), sha256_hex(&data));
+    let requests = server.requests();
+    assert_eq!(requests.last().unwrap().header("If-None-Match"), Some(ETAG));
+
+    // A damaged cache entry is fetched again instead of reused.
+    for file in fs::read_dir(&cache).unwrap() {
+        let path = file.unwrap().path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "data")
+        {
+            fs::remove_file(&path).unwrap();
+            fs::write(&path, b"garbage").unwrap();
+        }
+    }
+    let seen = server.requests().len();
+    let output = run();
+    assert_downloaded(&output, &target.join("index.tar"), &data);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Not modified"));
+    assert!(
+        server.requests()[seen..]
+            .iter()
+            .all(|request| request.header("If-None-Match").is_none())
+    );
+
+    let output = run_dlm(&["--cache-dir", cache.to_str().unwrap(), "cache", "purge"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 1 cached downloads"));
+    assert_eq!(cached_files(&cache, "data"), 0);
+}
+
+#[test]
+fn least_recently_used_downloads_are_evicted() {
+    let server = etag_server(payload(100_000));
+    let dir = scratch_dir("least_recently_used_downloads_are_evicted");
+    let cache = dir.join("cache");
+
+    for name in ["/a.bin", "/b.bin"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--cache-dir",
+            cache.to_str().unwrap(),
+            "--cache-size",
+            "150k",
+            &server.url(name),
+            "download-blocking",
+        ]);
+        assert!(output.status.success(), "{output:?}");
+    }
+
+    assert_eq!(cached_files(&cache, "data"), 1);
+    assert_eq!(cached_files(&cache, "json"), 1);
+    let entry = fs::read_dir(&cache)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .find(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .unwrap();
+    assert!(fs::read_to_string(entry).unwrap().contains("/b.bin"));
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use common::{TestServer, run_dlm, scratch_dir, sha256_hex};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::process::Output;
+
+/// `hello\n`, and its published sums.
+const CONTENTS: &[u8] = b"hello\n";
+const CONTENTS_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+const CONTENTS_MD5: &str = "b1946ac92492d2347c6235b4d2611184";
+const CONTENTS_SHA1: &str = "f572d396fae9206628714fb2ce00f72e94f2258f";
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Downloads `served` as `hello.txt.gz` with `flags`.
+fn download(served: &[u8], name: &str, flags: &[&str]) -> (Output, Vec<u8>) {
+    let server = TestServer::builder(served.to_vec()).start();
+    let dir = scratch_dir(name);
+    let url = server.url("/hello.txt.gz");
+    let mut args = vec!["-t", dir.to_str().unwrap()];
+    args.extend_from_slice(flags);
+    args.extend([url.as_str(), "download-async"]);
+    let output = run_dlm(&args);
+    let written = std::fs::read(dir.join("hello.txt.gz")).unwrap_or_default();
+    (output, written)
+}
+
+#[test]
+fn a_gzip_is_verified_by_the_sum_of_its_contents() {
+    let compressed = gzip(CONTENTS);
+    for (name, checksum) in [
+        ("checksum_decompressed_sha256", CONTENTS_SHA256.to_string()),
+        ("checksum_decompressed_md5", format!("md5:{CONTENTS_MD5}")),
+    ] {
+        let (output, written) = download(
+            &compressed,
+            name,
+            &["--checksum", &checksum, "--checksum-of", "decompressed"],
+        );
+        assert!(output.status.success(), "{output:?}");
+        // It's kept as downloaded.
+        assert_eq!(written, compressed);
+        assert!(
+            String::from_utf8_lossy(&output.stdout)
+                .contains(" of the decompressed contents matches"),
+            "{output:?}"
+        );
+    }
+
+    // The sum of the file itself is checked as is.
+    let (output, _) = download(
+        &compressed,
+        "checksum_of_file",
+        &["--checksum", &sha256_hex(&compressed)],
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Checksum PASS: the sha256 matches"),
+        "{output:?}"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Post-processing: hash "),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn a_mismatch_or_a_corrupt_file_exits_with_code_4() {
+    let compressed = gzip(CONTENTS);
+    let (output, written) = download(
+        &compressed,
+        "checksum_mismatch",
+        &["--checksum", CONTENTS_SHA256],
+    );
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("but --checksum expects {CONTENTS_SHA256}")),
+        "{output:?}"
+    );
+    assert!(
+        stderr.contains("hello.txt.gz.corrupt', but post-processing step 'checksum' failed"),
+        "{output:?}"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Checksum FAIL: the sha256 doesn't match"),
+        "{output:?}"
+    );
+    // Set aside to look at, where nothing takes it for the file.
+    assert!(written.is_empty());
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("checksum_mismatch");
+    assert_eq!(
+        std::fs::read(dir.join("hello.txt.gz.corrupt")).unwrap(),
+        compressed
+    );
+
+    let truncated = &compressed[..compressed.len() - 6];
+    for (name, served, reason) in [
+        (
+            "checksum_truncated",
+            truncated,
+            "is corrupt, it doesn't decompress",
+        ),
+        ("checksum_not_gzip", CONTENTS, "it's neither gzip nor zstd"),
+    ] {
+        let (output, _) = download(
+            served,
+            name,
+            &[
+                "--checksum",
+                CONTENTS_SHA256,
+                "--checksum-of",
+                "decompressed",
+            ],
+        );
+        assert_eq!(output.status.code(), Some(4), "{name}: {output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(reason),
+            "{name}: {output:?}"
+        );
+    }
+}
+
+#[test]
+fn a_digest_is_looked_up_in_a_sums_file_by_name() {
+    let sums = scratch_dir("checksum_sums_file").join("SHA1SUMS");
+    std::fs::write(
+        &sums,
+        format!(
+            "{}  ./dist/other.txt\n{CONTENTS_SHA1}  ./dist/hello.txt.gz\n",
+            "0".repeat(40)
+        ),
+    )
+    .unwrap();
+    let (output, written) = download(
+        CONTENTS,
+        "checksum_listed",
+        &["--sha1", sums.to_str().unwrap()],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(written, CONTENTS);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Checksum PASS: the sha1 matches"),
+        "{output:?}"
+    );
+
+    // A digest given outright, that doesn't match, kept as asked.
+    let (output, written) = download(
+        b"tampered\n",
+        "checksum_keep_on_mismatch",
+        &["--md5", CONTENTS_MD5, "--keep-on-mismatch"],
+    );
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains(&format!("but --md5 expects {CONTENTS_MD5}")),
+        "{output:?}"
+    );
+    assert_eq!(written, b"tampered\n");
+
+    let output = run_dlm(&[
+        "--sha256",
+        "no-such-SHA256SUMS",
+        "https://example.com/a",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(
+            "'no-such-SHA256SUMS' is neither a sha256 digest (64 hex digits) nor a sums file"
+        ),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn checksums_are_checked_for_their_length() {
+    let output = run_dlm(&[
+        "--checksum",
+        "sha512:abcd",
+        "https://example.com/a",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("'abcd' is not a sha512 digest, expected 128 hex digits"),
+        "{output:?}"
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn a_zstd_is_verified_by_the_sum_of_its_contents() {
+    let compressed = zstd::encode_all(CONTENTS, 3).unwrap();
+    let (output, written) = download(
+        &compressed,
+        "checksum_decompressed_zstd",
+        &[
+            "--checksum",
+            CONTENTS_SHA256,
+            "--checksum-of",
+            "decompressed",
+        ],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(written, compressed);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const TOTAL: usize = 200_000;
+
+/// A server on which the first `slow` requests for the last chunk trickle
+/// in at 5 KB/s, and everything else comes at once.
+fn slow_last_chunk(data: &[u8], slow: usize) -> TestServer {
+    let data = data.to_vec();
+    let slowed = Arc::new(AtomicUsize::new(0));
+    TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            let range = request.header("Range")?.strip_prefix("bytes=")?;
+            let (start, end) = range.split_once('-')?;
+            let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+            if request.method != "GET"
+                || end != TOTAL - 1
+                || slowed.fetch_add(1, Ordering::SeqCst) >= slow
+            {
+                return None;
+            }
+            Some(
+                Response::new(206, data[start..=end].to_vec())
+                    .header("Content-Range", format!("bytes {start}-{end}/{TOTAL}"))
+                    .drip(1000, Duration::from_millis(200)),
+            )
+        })
+        .start()
+}
+
+/// Where each request for the last chunk started.
+fn last_chunk_starts(server: &TestServer) -> Vec<usize> {
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.method == "GET")
+        .filter_map(|request| request.header("Range"))
+        .filter_map(|range| range.strip_prefix("bytes=")?.strip_suffix("-199999"))
+        .map(|start| start.parse().unwrap())
+        .collect()
+}
+
+/// Downloads from `server` with four workers and `--chunk-min-speed`,
+/// returning the output and the reasons of the chunk log's retries.
+fn run(server: &TestServer, name: &str, data: &[u8]) -> (String, Vec<String>) {
+    let dir = scratch_dir(name);
+    let log = dir.join("chunks.jsonl");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--chunk-min-speed",
+        "50k",
+        "--chunk-min-speed-grace",
+        "1s",
+        "--chunk-log",
+        log.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), data);
+    let retries = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["event"] == "retry")
+        .map(|event| event["error"].as_str().unwrap().to_string())
+        .collect();
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        retries,
+    )
+}
+
+#[test]
+fn a_slow_chunk_is_reassigned_keeping_what_it_has() {
+    let data = payload(TOTAL);
+    let server = slow_last_chunk(&data, 1);
+    let (stdout, retries) = run(&server, "chunk_min_speed_reassigned", &data);
+
+    assert_eq!(retries.len(), 1, "{retries:?}");
+    assert!(
+        retries[0].ends_with("while other chunks are done"),
+        "{retries:?}"
+    );
+    assert!(
+        stdout.contains("Chunks re-assigned for --chunk-min-speed: 1, bringing in "),
+        "{stdout}"
+    );
+    let starts = last_chunk_starts(&server);
+    assert_eq!(starts.len(), 2, "{starts:?}");
+    // The new request carries on after the bytes the slow one delivered.
+    assert!(starts[1] > starts[0], "{starts:?}");
+}
+
+#[test]
+fn a_chunk_is_reassigned_at_most_three_times() {
+    let data = payload(TOTAL);
+    let server = slow_last_chunk(&data, usize::MAX);
+    let (stdout, retries) = run(&server, "chunk_min_speed_capped", &data);
+
+    assert_eq!(retries.len(), 3, "{retries:?}");
+    assert!(
+        stdout.contains("Chunks re-assigned for --chunk-min-speed: 3, bringing in "),
+        "{stdout}"
+    );
+    assert_eq!(last_chunk_starts(&server).len(), 4);
+}
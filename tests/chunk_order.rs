@@ -0,0 +1,66 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::time::Duration;
+
+/// Downloads 200 KB with two workers in `order`, returning the segments'
+/// ranges in the order they were asked for.
+fn segment_requests(order: &str) -> Vec<String> {
+    let data = payload(200_000);
+    // Slow enough that neither worker is done before the other starts.
+    let server = TestServer::builder(data.clone())
+        .drip(5_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir(&format!("chunk_order_{order}"));
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-o",
+        "--no-warm-up",
+        "--chunk-order",
+        order,
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range"))
+        .filter(|range| *range != "bytes=0-0")
+        .map(str::to_string)
+        .collect()
+}
+
+/// The range of the `index`th of the eight 25 KB segments.
+fn segment(index: usize) -> String {
+    format!("bytes={}-{}", index * 25_000, (index + 1) * 25_000 - 1)
+}
+
+#[test]
+fn workers_take_segments_in_the_chunk_order() {
+    for (order, first) in [("sequential", [0, 1]), ("spread", [4, 2])] {
+        let mut requests = segment_requests(order);
+        assert_eq!(requests.len(), 8, "{requests:?}");
+        // The two workers start together, so either can ask first.
+        requests[..2].sort();
+        assert_eq!(requests[..2], first.map(segment), "{order}: {requests:?}");
+    }
+}
+
+#[test]
+fn unknown_chunk_orders_are_refused() {
+    let output = run_dlm(&[
+        "--chunk-order",
+        "random",
+        "https://example.com/a",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("expected sequential or spread"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,211 @@
+use download_manager::__private::chunks::{
+    AUTO_MAX_WORKERS, ChunkOrder, ResumeOrder, SplitReason, plan_chunks, resume_schedule, schedule,
+    split,
+};
+use download_manager::Workers;
+use proptest::prelude::*;
+use std::num::NonZeroU16;
+
+fn workers(count: u16) -> NonZeroU16 {
+    NonZeroU16::new(count).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn chunks_cover_the_download_in_order(
+        total in 0..1u64 << 50,
+        count in 1..=u16::MAX,
+        min_size in 0..1u64 << 30,
+    ) {
+        let chunks = plan_chunks(total, workers(count), min_size);
+        prop_assert!(chunks.len() <= count as usize);
+        prop_assert_eq!(chunks.is_empty(), total == 0);
+        let mut next = 0;
+        for (index, chunk) in chunks.iter().enumerate() {
+            prop_assert_eq!(chunk.start, next, "gap or overlap before chunk {}", index);
+            prop_assert!(chunk.start < chunk.end, "chunk {} is empty", index);
+            if index + 1 < chunks.len() {
+                prop_assert!(chunk.end - chunk.start >= min_size);
+                prop_assert_eq!((chunk.end - chunk.start) % min_size.max(1), 0);
+            }
+            next = chunk.end;
+        }
+        prop_assert_eq!(next, total);
+    }
+
+    #[test]
+    fn every_segment_is_scheduled_once(
+        count in 0..2048usize,
+        spread in any::<bool>(),
+    ) {
+        let order = match spread {
+            true => ChunkOrder::Spread,
+            false => ChunkOrder::Sequential,
+        };
+        let mut scheduled = schedule(count, order);
+        scheduled.sort_unstable();
+        prop_assert_eq!(scheduled, (0..count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn every_resumed_segment_is_scheduled_once(
+        segments in prop::collection::vec((0..1u64 << 40, 1..1u64 << 40), 0..256),
+        order in prop_oneof![
+            Just(ResumeOrder::Sequential),
+            Just(ResumeOrder::MostComplete),
+            Just(ResumeOrder::LargestRemaining),
+        ],
+    ) {
+        let mut scheduled = resume_schedule(&segments, order);
+        scheduled.sort_unstable();
+        prop_assert_eq!(scheduled, (0..segments.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn small_downloads_have_a_chunk_per_byte_at_most(
+        total in 0..64u64,
+        count in 1..=64u16,
+    ) {
+        let chunks = plan_chunks(total, workers(count), 1);
+        prop_assert_eq!(chunks.len() as u64, total.min(count as u64));
+    }
+
+    #[test]
+    fn a_split_gives_every_worker_a_part(
+        size in 0..1u64 << 40,
+        count in 0..=u8::MAX,
+        auto in any::<bool>(),
+        min_split_size in 0..1u64 << 30,
+    ) {
+        let asked = match auto {
+            true => Workers::Auto,
+            false => Workers::Count(count),
+        };
+        let split = split(asked, size, min_split_size);
+        prop_assert!(split.workers >= 1);
+        prop_assert!(split.workers <= asked.most().max(1));
+        prop_assert!(u64::from(split.workers) <= size.max(1));
+        if split.workers > 1 && min_split_size > 0 {
+            prop_assert!(size / u64::from(split.workers) >= min_split_size);
+        }
+        let chunks = plan_chunks(size, workers(split.workers.into()), 1);
+        prop_assert_eq!(chunks.len() as u64, size.min(split.workers.into()));
+    }
+}
+
+#[test]
+fn the_last_chunk_takes_the_remainder() {
+    assert_eq!(plan_chunks(10, workers(3), 1), [0..3, 3..6, 6..10]);
+    assert_eq!(plan_chunks(10, workers(3), 4), [0..4, 4..8, 8..10]);
+    assert_eq!(plan_chunks(10, workers(3), 16), vec![0..10]);
+    assert_eq!(plan_chunks(2, workers(4), 1), [0..1, 1..2]);
+}
+
+#[test]
+fn auto_goes_by_the_size_of_the_file() {
+    const MIB: u64 = 1024 * 1024;
+    let workers = |size| split(Workers::Auto, size, 0).workers;
+    assert_eq!(workers(0), 1);
+    assert_eq!(workers(10 * MIB - 1), 1);
+    assert_eq!(workers(10 * MIB), 2);
+    assert_eq!(workers(128 * MIB), 2);
+    assert_eq!(workers(129 * MIB), 3);
+    assert_eq!(workers(640 * MIB), 10);
+    assert_eq!(workers(100 * 1024 * MIB), AUTO_MAX_WORKERS);
+    assert_eq!(split(Workers::Auto, MIB, 0).why, SplitReason::Auto);
+}
+
+#[test]
+fn small_files_are_split_between_fewer_workers() {
+    let asked = split(Workers::Count(8), 1 << 20, 16 * 1024);
+    assert_eq!((asked.workers, asked.why), (8, SplitReason::Asked));
+    // A byte each at most, whatever the parts' size.
+    let tiny = split(Workers::Count(8), 3, 0);
+    assert_eq!((tiny.workers, tiny.why), (3, SplitReason::TooSmall));
+    assert_eq!(
+        tiny.to_string(),
+        "The file is only 3 bytes, splitting it between 3 workers instead of 8"
+    );
+    let empty = split(Workers::Count(8), 0, 0);
+    assert_eq!((empty.workers, empty.why), (1, SplitReason::TooSmall));
+    // No part shorter than --min-split-size.
+    let short = split(Workers::Count(8), 40_000, 16_000);
+    assert_eq!(
+        (short.workers, short.why),
+        (2, SplitReason::MinPartSize(16_000))
+    );
+    let kilobyte = split(Workers::Count(8), 1000, 16 * 1024);
+    assert_eq!(kilobyte.workers, 1);
+    assert!(
+        kilobyte
+            .to_string()
+            .ends_with("downloading it in one stream instead of 8"),
+        "{kilobyte}"
+    );
+    assert_eq!(split(Workers::Count(0), 1000, 0).workers, 1);
+}
+
+#[test]
+fn worker_counts_parse() {
+    assert_eq!("auto".parse(), Ok(Workers::Auto));
+    assert_eq!("8".parse(), Ok(Workers::Count(8)));
+    assert_eq!(
+        "256".parse::<Workers>(),
+        Err("bad worker count '256', expected 1 to 255 or auto".to_string())
+    );
+    assert_eq!(
+        "0".parse::<Workers>(),
+        Err("bad worker count '0', expected 1 to 255 or auto".to_string())
+    );
+    assert_eq!(Workers::Auto.to_string(), "auto");
+}
+
+#[test]
+fn sequential_goes_from_the_start_and_spread_halves_the_gaps() {
+    assert_eq!(schedule(5, ChunkOrder::Sequential), [0, 1, 2, 3, 4]);
+    assert_eq!(schedule(8, ChunkOrder::Spread), [4, 2, 6, 1, 3, 5, 7, 0]);
+    assert_eq!(schedule(3, ChunkOrder::Spread), [1, 0, 2]);
+    assert_eq!(schedule(1, ChunkOrder::Spread), [0]);
+    assert!(schedule(0, ChunkOrder::Spread).is_empty());
+}
+
+#[test]
+fn chunk_orders_parse() {
+    assert_eq!("spread".parse(), Ok(ChunkOrder::Spread));
+    assert_eq!("sequential".parse(), Ok(ChunkOrder::Sequential));
+    assert_eq!(
+        "random".parse::<ChunkOrder>(),
+        Err("unknown chunk order 'random', expected sequential or spread".to_string())
+    );
+}
+
+#[test]
+fn resumed_segments_are_ordered_by_what_they_have_done_or_left() {
+    // Done and left of each: 50%, 90%, untouched, 90% of a longer one, 20%.
+    let segments = [(100, 100), (90, 10), (0, 400), (900, 100), (50, 200)];
+    assert_eq!(
+        resume_schedule(&segments, ResumeOrder::Sequential),
+        [0, 1, 2, 3, 4]
+    );
+    // Ties stay in the order of the file.
+    assert_eq!(
+        resume_schedule(&segments, ResumeOrder::MostComplete),
+        [1, 3, 0, 4, 2]
+    );
+    assert_eq!(
+        resume_schedule(&segments, ResumeOrder::LargestRemaining),
+        [2, 4, 0, 3, 1]
+    );
+    assert!(resume_schedule(&[], ResumeOrder::MostComplete).is_empty());
+}
+
+#[test]
+fn resume_orders_parse() {
+    assert_eq!("most-complete".parse(), Ok(ResumeOrder::MostComplete));
+    assert_eq!("sequential".parse(), Ok(ResumeOrder::Sequential));
+    assert_eq!(
+        "largest-remaining".parse(),
+        Ok(ResumeOrder::LargestRemaining)
+    );
+    assert!("smallest".parse::<ResumeOrder>().is_err());
+}
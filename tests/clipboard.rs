@@ -0,0 +1,47 @@
+mod common;
+
+use download_manager::__private::utils::parse_url_list;
+
+#[test]
+fn clipboard_text_is_split_into_urls() {
+    let urls = parse_url_list(
+        "https://example.com/a.iso\n\thttp://example.com/b.iso  ftp://example.com/c.iso\r\n",
+    )
+    .unwrap();
+    let urls: Vec<_> = urls.iter().map(|url| url.as_str()).collect();
+    assert_eq!(
+        urls,
+        [
+            "https://example.com/a.iso",
+            "http://example.com/b.iso",
+            "ftp://example.com/c.iso"
+        ]
+    );
+
+    assert_eq!(
+        parse_url_list("https://example.com/a.iso and more").unwrap_err(),
+        "'and' is not a URL"
+    );
+    assert_eq!(
+        parse_url_list("file:///etc/passwd").unwrap_err(),
+        "'file:///etc/passwd' is not a URL"
+    );
+    assert_eq!(
+        parse_url_list(" \n ").unwrap_err(),
+        "there are no URLs in it"
+    );
+}
+
+#[cfg(feature = "clipboard")]
+#[test]
+fn from_clipboard_takes_the_place_of_a_url() {
+    let output = common::run_dlm(&[
+        "--from-clipboard",
+        "https://example.com/a.iso",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = common::run_dlm(&["--yes", "download-async"]);
+    assert_eq!(output.status.code(), Some(2));
+}
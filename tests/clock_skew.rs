@@ -0,0 +1,143 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::clock::Skew;
+use download_manager::__private::newer::{self, NewerThan};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+const HOUR: Duration = Duration::from_secs(3600);
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn last_modified(time: SystemTime) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let date = HeaderValue::from_str(&httpdate::fmt_http_date(time)).unwrap();
+    headers.insert(header::LAST_MODIFIED, date);
+    headers
+}
+
+/// Serves a file changed `age` before the server's clock, which is `ahead`
+/// (or behind, when negative) of this machine's.
+fn skewed_server(data: Vec<u8>, ahead: i64, age: Duration) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |_, _| {
+            let server_now = match ahead >= 0 {
+                true => SystemTime::now() + Duration::from_secs(ahead as u64),
+                false => SystemTime::now() - Duration::from_secs(ahead.unsigned_abs()),
+            };
+            Some(
+                Response::new(200, data.clone())
+                    .header("Date", httpdate::fmt_http_date(server_now))
+                    .header("Last-Modified", httpdate::fmt_http_date(server_now - age)),
+            )
+        })
+        .start()
+}
+
+#[test]
+fn skew_is_measured_both_ways() {
+    let local = SystemTime::now();
+
+    let ahead = Skew::between(local + HOUR, local);
+    assert_eq!(ahead.seconds(), 3600);
+    assert!(ahead.significant());
+    assert_eq!(ahead.to_server(local), local + HOUR);
+    assert_eq!(ahead.to_string(), "1h 0m 0s ahead of");
+
+    let behind = Skew::between(local - 10 * MINUTE - Duration::from_secs(5), local);
+    assert_eq!(behind.seconds(), -605);
+    assert!(behind.significant());
+    assert_eq!(
+        behind.to_server(local),
+        local - 10 * MINUTE - Duration::from_secs(5)
+    );
+    assert_eq!(behind.to_string(), "10m 5s behind");
+
+    let close = Skew::between(local + 4 * MINUTE, local);
+    assert!(!close.significant());
+}
+
+#[test]
+fn skew_corrects_the_comparison_in_both_directions() {
+    // When the file was written, by the server's clock.
+    let written = SystemTime::now();
+
+    // A local clock an hour fast dates the file an hour late, hiding a
+    // remote change ten minutes after it.
+    let local = written + HOUR;
+    let remote = last_modified(written + 10 * MINUTE);
+    assert!(newer::not_newer_by_headers(&remote, local).is_some());
+    let skew = Skew::between(written, local);
+    assert!(newer::not_newer_by_headers(&remote, skew.to_server(local)).is_none());
+
+    // An hour slow dates it an hour early, making a remote change ten
+    // minutes before it look newer.
+    let local = written - HOUR;
+    let remote = last_modified(written - 10 * MINUTE);
+    assert!(newer::not_newer_by_headers(&remote, local).is_none());
+    let skew = Skew::between(written, local);
+    assert!(newer::not_newer_by_headers(&remote, skew.to_server(local)).is_some());
+}
+
+#[test]
+fn timestamps_given_as_such_arent_corrected() {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+    assert_eq!(NewerThan::Time(time).server_time(), time);
+}
+
+#[test]
+fn a_slow_local_clock_doesnt_redownload_older_files() {
+    // Written just now by a clock an hour slow; the server's copy is from
+    // ten minutes before that.
+    let server = skewed_server(payload(50_000), 3600, 10 * MINUTE);
+    let dir = scratch_dir("a_slow_local_clock_doesnt_redownload_older_files");
+    let mirror = dir.join("mirror.bin");
+    std::fs::write(&mirror, "already have it").unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--newer-than",
+        mirror.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("not newer than"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ahead of this machine's"), "{stderr}");
+    assert!(stderr.contains("correcting local file times"), "{stderr}");
+    assert!(!dir.join("file.bin").exists());
+}
+
+#[test]
+fn a_fast_local_clock_doesnt_skip_newer_remote_files() {
+    // Written half an hour ago by a clock an hour fast, so an hour and a
+    // half before it says; the server's copy changed ten minutes ago.
+    let data = payload(50_000);
+    let server = skewed_server(data.clone(), -3600, 10 * MINUTE);
+    let dir = scratch_dir("a_fast_local_clock_doesnt_skip_newer_remote_files");
+    let mirror = dir.join("mirror.bin");
+    std::fs::write(&mirror, "already have it").unwrap();
+    File::options()
+        .write(true)
+        .open(&mirror)
+        .unwrap()
+        .set_modified(SystemTime::now() - 30 * MINUTE)
+        .unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--newer-than",
+        mirror.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("behind this machine's"), "{stderr}");
+}
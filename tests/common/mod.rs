@@ -0,0 +1,503 @@
+//! A small range-capable HTTP/1.1 server for integration tests.
+//!
+//! It runs on plain std threads so tests don't depend on the runtime under
+//! test, and every connection is closed after one response to keep the
+//! protocol handling trivial.
+#![allow(dead_code)]
+
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A request as seen by the server.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A canned response returned by a custom handler.
+#[derive(Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub drip: Option<(usize, Duration)>,
+    /// Leave out `Content-Length`, so the body ends with the connection.
+    pub no_length: bool,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            drip: None,
+            no_length: false,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Sends the body `chunk` bytes at a time, pausing `delay` after each.
+    pub fn drip(mut self, chunk: usize, delay: Duration) -> Self {
+        self.drip = Some((chunk, delay));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request, usize) -> Option<Response> + Send + Sync;
+
+pub struct TestServerBuilder {
+    payload: Vec<u8>,
+    accept_ranges: bool,
+    max_range: Option<usize>,
+    fail_after: Option<(usize, usize)>,
+    hang: bool,
+    drip: Option<(usize, Duration)>,
+    headers: Vec<(String, String)>,
+    handler: Option<Arc<Handler>>,
+    one_connection: Option<Refusal>,
+    no_length: bool,
+    ipv6: bool,
+}
+
+/// How a server that allows one connection at a time refuses another.
+#[derive(Clone, Copy, Debug)]
+pub enum Refusal {
+    /// Answers 503 Service Unavailable.
+    Unavailable,
+    /// Closes the connection without an answer.
+    Reset,
+}
+
+impl TestServerBuilder {
+    /// Ignore `Range` headers and always answer 200 with the full payload.
+    pub fn no_ranges(mut self) -> Self {
+        self.accept_ranges = false;
+        self
+    }
+
+    /// Answer ranges with at most `bytes` bytes from their start, whatever
+    /// end was asked for.
+    pub fn max_range(mut self, bytes: usize) -> Self {
+        self.max_range = Some(bytes);
+        self
+    }
+
+    /// Drop the connection after `bytes` body bytes, for the first `times`
+    /// payload responses.
+    pub fn fail_after(mut self, bytes: usize, times: usize) -> Self {
+        self.fail_after = Some((bytes, times));
+        self
+    }
+
+    /// Like [`fail_after`](Self::fail_after), but keep the connection open
+    /// without sending anything further instead of closing it.
+    pub fn stall_after(mut self, bytes: usize, times: usize) -> Self {
+        self.fail_after = Some((bytes, times));
+        self.hang = true;
+        self
+    }
+
+    /// Send the body `chunk` bytes at a time, sleeping `delay` in between.
+    pub fn drip(mut self, chunk: usize, delay: Duration) -> Self {
+        self.drip = Some((chunk, delay));
+        self
+    }
+
+    /// Leave `Content-Length` out of payload responses, so their body ends
+    /// when the connection closes.
+    pub fn no_length(mut self) -> Self {
+        self.no_length = true;
+        self
+    }
+
+    /// Listen on `[::1]` rather than `127.0.0.1`.
+    pub fn ipv6(mut self) -> Self {
+        self.ipv6 = true;
+        self
+    }
+
+    /// Refuse a connection while another is being served, as `refusal`.
+    pub fn one_connection(mut self, refusal: Refusal) -> Self {
+        self.one_connection = Some(refusal);
+        self
+    }
+
+    /// Send this header with every payload response.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Install a hook that may answer a request before the default payload
+    /// handling. It receives the request and its zero-based sequence number.
+    pub fn handler(
+        mut self,
+        handler: impl Fn(&Request, usize) -> Option<Response> + Send + Sync + 'static,
+    ) -> Self {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn start(self) -> TestServer {
+        let address = match self.ipv6 {
+            true => "[::1]:0",
+            false => "127.0.0.1:0",
+        };
+        let listener = TcpListener::bind(address).expect("bind test server");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(ServerState {
+            payload: self.payload,
+            accept_ranges: self.accept_ranges,
+            max_range: self.max_range,
+            fail_after: self.fail_after.map(|(bytes, _)| bytes),
+            failures_left: AtomicUsize::new(self.fail_after.map_or(0, |(_, times)| times)),
+            hang: self.hang,
+            drip: self.drip,
+            headers: self.headers,
+            handler: self.handler,
+            requests: Mutex::new(Vec::new()),
+            one_connection: self.one_connection,
+            no_length: self.no_length,
+            open: AtomicUsize::new(0),
+            refused: Mutex::new(Vec::new()),
+        });
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = server_state.clone();
+                thread::spawn(move || {
+                    // Clients hanging up mid-body is expected, not a failure.
+                    let _ = state.serve(stream);
+                });
+            }
+        });
+        TestServer { addr, state }
+    }
+}
+
+struct ServerState {
+    payload: Vec<u8>,
+    accept_ranges: bool,
+    max_range: Option<usize>,
+    fail_after: Option<usize>,
+    failures_left: AtomicUsize,
+    hang: bool,
+    drip: Option<(usize, Duration)>,
+    headers: Vec<(String, String)>,
+    handler: Option<Arc<Handler>>,
+    requests: Mutex<Vec<Request>>,
+    one_connection: Option<Refusal>,
+    no_length: bool,
+    /// Connections being served.
+    open: AtomicUsize,
+    refused: Mutex<Vec<Request>>,
+}
+
+/// Counts a connection as open until dropped.
+struct Open<'a>(&'a AtomicUsize);
+
+impl Drop for Open<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ServerState {
+    fn serve(&self, stream: TcpStream) -> std::io::Result<()> {
+        let others = self.open.fetch_add(1, Ordering::SeqCst);
+        let _open = Open(&self.open);
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut request = Request {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+        let length = request
+            .header("Content-Length")
+            .and_then(|value| value.parse().ok());
+        request.body = vec![0; length.unwrap_or(0)];
+        reader.read_exact(&mut request.body)?;
+        let sequence = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(request.clone());
+            requests.len() - 1
+        };
+
+        let mut stream = stream;
+        // The connection before may not be counted closed quite yet.
+        let busy = || {
+            others > 0 && {
+                thread::sleep(Duration::from_millis(20));
+                self.open.load(Ordering::SeqCst) > 1
+            }
+        };
+        if let Some(refusal) = self.one_connection.filter(|_| busy()) {
+            self.refused.lock().unwrap().push(request.clone());
+            return match refusal {
+                Refusal::Unavailable => {
+                    let response = Response::new(503, "one connection at a time");
+                    write_response(&mut stream, &request, response, None, None)
+                }
+                Refusal::Reset => stream.shutdown(std::net::Shutdown::Both),
+            };
+        }
+        if let Some(handler) = &self.handler
+            && let Some(response) = handler(&request, sequence)
+        {
+            let drip = response.drip;
+            return write_response(&mut stream, &request, response, None, drip);
+        }
+        if let Some(target) = request.path.strip_prefix("/redirect") {
+            let response = Response::new(302, Vec::new()).header("Location", target);
+            return write_response(&mut stream, &request, response, None, None);
+        }
+
+        let response = self.payload_response(&request);
+        let fail_after = self.fail_after.filter(|_| {
+            request.method != "HEAD"
+                && self
+                    .failures_left
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                        left.checked_sub(1)
+                    })
+                    .is_ok()
+        });
+        write_response(&mut stream, &request, response, fail_after, self.drip)?;
+        if fail_after.is_some() && !self.hang {
+            stream.shutdown(std::net::Shutdown::Both)?;
+        } else if fail_after.is_some() {
+            // Hold the connection open without sending anything else; the
+            // client is expected to give up on it.
+            thread::sleep(Duration::from_secs(60));
+        }
+        Ok(())
+    }
+
+    fn payload_response(&self, request: &Request) -> Response {
+        let total = self.payload.len();
+        let range = request
+            .header("Range")
+            .filter(|_| self.accept_ranges)
+            .map(|range| parse_range(range, total))
+            .map(|range| {
+                let max = self.max_range.unwrap_or(total);
+                range.map(|(start, end)| (start, end.min(start + max - 1)))
+            });
+        let mut response = match range {
+            None => Response::new(200, self.payload.clone()),
+            Some(Some((start, end))) => Response::new(206, self.payload[start..=end].to_vec())
+                .header("Content-Range", format!("bytes {start}-{end}/{total}")),
+            Some(None) => {
+                Response::new(416, Vec::new()).header("Content-Range", format!("bytes */{total}"))
+            }
+        };
+        if self.accept_ranges {
+            response = response.header("Accept-Ranges", "bytes");
+        }
+        response.no_length = self.no_length;
+        for (name, value) in &self.headers {
+            response = response.header(name, value.clone());
+        }
+        response
+    }
+}
+
+/// Parses a single `bytes=` range into inclusive offsets, `None` when it
+/// can't be satisfied.
+fn parse_range(header: &str, total: usize) -> Option<(usize, usize)> {
+    let spec = header.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            (total.checked_sub(suffix.min(total))?, total.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, total.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<usize>().ok()?.min(total.saturating_sub(1)),
+        ),
+    };
+    (start <= end && start < total).then_some((start, end))
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    request: &Request,
+    response: Response,
+    fail_after: Option<usize>,
+    drip: Option<(usize, Duration)>,
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Test\r\n", response.status);
+    let has_length = response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Content-Length"));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !has_length && !response.no_length {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes())?;
+    if request.method == "HEAD" {
+        return Ok(());
+    }
+
+    let body = match fail_after {
+        Some(limit) => &response.body[..limit.min(response.body.len())],
+        None => &response.body[..],
+    };
+    match drip {
+        Some((chunk, delay)) => {
+            for piece in body.chunks(chunk.max(1)) {
+                stream.write_all(piece)?;
+                stream.flush()?;
+                thread::sleep(delay);
+            }
+        }
+        None => stream.write_all(body)?,
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+}
+
+impl TestServer {
+    pub fn builder(payload: Vec<u8>) -> TestServerBuilder {
+        TestServerBuilder {
+            payload,
+            accept_ranges: true,
+            max_range: None,
+            fail_after: None,
+            hang: false,
+            drip: None,
+            headers: Vec::new(),
+            handler: None,
+            one_connection: None,
+            no_length: false,
+            ipv6: false,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// The requests refused by [`one_connection`](TestServerBuilder::one_connection).
+    pub fn refused(&self) -> Vec<Request> {
+        self.state.refused.lock().unwrap().clone()
+    }
+}
+
+/// Deterministic pseudo-random payload of the given length.
+pub fn payload(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// A scratch directory under the cargo target dir, wiped on creation.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn dlm() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dlm"));
+    command.env_remove("RUST_BACKTRACE");
+    // Keep manifests of downloads in flight out of the real home directory.
+    command.env(
+        "XDG_STATE_HOME",
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join("state"),
+    );
+    command
+}
+
+/// Runs `dlm` with the given arguments to completion.
+pub fn run_dlm(args: &[&str]) -> Output {
+    dlm().args(args).output().expect("run dlm")
+}
+
+/// Extracts the hash printed after a successful download.
+pub fn printed_sha256(output: &Output) -> Option<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("SHA256: ").map(str::to_string))
+}
+
+/// Asserts a successful run whose printed hash and file content match `data`.
+pub fn assert_downloaded(output: &Output, path: &Path, data: &[u8]) {
+    assert!(output.status.success(), "{output:?}");
+    let written = std::fs::read(path).unwrap_or_default();
+    let first_difference = written.iter().zip(data).position(|(a, b)| a != b);
+    assert!(
+        written == data,
+        "{} has {} bytes (expected {}), first difference at {first_difference:?}\n{output:?}",
+        path.display(),
+        written.len(),
+        data.len()
+    );
+    assert_eq!(printed_sha256(output), Some(sha256_hex(data)));
+}
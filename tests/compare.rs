@@ -0,0 +1,106 @@
+mod common;
+
+use common::{TestServer, payload, run_dlm, sha256_hex};
+use serde_json::Value;
+
+/// Runs `dlm compare` on `a` and `b` with `args` after them.
+fn compare(a: &TestServer, b: &TestServer, args: &[&str]) -> std::process::Output {
+    let (url_a, url_b) = (a.url("/file.bin"), b.url("/file.bin"));
+    let mut command = vec!["compare", &url_a, &url_b];
+    command.extend(args);
+    run_dlm(&command)
+}
+
+#[test]
+fn identical_mirrors_are_sampled_with_ranges() {
+    let data = payload(200_000);
+    let a = TestServer::builder(data.clone()).start();
+    let b = TestServer::builder(data).start();
+
+    let output = compare(&a, &b, &["--samples", "8", "--sample-size", "4k"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("A: http://"), "{stdout}");
+    assert!(stdout.contains("200000 bytes"), "{stdout}");
+    assert!(stdout.contains("Identical: compared"), "{stdout}");
+    for server in [&a, &b] {
+        let requests = server.requests();
+        assert!(requests.len() <= 9, "{requests:?}");
+        assert_eq!(requests[0].method, "HEAD");
+        assert!(
+            requests[1..]
+                .iter()
+                .all(|request| request.header("Range").is_some())
+        );
+    }
+}
+
+#[test]
+fn the_first_divergence_is_reported() {
+    let data = payload(200_000);
+    let mut changed = data.clone();
+    changed[150_000] ^= 0xff;
+    changed[199_999] ^= 0xff;
+    let a = TestServer::builder(data).start();
+    let b = TestServer::builder(changed).start();
+
+    // The last range is always among the samples.
+    let output = compare(&a, &b, &["--samples", "1", "--sample-size", "1k"]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Different at byte 199999: content differs"),
+        "{stdout}"
+    );
+
+    let output = compare(&a, &b, &["--full"]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "Different at byte 150000: content differs (compared 150000 bytes, every byte)"
+        ),
+        "{stdout}"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The mirrors differ from byte 150000"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_shorter_copy_diverges_where_it_ends() {
+    let data = payload(200_000);
+    let a = TestServer::builder(data.clone()).start();
+    let b = TestServer::builder(data[..120_000].to_vec()).start();
+
+    let output = compare(&a, &b, &["--json"]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let comparison: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(comparison["identical"], false);
+    assert_eq!(comparison["full"], false);
+    assert_eq!(comparison["divergence"]["offset"], 120_000);
+    assert_eq!(
+        comparison["divergence"]["reason"],
+        "A is 200000 bytes and B is 120000"
+    );
+}
+
+#[test]
+fn range_less_mirrors_are_compared_in_full() {
+    let data = payload(200_000);
+    let a = TestServer::builder(data.clone()).start();
+    let b = TestServer::builder(data.clone()).no_ranges().start();
+
+    let output = compare(&a, &b, &["--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let comparison: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(comparison["identical"], true);
+    assert_eq!(comparison["full"], true);
+    assert_eq!(comparison["compared"], 200_000);
+    assert_eq!(comparison["a"]["ranges"], true);
+    assert_eq!(comparison["b"]["ranges"], false);
+    assert_eq!(comparison["b"]["size"], 200_000);
+    assert_eq!(comparison["sha256"], sha256_hex(&data));
+}
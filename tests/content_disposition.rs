@@ -0,0 +1,177 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+const COMMANDS: [(&str, &[&str]); 3] = [
+    ("blocking", &["download-blocking"]),
+    ("async", &["download-async"]),
+    ("workers", &["download-async", "--workers", "4"]),
+];
+
+/// Downloads `path` off a server answering with `disposition` into a fresh
+/// directory, with `flags` before the URL and `command` after it.
+fn download(
+    name: &str,
+    disposition: &str,
+    path: &str,
+    flags: &[&str],
+    command: &[&str],
+) -> (Output, PathBuf, Vec<u8>) {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone())
+        .header("Content-Disposition", disposition)
+        .start();
+    let dir = scratch_dir(name);
+    let url = server.url(path);
+    let mut args = vec!["-t", dir.to_str().unwrap()];
+    args.extend_from_slice(flags);
+    args.push(&url);
+    args.extend_from_slice(command);
+    (run_dlm(&args), dir, data)
+}
+
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn the_server_names_the_file() {
+    for (name, command) in COMMANDS {
+        let (output, dir, data) = download(
+            &format!("the_server_names_the_file_{name}"),
+            r#"attachment; filename="report \"final\" 2024.pdf""#,
+            "/download?id=12345",
+            &[],
+            command,
+        );
+        assert_downloaded(&output, &dir.join(r#"report "final" 2024.pdf"#), &data);
+        assert_eq!(files(&dir), [r#"report "final" 2024.pdf"#], "{name}");
+    }
+}
+
+#[test]
+fn the_extended_name_wins() {
+    for (disposition, expected) in [
+        (
+            "attachment; filename=fallback.bin; filename*=UTF-8''%E2%82%AC%20rates.csv",
+            "€ rates.csv",
+        ),
+        ("inline; filename*=iso-8859-1'en'caf%E9.txt", "café.txt"),
+        // A charset that isn't understood leaves the plain name.
+        (
+            "attachment; filename*=koi8-r''%C1.txt; filename=plain.txt",
+            "plain.txt",
+        ),
+    ] {
+        let (output, dir, data) = download(
+            "the_extended_name_wins",
+            disposition,
+            "/7f3c9a2e-uuid",
+            &[],
+            &["download-async"],
+        );
+        assert_downloaded(&output, &dir.join(expected), &data);
+    }
+}
+
+#[test]
+fn names_from_the_server_stay_in_the_target_directory() {
+    for (name, command) in COMMANDS {
+        for (disposition, expected) in [
+            (r#"attachment; filename="../../evil.sh""#, "evil.sh"),
+            (r#"attachment; filename="..\\..\\evil.bat""#, "evil.bat"),
+            (r#"attachment; filename="/etc/passwd""#, "passwd"),
+            // Nothing usable is left, so the URL names it.
+            (r#"attachment; filename="..""#, "file.bin"),
+        ] {
+            let test = format!("names_from_the_server_stay_in_the_target_directory_{name}");
+            let (output, dir, data) = download(&test, disposition, "/file.bin", &[], command);
+            assert_downloaded(&output, &dir.join(expected), &data);
+            assert_eq!(files(&dir), [expected], "{name}: {disposition}");
+            assert!(!dir.parent().unwrap().join("evil.sh").exists());
+        }
+    }
+}
+
+#[test]
+fn output_overrides_the_servers_name() {
+    for (name, command) in COMMANDS {
+        for flag in ["-O", "--output"] {
+            let (output, dir, data) = download(
+                &format!("output_overrides_the_servers_name_{name}"),
+                "attachment; filename=served.bin",
+                "/download?id=1",
+                &[flag, "mine.bin"],
+                command,
+            );
+            assert_downloaded(&output, &dir.join("mine.bin"), &data);
+            assert_eq!(files(&dir), ["mine.bin"], "{name}");
+        }
+    }
+}
+
+#[test]
+fn url_segments_are_percent_decoded() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("url_segments_are_percent_decoded");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/my%20file.tar.gz"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("my file.tar.gz"), &data);
+
+    // An encoded slash is no way out either.
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/..%2F..%2Fescape.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("escape.bin"), &data);
+}
+
+#[test]
+fn a_file_the_server_named_is_kept_or_resumed() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone())
+        .header("Content-Disposition", "attachment; filename=named.bin")
+        .start();
+    let dir = scratch_dir("a_file_the_server_named_is_kept_or_resumed");
+    let url = server.url("/download?id=7");
+    std::fs::write(dir.join("named.bin"), &data[..40_000]).unwrap();
+
+    let output = run_dlm(&["-t", dir.to_str().unwrap(), &url, "download-async"]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("File exists at"),
+        "{output:?}"
+    );
+    assert_eq!(
+        std::fs::read(dir.join("named.bin")).unwrap(),
+        &data[..40_000]
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        &url,
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("named.bin"), &data);
+    let resumed = server
+        .requests()
+        .iter()
+        .any(|request| request.header("Range") == Some("bytes=40000-"));
+    assert!(resumed, "{:?}", server.requests());
+}
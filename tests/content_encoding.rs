@@ -0,0 +1,189 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+
+/// A CDN that gzips whatever it's asked for, ranges included, and says so.
+fn encoding_server(data: Vec<u8>) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            let range = request.header("Range")?;
+            let start: usize = range
+                .strip_prefix("bytes=")?
+                .split('-')
+                .next()?
+                .parse()
+                .ok()?;
+            Some(
+                Response::new(206, data[start..].to_vec())
+                    .header(
+                        "Content-Range",
+                        format!("bytes {start}-{}/{}", data.len() - 1, data.len()),
+                    )
+                    .header("Content-Encoding", "gzip"),
+            )
+        })
+        .start()
+}
+
+#[test]
+fn ranges_ask_for_the_plain_bytes() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("ranges_ask_for_the_plain_bytes");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let ranged: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|request| request.header("Range").is_some())
+        .collect();
+    // A warm-up per worker, then its chunk.
+    assert_eq!(ranged.len(), 4);
+    for request in ranged {
+        assert_eq!(request.header("Accept-Encoding"), Some("identity"));
+    }
+}
+
+#[test]
+fn encoded_chunks_are_refused() {
+    let data = payload(200_000);
+    let server = encoding_server(data);
+    let dir = scratch_dir("encoded_chunks_are_refused");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Content-Encoding: gzip"), "{stderr}");
+    assert!(stderr.contains("in one stream from the start"), "{stderr}");
+}
+
+#[test]
+fn encoded_resumes_are_refused() {
+    let data = payload(200_000);
+    let server = encoding_server(data.clone());
+    let dir = scratch_dir("encoded_resumes_are_refused");
+    std::fs::write(dir.join("file.bin"), &data[..50_000]).unwrap();
+
+    for command in ["download-async", "download-blocking"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ]);
+        assert!(!output.status.success(), "{command}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Content-Encoding: gzip"),
+            "{command}: {stderr}"
+        );
+        assert_eq!(
+            std::fs::metadata(dir.join("file.bin")).unwrap().len(),
+            50_000
+        );
+    }
+}
+
+/// A server that compresses whatever it's asked for, ignoring ranges.
+fn compressing_server(data: Vec<u8>) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |_, _| {
+            Some(Response::new(200, data.clone()).header("Content-Encoding", "gzip"))
+        })
+        .start()
+}
+
+#[test]
+fn a_compressed_file_is_downloaded_in_one_stream_as_sent() {
+    // Stands in for the gzip stream: it's saved byte for byte either way.
+    let data = payload(200_000);
+    let server = compressing_server(data.clone());
+    let dir = scratch_dir("a_compressed_file_is_downloaded_in_one_stream_as_sent");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The server sends the file with Content-Encoding: gzip, downloading")
+            && stderr.contains("in one stream instead of 4 workers"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("saving it as sent"), "{stderr}");
+}
+
+#[test]
+fn no_compression_asks_for_the_plain_bytes() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("no_compression_asks_for_the_plain_bytes");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--no-compression",
+        "--header",
+        "Accept-Encoding: gzip",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    for request in server.requests() {
+        assert_eq!(
+            request.header("Accept-Encoding"),
+            Some("identity"),
+            "{} {}",
+            request.method,
+            request.path
+        );
+    }
+}
+
+#[test]
+fn no_compression_refuses_a_compressed_answer() {
+    let server = compressing_server(payload(200_000));
+    let dir = scratch_dir("no_compression_refuses_a_compressed_answer");
+
+    for command in ["download-async", "download-blocking"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--no-compression",
+            &server.url("/file.bin"),
+            command,
+        ]);
+        assert_eq!(output.status.code(), Some(1), "{command}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(
+                "The server sent the file with Content-Encoding: gzip although --no-compression asked for the plain bytes"
+            ),
+            "{command}: {stderr}"
+        );
+        assert!(!dir.join("file.bin").exists(), "{command}");
+    }
+}
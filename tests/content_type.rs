@@ -0,0 +1,81 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+
+const ERROR_DOCUMENT: &str = r#"{"error": "NoSuchKey"}"#;
+
+fn json_server() -> TestServer {
+    TestServer::builder(payload(1_000))
+        .handler(|request, _| match request.path.as_str() {
+            "/backup.tar.zst" => {
+                Some(Response::new(200, ERROR_DOCUMENT).header("Content-Type", "application/json"))
+            }
+            "/generic.tar.zst" => Some(
+                Response::new(200, payload(1_000))
+                    .header("Content-Type", "application/octet-stream"),
+            ),
+            _ => None,
+        })
+        .start()
+}
+
+#[test]
+fn contradicting_content_type_warns() {
+    let server = json_server();
+    let dir = scratch_dir("contradicting_content_type_warns");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/backup.tar.zst"),
+        "download-async",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("served as application/json, but named like a .zst file"),
+        "{stderr}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/generic.tar.zst"),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("served as"));
+}
+
+#[test]
+fn strict_content_type_fails_before_writing() {
+    let server = json_server();
+    let dir = scratch_dir("strict_content_type_fails_before_writing");
+    let existing = dir.join("backup.tar.zst");
+    std::fs::write(&existing, "yesterday's backup").unwrap();
+
+    for mode in [
+        &["download-blocking"][..],
+        &["download-async", "--workers", "2"],
+    ] {
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--overwrite",
+            "--strict-content-type",
+        ];
+        let url = server.url("/backup.tar.zst");
+        args.push(&url);
+        args.extend(mode);
+        let output = run_dlm(&args);
+
+        assert_eq!(output.status.code(), Some(4), "{mode:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Not writing"), "{stderr}");
+        assert_eq!(
+            std::fs::read_to_string(&existing).unwrap(),
+            "yesterday's backup"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{TestServer, payload, printed_sha256, run_dlm, scratch_dir};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+fn ctl(socket: &Path, request: &[&str]) -> Option<serde_json::Value> {
+    let mut args = vec!["ctl", "--socket", socket.to_str().unwrap()];
+    args.extend(request);
+    let output = run_dlm(&args);
+    output
+        .status
+        .success()
+        .then(|| serde_json::from_slice(&output.stdout).unwrap())
+}
+
+#[test]
+fn control_socket_pauses_limits_and_cancels() {
+    let server = TestServer::builder(payload(200_000))
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("control_socket_pauses_limits_and_cancels");
+    let socket = dir.join("dlm.sock");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--control-socket",
+            socket.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "2",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        match ctl(&socket, &["status"]) {
+            Some(status) if status["downloaded"].as_u64() > Some(0) => break status,
+            _ if Instant::now() > deadline => panic!("control socket never answered"),
+            _ => std::thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    assert_eq!(status["state"], "running");
+    assert_eq!(status["total"], 200_000);
+    assert_eq!(status["paused"], false);
+    assert_eq!(
+        std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    assert_eq!(ctl(&socket, &["pause"]).unwrap()["paused"], true);
+    std::thread::sleep(Duration::from_millis(300));
+    let before = ctl(&socket, &["status"]).unwrap()["downloaded"].clone();
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(ctl(&socket, &["status"]).unwrap()["downloaded"], before);
+
+    let limited = ctl(&socket, &["set-rate-limit", "2k"]).unwrap();
+    assert_eq!(limited["rate_limit"], 2048);
+    assert_eq!(ctl(&socket, &["resume"]).unwrap()["paused"], false);
+
+    ctl(&socket, &["cancel"]).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("interrupted"));
+    assert!(printed_sha256(&output).is_none());
+    assert!(!socket.exists());
+}
@@ -0,0 +1,244 @@
+#![cfg(feature = "sigstore")]
+
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use common::{Response, TestServer, assert_downloaded, dlm, payload, scratch_dir};
+use rustls::SignatureScheme;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::pem::PemObject;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// The key pair `cosign.key` and `cosign.pub` and the unrelated
+/// `other.key`, all ECDSA P-256 as `cosign generate-key-pair` makes them.
+fn keys() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs")
+}
+
+/// `data` signed with the private key `key` as `cosign sign-blob` signs
+/// it: ECDSA over its SHA-256, DER, in base64.
+fn sign(key: &str, data: &[u8]) -> String {
+    let key = PrivateKeyDer::from_pem_file(keys().join(key)).unwrap();
+    let key = rustls::crypto::ring::sign::any_ecdsa_type(&key).unwrap();
+    let signer = key
+        .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+        .unwrap();
+    STANDARD.encode(signer.sign(data).unwrap())
+}
+
+/// A server with the file at `/file.bin` and signatures beside it: a good
+/// one, bare and in a cosign bundle, one of other data, one by another
+/// key, and a keyless bundle that isn't one.
+fn server(data: Vec<u8>) -> TestServer {
+    let good = sign("cosign.key", &data);
+    let bundle = serde_json::json!({ "base64Signature": good }).to_string();
+    let forged = sign("cosign.key", b"something else");
+    let unmatched = sign("other.key", &data);
+    TestServer::builder(data)
+        .handler(move |request, _| match request.path.as_str() {
+            "/file.bin.sig" => Some(Response::new(200, good.clone())),
+            "/file.bin.bundle" => Some(Response::new(200, bundle.clone())),
+            "/forged.sig" => Some(Response::new(200, forged.clone())),
+            "/unmatched.sig" => Some(Response::new(200, unmatched.clone())),
+            "/file.bin.sigstore.json" => Some(Response::new(200, r#"{"mediaType": 1}"#)),
+            "/missing.sig" => Some(Response::new(404, "")),
+            _ => None,
+        })
+        .start()
+}
+
+/// Downloads `/file.bin` into `dir`, with the state directory there and
+/// `--progress json` on stdout.
+fn download(server: &TestServer, dir: &Path, args: &[&str]) -> Output {
+    dlm()
+        .args(["--state-dir", dir.join("state").to_str().unwrap()])
+        .args(["-t", dir.to_str().unwrap()])
+        .args(["--progress", "json", "--progress-stdout"])
+        .args(args)
+        .args([&server.url("/file.bin"), "download-async"])
+        .output()
+        .unwrap()
+}
+
+/// The `--progress json` event called `name`.
+fn event(output: &Output, name: &str) -> Value {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .find(|event| event["event"] == name)
+        .unwrap_or_else(|| panic!("no {name} event: {output:?}"))
+}
+
+/// What `usage.jsonl` recorded of the download.
+fn usage(dir: &Path) -> Value {
+    let usage = std::fs::read_to_string(dir.join("state/usage.jsonl")).unwrap();
+    serde_json::from_str(usage.lines().next().unwrap()).unwrap()
+}
+
+#[test]
+fn a_signature_that_holds_is_recorded() {
+    let data = payload(20_000);
+    let server = server(data.clone());
+    let dir = scratch_dir("a_signature_that_holds_is_recorded");
+    let key = keys().join("cosign.pub");
+
+    // Bare, and in the bundle `cosign sign-blob --bundle` writes.
+    for signature in ["/file.bin.sig", "/file.bin.bundle"] {
+        let dir = dir.join(&signature[1..]);
+        let signature_url = server.url(signature);
+        let output = download(
+            &server,
+            &dir,
+            &[
+                "--cosign-signature-url",
+                &signature_url,
+                "--cosign-key",
+                key.to_str().unwrap(),
+            ],
+        );
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains(&format!(
+                "Signature PASS: verified for key {}",
+                key.display()
+            )),
+            "{stdout}"
+        );
+
+        let expected = serde_json::json!({
+            "url": signature_url,
+            "signer": format!("key {}", key.display()),
+            "verified": true,
+        });
+        assert_eq!(event(&output, "completed")["signature"], expected);
+        assert_eq!(usage(&dir)["signature"], expected);
+    }
+}
+
+#[test]
+fn a_signature_that_doesnt_hold_exits_with_12() {
+    let server = server(payload(20_000));
+    let dir = scratch_dir("a_signature_that_doesnt_hold_exits_with_12");
+    let key = keys().join("cosign.pub");
+    let key = key.to_str().unwrap();
+    let keyless = [
+        "--cosign-certificate-identity",
+        "release@example.com",
+        "--cosign-oidc-issuer",
+        "https://accounts.example.com",
+    ];
+
+    // A signature of other data, one by another key, and a keyless bundle
+    // that isn't one.
+    for (signature, signer) in [
+        ("/forged.sig", &["--cosign-key", key][..]),
+        ("/unmatched.sig", &["--cosign-key", key][..]),
+        ("/file.bin.sigstore.json", &keyless[..]),
+    ] {
+        let dir = dir.join(&signature[1..]);
+        let signature_url = server.url(signature);
+        let args = [&["--cosign-signature-url", &signature_url][..], signer].concat();
+        let output = download(&server, &dir, &args);
+        assert_eq!(output.status.code(), Some(12), "{output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("Signature FAIL"),
+            "{output:?}"
+        );
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("The cosign signature of"),
+            "{output:?}"
+        );
+        let error = event(&output, "error");
+        assert_eq!(error["exit_code"], 12);
+        assert_eq!(error["signature"]["verified"], false);
+        let usage = usage(&dir);
+        assert_eq!(usage["outcome"], "failed");
+        assert_eq!(usage["signature"]["verified"], false);
+    }
+}
+
+#[test]
+fn a_signature_that_cant_be_fetched_exits_with_8() {
+    let server = server(payload(20_000));
+    let dir = scratch_dir("a_signature_that_cant_be_fetched_exits_with_8");
+
+    let output = download(
+        &server,
+        &dir,
+        &[
+            "--cosign-signature-url",
+            &server.url("/missing.sig"),
+            "--cosign-key",
+            keys().join("cosign.pub").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(&format!(
+            "Cannot fetch the cosign signature from {}",
+            server.url("/missing.sig")
+        )),
+        "{output:?}"
+    );
+    assert!(event(&output, "error").get("signature").is_none());
+}
+
+#[test]
+fn a_key_that_cant_be_loaded_isnt_a_signature_that_doesnt_hold() {
+    let server = server(payload(20_000));
+    let dir = scratch_dir("a_key_that_cant_be_loaded_isnt_a_signature_that_doesnt_hold");
+
+    // Missing, and not a public key.
+    for key in [keys().join("missing.pub"), keys().join("ca.pem")] {
+        let dir = dir.join(key.file_name().unwrap());
+        let output = download(
+            &server,
+            &dir,
+            &[
+                "--cosign-signature-url",
+                &server.url("/file.bin.sig"),
+                "--cosign-key",
+                key.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(output.status.code(), Some(1), "{output:?}");
+        assert!(
+            !String::from_utf8_lossy(&output.stdout).contains("Signature FAIL"),
+            "{output:?}"
+        );
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(&format!(
+                "Cannot check the cosign signature of '{}': loading public key '{}'",
+                dir.join("file.bin").display(),
+                key.display()
+            )),
+            "{output:?}"
+        );
+        assert!(event(&output, "error").get("signature").is_none());
+    }
+}
+
+#[test]
+fn a_signature_url_needs_a_key_or_an_identity() {
+    let dir = scratch_dir("a_signature_url_needs_a_key_or_an_identity");
+    let output = dlm()
+        .args(["-t", dir.to_str().unwrap()])
+        .args(["--cosign-signature-url", "http://localhost/file.bin.sig"])
+        .args(["http://localhost/file.bin", "download-async"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+
+    let output = dlm()
+        .args(["-t", dir.to_str().unwrap()])
+        .args(["--cosign-signature-url", "http://localhost/file.bin.sig"])
+        .args(["--cosign-certificate-identity", "release@example.com"])
+        .args(["http://localhost/file.bin", "download-async"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+#[test]
+fn soft_quota_warns_and_hard_quota_refuses() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("soft_quota_warns_and_hard_quota_refuses");
+    std::fs::create_dir_all(dir.join("old")).unwrap();
+    std::fs::write(dir.join("old/existing.bin"), payload(60_000)).unwrap();
+    // Neither is counted a second time.
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.join("old"), dir.join("link")).unwrap();
+    std::fs::hard_link(dir.join("old/existing.bin"), dir.join("again.bin")).unwrap();
+    let url = server.url("/file.bin");
+    let target = dir.to_str().unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "--dir-quota-hard",
+        "150k",
+        &url,
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(6), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "holds 58.59 KiB and 97.66 KiB more would take it past its hard quota of 150.00 KiB"
+        ),
+        "{stderr}"
+    );
+    assert!(stderr.contains("--force"), "{stderr}");
+    assert!(!dir.join("file.bin").exists());
+
+    let output = run_dlm(&["-t", target, "--dir-quota", "150k", &url, "download-async"]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("over its quota of 150.00 KiB"), "{stderr}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Target directory: 58.59 KiB before, 156.25 KiB after"),
+        "{stdout}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "--dir-quota-hard",
+        "150k",
+        "--force",
+        "--output",
+        "forced.bin",
+        &url,
+        "download-blocking",
+    ]);
+    assert_downloaded(&output, &dir.join("forced.bin"), &data);
+}
@@ -0,0 +1,1232 @@
+mod common;
+
+use common::{
+    Response, TestServer, assert_downloaded, payload, printed_sha256, run_dlm, scratch_dir,
+    sha256_hex,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[test]
+fn blocking_download_matches_payload() {
+    let data = payload(300_001);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("blocking_download_matches_payload");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-blocking",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hashed in "), "{stdout}");
+}
+
+#[test]
+fn async_download_matches_payload() {
+    let data = payload(300_001);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("async_download_matches_payload");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+#[test]
+fn async_download_follows_redirects() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("async_download_follows_redirects");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/redirect/file.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+#[test]
+fn worker_download_merges_parts_in_order() {
+    let data = payload(1_000_003);
+    let server = TestServer::builder(data.clone()).start();
+
+    for workers in ["1", "2", "3", "7"] {
+        let dir = scratch_dir(&format!("worker_download_merges_parts_{workers}"));
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            workers,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let leftovers: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name() != "file.bin")
+            .collect();
+        assert!(leftovers.is_empty(), "part files were not cleaned up");
+    }
+}
+
+#[test]
+fn workers_fall_back_to_one_stream_without_ranges() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).no_ranges().start();
+    let dir = scratch_dir("workers_fall_back_to_one_stream_without_ranges");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("doesn't accept ranges, downloading"),
+        "{stderr}"
+    );
+    // Only the probe asked for a range.
+    let ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    assert!(
+        ranges.iter().all(|range| range == "bytes=0-0"),
+        "{ranges:?}"
+    );
+}
+
+/// The id in the name of a worker-mode download's parts.
+fn download_id(url: &str, length: usize) -> String {
+    sha256_hex(format!("{url}\n{length}").as_bytes())[..8].to_string()
+}
+
+#[test]
+fn parts_are_named_by_download_and_index() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("parts_are_named_by_download_and_index");
+    let long = format!("{}.bin", "a".repeat(240));
+    let url = server.url("/file.bin");
+    let id = download_id(&url, data.len());
+
+    for name in ["file.bin", &long] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--output",
+            name,
+            "--no-cleanup",
+            &url,
+            "download-async",
+            "--workers",
+            "3",
+        ]);
+        assert_downloaded(&output, &dir.join(name), &data);
+        // Long names are cut short to keep the suffix.
+        let base = &name[..name.len().min(255 - 15)];
+        let mut parts: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|part| part.starts_with(&format!("{base}.{id}.")))
+            .collect();
+        parts.sort();
+        assert_eq!(
+            parts,
+            [
+                format!("{base}.{id}.p0000"),
+                format!("{base}.{id}.p0001"),
+                format!("{base}.{id}.p0002")
+            ]
+        );
+    }
+}
+
+#[test]
+fn stale_parts_are_removed_after_merging() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("stale_parts_are_removed_after_merging");
+    let url = server.url("/file.bin");
+    let id = download_id(&url, data.len());
+    // Left by an older version, and by a run with more workers.
+    let stale = ["file.bin.part.0-99999", &format!("file.bin.{id}.p0003")];
+    // Another download under the same name, and a file that only looks
+    // like a part.
+    let kept = ["file.bin.0123abcd.p0000", "file.bin.part.notes"];
+    for name in stale.iter().chain(&kept) {
+        std::fs::write(dir.join(name), b"left over").unwrap();
+    }
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    for name in stale {
+        assert!(!dir.join(name).exists(), "{name}");
+    }
+    for name in kept {
+        assert!(dir.join(name).exists(), "{name}");
+    }
+}
+
+#[test]
+fn worker_download_hashes_while_merging() {
+    let data = payload(1_000_003);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("worker_download_hashes_while_merging");
+
+    let output = run_dlm(&[
+        "-v",
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Hashed while merging the parts, in "),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("after the download"), "{stderr}");
+
+    // The merged digest matches a separate pass over the file.
+    let file = dir.join("file.bin");
+    let rehashed = run_dlm(&["hash", file.to_str().unwrap()]);
+    let rehashed = String::from_utf8_lossy(&rehashed.stdout);
+    assert_eq!(
+        rehashed.split_whitespace().next(),
+        printed_sha256(&output).as_deref()
+    );
+}
+
+#[test]
+fn buffered_and_serial_writes_match_payload() {
+    let data = payload(1_000_003);
+    let server = TestServer::builder(data.clone()).start();
+
+    for (name, extra) in [
+        ("unbuffered", &["--write-buffer", "0"][..]),
+        ("serial", &["--write-buffer", "64k", "--serial-writes"][..]),
+    ] {
+        let dir = scratch_dir(&format!("buffered_and_serial_writes_{name}"));
+        let mut args = vec!["-t", dir.to_str().unwrap()];
+        args.extend(extra);
+        let url = server.url("/file.bin");
+        args.extend([url.as_str(), "download-async", "--workers", "4"]);
+
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+    }
+}
+
+#[test]
+fn resume_appends_to_partial_file() {
+    let data = payload(500_000);
+    let server = TestServer::builder(data.clone()).start();
+
+    for command in ["download-blocking", "download-async"] {
+        let dir = scratch_dir(&format!("resume_appends_to_partial_file_{command}"));
+        std::fs::write(dir.join("file.bin"), &data[..123_456]).unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(last.header("Range"), Some("bytes=123456-"));
+    }
+}
+
+#[test]
+fn resume_of_a_complete_file_says_so_and_leaves_it() {
+    let data = payload(500_000);
+    let server = TestServer::builder(data.clone()).start();
+
+    for command in ["download-blocking", "download-async"] {
+        let dir = scratch_dir(&format!(
+            "resume_of_a_complete_file_says_so_and_leaves_it_{command}"
+        ));
+        std::fs::write(dir.join("file.bin"), &data).unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        // The server answers 416 to a range starting at the end.
+        assert!(!output.status.success(), "{command}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("File already complete"),
+            "{command}: {stderr}"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("file.bin")).unwrap(),
+            data,
+            "{command}"
+        );
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(last.header("Range"), Some("bytes=500000-"), "{command}");
+    }
+}
+
+#[test]
+fn a_body_without_a_length_is_read_until_the_connection_closes() {
+    let data = payload(300_001);
+    let server = TestServer::builder(data.clone()).no_length().start();
+    let modes: [&[&str]; 3] = [
+        &["download-blocking"],
+        &["download-async"],
+        &["download-async", "--workers", "4"],
+    ];
+
+    for mode in modes {
+        let dir = scratch_dir(&format!(
+            "a_body_without_a_length_is_read_until_the_connection_closes_{}",
+            mode.join("_")
+        ));
+        let url = server.url("/file.bin");
+        let mut args = vec!["-t", dir.to_str().unwrap(), &url];
+        args.extend(mode);
+
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+    }
+}
+
+#[test]
+fn worker_resume_splits_only_the_remaining_bytes() {
+    let data = payload(1_000_003);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("worker_resume_splits_only_the_remaining_bytes");
+    std::fs::write(dir.join("file.bin"), &data[..200_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let mut ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    ranges.sort();
+    assert_eq!(
+        ranges,
+        [
+            // A connection warmed up per worker.
+            "bytes=0-0",
+            "bytes=0-0",
+            "bytes=0-0",
+            "bytes=0-0",
+            // Checking the partial file is the start of this one.
+            "bytes=134464-199999",
+            "bytes=200000-399999",
+            "bytes=400000-599999",
+            "bytes=600000-799999",
+            "bytes=800000-1000002",
+        ]
+    );
+
+    // Bigger than the remote file: it can't be the start of it.
+    std::fs::write(dir.join("file.bin"), payload(1_000_004)).unwrap();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("more than the 1000003"), "{stderr}");
+}
+
+#[test]
+fn unresumable_download_restarts_when_asked() {
+    let data = payload(200_000);
+    let no_ranges = TestServer::builder(data.clone()).no_ranges().start();
+    let ranges = TestServer::builder(data.clone()).start();
+
+    for command in ["download-blocking", "download-async"] {
+        // A server ignoring ranges, and a local file bigger than the remote.
+        for (server, local, why) in [
+            (&no_ranges, &data[..50_000], "ignored the range request"),
+            (
+                &ranges,
+                &payload(250_000)[..],
+                "the remote file is 200000 bytes",
+            ),
+        ] {
+            let dir = scratch_dir(&format!("unresumable_download_restarts_{command}"));
+            std::fs::write(dir.join("file.bin"), local).unwrap();
+            let args = [
+                "-t",
+                dir.to_str().unwrap(),
+                "--resume",
+                &server.url("/file.bin"),
+                command,
+            ];
+
+            let output = run_dlm(&args);
+            assert!(!output.status.success());
+
+            let mut restart = args.to_vec();
+            restart.insert(0, "--restart-on-unresumable");
+            let output = run_dlm(&restart);
+            assert_downloaded(&output, &dir.join("file.bin"), &data);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(why), "{stderr}");
+            assert!(stderr.contains("starting over"), "{stderr}");
+        }
+    }
+}
+
+#[test]
+fn continue_at_cuts_the_local_file_and_appends() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+
+    for command in ["download-blocking", "download-async"] {
+        let dir = scratch_dir(&format!("continue_at_cuts_the_local_file_{command}"));
+        // A good prefix followed by a tail that can't be trusted.
+        let mut partial = data[..100_000].to_vec();
+        partial.extend(std::iter::repeat_n(0xAA, 50_000));
+        std::fs::write(dir.join("file.bin"), partial).unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--continue-at",
+            "100000",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(last.header("Range"), Some("bytes=100000-"));
+    }
+}
+
+#[test]
+fn continue_at_checks_the_offset() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("continue_at_checks_the_offset");
+    std::fs::write(dir.join("file.bin"), &data[..10_000]).unwrap();
+    let url = server.url("/file.bin");
+    let continue_at = |offset: &str, extra: &[&str]| {
+        let mut args = vec!["-t", dir.to_str().unwrap(), "--continue-at", offset];
+        args.extend(extra);
+        args.extend([url.as_str(), "download-async"]);
+        run_dlm(&args)
+    };
+
+    let output = continue_at("20000", &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--sparse-fill"));
+
+    let output = continue_at("60000", &["--sparse-fill"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("beyond the remote size (50000 bytes)"),
+        "{output:?}"
+    );
+    // Nothing is cut or filled when the server refuses.
+    assert_eq!(
+        std::fs::read(dir.join("file.bin")).unwrap(),
+        &data[..10_000]
+    );
+
+    let output = continue_at("-", &[]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+#[test]
+fn interrupt_stops_download() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data)
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("interrupt_stops_download");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(700));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("interrupted"));
+    assert!(printed_sha256(&output).is_none());
+}
+
+#[test]
+fn an_interrupted_worker_download_keeps_its_parts_for_resume() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("an_interrupted_worker_download_keeps_its_parts_for_resume");
+    let url = server.url("/file.bin");
+
+    // Carried on from as parts, whether --resume asks for them or not.
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--part-files",
+            &url,
+            "download-async",
+            "--workers",
+            "4",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1_000));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("/ 390.62 KiB, rerun with --resume to continue"),
+        "{stderr}"
+    );
+    let kept: u64 = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().contains(".p000"))
+        .map(|path| std::fs::metadata(path).unwrap().len())
+        .sum();
+    assert!(kept > 0);
+
+    let before = server.requests().len();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    // Only what the parts were missing was downloaded again.
+    let fetched: u64 = server.requests()[before..]
+        .iter()
+        .filter_map(|request| request.header("Range"))
+        .filter(|range| *range != "bytes=0-0")
+        .map(|range| {
+            let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+            end.parse::<u64>().unwrap() + 1 - start.parse::<u64>().unwrap()
+        })
+        .sum();
+    assert_eq!(fetched, data.len() as u64 - kept);
+}
+
+#[test]
+fn interrupted_hash_leaves_the_download_unverified() {
+    // Sparse, so it takes no space but a while to hash.
+    const SIZE: u64 = 8 << 30;
+    let dir = scratch_dir("interrupted_hash_leaves_the_download_unverified");
+    let path = dir.join("big.bin");
+    std::fs::File::create(&path)
+        .unwrap()
+        .set_len(SIZE - 1)
+        .unwrap();
+    // Only the last byte is missing, so the download itself is over at once.
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| {
+            let last = SIZE - 1;
+            Some(
+                Response::new(206, vec![0])
+                    .header("Content-Range", format!("bytes {last}-{last}/{SIZE}")),
+            )
+        })
+        .start();
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/big.bin"),
+            "download-async",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1_500));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("so it's unverified"));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), SIZE);
+}
+
+#[test]
+fn interrupt_with_nothing_to_wind_down_waits_for_a_second_one() {
+    // The dry run's preflight request hangs, so no download is running yet.
+    let server = TestServer::builder(payload(1_000))
+        .handler(|_, _| {
+            std::thread::sleep(Duration::from_secs(10));
+            None
+        })
+        .start();
+    let dir = scratch_dir("interrupt_with_nothing_to_wind_down_waits_for_a_second_one");
+
+    let mut child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--dry-run",
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let interrupt = |child: &std::process::Child| {
+        std::process::Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .unwrap()
+    };
+    std::thread::sleep(Duration::from_millis(500));
+    interrupt(&child);
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait().unwrap().is_none());
+    let sent = std::time::Instant::now();
+    interrupt(&child);
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130));
+    assert!(sent.elapsed() < Duration::from_secs(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("press Ctrl+C again to exit at once"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Interrupted again, exiting"), "{stderr}");
+}
+
+#[test]
+fn capped_range_responses_are_completed() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).max_range(7_000).start();
+    let dir = scratch_dir("capped_range_responses_are_completed");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    // The first chunk takes 25,000 bytes: its own request and three more.
+    assert!(
+        ranges.contains(&"bytes=21000-24999".to_string()),
+        "{ranges:?}"
+    );
+}
+
+#[test]
+fn dropped_connection_is_retried_after_resolving_again() {
+    let data = payload(200_000);
+    let dir = scratch_dir("dropped_connection_is_retried_after_resolving_again");
+
+    for mode in ["download-blocking", "download-async"] {
+        let server = TestServer::builder(data.clone())
+            .fail_after(70_000, 1)
+            .start();
+        let url = server.url("/file.bin").replace("127.0.0.1", "localhost");
+        let output = run_dlm(&["-vv", "-t", dir.to_str().unwrap(), "-o", &url, mode]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Connection lost"), "{stderr}");
+        // Had the retry gone by the cache, nothing would have been looked up.
+        assert!(
+            stderr.contains("Re-resolved localhost: [127.0.0.1] -> [127.0.0.1]"),
+            "{stderr}"
+        );
+        assert_eq!(server.requests()[1].header("Range"), Some("bytes=70000-"));
+
+        // As many times as --retries allows: a second drop is then the
+        // download's end.
+        let server = TestServer::builder(data.clone())
+            .fail_after(70_000, 2)
+            .start();
+        let url = server.url("/file.bin").replace("127.0.0.1", "localhost");
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--retries",
+            "1",
+            "-o",
+            &url,
+            mode,
+        ]);
+        assert!(!output.status.success(), "{output:?}");
+    }
+}
+
+#[test]
+fn stalled_stream_is_re_requested() {
+    let data = payload(100_000);
+
+    for command in ["download-blocking", "download-async"] {
+        let server = TestServer::builder(data.clone())
+            .stall_after(40_000, 1)
+            .start();
+        let dir = scratch_dir(&format!("stalled_stream_is_re_requested_{command}"));
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--stall-timeout",
+            "1",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("stalled"));
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(last.header("Range"), Some("bytes=40000-"));
+    }
+}
+
+#[test]
+fn read_timeout_re_requests_a_silent_stream_before_the_stall_check() {
+    let data = payload(100_000);
+
+    for (mode, streams) in [
+        (&["download-blocking"][..], 1),
+        (&["download-async"], 1),
+        (&["download-async", "--workers", "2"], 2),
+    ] {
+        let server = TestServer::builder(data.clone())
+            .stall_after(40_000, 1)
+            .start();
+        let dir = scratch_dir(&format!(
+            "read_timeout_re_requests_a_silent_stream_before_the_stall_check_{}",
+            mode.len()
+        ));
+        let started = std::time::Instant::now();
+        let url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--no-warm-up",
+            "--stall-timeout",
+            "60",
+            "--read-timeout",
+            "1",
+            &url,
+        ];
+        args.extend(mode);
+        let output = run_dlm(&args);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "{mode:?} waited for the stall check"
+        );
+        // The stream that went silent was asked for the rest once more.
+        let requests = server.requests();
+        let gets = requests
+            .iter()
+            .filter(|request| request.method == "GET")
+            .count();
+        assert_eq!(gets, streams + 1, "{mode:?}: {requests:?}");
+    }
+}
+
+#[test]
+fn slow_transfer_aborts_with_dedicated_exit_code() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data)
+        .drip(100, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("slow_transfer_aborts_with_dedicated_exit_code");
+
+    for command in ["download-blocking", "download-async"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--overwrite",
+            "--min-avg-speed",
+            "1M",
+            "--min-avg-window",
+            "1s",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_eq!(output.status.code(), Some(3), "{output:?}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("stayed below"));
+    }
+}
+
+#[test]
+fn invalid_speed_is_rejected_by_clap() {
+    let output = run_dlm(&[
+        "--min-avg-speed",
+        "fast",
+        "http://127.0.0.1:1/file.bin",
+        "download-async",
+    ]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not a size"));
+}
+
+#[test]
+fn zero_workers_is_rejected_by_clap() {
+    let output = run_dlm(&[
+        "http://127.0.0.1:1/file.bin",
+        "download-async",
+        "--workers",
+        "0",
+    ]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad worker count '0'"));
+}
+
+#[test]
+fn tail_downloads_only_the_last_bytes() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("tail_downloads_only_the_last_bytes");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--tail",
+        "64k",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(
+        &output,
+        &dir.join("file.bin.tail"),
+        &data[300_000 - 65_536..],
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("(300000 bytes)"));
+    let last = server.requests().last().cloned().unwrap();
+    assert_eq!(last.header("Range"), Some("bytes=-65536"));
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--tail",
+        "1M",
+        "--output",
+        "whole.bin",
+        &server.url("/file.bin"),
+        "download-blocking",
+    ]);
+
+    assert_downloaded(&output, &dir.join("whole.bin"), &data);
+}
+
+#[test]
+fn tail_refuses_servers_without_ranges() {
+    let server = TestServer::builder(payload(300_000)).no_ranges().start();
+    let dir = scratch_dir("tail_refuses_servers_without_ranges");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--tail",
+        "100",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ignored the suffix range"));
+    assert!(std::fs::metadata(dir.join("file.bin.tail")).is_err());
+}
+
+#[test]
+fn very_verbose_dumps_headers_and_redacts_secrets() {
+    let data = payload(50_000);
+    let body = data.clone();
+    let server = TestServer::builder(data.clone())
+        .handler(move |_, _| {
+            Some(Response::new(200, body.clone()).header("Set-Cookie", "session=hunter2"))
+        })
+        .start();
+    let dir = scratch_dir("very_verbose_dumps_headers_and_redacts_secrets");
+    let log_file = dir.join("dlm.log");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-vv",
+        "--log-file",
+        log_file.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("> GET /file.bin HTTP/1.1"), "{stderr}");
+    assert!(stderr.contains("< HTTP/1.1 200 OK"));
+    assert!(stderr.contains("< set-cookie: <redacted>"));
+    assert!(!stderr.contains("hunter2"));
+    let log = std::fs::read_to_string(&log_file).unwrap();
+    assert!(log.contains("< HTTP/1.1 200 OK"));
+
+    std::fs::remove_file(dir.join("file.bin")).unwrap();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-vv",
+        "--show-secrets",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("< set-cookie: session=hunter2"));
+}
+
+#[test]
+fn chunk_log_records_lifecycle_and_reports() {
+    let data = payload(400_000);
+    // Worker mode probes the content length with HEADs, and without a
+    // warm-up the stall hits a chunk.
+    let server = TestServer::builder(data.clone())
+        .stall_after(50_000, 1)
+        .start();
+    let dir = scratch_dir("chunk_log_records_lifecycle_and_reports");
+    let log = dir.join("chunks.jsonl");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--stall-timeout",
+        "1",
+        "--no-warm-up",
+        "--chunk-log",
+        log.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    let events: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let count = |event: &str| events.iter().filter(|e| e["event"] == event).count();
+    assert_eq!(count("scheduled"), 4);
+    assert_eq!(count("started"), 4);
+    assert_eq!(count("completed"), 4);
+    let completed = events.iter().find(|e| e["event"] == "completed").unwrap();
+    assert!(completed["network_ms"].is_u64() && completed["disk_ms"].is_u64());
+    assert_eq!(count("retry"), 1);
+    assert_eq!(count("first_byte"), 4);
+    assert!(count("bytes") >= 4);
+    let scheduled = events.iter().find(|e| e["event"] == "scheduled").unwrap();
+    assert_eq!(scheduled["start"], 0);
+    assert_eq!(scheduled["end"], 99_999);
+
+    let output = run_dlm(&["report", log.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("chunks: 4 (4 completed, 0 failed), retries: 1"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Slowest chunks:"));
+    assert!(stdout.contains("Time to first byte: min "), "{stdout}");
+    assert!(stdout.contains("over 4 chunks"), "{stdout}");
+    assert!(stdout.contains("Time split, over all chunks: "), "{stdout}");
+    assert!(stdout.contains("Transfer stalled"));
+}
+
+#[test]
+fn slow_network_is_reported_as_network_bound() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("slow_network_is_reported_as_network_bound");
+
+    for mode in ["download-blocking", "download-async"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "-o",
+            &server.url("/file.bin"),
+            mode,
+        ]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let split = stdout
+            .lines()
+            .find(|line| line.starts_with("Time split: "))
+            .unwrap_or_else(|| panic!("no time split in {stdout}"));
+        assert!(split.ends_with("(network-bound)"), "{split}");
+    }
+}
+
+#[test]
+fn time_to_first_byte_is_reported_apart_from_transfer() {
+    let data = payload(10_000);
+    let body = data.clone();
+    let server = TestServer::builder(data.clone())
+        .handler(move |_, _| {
+            std::thread::sleep(Duration::from_millis(300));
+            Some(Response::new(200, body.clone()))
+        })
+        .start();
+    let dir = scratch_dir("time_to_first_byte_is_reported_apart_from_transfer");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ttfb: u64 = stdout
+        .split("first byte after ")
+        .nth(1)
+        .and_then(|rest| rest.split("ms").next())
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or_else(|| panic!("no time to first byte in {stdout}"));
+    assert!(ttfb >= 300, "{stdout}");
+}
+
+#[test]
+fn stats_and_write_out_report_time_to_first_byte() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|_, _| {
+            std::thread::sleep(Duration::from_millis(200));
+            None
+        })
+        .start();
+    let dir = scratch_dir("stats_and_write_out_report_time_to_first_byte");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--stats",
+        "--write-out",
+        r"%{size_download} %{time_starttransfer} %{ttfb_min_ms}\n%{json}\n",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "Stats:\n",
+        "  First byte:        ",
+        "  First byte spread: min ",
+        " after the first byte\n",
+    ] {
+        assert!(stdout.contains(line), "no {line:?} in {stdout}");
+    }
+    let mut lines = stdout.lines().rev();
+    let json: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+    let written: Vec<&str> = lines.next().unwrap().split(' ').collect();
+    assert_eq!(written[0], "100000");
+    assert!(written[1].parse::<f64>().unwrap() >= 0.2, "{stdout}");
+    assert!(written[2].parse::<u64>().unwrap() >= 200, "{stdout}");
+    assert_eq!(json["size_download"], 100_000);
+    assert_eq!(json["ttfb_spread"]["chunks"], 4);
+    assert!(json["speed_download"].as_u64().unwrap() > 0);
+
+    let output = run_dlm(&["--write-out", "%{bogus}", &server.url("/file.bin")]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown variable 'bogus'"));
+}
+
+#[cfg(feature = "otel")]
+#[test]
+fn otel_endpoint_receives_download_spans() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| (request.method == "POST").then(|| Response::new(200, Vec::new())))
+        .start();
+    let dir = scratch_dir("otel_endpoint_receives_download_spans");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--otel-endpoint",
+        &server.url("/"),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let export = server
+        .requests()
+        .into_iter()
+        .find(|request| request.method == "POST")
+        .expect("spans were exported");
+    assert_eq!(export.path, "/v1/traces");
+    assert_eq!(
+        export.header("Content-Type"),
+        Some("application/x-protobuf")
+    );
+}
+
+#[test]
+fn corrupt_piece_is_downloaded_again() {
+    let data = payload(300_000);
+    let corrupted = AtomicBool::new(false);
+    let body = data.clone();
+    // The first chunk arrives with a flipped byte in its second piece, once.
+    let server = TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            let first_chunk = request.header("Range") == Some("bytes=0-196607");
+            if !first_chunk || corrupted.swap(true, Ordering::SeqCst) {
+                return None;
+            }
+            let mut body = body[..196_608].to_vec();
+            body[70_000] ^= 0xff;
+            Some(
+                Response::new(206, body)
+                    .header("Content-Range", "bytes 0-196607/300000")
+                    .header("Accept-Ranges", "bytes"),
+            )
+        })
+        .start();
+    let dir = scratch_dir("corrupt_piece_is_downloaded_again");
+    let hashes = dir.join("pieces.txt");
+    let mut list = String::from("64K\n");
+    for piece in data.chunks(65_536) {
+        list.push_str(&sha256_hex(piece));
+        list.push('\n');
+    }
+    std::fs::write(&hashes, list).unwrap();
+    let target = dir.join("target");
+    let log = dir.join("chunks.jsonl");
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--chunk-log",
+        log.to_str().unwrap(),
+        "--piece-hashes",
+        hashes.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+
+    assert_downloaded(&output, &target.join("file.bin"), &data);
+    assert!(
+        server
+            .requests()
+            .iter()
+            .any(|request| request.header("Range") == Some("bytes=65536-131071"))
+    );
+
+    let output = run_dlm(&["report", log.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Pieces re-downloaded after a hash mismatch: 1"),
+        "{stdout}"
+    );
+    assert!(stdout.contains(&format!(
+        "piece hash mismatch from {}",
+        server.url("/file.bin")
+    )));
+}
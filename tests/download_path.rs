@@ -0,0 +1,43 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::utils::build_download_path;
+use std::path::Path;
+use url::Url;
+
+#[test]
+fn the_query_is_not_part_of_the_name() {
+    let dir = Path::new("/downloads");
+    let cases = [
+        (
+            "https://example.com/files/report.pdf?token=abc",
+            "report.pdf",
+        ),
+        ("https://example.com/download?id=12345", "download"),
+        ("https://example.com/get.php?file=a%2Fb.zip", "get.php"),
+        ("https://example.com/a%20b.txt?x=1#top", "a b.txt"),
+        // Nothing but a query leaves no name at all.
+        ("https://example.com/?file=report.pdf", "tmp.bin"),
+        ("https://example.com?file=report.pdf", "tmp.bin"),
+        ("https://example.com/dir/?page=2", "tmp.bin"),
+    ];
+    for (url, name) in cases {
+        let url = Url::parse(url).unwrap();
+        assert_eq!(build_download_path(&url, dir), dir.join(name), "{url}");
+    }
+}
+
+#[test]
+fn a_query_only_url_downloads_to_tmp_bin() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_query_only_url_downloads_to_tmp_bin");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/?file=report.pdf"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("tmp.bin"), &data);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{TestServer, payload, run_dlm, scratch_dir};
+
+#[test]
+fn dry_run_prints_the_chunk_layout_without_writing() {
+    let server = TestServer::builder(payload(300_000)).start();
+    let dir = scratch_dir("dry_run_prints_the_chunk_layout_without_writing");
+    let target = dir.join("target");
+    let chunk_log = dir.join("chunks.jsonl");
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--dry-run",
+        "--chunk-log",
+        chunk_log.to_str().unwrap(),
+        "--part-files",
+        &server.url("/redirect/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Redirected:  http://"), "{stdout}");
+    assert!(stdout.contains("Action:      create"), "{stdout}");
+    assert!(stdout.contains("(300000 bytes)"), "{stdout}");
+    for part in [".p0000", ".p0002"] {
+        assert!(stdout.contains(part), "{stdout}");
+    }
+    assert!(!target.exists());
+    assert!(!chunk_log.exists());
+    assert!(
+        server
+            .requests()
+            .iter()
+            .all(|request| request.method == "HEAD")
+    );
+}
+
+#[test]
+fn dry_run_json_plans_a_resume() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("dry_run_json_plans_a_resume");
+    let path = dir.join("file.bin");
+    std::fs::write(&path, &data[..40_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        "--dry-run",
+        "--json",
+        &server.url("/file.bin"),
+        "download-blocking",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["action"]["kind"], "resume");
+    assert_eq!(plan["action"]["from"], 40_000);
+    assert_eq!(plan["disk_usage"], 60_000);
+    assert_eq!(plan["segments"][0]["start"], 40_000);
+    assert_eq!(plan["segments"][0]["end"], 99_999);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 40_000);
+}
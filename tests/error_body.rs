@@ -0,0 +1,97 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+
+const EXPIRED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Request has expired</Message><Expires>2026-01-01T00:00:00Z</Expires></Error>"#;
+
+fn expired_server() -> TestServer {
+    TestServer::builder(payload(1_000))
+        .handler(|_, _| Some(Response::new(403, EXPIRED).header("Content-Type", "application/xml")))
+        .start()
+}
+
+#[test]
+fn s3_error_message_is_shown() {
+    let server = expired_server();
+    let dir = scratch_dir("s3_error_message_is_shown");
+
+    for mode in [
+        &["download-async"][..],
+        &["download-async", "--workers", "4"],
+        &["download-blocking"],
+    ] {
+        let mut args = vec!["-t", dir.to_str().unwrap(), "-o"];
+        let url = server.url("/bucket/key.tar");
+        args.push(&url);
+        args.extend(mode);
+        let output = run_dlm(&args);
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Unexpected status: 403 Forbidden (AccessDenied: Request has expired)"),
+            "{mode:?}: {stderr}"
+        );
+    }
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-o",
+        "--show-error-body=false",
+        &server.url("/bucket/key.tar"),
+        "download-async",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unexpected status: 403 Forbidden"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("AccessDenied"), "{stderr}");
+}
+
+#[test]
+fn chunk_errors_show_json_fields_but_not_binary_bodies() {
+    let server = TestServer::builder(payload(200_000))
+        .handler(|request, _| match request.header("Range") {
+            Some("bytes=100000-199999") => Some(
+                Response::new(503, r#"{"error":{"code":503,"message":"Backend busy"}}"#)
+                    .header("Content-Type", "application/json"),
+            ),
+            Some(_) => None,
+            None if request.path == "/binary" => {
+                Some(Response::new(500, vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0x00]))
+            }
+            None => None,
+        })
+        .start();
+    let dir = scratch_dir("chunk_errors_show_json_fields_but_not_binary_bodies");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("503 Service Unavailable (503: Backend busy)"),
+        "{stderr}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/binary"),
+        "download-async",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Server error: 500 Internal Server Error, still failing"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::fs;
+
+#[test]
+fn failure_writes_a_report() {
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| {
+            Some(
+                Response::new(503, "down for maintenance")
+                    .header("Retry-After", "0")
+                    .header("Set-Cookie", "session=secret"),
+            )
+        })
+        .start();
+    let dir = scratch_dir("failure_writes_a_report");
+    let report = dir.join("report.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--error-report",
+        report.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let json: Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(json["exit_code"], 8);
+    assert_eq!(json["cancelled"], false);
+    assert!(
+        json["errors"][0]
+            .as_str()
+            .unwrap()
+            .contains("503 Service Unavailable"),
+        "{json}"
+    );
+    assert_eq!(json["url"], server.url("/file.bin"));
+    assert_eq!(json["destination"], dir.join("file.bin").to_str().unwrap());
+    assert_eq!(json["last_response"]["status"], 503);
+    let headers = json["last_response"]["headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!(["set-cookie", "<redacted>"])));
+    assert_eq!(json["retries"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        json["retries"][0]["reason"],
+        "server answered 503 Service Unavailable"
+    );
+    assert_eq!(json["environment"]["version"], env!("CARGO_PKG_VERSION"));
+
+    let output = run_dlm(&["report", report.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Download failed, exit code 8"), "{stdout}");
+    assert!(stdout.contains("Last response: 503"), "{stdout}");
+}
+
+#[test]
+fn success_writes_no_report() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("success_writes_no_report");
+    let report = dir.join("report.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--error-report",
+        report.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!report.exists());
+}
+
+#[test]
+fn an_unwritable_report_leaves_the_error_alone() {
+    let dir = scratch_dir("an_unwritable_report_leaves_the_error_alone");
+    let report = dir.join("missing").join("report.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--error-report",
+        report.to_str().unwrap(),
+        "s3://bucket/key",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unsupported URL scheme 's3'"), "{stderr}");
+    assert!(stderr.contains("Cannot write the error report"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn cancelling_writes_a_cancelled_report() {
+    use std::time::{Duration, Instant};
+
+    let server = TestServer::builder(payload(200_000))
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("cancelling_writes_a_cancelled_report");
+    let socket = dir.join("dlm.sock");
+    let report = dir.join("report.json");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--control-socket",
+            socket.to_str().unwrap(),
+            "--error-report",
+            report.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "2",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let ctl = |request: &str| {
+        run_dlm(&["ctl", "--socket", socket.to_str().unwrap(), request])
+            .status
+            .success()
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !ctl("status") {
+        assert!(Instant::now() < deadline, "control socket never answered");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert!(ctl("cancel"));
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+
+    let json: Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(json["cancelled"], true, "{json}");
+    assert_eq!(json["total"], 200_000);
+    assert!(json["chunks"].is_object(), "{json}");
+}
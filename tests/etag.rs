@@ -0,0 +1,85 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::fs;
+
+/// A server whose ETag is `first` for the start of the file, and `rest`
+/// for ranges further in, as if it was replaced between the chunks.
+fn changing_server(data: Vec<u8>, first: &'static str, rest: &'static str) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            let total = data.len();
+            let Some(range) = request.header("Range") else {
+                return Some(
+                    Response::new(200, data.clone())
+                        .header("Accept-Ranges", "bytes")
+                        .header("ETag", first),
+                );
+            };
+            let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+            let start: usize = start.parse().ok()?;
+            let end = end.parse::<usize>().ok()?.min(total - 1);
+            let etag = if start == 0 { first } else { rest };
+            Some(
+                Response::new(206, data[start..=end].to_vec())
+                    .header("Content-Range", format!("bytes {start}-{end}/{total}"))
+                    .header("ETag", etag),
+            )
+        })
+        .start()
+}
+
+#[test]
+fn a_strong_etag_changing_between_chunks_fails_the_download() {
+    let server = changing_server(payload(400_000), "\"v1\"", "\"v2\"");
+    let dir = scratch_dir("a_strong_etag_changing_between_chunks_fails_the_download");
+    let report = dir.join("report.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--error-report",
+        report.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("the server's ETag changed from \"v1\" to \"v2\" during the download"),
+        "{output:?}"
+    );
+    assert!(!dir.join("file.bin").exists());
+
+    let json: Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    let mismatches = json["etag_mismatches"].as_array().unwrap();
+    assert!(!mismatches.is_empty(), "{json}");
+    assert_eq!(mismatches[0]["expected"], "\"v1\"");
+    assert_eq!(mismatches[0]["actual"], "\"v2\"");
+    assert_eq!(mismatches[0]["weak"], false);
+}
+
+#[test]
+fn a_weak_etag_changing_is_only_warned_about() {
+    let data = payload(400_000);
+    let server = changing_server(data.clone(), "W/\"v1\"", "W/\"v2\"");
+    let dir = scratch_dir("a_weak_etag_changing_is_only_warned_about");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("the weak ETag changed from W/\"v1\" to W/\"v2\""),
+        "{output:?}"
+    );
+}
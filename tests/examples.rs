@@ -0,0 +1,78 @@
+mod common;
+
+use common::{TestServer, payload, scratch_dir, sha256_hex};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Runs a built example. `cargo test` builds every example before the
+/// tests, into `examples/` next to the test binaries' `deps/`, so one
+/// that stopped compiling fails the run.
+fn run_example(name: &str, args: &[&str]) -> Output {
+    let deps = std::env::current_exe().unwrap();
+    let path: PathBuf = deps
+        .parent()
+        .and_then(|deps| deps.parent())
+        .unwrap()
+        .join("examples")
+        .join(name)
+        .with_extension(std::env::consts::EXE_EXTENSION);
+    Command::new(&path)
+        .args(args)
+        .output()
+        .unwrap_or_else(|error| panic!("run {}: {error}", path.display()))
+}
+
+#[test]
+fn simple_prints_the_result() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("simple_prints_the_result");
+
+    let output = run_example("simple", &[&server.url("/file.bin"), dir.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("DownloadResult {"), "{stdout}");
+    assert!(stdout.contains("size: 100000"), "{stdout}");
+    assert!(
+        stdout.contains(&format!("sha256: {}", sha256_hex(&data))),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn custom_progress_draws_its_own_line() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("custom_progress_draws_its_own_line");
+
+    let output = run_example(
+        "custom_progress",
+        &[&server.url("/file.bin"), dir.to_str().unwrap()],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("100000 bytes of 100000 (100%)"), "{stdout}");
+    assert!(stdout.contains("Saved to "), "{stdout}");
+}
+
+#[test]
+fn pool_downloads_every_url() {
+    let first = payload(100_000);
+    let second = payload(50_000);
+    let a = TestServer::builder(first.clone()).start();
+    let b = TestServer::builder(second.clone()).start();
+    let dir = scratch_dir("pool_downloads_every_url");
+
+    let output = run_example(
+        "pool",
+        &[dir.to_str().unwrap(), &a.url("/a.bin"), &b.url("/b.bin")],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("a.bin")).unwrap(), first);
+    assert_eq!(std::fs::read(dir.join("b.bin")).unwrap(), second);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("150000 bytes of 150000 (100%)"), "{stdout}");
+    assert!(stdout.contains("2 done"), "{stdout}");
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+
+const MODES: [&[&str]; 3] = [
+    &["download-blocking"],
+    &["download-async"],
+    &["download-async", "--workers", "3"],
+];
+
+#[test]
+fn a_matching_size_downloads_in_every_mode() {
+    let data = payload(120_000);
+    let server = TestServer::builder(data.clone()).start();
+    for (index, mode) in MODES.iter().enumerate() {
+        let dir = scratch_dir(&format!("a_matching_size_downloads_in_every_mode_{index}"));
+        let url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--expected-size",
+            "120000",
+            &url,
+        ];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+    }
+}
+
+#[test]
+fn a_content_length_that_differs_stops_every_mode_before_the_transfer() {
+    let server = TestServer::builder(payload(120_000)).start();
+    for (index, mode) in MODES.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "a_content_length_that_differs_stops_every_mode_before_the_transfer_{index}"
+        ));
+        let url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--expected-size",
+            "100000",
+            &url,
+        ];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert_eq!(output.status.code(), Some(11), "{mode:?}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("is 120000 bytes, but --expected-size is 100000"),
+            "{mode:?}: {stderr}"
+        );
+        assert!(!dir.join("file.bin").exists(), "{mode:?}");
+    }
+    // Worker mode got no further than its probe.
+    assert!(
+        server
+            .requests()
+            .iter()
+            .filter_map(|request| request.header("Range"))
+            .all(|range| range == "bytes=0-0")
+    );
+}
+
+#[test]
+fn a_partial_file_past_the_size_is_refused_before_the_transfer() {
+    let server = TestServer::builder(payload(100_000)).start();
+    let dir = scratch_dir("a_partial_file_past_the_size_is_refused_before_the_transfer");
+    std::fs::write(dir.join("file.bin"), vec![0; 110_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        "--expected-size",
+        "100000",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(11), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("to resume is 110000 bytes"),
+        "{output:?}"
+    );
+    let requests = server.requests();
+    assert!(
+        requests.iter().all(|request| request.method == "HEAD"),
+        "{requests:?}"
+    );
+    assert_eq!(
+        std::fs::metadata(dir.join("file.bin")).unwrap().len(),
+        110_000
+    );
+}
+
+#[test]
+fn the_error_report_and_json_progress_record_expected_and_actual() {
+    let server = TestServer::builder(payload(120_000)).start();
+    let dir = scratch_dir("the_error_report_and_json_progress_record_expected_and_actual");
+    let report = dir.join("report.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--expected-size",
+        "100000",
+        "--error-report",
+        report.to_str().unwrap(),
+        "--progress",
+        "json",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(11), "{output:?}");
+    let expected = serde_json::json!({
+        "check": "announced",
+        "expected": 100_000,
+        "actual": 120_000,
+    });
+    let report: Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["size_mismatch"], expected);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error: Value =
+        serde_json::from_str(stderr.lines().rfind(|line| line.starts_with('{')).unwrap()).unwrap();
+    assert_eq!(error["event"], "error");
+    assert_eq!(error["size_mismatch"], expected);
+}
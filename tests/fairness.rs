@@ -0,0 +1,109 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::fairness::Balancer;
+use download_manager::__private::throttle::Throttle;
+use std::time::{Duration, Instant};
+
+#[test]
+fn a_hog_is_capped_while_another_starves() {
+    let mut balancer = Balancer::default();
+    // 3.6 MB/s over three chunks is 1.2 MB/s each; chunk 0 takes over twice
+    // that while chunks 1 and 2 get under half.
+    let caps = balancer.rebalance(&[Some(3_000_000), Some(500_000), Some(100_000), None], None);
+    let capped = Some(2_400_000);
+    assert_eq!(caps, [capped, capped, capped, None]);
+
+    // Still starving; the share stays what they managed uncapped.
+    let caps = balancer.rebalance(&[Some(2_400_000), Some(900_000), Some(300_000), None], None);
+    assert_eq!(caps, [capped, capped, capped, None]);
+
+    // Caught up.
+    let caps = balancer.rebalance(
+        &[Some(1_500_000), Some(1_200_000), Some(900_000), None],
+        None,
+    );
+    assert_eq!(caps, [None; 4]);
+}
+
+#[test]
+fn nothing_is_capped_without_starving_or_hogging() {
+    let mut balancer = Balancer::default();
+    // Uneven, but no chunk under half its share.
+    assert_eq!(
+        balancer.rebalance(&[Some(2_000_000), Some(700_000), Some(600_000)], None),
+        [None; 3]
+    );
+    // Starving, but nothing over twice its share.
+    assert_eq!(
+        balancer.rebalance(&[Some(1_000_000), Some(1_000_000), Some(100_000)], None),
+        [None; 3]
+    );
+    // A single chunk has no one to share with.
+    assert_eq!(
+        balancer.rebalance(&[Some(5_000_000), None, None], None),
+        [None; 3]
+    );
+}
+
+#[test]
+fn the_rate_limit_is_the_budget() {
+    let mut balancer = Balancer::default();
+    // 3 MB/s is 1 MB/s a chunk, even though they only reach 2.3 MB/s.
+    let caps = balancer.rebalance(
+        &[Some(2_100_000), Some(100_000), Some(100_000)],
+        Some(3_000_000),
+    );
+    assert_eq!(caps, [Some(2_000_000); 3]);
+}
+
+#[test]
+fn caps_that_slow_the_download_are_given_up() {
+    let mut balancer = Balancer::default();
+    let speeds = [Some(3_000_000), Some(500_000), Some(100_000)];
+    assert_eq!(balancer.rebalance(&speeds, None), [Some(2_400_000); 3]);
+
+    // Capped, the chunks only manage 2.6 MB/s together.
+    let caps = balancer.rebalance(&[Some(2_200_000), Some(300_000), Some(100_000)], None);
+    assert_eq!(caps, [None; 3]);
+    // And aren't capped again.
+    assert_eq!(balancer.rebalance(&speeds, None), [None; 3]);
+}
+
+#[test]
+fn a_chunk_cap_holds_back_only_its_chunk() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let throttle = Throttle::new(None);
+    throttle.cap_chunks(&[None, Some(100_000)]);
+    assert_eq!(throttle.chunk_caps(), [None, Some(100_000)]);
+
+    runtime.block_on(async {
+        let start = Instant::now();
+        throttle.take_chunk(0, 50_000).await;
+        throttle.take_chunk(5, 50_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        throttle.take_chunk(1, 20_000).await;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    });
+
+    throttle.cap_chunks(&[]);
+    assert!(throttle.chunk_caps().is_empty());
+}
+
+#[test]
+fn fair_workers_download() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("fair_workers_download");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--fair-workers",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
@@ -0,0 +1,67 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, scratch_dir};
+use nix::sys::resource::{Resource, getrlimit, setrlimit};
+use std::os::unix::process::CommandExt;
+
+#[test]
+fn workers_fit_in_a_lowered_open_file_limit() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("workers_fit_in_a_lowered_open_file_limit");
+
+    let mut command = common::dlm();
+    command.args([
+        "-t",
+        dir.to_str().unwrap(),
+        "-o",
+        "--min-split-size",
+        "0",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "250",
+    ]);
+    // SAFETY: setrlimit is async-signal-safe.
+    unsafe {
+        command.pre_exec(|| {
+            let (_, hard) = getrlimit(Resource::RLIMIT_NOFILE)?;
+            setrlimit(Resource::RLIMIT_NOFILE, 128, hard)?;
+            Ok(())
+        });
+    }
+    let output = command.output().unwrap();
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("only leaves room for 48 workers, using them instead of 250"),
+        "{stderr}"
+    );
+    // Two probes for the length, eight connections warmed up, then one
+    // request per worker.
+    assert_eq!(server.requests().len(), 58);
+
+    // With room to spare, every worker runs.
+    let requests = server.requests().len();
+    let output = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "-o",
+            "--min-split-size",
+            "0",
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "250",
+        ])
+        .output()
+        .unwrap();
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    if getrlimit(Resource::RLIMIT_NOFILE).unwrap().0 >= 532 {
+        assert_eq!(server.requests().len() - requests, 260);
+    }
+}
@@ -0,0 +1,202 @@
+#![cfg(feature = "ffi")]
+
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+use download_manager::ffi::{dm_cancel, dm_download};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+thread_local! {
+    /// The events the callback got on this test's thread.
+    static EVENTS: RefCell<Vec<Value>> = const { RefCell::new(Vec::new()) };
+}
+
+extern "C" fn record(event: *const c_char) {
+    let event = unsafe { CStr::from_ptr(event) }.to_str().unwrap();
+    EVENTS.with_borrow_mut(|events| events.push(serde_json::from_str(event).unwrap()));
+}
+
+fn download(config: &str, callback: extern "C" fn(*const c_char)) -> (i32, Vec<Value>) {
+    EVENTS.with_borrow_mut(Vec::clear);
+    let config = CString::new(config).unwrap();
+    let code = unsafe { dm_download(config.as_ptr(), Some(callback), std::ptr::null_mut()) };
+    (code, EVENTS.with_borrow_mut(std::mem::take))
+}
+
+fn kinds(events: &[Value]) -> Vec<&str> {
+    let mut kinds: Vec<_> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    kinds.dedup();
+    kinds
+}
+
+#[test]
+fn downloads_what_a_dry_run_planned() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("ffi_downloads_what_a_dry_run_planned");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--dry-run",
+        "--json",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let (code, events) = download(&String::from_utf8_lossy(&output.stdout), record);
+    assert_eq!(code, 0, "{events:?}");
+    assert_eq!(kinds(&events), ["started", "progress", "finished"]);
+    let progress = &events[events.len() - 2];
+    assert_eq!(progress["downloaded"], 300_000);
+    assert_eq!(progress["chunks"]["completed"], 3);
+    let path = events.last().unwrap()["path"].as_str().unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), data);
+    // Over, so there's nothing to cancel.
+    assert_eq!(dm_cancel(events[0]["handle"].as_u64().unwrap()), 1);
+}
+
+#[test]
+fn a_plan_runs_as_many_workers_as_it_says_not_segments() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("ffi_a_plan_runs_as_many_workers_as_it_says_not_segments");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--dry-run",
+        "--json",
+        "--segment-size",
+        "50K",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let plan: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["workers"], 2, "{plan}");
+    assert!(plan["segments"].as_array().unwrap().len() > 2, "{plan}");
+
+    let (code, events) = download(&plan.to_string(), record);
+    assert_eq!(code, 0, "{events:?}");
+    let progress = &events[events.len() - 2];
+    assert_eq!(
+        progress["chunk_map"].as_array().unwrap().len(),
+        2,
+        "{progress}"
+    );
+    let path = events.last().unwrap()["path"].as_str().unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), data);
+}
+
+#[test]
+fn failures_answer_with_the_cli_exit_codes() {
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| Some(Response::new(401, "Unauthorized")))
+        .start();
+    let dir = scratch_dir("ffi_failures_answer_with_the_cli_exit_codes");
+    let config = json!({
+        "url": server.url("/file.bin"),
+        "destination": dir.join("file.bin"),
+    });
+
+    let (code, events) = download(&config.to_string(), record);
+    assert_eq!(code, 7);
+    let failed = events.last().unwrap();
+    assert_eq!(failed["event"], "failed");
+    assert_eq!(failed["code"], 7);
+
+    let (code, events) = download("{\"destination\": 1}", record);
+    assert_eq!(code, 2);
+    assert_eq!(kinds(&events), ["failed"]);
+    let error = events[0]["error"].as_str().unwrap();
+    assert!(error.contains("Invalid config"), "{error}");
+}
+
+#[test]
+fn skipping_plans_download_nothing() {
+    let config = json!({
+        "url": "http://127.0.0.1:9/file.bin",
+        "action": { "kind": "skip", "reason": "already complete" },
+    });
+    let (code, events) = download(&config.to_string(), record);
+    assert_eq!(code, 0);
+    assert_eq!(
+        events,
+        [json!({"event": "skipped", "reason": "already complete"})]
+    );
+}
+
+#[test]
+fn cancelling_from_another_thread_stops_the_download() {
+    let server = TestServer::builder(payload(1_000_000))
+        .drip(4096, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("ffi_cancelling_from_another_thread_stops_the_download");
+    let config = json!({
+        "url": server.url("/file.bin"),
+        "destination": dir.join("file.bin"),
+    });
+    let config = CString::new(config.to_string()).unwrap();
+    let handle = AtomicU64::new(0);
+
+    EVENTS.with_borrow_mut(Vec::clear);
+    let (code, cancelled) = thread::scope(|scope| {
+        // The handle is there before the download blocks this thread.
+        let canceller = scope.spawn(|| {
+            while handle.load(Ordering::SeqCst) == 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+            thread::sleep(Duration::from_millis(100));
+            dm_cancel(handle.load(Ordering::SeqCst))
+        });
+        let code = unsafe { dm_download(config.as_ptr(), Some(record), handle.as_ptr()) };
+        (code, canceller.join().unwrap())
+    });
+    let events = EVENTS.with_borrow_mut(std::mem::take);
+    assert_eq!(cancelled, 0);
+    assert_eq!(code, 130);
+    let handle = handle.into_inner();
+    assert_eq!(events[0], json!({"event": "started", "handle": handle}));
+    let failed = events.last().unwrap();
+    assert_eq!(failed["event"], "failed");
+    assert!(
+        failed["error"].as_str().unwrap().contains("interrupted"),
+        "{failed}"
+    );
+    // Taken off the running downloads.
+    assert_eq!(dm_cancel(handle), 1);
+}
+
+#[cfg(feature = "test-hooks")]
+#[test]
+fn a_panicked_download_answers_101_and_is_let_go() {
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| Some(Response::new(200, "").header("X-Test-Panic", "mid-download")))
+        .start();
+    let dir = scratch_dir("ffi_a_panicked_download_answers_101_and_is_let_go");
+    let config = json!({
+        "url": server.url("/file.bin"),
+        "destination": dir.join("file.bin"),
+    });
+
+    let (code, events) = download(&config.to_string(), record);
+    assert_eq!(code, 101);
+    assert_eq!(
+        events.last().unwrap(),
+        &json!({"event": "failed", "error": "The download panicked", "code": 101})
+    );
+    // Taken off the running downloads all the same.
+    assert_eq!(dm_cancel(events[0]["handle"].as_u64().unwrap()), 1);
+}
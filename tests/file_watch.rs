@@ -0,0 +1,79 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{TestServer, payload, scratch_dir};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Downloads a slow file into `dir` with `mode`, doing `meddle` to it once
+/// some of it was written.
+fn meddle_with(dir: &Path, mode: &str, meddle: impl FnOnce(&Path)) -> Output {
+    let server = TestServer::builder(payload(300_000))
+        .drip(5_000, Duration::from_millis(50))
+        .start();
+    let child = common::dlm()
+        .args(["-t", dir.to_str().unwrap(), &server.url("/file.bin"), mode])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let file = dir.join("file.bin.part");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while std::fs::metadata(&file).map_or(0, |metadata| metadata.len()) < 20_000 {
+        assert!(Instant::now() < deadline, "nothing written");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    meddle(&file);
+    child.wait_with_output().unwrap()
+}
+
+fn assert_failed_with(output: &Output, message: &str) {
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(message),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn a_file_deleted_mid_download_fails_it() {
+    let dir = scratch_dir("file_watch_deleted");
+    let output = meddle_with(&dir, "download-async", |file| {
+        std::fs::remove_file(file).unwrap();
+    });
+    assert_failed_with(
+        &output,
+        "file.bin.part' was deleted by something else while it was being downloaded",
+    );
+}
+
+#[test]
+fn a_file_truncated_mid_download_fails_it() {
+    let dir = scratch_dir("file_watch_truncated");
+    let output = meddle_with(&dir, "download-blocking", |file| {
+        OpenOptions::new()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+    });
+    assert_failed_with(
+        &output,
+        "file.bin.part' changed underneath the download: it's ",
+    );
+}
+
+#[test]
+fn a_file_replaced_mid_download_fails_it() {
+    let dir = scratch_dir("file_watch_replaced");
+    let output = meddle_with(&dir, "download-async", |file| {
+        let other = file.with_extension("other");
+        std::fs::write(&other, payload(300_000)).unwrap();
+        std::fs::rename(&other, file).unwrap();
+    });
+    assert_failed_with(&output, "file.bin.part' was replaced by another file");
+}
@@ -0,0 +1,52 @@
+use download_manager::__private::filesystem::{self, Filesystem};
+use std::path::Path;
+
+const FAT_LIMIT: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+#[test]
+fn only_fat_stops_short_of_any_download() {
+    assert_eq!(Filesystem::Fat.max_file_size(), Some(FAT_LIMIT));
+    for filesystem in [
+        Filesystem::ExFat,
+        Filesystem::Ntfs,
+        Filesystem::Ext4,
+        Filesystem::Btrfs,
+        Filesystem::Xfs,
+        Filesystem::Apfs,
+    ] {
+        assert_eq!(filesystem.max_file_size(), None, "{filesystem}");
+    }
+}
+
+#[test]
+fn windows_and_macos_names_are_recognised() {
+    for name in ["FAT32", "FAT", "msdos", "vfat"] {
+        assert_eq!(Filesystem::from_name(name), Some(Filesystem::Fat), "{name}");
+    }
+    assert_eq!(Filesystem::from_name("exFAT"), Some(Filesystem::ExFat));
+    assert_eq!(Filesystem::from_name("NTFS"), Some(Filesystem::Ntfs));
+    assert_eq!(Filesystem::from_name("apfs"), Some(Filesystem::Apfs));
+    assert_eq!(Filesystem::from_name("ReFS"), None);
+}
+
+#[test]
+fn a_file_past_the_limit_is_refused() {
+    let stick = Path::new("/media/stick");
+    let iso = 6_000_000_000;
+    let reason = filesystem::too_large(stick, Some(Filesystem::Fat), iso).unwrap();
+    assert!(
+        reason.contains("'/media/stick' is on FAT, which can't hold a file over 4.00 GiB"),
+        "{reason}"
+    );
+
+    assert_eq!(
+        filesystem::too_large(stick, Some(Filesystem::Fat), FAT_LIMIT),
+        None
+    );
+    assert_eq!(
+        filesystem::too_large(stick, Some(Filesystem::ExFat), iso),
+        None
+    );
+    // Unidentified filesystems aren't checked.
+    assert_eq!(filesystem::too_large(stick, None, iso), None);
+}
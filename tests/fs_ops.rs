@@ -0,0 +1,48 @@
+use download_manager::__private::fs_ops::{self, Operation};
+use std::path::Path;
+
+// One test: forbidding is for the rest of the process.
+#[test]
+fn forbidden_changes_are_refused_and_recorded() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fs_ops_forbidden");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let kept = dir.join("kept.bin");
+    fs_ops::write(&kept, b"kept").unwrap();
+    let before = fs_ops::snapshot(&dir);
+
+    fs_ops::forbid();
+    let created = dir.join("created.bin");
+    let attempts = [
+        std::panic::catch_unwind(|| fs_ops::create(&created).map(drop)),
+        std::panic::catch_unwind(|| fs_ops::rename(&kept, &created)),
+        std::panic::catch_unwind(|| fs_ops::remove_file(&kept)),
+    ];
+
+    // A debug build panics, a release build fails with an error.
+    for attempt in attempts {
+        let refused = match attempt {
+            Ok(result) => result.is_err(),
+            Err(_) => true,
+        };
+        assert!(refused);
+    }
+    assert_eq!(
+        fs_ops::refused(),
+        [
+            Operation {
+                what: "create",
+                path: created.clone(),
+            },
+            Operation {
+                what: "rename",
+                path: kept.clone(),
+            },
+            Operation {
+                what: "remove",
+                path: kept.clone(),
+            },
+        ]
+    );
+    assert_eq!(fs_ops::snapshot(&dir), before);
+}
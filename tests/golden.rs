@@ -0,0 +1,117 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir, sha256_hex};
+use std::path::{Path, PathBuf};
+
+/// Compares `actual` with the snapshot `name` in tests/golden, with the
+/// server's address and the scratch dir, which differ on every run, put
+/// back as `{server}` and `{dir}`. `DLM_UPDATE_GOLDEN=1` writes it instead.
+fn assert_golden(name: &str, actual: &str, server: &TestServer, dir: &Path) {
+    let actual = actual
+        .replace(server.url("").trim_end_matches('/'), "{server}")
+        .replace(dir.to_str().unwrap(), "{dir}");
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("DLM_UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &actual).unwrap();
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "{}: {error}; write it with DLM_UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    assert_eq!(actual, expected, "{name}");
+}
+
+/// The JSON lines of `output`, as written.
+fn json_lines(output: &[u8]) -> String {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+#[test]
+fn deterministic_json_progress_matches_its_snapshot() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let modes: [(&str, &[&str]); 2] = [
+        ("single-stream", &["download-async"]),
+        ("workers", &["download-async", "--workers", "3"]),
+    ];
+    for (name, mode) in modes {
+        let dir = scratch_dir(&format!(
+            "deterministic_json_progress_matches_its_snapshot_{name}"
+        ));
+        let url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--progress",
+            "json",
+            "--json-deterministic",
+            &url,
+        ];
+        args.extend(mode);
+
+        // Twice, to the same lines.
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let output = run_dlm(&args);
+            assert_downloaded(&output, &dir.join("file.bin"), &data);
+            runs.push(json_lines(&output.stderr));
+            std::fs::remove_file(dir.join("file.bin")).unwrap();
+        }
+        assert_eq!(runs[0], runs[1], "{name}");
+        assert_golden(&format!("progress-{name}.jsonl"), &runs[0], &server, &dir);
+    }
+}
+
+#[test]
+fn a_dry_run_plan_matches_its_snapshot() {
+    let server = TestServer::builder(payload(300_000)).start();
+    let dir = scratch_dir("a_dry_run_plan_matches_its_snapshot");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--dry-run",
+        "--json",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    // The parts are named after the URL, port and all.
+    let id = &sha256_hex(format!("{}\n300000", server.url("/file.bin")).as_bytes())[..8];
+    let plan = String::from_utf8(output.stdout)
+        .unwrap()
+        .replace(id, "{id}");
+    assert_golden("dry-run.json", &plan, &server, &dir);
+}
+
+#[test]
+fn a_deterministic_failure_matches_its_snapshot() {
+    let server = TestServer::builder(payload(1000))
+        .handler(|_, _| Some(Response::new(404, "not here")))
+        .start();
+    let dir = scratch_dir("a_deterministic_failure_matches_its_snapshot");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "json",
+        "--json-deterministic",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let lines = json_lines(&output.stderr);
+    assert_golden("progress-error.jsonl", &lines, &server, &dir);
+}
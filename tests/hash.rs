@@ -0,0 +1,68 @@
+mod common;
+
+use common::{run_dlm, scratch_dir, sha256_hex};
+
+#[test]
+fn hash_prints_sums_in_each_algorithm() {
+    let dir = scratch_dir("hash_prints_sums_in_each_algorithm");
+    let file = dir.join("abc.txt");
+    std::fs::write(&file, "abc").unwrap();
+    let path = file.to_str().unwrap();
+
+    for (algo, expected) in [
+        ("sha256", sha256_hex(b"abc")),
+        (
+            "sha512",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+                .to_string(),
+        ),
+        ("md5", "900150983cd24fb0d6963f7d28e17f72".to_string()),
+        (
+            "blake3",
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85".to_string(),
+        ),
+    ] {
+        let output = run_dlm(&["hash", "--algo", algo, path]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{expected}  {path}\n"),
+            "{algo}"
+        );
+    }
+}
+
+#[test]
+fn hash_check_reports_each_file_and_fails_on_a_mismatch() {
+    let dir = scratch_dir("hash_check_reports_each_file_and_fails_on_a_mismatch");
+    let good = dir.join("good.bin");
+    let bad = dir.join("bad.bin");
+    std::fs::write(&good, "good").unwrap();
+    std::fs::write(&bad, "bad").unwrap();
+    let output = run_dlm(&["hash", good.to_str().unwrap(), bad.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let sums = dir.join("SHA256SUMS");
+    std::fs::write(&sums, &output.stdout).unwrap();
+
+    let output = run_dlm(&["hash", "--check", sums.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+
+    std::fs::write(&bad, "tampered").unwrap();
+    let output = run_dlm(&["hash", "--check", sums.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("{}: OK", good.display())),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("{}: FAILED", bad.display())),
+        "{stdout}"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 computed checksums did NOT match"),
+        "{stderr}"
+    );
+}
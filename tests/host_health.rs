@@ -0,0 +1,64 @@
+use download_manager::__private::host_health::{HostCircuits, is_host_failure};
+use download_manager::DownloadError;
+use reqwest::StatusCode;
+use std::time::{Duration, Instant};
+use url::Url;
+
+fn status(code: u16) -> anyhow::Error {
+    let status = StatusCode::from_u16(code).unwrap();
+    match status.is_server_error() {
+        true => DownloadError::ServerError { status, body: None },
+        false => DownloadError::UnexpectedStatus { status, body: None },
+    }
+    .into()
+}
+
+#[test]
+fn failing_host_is_skipped_until_a_trial_succeeds() {
+    let mut circuits = HostCircuits::new(3, Duration::from_secs(60));
+    let mirror: Url = "https://mirror.example/a.iso".parse().unwrap();
+    let other: Url = "https://other.example/a.iso".parse().unwrap();
+    let start = Instant::now();
+
+    // A 404 is an answer, and resets the count.
+    for error in [status(503), status(404), status(502), status(500)] {
+        assert!(circuits.admit(&mirror, start));
+        circuits.record(&mirror, Some(&error), start);
+    }
+    assert!(circuits.admit(&mirror, start));
+    circuits.record(&mirror, Some(&status(503)), start);
+    assert!(!circuits.admit(&mirror, start));
+    assert!(circuits.admit(&other, start));
+
+    // Half-open: one trial, which fails and opens it again.
+    let later = start + Duration::from_secs(61);
+    assert!(circuits.admit(&mirror, later));
+    assert!(!circuits.admit(&mirror, later));
+    circuits.record(&mirror, Some(&status(503)), later);
+    assert!(!circuits.admit(&mirror, later + Duration::from_secs(59)));
+
+    let recovered = later + Duration::from_secs(60);
+    assert!(circuits.admit(&mirror, recovered));
+    circuits.record(&mirror, None, recovered);
+    assert!(circuits.admit(&mirror, recovered));
+
+    let stats = &circuits.stats()["mirror.example"];
+    assert_eq!(stats.attempts, 8);
+    assert_eq!(stats.failures, 5);
+    assert_eq!(stats.consecutive_failures, 0);
+    assert_eq!(stats.skipped, 3);
+    assert_eq!(stats.tripped, 2);
+}
+
+#[test]
+fn connect_failures_count_against_the_host() {
+    // Nothing listens on the discard port.
+    let error = reqwest::blocking::get("http://127.0.0.1:9/file.bin").unwrap_err();
+    assert!(is_host_failure(
+        &anyhow::Error::new(error).context("Chunk 0")
+    ));
+    assert!(!is_host_failure(&status(404)));
+    assert!(!is_host_failure(&anyhow::anyhow!(
+        "No space left on device"
+    )));
+}
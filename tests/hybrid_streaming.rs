@@ -0,0 +1,132 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, dlm, payload, run_dlm, scratch_dir};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Streams `data` from a slow server into `dir/video.bin` with `flags` and
+/// `workers`, checking the file is a start of it whenever it's looked at,
+/// and returns the lengths it was seen at.
+fn watch_stream(data: &[u8], dir: &Path, flags: &[&str], workers: &str) -> Vec<usize> {
+    let server = TestServer::builder(data.to_vec())
+        .drip(5_000, Duration::from_millis(20))
+        .start();
+    let path = dir.join("video.bin");
+
+    let mut child = dlm()
+        .args(["-t", dir.to_str().unwrap(), "--hybrid-streaming"])
+        .args(flags)
+        .arg(server.url("/video.bin"))
+        .args(["download-async", "--workers", workers])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut seen = Vec::new();
+    while child.try_wait().unwrap().is_none() {
+        if let Ok(content) = std::fs::read(&path) {
+            assert_eq!(content, data[..content.len()], "at {} bytes", content.len());
+            seen.push(content.len());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(child.wait().unwrap().success());
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let left: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(left, ["video.bin"]);
+    seen
+}
+
+#[test]
+fn the_file_is_a_valid_start_of_the_download_all_along() {
+    let data = payload(400_000);
+    let dir = scratch_dir("the_file_is_a_valid_start_of_the_download_all_along");
+    let seen = watch_stream(&data, &dir, &[], "4");
+    // It grew as it went, rather than all at once at the end.
+    assert!(
+        seen.iter().any(|len| *len > 0 && *len < data.len()),
+        "{seen:?}"
+    );
+}
+
+#[test]
+fn sequential_segments_are_appended_as_they_come() {
+    let data = payload(400_000);
+    let dir = scratch_dir("sequential_segments_are_appended_as_they_come");
+    let seen = watch_stream(&data, &dir, &["--chunk-order", "sequential"], "2");
+    // Eight 50 KB segments for two workers: it grew past the first few
+    // while the rest were still downloading.
+    assert!(
+        seen.iter().any(|len| *len > 100_000 && *len < data.len()),
+        "{seen:?}"
+    );
+}
+
+#[test]
+fn spread_segments_cant_be_streamed() {
+    let server = TestServer::builder(payload(1_000)).start();
+    let dir = scratch_dir("spread_segments_cant_be_streamed");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--hybrid-streaming",
+        "--chunk-order",
+        "spread",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("can't be used with --chunk-order spread"),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn hybrid_streaming_survives_a_dropped_chunk() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .fail_after(30_000, 100)
+        .start();
+    let dir = scratch_dir("hybrid_streaming_survives_a_dropped_chunk");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--hybrid-streaming",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+#[test]
+fn hybrid_streaming_needs_a_worker_besides_the_stream() {
+    let server = TestServer::builder(payload(1_000)).start();
+    let dir = scratch_dir("hybrid_streaming_needs_a_worker_besides_the_stream");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--hybrid-streaming",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "1",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("needs download-async --workers 2 or more"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+/// A server of `versions[n]` under ETag `"v<n>"`, currently the one in
+/// `current`, answering `If-Range` the way RFC 9110 has it: a range only of
+/// the version it names.
+fn versioned_server(versions: Vec<Vec<u8>>, current: Arc<Mutex<usize>>) -> TestServer {
+    TestServer::builder(Vec::new())
+        .handler(move |request, _| {
+            let version = *current.lock().unwrap();
+            let data = &versions[version];
+            let etag = format!("\"v{version}\"");
+            let whole = Response::new(200, data.clone())
+                .header("Accept-Ranges", "bytes")
+                .header("ETag", etag.clone());
+            let Some(range) = request.header("Range") else {
+                return Some(whole);
+            };
+            if request.header("If-Range").is_some_and(|sent| sent != etag) {
+                return Some(whole);
+            }
+            let start: usize = range
+                .strip_prefix("bytes=")?
+                .strip_suffix('-')?
+                .parse()
+                .ok()?;
+            let total = data.len();
+            Some(
+                Response::new(206, data[start..].to_vec())
+                    .header(
+                        "Content-Range",
+                        format!("bytes {start}-{}/{total}", total - 1),
+                    )
+                    .header("ETag", etag),
+            )
+        })
+        .start()
+}
+
+/// Downloads the first 100000 bytes of version 0 with `command`, the
+/// connection dropping there, and leaves what's kept beside it.
+fn interrupted(server: &TestServer, dir: &std::path::Path, command: &str) {
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--retries",
+        "0",
+        &server.url("/file.bin"),
+        command,
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(dir.join("file.bin.meta").is_file());
+}
+
+#[test]
+fn an_unchanged_file_is_resumed_with_if_range() {
+    let data = payload(300_000);
+
+    for command in ["download-blocking", "download-async"] {
+        let server = TestServer::builder(data.clone())
+            .header("ETag", "\"v0\"")
+            .fail_after(100_000, 1)
+            .start();
+        let dir = scratch_dir(&format!(
+            "an_unchanged_file_is_resumed_with_if_range_{command}"
+        ));
+        interrupted(&server, &dir, command);
+        let meta: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("file.bin.meta")).unwrap()).unwrap();
+        assert_eq!(meta["etag"], "\"v0\"");
+        assert_eq!(meta["size"], 300_000);
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(last.header("Range"), Some("bytes=100000-"));
+        assert_eq!(last.header("If-Range"), Some("\"v0\""));
+        assert!(!dir.join("file.bin.meta").exists());
+    }
+}
+
+#[test]
+fn a_changed_file_aborts_the_resume_or_restarts_it() {
+    let (old, new) = (payload(300_000), payload(250_000).repeat(2));
+
+    for command in ["download-blocking", "download-async"] {
+        let current = Arc::new(Mutex::new(0));
+        let server = versioned_server(vec![old.clone(), new.clone()], current.clone());
+        let dir = scratch_dir(&format!("a_changed_file_aborts_the_resume_{command}"));
+        fs::write(dir.join("file.bin"), &old[..100_000]).unwrap();
+        fs::write(
+            dir.join("file.bin.meta"),
+            r#"{"etag":"\"v0\"","last_modified":null,"size":300000}"#,
+        )
+        .unwrap();
+        *current.lock().unwrap() = 1;
+
+        let args = [
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ];
+        let output = run_dlm(&args);
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("The remote file changed since the download started"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("--overwrite"), "{stderr}");
+        assert_eq!(fs::read(dir.join("file.bin")).unwrap(), &old[..100_000]);
+
+        let mut restart = args.to_vec();
+        restart.splice(0..0, ["--if-changed", "restart"]);
+        let output = run_dlm(&restart);
+        assert_downloaded(&output, &dir.join("file.bin"), &new);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("it no longer matches \"v0\""), "{stderr}");
+        assert!(stderr.contains("starting over"), "{stderr}");
+        assert!(!dir.join("file.bin.meta").exists());
+    }
+}
+
+#[test]
+fn a_range_of_another_size_is_not_appended() {
+    let data = payload(300_000);
+    // Ignores If-Range, but its Content-Range gives the new size away.
+    let server = TestServer::builder(data.clone()).start();
+
+    for command in ["download-blocking", "download-async"] {
+        let dir = scratch_dir(&format!(
+            "a_range_of_another_size_is_not_appended_{command}"
+        ));
+        fs::write(dir.join("file.bin"), &data[..100_000]).unwrap();
+        fs::write(
+            dir.join("file.bin.meta"),
+            r#"{"etag":null,"last_modified":"Mon, 05 Oct 2026 00:00:00 GMT","size":280000}"#,
+        )
+        .unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            &server.url("/file.bin"),
+            command,
+        ]);
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("it's 300000 bytes now, not 280000"),
+            "{stderr}"
+        );
+        let last = server.requests().last().cloned().unwrap();
+        assert_eq!(
+            last.header("If-Range"),
+            Some("Mon, 05 Oct 2026 00:00:00 GMT")
+        );
+        assert_eq!(fs::metadata(dir.join("file.bin")).unwrap().len(), 100_000);
+    }
+}
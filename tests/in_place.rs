@@ -0,0 +1,123 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, dlm, payload, run_dlm, scratch_dir};
+use std::process::Stdio;
+use std::time::Duration;
+
+#[test]
+fn chunks_are_written_into_the_file_without_parts() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone())
+        .drip(10_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("chunks_are_written_into_the_file_without_parts");
+    let path = dir.join("file.bin");
+
+    let mut child = dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "4",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // What dlm checks the target directory is writable with, gone again
+    // before the download starts.
+    let probe = format!(".dlm-write-test-{}", child.id());
+    while child.try_wait().unwrap().is_none() {
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            assert!(
+                ["file.bin", "file.bin.part", "file.bin.download-state"].contains(&name.as_str())
+                    || name.ends_with(".partial")
+                    || name == probe,
+                "{name}"
+            );
+        }
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            assert_eq!(metadata.len(), data.len() as u64);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(child.wait().unwrap().success());
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let left: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(left, ["file.bin"]);
+}
+
+#[test]
+fn an_interrupted_in_place_download_carries_on_from_its_state() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("an_interrupted_in_place_download_carries_on_from_its_state");
+    let args = [
+        "-t",
+        dir.to_str().unwrap(),
+        "--write-buffer",
+        "0",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ];
+
+    let child = dlm()
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(1_500));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(!child.wait_with_output().unwrap().status.success());
+    // As long as the whole file, so only the state tells what's missing,
+    // and kept apart from it until it's whole.
+    assert_eq!(
+        std::fs::metadata(dir.join("file.bin.part")).unwrap().len(),
+        data.len() as u64
+    );
+    assert!(!dir.join("file.bin").exists());
+    let state: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.join("file.bin.download-state")).unwrap())
+            .unwrap();
+    let written: Vec<u64> = state["written"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|written| written.as_u64().unwrap())
+        .collect();
+    assert_eq!(written.len(), 4, "{state}");
+    assert!(written.iter().all(|written| *written > 0), "{state}");
+    let requested = server.requests().len();
+
+    let output = run_dlm(&[&["--resume"], &args[..]].concat());
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!dir.join("file.bin.download-state").exists());
+    assert!(!dir.join("file.bin.part").exists());
+    // Each chunk asked for the rest of its range, from where it was.
+    let ranges: Vec<_> = server.requests()[requested..]
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    for (chunk, written) in written.iter().enumerate() {
+        let start = chunk as u64 * 50_000 + written;
+        let end = chunk as u64 * 50_000 + 49_999;
+        assert!(
+            ranges.contains(&format!("bytes={start}-{end}")),
+            "{ranges:?} {written:?}"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use common::scratch_dir;
+use download_manager::__private::inodes;
+
+#[test]
+fn parts_past_the_free_inodes_are_refused() {
+    let dir = scratch_dir("parts_past_the_free_inodes_are_refused");
+    let file = dir.join("file.bin");
+    inodes::check(&file, 8).unwrap();
+
+    // Only filesystems with a fixed number of inodes can run out.
+    if inodes::free_inodes(&dir).is_some() {
+        let error = inodes::check(&file, u64::MAX).unwrap_err().to_string();
+        assert!(
+            error.contains(&format!(
+                "more files (inodes) but the download needs {}",
+                u64::MAX
+            )),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+const LANDING: &str = "/projects/tool/files/tool-1.2.tar.gz/download";
+
+fn landing_server(data: Vec<u8>, page: &'static str) -> TestServer {
+    TestServer::builder(data)
+        .handler(move |request, _| {
+            (request.path == LANDING).then(|| {
+                Response::new(200, page).header("Content-Type", "text/html; charset=utf-8")
+            })
+        })
+        .start()
+}
+
+#[test]
+fn landing_page_link_is_followed_once() {
+    let data = payload(50_000);
+    let server = landing_server(
+        data.clone(),
+        r#"<html><body>
+            <a href="/projects/tool/">Project</a>
+            <a class="direct" HREF="/mirror/tool-1.2.tar.gz?use_mirror=x&amp;r=1">direct link</a>
+            <a href="/mirror/tool-1.2.zip">zip</a>
+        </body></html>"#,
+    );
+    let dir = scratch_dir("landing_page_link_is_followed_once");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--follow-landing-page",
+        &server.url(LANDING),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("tool-1.2.tar.gz"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("resolved to http://") && stdout.contains("use_mirror=x&r=1"),
+        "{stdout}"
+    );
+    let landing_requests = server
+        .requests()
+        .iter()
+        .filter(|request| request.path == LANDING)
+        .count();
+    assert_eq!(landing_requests, 1);
+}
+
+#[test]
+fn ambiguous_landing_page_lists_the_candidates() {
+    let server = landing_server(
+        payload(1_000),
+        r#"<meta charset="utf-8">
+        <a href="https://mirror-a.example/tool-1.2.tar.gz">A</a>
+        <a href='https://mirror-b.example/tool-1.2.tar.gz'>B</a>"#,
+    );
+    let dir = scratch_dir("ambiguous_landing_page_lists_the_candidates");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--follow-landing-page",
+        &server.url(LANDING),
+        "download-async",
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("several candidates"), "{stderr}");
+    assert!(stderr.contains("https://mirror-a.example/tool-1.2.tar.gz"));
+    assert!(stderr.contains("https://mirror-b.example/tool-1.2.tar.gz"));
+    assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::time::{Duration, Instant};
+
+#[test]
+fn the_limit_is_shared_by_every_worker() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    for (name, command) in [
+        ("blocking", &["download-blocking"][..]),
+        ("async", &["download-async"]),
+        ("workers", &["download-async", "--workers", "8"]),
+    ] {
+        let dir = scratch_dir(&format!("the_limit_is_shared_by_every_worker_{name}"));
+        let url = server.url("/file.bin");
+        let mut args = vec!["-t", dir.to_str().unwrap(), "--limit-rate", "150k", &url];
+        args.extend_from_slice(command);
+
+        let started = Instant::now();
+        let output = run_dlm(&args);
+        let took = started.elapsed();
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        // Two seconds at 150 KiB/s; eight workers with a limit each would
+        // be done in a quarter of one.
+        assert!(took >= Duration::from_millis(1_500), "{name}: {took:?}");
+    }
+}
+
+#[test]
+fn a_rate_that_isnt_a_size_is_refused() {
+    for (rate, message) in [
+        ("lots", "'lots' is not a size like 500k, 2M or 1048576"),
+        ("2Q", "unknown size suffix 'q' in '2Q'"),
+    ] {
+        let output = run_dlm(&[
+            "--limit-rate",
+            rate,
+            "http://127.0.0.1:1/file.bin",
+            "download-async",
+        ]);
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{stderr}");
+    }
+}
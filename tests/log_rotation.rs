@@ -0,0 +1,90 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::path::Path;
+
+fn rotated(log: &Path, n: usize) -> std::path::PathBuf {
+    log.with_extension(format!("log.{n}"))
+}
+
+#[test]
+fn log_file_is_rotated_at_startup_and_mid_run() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("log_file_is_rotated_at_startup_and_mid_run");
+    let log = dir.join("dlm.log");
+    let seed = "from an earlier run\n".repeat(60);
+    std::fs::write(&log, &seed).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-vv",
+        "--log-file",
+        log.to_str().unwrap(),
+        "--log-max-size",
+        "300",
+        "--log-keep",
+        "20",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    let files: Vec<String> = std::iter::once(log.clone())
+        .chain((1..=20).map(|n| rotated(&log, n)))
+        .take_while(|path| path.exists())
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect();
+    // Over the limit already, so it moved aside before the run logged
+    // anything; then the run filled more than one file.
+    assert!(files.len() > 2, "{files:?}");
+    assert_eq!(files.last().unwrap(), &seed);
+    for file in &files[..files.len() - 1] {
+        assert!(file.len() <= 300, "{file}");
+        assert!(file.ends_with('\n'), "{file}");
+        assert!(!file.contains("earlier run"));
+    }
+    // Newest last: the end of the run is in the live file.
+    assert!(files[0].contains("Post-processing step"), "{}", files[0]);
+    assert!(
+        files[files.len() - 2].contains("> GET /file.bin HTTP/1.1"),
+        "{files:?}"
+    );
+}
+
+#[test]
+fn rotation_keeps_only_so_many_files() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("rotation_keeps_only_so_many_files");
+    let log = dir.join("dlm.log");
+
+    for _ in 0..2 {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "-o",
+            "-vv",
+            "--log-file",
+            log.to_str().unwrap(),
+            "--log-max-size",
+            "300",
+            "--log-keep",
+            "2",
+            &server.url("/file.bin"),
+            "download-async",
+        ]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+    }
+    assert!(rotated(&log, 2).exists());
+    assert!(!rotated(&log, 3).exists());
+
+    let output = run_dlm(&[
+        "--log-keep",
+        "2",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{TestServer, payload, run_dlm, scratch_dir};
+use download_manager::MemoryLimit;
+use download_manager::TransferOptions;
+use download_manager::TransferProgress;
+use download_manager::download_with_workers;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+#[test]
+fn buffers_stay_under_the_cap_and_are_all_freed() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone())
+        .drip(16 * 1024, Duration::from_millis(5))
+        .start();
+    let dir = scratch_dir("buffers_stay_under_the_cap_and_are_all_freed");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let memory = Arc::new(MemoryLimit::new(Some(600_000)));
+    let options = TransferOptions {
+        write_buffer: 256 * 1024,
+        memory: memory.clone(),
+        ..TransferOptions::default()
+    };
+    let path = runtime
+        .block_on(download_with_workers(
+            &reqwest::Client::new(),
+            Url::parse(&server.url("/file.bin")).unwrap(),
+            &dir,
+            4,
+            TransferProgress::new(Default::default()),
+            &options,
+        ))
+        .unwrap();
+
+    assert_eq!(std::fs::read(path).unwrap(), data);
+    assert_eq!(memory.in_use(), 0);
+    assert!(memory.peak() > 0);
+    assert!(memory.peak() <= 600_000, "{}", memory.peak());
+}
+
+#[test]
+fn chunks_past_the_cap_wait_and_say_so() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone())
+        .drip(16 * 1024, Duration::from_millis(5))
+        .start();
+    let dir = scratch_dir("chunks_past_the_cap_wait_and_say_so");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--write-buffer",
+        "256k",
+        "--max-memory",
+        "300k",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Buffers are near --max-memory"), "{stderr}");
+    assert!(stderr.contains("write buffer gets"), "{stderr}");
+    assert!(stderr.contains("holding chunk"), "{stderr}");
+}
+
+#[test]
+fn read_buffers_stay_under_a_cap_smaller_than_their_floor() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("read_buffers_stay_under_a_cap_smaller_than_their_floor");
+
+    // Each part is hashed as it's written.
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--part-files",
+        "--max-memory",
+        "40000",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("hashing gets 39.06 KiB instead of 1.00 MiB"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("gets 64.00 KiB"), "{stderr}");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+const QUERY: &str = r#"{"table":"orders","format":"csv"}"#;
+
+#[test]
+fn post_export_follows_see_other_with_get() {
+    let data = payload(120_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            (request.method == "POST" && request.path == "/export")
+                .then(|| Response::new(303, Vec::new()).header("Location", "/exports/orders.csv"))
+        })
+        .start();
+    let dir = scratch_dir("post_export_follows_see_other_with_get");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--method",
+        "post",
+        "--data",
+        QUERY,
+        "--content-type",
+        "application/json",
+        "--output",
+        "orders.csv",
+        &server.url("/export"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("orders.csv"), &data);
+    let requests = server.requests();
+    let export = &requests[0];
+    assert_eq!(export.method, "POST");
+    assert_eq!(export.body, QUERY.as_bytes());
+    assert_eq!(export.header("Content-Type"), Some("application/json"));
+    let fetch = &requests[1];
+    assert_eq!(
+        (fetch.method.as_str(), fetch.path.as_str()),
+        ("GET", "/exports/orders.csv")
+    );
+    assert!(fetch.body.is_empty());
+}
+
+#[test]
+fn post_rules_out_workers_and_resume() {
+    let dir = scratch_dir("post_rules_out_workers_and_resume");
+    let url = "http://127.0.0.1:9/export";
+
+    for (args, message) in [
+        (
+            &["--method", "POST", url, "download-async", "--workers", "4"][..],
+            "can't be split between workers",
+        ),
+        (
+            &["--method", "PUT", "--resume", url, "download-blocking"][..],
+            "--resume can't be used with --method PUT",
+        ),
+        (
+            &["--data", "x", url, "download-blocking"][..],
+            "need --method POST or PUT",
+        ),
+    ] {
+        let mut full = vec!["-t", dir.to_str().unwrap()];
+        full.extend(args);
+        let output = run_dlm(&full);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{stderr}");
+    }
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+/// Nothing listens here.
+const DEAD: &str = "http://127.0.0.1:9/file.bin";
+
+#[test]
+fn a_dead_url_falls_back_to_a_mirror_named_after_the_url() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let modes: [&[&str]; 2] = [&["download-async"], &["download-async", "--workers", "3"]];
+    for (index, mode) in modes.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "a_dead_url_falls_back_to_a_mirror_named_after_the_url_{index}"
+        ));
+        let mirror = server.url("/pub/images/other-name.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--retries",
+            "1",
+            "--mirror",
+            &mirror,
+            DEAD,
+        ];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(!dir.join("other-name.bin").exists(), "{mode:?}");
+    }
+}
+
+#[test]
+fn a_mirror_of_another_size_is_left_out() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let other = TestServer::builder(payload(150_000)).start();
+    let dir = scratch_dir("a_mirror_of_another_size_is_left_out");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--mirror",
+        &other.url("/file.bin"),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("it says the file is 150000 bytes, not 200000 bytes"),
+        "{stderr}"
+    );
+    // Only probed.
+    assert!(
+        other.requests().iter().all(|request| request
+            .header("Range")
+            .is_none_or(|range| range == "bytes=0-0")),
+        "{:?}",
+        other.requests()
+    );
+}
+
+#[test]
+fn the_chunks_are_spread_across_the_mirrors() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("the_chunks_are_spread_across_the_mirrors");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--no-warm-up",
+        "--mirror",
+        &server.url("/b/file.bin"),
+        &server.url("/a/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let requests = server.requests();
+    for path in ["/a/file.bin", "/b/file.bin"] {
+        let chunks = requests
+            .iter()
+            .filter(|request| request.path == path)
+            .filter(|request| {
+                request
+                    .header("Range")
+                    .is_some_and(|range| range != "bytes=0-0")
+            })
+            .count();
+        assert_eq!(chunks, 2, "{path}: {requests:?}");
+    }
+}
+
+#[test]
+fn a_chunk_refused_by_one_mirror_carries_on_from_the_next() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            let chunk = request
+                .header("Range")
+                .is_some_and(|range| range != "bytes=0-0");
+            (request.path == "/a/file.bin" && chunk).then(|| Response::new(404, "gone"))
+        })
+        .start();
+    let dir = scratch_dir("a_chunk_refused_by_one_mirror_carries_on_from_the_next");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "plain",
+        "--mirror",
+        &server.url("/b/file.bin"),
+        &server.url("/a/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Chunk 0: ") && stderr.contains(", carrying on from http://"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_single_stream_carries_on_from_a_mirror_where_the_url_gave_up() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            (request.path == "/a/file.bin" && request.method == "GET")
+                .then(|| Response::new(503, "down for maintenance"))
+        })
+        .start();
+    let dir = scratch_dir("a_single_stream_carries_on_from_a_mirror_where_the_url_gave_up");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--retries",
+        "1",
+        "--mirror",
+        &server.url("/b/file.bin"),
+        &server.url("/a/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!dir.join("file.bin.part").exists());
+}
+
+#[test]
+fn mirror_needs_download_async() {
+    let dir = scratch_dir("mirror_needs_download_async");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--mirror",
+        "http://127.0.0.1:9/b/file.bin",
+        DEAD,
+        "download-blocking",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--mirror needs download-async"), "{stderr}");
+}
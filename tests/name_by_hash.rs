@@ -0,0 +1,75 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir, sha256_hex};
+use download_manager::__private::naming::NameTemplate;
+
+#[test]
+fn download_is_named_after_its_hash() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("download_is_named_after_its_hash");
+    let hash = sha256_hex(&data);
+    let url = server.url("/file.bin");
+
+    for (template, name) in [
+        (None, format!("{hash}.bin")),
+        (
+            Some("{sha256_short}-{name}"),
+            format!("{}-file.bin", &hash[..12]),
+        ),
+    ] {
+        let mut args = vec!["-t", dir.to_str().unwrap(), "--name-by-hash"];
+        if let Some(template) = template {
+            args.extend(["--name-template", template]);
+        }
+        args.extend([url.as_str(), "download-async"]);
+        let output = run_dlm(&args);
+
+        let path = dir.join(&name);
+        assert_downloaded(&output, &path, &data);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains(&format!("{url} -> {}", path.display())),
+            "{stdout}"
+        );
+    }
+    assert!(!dir.join("file.bin").exists());
+    assert!(!dir.join(".dlm-file.bin").exists());
+
+    // The same content again is a duplicate.
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--name-by-hash",
+        &url,
+        "download-blocking",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Already have"), "{stdout}");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    for args in [
+        &["--name-by-hash", "--output", "a.bin"][..],
+        &["--name-by-hash", "--name-template", "{md5}.bin"][..],
+        &["--name-template", "{sha256}"][..],
+    ] {
+        let mut args = args.to_vec();
+        args.extend([url.as_str(), "download-async"]);
+        assert_eq!(run_dlm(&args).status.code(), Some(2));
+    }
+}
+
+#[test]
+fn name_template_fills_in_placeholders() {
+    let hash = [0xab; 32];
+    let template: NameTemplate = "{stem}-{sha256_short}{ext}".parse().unwrap();
+    assert_eq!(template.render(&hash, "a.tar.gz"), "a.tar-abababababab.gz");
+    assert_eq!(
+        NameTemplate::default().render(&hash, "README"),
+        "ab".repeat(32)
+    );
+    for bad in ["{name}", "{sha256", "x/{sha256}"] {
+        assert!(bad.parse::<NameTemplate>().is_err(), "{bad}");
+    }
+}
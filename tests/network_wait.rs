@@ -0,0 +1,60 @@
+mod common;
+
+use common::{run_dlm, scratch_dir};
+use std::time::{Duration, Instant};
+
+/// Never resolves.
+const UNRESOLVABLE: &str = "http://dlm-wait-for-network.invalid/file.bin";
+
+#[test]
+fn an_unresolvable_host_is_waited_for_until_the_wait_is_up() {
+    let modes: [&[&str]; 3] = [
+        &["download-blocking"],
+        &["download-async"],
+        &["download-async", "--workers", "2"],
+    ];
+    for (index, mode) in modes.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "an_unresolvable_host_is_waited_for_until_the_wait_is_up_{index}"
+        ));
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--progress",
+            "plain",
+            "--wait-for-network=2s",
+            UNRESOLVABLE,
+        ];
+        args.extend(*mode);
+        let started = Instant::now();
+        let output = run_dlm(&args);
+        assert!(!output.status.success(), "{mode:?}: {output:?}");
+        assert!(started.elapsed() >= Duration::from_secs(2), "{mode:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("No network (") || stderr.contains("can't be resolved or reached"),
+            "{mode:?}: {stderr}"
+        );
+        assert!(
+            stderr.contains("Gave up waiting for the network after"),
+            "{mode:?}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn a_refused_connection_is_not_waited_for() {
+    let dir = scratch_dir("a_refused_connection_is_not_waited_for");
+    let started = Instant::now();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--wait-for-network=1m",
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(started.elapsed() < Duration::from_secs(30));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("waiting"), "{stderr}");
+}
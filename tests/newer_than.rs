@@ -0,0 +1,93 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+const LAST_MODIFIED: &str = "Wed, 01 May 2024 12:00:00 GMT";
+
+/// Serves a file last changed at [`LAST_MODIFIED`]. `/conditional.bin`
+/// answers `If-Modified-Since` with 304; `/unconditional.bin` ignores it.
+fn dated_server(data: Vec<u8>) -> TestServer {
+    TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            if request.path == "/conditional.bin" && request.header("If-Modified-Since").is_some() {
+                return Some(Response::new(304, Vec::new()));
+            }
+            Some(Response::new(200, data.clone()).header("Last-Modified", LAST_MODIFIED))
+        })
+        .start()
+}
+
+#[test]
+fn unchanged_file_is_skipped() {
+    let server = dated_server(payload(100_000));
+    let dir = scratch_dir("unchanged_file_is_skipped");
+    let mirror = dir.join("mirror.bin");
+    std::fs::write(&mirror, "already have it").unwrap();
+
+    for (newer_than, path) in [
+        ("2024-05-01T12:00:00Z", "/conditional.bin"),
+        // The file on the mirror was touched just now.
+        (mirror.to_str().unwrap(), "/unconditional.bin"),
+        ("2024-05-01 14:30:00.25+02:00", "/unconditional.bin"),
+    ] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--newer-than",
+            newer_than,
+            &server.url(path),
+            "download-async",
+        ]);
+
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("not newer than"), "{stdout}");
+        assert!(!dir.join(&path[1..]).exists());
+    }
+
+    let requests = server.requests();
+    assert_eq!(requests[0].header("If-Modified-Since"), Some(LAST_MODIFIED));
+}
+
+#[test]
+fn changed_file_is_downloaded() {
+    let data = payload(100_000);
+    let server = dated_server(data.clone());
+    let dir = scratch_dir("changed_file_is_downloaded");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--newer-than",
+        "2024-04-30T23:59:59-01:00",
+        &server.url("/unconditional.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("unconditional.bin"), &data);
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--newer-than",
+        "2024-06-01T00:00:00Z",
+        "--dry-run",
+        "--json",
+        &server.url("/unconditional.bin"),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["action"]["kind"], "skip");
+    assert_eq!(
+        plan["action"]["reason"],
+        format!("not newer than Sat, 01 Jun 2024 00:00:00 GMT (last modified {LAST_MODIFIED})")
+    );
+
+    let output = run_dlm(&[
+        "--newer-than",
+        "yesterday",
+        &server.url("/a"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
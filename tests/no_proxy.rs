@@ -0,0 +1,141 @@
+mod common;
+
+use common::{TestServer, dlm, payload, scratch_dir};
+use download_manager::__private::proxy::{is_local, no_proxy_matches};
+use url::Url;
+
+fn matches(no_proxy: &str, url: &str) -> bool {
+    no_proxy_matches(no_proxy, &Url::parse(url).unwrap())
+}
+
+#[test]
+fn domains_cover_themselves_and_their_subdomains() {
+    for entry in [
+        "example.com",
+        ".example.com",
+        "*.example.com",
+        "EXAMPLE.com.",
+    ] {
+        assert!(matches(entry, "https://example.com/a"), "{entry}");
+        assert!(matches(entry, "https://files.Example.com./a"), "{entry}");
+        assert!(!matches(entry, "https://badexample.com/a"), "{entry}");
+        assert!(
+            !matches(entry, "https://example.com.evil.test/a"),
+            "{entry}"
+        );
+    }
+    assert!(!matches("files.example.com", "https://example.com/a"));
+    assert!(matches("*", "https://anything.test/a"));
+    assert!(!matches("", "https://anything.test/a"));
+    assert!(!matches(".", "https://anything.test/a"));
+}
+
+#[test]
+fn entries_are_separated_by_commas_or_spaces() {
+    let no_proxy = " intranet.test,localhost  10.0.0.0/8 ,,";
+    assert!(matches(no_proxy, "http://intranet.test/a"));
+    assert!(matches(no_proxy, "http://localhost:8080/a"));
+    assert!(matches(no_proxy, "http://10.1.2.3/a"));
+    assert!(!matches(no_proxy, "http://example.com/a"));
+}
+
+#[test]
+fn ports_narrow_an_entry() {
+    assert!(matches("example.com:8080", "http://example.com:8080/a"));
+    assert!(!matches("example.com:8080", "http://example.com/a"));
+    // The scheme's default port counts.
+    assert!(matches("example.com:443", "https://example.com/a"));
+    assert!(matches("127.0.0.1:80", "http://127.0.0.1/a"));
+    assert!(matches("[::1]:8080", "http://[::1]:8080/a"));
+    assert!(!matches("[::1]:8080", "http://[::1]:9090/a"));
+    // Not a port, so not an entry that covers anything.
+    assert!(!matches("example.com:http", "http://example.com/a"));
+    assert!(!matches("[::1]8080", "http://[::1]/a"));
+}
+
+#[test]
+fn addresses_are_matched_as_addresses() {
+    assert!(matches("127.0.0.1", "http://127.0.0.1:3000/a"));
+    assert!(!matches("127.0.0.1", "http://127.0.0.2/a"));
+    assert!(matches("::1", "http://[::1]/a"));
+    assert!(matches("[::1]", "http://[::1]/a"));
+    assert!(matches("fe80::1", "http://[fe80:0:0::1]/a"));
+    assert!(!matches("::1", "http://[::2]/a"));
+    // Names aren't resolved, nor are addresses taken for names.
+    assert!(!matches("localhost", "http://127.0.0.1/a"));
+    assert!(!matches("127.0.0.1", "http://localhost/a"));
+    assert!(!matches("0.0.1", "http://127.0.0.1/a"));
+}
+
+#[test]
+fn cidr_ranges_cover_their_addresses() {
+    assert!(matches("10.0.0.0/8", "http://10.255.0.1/a"));
+    assert!(!matches("10.0.0.0/8", "http://11.0.0.1/a"));
+    assert!(matches("192.168.1.0/24", "http://192.168.1.200/a"));
+    assert!(!matches("192.168.1.0/24", "http://192.168.2.1/a"));
+    assert!(matches("172.16.0.0/12", "http://172.31.255.255/a"));
+    assert!(!matches("172.16.0.0/12", "http://172.32.0.0/a"));
+    assert!(matches("0.0.0.0/0", "http://8.8.8.8/a"));
+    assert!(matches("10.1.2.3/32", "http://10.1.2.3/a"));
+    assert!(!matches("10.1.2.3/32", "http://10.1.2.4/a"));
+    assert!(matches("fd00::/8", "http://[fd12:3456::1]/a"));
+    assert!(!matches("fd00::/8", "http://[fe80::1]/a"));
+    // Neither a name nor the other family, nor a prefix too long.
+    assert!(!matches("10.0.0.0/8", "http://10.example.test/a"));
+    assert!(!matches("::/0", "http://10.0.0.1/a"));
+    assert!(!matches("10.0.0.0/33", "http://10.0.0.1/a"));
+}
+
+#[test]
+fn loopback_and_link_local_hosts_are_local() {
+    for url in [
+        "http://localhost/a",
+        "http://files.localhost./a",
+        "http://127.0.0.1/a",
+        "http://127.8.9.10/a",
+        "http://[::1]/a",
+        "http://169.254.169.254/a",
+        "http://[fe80::1]/a",
+    ] {
+        assert!(is_local(&Url::parse(url).unwrap()), "{url}");
+    }
+    for url in [
+        "http://example.com/a",
+        "http://localhost.example.com/a",
+        "http://10.0.0.1/a",
+        "http://[fd00::1]/a",
+    ] {
+        assert!(!is_local(&Url::parse(url).unwrap()), "{url}");
+    }
+}
+
+#[test]
+fn loopback_downloads_skip_the_proxy_unless_proxy_all() {
+    let data = payload(50_000);
+    let origin = TestServer::builder(data.clone()).start();
+    let proxy = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("loopback_downloads_skip_the_proxy");
+    let download = |extra: &[&str]| {
+        dlm()
+            .env("HTTP_PROXY", proxy.url("/"))
+            .env_remove("NO_PROXY")
+            .env_remove("no_proxy")
+            .args(["-t", dir.to_str().unwrap(), "-o"])
+            .args(extra)
+            .args([&origin.url("/file.bin"), "download-async"])
+            .output()
+            .unwrap()
+    };
+
+    let output = download(&[]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(proxy.requests().is_empty());
+    let direct = origin.requests().len();
+    assert!(direct > 0);
+
+    let output = download(&["--proxy-all"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!proxy.requests().is_empty());
+    assert_eq!(origin.requests().len(), direct);
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{Refusal, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::time::Duration;
+
+/// A server allowing one connection at a time, slow enough that chunks
+/// downloading at once overlap.
+fn one_connection(data: &[u8], refusal: Refusal) -> TestServer {
+    TestServer::builder(data.to_vec())
+        .one_connection(refusal)
+        .drip(16 * 1024, Duration::from_millis(5))
+        .start()
+}
+
+#[test]
+fn refused_chunks_are_downloaded_one_after_another() {
+    let data = payload(400_000);
+    for refusal in [Refusal::Unavailable, Refusal::Reset] {
+        let server = one_connection(&data, refusal);
+        let dir = scratch_dir(&format!(
+            "refused_chunks_are_downloaded_one_after_another_{refusal:?}"
+        ));
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "4",
+        ]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(!server.refused().is_empty(), "{refusal:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let host = server
+            .url("")
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+        assert!(
+            stderr.contains(&format!(
+                "{host} allows one connection at a time, downloading the other "
+            )) && stderr.contains(" chunks one after another\n"),
+            "{refusal:?}: {stderr}"
+        );
+        // The parts were merged, and their plan removed.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "{refusal:?}");
+    }
+}
+
+#[test]
+fn one_connection_never_opens_a_second() {
+    let data = payload(400_000);
+    let server = one_connection(&data, Refusal::Unavailable);
+    let dir = scratch_dir("one_connection_never_opens_a_second");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--one-connection",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(server.refused().is_empty(), "{:?}", server.refused());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Downloading the 4 chunks one after another over one connection (--one-connection)"
+        ),
+        "{stderr}"
+    );
+    let chunks = server
+        .requests()
+        .iter()
+        .filter(|request| request.method == "GET")
+        .count();
+    assert_eq!(chunks, 4, "{:?}", server.requests());
+}
+
+#[test]
+fn a_batch_remembers_a_host_allowing_one_connection() {
+    let data = payload(400_000);
+    let server = one_connection(&data, Refusal::Unavailable);
+    let dir = scratch_dir("a_batch_remembers_a_host_allowing_one_connection");
+    let list = dir.join("urls.txt");
+    std::fs::write(
+        &list,
+        format!("{}\n{}\n", server.url("/a.bin"), server.url("/b.bin")),
+    )
+    .unwrap();
+    let target = dir.join("files");
+    std::fs::create_dir_all(&target).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "--input-file",
+        list.to_str().unwrap(),
+        "--parallel-downloads",
+        "1",
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    for name in ["a.bin", "b.bin"] {
+        assert_eq!(std::fs::read(target.join(name)).unwrap(), data, "{name}");
+    }
+    let refused: Vec<_> = server
+        .refused()
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    assert!(refused.iter().any(|path| path == "/a.bin"), "{refused:?}");
+    assert!(!refused.iter().any(|path| path == "/b.bin"), "{refused:?}");
+}
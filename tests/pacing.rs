@@ -0,0 +1,89 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// When each chunk's requests went out, in ms since the run started, from
+/// a `--record` transcript.
+fn chunk_requests(transcript: &Path) -> BTreeMap<u64, Vec<u64>> {
+    let mut requests: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for line in std::fs::read_to_string(transcript).unwrap().lines() {
+        let entry: Value = serde_json::from_str(line).unwrap();
+        if entry["event"] == "request"
+            && let Some(chunk) = entry["chunk"].as_u64()
+        {
+            let at = entry["at_ms"].as_u64().unwrap();
+            requests.entry(chunk).or_default().push(at);
+        }
+    }
+    requests
+}
+
+fn download(name: &str, flags: &[&str]) -> BTreeMap<u64, Vec<u64>> {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir(name);
+    let transcript = dir.join("transcript.jsonl");
+    let mut args = vec![
+        "-t",
+        dir.to_str().unwrap(),
+        "--record",
+        transcript.to_str().unwrap(),
+    ];
+    args.extend_from_slice(flags);
+    let url = server.url("/file.bin");
+    args.extend([url.as_str(), "download-async", "--workers", "4"]);
+    let output = run_dlm(&args);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    chunk_requests(&transcript)
+}
+
+#[test]
+fn ramp_up_spreads_the_chunks_over_the_interval() {
+    let requests = download(
+        "ramp_up_spreads_the_chunks_over_the_interval",
+        &["--ramp-up", "2s"],
+    );
+    let firsts: Vec<u64> = requests.values().map(|times| times[0]).collect();
+    assert_eq!(firsts.len(), 4, "{requests:?}");
+    let (first, last) = (firsts.iter().min().unwrap(), firsts.iter().max().unwrap());
+    // A chunk every half second: at 0, 0.5, 1 and 1.5s.
+    assert!(last - first >= 1_400, "{firsts:?}");
+    let mut sorted = firsts.clone();
+    sorted.sort();
+    for pair in sorted.windows(2) {
+        assert!(pair[1] - pair[0] >= 400, "{sorted:?}");
+    }
+}
+
+#[test]
+fn requests_stay_under_the_rate_cap() {
+    let requests = download(
+        "requests_stay_under_the_rate_cap",
+        &["--max-requests-per-second", "4", "--request-jitter", "50"],
+    );
+    let mut times: Vec<u64> = requests.values().flatten().copied().collect();
+    times.sort();
+    assert_eq!(times.len(), 4, "{requests:?}");
+    // One every 250ms, give or take the clock's granularity.
+    for pair in times.windows(2) {
+        assert!(pair[1] - pair[0] >= 240, "{times:?}");
+    }
+}
+
+#[test]
+fn a_rate_cap_of_zero_is_refused() {
+    let output = run_dlm(&[
+        "--max-requests-per-second",
+        "0",
+        "http://127.0.0.1:1/file.bin",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("isn't a number of requests above 0"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,91 @@
+#![cfg(all(unix, feature = "test-hooks"))]
+
+mod common;
+
+use common::{Response, TestServer, payload, scratch_dir};
+use nix::pty::openpty;
+use nix::sys::termios::tcgetattr;
+use std::fs::File;
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[test]
+fn a_panic_mid_download_leaves_the_terminal_usable_and_the_download_resumable() {
+    let data = payload(400_000);
+    // The connection is cut past 100000 bytes, and the first request
+    // resuming from there is answered with the header dlm panics on.
+    let panicked = AtomicBool::new(false);
+    let server = TestServer::builder(data.clone())
+        .drip(8_000, Duration::from_millis(20))
+        .fail_after(100_000, 1)
+        .handler(move |request, _| {
+            let resumed = request
+                .header("Range")
+                .is_some_and(|range| !range.starts_with("bytes=0-"));
+            (resumed && !panicked.swap(true, Ordering::SeqCst))
+                .then(|| Response::new(503, "").header("X-Test-Panic", "resumed past the cut"))
+        })
+        .start();
+    let dir = scratch_dir("a_panic_mid_download_leaves_the_terminal_usable");
+    let state = dir.join("state");
+    let pty = openpty(None, None).unwrap();
+    let before = tcgetattr(&pty.slave).unwrap();
+
+    let mut dlm = common::dlm()
+        .env("XDG_STATE_HOME", &state)
+        .args([
+            "-t",
+            dir.join("out").to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "1",
+        ])
+        .stdin(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stdout(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stderr(Stdio::from(pty.slave.try_clone().unwrap()))
+        .spawn()
+        .unwrap();
+    let status = dlm.wait().unwrap();
+    let after = tcgetattr(&pty.slave).unwrap();
+    drop(pty.slave);
+    let mut screen = Vec::new();
+    // Linux ends the read with EIO once the terminal has no other end.
+    let _ = File::from(pty.master).read_to_end(&mut screen);
+    let screen = String::from_utf8_lossy(&screen);
+
+    assert_eq!(status.code(), Some(101), "{screen}");
+    let message = screen
+        .find("x-test-panic: resumed past the cut")
+        .unwrap_or_else(|| panic!("{screen}"));
+    // Nothing drawn over the message, and the cursor shown before it.
+    assert!(!screen[message..].contains("Downloaded:"), "{screen:?}");
+    assert!(screen[..message].contains("\x1b[?25h"), "{screen:?}");
+    assert_eq!(before.local_flags, after.local_flags);
+    assert_eq!(before.input_flags, after.input_flags);
+
+    // The manifest was written down, so `dlm resume` finds the download.
+    let manifests: Vec<_> = std::fs::read_dir(state.join("download-manager/active"))
+        .unwrap()
+        .flatten()
+        .collect();
+    assert_eq!(manifests.len(), 1);
+    let mut manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(manifests[0].path()).unwrap()).unwrap();
+    assert!(
+        manifest["downloaded"].as_u64().unwrap() >= 100_000,
+        "{manifest}"
+    );
+    // Stale, as it is once the heartbeat would have come, so it can be
+    // resumed straight away.
+    manifest["updated"] = 0.into();
+    std::fs::write(manifests[0].path(), serde_json::to_vec(&manifest).unwrap()).unwrap();
+    let output = common::dlm()
+        .env("XDG_STATE_HOME", &state)
+        .args(["resume", manifest["id"].as_str().unwrap()])
+        .output()
+        .unwrap();
+    common::assert_downloaded(&output, &dir.join("out/file.bin"), &data);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+const MODES: [&[&str]; 3] = [
+    &["download-blocking"],
+    &["download-async"],
+    &["download-async", "--workers", "3"],
+];
+
+#[test]
+fn a_failed_download_leaves_its_part_file_and_no_file() {
+    let server = TestServer::builder(payload(200_000))
+        .fail_after(20_000, 100)
+        .start();
+    for (index, mode) in MODES.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "a_failed_download_leaves_its_part_file_and_no_file_{index}"
+        ));
+        let url = server.url("/file.bin");
+        let mut args = vec!["-t", dir.to_str().unwrap(), "--retries", "1", &url];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert!(!output.status.success(), "{mode:?}: {output:?}");
+        assert!(!dir.join("file.bin").exists(), "{mode:?}");
+        // Worker mode leaves its parts instead, the file ahead of them unmade.
+        if mode.len() == 1 {
+            assert!(
+                std::fs::metadata(dir.join("file.bin.part")).unwrap().len() >= 20_000,
+                "{mode:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn resume_carries_on_from_the_part_file_in_every_mode() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    for (index, mode) in MODES.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "resume_carries_on_from_the_part_file_in_every_mode_{index}"
+        ));
+        std::fs::write(dir.join("file.bin.part"), &data[..80_000]).unwrap();
+        let url = server.url("/file.bin");
+        let mut args = vec!["-t", dir.to_str().unwrap(), "--resume", &url];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        assert!(!dir.join("file.bin.part").exists(), "{mode:?}");
+    }
+    assert!(
+        server
+            .requests()
+            .iter()
+            .filter_map(|request| request.header("Range"))
+            .all(|range| range == "bytes=0-0" || !range.starts_with("bytes=0-")),
+        "{:?}",
+        server.requests()
+    );
+}
+
+#[test]
+fn no_partial_removes_the_part_file_of_a_failed_download() {
+    let server = TestServer::builder(payload(200_000))
+        .fail_after(20_000, 100)
+        .start();
+    for (index, mode) in MODES.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "no_partial_removes_the_part_file_of_a_failed_download_{index}"
+        ));
+        let url = server.url("/file.bin");
+        let mut args = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--retries",
+            "1",
+            "--no-partial",
+            &url,
+        ];
+        args.extend(*mode);
+        let output = run_dlm(&args);
+        assert!(!output.status.success(), "{mode:?}: {output:?}");
+        assert!(!dir.join("file.bin").exists(), "{mode:?}");
+        assert!(!dir.join("file.bin.part").exists(), "{mode:?}");
+    }
+}
+
+#[test]
+fn a_part_file_is_started_over_without_resume() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_part_file_is_started_over_without_resume");
+    std::fs::write(dir.join("file.bin.part"), vec![0; 150_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!dir.join("file.bin.part").exists());
+}
+
+#[test]
+fn overwrite_replaces_the_file_and_its_part_file() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("overwrite_replaces_the_file_and_its_part_file");
+    std::fs::write(dir.join("file.bin"), b"an older version").unwrap();
+    std::fs::write(dir.join("file.bin.part"), vec![0; 40_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--overwrite",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!dir.join("file.bin.part").exists());
+}
+
+#[test]
+fn a_file_at_the_destination_is_kept_in_every_worker_mode() {
+    let server = TestServer::builder(payload(100_000)).start();
+    let modes: [&[&str]; 3] = [&[], &["--part-files"], &["--hybrid-streaming"]];
+    for (index, mode) in modes.iter().enumerate() {
+        let dir = scratch_dir(&format!(
+            "a_file_at_the_destination_is_kept_in_every_worker_mode_{index}"
+        ));
+        std::fs::write(dir.join("file.bin"), b"someone else's file").unwrap();
+        let url = server.url("/file.bin");
+        let mut args = vec!["-t", dir.to_str().unwrap()];
+        args.extend(*mode);
+        args.extend([url.as_str(), "download-async", "--workers", "3"]);
+        let output = run_dlm(&args);
+        assert_eq!(output.status.code(), Some(1), "{mode:?}: {output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("File exists"),
+            "{mode:?}: {output:?}"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("file.bin")).unwrap(),
+            b"someone else's file",
+            "{mode:?}"
+        );
+        assert!(!dir.join("file.bin.part").exists(), "{mode:?}");
+    }
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The server's URL by host name, which goes through the resolver, unlike
+/// 127.0.0.1.
+fn by_name(server: &TestServer, path: &str) -> String {
+    server.url(path).replace("127.0.0.1", "localhost")
+}
+
+#[test]
+fn workers_pin_the_node_by_default() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("workers_pin_the_node_by_default");
+    let url = by_name(&server, "/file.bin");
+
+    let output = run_dlm(&[
+        "-v",
+        "-t",
+        dir.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Pinning localhost to 127.0.0.1 for every chunk"),
+        "{stderr}"
+    );
+
+    let output = run_dlm(&[
+        "-v",
+        "-t",
+        dir.to_str().unwrap(),
+        "--overwrite",
+        "--no-pin-ip",
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Pinning"));
+}
+
+/// Fails a worker download of `data` that pins its node, leaving its
+/// manifest in `dir/state`, then lets the server answer again. Returns the
+/// server and the manifest's path.
+fn failed_download(dir: &Path, data: Vec<u8>) -> (TestServer, std::path::PathBuf) {
+    let broken = Arc::new(AtomicBool::new(true));
+    let server = TestServer::builder(data)
+        .handler({
+            let broken = broken.clone();
+            move |request, _| {
+                (broken.load(Ordering::SeqCst) && request.header("Range").is_some())
+                    .then(|| Response::new(404, "gone"))
+            }
+        })
+        .start();
+    let state = dir.join("state");
+    let output = run_dlm(&[
+        "-v",
+        "-t",
+        dir.to_str().unwrap(),
+        "--state-dir",
+        state.to_str().unwrap(),
+        &by_name(&server, "/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    broken.store(false, Ordering::SeqCst);
+    let manifest = fs::read_dir(state.join("active"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    (server, manifest)
+}
+
+/// Rewrites the manifest at `path` with `change`, marking it stale so it
+/// can be resumed straight away.
+fn edit_manifest(path: &Path, change: impl FnOnce(&mut Value)) -> String {
+    let mut manifest: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+    manifest["updated"] = 0.into();
+    change(&mut manifest);
+    fs::write(path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+    manifest["id"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn resumes_carry_on_with_the_pinned_node() {
+    let data = payload(200_000);
+    let dir = scratch_dir("resumes_carry_on_with_the_pinned_node");
+    let (server, manifest) = failed_download(&dir, data.clone());
+
+    let id = edit_manifest(&manifest, |manifest| {
+        assert_eq!(manifest["pinned"]["host"], "localhost");
+        assert_eq!(
+            manifest["pinned"]["address"],
+            server.url("").trim_start_matches("http://")
+        );
+    });
+    let state = dir.join("state");
+    let output = run_dlm(&["-v", "--state-dir", state.to_str().unwrap(), "resume", &id]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Pinning localhost to 127.0.0.1 again"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_pinned_node_that_went_away_is_looked_up_again() {
+    let data = payload(200_000);
+    let dir = scratch_dir("a_pinned_node_that_went_away_is_looked_up_again");
+    let (_server, manifest) = failed_download(&dir, data.clone());
+
+    // A port nothing listens on any more.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let id = edit_manifest(&manifest, |manifest| {
+        manifest["pinned"]["address"] = closed.to_string().into();
+    });
+    let state = dir.join("state");
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "resume", &id]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Node 127.0.0.1 of localhost no longer answers"),
+        "{stderr}"
+    );
+}
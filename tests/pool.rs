@@ -0,0 +1,221 @@
+mod common;
+
+use common::{Response, TestServer, payload, scratch_dir};
+use download_manager::{
+    ClientOptions, DownloadPool, DownloadRequest, PoolEvent, PoolOptions, RetryBudget, RetryUsage,
+    TransferOptions, TransferState,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn a_pool_of_one_runs_its_downloads_one_after_the_other() {
+    let data = payload(60_000);
+    let server = TestServer::builder(data.clone())
+        .drip(10_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("a_pool_of_one_runs_its_downloads_one_after_the_other");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (events, totals) = runtime.block_on(async {
+        let client = ClientOptions::default().build_async().unwrap();
+        let pool = DownloadPool::new(
+            client,
+            PoolOptions {
+                parallelism: 1,
+                ..PoolOptions::default()
+            },
+        );
+        let mut receiver = pool.events();
+        let handles: Vec<_> = ["/a.bin", "/b.bin", "/c.bin"]
+            .into_iter()
+            .map(|path| {
+                let url = server.url(path).parse().unwrap();
+                pool.submit(DownloadRequest::new(url, &dir))
+            })
+            .collect();
+        for handle in handles {
+            handle.wait().await.unwrap();
+        }
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            match event {
+                PoolEvent::Started { id } => events.push(format!("started {id}")),
+                PoolEvent::Finished { id, .. } => events.push(format!("finished {id}")),
+                _ => {}
+            }
+        }
+        (events, pool.totals())
+    });
+
+    assert_eq!(
+        events,
+        [
+            "started 0",
+            "finished 0",
+            "started 1",
+            "finished 1",
+            "started 2",
+            "finished 2"
+        ]
+    );
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        assert_eq!(std::fs::read(dir.join(name)).unwrap(), data);
+    }
+    assert_eq!(totals.completed, 3);
+    assert_eq!(totals.downloaded, 180_000);
+    assert_eq!(totals.total, 180_000);
+}
+
+#[test]
+fn each_pool_spends_a_retry_budget_of_its_own() {
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| Some(Response::new(503, "down").header("Retry-After", "0")))
+        .start();
+    let dir = scratch_dir("each_pool_spends_a_retry_budget_of_its_own");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for name in ["first.bin", "second.bin"] {
+        let retries = runtime.block_on(async {
+            let client = ClientOptions::default().build_async().unwrap();
+            let pool = DownloadPool::new(
+                client,
+                PoolOptions {
+                    retry_budget: Arc::new(RetryBudget::new(Some(1))),
+                    ..PoolOptions::default()
+                },
+            );
+            let url = server.url(&format!("/{name}")).parse().unwrap();
+            let handle = pool.submit(DownloadRequest::new(url, &dir));
+            let progress = handle.progress();
+            assert!(handle.wait().await.is_err());
+            progress.snapshot().retries
+        });
+        let spent = RetryUsage {
+            used: 1,
+            budget: Some(1),
+        };
+        assert_eq!(retries, spent, "{name}");
+    }
+    // A request and its one retry each.
+    assert_eq!(server.requests().len(), 4);
+}
+
+#[test]
+fn a_cancelled_download_leaves_the_others_running() {
+    let data = payload(100_000);
+    let slow = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let fast = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_cancelled_download_leaves_the_others_running");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let client = ClientOptions::default().build_async().unwrap();
+        let pool = DownloadPool::new(client, PoolOptions::default());
+        let cancelled = pool.submit(DownloadRequest::new(
+            slow.url("/slow.bin").parse().unwrap(),
+            &dir,
+        ));
+        let finished = pool.submit(DownloadRequest::new(
+            fast.url("/fast.bin").parse().unwrap(),
+            &dir,
+        ));
+        let progress = cancelled.progress();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancelled.cancel();
+
+        assert!(finished.wait().await.is_ok());
+        let error = cancelled.wait().await.unwrap_err();
+        assert_eq!(error.to_string(), "Download cancelled.");
+        assert_eq!(progress.snapshot().state, TransferState::Interrupted);
+        let totals = pool.totals();
+        assert_eq!((totals.completed, totals.failed), (1, 1));
+    });
+    assert_eq!(std::fs::read(dir.join("fast.bin")).unwrap(), data);
+}
+
+#[test]
+fn a_cancelled_download_keeps_or_removes_its_partial_file_as_told() {
+    let server = TestServer::builder(payload(100_000))
+        .drip(1_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("a_cancelled_download_keeps_or_removes_its_partial_file_as_told");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for (name, no_partial) in [("kept.bin", false), ("removed.bin", true)] {
+        let progress = runtime.block_on(async {
+            let client = ClientOptions::default().build_async().unwrap();
+            let pool = DownloadPool::new(client, PoolOptions::default());
+            let request =
+                DownloadRequest::new(server.url(&format!("/{name}")).parse().unwrap(), &dir)
+                    .options(TransferOptions {
+                        no_partial,
+                        ..TransferOptions::default()
+                    });
+            let handle = pool.submit(request);
+            let progress = handle.progress();
+            while progress.snapshot().downloaded == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            handle.cancel();
+            let error = handle.wait().await.unwrap_err();
+            assert_eq!(error.to_string(), "Download cancelled.");
+            progress.snapshot()
+        });
+        assert_eq!(progress.state, TransferState::Interrupted);
+        assert!(!dir.join(name).exists());
+        let partial = dir.join(format!("{name}.part"));
+        match no_partial {
+            false => assert!(
+                std::fs::metadata(&partial).unwrap().len() > 0,
+                "{name}: nothing to resume from"
+            ),
+            true => assert!(!partial.exists(), "{name}: the partial file is left"),
+        }
+    }
+}
+
+#[test]
+fn a_queued_download_is_taken_off_the_queue() {
+    let data = payload(20_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(20))
+        .start();
+    let dir = scratch_dir("a_queued_download_is_taken_off_the_queue");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let client = ClientOptions::default().build_async().unwrap();
+        let pool = DownloadPool::new(
+            client,
+            PoolOptions {
+                parallelism: 1,
+                ..PoolOptions::default()
+            },
+        );
+        let running = pool.submit(DownloadRequest::new(
+            server.url("/running.bin").parse().unwrap(),
+            &dir,
+        ));
+        let queued = pool.submit(DownloadRequest::new(
+            server.url("/queued.bin").parse().unwrap(),
+            &dir,
+        ));
+        let progress = queued.progress();
+        queued.cancel();
+
+        let error = queued.wait().await.unwrap_err();
+        assert_eq!(error.to_string(), "Download cancelled.");
+        assert_eq!(progress.snapshot().state, TransferState::Interrupted);
+        assert!(running.wait().await.is_ok());
+    });
+    assert_eq!(std::fs::read(dir.join("running.bin")).unwrap(), data);
+    assert!(
+        server
+            .requests()
+            .iter()
+            .all(|request| request.path != "/queued.bin")
+    );
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{Response, TestServer, payload, scratch_dir};
+use download_manager::__private::preflight::{PreflightCache, Report, Totals, check_all};
+use download_manager::RetryBudget;
+use std::time::Duration;
+use url::Url;
+
+fn server() -> TestServer {
+    TestServer::builder(payload(5000))
+        .handler(|request, _| match request.path.as_str() {
+            "/gone.iso" => Some(Response::new(404, "")),
+            "/secret.iso" => Some(Response::new(403, "")),
+            // Refuses HEAD, like some CDNs.
+            "/no-head.iso" if request.method == "HEAD" => Some(Response::new(405, "")),
+            _ => None,
+        })
+        .start()
+}
+
+fn urls(server: &TestServer, paths: &[&str]) -> Vec<Url> {
+    paths
+        .iter()
+        .map(|path| Url::parse(&server.url(path)).unwrap())
+        .collect()
+}
+
+#[test]
+fn failing_entries_are_listed_first_with_totals() {
+    let server = server();
+    let urls = urls(
+        &server,
+        &["/a.iso", "/gone.iso", "/no-head.iso", "/secret.iso"],
+    );
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = reqwest::Client::new();
+    let budget = RetryBudget::default();
+    let report = Report::new(runtime.block_on(check_all(&client, &urls, 2, None, &budget, None)));
+
+    let names: Vec<_> = report
+        .entries
+        .iter()
+        .map(|entry| entry.file_name.as_str())
+        .collect();
+    assert_eq!(names, ["gone.iso", "secret.iso", "a.iso", "no-head.iso"]);
+    assert_eq!(report.entries[0].status, Some(404));
+    assert_eq!(report.entries[2].size, Some(5000));
+    assert!(report.entries[2].resumable);
+    assert_eq!(
+        report.totals,
+        Totals {
+            entries: 4,
+            bytes: 10_000,
+            unknown_size: 0,
+            failed: 2,
+            missing: 1,
+            forbidden: 1,
+        }
+    );
+
+    let table = report.to_string();
+    let lines: Vec<_> = table.lines().collect();
+    assert!(lines[0].starts_with("FILE"), "{table}");
+    assert!(lines[1].contains("404 Not Found"), "{table}");
+    assert!(lines[3].contains("yes"), "{table}");
+    assert_eq!(
+        lines.last().unwrap(),
+        &"Total: 9.77 KiB in 4 entries; 2 failing (1 missing, 1 forbidden)"
+    );
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["totals"]["bytes"], 10_000);
+    assert_eq!(json["entries"][1]["status"], 403);
+}
+
+#[test]
+fn cached_entries_are_not_asked_for_again() {
+    let server = server();
+    let urls = urls(&server, &["/a.iso", "/gone.iso"]);
+    let path = scratch_dir("cached_entries_are_not_asked_for_again").join("preflight.json");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = reqwest::Client::new();
+    let budget = RetryBudget::default();
+
+    let mut cache = PreflightCache::load(&path, Duration::from_secs(3600)).unwrap();
+    runtime.block_on(check_all(
+        &client,
+        &urls,
+        4,
+        Some(&mut cache),
+        &budget,
+        None,
+    ));
+    cache.save().unwrap();
+    let asked = server.requests().len();
+
+    let mut cache = PreflightCache::load(&path, Duration::from_secs(3600)).unwrap();
+    let entries = runtime.block_on(check_all(
+        &client,
+        &urls,
+        4,
+        Some(&mut cache),
+        &budget,
+        None,
+    ));
+    assert_eq!(entries[0].size, Some(5000));
+    // Only the failure was asked again, by HEAD and then GET.
+    let again: Vec<_> = server.requests()[asked..]
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    assert_eq!(again, ["/gone.iso", "/gone.iso"]);
+
+    // Stale entries are.
+    let cache = PreflightCache::load(&path, Duration::ZERO).unwrap();
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(cache.fresh(&urls[0]).is_none());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+use download_manager::__private::get_content_length;
+use download_manager::__private::progress_handle::PreflightStep;
+use download_manager::ClientOptions;
+use download_manager::RetryBudget;
+use download_manager::TransferProgress;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use url::Url;
+
+#[test]
+fn each_step_before_the_first_byte_is_timed() {
+    let server = TestServer::builder(payload(5000))
+        .handler(|request, _| {
+            (request.path == "/latest")
+                .then(|| Response::new(302, Vec::new()).header("Location", "/file.bin"))
+        })
+        .start();
+    let url = Url::parse(&server.url("/latest").replace("127.0.0.1", "localhost")).unwrap();
+    let client = ClientOptions::default().build_async().unwrap();
+    let progress = TransferProgress::new(Arc::new(AtomicBool::new(false)));
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let total = runtime
+        .block_on(progress.preflight(get_content_length(
+            &client,
+            &url,
+            &RetryBudget::default(),
+            None,
+        )))
+        .unwrap();
+    assert_eq!(total, 5000);
+    let (step, _) = progress.preflight_step().unwrap();
+    assert_eq!(step, PreflightStep::AwaitingResponse);
+
+    let snapshot = progress.snapshot();
+    let steps: Vec<_> = snapshot
+        .preflight_steps
+        .iter()
+        .map(|timing| timing.step.clone())
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            PreflightStep::ProbingRanges,
+            PreflightStep::Connecting,
+            PreflightStep::ResolvingDns {
+                host: "localhost".into()
+            },
+            PreflightStep::Connecting,
+            PreflightStep::AwaitingResponse,
+            PreflightStep::FollowingRedirect {
+                redirect: 1,
+                max: 10
+            },
+            PreflightStep::Connecting,
+        ]
+    );
+    let json = serde_json::to_value(&snapshot).unwrap();
+    assert_eq!(json["preflight"]["step"], "awaiting_response");
+    assert_eq!(json["preflight_steps"][5]["step"], "following_redirect");
+    assert_eq!(json["preflight_steps"][5]["redirect"], 1);
+}
+
+#[test]
+fn a_redirect_loop_stops_at_the_limit() {
+    let server = TestServer::builder(payload(5000))
+        .handler(|request, _| {
+            (request.path == "/loop")
+                .then(|| Response::new(302, Vec::new()).header("Location", "/loop"))
+        })
+        .start();
+    let dir = scratch_dir("preflight_redirect_loop");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/loop"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("too many redirects, stopped after 10"),
+        "{output:?}"
+    );
+}
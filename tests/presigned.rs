@@ -0,0 +1,163 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+
+const SIGNED: &str = "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=20240501T120000Z&X-Amz-Expires=3600&X-Amz-SignedHeaders=host&X-Amz-Signature=0123abcd";
+
+/// An object store refusing every request with `code` and `message`, as S3
+/// and GCS word their XML errors.
+fn refusing_server(code: &'static str, message: &'static str) -> TestServer {
+    TestServer::builder(Vec::new())
+        .handler(move |_, _| {
+            Some(
+                Response::new(
+                    403,
+                    format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{code}</Code><Message>{message}</Message><RequestId>4442587FB7D0A2F9</RequestId></Error>"
+                    ),
+                )
+                .header("Content-Type", "application/xml"),
+            )
+        })
+        .start()
+}
+
+#[test]
+fn expired_urls_are_reported_as_such() {
+    let server = refusing_server("AccessDenied", "Request has expired");
+    let dir = scratch_dir("expired_urls_are_reported_as_such");
+
+    for command in ["download-async", "download-blocking"] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--show-error-body",
+            "false",
+            &server.url(&format!("/file.bin?{SIGNED}")),
+            command,
+        ]);
+        assert_eq!(output.status.code(), Some(7), "{command}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(
+                "Presigned URL expired at Wed, 01 May 2024 13:00:00 GMT (AccessDenied: Request has expired); regenerate it"
+            ),
+            "{command}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn expired_tokens_are_reported_with_gcs_urls() {
+    let server = refusing_server("ExpiredToken", "The provided token has expired.");
+    let dir = scratch_dir("expired_tokens_are_reported_with_gcs_urls");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin?X-Goog-Algorithm=GOOG4-RSA-SHA256&X-Goog-Signature=0123abcd"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Presigned URL expired (ExpiredToken: The provided token has expired.); regenerate it"
+        ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn other_refusals_stay_a_403() {
+    let server = refusing_server("AccessDenied", "Access Denied");
+    let dir = scratch_dir("other_refusals_stay_a_403");
+    let signed = SIGNED.replace("X-Amz-Expires=3600", "X-Amz-Expires=4000000000");
+
+    for url in [
+        server.url(&format!("/file.bin?{signed}")),
+        server.url("/file.bin"),
+    ] {
+        let output = run_dlm(&["-t", dir.to_str().unwrap(), &url, "download-async"]);
+        assert_eq!(output.status.code(), Some(1), "{url}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("403 Forbidden (AccessDenied: Access Denied)"),
+            "{url}: {stderr}"
+        );
+        assert!(!stderr.contains("expired"), "{url}: {stderr}");
+    }
+}
+
+#[test]
+fn workers_skip_head_and_follow_the_redirect_once() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            (request.method == "HEAD")
+                .then(|| Response::new(403, "<Error><Code>SignatureDoesNotMatch</Code></Error>"))
+        })
+        .start();
+    let dir = scratch_dir("workers_skip_head_and_follow_the_redirect_once");
+    let url = server.url(&format!("/redirect/store/file.bin?{SIGNED}"));
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--dry-run",
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("200000"), "{stdout}");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let requests = server.requests();
+    assert!(requests.iter().all(|request| request.method == "GET"));
+    // Only the probes follow the redirect, the chunks go straight to where
+    // it led.
+    let chunks: Vec<_> = requests
+        .iter()
+        .filter(|request| request.header("Range") != Some("bytes=0-0"))
+        .collect();
+    assert_eq!(chunks.len(), 4);
+    assert!(
+        chunks
+            .iter()
+            .all(|request| request.path.starts_with("/store/file.bin?"))
+    );
+    assert_eq!(requests[0].header("Range"), Some("bytes=0-0"));
+}
+
+#[test]
+fn only_get_is_signed() {
+    let dir = scratch_dir("only_get_is_signed");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--method",
+        "POST",
+        "--data",
+        "{}",
+        &format!("https://bucket.s3.amazonaws.com/file.bin?{SIGNED}"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("presign the URL for POST instead"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,466 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::progress::ChunkState;
+use download_manager::__private::progress_handle::ChunkSummary;
+use download_manager::__private::render::{self, Glyphs, JsonEvents};
+use download_manager::TransferProgress;
+use download_manager::TransferState;
+use download_manager::schema::SCHEMA_VERSION;
+use download_manager::{JsonLines, PlainText, Renderer};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+fn interrupted() -> Arc<AtomicBool> {
+    Arc::new(AtomicBool::new(false))
+}
+
+#[test]
+fn chunks_move_through_their_states() {
+    let progress = TransferProgress::chunked(3, 3_000, interrupted());
+    assert_eq!(progress.chunk_states(), vec![ChunkState::Pending; 3]);
+    assert_eq!(
+        progress.snapshot().chunks,
+        ChunkSummary {
+            pending: 3,
+            ..ChunkSummary::default()
+        }
+    );
+    // Nothing is downloading, so nothing can be stalled.
+    assert_eq!(progress.idle(), Duration::ZERO);
+
+    progress.set_chunk_state(0, ChunkState::Downloading { worker_id: 0 });
+    progress.set_chunk_state(1, ChunkState::Downloading { worker_id: 1 });
+    progress.update_chunk_bytes(0, 1_000);
+    progress.set_chunk_state(0, ChunkState::Completed);
+    progress.set_chunk_state(2, ChunkState::Failed);
+    // Out of range, ignored.
+    progress.set_chunk_state(7, ChunkState::Completed);
+    progress.update_chunk_bytes(7, 5_000);
+
+    assert_eq!(
+        progress.chunk_states(),
+        vec![
+            ChunkState::Completed,
+            ChunkState::Downloading { worker_id: 1 },
+            ChunkState::Failed,
+        ]
+    );
+    let snapshot = progress.snapshot();
+    assert_eq!(
+        snapshot.chunks,
+        ChunkSummary {
+            pending: 0,
+            downloading: 1,
+            completed: 1,
+            failed: 1,
+            retrying: 0,
+        }
+    );
+    assert_eq!(snapshot.downloaded, 1_000);
+    assert_eq!(snapshot.total, 3_000);
+    assert_eq!(snapshot.state, TransferState::Running);
+}
+
+#[test]
+fn the_prefix_counts_toward_the_download() {
+    let progress = TransferProgress::chunked(2, 10_000, interrupted());
+    progress.set_prefix(4_000);
+    progress.update_chunk_bytes(0, 1_000);
+    progress.update_chunk_bytes(1, 500);
+    assert_eq!(progress.downloaded(), 5_500);
+    let snapshot = progress.snapshot();
+    assert_eq!((snapshot.downloaded, snapshot.prefix), (5_500, 4_000));
+
+    // A single stream reports the file's size, the prefix included.
+    let progress = TransferProgress::new(interrupted());
+    progress.set_prefix(4_000);
+    assert_eq!(progress.downloaded(), 4_000);
+    progress.set_downloaded(6_000);
+    assert_eq!(progress.downloaded(), 6_000);
+    // Restarting from zero.
+    progress.set_prefix(0);
+    progress.set_downloaded(0);
+    assert_eq!(progress.downloaded(), 0);
+    assert_eq!(progress.snapshot().chunks, ChunkSummary::default());
+    assert_eq!(
+        progress.chunk_states(),
+        vec![ChunkState::Downloading { worker_id: 0 }]
+    );
+}
+
+#[test]
+fn clones_share_one_model() {
+    let progress = TransferProgress::new(interrupted());
+    let worker = progress.clone();
+    worker.set_total(2_048);
+    worker.set_downloaded(1_024);
+    worker.println("Resuming");
+    assert_eq!((progress.downloaded(), progress.total()), (1_024, 2_048));
+    assert_eq!(progress.take_notes(), ["Resuming"]);
+    assert!(worker.take_notes().is_empty());
+}
+
+#[test]
+fn plain_text_writes_a_line_per_change() {
+    let progress = TransferProgress::new(interrupted());
+    let renderer = PlainText::new(Vec::new(), Duration::from_secs(30));
+    progress.set_total(2_048);
+    progress.set_downloaded(1_024);
+    renderer.render(&progress);
+    renderer.render(&progress);
+    progress.println("Resuming at byte 1024");
+    progress.set_downloaded(2_048);
+    renderer.render(&progress);
+    renderer.finish(&progress, "Download complete");
+
+    let output = String::from_utf8(renderer.into_inner()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 4, "{output}");
+    assert!(
+        lines[0].starts_with("Downloaded: 1.00 KiB / 2.00 KiB (50%) @ "),
+        "{output}"
+    );
+    assert_eq!(lines[1], "Resuming at byte 1024");
+    assert!(
+        lines[2].starts_with("Downloaded: 2.00 KiB / 2.00 KiB (100%) @ "),
+        "{output}"
+    );
+    assert_eq!(lines[3], "Download complete");
+    assert!(!output.contains('\x1b'), "{output:?}");
+}
+
+#[test]
+fn json_lines_carry_snapshots_and_notes() {
+    let progress = TransferProgress::chunked(2, 100, interrupted());
+    let renderer = JsonLines::new(Vec::new());
+    progress.set_chunk_state(0, ChunkState::Downloading { worker_id: 0 });
+    progress.update_chunk_bytes(0, 40);
+    renderer.render(&progress);
+    renderer.render(&progress);
+    progress.println("Time to first byte: 3ms");
+    renderer.clear(&progress);
+
+    let output = String::from_utf8(renderer.into_inner()).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2, "{output}");
+    assert_eq!(lines[0]["downloaded"], 40);
+    assert_eq!(lines[0]["total"], 100);
+    assert_eq!(lines[0]["chunks"]["downloading"], 1);
+    assert_eq!(lines[0]["state"], "running");
+    assert_eq!(lines[1]["message"], "Time to first byte: 3ms");
+    for line in &lines {
+        assert_eq!(line["schema_version"], SCHEMA_VERSION);
+    }
+}
+
+#[test]
+fn json_events_report_what_changed_since_the_last_render() {
+    let progress = TransferProgress::chunked(2, 100, interrupted());
+    let renderer = JsonEvents::new(Vec::new(), "http://example.com/file.bin");
+    progress.set_chunk_state(0, ChunkState::Downloading { worker_id: 0 });
+    progress.update_chunk_bytes(0, 50);
+    renderer.render(&progress);
+    renderer.render(&progress);
+    progress.set_chunk_state(0, ChunkState::Completed);
+    progress.println("Time to first byte: 3ms");
+    renderer.finish(&progress, "Download complete");
+
+    let output = String::from_utf8(renderer.into_inner()).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<_> = lines.iter().map(|line| line["event"].clone()).collect();
+    assert_eq!(
+        events,
+        [
+            "started",
+            "progress",
+            "message",
+            "chunk_completed",
+            "message"
+        ],
+        "{output}"
+    );
+    assert_eq!(lines[0]["url"], "http://example.com/file.bin");
+    assert_eq!(lines[1]["downloaded"], 50);
+    assert_eq!(lines[1]["total"], 100);
+    assert!(lines[1]["elapsed_ms"].is_u64(), "{output}");
+    assert_eq!(lines[2]["message"], "Time to first byte: 3ms");
+    assert_eq!(lines[3]["chunk"], 0);
+    assert_eq!(lines[3]["chunks"], 2);
+    assert_eq!(lines[4]["message"], "Download complete");
+    for line in &lines {
+        assert_eq!(line["schema_version"], SCHEMA_VERSION);
+    }
+}
+
+#[test]
+fn plain_lines_wait_out_their_interval_but_notes_do_not() {
+    let progress = TransferProgress::new(interrupted());
+    progress.set_total(1_000);
+    let renderer =
+        PlainText::new(Vec::new(), Duration::from_secs(30)).with_interval(Duration::from_secs(60));
+    progress.set_downloaded(100);
+    renderer.render(&progress);
+    progress.set_downloaded(200);
+    progress.println("Resuming at byte 1024");
+    renderer.render(&progress);
+    renderer.finish(&progress, "Download complete");
+
+    let output = String::from_utf8(renderer.into_inner()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "{output}");
+    assert!(
+        lines[0].starts_with("Downloaded: 100 B / 1000 B (10%) @ "),
+        "{output}"
+    );
+    assert_eq!(lines[1], "Resuming at byte 1024");
+    assert_eq!(lines[2], "Download complete");
+}
+
+#[test]
+fn sparkline_glyphs_scale_to_the_fastest_sample() {
+    assert_eq!(render::spark_glyph(0, 800), '▁');
+    assert_eq!(render::spark_glyph(400, 800), '▅');
+    assert_eq!(render::spark_glyph(799, 800), '█');
+    assert_eq!(render::spark_glyph(800, 800), '█');
+    // Nothing moved yet.
+    assert_eq!(render::spark_glyph(0, 0), '▁');
+
+    assert_eq!(render::sparkline(&[0, 1, 2, 3, 4, 5, 6, 7]), "▁▂▃▄▅▆▇█");
+    assert_eq!(render::sparkline(&[5_000; 4]), "████");
+    assert_eq!(render::sparkline(&[10, 1_000, 10]), "▁█▁");
+    assert_eq!(render::sparkline(&[]), "");
+}
+
+#[test]
+fn keepalive_lines_come_on_time_whether_or_not_anything_changed() {
+    let progress = TransferProgress::new(interrupted());
+    progress.set_total(4_096);
+    progress.set_downloaded(1_024);
+    progress.println("Resuming at byte 1024");
+
+    let quiet = PlainText::keepalive(
+        Vec::new(),
+        Duration::from_secs(30),
+        Duration::from_secs(3_600),
+    );
+    quiet.render(&progress);
+    assert!(quiet.into_inner().is_empty());
+
+    let renderer = PlainText::keepalive(Vec::new(), Duration::from_secs(30), Duration::ZERO);
+    renderer.render(&progress);
+    renderer.render(&progress);
+    let output = String::from_utf8(renderer.into_inner()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 2, "{output}");
+    assert!(
+        lines[0].starts_with("Downloaded: 1.00 KiB / 4.00 KiB (25%) @ "),
+        "{output}"
+    );
+    assert_eq!(lines[0], lines[1]);
+    // The notes are the progress bar's to print.
+    assert_eq!(progress.take_notes(), ["Resuming at byte 1024"]);
+}
+
+#[test]
+fn keepalive_output_speaks_up_during_long_downloads() {
+    let data = payload(64_000);
+    let server = TestServer::builder(data)
+        .drip(8_000, Duration::from_millis(150))
+        .start();
+    let dir = scratch_dir("keepalive_output_speaks_up_during_long_downloads");
+
+    for (every, expected) in [("200ms", true), ("0", false)] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--overwrite",
+            "--keepalive-output",
+            every,
+            &server.url("/file.bin"),
+            "download-async",
+        ]);
+        assert!(output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(
+            stderr.contains("Downloaded: "),
+            expected,
+            "{every}: {stderr}"
+        );
+        if expected {
+            assert!(stderr.contains("%) @ "), "{stderr}");
+        }
+    }
+}
+
+#[test]
+fn nothing_is_drawn_after_the_error_of_a_failed_download() {
+    let server = TestServer::builder(payload(400_000))
+        .drip(4_000, Duration::from_millis(20))
+        .fail_after(40_000, 100)
+        .start();
+    let dir = scratch_dir("nothing_is_drawn_after_the_error_of_a_failed_download");
+
+    for args in [
+        &["download-async"][..],
+        &["download-async", "--workers", "4"],
+    ] {
+        let mut command = vec![
+            "-t",
+            dir.to_str().unwrap(),
+            "--overwrite",
+            "--retries",
+            "0",
+            "--keepalive-output",
+            "50ms",
+        ];
+        let url = server.url("/file.bin");
+        command.push(&url);
+        command.extend(args);
+        let output = run_dlm(&command);
+        assert!(!output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let (before, after) = stderr
+            .split_once("Error: ")
+            .unwrap_or_else(|| panic!("{stderr}"));
+        assert!(before.contains("Downloaded: "), "{args:?}: {stderr}");
+        assert!(!after.contains("Downloaded: "), "{args:?}: {stderr}");
+    }
+}
+
+#[test]
+fn ascii_draws_the_same_progress_without_blocks() {
+    colored::control::set_override(false);
+    let progress = TransferProgress::chunked(5, 5_000, interrupted());
+    progress.set_chunk_state(0, ChunkState::Downloading { worker_id: 0 });
+    progress.update_chunk_bytes(0, 1_000);
+    progress.set_chunk_state(0, ChunkState::Completed);
+    progress.set_chunk_state(1, ChunkState::Downloading { worker_id: 1 });
+    progress.update_chunk_bytes(1, 500);
+    progress.set_chunk_state(3, ChunkState::Failed);
+    progress.set_chunk_state(4, ChunkState::Retrying);
+
+    assert_eq!(
+        render::chunk_line(&progress, Glyphs::Unicode),
+        "[██░█░] Downloaded: 1.46 KiB / 4.88 KiB @ 1.46 KiB/s"
+    );
+    assert_eq!(
+        render::chunk_line(&progress, Glyphs::Ascii),
+        "[#-.x!] 30% Downloaded: 1.46 KiB / 4.88 KiB @ 1.46 KiB/s"
+    );
+    assert_eq!(
+        render::spinner_line(&progress, Glyphs::Unicode),
+        "[██████░░░░░░░░░░░░░░] 30% Downloaded: 1.46 KiB / 4.88 KiB @ 1.46 KiB/s"
+    );
+    assert_eq!(
+        render::spinner_line(&progress, Glyphs::Ascii),
+        "[######--------------] 30% Downloaded: 1.46 KiB / 4.88 KiB @ 1.46 KiB/s"
+    );
+
+    let samples = [0, 1, 2, 3, 4, 5, 6, 7];
+    assert_eq!(Glyphs::Unicode.sparkline(&samples), "▁▂▃▄▅▆▇█");
+    assert_eq!(Glyphs::Ascii.sparkline(&samples), "_.,-~=*#");
+}
+
+#[test]
+fn downloading_chunks_fill_up_an_eighth_at_a_time() {
+    colored::control::set_override(false);
+    let progress = TransferProgress::chunked(4, 4_000, interrupted());
+    for chunk_id in 0..4 {
+        let start = chunk_id as u64 * 1_000;
+        progress.set_chunk_range(chunk_id, (start, start + 999));
+        progress.set_chunk_state(
+            chunk_id,
+            ChunkState::Downloading {
+                worker_id: chunk_id,
+            },
+        );
+    }
+    progress.update_chunk_bytes(1, 100);
+    progress.update_chunk_bytes(2, 500);
+    progress.update_chunk_bytes(3, 1_000);
+    assert_eq!(
+        progress.chunk_ranges(),
+        vec![
+            Some((0, 999)),
+            Some((1_000, 1_999)),
+            Some((2_000, 2_999)),
+            Some((3_000, 3_999))
+        ]
+    );
+    assert!(progress.chunks().iter().all(|chunk| !chunk.stuck));
+
+    assert!(
+        render::chunk_line(&progress, Glyphs::Unicode).starts_with("[▁▁▄█] Downloaded: "),
+        "{}",
+        render::chunk_line(&progress, Glyphs::Unicode)
+    );
+    assert!(
+        render::chunk_line(&progress, Glyphs::Ascii).starts_with("[__-#] 40% Downloaded: "),
+        "{}",
+        render::chunk_line(&progress, Glyphs::Ascii)
+    );
+
+    // Once split, it only has the first half to download.
+    progress.set_chunk_range(3, (3_000, 3_499));
+    progress.set_chunk_state(3, ChunkState::Completed);
+    assert_eq!(progress.chunks()[3].len, 500);
+}
+
+#[test]
+fn the_spinner_line_has_a_bar_only_once_the_total_is_known() {
+    colored::control::set_override(false);
+    let progress = TransferProgress::new(interrupted());
+    progress.set_downloaded(1_500);
+    assert_eq!(
+        render::spinner_line(&progress, Glyphs::Unicode),
+        "Downloaded: 1.46 KiB @ 1.46 KiB/s"
+    );
+    progress.set_total(6_000);
+    assert_eq!(
+        render::spinner_line(&progress, Glyphs::Ascii),
+        "[#####---------------] 25% Downloaded: 1.46 KiB / 5.86 KiB @ 1.46 KiB/s"
+    );
+}
+
+#[test]
+fn the_time_left_goes_by_this_runs_bytes_not_the_resumed_ones() {
+    let progress = TransferProgress::new(interrupted());
+    // Resumed at 1000 of 5000 bytes, and 1000 more since.
+    progress.set_prefix(1_000);
+    progress.set_total(5_000);
+    progress.set_downloaded(2_000);
+    assert_eq!(progress.eta(), None);
+    std::thread::sleep(Duration::from_secs(1));
+    // 3000 left at 1000 a second or so.
+    let eta = progress.eta().unwrap();
+    assert!(
+        (Duration::from_secs(3)..Duration::from_secs(6)).contains(&eta),
+        "{eta:?}"
+    );
+    assert!(render::spinner_line(&progress, Glyphs::Unicode).ends_with(" left"));
+}
+
+#[test]
+fn ascii_is_a_flag() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("ascii_is_a_flag");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--ascii",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir, sha256_hex};
+use serde_json::Value;
+use std::time::Duration;
+
+/// The JSON lines of `output`, leaving out anything else written there.
+fn events(output: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn json_progress_reports_a_download_from_start_to_end() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("json_progress_reports_a_download_from_start_to_end");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "json",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    let path = dir.join("file.bin");
+    assert_downloaded(&output, &path, &data);
+    let events = events(&output.stderr);
+    let kinds: Vec<_> = events.iter().map(|event| &event["event"]).collect();
+    assert_eq!(kinds[0], "started", "{kinds:?}");
+    assert_eq!(events[0]["url"], server.url("/file.bin"));
+    assert_eq!(
+        kinds
+            .iter()
+            .filter(|kind| **kind == "chunk_completed")
+            .count(),
+        3,
+        "{kinds:?}"
+    );
+    let last_progress = events
+        .iter()
+        .rfind(|event| event["event"] == "progress")
+        .unwrap();
+    assert_eq!(last_progress["downloaded"], 300_000);
+    assert_eq!(last_progress["total"], 300_000);
+
+    let completed = events.last().unwrap();
+    assert_eq!(completed["event"], "completed", "{kinds:?}");
+    assert_eq!(completed["path"], path.to_str().unwrap());
+    assert_eq!(completed["bytes"], 300_000);
+    assert_eq!(completed["sha256"], sha256_hex(&data));
+    assert!(completed["duration_ms"].is_u64());
+    // Nothing meant for a terminal got in between.
+    assert!(!String::from_utf8_lossy(&output.stderr).contains('\x1b'));
+}
+
+#[test]
+fn a_resumed_download_counts_what_it_had_towards_its_total() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    for mode in ["download-async", "download-blocking"] {
+        let dir = scratch_dir(&format!(
+            "a_resumed_download_counts_what_it_had_towards_its_total_{mode}"
+        ));
+        std::fs::write(dir.join("file.bin.part"), &data[..120_000]).unwrap();
+
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--resume",
+            "--progress",
+            "json",
+            &server.url("/file.bin"),
+            mode,
+        ]);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let progress: Vec<_> = events(&output.stderr)
+            .into_iter()
+            .filter(|event| event["event"] == "progress" && event["total"] != 0)
+            .collect();
+        // The 206 sent 180000 bytes of the 300000.
+        assert!(
+            progress
+                .iter()
+                .all(|event| event["total"] == 300_000
+                    && event["downloaded"].as_u64() <= Some(300_000)),
+            "{mode}: {progress:?}"
+        );
+        assert_eq!(progress.last().unwrap()["downloaded"], 300_000, "{mode}");
+    }
+}
+
+#[test]
+fn json_progress_ends_a_failed_run_with_its_error() {
+    let server = TestServer::builder(payload(1_000))
+        .handler(|_, _| Some(Response::new(404, "gone")))
+        .start();
+    let dir = scratch_dir("json_progress_ends_a_failed_run_with_its_error");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "json",
+        "--progress-stdout",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let events = events(&output.stdout);
+    let error = events.last().unwrap();
+    assert_eq!(error["event"], "error", "{events:?}");
+    assert_eq!(
+        error["exit_code"],
+        output.status.code().unwrap(),
+        "{events:?}"
+    );
+    assert!(
+        error["message"].as_str().unwrap().contains("404"),
+        "{error}"
+    );
+}
+
+#[test]
+fn plain_progress_writes_lines_without_escape_codes() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .drip(20_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("plain_progress_writes_lines_without_escape_codes");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "plain",
+        "--plain-interval",
+        "100ms",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Downloaded: "), "{stderr}");
+    assert!(!stderr.contains('\x1b'), "{stderr:?}");
+}
+
+#[test]
+fn no_progress_silences_the_keepalive_too() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .drip(20_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("no_progress_silences_the_keepalive_too");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "none",
+        "--keepalive-output",
+        "100ms",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Downloaded: "), "{stderr}");
+}
+
+#[test]
+fn an_unknown_progress_mode_is_a_usage_error() {
+    let output = run_dlm(&[
+        "--progress",
+        "fancy",
+        "http://example.com/",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("unknown progress mode 'fancy', expected bar, plain, json or none")
+    );
+}
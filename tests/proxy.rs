@@ -0,0 +1,183 @@
+mod common;
+
+use common::{Request, Response, TestServer, dlm, payload, scratch_dir};
+use download_manager::{ClientOptions, Downloader, TransferProgress};
+use std::process::Output;
+
+/// `alice:s3cret`, Basic-encoded.
+const CREDENTIALS: &str = "Basic YWxpY2U6czNjcmV0";
+
+/// A proxy in front of `data` that wants `challenge`, and lets through
+/// requests that answer it with [`CREDENTIALS`].
+fn proxy(data: Vec<u8>, challenge: &'static str) -> TestServer {
+    TestServer::builder(data)
+        .handler(move |request: &Request, _| {
+            (request.header("proxy-authorization") != Some(CREDENTIALS)).then(|| {
+                Response::new(407, "Access denied").header("Proxy-Authenticate", challenge)
+            })
+        })
+        .start()
+}
+
+/// Downloads a made-up host through `proxy`, which serves it.
+fn through(proxy: &TestServer, name: &str, args: &[&str]) -> Output {
+    let dir = scratch_dir(name);
+    dlm()
+        .env("HTTP_PROXY", proxy.url("/"))
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .env_remove("DM_PROXY_USER")
+        .env_remove("DM_PROXY_PASSWORD")
+        .args(["-t", dir.to_str().unwrap()])
+        .args(args)
+        .args(["http://files.example.test/file.bin", "download-async"])
+        .args(["--workers", "3"])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn a_proxy_wanting_credentials_is_named() {
+    let server = proxy(payload(1_000), r#"Basic realm="corp""#);
+    let output = through(&server, "a_proxy_wanting_credentials_is_named", &[]);
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "The proxy {} wants credentials (407 Proxy Authentication Required, Basic)",
+            server.url("/")
+        )),
+        "{stderr}"
+    );
+    assert!(stderr.contains("--proxy-user"), "{stderr}");
+}
+
+#[test]
+fn basic_credentials_reach_every_chunk() {
+    let data = payload(300_000);
+    let server = proxy(data.clone(), r#"Basic realm="corp""#);
+    let name = "basic_credentials_reach_every_chunk";
+    let dir = scratch_dir(name);
+    let output = dlm()
+        .env("HTTP_PROXY", server.url("/"))
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .env("DM_PROXY_PASSWORD", "s3cret")
+        .args(["-t", dir.to_str().unwrap(), "--proxy-user", "alice"])
+        .args(["http://files.example.test/file.bin", "download-async"])
+        .args(["--workers", "3"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    // The proxy turns away anything without them, chunk requests included.
+    let ranges = server
+        .requests()
+        .iter()
+        .filter(|request| request.header("range").is_some())
+        .count();
+    assert!(ranges >= 3, "{ranges}");
+}
+
+#[test]
+fn the_proxy_flag_is_used_over_the_environment() {
+    let data = payload(100_000);
+    let server = proxy(data.clone(), r#"Basic realm="corp""#);
+    let dir = scratch_dir("the_proxy_flag_is_used_over_the_environment");
+    let output = dlm()
+        .env("HTTP_PROXY", "http://127.0.0.1:9")
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .args(["-t", dir.to_str().unwrap(), "--proxy", &server.url("/")])
+        .args(["--proxy-user", "alice", "--proxy-password", "s3cret"])
+        .args(["http://files.example.test/file.bin", "download-async"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+}
+
+#[test]
+fn refused_credentials_are_told_apart() {
+    let server = proxy(payload(1_000), r#"Basic realm="corp""#);
+    let output = through(
+        &server,
+        "refused_credentials_are_told_apart",
+        &["--proxy-user", "alice", "--proxy-password", "wrong"],
+    );
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("refused the credentials"), "{stderr}");
+}
+
+#[test]
+fn unsupported_schemes_are_named() {
+    let server = proxy(payload(1_000), "NTLM");
+    let output = through(&server, "unsupported_schemes_are_named", &[]);
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("wants NTLM credentials") && stderr.contains("only Basic"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_refused_tunnel_is_a_proxy_error() {
+    let server = proxy(payload(1_000), r#"Basic realm="corp""#);
+    let dir = scratch_dir("a_refused_tunnel_is_a_proxy_error");
+    let output = dlm()
+        .env("HTTPS_PROXY", server.url("/"))
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .args(["-t", dir.to_str().unwrap()])
+        .args(["https://files.example.test/file.bin", "download-async"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The proxy wants credentials (407 Proxy Authentication Required)"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn clients_go_through_proxies_of_their_own() {
+    let data = payload(20_000);
+    let first = TestServer::builder(data.clone()).start();
+    let second = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("clients_go_through_proxies_of_their_own");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    runtime.block_on(async {
+        let downloads = [(&first, "first"), (&second, "second")].map(|(proxy, name)| {
+            let downloader = Downloader::with_client_options(ClientOptions {
+                proxy: Some(proxy.url("/").parse().unwrap()),
+                ..ClientOptions::default()
+            })
+            .unwrap()
+            .with_target_dir(dir.join(name));
+            async move {
+                downloader
+                    .download(
+                        "http://files.example.test/file.bin".parse().unwrap(),
+                        TransferProgress::new(Default::default()),
+                    )
+                    .await
+            }
+        });
+        let [first, second] = downloads;
+        let (first, second) = tokio::join!(first, second);
+        first.unwrap();
+        second.unwrap();
+    });
+
+    for (proxy, name) in [(&first, "first"), (&second, "second")] {
+        assert_eq!(
+            std::fs::read(dir.join(name).join("file.bin")).unwrap(),
+            data
+        );
+        assert_eq!(proxy.requests().len(), 1, "{name}: {:?}", proxy.requests());
+    }
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{Request, Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A CDN that answers each range request for `/file.bin` with a redirect
+/// to a URL signed for it, which is refused once it's been used. The first
+/// chunk's first answer is cut short, for it to retry.
+fn signing_cdn(data: Vec<u8>) -> impl Fn(&Request, usize) -> Option<Response> {
+    let used = Mutex::new(HashSet::new());
+    let cut = AtomicBool::new(false);
+    move |request, sequence| {
+        let range = request.header("Range")?;
+        if request.path == "/file.bin" {
+            let signed = format!("/signed/{sequence}/file.bin");
+            return Some(Response::new(302, Vec::new()).header("Location", signed));
+        }
+        if !used.lock().unwrap().insert(request.path.clone()) {
+            return Some(Response::new(403, "expired"));
+        }
+        let end = range.strip_prefix("bytes=0-")?.parse::<usize>().ok()?;
+        if end == 0 || cut.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(
+            Response::new(206, data[..30_000].to_vec())
+                .header("Content-Range", format!("bytes 0-{end}/{}", data.len()))
+                .header("Content-Length", (end + 1).to_string()),
+        )
+    }
+}
+
+#[test]
+fn a_retry_follows_the_redirect_again_once_its_signed_url_expired() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .handler(signing_cdn(data.clone()))
+        .start();
+    let dir = scratch_dir("a_retry_follows_the_redirect_again_once_its_signed_url_expired");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Redirects:\n  chunk 0: 2 followed, 1 expired\n  chunk 1: 1 followed"),
+        "{stdout}"
+    );
+    let requests = server.requests();
+    let signed: Vec<_> = requests
+        .iter()
+        .filter(|request| request.path.starts_with("/signed/"))
+        .collect();
+    assert!(
+        signed
+            .iter()
+            .all(|request| request.header("Range").is_some()),
+        "{signed:?}"
+    );
+    // A retry went straight back to a signed URL before following the
+    // redirect again.
+    let distinct: HashSet<_> = signed.iter().map(|request| &request.path).collect();
+    assert!(distinct.len() < signed.len(), "{signed:?}");
+}
+
+#[test]
+fn a_redirect_answering_another_range_fails_the_chunk() {
+    let data = payload(200_000);
+    let total = data.len();
+    let first = data[..50_000].to_vec();
+    let server = TestServer::builder(data)
+        .handler(move |request, _| {
+            if request.method != "GET" || request.header("Range") == Some("bytes=0-0") {
+                return None;
+            }
+            match request.path.as_str() {
+                "/file.bin" => {
+                    Some(Response::new(302, Vec::new()).header("Location", "/signed/file.bin"))
+                }
+                // As if the hop dropped the Range header's start.
+                _ => Some(
+                    Response::new(206, first.clone())
+                        .header("Content-Range", format!("bytes 0-49999/{total}")),
+                ),
+            }
+        })
+        .start();
+    let dir = scratch_dir("a_redirect_answering_another_range_fails_the_chunk");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("but it sent bytes 0-49999"), "{stderr}");
+    assert!(!dir.join("file.bin").exists());
+}
@@ -0,0 +1,226 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::rate_limit::{self, Quota};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+type Headers<'a> = &'a [(&'a str, &'a str)];
+
+fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            (
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn the_quota_is_read_from_any_of_the_headers() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let in_30s = Some(now + Duration::from_secs(30));
+    let cases: [(Headers, Option<Quota>); 7] = [
+        // GitHub's, reset as a Unix time.
+        (
+            &[
+                ("X-RateLimit-Limit", "60"),
+                ("X-RateLimit-Remaining", "12"),
+                ("X-RateLimit-Reset", "1700000030"),
+            ],
+            Some(Quota {
+                limit: Some(60),
+                remaining: 12,
+                reset: in_30s,
+            }),
+        ),
+        // The IETF draft's, reset in seconds, the limit with its policy.
+        (
+            &[
+                ("RateLimit-Limit", "100, 100;w=60"),
+                ("RateLimit-Remaining", "3"),
+                ("RateLimit-Reset", "30"),
+            ],
+            Some(Quota {
+                limit: Some(100),
+                remaining: 3,
+                reset: in_30s,
+            }),
+        ),
+        (
+            &[("RateLimit", "limit=100, remaining=50, reset=30")],
+            Some(Quota {
+                limit: Some(100),
+                remaining: 50,
+                reset: in_30s,
+            }),
+        ),
+        (
+            &[("RateLimit", "\"default\";r=0;t=30")],
+            Some(Quota {
+                limit: None,
+                remaining: 0,
+                reset: in_30s,
+            }),
+        ),
+        // Retry-After stands in for a missing reset.
+        (
+            &[("X-RateLimit-Remaining", "0"), ("Retry-After", "30")],
+            Some(Quota {
+                limit: None,
+                remaining: 0,
+                reset: in_30s,
+            }),
+        ),
+        (&[("X-RateLimit-Limit", "60")], None),
+        (&[("X-RateLimit-Remaining", "soon")], None),
+    ];
+    for (pairs, expected) in cases {
+        assert_eq!(Quota::parse(&headers(pairs), now), expected, "{pairs:?}");
+    }
+}
+
+#[test]
+fn requests_wait_for_the_reset_once_few_are_left() {
+    let now = SystemTime::now();
+    let quota = |remaining, reset: Option<Duration>| Quota {
+        limit: Some(60),
+        remaining,
+        reset: reset.map(|reset| now + reset),
+    };
+    let minute = Duration::from_secs(60);
+    let cases = [
+        (
+            quota(rate_limit::LOW, Some(Duration::from_secs(30))),
+            minute,
+            None,
+        ),
+        (
+            quota(rate_limit::LOW - 1, Some(Duration::from_secs(30))),
+            minute,
+            Some(Duration::from_secs(30)),
+        ),
+        // No longer than --max-rate-limit-wait, and not at all with 0.
+        (
+            quota(0, Some(Duration::from_secs(600))),
+            minute,
+            Some(minute),
+        ),
+        (
+            quota(0, Some(Duration::from_secs(30))),
+            Duration::ZERO,
+            None,
+        ),
+        // Already reset, or no saying when.
+        (quota(0, None), minute, None),
+    ];
+    for (quota, max, expected) in cases {
+        assert_eq!(quota.pause(now, max), expected, "{quota:?}, {max:?}");
+    }
+    let passed = Quota {
+        reset: Some(now - Duration::from_secs(1)),
+        ..quota(0, None)
+    };
+    assert_eq!(passed.pause(now, minute), None);
+}
+
+#[test]
+fn a_403_is_rate_limited_only_with_the_headers_to_say_so() {
+    let cases: [(StatusCode, Headers, bool); 5] = [
+        (StatusCode::TOO_MANY_REQUESTS, &[], true),
+        (
+            StatusCode::FORBIDDEN,
+            &[("X-RateLimit-Remaining", "0")],
+            true,
+        ),
+        (StatusCode::FORBIDDEN, &[("Retry-After", "60")], true),
+        (
+            StatusCode::FORBIDDEN,
+            &[("X-RateLimit-Remaining", "40")],
+            false,
+        ),
+        (StatusCode::FORBIDDEN, &[], false),
+    ];
+    for (status, pairs, expected) in cases {
+        assert_eq!(
+            rate_limit::is_rate_limited(status, &headers(pairs)),
+            expected,
+            "{status} {pairs:?}"
+        );
+    }
+}
+
+#[test]
+fn a_download_waits_while_the_server_has_few_requests_left() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .header("X-RateLimit-Limit", "60")
+        .header("X-RateLimit-Remaining", "1")
+        .header("X-RateLimit-Reset", "30")
+        .start();
+    let dir = scratch_dir("a_download_waits_while_the_server_has_few_requests_left");
+
+    let started = Instant::now();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--progress",
+        "plain",
+        "--max-rate-limit-wait",
+        "1s",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    // The chunks' requests wait on what the probe found, once.
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("has 1 of 60 requests left, resetting in ")
+            && stderr.contains("waiting 1s before the next request (--max-rate-limit-wait)"),
+        "{stderr}"
+    );
+    assert_eq!(
+        stderr.matches("--max-rate-limit-wait").count(),
+        1,
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_rate_limited_403_is_told_apart_from_a_refusal() {
+    let server = TestServer::builder(payload(1000))
+        .handler(|_, _| {
+            Some(
+                Response::new(403, "API rate limit exceeded")
+                    .header("Content-Type", "text/plain")
+                    .header("X-RateLimit-Remaining", "0")
+                    .header("X-RateLimit-Reset", "30"),
+            )
+        })
+        .start();
+    let dir = scratch_dir("a_rate_limited_403_is_told_apart_from_a_refusal");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--max-rate-limit-wait",
+        "0",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Rate limited by the server (403 Forbidden), the limit resets in 30s (API rate limit exceeded); try again later"
+        ),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "releases")]
+
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::{Value, json};
+
+/// A releases API whose latest release of any project is `tag`, with an
+/// asset for each of `names` on `assets`. GitLab's is told apart by its
+/// path.
+fn api(tag: &str, names: &[&str], assets: &TestServer) -> TestServer {
+    let github = json!({
+        "tag_name": tag,
+        "assets": names
+            .iter()
+            .map(|name| json!({"name": name, "browser_download_url": assets.url(&format!("/download/{name}"))}))
+            .collect::<Vec<_>>(),
+    });
+    let gitlab = json!({
+        "tag_name": tag,
+        "assets": {
+            "links": names
+                .iter()
+                .map(|name| json!({"name": name, "url": assets.url(&format!("/links/{name}"))}))
+                .collect::<Vec<_>>(),
+        },
+    });
+    TestServer::builder(Vec::new())
+        .handler(move |request, _| {
+            let release = match request.path.as_str() {
+                path if path.starts_with("/repos/") => &github,
+                path if path.starts_with("/projects/") => &gitlab,
+                _ => return Some(Response::new(404, "{}")),
+            };
+            Some(Response::new(200, release.to_string()).header("Content-Type", "application/json"))
+        })
+        .start()
+}
+
+#[test]
+fn the_asset_matching_the_pattern_is_downloaded() {
+    let data = payload(50_000);
+    let assets = TestServer::builder(data.clone()).start();
+    let api = api(
+        "v1.2.0",
+        &[
+            "tool-1.2.0-darwin-arm64.tar.gz",
+            "tool-1.2.0-linux-x86_64.tar.gz",
+        ],
+        &assets,
+    );
+    let dir = scratch_dir("the_asset_matching_the_pattern_is_downloaded");
+    let state = dir.join("state");
+
+    let output = run_dlm(&[
+        "--state-dir",
+        state.to_str().unwrap(),
+        "-t",
+        dir.to_str().unwrap(),
+        "--releases-api",
+        &api.url("/"),
+        "--bearer-token",
+        "s3cret",
+        "--github-latest",
+        "acme/tool",
+        "--asset-pattern",
+        "*linux-x86_64*",
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("tool-1.2.0-linux-x86_64.tar.gz"), &data);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains(
+            "github://acme/tool/*linux-x86_64* resolved to tool-1.2.0-linux-x86_64.tar.gz of release v1.2.0"
+        ),
+        "{output:?}"
+    );
+    let requests = api.requests();
+    assert_eq!(requests[0].path, "/repos/acme/tool/releases/latest");
+    assert_eq!(requests[0].header("Authorization"), Some("Bearer s3cret"));
+    // The token is for the API only.
+    assert!(
+        assets
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization").is_none())
+    );
+
+    let usage: Value = serde_json::from_str(
+        std::fs::read_to_string(state.join("usage.jsonl"))
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(usage["release"], "github://acme/tool/*linux-x86_64*");
+    assert_eq!(
+        usage["url"],
+        assets.url("/download/tool-1.2.0-linux-x86_64.tar.gz")
+    );
+}
+
+#[test]
+fn a_pattern_matching_several_assets_lists_them() {
+    let assets = TestServer::builder(payload(1_000)).start();
+    let api = api(
+        "v2.0",
+        &["tool-2.0-linux.zip", "tool-2.0-windows.zip", "SHA256SUMS"],
+        &assets,
+    );
+    let dir = scratch_dir("a_pattern_matching_several_assets_lists_them");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--releases-api",
+        &api.url("/"),
+        "gitlab://group/sub/tool/*.zip",
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Release v2.0 of 'group/sub/tool' has several of its 3 assets matching '*.zip'"
+        ),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("  tool-2.0-linux.zip\n  tool-2.0-windows.zip"),
+        "{stderr}"
+    );
+    assert_eq!(
+        api.requests()[0].path,
+        "/projects/group%2Fsub%2Ftool/releases/permalink/latest"
+    );
+    assert!(assets.requests().is_empty());
+}
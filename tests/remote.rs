@@ -0,0 +1,190 @@
+mod common;
+
+use common::{Request, Response, TestServer, payload, run_dlm, scratch_dir};
+use download_manager::__private::remote::{ProbeMethod, RemoteInfo, probe_remote};
+use download_manager::RetryBudget;
+use url::Url;
+
+const SIZE: usize = 100_000;
+
+/// Probes `path` on `server`.
+fn probe(server: &TestServer, path: &str) -> anyhow::Result<RemoteInfo> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let url = Url::parse(&server.url(path)).unwrap();
+    runtime.block_on(probe_remote(
+        &reqwest::Client::new(),
+        &url,
+        &RetryBudget::default(),
+        None,
+    ))
+}
+
+/// The method and `Range` of every request `server` got.
+fn asked(server: &TestServer) -> Vec<(String, Option<String>)> {
+    server
+        .requests()
+        .iter()
+        .map(|request| {
+            let range = request.header("Range").map(str::to_string);
+            (request.method.clone(), range)
+        })
+        .collect()
+}
+
+/// A server whose answer to HEAD is `head`, once past any redirect.
+fn server_with_head(head: impl Fn(&Request) -> Response + Send + Sync + 'static) -> TestServer {
+    TestServer::builder(payload(SIZE))
+        .handler(move |request, _| {
+            (request.method == "HEAD" && !request.path.starts_with("/redirect"))
+                .then(|| head(request))
+        })
+        .start()
+}
+
+fn first_byte() -> (String, Option<String>) {
+    ("GET".to_string(), Some("bytes=0-0".to_string()))
+}
+
+#[test]
+fn head_answers_when_it_works() {
+    let server = server_with_head(|_| {
+        Response::new(200, payload(SIZE))
+            .header("Accept-Ranges", "bytes")
+            .header("ETag", "\"v1\"")
+            .header("Last-Modified", "Wed, 01 May 2024 12:00:00 GMT")
+            .header("Content-Type", "application/octet-stream")
+    });
+
+    let remote = probe(&server, "/redirect/file.bin").unwrap();
+    assert_eq!(remote.method, ProbeMethod::Head);
+    assert_eq!(remote.size, Some(SIZE as u64));
+    assert!(remote.ranges);
+    assert_eq!(remote.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(
+        remote.last_modified.as_deref(),
+        Some("Wed, 01 May 2024 12:00:00 GMT")
+    );
+    assert_eq!(
+        remote.content_type.as_deref(),
+        Some("application/octet-stream")
+    );
+    assert_eq!(remote.final_url.path(), "/file.bin");
+    assert_eq!(asked(&server).len(), 2);
+}
+
+#[test]
+fn refused_or_empty_heads_fall_back_to_a_range_get() {
+    for head in [
+        Response::new(405, "Method Not Allowed"),
+        // A script that answers HEAD without looking at the file.
+        Response::new(200, Vec::new()),
+    ] {
+        let status = head.status;
+        let server = server_with_head(move |_| head.clone());
+
+        let remote = probe(&server, "/file.bin").unwrap();
+        assert_eq!(remote.method, ProbeMethod::RangeGet, "{status}");
+        assert_eq!(remote.size, Some(SIZE as u64), "{status}");
+        assert!(remote.ranges, "{status}");
+        assert_eq!(
+            asked(&server),
+            [("HEAD".to_string(), None), first_byte()],
+            "{status}"
+        );
+    }
+}
+
+#[test]
+fn an_ignored_range_means_no_range_support() {
+    let server = TestServer::builder(payload(SIZE))
+        .no_ranges()
+        .handler(|request, _| (request.method == "HEAD").then(|| Response::new(405, "")))
+        .start();
+
+    let remote = probe(&server, "/file.bin").unwrap();
+    assert_eq!(remote.method, ProbeMethod::RangeGet);
+    assert_eq!(remote.size, Some(SIZE as u64));
+    assert!(!remote.ranges);
+}
+
+#[test]
+fn a_refused_range_falls_back_to_a_plain_get() {
+    let server = TestServer::builder(payload(SIZE))
+        .no_ranges()
+        .handler(|request, _| match request.method.as_str() {
+            "HEAD" => Some(Response::new(405, "")),
+            _ if request.header("Range").is_some() => Some(Response::new(400, "Bad Request")),
+            _ => None,
+        })
+        .start();
+
+    let remote = probe(&server, "/file.bin").unwrap();
+    assert_eq!(remote.method, ProbeMethod::Get);
+    assert_eq!(remote.size, Some(SIZE as u64));
+    assert!(!remote.ranges);
+    assert_eq!(
+        asked(&server),
+        [
+            ("HEAD".to_string(), None),
+            first_byte(),
+            ("GET".to_string(), None)
+        ]
+    );
+}
+
+#[test]
+fn a_missing_file_isnt_asked_for_three_times() {
+    let server = TestServer::builder(Vec::new())
+        .handler(|_, _| Some(Response::new(404, "Not Found")))
+        .start();
+
+    let error = probe(&server, "/file.bin").unwrap_err();
+    assert!(error.to_string().contains("404"), "{error}");
+    assert_eq!(asked(&server), [("HEAD".to_string(), None), first_byte()]);
+}
+
+#[test]
+fn presigned_urls_skip_head() {
+    let server = TestServer::builder(payload(SIZE)).start();
+
+    let remote = probe(&server, "/file.bin?X-Amz-Signature=0123abcd").unwrap();
+    assert_eq!(remote.method, ProbeMethod::RangeGet);
+    assert_eq!(remote.size, Some(SIZE as u64));
+    assert_eq!(asked(&server), [first_byte()]);
+}
+
+#[test]
+fn workers_download_from_servers_refusing_head() {
+    let data = payload(SIZE);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| (request.method == "HEAD").then(|| Response::new(405, "")))
+        .start();
+    let dir = scratch_dir("workers_download_from_servers_refusing_head");
+    let url = server.url("/file.bin");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--dry-run",
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Asked with:  a one-byte range GET"),
+        "{stdout}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    common::assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{TestServer, payload, run_dlm, scratch_dir, sha256_hex};
+use std::fs;
+
+#[test]
+fn compare_rewrites_only_the_bad_ranges() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("compare_rewrites_only_the_bad_ranges");
+    let local = dir.join("file.bin");
+    let mut damaged = data[..250_000].to_vec();
+    damaged[100_000] ^= 0xff;
+    fs::write(&local, damaged).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "repair",
+        "--compare",
+        "--sample-size",
+        "64k",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read(&local).unwrap(), data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(2 ranges)"), "{stdout}");
+    assert!(stdout.contains("Re-fetched bytes 65536-131071"), "{stdout}");
+    assert!(
+        stdout.contains("Re-fetched bytes 196608-299999"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn piece_hashes_fetch_only_the_corrupt_piece() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("piece_hashes_fetch_only_the_corrupt_piece");
+    let hashes = dir.join("pieces.txt");
+    let mut list = String::from("64K\n");
+    for piece in data.chunks(65_536) {
+        list.push_str(&sha256_hex(piece));
+        list.push('\n');
+    }
+    fs::write(&hashes, list).unwrap();
+    let local = dir.join("copy.bin");
+    let mut damaged = data.clone();
+    damaged[140_000] ^= 0xff;
+    fs::write(&local, damaged).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--piece-hashes",
+        hashes.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "repair",
+        "--file",
+        local.to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read(&local).unwrap(), data);
+    let ranges: Vec<String> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    assert_eq!(ranges, ["bytes=0-0", "bytes=131072-196607"]);
+}
+
+#[test]
+fn repair_refuses_servers_without_ranges() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).no_ranges().start();
+    let dir = scratch_dir("repair_refuses_servers_without_ranges");
+    let local = dir.join("file.bin");
+    fs::write(&local, &data[..5_000]).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "repair",
+        "--compare",
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("supports range requests"), "{stderr}");
+    assert_eq!(fs::read(&local).unwrap(), &data[..5_000]);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::path::Path;
+
+/// Records a four-worker download from a server dropping every response
+/// after 20,000 bytes into `dir/transcript.jsonl`.
+fn record(dir: &Path, url: &str) {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .fail_after(20_000, 100)
+        .start();
+    let url = server.url("/file.bin").replace("127.0.0.1", url);
+    let transcript = dir.join("transcript.jsonl");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--record",
+        transcript.to_str().unwrap(),
+        &url,
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+fn entries(transcript: &Path) -> Vec<Value> {
+    std::fs::read_to_string(transcript)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn a_recorded_download_replays_the_same_without_the_server() {
+    let dir = scratch_dir("a_recorded_download_replays_the_same_without_the_server");
+    record(&dir, "user:hunter2@127.0.0.1");
+    let transcript = dir.join("transcript.jsonl");
+
+    let entries = entries(&transcript);
+    assert_eq!(entries[0]["event"], "start");
+    assert_eq!(entries.last().unwrap()["event"], "outcome");
+    assert_eq!(entries.last().unwrap()["exit_code"], 0);
+    for event in ["request", "response", "scheduled", "retry"] {
+        assert!(
+            entries.iter().any(|entry| entry["event"] == event),
+            "no {event} in {entries:?}"
+        );
+    }
+    let text = std::fs::read_to_string(&transcript).unwrap();
+    assert!(!text.contains("hunter2"), "{text}");
+
+    // The server is gone by now: the replay answers as it did.
+    let output = run_dlm(&["replay", transcript.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        stdout.contains("Replay matched the recording: ")
+            && stdout.contains("over 4 chunks, exit code 0"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn a_replay_deciding_differently_fails() {
+    let dir = scratch_dir("a_replay_deciding_differently_fails");
+    record(&dir, "127.0.0.1");
+    let transcript = dir.join("transcript.jsonl");
+    // As if the recorded run had failed.
+    let text = std::fs::read_to_string(&transcript)
+        .unwrap()
+        .replace("\"exit_code\":0", "\"exit_code\":9");
+    std::fs::write(&transcript, text).unwrap();
+
+    let output = run_dlm(&["replay", transcript.to_str().unwrap()]);
+    assert!(!output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("exit code"), "{stdout}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("diverged from the recording"),
+        "{output:?}"
+    );
+}
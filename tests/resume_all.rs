@@ -0,0 +1,313 @@
+mod common;
+
+use common::{TestServer, dlm, payload, run_dlm, scratch_dir};
+use serde_json::{Value, json};
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Leaves behind what a download of `url` into `dir`, cut off after
+/// `downloaded` bytes of `data`, would: its manifest, last written long
+/// ago, and the start of the file.
+fn cut_off(state: &Path, dir: &Path, id: &str, url: &str, data: &[u8], downloaded: usize) {
+    let name = url.rsplit('/').next().unwrap();
+    fs::write(dir.join(name), &data[..downloaded]).unwrap();
+    let manifest = json!({
+        "id": id,
+        "url": url,
+        "destination": dir.join(name),
+        "cwd": dir,
+        "args": ["-t", dir, url, "download-async"],
+        "started": 0,
+        "updated": 0,
+        "downloaded": downloaded,
+        "total": data.len(),
+    });
+    fs::create_dir_all(state.join("active")).unwrap();
+    fs::write(
+        state.join(format!("active/{id}.json")),
+        serde_json::to_vec(&manifest).unwrap(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn every_cut_off_download_is_finished() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("every_cut_off_download_is_finished");
+    let state = dir.join("state");
+    cut_off(
+        &state,
+        &dir,
+        "aaaaaaaaaaaa",
+        &server.url("/one.bin"),
+        &data,
+        12_000,
+    );
+    cut_off(
+        &state,
+        &dir,
+        "bbbbbbbbbbbb",
+        &server.url("/two.bin"),
+        &data,
+        3_000,
+    );
+    // Parts of a download no manifest remembers the URL of.
+    fs::write(
+        dir.join("orphan.bin.0123abcd.plan"),
+        r#"{"id":"0123abcd","ranges":[[0,999]],"paths":[]}"#,
+    )
+    .unwrap();
+
+    let output = run_dlm(&[
+        "--state-dir",
+        state.to_str().unwrap(),
+        "resume-all",
+        dir.to_str().unwrap(),
+        "--parallel",
+        "2",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let listed = |id: &str| {
+        stdout
+            .lines()
+            .find(|line| line.starts_with(id))
+            .unwrap_or_else(|| panic!("{stdout}"))
+            .to_string()
+    };
+    assert!(listed("aaaaaaaaaaaa").contains(" 40%  "), "{stdout}");
+    assert!(listed("bbbbbbbbbbbb").contains(" 10%  "), "{stdout}");
+    assert!(
+        listed("-   ").contains("orphan.bin (0 B of 1000 B, no manifest has its URL"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Resumed 2 downloads: 2 completed, 0 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(fs::read(dir.join("one.bin")).unwrap(), data);
+    assert_eq!(fs::read(dir.join("two.bin")).unwrap(), data);
+    assert_eq!(fs::read_dir(state.join("active")).unwrap().count(), 0);
+}
+
+#[test]
+fn a_download_whose_remote_changed_is_skipped_unless_restarted() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_download_whose_remote_changed_is_skipped_unless_restarted");
+    let state = dir.join("state");
+    // It was 40,000 bytes when it was cut off.
+    let before = payload(40_000);
+    cut_off(
+        &state,
+        &dir,
+        "cccccccccccc",
+        &server.url("/file.bin"),
+        &before,
+        12_000,
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--state-dir",
+        state.to_str().unwrap(),
+        "resume-all",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "Download 1 of 1, cccccccccccc: skipped, the remote file is now 29.30 KiB instead of 39.06 KiB"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("0 completed, 0 failed, 1 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(fs::metadata(dir.join("file.bin")).unwrap().len(), 12_000);
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--state-dir",
+        state.to_str().unwrap(),
+        "--restart-on-unresumable",
+        "resume-all",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("starting it over"), "{stdout}");
+    assert!(
+        stdout.contains("1 completed, 0 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(fs::read(dir.join("file.bin")).unwrap(), data);
+}
+
+#[test]
+fn relative_paths_are_read_from_where_the_download_started() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("relative_paths_are_read_from_where_the_download_started");
+    let state = dir.join("state");
+    let url = server.url("/file.bin");
+    cut_off(&state, &dir, "ffffffffffff", &url, &data, 12_000);
+    // Started in `dir` as `dlm --state-dir state -t . <url> ...`.
+    let path = state.join("active/ffffffffffff.json");
+    let mut manifest: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    manifest["args"] = json!(["--state-dir", "state", "-t", ".", url, "download-async"]);
+    fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "resume-all"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(fs::read(dir.join("file.bin")).unwrap(), data);
+    // Its own state directory is where its manifest is, and gone from.
+    assert_eq!(fs::read_dir(state.join("active")).unwrap().count(), 0);
+    assert!(state.join("usage.jsonl").exists());
+}
+
+#[test]
+fn an_interrupted_resume_all_exits_as_an_interrupted_download_does() {
+    let data = payload(40_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("an_interrupted_resume_all_exits_as_an_interrupted_download_does");
+    let state = dir.join("state");
+    for (id, name) in [("dddddddddddd", "/one.bin"), ("eeeeeeeeeeee", "/two.bin")] {
+        cut_off(&state, &dir, id, &server.url(name), &data, 1_000);
+    }
+
+    let child = dlm()
+        .args(["--state-dir", state.to_str().unwrap()])
+        .args(["resume-all", dir.to_str().unwrap(), "--parallel", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(700));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(130), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" interrupted"), "{stdout}");
+    // Both are left for the next run.
+    assert_eq!(fs::read_dir(state.join("active")).unwrap().count(), 2);
+}
+
+#[test]
+fn each_download_keeps_its_own_proxy_and_memory_cap() {
+    let data = payload(30_000);
+    let one = TestServer::builder(data.clone()).start();
+    let two = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("each_download_keeps_its_own_proxy_and_memory_cap");
+    let state = dir.join("state");
+    // A made-up host, which only the proxy each was started with serves.
+    let flags = [
+        (&one, "aaaaaaaaaaab", "/one.bin", "64KiB"),
+        (&two, "aaaaaaaaaaac", "/two.bin", "2MiB"),
+    ];
+    for (proxy, id, name, max_memory) in flags {
+        let url = format!("http://files.example.test{name}");
+        cut_off(&state, &dir, id, &url, &data, 6_000);
+        let path = state.join(format!("active/{id}.json"));
+        let mut manifest: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        manifest["args"] = json!([
+            "-t",
+            dir,
+            "--proxy",
+            proxy.url("/"),
+            "--max-memory",
+            max_memory,
+            url,
+            "download-async"
+        ]);
+        fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+    }
+
+    let output = dlm()
+        .env_remove("HTTP_PROXY")
+        .env_remove("http_proxy")
+        .env_remove("ALL_PROXY")
+        .env_remove("all_proxy")
+        .args(["--state-dir", state.to_str().unwrap()])
+        .args(["resume-all", dir.to_str().unwrap(), "--parallel", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Resumed 2 downloads: 2 completed, 0 failed, 0 skipped"),
+        "{stdout}"
+    );
+    assert_eq!(fs::read(dir.join("one.bin")).unwrap(), data);
+    assert_eq!(fs::read(dir.join("two.bin")).unwrap(), data);
+    for (proxy, name) in [(&one, "/one.bin"), (&two, "/two.bin")] {
+        let requests = proxy.requests();
+        assert!(!requests.is_empty());
+        assert!(
+            requests.iter().all(|request| request.path.ends_with(name)),
+            "{name}: {:?}",
+            requests
+                .iter()
+                .map(|request| &request.path)
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn downloads_with_different_process_flags_are_not_resumed_together() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("downloads_with_different_process_flags_are_not_resumed_together");
+    let state = dir.join("state");
+    cut_off(
+        &state,
+        &dir,
+        "aaaaaaaaaaad",
+        &server.url("/one.bin"),
+        &data,
+        6_000,
+    );
+    cut_off(
+        &state,
+        &dir,
+        "aaaaaaaaaaae",
+        &server.url("/two.bin"),
+        &data,
+        6_000,
+    );
+    let path = state.join("active/aaaaaaaaaaae.json");
+    let mut manifest: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    manifest["args"] = json!([
+        "-t",
+        dir,
+        "--strict",
+        server.url("/two.bin"),
+        "download-async"
+    ]);
+    fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+    let output = run_dlm(&[
+        "--state-dir",
+        state.to_str().unwrap(),
+        "resume-all",
+        dir.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can't be resumed together"), "{stderr}");
+    // Neither was touched.
+    assert_eq!(fs::read_dir(state.join("active")).unwrap().count(), 2);
+    assert_eq!(fs::metadata(dir.join("one.bin")).unwrap().len(), 6_000);
+}
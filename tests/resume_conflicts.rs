@@ -0,0 +1,86 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::path::Path;
+use std::process::Output;
+
+const TOTAL: usize = 100_000;
+
+/// Two releases with the same file name and size, one per server.
+fn versions() -> ((TestServer, Vec<u8>), (TestServer, Vec<u8>)) {
+    let older = payload(TOTAL);
+    let newer: Vec<u8> = older.iter().map(|byte| byte ^ 0x5a).collect();
+    (
+        (TestServer::builder(older.clone()).start(), older),
+        (TestServer::builder(newer.clone()).start(), newer),
+    )
+}
+
+/// `--resume`s the download of `server`'s `release.tar.gz` into `dir`.
+fn resume(server: &TestServer, dir: &Path, extra: &[&str]) -> Output {
+    let url = server.url("/release.tar.gz");
+    let mut args = vec!["-t", dir.to_str().unwrap(), "--resume"];
+    args.extend_from_slice(extra);
+    args.extend([url.as_str(), "download-async"]);
+    run_dlm(&args)
+}
+
+#[test]
+fn another_version_with_the_same_name_is_saved_next_to_it() {
+    let ((older, old_data), (newer, new_data)) = versions();
+    let dir = scratch_dir("resume_conflict_renamed");
+    let release = dir.join("release.tar.gz");
+
+    // Complete, and as big as the new one.
+    std::fs::write(&release, &old_data).unwrap();
+    let output = resume(&newer, &dir, &[]);
+    assert_downloaded(&output, &dir.join("release (2).tar.gz"), &new_data);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Saving as '"),
+        "{output:?}"
+    );
+    assert_eq!(std::fs::read(&release).unwrap(), old_data);
+
+    // The start of the old one isn't carried on with the new one's tail.
+    std::fs::write(&release, &old_data[..40_000]).unwrap();
+    let output = resume(&newer, &dir, &[]);
+    assert_downloaded(&output, &dir.join("release (3).tar.gz"), &new_data);
+    assert_eq!(std::fs::read(&release).unwrap(), &old_data[..40_000]);
+
+    // Its own start is resumed as always.
+    std::fs::write(&release, &old_data[..40_000]).unwrap();
+    let output = resume(&older, &dir, &[]);
+    assert_downloaded(&output, &release, &old_data);
+    assert!(!dir.join("release (4).tar.gz").exists());
+    let requests = older.requests();
+    assert_eq!(
+        requests.last().unwrap().header("Range"),
+        Some("bytes=40000-")
+    );
+}
+
+#[test]
+fn on_conflict_skip_and_error_leave_the_file_alone() {
+    let ((_, old_data), (newer, _)) = versions();
+    let dir = scratch_dir("resume_conflict_refused");
+    let release = dir.join("release.tar.gz");
+    std::fs::write(&release, &old_data[..40_000]).unwrap();
+
+    let output = resume(&newer, &dir, &["--on-conflict", "skip"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Skipped "),
+        "{output:?}"
+    );
+
+    let output = resume(&newer, &dir, &["--on-conflict", "error"]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("bytes 0-39999 differ from the remote file"),
+        "{output:?}"
+    );
+
+    assert_eq!(std::fs::read(&release).unwrap(), &old_data[..40_000]);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
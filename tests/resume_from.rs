@@ -0,0 +1,307 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::parts::{PartLayout, ResumeFrom};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use url::Url;
+
+#[test]
+fn parts_are_kept_and_the_rest_planned_beside_the_new_file() {
+    let dir = scratch_dir("parts_are_kept_and_the_rest_planned_beside_the_new_file");
+    let (old, new) = (dir.join("old"), dir.join("new"));
+    std::fs::create_dir_all(&old).unwrap();
+    let url = Url::parse("http://example.com/file.bin").unwrap();
+    // An earlier --resume had kept 100 bytes; then three parts.
+    let earlier = PartLayout::new(&url, 1_000, vec![(100, 399), (400, 699), (700, 999)])
+        .beside(&old.join("file.bin"))
+        .unwrap();
+    std::fs::write(old.join("file.bin"), [0; 100]).unwrap();
+    std::fs::write(&earlier.paths[0], [0; 300]).unwrap();
+    std::fs::write(&earlier.paths[1], [0; 120]).unwrap();
+    std::fs::write(&earlier.paths[2], []).unwrap();
+
+    let resume_from = ResumeFrom {
+        file: old.join("file.bin"),
+        layout: earlier.clone(),
+    };
+    let resumption = resume_from
+        .carry_on(&url, 1_000, &new.join("file.bin"))
+        .unwrap();
+    assert_eq!(resumption.kept, 520);
+    assert_eq!(
+        resumption.layout.ranges,
+        [(0, 99), (100, 399), (400, 519), (520, 699), (700, 999)]
+    );
+    let new_part = |index| new.join(format!("file.bin.{}.p000{index}", earlier.id));
+    assert_eq!(
+        resumption.layout.paths,
+        [
+            old.join("file.bin"),
+            earlier.paths[0].clone(),
+            earlier.paths[1].clone(),
+            new_part(1),
+            new_part(2),
+        ]
+    );
+    assert_eq!(resumption.fetch, [3, 4]);
+    // The second part carries on 120 bytes in.
+    assert_eq!(resumption.segments(), [(120, 180), (0, 300)]);
+
+    // The remote file changed size since.
+    let error = resume_from
+        .carry_on(&url, 2_000, &new.join("file.bin"))
+        .unwrap_err();
+    assert!(error.to_string().contains("another download"), "{error}");
+}
+
+#[test]
+fn a_stopped_worker_download_is_finished_in_another_directory() {
+    let data = payload(300_000);
+    let failing = Arc::new(AtomicBool::new(true));
+    let server = TestServer::builder(data.clone())
+        .handler({
+            let failing = failing.clone();
+            move |request, _| {
+                let last = request
+                    .header("range")
+                    .is_some_and(|range| range.starts_with("bytes=200000-"));
+                (last && failing.load(Ordering::SeqCst)).then(|| Response::new(403, "no"))
+            }
+        })
+        .start();
+    let dir = scratch_dir("a_stopped_worker_download_is_finished_in_another_directory");
+    let (full, roomy, state) = (dir.join("full"), dir.join("roomy"), dir.join("state"));
+    let url = server.url("/file.bin");
+    let args = |target: &str| {
+        vec![
+            "-t".to_string(),
+            target.to_string(),
+            "--state-dir".to_string(),
+            state.to_str().unwrap().to_string(),
+        ]
+    };
+
+    let mut first = args(full.to_str().unwrap());
+    first.extend([
+        "--part-files".into(),
+        url.clone(),
+        "download-async".into(),
+        "--workers".into(),
+        "3".into(),
+    ]);
+    let output = run_dlm(&first.iter().map(String::as_str).collect::<Vec<_>>());
+    assert!(!output.status.success(), "{output:?}");
+    // Stale once its heartbeat stops.
+    std::thread::sleep(Duration::from_secs(6));
+
+    failing.store(false, Ordering::SeqCst);
+    let before = server.requests().len();
+    let mut second = args(roomy.to_str().unwrap());
+    second.extend([
+        "--resume-from".into(),
+        full.to_str().unwrap().into(),
+        url,
+        "download-async".into(),
+        "--workers".into(),
+        "3".into(),
+    ]);
+    let output = run_dlm(&second.iter().map(String::as_str).collect::<Vec<_>>());
+    assert_downloaded(&output, &roomy.join("file.bin"), &data);
+    let ranges: Vec<String> = server.requests()[before..]
+        .iter()
+        .filter_map(|request| request.header("range").map(str::to_string))
+        .collect();
+    assert_eq!(ranges, ["bytes=200000-299999"]);
+
+    // Parts are gone from both directories, and so is the earlier manifest.
+    let names = |dir: &std::path::Path| -> Vec<String> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert!(names(&full).is_empty(), "{:?}", names(&full));
+    assert_eq!(names(&roomy), ["file.bin"]);
+    assert_eq!(std::fs::read_dir(state.join("active")).unwrap().count(), 0);
+}
+
+#[test]
+fn resume_from_needs_a_recorded_download() {
+    let dir = scratch_dir("resume_from_needs_a_recorded_download");
+    let output = run_dlm(&[
+        "--state-dir",
+        dir.join("state").to_str().unwrap(),
+        "--resume-from",
+        dir.to_str().unwrap(),
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No worker-mode download of"), "{stderr}");
+
+    let output = run_dlm(&[
+        "--resume-from",
+        dir.to_str().unwrap(),
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--resume-from needs download-async with --workers"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn resume_picks_up_the_parts_beside_the_file_whatever_the_workers() {
+    let data = payload(300_000);
+    let failing = Arc::new(AtomicBool::new(true));
+    let server = TestServer::builder(data.clone())
+        .handler({
+            let failing = failing.clone();
+            move |request, _| {
+                let last = request
+                    .header("range")
+                    .is_some_and(|range| range.starts_with("bytes=200000-"));
+                (last && failing.load(Ordering::SeqCst)).then(|| Response::new(403, "no"))
+            }
+        })
+        .start();
+    let dir = scratch_dir("resume_picks_up_the_parts_beside_the_file_whatever_the_workers");
+    let url = server.url("/file.bin");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--part-files",
+        &url,
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    // The middle part got half-way.
+    let middle = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".p0001"))
+        .unwrap();
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&middle)
+        .unwrap();
+    file.set_len(40_000).unwrap();
+
+    failing.store(false, Ordering::SeqCst);
+    let before = server.requests().len();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        &url,
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let mut ranges: Vec<String> = server.requests()[before..]
+        .iter()
+        .filter_map(|request| request.header("range").map(str::to_string))
+        .filter(|range| range != "bytes=0-0")
+        .collect();
+    ranges.sort();
+    assert_eq!(ranges, ["bytes=140000-199999", "bytes=200000-299999"]);
+    let names: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["file.bin"]);
+}
+
+#[test]
+fn resume_order_most_complete_finishes_the_part_nearest_done_first() {
+    let data = payload(300_000);
+    let failing = Arc::new(AtomicBool::new(true));
+    let server = TestServer::builder(data.clone())
+        .handler({
+            let failing = failing.clone();
+            move |request, _| {
+                let last = request
+                    .header("range")
+                    .is_some_and(|range| range.starts_with("bytes=200000-"));
+                (last && failing.load(Ordering::SeqCst)).then(|| Response::new(403, "no"))
+            }
+        })
+        .start();
+    let dir = scratch_dir("resume_order_most_complete_finishes_the_part_nearest_done_first");
+    let url = server.url("/file.bin");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--part-files",
+        &url,
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    // The first part got a tenth of the way, the middle under half-way,
+    // the last nearly all the way.
+    let part = |suffix: &str| {
+        std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with(suffix))
+            .unwrap()
+    };
+    for (suffix, length) in [(".p0000", 10_000), (".p0001", 40_000)] {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(part(suffix))
+            .unwrap();
+        file.set_len(length).unwrap();
+    }
+    std::fs::write(part(".p0002"), &data[200_000..290_000]).unwrap();
+
+    failing.store(false, Ordering::SeqCst);
+    let before = server.requests().len();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--resume",
+        "--resume-order",
+        "most-complete",
+        &url,
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let ranges: Vec<String> = server.requests()[before..]
+        .iter()
+        .filter_map(|request| request.header("range").map(str::to_string))
+        .filter(|range| range != "bytes=0-0")
+        .collect();
+    let mut sorted = ranges.clone();
+    sorted.sort();
+    assert_eq!(
+        sorted,
+        [
+            "bytes=10000-99999",
+            "bytes=140000-199999",
+            "bytes=290000-299999",
+        ]
+    );
+    // The least complete waits for a worker to free up.
+    let at = |range| ranges.iter().position(|sent| sent == range).unwrap();
+    assert!(
+        at("bytes=290000-299999") < at("bytes=10000-99999"),
+        "{ranges:?}"
+    );
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+#[test]
+fn chunks_pick_up_where_their_part_left_off_after_a_drop() {
+    let data = payload(200_000);
+    // Every response drops after 20,000 bytes, so each chunk takes two
+    // retries.
+    let server = TestServer::builder(data.clone())
+        .fail_after(20_000, 100)
+        .start();
+    let dir = scratch_dir("chunks_pick_up_where_their_part_left_off_after_a_drop");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    for range in [
+        "bytes=20000-49999",
+        "bytes=40000-49999",
+        "bytes=170000-199999",
+    ] {
+        assert!(ranges.contains(&range.to_string()), "{ranges:?}");
+    }
+}
+
+#[test]
+fn a_chunk_out_of_retries_names_the_range_it_could_not_fetch() {
+    let server = TestServer::builder(payload(200_000))
+        .fail_after(20_000, 100)
+        .start();
+    let dir = scratch_dir("a_chunk_out_of_retries_names_the_range_it_could_not_fetch");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--retries",
+        "1",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Bytes 40000-99999 could not be fetched by chunk 0, still failing after 1 retries"
+        ) || stderr.contains(
+            "Bytes 140000-199999 could not be fetched by chunk 1, still failing after 1 retries"
+        ),
+        "{stderr}"
+    );
+    assert!(!dir.join("file.bin").exists());
+}
+
+#[test]
+fn a_permanent_error_is_not_retried() {
+    let dir = scratch_dir("a_permanent_error_is_not_retried");
+
+    for mode in ["download-blocking", "download-async"] {
+        // The first response drops; asking for the rest is forbidden.
+        let server = TestServer::builder(payload(200_000))
+            .fail_after(70_000, 1)
+            .handler(|request, _| {
+                request
+                    .header("Range")
+                    .map(|_| Response::new(403, "Forbidden"))
+            })
+            .start();
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--overwrite",
+            &server.url("/file.bin"),
+            mode,
+        ]);
+        assert!(!output.status.success(), "{mode}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("403"), "{mode}: {stderr}");
+        assert_eq!(server.requests().len(), 2, "{mode}: {stderr}");
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{Response, TestServer, payload, run_dlm, scratch_dir};
+use serde_json::{Value, json};
+use std::fs;
+
+fn unavailable() -> TestServer {
+    TestServer::builder(Vec::new())
+        .handler(|_, _| Some(Response::new(503, "down").header("Retry-After", "0")))
+        .start()
+}
+
+/// Runs dlm against `server` with `args`, returning its error report.
+fn failed_run(server: &TestServer, name: &str, args: &[&str]) -> (String, Value) {
+    let dir = scratch_dir(name);
+    let report = dir.join("report.json");
+    let mut all = vec![
+        "-t",
+        dir.to_str().unwrap(),
+        "--error-report",
+        report.to_str().unwrap(),
+    ];
+    all.extend(args);
+    let url = server.url("/file.bin");
+    all.extend([url.as_str(), "download-async"]);
+    let output = run_dlm(&all);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let report = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    (String::from_utf8_lossy(&output.stderr).into_owned(), report)
+}
+
+#[test]
+fn a_spent_budget_stops_retrying() {
+    let server = unavailable();
+    let (stderr, report) = failed_run(&server, "retry_budget_spent", &["--retry-budget", "2"]);
+    assert!(
+        stderr.contains(
+            "The retry budget of 2 for this run is spent; failing instead of retrying from now on"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(report["retries"].as_array().unwrap().len(), 2);
+    assert_eq!(report["retry_budget"], json!({ "used": 2, "budget": 2 }));
+    // One request, then one per retry.
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn zero_means_no_budget() {
+    let server = unavailable();
+    let (stderr, report) = failed_run(&server, "retry_budget_none", &["--retry-budget", "0"]);
+    assert!(!stderr.contains("retry budget"), "{stderr}");
+    assert_eq!(report["retry_budget"], json!({ "used": 3, "budget": null }));
+}
+
+#[test]
+fn a_stall_past_the_budget_fails_the_download() {
+    let server = TestServer::builder(payload(100_000))
+        .stall_after(10_000, 10)
+        .start();
+    let dir = scratch_dir("retry_budget_stall");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--stall-timeout",
+        "1",
+        "--retry-budget",
+        "1",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("and the retry budget of 1 for this run is spent (--retry-budget)"),
+        "{stderr}"
+    );
+    assert_eq!(server.requests().len(), 2);
+}
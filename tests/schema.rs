@@ -0,0 +1,81 @@
+mod common;
+
+use common::run_dlm;
+use download_manager::schema::{Artifact, SCHEMA_VERSION};
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Where the schemas of `version` are kept.
+fn snapshots(version: u32) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/schemas/v{version}"))
+}
+
+fn pretty(artifact: Artifact) -> String {
+    serde_json::to_string_pretty(&artifact.schema()).unwrap() + "\n"
+}
+
+/// A change to what an artifact serializes has to come with a new
+/// `SCHEMA_VERSION`. Its schemas are written with `DLM_UPDATE_SCHEMAS=1`,
+/// which never touches a version that already has them.
+#[test]
+fn the_schemas_match_their_version() {
+    let dir = snapshots(SCHEMA_VERSION);
+    if !dir.exists() && std::env::var_os("DLM_UPDATE_SCHEMAS").is_some() {
+        std::fs::create_dir_all(&dir).unwrap();
+        for artifact in Artifact::ALL {
+            let path = dir.join(format!("{}.json", artifact.name()));
+            std::fs::write(path, pretty(artifact)).unwrap();
+        }
+    }
+    assert!(
+        dir.exists(),
+        "no schemas for version {SCHEMA_VERSION}; write them with DLM_UPDATE_SCHEMAS=1"
+    );
+    for artifact in Artifact::ALL {
+        let path = dir.join(format!("{}.json", artifact.name()));
+        let snapshot = std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| panic!("{}: {error}", path.display()));
+        assert!(
+            snapshot == pretty(artifact),
+            "the {} schema changed since version {SCHEMA_VERSION} was released; \
+             bump SCHEMA_VERSION and write the new schemas with DLM_UPDATE_SCHEMAS=1",
+            artifact.name()
+        );
+    }
+}
+
+#[test]
+fn every_version_keeps_its_schemas() {
+    for version in 1..=SCHEMA_VERSION {
+        assert!(snapshots(version).exists(), "version {version}");
+    }
+}
+
+#[test]
+fn dump_prints_every_schema_or_one() {
+    let output = run_dlm(&["schema", "dump"]);
+    assert!(output.status.success(), "{output:?}");
+    let all: Value = serde_json::from_slice(&output.stdout).unwrap();
+    for artifact in Artifact::ALL {
+        let schema = serde_json::to_value(artifact.schema()).unwrap();
+        assert_eq!(all[artifact.name()], schema, "{}", artifact.name());
+    }
+
+    let output = run_dlm(&["schema", "dump", "usage"]);
+    assert!(output.status.success(), "{output:?}");
+    let usage: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(usage, all["usage"]);
+    assert_eq!(usage["properties"]["schema_version"]["type"], "integer");
+
+    let output = run_dlm(&["schema", "dump", "nope"]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown artifact 'nope'"));
+}
+
+#[test]
+fn artifacts_carry_their_schema_version() {
+    let output = run_dlm(&["version", "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let version: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(version["schema_version"], SCHEMA_VERSION);
+}
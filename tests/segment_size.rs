@@ -0,0 +1,276 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::segment_tuning::{SegmentBounds, SegmentSize, SegmentTuner};
+use std::time::Duration;
+
+const MIB: u64 = 1 << 20;
+
+/// The ranges asked for, the one-byte probe left out, sorted by their start.
+fn requested_ranges(server: &TestServer) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range"))
+        .filter(|range| *range != "bytes=0-0")
+        .map(|range| {
+            let (start, end) = range["bytes=".len()..].split_once('-').unwrap();
+            (start.parse().unwrap(), end.parse().unwrap())
+        })
+        .collect();
+    ranges.sort();
+    ranges
+}
+
+#[test]
+fn segment_size_queues_segments_for_the_workers() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("segment_size_queues_segments_for_the_workers");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--segment-size",
+        "64000",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    // Seven segments of 57142 bytes, the last taking the rest.
+    let ranges = requested_ranges(&server);
+    assert_eq!(ranges.len(), 7, "{ranges:?}");
+    assert_eq!(ranges[0], (0, 57_141));
+    assert_eq!(ranges[6], (342_852, 399_999));
+}
+
+#[test]
+fn a_file_of_a_segment_per_worker_or_less_keeps_one_per_worker() {
+    let data = payload(400_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_file_of_a_segment_per_worker_or_less_keeps_one_per_worker");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--segment-size",
+        "200000",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert_eq!(requested_ranges(&server).len(), 4);
+}
+
+#[test]
+fn an_idle_worker_takes_half_of_a_slow_segment() {
+    let data = payload(400_000);
+    let slow = data[..100_000].to_vec();
+    // The first segment's connection is slow, about 1s for all of it; every
+    // other range comes at once.
+    let server = TestServer::builder(data.clone())
+        .handler(move |request, _| {
+            let end = request.header("Range")?.strip_prefix("bytes=0-")?;
+            (end == "99999").then(|| {
+                Response::new(206, slow.clone())
+                    .header("Content-Range", "bytes 0-99999/400000")
+                    .drip(2_000, Duration::from_millis(20))
+            })
+        })
+        .start();
+    let dir = scratch_dir("an_idle_worker_takes_half_of_a_slow_segment");
+    let log = dir.join("chunks.jsonl");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--no-warm-up",
+        "--segment-size",
+        "100000",
+        "--min-steal-size",
+        "10000",
+        "--chunk-log",
+        log.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    // Split off the first segment, what's left of it each time.
+    let scheduled: Vec<(u64, u64)> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["event"] == "scheduled")
+        .map(|event| {
+            (
+                event["start"].as_u64().unwrap(),
+                event["end"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    let splits: Vec<_> = scheduled[4..].to_vec();
+    assert!(!splits.is_empty(), "{scheduled:?}");
+    assert!(
+        splits
+            .iter()
+            .all(|(start, end)| *start > 0 && *end < 100_000 && end - start + 1 >= 10_000),
+        "{splits:?}"
+    );
+    assert_eq!(splits[0].1, 99_999, "{splits:?}");
+    // The slow connection was left well before the end of its segment.
+    let slow_bytes = splits.iter().map(|(start, _)| *start).min().unwrap();
+    assert!(slow_bytes < 60_000, "{splits:?}");
+}
+
+#[test]
+fn segment_size_is_a_size_or_auto() {
+    assert_eq!("auto".parse(), Ok(SegmentSize::Auto));
+    assert_eq!("32M".parse(), Ok(SegmentSize::Fixed(32 * MIB)));
+    assert_eq!("0".parse(), Ok(SegmentSize::Fixed(0)));
+    assert!("automatic".parse::<SegmentSize>().is_err());
+}
+
+#[test]
+fn auto_segments_are_sized_to_keep_the_first_byte_under_5_percent() {
+    let bounds = SegmentBounds {
+        min: MIB,
+        max: 256 * MIB,
+    };
+    let ms = Duration::from_millis;
+    let cases = [
+        // 10% waiting for the first byte: twice as long, at most.
+        (ms(1100), ms(100), Some(16 * MIB)),
+        (ms(1500), ms(500), Some(16 * MIB)),
+        // 0.1%: half as long, at most.
+        (ms(1001), ms(1), Some(4 * MIB)),
+        // 2.5%: as long as makes it 5%.
+        (ms(1025), ms(25), Some(4 * MIB)),
+        (ms(1040), ms(40), Some(6_710_886)),
+        // Close enough to 5% to stay.
+        (ms(1052), ms(52), None),
+        (ms(1000), ms(1000), None),
+    ];
+    for (elapsed, ttfb, expected) in cases {
+        let mut tuner = SegmentTuner::new(bounds, 1);
+        assert_eq!(tuner.size(), 8 * MIB);
+        assert_eq!(
+            tuner.record(8 * MIB, elapsed, ttfb),
+            expected,
+            "{elapsed:?} {ttfb:?}"
+        );
+        let tuning = tuner.tuning();
+        assert_eq!(tuning.initial, 8 * MIB);
+        assert_eq!(tuning.last, expected.unwrap_or(8 * MIB));
+        assert_eq!(tuning.adjustments, u32::from(expected.is_some()));
+    }
+}
+
+#[test]
+fn auto_segments_stay_within_the_bounds_and_alignment() {
+    let ms = Duration::from_millis;
+    let narrow = SegmentBounds {
+        min: 6 * MIB,
+        max: 10 * MIB,
+    };
+    let mut tuner = SegmentTuner::new(narrow, 1);
+    assert_eq!(tuner.record(8 * MIB, ms(2000), ms(1000)), Some(10 * MIB));
+    assert_eq!(tuner.record(10 * MIB, ms(2000), ms(1000)), None);
+    assert_eq!(tuner.record(10 * MIB, ms(1001), ms(1)), Some(6 * MIB));
+    assert_eq!(tuner.tuning().adjustments, 2);
+
+    // Below the bounds, the first segments are as small as allowed.
+    let small = SegmentBounds {
+        min: 64 << 10,
+        max: 256 << 10,
+    };
+    assert_eq!(SegmentTuner::new(small, 1).size(), 256 << 10);
+
+    // Whole pieces, with --piece-hashes.
+    let mut tuner = SegmentTuner::new(
+        SegmentBounds {
+            min: MIB,
+            max: 256 * MIB,
+        },
+        3 * MIB,
+    );
+    assert_eq!(tuner.size(), 9 * MIB);
+    assert_eq!(tuner.record(9 * MIB, ms(1100), ms(100)), Some(18 * MIB));
+}
+
+#[test]
+fn auto_segments_are_taken_off_the_rest_of_the_file() {
+    let data = payload(2_000_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("auto_segments_are_taken_off_the_rest_of_the_file");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--segment-size",
+        "auto",
+        "--segment-size-min",
+        "64K",
+        "--segment-size-max",
+        "256K",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    // A 256 KiB segment each to start with, then the rest a segment of
+    // 64 to 256 KiB at a time, the last taking up to half as much again.
+    let ranges = requested_ranges(&server);
+    assert_eq!(ranges[..2], [(0, 262_143), (262_144, 524_287)]);
+    assert!(ranges.len() >= 6, "{ranges:?}");
+    for pair in ranges.windows(2) {
+        assert_eq!(pair[1].0, pair[0].1 + 1, "{ranges:?}");
+    }
+    assert_eq!(ranges.last().unwrap().1, 1_999_999);
+    let (last, carved) = ranges[2..].split_last().unwrap();
+    for (start, end) in carved {
+        assert!(
+            (64 << 10..=256 << 10).contains(&(end + 1 - start)),
+            "{ranges:?}"
+        );
+    }
+    assert!(last.1 + 1 - last.0 <= 384 << 10, "{ranges:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Segment size: from 256.00 KiB to "),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn segment_size_min_over_max_is_refused() {
+    let dir = scratch_dir("segment_size_min_over_max_is_refused");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--segment-size",
+        "auto",
+        "--segment-size-min",
+        "64M",
+        "--segment-size-max",
+        "32M",
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--segment-size-min 64.00 MiB is more than --segment-size-max 32.00 MiB"),
+        "{stderr}"
+    );
+}
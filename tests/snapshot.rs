@@ -0,0 +1,52 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, scratch_dir};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[test]
+fn sigusr1_prints_a_snapshot_and_the_download_carries_on() {
+    let data = payload(60_000);
+    let server = TestServer::builder(data.clone())
+        .drip(2_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("sigusr1_prints_a_snapshot_and_the_download_carries_on");
+    let url = server.url("/file.bin");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--max-memory",
+            "64M",
+            &url,
+            "download-async",
+            "--workers",
+            "2",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(700));
+    Command::new("kill")
+        .args(["-USR1", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in [
+        "Snapshot:\n",
+        &format!("  {url}: Downloaded: "),
+        ", chunks ",
+        "  Buffers: ",
+        ", of --max-memory 64.00 MiB",
+        "  Retries: 0 of --retry-budget 50\n",
+    ] {
+        assert!(stderr.contains(line), "no {line:?} in {stderr}");
+    }
+}
@@ -0,0 +1,302 @@
+mod common;
+
+use common::{TestServer, dlm, payload, scratch_dir};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What a client asked [`SocksServer`] to connect to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Asked {
+    Name(String, u16),
+    Address(SocketAddr),
+}
+
+/// A SOCKS5 proxy that connects whatever it's asked for to `origin`, or
+/// answers `reply` instead when that's a failure code, and remembers what
+/// it was asked for and with which credentials.
+struct SocksServer {
+    addr: SocketAddr,
+    asked: Arc<Mutex<Vec<Asked>>>,
+    credentials: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl SocksServer {
+    fn start(origin: SocketAddr, reply: u8, wants_credentials: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = SocksServer {
+            addr: listener.local_addr().unwrap(),
+            asked: Arc::default(),
+            credentials: Arc::default(),
+        };
+        let (asked, credentials) = (server.asked.clone(), server.credentials.clone());
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let (asked, credentials) = (asked.clone(), credentials.clone());
+                thread::spawn(move || {
+                    let _ = serve(
+                        client,
+                        origin,
+                        reply,
+                        wants_credentials,
+                        &asked,
+                        &credentials,
+                    );
+                });
+            }
+        });
+        server
+    }
+
+    fn url(&self, scheme: &str) -> String {
+        format!("{scheme}://{}", self.addr)
+    }
+
+    fn asked(&self) -> Vec<Asked> {
+        self.asked.lock().unwrap().clone()
+    }
+}
+
+fn serve(
+    mut client: TcpStream,
+    origin: SocketAddr,
+    reply: u8,
+    wants_credentials: bool,
+    asked: &Mutex<Vec<Asked>>,
+    credentials: &Mutex<Vec<(String, String)>>,
+) -> std::io::Result<()> {
+    let mut greeting = [0; 2];
+    client.read_exact(&mut greeting)?;
+    let mut methods = vec![0; greeting[1] as usize];
+    client.read_exact(&mut methods)?;
+    if wants_credentials {
+        if !methods.contains(&0x02) {
+            return client.write_all(&[0x05, 0xFF]);
+        }
+        client.write_all(&[0x05, 0x02])?;
+        let mut user = [0; 2];
+        client.read_exact(&mut user)?;
+        let mut user = vec![0; user[1] as usize];
+        client.read_exact(&mut user)?;
+        let mut password = [0; 1];
+        client.read_exact(&mut password)?;
+        let mut password = vec![0; password[0] as usize];
+        client.read_exact(&mut password)?;
+        credentials.lock().unwrap().push((
+            String::from_utf8(user).unwrap(),
+            String::from_utf8(password).unwrap(),
+        ));
+        client.write_all(&[0x01, 0x00])?;
+    } else {
+        client.write_all(&[0x05, 0x00])?;
+    }
+    let mut request = [0; 4];
+    client.read_exact(&mut request)?;
+    let mut port = [0; 2];
+    let target = match request[3] {
+        0x01 => {
+            let mut address = [0; 4];
+            client.read_exact(&mut address)?;
+            client.read_exact(&mut port)?;
+            Asked::Address(SocketAddr::from((address, u16::from_be_bytes(port))))
+        }
+        0x04 => {
+            let mut address = [0; 16];
+            client.read_exact(&mut address)?;
+            client.read_exact(&mut port)?;
+            Asked::Address(SocketAddr::from((address, u16::from_be_bytes(port))))
+        }
+        _ => {
+            let mut length = [0; 1];
+            client.read_exact(&mut length)?;
+            let mut name = vec![0; length[0] as usize];
+            client.read_exact(&mut name)?;
+            client.read_exact(&mut port)?;
+            Asked::Name(String::from_utf8(name).unwrap(), u16::from_be_bytes(port))
+        }
+    };
+    asked.lock().unwrap().push(target);
+    client.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0])?;
+    if reply != 0x00 {
+        return Ok(());
+    }
+    let upstream = TcpStream::connect(origin)?;
+    let (mut from_client, mut to_origin) = (client.try_clone()?, upstream.try_clone()?);
+    let requests = thread::spawn(move || {
+        let _ = std::io::copy(&mut from_client, &mut to_origin);
+        let _ = to_origin.shutdown(Shutdown::Write);
+    });
+    let (mut from_origin, mut to_client) = (upstream, client);
+    std::io::copy(&mut from_origin, &mut to_client)?;
+    to_client.shutdown(Shutdown::Both)?;
+    let _ = requests.join();
+    Ok(())
+}
+
+fn origin_of(server: &TestServer) -> SocketAddr {
+    server
+        .url("")
+        .trim_start_matches("http://")
+        .parse()
+        .unwrap()
+}
+
+fn through(proxy: &str, dir: &Path, url: &str, args: &[&str]) -> Output {
+    dlm()
+        .env_remove("NO_PROXY")
+        .env_remove("no_proxy")
+        .env_remove("DM_PROXY_USER")
+        .env_remove("DM_PROXY_PASSWORD")
+        .args(["-t", dir.to_str().unwrap(), "--proxy", proxy])
+        .args(args)
+        .args([url, "download-async", "--workers", "3"])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn socks5h_leaves_resolving_to_the_proxy() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let socks = SocksServer::start(origin_of(&server), 0x00, false);
+    let dir = scratch_dir("socks5h_leaves_resolving_to_the_proxy");
+    // `.invalid` never resolves, so only the proxy can have found it.
+    let url = "http://files.invalid/file.bin";
+    let output = through(&socks.url("socks5h"), &dir, url, &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    let asked = socks.asked();
+    assert!(asked.len() >= 3, "{asked:?}");
+    assert!(
+        asked
+            .iter()
+            .all(|asked| *asked == Asked::Name("files.invalid".to_string(), 80)),
+        "{asked:?}"
+    );
+}
+
+#[test]
+fn socks5_resolves_here_and_asks_for_an_address() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let socks = SocksServer::start(origin_of(&server), 0x00, false);
+    let dir = scratch_dir("socks5_resolves_here_and_asks_for_an_address");
+    let url = server.url("/file.bin").replace("127.0.0.1", "localhost");
+    let output = through(&socks.url("socks5"), &dir, &url, &["--proxy-all"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    let asked = socks.asked();
+    assert!(!asked.is_empty());
+    assert!(
+        asked.iter().all(|asked| matches!(
+            asked,
+            Asked::Address(address) if address.ip().is_loopback()
+        )),
+        "{asked:?}"
+    );
+}
+
+#[test]
+fn credentials_in_the_proxy_url_are_sent_to_it() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let socks = SocksServer::start(origin_of(&server), 0x00, true);
+    let dir = scratch_dir("credentials_in_the_proxy_url_are_sent_to_it");
+    let proxy = socks.url("socks5h").replace("://", "://alice:s3cret@");
+    let output = through(&proxy, &dir, "http://files.invalid/file.bin", &[]);
+    assert!(output.status.success(), "{output:?}");
+    let credentials = socks.credentials.lock().unwrap().clone();
+    assert!(!credentials.is_empty());
+    assert!(
+        credentials
+            .iter()
+            .all(|sent| *sent == ("alice".to_string(), "s3cret".to_string())),
+        "{credentials:?}"
+    );
+}
+
+#[test]
+fn an_unreachable_proxy_is_named() {
+    // A port nothing listens on once the listener is gone.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let proxy = format!("socks5h://127.0.0.1:{port}");
+    let dir = scratch_dir("an_unreachable_proxy_is_named");
+    let url = "http://files.invalid/file.bin";
+    let output = through(&proxy, &dir, url, &["--retries", "0"]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("Cannot connect to the SOCKS proxy {proxy}")),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_host_the_proxy_cannot_reach_is_named() {
+    let server = TestServer::builder(payload(1_000)).start();
+    let socks = SocksServer::start(origin_of(&server), 0x04, false);
+    let proxy = socks.url("socks5h");
+    let dir = scratch_dir("a_host_the_proxy_cannot_reach_is_named");
+    let url = "https://files.invalid/file.bin";
+    let output = through(&proxy, &dir, url, &["--retries", "0"]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "The SOCKS proxy {proxy} couldn't connect to files.invalid:443: host unreachable"
+        )),
+        "{stderr}"
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn proxy_user_is_sent_to_it_too() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let socks = SocksServer::start(origin_of(&server), 0x00, true);
+    let dir = scratch_dir("proxy_user_is_sent_to_it_too");
+    let flags = ["--proxy-user", "bob", "--proxy-password", "hunter2"];
+    let output = through(
+        &socks.url("socks5h"),
+        &dir,
+        "http://files.invalid/file.bin",
+        &flags,
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    let credentials = socks.credentials.lock().unwrap().clone();
+    assert!(!credentials.is_empty());
+    assert!(
+        credentials
+            .iter()
+            .all(|sent| *sent == ("bob".to_string(), "hunter2".to_string())),
+        "{credentials:?}"
+    );
+}
+
+#[test]
+fn a_host_that_wont_resolve_here_is_named() {
+    let server = TestServer::builder(payload(1_000)).start();
+    let socks = SocksServer::start(origin_of(&server), 0x00, false);
+    let proxy = socks.url("socks5");
+    let dir = scratch_dir("a_host_that_wont_resolve_here_is_named");
+    let url = "http://files.invalid/file.bin";
+    let output = through(&proxy, &dir, url, &["--retries", "0"]);
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Cannot resolve files.invalid:80 to reach it through the SOCKS proxy {proxy}"
+        )),
+        "{stderr}"
+    );
+    assert!(socks.asked().is_empty());
+}
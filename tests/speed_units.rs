@@ -0,0 +1,53 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::speed::SpeedUnits;
+
+#[test]
+fn rates_are_formatted_at_unit_boundaries() {
+    let si = SpeedUnits::BytesSi;
+    assert_eq!(si.rate(999), "999 B/s");
+    assert_eq!(si.rate(999_000), "999.00 kB/s");
+    assert_eq!(si.rate(999_999), "1.00 MB/s");
+    assert_eq!(si.rate(1_000_000), "1.00 MB/s");
+
+    let binary = SpeedUnits::BytesBinary;
+    assert_eq!(binary.rate(1_000_000), "976.56 KiB/s");
+    assert_eq!(binary.rate(996_148), "972.80 KiB/s");
+    assert_eq!(binary.rate(1_048_576), "1.00 MiB/s");
+
+    let bits = SpeedUnits::Bits;
+    assert_eq!(bits.rate(124), "992 bit/s");
+    assert_eq!(bits.rate(125), "1.00 kbit/s");
+    assert_eq!(bits.rate(1_000_000), "8.00 Mbit/s");
+    assert_eq!(bits.rate(125_000_000), "1.00 Gbit/s");
+
+    // Sizes stay in bytes.
+    assert_eq!(bits.size(1_500_000), "1.50 MB");
+    assert_eq!(binary.size(1_572_864), "1.50 MiB");
+    assert_eq!("BITS".parse::<SpeedUnits>(), Ok(SpeedUnits::Bits));
+    assert!("megabits".parse::<SpeedUnits>().is_err());
+}
+
+#[test]
+fn summary_shows_the_chosen_units() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("summary_shows_the_chosen_units");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--speed-units",
+        "bits",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Downloaded: 100.00 kB, speed: "),
+        "{stdout}"
+    );
+    assert!(stdout.contains("bit/s"), "{stdout}");
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+/// The ranges asked for the download itself, leaving out the probes.
+fn chunk_ranges(server: &TestServer) -> Vec<String> {
+    server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range"))
+        .filter(|range| *range != "bytes=0-0")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn a_file_smaller_than_the_workers_gets_a_byte_each() {
+    let data = payload(3);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_file_smaller_than_the_workers_gets_a_byte_each");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--min-split-size",
+        "0",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "8",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The file is only 3 bytes, splitting it between 3 workers instead of 8"),
+        "{stderr}"
+    );
+    // The first chunk's range is the probe's.
+    let mut ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    ranges.sort();
+    ranges.dedup();
+    assert_eq!(ranges, ["bytes=0-0", "bytes=1-1", "bytes=2-2"]);
+}
+
+#[test]
+fn a_file_under_the_min_split_size_is_not_split() {
+    let data = payload(1000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_file_under_the_min_split_size_is_not_split");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "8",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "too small for parts of --min-split-size 16.00 KiB each, downloading it in one stream instead of 8"
+        ),
+        "{stderr}"
+    );
+    assert!(chunk_ranges(&server).is_empty(), "{:?}", server.requests());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn auto_workers_go_by_the_size_of_the_file() {
+    let small = payload(200_000);
+    let big = payload(12 << 20);
+    let server = TestServer::builder(small.clone()).start();
+    let big_server = TestServer::builder(big.clone()).start();
+    let dir = scratch_dir("auto_workers_go_by_the_size_of_the_file");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/small.bin"),
+        "download-async",
+        "--workers",
+        "auto",
+    ]);
+    assert_downloaded(&output, &dir.join("small.bin"), &small);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("downloading it in one stream (--workers auto)"),
+        "{stderr}"
+    );
+    assert!(chunk_ranges(&server).is_empty(), "{:?}", server.requests());
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        // No third chunk split off a slow one.
+        "--min-steal-size",
+        "0",
+        &big_server.url("/big.bin"),
+        "download-async",
+        "--workers",
+        "auto",
+    ]);
+    assert_downloaded(&output, &dir.join("big.bin"), &big);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The file is 12.00 MiB, splitting it between 2 workers (--workers auto)"),
+        "{stderr}"
+    );
+    assert_eq!(chunk_ranges(&big_server).len(), 2);
+}
+
+#[test]
+fn auto_workers_without_ranges_download_in_one_stream() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).no_ranges().start();
+    let dir = scratch_dir("auto_workers_without_ranges_download_in_one_stream");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "auto",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The server doesn't accept ranges, downloading")
+            && stderr.contains("in one stream\n"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn dry_run_splits_as_the_download_would() {
+    let server = TestServer::builder(payload(1000)).start();
+    let dir = scratch_dir("dry_run_splits_as_the_download_would");
+
+    for (min_split_size, segments) in [("16K", 1), ("0", 8)] {
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--dry-run",
+            "--json",
+            "--min-split-size",
+            min_split_size,
+            &server.url("/file.bin"),
+            "download-async",
+            "--workers",
+            "8",
+        ]);
+        assert!(output.status.success(), "{output:?}");
+        let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            plan["segments"].as_array().unwrap().len(),
+            segments,
+            "{plan}"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::time::Duration;
+
+#[test]
+fn finished_download_leaves_no_manifest() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("finished_download_leaves_no_manifest");
+    let state = dir.join("state");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--state-dir",
+        state.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert_eq!(std::fs::read_dir(state.join("active")).unwrap().count(), 0);
+}
+
+#[test]
+fn interrupted_download_is_listed_and_resumed() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("interrupted_download_is_listed_and_resumed");
+    let state = dir.join("state");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--state-dir",
+            state.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(!child.wait_with_output().unwrap().status.success());
+
+    // Stale once its heartbeat stops.
+    std::thread::sleep(Duration::from_secs(6));
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "status"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout
+        .lines()
+        .find(|line| line.contains("orphaned"))
+        .unwrap_or_else(|| panic!("{stdout}"));
+    assert!(line.contains("file.bin"), "{stdout}");
+    let id = line.split_whitespace().next().unwrap();
+    assert!(stdout.contains(&format!("dlm resume {id}")), "{stdout}");
+
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "resume", &id[..6]]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Resume mode enabled"));
+    assert_eq!(std::fs::read_dir(state.join("active")).unwrap().count(), 0);
+}
+
+#[test]
+fn a_run_failing_before_the_download_still_leaves_its_manifest() {
+    let server = TestServer::builder(payload(10_000)).start();
+    let dir = scratch_dir("a_run_failing_before_the_download_still_leaves_its_manifest");
+    let state = dir.join("state");
+
+    // The control socket can't be made, once the manifest is written.
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--state-dir",
+        state.to_str().unwrap(),
+        "--control-socket",
+        dir.join("missing/control.sock").to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+
+    let manifests: Vec<_> = std::fs::read_dir(state.join("active"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(manifests.len(), 1, "{output:?}");
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&manifests[0]).unwrap()).unwrap();
+    assert_eq!(manifest["url"], server.url("/file.bin"));
+    assert_eq!(manifest["downloaded"], 0);
+}
+
+#[test]
+fn status_redacts_credentials_that_resume_still_sends() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .handler(|request: &common::Request, _| {
+            (request.header("authorization") != Some("Bearer s3cr3t-token"))
+                .then(|| common::Response::new(401, "Unauthorized"))
+        })
+        .start();
+    let dir = scratch_dir("status_redacts_credentials_that_resume_still_sends");
+    let state = dir.join("state");
+
+    let child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--state-dir",
+            state.to_str().unwrap(),
+            "--bearer-token",
+            "s3cr3t-token",
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(!child.wait_with_output().unwrap().status.success());
+    // Stale at once rather than when its heartbeat stops.
+    let path = std::fs::read_dir(state.join("active"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let mut manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    manifest["updated"] = 0.into();
+    std::fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "status"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("--bearer-token '<redacted>'"), "{stdout}");
+    assert!(!stdout.contains("s3cr3t-token"), "{stdout}");
+
+    let id = manifest["id"].as_str().unwrap();
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "resume", id]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// The status file at `path`, once it's there and parses: a torn write
+/// would fail here.
+fn read_status(path: &Path) -> Option<Value> {
+    let text = std::fs::read(path).ok()?;
+    Some(serde_json::from_slice(&text).expect("a whole document"))
+}
+
+#[test]
+fn the_status_file_follows_the_download_and_goes_when_it_succeeds() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .drip(2_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("the_status_file_follows_the_download_and_goes_when_it_succeeds");
+    let status = dir.join("status.json");
+    let mut child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--status-file",
+            status.to_str().unwrap(),
+            "--status-interval",
+            "50ms",
+            &server.url("/file.bin?token=secret"),
+            "download-async",
+            "--workers",
+            "2",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let json = loop {
+        if let Some(json) = read_status(&status)
+            && json["downloaded"].as_u64() > Some(0)
+        {
+            break json;
+        }
+        assert!(Instant::now() < deadline, "no progress in the status file");
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert_eq!(json["name"], "file.bin");
+    assert_eq!(json["url"], server.url("/file.bin"));
+    assert_eq!(json["state"], "running");
+    assert_eq!(json["total"], 200_000);
+    assert!(json["speed"].is_u64(), "{json}");
+    assert!(json["eta_secs"].is_u64(), "{json}");
+    assert_eq!(
+        json["chunks"]["downloading"].as_u64().unwrap()
+            + json["chunks"]["pending"].as_u64().unwrap()
+            + json["chunks"]["completed"].as_u64().unwrap(),
+        2,
+        "{json}"
+    );
+    assert!(json["schema_version"].is_u64());
+
+    assert!(child.wait().unwrap().success());
+    assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), data);
+    assert!(!status.exists());
+    assert!(!dir.join("status.json.partial").exists());
+}
+
+#[test]
+fn keep_status_file_leaves_the_completed_state() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("keep_status_file_leaves_the_completed_state");
+    let status = dir.join("status.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--status-file",
+        status.to_str().unwrap(),
+        "--keep-status-file",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let json = read_status(&status).unwrap();
+    assert_eq!(json["state"], "completed", "{json}");
+    assert_eq!(json["downloaded"], 50_000);
+    assert_eq!(json["eta_secs"], Value::Null);
+}
+
+#[test]
+fn a_failed_download_leaves_its_error_in_the_status_file() {
+    let server = TestServer::builder(payload(1_000))
+        .handler(|_, _| Some(Response::new(404, "gone")))
+        .start();
+    let dir = scratch_dir("a_failed_download_leaves_its_error_in_the_status_file");
+    let status = dir.join("status.json");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--status-file",
+        status.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let json = read_status(&status).unwrap();
+    let failed = json["state"]["failed"]
+        .as_str()
+        .unwrap_or_else(|| panic!("{json}"));
+    assert!(failed.contains("404"), "{failed}");
+}
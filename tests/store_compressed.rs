@@ -0,0 +1,116 @@
+mod common;
+
+use common::{TestServer, payload, printed_sha256, run_dlm, scratch_dir, sha256_hex};
+use std::io::Read;
+
+fn gunzip(path: &std::path::Path) -> Vec<u8> {
+    let mut data = Vec::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+        .read_to_end(&mut data)
+        .unwrap();
+    data
+}
+
+#[test]
+fn stored_gzip_decompresses_to_the_download() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("stored_gzip_decompresses_to_the_download");
+    let target = dir.to_str().unwrap();
+
+    for (name, command) in [
+        ("async.bin", &["download-async"][..]),
+        ("workers.bin", &["download-async", "--workers", "3"]),
+        ("blocking.bin", &["download-blocking"]),
+    ] {
+        let url = server.url(&format!("/{name}"));
+        let mut args = vec!["-t", target, "--store-compressed", "gzip:9", &url];
+        args.extend(command);
+        let output = run_dlm(&args);
+        assert!(output.status.success(), "{output:?}");
+        assert!(!dir.join(name).exists());
+        let stored = dir.join(format!("{name}.gz"));
+        assert_eq!(gunzip(&stored), data, "{name}");
+        // The hash is the content's, so it matches the published sum.
+        assert_eq!(printed_sha256(&output).unwrap(), sha256_hex(&data));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(&format!("{name}.gz")), "{stdout}");
+        if !command.contains(&"--workers") {
+            assert!(
+                stdout.contains("Compressed: 292.97 KiB raw, stored as "),
+                "{stdout}"
+            );
+        }
+    }
+}
+
+#[test]
+fn a_dropped_connection_carries_on_compressing() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .fail_after(80_000, 1)
+        .start();
+    let dir = scratch_dir("a_dropped_connection_carries_on_compressing");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--store-compressed",
+        "gzip",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(gunzip(&dir.join("file.bin.gz")), data);
+    assert_eq!(printed_sha256(&output).unwrap(), sha256_hex(&data));
+}
+
+#[test]
+fn compressed_files_are_not_resumed() {
+    let output = run_dlm(&[
+        "--resume",
+        "--store-compressed",
+        "gzip",
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+
+    let output = run_dlm(&[
+        "--store-compressed",
+        "gzip:12",
+        "http://127.0.0.1:9/file.bin",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'12' is not a gzip level, expected 0 to 9"),
+        "{stderr}"
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn stored_zstd_decompresses_to_the_download() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("stored_zstd_decompresses_to_the_download");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--store-compressed",
+        "zstd:19",
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stored = std::fs::read(dir.join("file.bin.zst")).unwrap();
+    assert_eq!(zstd::decode_all(&stored[..]).unwrap(), data);
+    assert_eq!(printed_sha256(&output).unwrap(), sha256_hex(&data));
+}
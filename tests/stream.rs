@@ -0,0 +1,263 @@
+mod common;
+
+use common::{TestServer, payload, run_dlm, scratch_dir, sha256_hex};
+use download_manager::__private::error;
+use download_manager::{
+    DownloadError, DownloadOutcome, Downloader, PostProcessor, TransferOptions, TransferProgress,
+    TransferState, Workers,
+};
+use std::path::PathBuf;
+
+fn copy_to_file(url: &str, path: &std::path::Path) -> std::io::Result<u64> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut reader = Downloader::new().unwrap().open_stream(url.parse().unwrap());
+        let mut file = tokio::fs::File::create(path).await?;
+        let copied = tokio::io::copy(&mut reader, &mut file).await?;
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        Ok(copied)
+    })
+}
+
+#[test]
+fn stream_matches_file_download() {
+    let data = payload(300_001);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("stream_matches_file_download");
+
+    let streamed = dir.join("streamed.bin");
+    assert_eq!(
+        copy_to_file(&server.url("/file.bin"), &streamed).unwrap(),
+        300_001
+    );
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+
+    assert_eq!(
+        common::printed_sha256(&output).as_deref(),
+        Some(sha256_hex(&std::fs::read(streamed).unwrap()).as_str())
+    );
+}
+
+#[test]
+fn stream_resumes_after_connection_drop() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone())
+        .fail_after(70_000, 2)
+        .start();
+    let dir = scratch_dir("stream_resumes_after_connection_drop");
+    let path = dir.join("file.bin");
+
+    copy_to_file(&server.url("/file.bin"), &path).unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            None,
+            Some("bytes=70000-".to_string()),
+            Some("bytes=140000-".to_string())
+        ]
+    );
+}
+
+#[test]
+fn stream_reports_missing_files() {
+    let server = TestServer::builder(payload(10))
+        .handler(|_, _| Some(common::Response::new(404, "gone")))
+        .start();
+    let dir = scratch_dir("stream_reports_missing_files");
+
+    let error = copy_to_file(&server.url("/file.bin"), &dir.join("file.bin")).unwrap_err();
+    assert!(error.to_string().contains("404"), "{error}");
+}
+
+#[test]
+fn progress_handle_reports_the_final_state() {
+    let data = payload(120_000);
+    let server = TestServer::builder(data.clone()).start();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let (finished, dropped) = runtime.block_on(async {
+        let downloader = Downloader::new().unwrap();
+        let mut reader = downloader.open_stream(server.url("/file.bin").parse().unwrap());
+        let mut handle = reader.progress().handle();
+        tokio::io::copy(&mut reader, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        let finished = handle.finished().await;
+
+        // Dropping a stream halfway reports an interruption.
+        let reader = downloader.open_stream(server.url("/file.bin").parse().unwrap());
+        let mut handle = reader.progress().handle();
+        drop(reader);
+        (finished, handle.finished().await)
+    });
+
+    assert_eq!(finished.state, TransferState::Completed);
+    assert_eq!(finished.downloaded, 120_000);
+    assert_eq!(finished.total, 120_000);
+    assert_eq!(dropped.state, TransferState::Interrupted);
+}
+
+#[test]
+fn downloader_saves_to_its_target_dir_and_types_its_errors() {
+    let data = payload(150_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("downloader_saves_to_its_target_dir_and_types_its_errors");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let url: url::Url = server.url("/file.bin").parse().unwrap();
+    let downloader = Downloader::new()
+        .unwrap()
+        .with_target_dir(dir.join("nested"))
+        .with_workers(3);
+    let progress = || TransferProgress::new(Default::default());
+
+    let path = runtime
+        .block_on(downloader.download(url.clone(), progress()))
+        .unwrap();
+    assert_eq!(path, dir.join("nested").join("file.bin"));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    let ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect();
+    assert!(
+        ranges.contains(&"bytes=100000-149999".to_string()),
+        "{ranges:?}"
+    );
+
+    // Neither resuming nor overwriting: the file is in the way.
+    let single = downloader.clone().with_workers(1);
+    let error = runtime
+        .block_on(single.download(url.clone(), progress()))
+        .unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref(),
+            Some(DownloadError::FileExists { path: existing }) if *existing == path
+        ),
+        "{error:?}"
+    );
+
+    let overwriting = single.with_options(TransferOptions {
+        overwrite: true,
+        ..TransferOptions::default()
+    });
+    let cancelled = progress();
+    cancelled
+        .interrupted
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    let error = runtime
+        .block_on(overwriting.download(url, cancelled))
+        .unwrap_err();
+    assert!(
+        matches!(error.downcast_ref(), Some(DownloadError::Interrupted)),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn downloader_picks_the_workers_with_auto() {
+    let data = payload(150_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("downloader_picks_the_workers_with_auto");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let progress = TransferProgress::new(Default::default());
+
+    let path = runtime
+        .block_on(
+            Downloader::new()
+                .unwrap()
+                .with_target_dir(&dir)
+                .with_workers(Workers::Auto)
+                .download(server.url("/file.bin").parse().unwrap(), progress.clone()),
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert_eq!(progress.total(), data.len() as u64);
+    // Too small to be worth splitting.
+    let ranged = server
+        .requests()
+        .iter()
+        .filter(|request| request.method == "GET")
+        .filter(|request| {
+            request
+                .header("Range")
+                .is_some_and(|range| range != "bytes=0-0")
+        })
+        .count();
+    assert_eq!(ranged, 0, "{:?}", server.requests());
+}
+
+/// Moves the download into its directory, or fails without one.
+struct Quarantine(Option<PathBuf>);
+
+impl PostProcessor for Quarantine {
+    fn name(&self) -> &str {
+        "quarantine"
+    }
+
+    fn run(&self, outcome: &mut DownloadOutcome) -> anyhow::Result<()> {
+        let Some(dir) = &self.0 else {
+            anyhow::bail!("no quarantine for {}", outcome.url);
+        };
+        let moved = dir.join(outcome.path.file_name().unwrap());
+        std::fs::rename(&outcome.path, &moved)?;
+        outcome.path = moved;
+        Ok(())
+    }
+}
+
+#[test]
+fn downloader_runs_added_steps_after_the_transfer() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("downloader_runs_added_steps_after_the_transfer");
+    let quarantine = dir.join("quarantine");
+    std::fs::create_dir(&quarantine).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let progress = || TransferProgress::new(Default::default());
+
+    let path = runtime
+        .block_on(
+            Downloader::new()
+                .unwrap()
+                .with_target_dir(&dir)
+                .with_post_processor(Quarantine(Some(quarantine.clone())))
+                .download(server.url("/file.bin").parse().unwrap(), progress()),
+        )
+        .unwrap();
+    assert_eq!(path, quarantine.join("file.bin"));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+
+    let error = runtime
+        .block_on(
+            Downloader::new()
+                .unwrap()
+                .with_target_dir(&dir)
+                .with_post_processor(Quarantine(None))
+                .download(server.url("/other.bin").parse().unwrap(), progress()),
+        )
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Downloaded '{}', but post-processing step 'quarantine' failed",
+            dir.join("other.bin").display()
+        )
+    );
+    assert!(format!("{error:#}").contains("no quarantine for"));
+    assert_eq!(error::exit_code(&error), 9);
+    assert!(dir.join("other.bin").is_file());
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::fs;
+
+#[test]
+fn strict_fails_on_a_warning_unless_it_is_allowed() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("strict_fails_on_a_warning_unless_it_is_allowed");
+    let report = dir.join("report.json");
+    let url = server.url("/file.bin?utm_source=newsletter");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--strict",
+        "--error-report",
+        report.to_str().unwrap(),
+        &url,
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(10), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("--strict-allow tracking-params only warns about it"),
+        "{output:?}"
+    );
+    let json: Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(json["exit_code"], 10);
+    assert_eq!(json["warnings"][0]["id"], "tracking-params", "{json}");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--overwrite",
+        "--strict",
+        "--strict-allow",
+        "tracking-params",
+        &url,
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+}
+
+#[test]
+fn strict_fails_with_the_code_of_the_error_a_warning_stands_in_for() {
+    let server = TestServer::builder(payload(1_000))
+        .handler(|_, _| {
+            Some(
+                Response::new(200, r#"{"error": "NoSuchKey"}"#)
+                    .header("Content-Type", "application/json"),
+            )
+        })
+        .start();
+    let dir = scratch_dir("strict_fails_with_the_code_of_the_error_a_warning_stands_in_for");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--strict",
+        &server.url("/backup.tar.zst"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(!dir.join("backup.tar.zst").exists());
+}
+
+#[test]
+fn diagnostics_list_names_every_warning() {
+    let output = run_dlm(&["diagnostics", "list"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for id in [
+        "content-type-mismatch",
+        "no-content-length",
+        "clock-skew",
+        "suspicious",
+    ] {
+        assert!(stdout.contains(id), "{stdout}");
+    }
+
+    let output = run_dlm(&["--strict", "--strict-allow", "no-such-warning", "status"]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+const LOGIN_PAGE: &str = "<!DOCTYPE html>\n<html><body>Please log in</body></html>";
+
+fn login_server() -> TestServer {
+    TestServer::builder(payload(1_000))
+        .handler(|request, _| {
+            (request.path == "/ubuntu.iso").then(|| {
+                Response::new(200, LOGIN_PAGE).header("Content-Type", "text/html; charset=utf-8")
+            })
+        })
+        .start()
+}
+
+#[test]
+fn html_served_for_a_binary_fails_with_a_preview() {
+    let server = login_server();
+    let dir = scratch_dir("html_served_for_a_binary_fails_with_a_preview");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/ubuntu.iso"),
+        "download-async",
+    ]);
+
+    assert_eq!(output.status.code(), Some(14));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("WARNING"), "{stderr}");
+    assert!(stderr.contains("served as text/html"), "{stderr}");
+    assert!(stderr.contains("<!DOCTYPE html>\\n<html>"), "{stderr}");
+    // The file stays around for a look.
+    assert!(dir.join("ubuntu.iso").exists());
+}
+
+#[test]
+fn allow_suspicious_keeps_the_download() {
+    let server = login_server();
+    let dir = scratch_dir("allow_suspicious_keeps_the_download");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--allow-suspicious",
+        &server.url("/ubuntu.iso"),
+        "download-blocking",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("WARNING"), "{stderr}");
+    assert_eq!(
+        std::fs::read(dir.join("ubuntu.iso")).unwrap(),
+        LOGIN_PAGE.as_bytes()
+    );
+}
+
+#[test]
+fn a_small_binary_of_the_announced_size_is_fine() {
+    let data = payload(100);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("a_small_binary_of_the_announced_size_is_fine");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/tiny.bin"),
+        "download-async",
+    ]);
+
+    assert_downloaded(&output, &dir.join("tiny.bin"), &data);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("WARNING"));
+}
+
+#[test]
+fn a_small_binary_of_no_announced_size_is_suspicious() {
+    let server = TestServer::builder(payload(100)).no_length().start();
+    let dir = scratch_dir("a_small_binary_of_no_announced_size_is_suspicious");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/tiny.bin"),
+        "download-async",
+    ]);
+
+    assert_eq!(output.status.code(), Some(14));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("only 100 bytes for a .bin file"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,105 @@
+#![cfg(all(feature = "systemd", target_os = "linux"))]
+
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::os::unix::process::CommandExt;
+use std::time::Duration;
+
+#[test]
+fn notify_socket_gets_ready_and_progress() {
+    let data = payload(30_000);
+    let server = TestServer::builder(data.clone())
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("notify_socket_gets_ready_and_progress");
+    let notify = UnixDatagram::bind(dir.join("notify.sock")).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+
+    let output = common::dlm()
+        .env("NOTIFY_SOCKET", dir.join("notify.sock"))
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .output()
+        .unwrap();
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let mut messages = Vec::new();
+    let mut buffer = [0; 512];
+    while let Ok(length) = notify.recv(&mut buffer) {
+        messages.push(String::from_utf8_lossy(&buffer[..length]).to_string());
+    }
+    assert_eq!(
+        messages[0], "READY=1\nSTATUS=Starting file.bin",
+        "{messages:?}"
+    );
+    assert!(
+        messages.iter().any(
+            |message| message.starts_with("STATUS=Downloading file.bin: ")
+                && message.contains("% (")
+        ),
+        "{messages:?}"
+    );
+    assert_eq!(
+        messages.last().unwrap(),
+        "STOPPING=1\nSTATUS=Downloaded file.bin"
+    );
+}
+
+#[test]
+fn control_socket_comes_from_socket_activation() {
+    let server = TestServer::builder(payload(30_000))
+        .drip(1_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("control_socket_comes_from_socket_activation");
+    let socket = dir.join("control.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let fd = listener.as_raw_fd();
+
+    // As systemd would: the socket as descriptor 3, and LISTEN_PID naming
+    // the process that's exec'd, which the shell's `exec` keeps.
+    let mut command = std::process::Command::new("sh");
+    command
+        .args(["-c", r#"LISTEN_PID=$$ exec "$0" "$@""#])
+        .arg(env!("CARGO_BIN_EXE_dlm"))
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            &server.url("/file.bin"),
+            "download-async",
+        ])
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_FDNAMES", "control")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    // SAFETY: dup2 is async-signal-safe, and the new descriptor isn't
+    // close-on-exec.
+    unsafe {
+        command.pre_exec(move || {
+            let mut target = std::os::fd::FromRawFd::from_raw_fd(3);
+            let result = nix::unistd::dup2(std::os::fd::BorrowedFd::borrow_raw(fd), &mut target);
+            std::mem::forget(target);
+            result.map_err(std::io::Error::from)
+        });
+    }
+    let child = command.spawn().unwrap();
+    drop(listener);
+
+    std::thread::sleep(Duration::from_millis(500));
+    let output = run_dlm(&["ctl", "--socket", socket.to_str().unwrap(), "status"]);
+    assert!(output.status.success(), "{output:?}");
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["total"], 30_000);
+
+    assert!(child.wait_with_output().unwrap().status.success());
+    // It belongs to systemd, so it's left in place.
+    assert!(socket.exists());
+}
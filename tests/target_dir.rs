@@ -0,0 +1,53 @@
+mod common;
+
+use common::{run_dlm, scratch_dir};
+use std::process::Output;
+
+/// Runs a download into `target`; the URL is never reached.
+fn download_into(target: &std::path::Path) -> Output {
+    run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "http://127.0.0.1:1/file.bin",
+        "download-async",
+    ])
+}
+
+fn assert_usage_error(output: &Output, message: &str) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(stderr.contains(message), "{stderr}");
+    assert!(!stderr.contains("Os {"), "{stderr}");
+}
+
+#[test]
+fn target_that_is_a_file_is_reported() {
+    let dir = scratch_dir("target_that_is_a_file_is_reported");
+    let file = dir.join("downloads");
+    std::fs::write(&file, "not a directory").unwrap();
+
+    let output = download_into(&file);
+    assert_usage_error(&output, "is a file, not a directory");
+
+    // A file further up the path is the one that gets named.
+    let output = download_into(&file.join("nested"));
+    assert_usage_error(
+        &output,
+        &format!("'{}' is a file, not a directory", file.display()),
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn uncreatable_target_names_the_failing_component() {
+    // Nobody, root included, can create directories in /proc.
+    let output = download_into(std::path::Path::new("/proc/dlm-test/nested"));
+    assert_usage_error(&output, "failed at '/proc/dlm-test'");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn unwritable_target_reports_the_owner() {
+    let output = download_into(std::path::Path::new("/proc"));
+    assert_usage_error(&output, "'/proc' is not writable (owned by root)");
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{TestServer, payload, scratch_dir};
+use download_manager::__private::target_wait;
+use download_manager::TransferOptions;
+use download_manager::TransferProgress;
+use download_manager::download_with_workers;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use url::Url;
+
+fn download(server: &TestServer, target: &Path) -> anyhow::Result<std::path::PathBuf> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(download_with_workers(
+        &reqwest::Client::new(),
+        Url::parse(&server.url("/file.bin")).unwrap(),
+        target,
+        4,
+        TransferProgress::new(Default::default()),
+        &TransferOptions::default(),
+    ))
+}
+
+// One test, as the wait is process-wide.
+#[test]
+fn opens_wait_for_a_target_that_shows_up_late() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("opens_wait_for_a_target_that_shows_up_late");
+    let target = dir.join("mount");
+
+    // Gone for good: given up on after the wait, with the OS's error.
+    target_wait::set_wait(Some(Duration::from_millis(400)));
+    let start = Instant::now();
+    let error = format!("{:#}", download(&server, &target).unwrap_err());
+    assert!(error.contains("No such file or directory"), "{error}");
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    // Mounted while the workers are waiting.
+    target_wait::set_wait(Some(Duration::from_secs(4)));
+    let mount = {
+        let target = target.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            std::fs::create_dir(target).unwrap();
+        })
+    };
+    let path = download(&server, &target).unwrap();
+    mount.join().unwrap();
+    target_wait::set_wait(None);
+    assert_eq!(std::fs::read(path).unwrap(), data);
+
+    // Without a wait, a missing directory fails at once.
+    std::fs::remove_dir_all(&target).unwrap();
+    let start = Instant::now();
+    assert!(download(&server, &target).is_err());
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, dlm, payload, scratch_dir};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::Arc;
+use std::thread;
+
+fn certs() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs")
+}
+
+/// TLS for `origin` on a port of its own, speaking only `versions`, with a
+/// certificate for `localhost` from the test CA in tests/certs.
+fn terminate(origin: &TestServer, versions: &[&'static rustls::SupportedProtocolVersion]) -> u16 {
+    let origin: SocketAddr = origin
+        .url("")
+        .trim_start_matches("http://")
+        .parse()
+        .unwrap();
+    let chain = CertificateDer::pem_file_iter(certs().join("server.pem"))
+        .unwrap()
+        .chain(CertificateDer::pem_file_iter(certs().join("ca.pem")).unwrap())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_file(certs().join("server.key")).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .unwrap();
+    let config = Arc::new(config);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let config = config.clone();
+            thread::spawn(move || {
+                let connection = ServerConnection::new(config).unwrap();
+                let _ = relay(StreamOwned::new(connection, client), origin);
+            });
+        }
+    });
+    port
+}
+
+/// Hands one request to `origin`, which answers it and closes.
+fn relay(
+    mut tls: StreamOwned<ServerConnection, TcpStream>,
+    origin: SocketAddr,
+) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut reader = BufReader::new(&mut tls);
+    while !head.ends_with(b"\r\n\r\n") {
+        let read = reader.read_until(b'\n', &mut head)?;
+        if read == 0 {
+            return Ok(());
+        }
+    }
+    drop(reader);
+    let mut upstream = TcpStream::connect(origin)?;
+    upstream.write_all(&head)?;
+    std::io::copy(&mut upstream, &mut tls)?;
+    tls.conn.send_close_notify();
+    tls.flush()
+}
+
+fn download(port: u16, dir: &Path, args: &[&str]) -> Output {
+    dlm()
+        .env("SSL_CERT_FILE", certs().join("ca.pem"))
+        .env_remove("HTTPS_PROXY")
+        .env_remove("https_proxy")
+        .env_remove("ALL_PROXY")
+        .env_remove("all_proxy")
+        .args(["-t", dir.to_str().unwrap()])
+        .args(args)
+        .arg(format!("https://localhost:{port}/file.bin"))
+        .args(["download-async", "--workers", "2"])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn the_negotiated_session_is_reported() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let port = terminate(&server, &[&rustls::version::TLS13]);
+    let dir = scratch_dir("the_negotiated_session_is_reported");
+    let output = download(port, &dir, &["--min-tls", "1.2"]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "TLS: TLS 1.3; certificate for localhost issued by dlm test CA, expiring 2126-09-22 (2 certificates in the chain)"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn the_json_summary_has_the_session() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let port = terminate(&server, &[&rustls::version::TLS12]);
+    let dir = scratch_dir("the_json_summary_has_the_session");
+    let output = download(port, &dir, &["--progress", "json", "--progress-stdout"]);
+    assert!(output.status.success(), "{output:?}");
+    let completed = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|event| event["event"] == "completed")
+        .unwrap();
+    let tls = &completed["tls"];
+    assert_eq!(tls["host"], "localhost", "{completed}");
+    assert_eq!(tls["version"], "TLS 1.2", "{completed}");
+    // rustls doesn't tell reqwest which suite it agreed on.
+    assert!(tls.get("cipher").is_none(), "{completed}");
+    assert_eq!(tls["chain"][0]["subject"], "localhost", "{completed}");
+    assert_eq!(tls["chain"][1]["subject"], "dlm test CA", "{completed}");
+}
+
+#[test]
+fn a_host_below_min_tls_is_refused_before_any_request() {
+    let server = TestServer::builder(payload(10_000)).start();
+    let port = terminate(&server, &[&rustls::version::TLS12]);
+    let dir = scratch_dir("a_host_below_min_tls_is_refused_before_any_request");
+    let output = download(port, &dir, &["--min-tls", "1.3"]);
+    assert_eq!(output.status.code(), Some(13), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "localhost can't be held to TLS 1.3 or newer, as --min-tls asks: it speaks no version that new (received fatal alert: ProtocolVersion)"
+        ),
+        "{stderr}"
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn an_untrusted_certificate_is_refused_before_any_request() {
+    let server = TestServer::builder(payload(10_000)).start();
+    let port = terminate(&server, &[&rustls::version::TLS13]);
+    let dir = scratch_dir("an_untrusted_certificate_is_refused_before_any_request");
+    // Trusting the server's own certificate, which the test CA issued,
+    // rather than the CA.
+    let output = dlm()
+        .env("SSL_CERT_FILE", certs().join("server.pem"))
+        .env_remove("HTTPS_PROXY")
+        .env_remove("https_proxy")
+        .env_remove("ALL_PROXY")
+        .env_remove("all_proxy")
+        .args(["-t", dir.to_str().unwrap(), "--min-tls", "1.2"])
+        .arg(format!("https://localhost:{port}/file.bin"))
+        .arg("download-async")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(13), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "localhost can't be held to TLS 1.2 or newer, as --min-tls asks: the handshake failed: invalid peer certificate"
+        ),
+        "{stderr}"
+    );
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn plain_http_is_left_alone() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("plain_http_is_left_alone");
+    let output = dlm()
+        .args(["-t", dir.to_str().unwrap(), "--min-tls", "1.3"])
+        .args([&server.url("/file.bin"), "download-async"])
+        .output()
+        .unwrap();
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("TLS:"));
+}
+
+/// Like [`terminate`], with OpenSSL, which still speaks TLS 1.1.
+#[cfg(target_os = "linux")]
+fn terminate_tls_1_1(origin: &TestServer) -> u16 {
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVersion};
+    let origin: SocketAddr = origin
+        .url("")
+        .trim_start_matches("http://")
+        .parse()
+        .unwrap();
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_cipher_list("DEFAULT@SECLEVEL=0").unwrap();
+    acceptor
+        .set_min_proto_version(Some(SslVersion::TLS1_1))
+        .unwrap();
+    acceptor
+        .set_max_proto_version(Some(SslVersion::TLS1_1))
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(certs().join("server.pem"))
+        .unwrap();
+    acceptor
+        .set_private_key_file(certs().join("server.key"), SslFiletype::PEM)
+        .unwrap();
+    let acceptor = Arc::new(acceptor.build());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let acceptor = acceptor.clone();
+            thread::spawn(move || {
+                let Ok(mut tls) = acceptor.accept(client) else {
+                    return;
+                };
+                let mut head = Vec::new();
+                let mut reader = BufReader::new(&mut tls);
+                while !head.ends_with(b"\r\n\r\n") {
+                    match reader.read_until(b'\n', &mut head) {
+                        Ok(0) | Err(_) => return,
+                        Ok(_) => {}
+                    }
+                }
+                drop(reader);
+                let Ok(mut upstream) = TcpStream::connect(origin) else {
+                    return;
+                };
+                let _ = upstream.write_all(&head);
+                let _ = std::io::copy(&mut upstream, &mut tls);
+                let _ = tls.shutdown();
+            });
+        }
+    });
+    port
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_host_older_than_tls_1_2_is_refused() {
+    let server = TestServer::builder(payload(10_000)).start();
+    let port = terminate_tls_1_1(&server);
+    let dir = scratch_dir("a_host_older_than_tls_1_2_is_refused");
+    let output = download(port, &dir, &["--min-tls", "1.2"]);
+    assert_eq!(output.status.code(), Some(13), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "localhost can't be held to TLS 1.2 or newer, as --min-tls asks: it speaks no version that new (received fatal alert: ProtocolVersion)"
+        ),
+        "{stderr}"
+    );
+    assert!(server.requests().is_empty());
+}
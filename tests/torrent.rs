@@ -0,0 +1,213 @@
+mod common;
+
+use common::{Response, TestServer, run_dlm, scratch_dir};
+
+/// A single-file torrent for `data`, bencoded by hand with its keys in
+/// order.
+#[cfg(feature = "torrent")]
+fn torrent(name: &str, data: &[u8], piece_length: usize, seed: &str) -> Vec<u8> {
+    use sha1::{Digest, Sha1};
+
+    let pieces: Vec<u8> = data
+        .chunks(piece_length)
+        .flat_map(|piece| Sha1::digest(piece).to_vec())
+        .collect();
+    let mut bytes = format!(
+        "d4:infod6:lengthi{}e4:name{}:{name}12:piece lengthi{piece_length}e6:pieces{}:",
+        data.len(),
+        name.len(),
+        pieces.len()
+    )
+    .into_bytes();
+    bytes.extend(pieces);
+    bytes.extend(format!("e8:url-listl{}:{seed}ee", seed.len()).into_bytes());
+    bytes
+}
+
+#[test]
+fn torrents_and_magnets_are_refused() {
+    let server = TestServer::builder(b"d4:infodee".to_vec())
+        .handler(|request, _| {
+            (request.path == "/served-as.bin").then(|| {
+                Response::new(200, "d4:infodee").header("Content-Type", "application/x-bittorrent")
+            })
+        })
+        .start();
+    let dir = scratch_dir("torrents_and_magnets_are_refused");
+    let target = dir.to_str().unwrap();
+
+    let output = run_dlm(&["-t", target, &server.url("/file.torrent"), "download-async"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("dlm is not a BitTorrent client"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("--torrent save"), "{stderr}");
+    assert!(!dir.join("file.torrent").exists());
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        &server.url("/served-as.bin"),
+        "download-blocking",
+    ]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("the server sent a torrent (application/x-bittorrent)"),
+        "{stderr}"
+    );
+    assert!(!dir.join("served-as.bin").exists());
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "--torrent",
+        "save",
+        &server.url("/file.torrent"),
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        std::fs::read(dir.join("file.torrent")).unwrap(),
+        b"d4:infodee"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056&ws=http%3A%2F%2Fseed.example%2Fa.iso",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unverified: dlm 'http://seed.example/a.iso' download-async"),
+        "{stderr}"
+    );
+}
+
+#[cfg(feature = "torrent")]
+#[test]
+fn torrent_content_comes_from_its_http_seeds() {
+    let data = common::payload(300_000);
+    let seed = TestServer::builder(data.clone()).start();
+    let good = torrent("linux.iso", &data, 32_768, &seed.url("/releases/"));
+    let mut bad_data = data.clone();
+    bad_data[100_000] ^= 0xff;
+    let bad = torrent("linux.iso", &bad_data, 32_768, &seed.url("/releases/"));
+    let server = TestServer::builder(Vec::new())
+        .handler(move |request, _| match request.path.as_str() {
+            "/good.torrent" => Some(Response::new(200, good.clone())),
+            _ => Some(Response::new(200, bad.clone())),
+        })
+        .start();
+    let dir = scratch_dir("torrent_content_comes_from_its_http_seeds");
+    let target = dir.to_str().unwrap();
+
+    // Without --torrent, only the offer.
+    let output = run_dlm(&["-t", target, &server.url("/good.torrent"), "download-async"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "It lists 1 HTTP seed(s), though: pass --torrent webseeds to download 'linux.iso' (292.97 KiB)"
+        ),
+        "{stderr}"
+    );
+    assert!(seed.requests().is_empty());
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "--torrent",
+        "webseeds",
+        &server.url("/good.torrent"),
+        "download-async",
+        "--workers",
+        "2",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(dir.join("linux.iso")).unwrap(), data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "Torrent 'linux.iso' (292.97 KiB, 10 pieces): downloading from its HTTP seed"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Checked 10 pieces against the torrent"),
+        "{stdout}"
+    );
+    assert!(
+        seed.requests()
+            .iter()
+            .all(|request| request.path == "/releases/linux.iso")
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        target,
+        "--torrent",
+        "webseeds",
+        "--output",
+        "mismatch.iso",
+        &server.url("/bad.torrent"),
+        "download-blocking",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 of 10 pieces don't match the torrent's hashes: 3"),
+        "{stderr}"
+    );
+}
+
+#[cfg(feature = "torrent")]
+#[test]
+fn torrent_metadata_is_checked() {
+    use download_manager::__private::torrent::Torrent;
+
+    let data = common::payload(1_000);
+    let parsed =
+        Torrent::parse(&torrent("a.bin", &data, 512, "http://seed.example/a.bin")).unwrap();
+    assert_eq!(parsed.length, 1_000);
+    assert_eq!(parsed.pieces.len(), 2);
+    // A seed naming the file is used as is, a directory gets the name.
+    assert_eq!(
+        parsed.file_url(&parsed.webseeds[0]).as_str(),
+        "http://seed.example/a.bin"
+    );
+    assert_eq!(
+        parsed
+            .file_url(&"http://seed.example/pub/".parse().unwrap())
+            .as_str(),
+        "http://seed.example/pub/a.bin"
+    );
+
+    let escape = torrent("../a.bin", &data, 512, "http://seed.example/");
+    let error = Torrent::parse(&escape).unwrap_err();
+    assert_eq!(error.to_string(), "'../a.bin' is not a plain file name");
+
+    let mut wrong_count = torrent("a.bin", &data, 1_000, "http://seed.example/");
+    // Claim 2 000 bytes, which would need two pieces.
+    let at = wrong_count
+        .windows(7)
+        .position(|w| w == b"i1000e4")
+        .unwrap();
+    wrong_count[at + 1] = b'2';
+    assert_eq!(
+        Torrent::parse(&wrong_count).unwrap_err().to_string(),
+        "1 piece hashes, but 2000 bytes in pieces of 1000 make 2"
+    );
+
+    let multi =
+        b"d4:infod5:filesld6:lengthi1e4:pathl1:aeee4:name3:dir12:piece lengthi1e6:pieces0:ee";
+    assert_eq!(
+        Torrent::parse(multi).unwrap_err().to_string(),
+        "'dir' holds 1 files, and only single-file torrents can be downloaded from HTTP seeds"
+    );
+    assert!(Torrent::parse(b"d4:infod4:name1:ai01ee").is_err());
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use download_manager::__private::tracking;
+use url::Url;
+
+#[test]
+fn tracking_params_are_stripped_from_real_world_urls() {
+    for (url, expected, removed) in [
+        (
+            "https://releases.example.org/app-1.2.tar.gz?utm_source=newsletter&utm_medium=email&utm_campaign=launch",
+            "https://releases.example.org/app-1.2.tar.gz",
+            &["utm_source", "utm_medium", "utm_campaign"][..],
+        ),
+        (
+            "https://cdn.example.com/video.mp4?fbclid=IwAR0abc123",
+            "https://cdn.example.com/video.mp4",
+            &["fbclid"],
+        ),
+        (
+            "https://example.com/get?id=42&gclid=Cj0KCQ&lang=en",
+            "https://example.com/get?id=42&lang=en",
+            &["gclid"],
+        ),
+        (
+            "https://example.com/file.zip?v=3&mc_cid=a1&mc_eid=b2#top",
+            "https://example.com/file.zip?v=3#top",
+            &["mc_cid", "mc_eid"],
+        ),
+        (
+            "https://example.com/paper.pdf?UTM_Source=twitter&msclkid=99",
+            "https://example.com/paper.pdf",
+            &["UTM_Source", "msclkid"],
+        ),
+        // The rest of the query is kept byte for byte.
+        (
+            "https://example.com/d?name=a%20b+c&igshid=x&q=%E2%9C%93",
+            "https://example.com/d?name=a%20b+c&q=%E2%9C%93",
+            &["igshid"],
+        ),
+        (
+            "https://example.com/iso?release=24.04",
+            "https://example.com/iso?release=24.04",
+            &[],
+        ),
+        // Signatures and tokens stay.
+        (
+            "https://example.com/f?token=abc&sig=def&utm_source=x",
+            "https://example.com/f?token=abc&sig=def",
+            &["utm_source"],
+        ),
+        // Nothing at all is touched in a presigned URL.
+        (
+            "https://bucket.s3.amazonaws.com/k.bin?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Signature=abc&utm_source=x",
+            "https://bucket.s3.amazonaws.com/k.bin?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Signature=abc&utm_source=x",
+            &[],
+        ),
+    ] {
+        let (stripped, names) = tracking::strip(&Url::parse(url).unwrap(), &[]);
+        assert_eq!(stripped.as_str(), expected, "{url}");
+        assert_eq!(names, removed, "{url}");
+    }
+}
+
+#[test]
+fn extra_params_are_stripped_but_signatures_never_are() {
+    let url = Url::parse("https://example.com/f?ref=home&Signature=s&x-amz-date=1&keep=1").unwrap();
+    let extra = [
+        "ref".to_string(),
+        "signature".to_string(),
+        "X-Amz-Date".to_string(),
+    ];
+    let (stripped, removed) = tracking::strip(&url, &extra);
+    assert_eq!(
+        stripped.as_str(),
+        "https://example.com/f?Signature=s&x-amz-date=1&keep=1"
+    );
+    assert_eq!(removed, ["ref"]);
+    assert!(tracking::is_protected("X-Amz-Credential"));
+    assert!(!tracking::is_tracking("token", &["token".to_string()]));
+}
+
+#[test]
+fn dlm_requests_the_url_without_tracking_params() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("tracking_params_stripped");
+    let url = server.url("/file.bin?utm_source=feed&v=2&fbclid=abc");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--strip-tracking-params",
+        &url,
+        "download-async",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Removed tracking parameters: utm_source, fbclid"),
+        "{output:?}"
+    );
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    for request in server.requests() {
+        assert_eq!(request.path, "/file.bin?v=2");
+    }
+
+    let dir = scratch_dir("tracking_params_kept");
+    let output = run_dlm(&["-t", dir.to_str().unwrap(), &url, "download-async"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(
+            "The URL has tracking parameters (utm_source, fbclid); --strip-tracking-params removes them"
+        ),
+        "{output:?}"
+    );
+}
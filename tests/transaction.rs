@@ -0,0 +1,102 @@
+mod common;
+
+use common::scratch_dir;
+use download_manager::__private::transaction::{STAGING, Transaction};
+use std::fs;
+
+#[test]
+fn commit_moves_every_staged_file_into_the_target() {
+    let target = scratch_dir("commit_moves_every_staged_file_into_the_target");
+    let transaction = Transaction::begin(&target).unwrap();
+    fs::write(transaction.staging_dir().join("shard-1.bin"), "one").unwrap();
+    fs::write(transaction.staging_dir().join("shard-2.bin"), "two").unwrap();
+
+    let files = transaction.commit(false).unwrap();
+    assert_eq!(
+        files,
+        [target.join("shard-1.bin"), target.join("shard-2.bin")]
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("shard-2.bin")).unwrap(),
+        "two"
+    );
+    assert!(!target.join(STAGING).exists());
+}
+
+#[test]
+fn rolling_back_leaves_the_target_alone() {
+    let target = scratch_dir("rolling_back_leaves_the_target_alone");
+    let transaction = Transaction::begin(&target).unwrap();
+    fs::write(transaction.staging_dir().join("shard-1.bin"), "one").unwrap();
+    transaction.roll_back(false).unwrap();
+    assert!(!target.join("shard-1.bin").exists());
+
+    // Kept for the next run to resume.
+    let transaction = Transaction::begin(&target).unwrap();
+    assert!(transaction.staging_dir().join("shard-1.bin").exists());
+    transaction.roll_back(true).unwrap();
+    assert!(!target.join(STAGING).exists());
+}
+
+#[test]
+fn staged_files_dropped_before_the_commit_follow_the_policy() {
+    let target = scratch_dir("staged_files_dropped_before_the_commit_follow_the_policy");
+    let transaction = Transaction::begin(&target).unwrap();
+    fs::write(transaction.staging_dir().join("shard-1.bin"), "one").unwrap();
+
+    // Kept to resume, as when a run without --remove-on-error panics.
+    drop(transaction.staged(false));
+    assert!(transaction.staging_dir().join("shard-1.bin").exists());
+    // Disarmed once the downloads are all there.
+    transaction.staged(true).disarm();
+    assert!(transaction.staging_dir().join("shard-1.bin").exists());
+
+    drop(transaction.staged(true));
+    assert!(!target.join(STAGING).exists());
+    assert!(!target.join("shard-1.bin").exists());
+}
+
+#[test]
+fn an_existing_file_blocks_the_whole_commit() {
+    let target = scratch_dir("an_existing_file_blocks_the_whole_commit");
+    fs::write(target.join("shard-2.bin"), "old").unwrap();
+    let transaction = Transaction::begin(&target).unwrap();
+    fs::write(transaction.staging_dir().join("shard-1.bin"), "one").unwrap();
+    fs::write(transaction.staging_dir().join("shard-2.bin"), "two").unwrap();
+
+    let error = transaction.commit(false).unwrap_err().to_string();
+    assert!(error.contains("already exists"), "{error}");
+    assert!(!target.join("shard-1.bin").exists());
+    assert_eq!(
+        fs::read_to_string(target.join("shard-2.bin")).unwrap(),
+        "old"
+    );
+
+    let transaction = Transaction::begin(&target).unwrap();
+    transaction.commit(true).unwrap();
+    assert_eq!(
+        fs::read_to_string(target.join("shard-2.bin")).unwrap(),
+        "two"
+    );
+}
+
+#[test]
+fn an_interrupted_commit_is_finished_by_the_next_run() {
+    let target = scratch_dir("an_interrupted_commit_is_finished_by_the_next_run");
+    // A directory in the way stops the commit after the first move.
+    fs::create_dir(target.join("shard-2.bin")).unwrap();
+    let transaction = Transaction::begin(&target).unwrap();
+    fs::write(transaction.staging_dir().join("shard-1.bin"), "one").unwrap();
+    fs::write(transaction.staging_dir().join("shard-2.bin"), "two").unwrap();
+    let error = format!("{:#}", transaction.commit(true).unwrap_err());
+    assert!(error.contains("run again to finish the commit"), "{error}");
+    assert!(target.join("shard-1.bin").exists());
+
+    fs::remove_dir(target.join("shard-2.bin")).unwrap();
+    let transaction = Transaction::begin(&target).unwrap();
+    assert_eq!(
+        fs::read_to_string(target.join("shard-2.bin")).unwrap(),
+        "two"
+    );
+    assert_eq!(fs::read_dir(transaction.staging_dir()).unwrap().count(), 0);
+}
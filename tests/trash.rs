@@ -0,0 +1,74 @@
+mod common;
+
+use common::{TestServer, dlm, payload, scratch_dir};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+/// Runs dlm with a trash of its own in `dir`.
+fn run_with_trash(dir: &Path, args: &[&str]) -> Output {
+    dlm()
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .args(args)
+        .output()
+        .expect("run dlm")
+}
+
+fn trashed(dir: &Path) -> Vec<u8> {
+    fs::read(dir.join("data/Trash/files/file.bin")).unwrap_or_default()
+}
+
+#[test]
+fn overwrite_moves_the_old_file_to_the_trash() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("overwrite_moves_the_old_file_to_the_trash");
+    let target = dir.join("downloads");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("file.bin"), "old").unwrap();
+
+    let output = run_with_trash(
+        &dir,
+        &[
+            "-t",
+            target.to_str().unwrap(),
+            "--overwrite",
+            "--use-trash",
+            &server.url("/file.bin"),
+            "download-async",
+        ],
+    );
+    common::assert_downloaded(&output, &target.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("was moved to the trash"), "{stdout}");
+    assert!(
+        stdout.contains("Replaced 3 B from ") && stdout.contains("; the old file is in the trash"),
+        "{stdout}"
+    );
+    assert_eq!(trashed(&dir), b"old");
+}
+
+#[test]
+fn force_delete_wins_over_use_trash() {
+    let data = payload(50_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("force_delete_wins_over_use_trash");
+    let target = dir.join("downloads");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("file.bin"), "old").unwrap();
+
+    let output = run_with_trash(
+        &dir,
+        &[
+            "-t",
+            target.to_str().unwrap(),
+            "--overwrite",
+            "--use-trash",
+            "--force-delete",
+            &server.url("/file.bin"),
+            "download-async",
+        ],
+    );
+    common::assert_downloaded(&output, &target.join("file.bin"), &data);
+    assert!(trashed(&dir).is_empty());
+}
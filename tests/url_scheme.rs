@@ -0,0 +1,71 @@
+mod common;
+
+use common::{run_dlm, scratch_dir};
+use download_manager::__private::utils::validate_url;
+use url::Url;
+
+#[test]
+fn only_http_and_https_are_downloaded() {
+    let cases = [
+        ("https://example.com/a.iso", None),
+        ("http://example.com/a.iso", None),
+        (
+            "s3://bucket/key",
+            Some(
+                "Unsupported URL scheme 's3'; dlm downloads http and https URLs. Use `aws s3 cp`, or a presigned https:// URL",
+            ),
+        ),
+        (
+            "gs://bucket/key",
+            Some("scheme 'gs'; dlm downloads http and https URLs. Use `gcloud storage cp`"),
+        ),
+        (
+            "ssh://host/file",
+            Some("scheme 'ssh'; dlm downloads http and https URLs. Use scp or sftp"),
+        ),
+        (
+            "ftp://example.com/a.iso",
+            Some("scheme 'ftp'; dlm downloads http and https URLs. Use curl or wget"),
+        ),
+        (
+            "file:///etc/hosts",
+            Some("scheme 'file'; dlm downloads http and https URLs. Copy local files"),
+        ),
+        (
+            "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056",
+            Some("Magnet links need a BitTorrent client"),
+        ),
+        (
+            "htps://example.com/a.iso",
+            Some("Unsupported URL scheme 'htps'; dlm downloads http and https URLs"),
+        ),
+    ];
+    for (url, message) in cases {
+        let result = validate_url(&Url::parse(url).unwrap());
+        match message {
+            None => assert!(result.is_ok(), "{url}: {result:?}"),
+            Some(message) => {
+                let error = result.unwrap_err().to_string();
+                assert!(error.contains(message), "{url}: {error}");
+                assert!(!error.contains('\n'), "{url}: {error}");
+            }
+        }
+    }
+}
+
+#[test]
+fn unsupported_scheme_fails_before_the_target_is_created() {
+    let dir = scratch_dir("unsupported_scheme_fails_before_the_target_is_created");
+    let target = dir.join("downloads");
+
+    let output = run_dlm(&[
+        "-t",
+        target.to_str().unwrap(),
+        "s3://bucket/key",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unsupported URL scheme 's3'"), "{stderr}");
+    assert!(!target.exists());
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+fn download(state: &Path, dir: &Path, url: &str, extra: &[&str]) -> std::process::Output {
+    let mut args = vec![
+        "--state-dir",
+        state.to_str().unwrap(),
+        "-t",
+        dir.to_str().unwrap(),
+    ];
+    args.extend(extra);
+    args.extend([url, "download-async"]);
+    run_dlm(&args)
+}
+
+fn transfers(state: &Path) -> Vec<Value> {
+    fs::read_to_string(state.join("usage.jsonl"))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn every_run_is_counted_failed_ones_too() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            request
+                .path
+                .starts_with("/down")
+                .then(|| Response::new(404, "gone"))
+        })
+        .start();
+    let dir = scratch_dir("every_run_is_counted_failed_ones_too");
+    let state = dir.join("state");
+
+    let output = download(&state, &dir, &server.url("/file.bin"), &[]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let output = download(&state, &dir, &server.url("/down.bin"), &[]);
+    assert!(!output.status.success(), "{output:?}");
+
+    let transfers = transfers(&state);
+    assert_eq!(transfers.len(), 2, "{transfers:?}");
+    assert_eq!(transfers[0]["bytes"], 100_000);
+    assert_eq!(transfers[0]["outcome"], "completed");
+    assert_eq!(transfers[0]["url"], server.url("/file.bin"));
+    assert_eq!(transfers[1]["outcome"], "failed");
+
+    let output = run_dlm(&["--state-dir", state.to_str().unwrap(), "usage"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let row = stdout
+        .lines()
+        .find(|line| line.starts_with(&today))
+        .unwrap_or_else(|| panic!("{stdout}"));
+    // Two downloads, one of them failed.
+    assert!(row.ends_with("  2  1"), "{row}");
+
+    let output = run_dlm(&[
+        "--state-dir",
+        state.to_str().unwrap(),
+        "usage",
+        "--by",
+        "month",
+    ]);
+    let month = chrono::Local::now().format("%Y-%m").to_string();
+    assert!(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.starts_with(&format!("{month} "))),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn an_enforced_limit_refuses_downloads_past_it() {
+    let data = payload(10_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("an_enforced_limit_refuses_downloads_past_it");
+    let state = dir.join("state");
+    let state_dir = state.to_str().unwrap();
+
+    let output = run_dlm(&["--state-dir", state_dir, "usage", "--limit", "day=5k"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Limit per day: 5"),
+        "{output:?}"
+    );
+    // Under the limit, nothing to say.
+    let output = download(&state, &dir, &server.url("/a.bin"), &[]);
+    assert_downloaded(&output, &dir.join("a.bin"), &data);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("WARNING"));
+
+    // Past it, only a warning...
+    let output = download(&state, &dir, &server.url("/b.bin"), &[]);
+    assert_downloaded(&output, &dir.join("b.bin"), &data);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("past the limit of"),
+        "{output:?}"
+    );
+
+    // ...until it's enforced.
+    let output = run_dlm(&[
+        "--state-dir",
+        state_dir,
+        "usage",
+        "--limit",
+        "day=5k",
+        "--enforce",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let output = download(&state, &dir, &server.url("/c.bin"), &[]);
+    assert_eq!(output.status.code(), Some(6), "{output:?}");
+    assert!(!dir.join("c.bin").exists());
+    let output = download(&state, &dir, &server.url("/c.bin"), &["--force"]);
+    assert_downloaded(&output, &dir.join("c.bin"), &data);
+
+    let output = run_dlm(&["--state-dir", state_dir, "usage", "--no-limit"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Limit per"));
+    let output = download(&state, &dir, &server.url("/d.bin"), &[]);
+    assert_downloaded(&output, &dir.join("d.bin"), &data);
+}
+
+#[test]
+fn an_overwrite_records_what_it_replaced() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("an_overwrite_records_what_it_replaced");
+    let state = dir.join("state");
+    fs::write(dir.join("file.bin"), "old").unwrap();
+    let old_hash = common::sha256_hex(b"old");
+
+    let output = download(
+        &state,
+        &dir,
+        &server.url("/file.bin"),
+        &["--overwrite", "--diff-hash"],
+    );
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let today = chrono::Local::now().format("%Y-%m-%d");
+    let expected = format!(
+        "Replaced 3 B (sha256 {}…) from {today} with 97.66 KiB (sha256 {}…)",
+        &old_hash[..8],
+        &common::sha256_hex(&data)[..8]
+    );
+    assert!(stdout.contains(&expected), "{expected}\n{stdout}");
+
+    let replaced = &transfers(&state)[0]["replaced"];
+    assert_eq!(replaced["size"], 3);
+    assert_eq!(replaced["sha256"], old_hash.as_str());
+    assert!(replaced["modified"].is_u64(), "{replaced}");
+
+    // Without --diff-hash the old file isn't read.
+    let output = download(&state, &dir, &server.url("/file.bin"), &["--overwrite"]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let transfers = transfers(&state);
+    assert!(
+        transfers[1]["replaced"]["sha256"].is_null(),
+        "{transfers:?}"
+    );
+    assert_eq!(transfers[1]["replaced"]["size"], 100_000);
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir, sha256_hex};
+use download_manager::__private::parts::PartLayout;
+use std::path::Path;
+use url::Url;
+
+const RANGES: [(u64, u64); 3] = [(0, 99_999), (100_000, 199_999), (200_000, 299_999)];
+
+/// What an earlier run of the download of `url` into `dir` left: a plan,
+/// and `parts` for its first ranges.
+fn left_behind(url: &str, dir: &Path, parts: &[Vec<u8>], sha256: &[Option<String>]) {
+    let url = Url::parse(url).unwrap();
+    let mut layout = PartLayout::new(&url, 300_000, RANGES.to_vec())
+        .beside(&dir.join("file.bin"))
+        .unwrap();
+    for (index, part) in parts.iter().enumerate() {
+        std::fs::write(&layout.paths[index], part).unwrap();
+    }
+    for (index, sha256) in sha256.iter().enumerate() {
+        if let Some(sha256) = sha256 {
+            layout.record_sha256(index, sha256.clone());
+        }
+    }
+    layout.save_plan(&dir.join("file.bin")).unwrap();
+}
+
+fn resume(url: &str, dir: &Path, verify: bool) -> std::process::Output {
+    let mut args = vec!["-t", dir.to_str().unwrap(), "--resume"];
+    if verify {
+        args.push("--verify-parts");
+    }
+    args.extend([url, "download-async", "--workers", "3"]);
+    run_dlm(&args)
+}
+
+#[test]
+fn a_part_longer_than_its_range_is_downloaded_again() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let url = server.url("/file.bin");
+    let dir = scratch_dir("a_part_longer_than_its_range_is_downloaded_again");
+    let mut long = data[..100_000].to_vec();
+    long.extend([0; 10]);
+    left_behind(&url, &dir, &[long, data[100_000..200_000].to_vec()], &[]);
+
+    let output = resume(&url, &dir, false);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("failed its size check: it holds 100010 bytes where its range has 100000"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("Parts: 2 checked, 1 downloaded again"),
+        "{stdout}"
+    );
+    let mut ranges: Vec<_> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .filter(|range| range != "bytes=0-0")
+        .collect();
+    ranges.sort();
+    assert_eq!(ranges, ["bytes=0-99999", "bytes=200000-299999"]);
+}
+
+#[test]
+fn verify_parts_checks_a_kept_part_by_its_recorded_sha256() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let url = server.url("/file.bin");
+    let dir = scratch_dir("verify_parts_checks_a_kept_part_by_its_recorded_sha256");
+    // As long as its range, but not what the server has.
+    let mut corrupt = data[..100_000].to_vec();
+    corrupt[5_000] ^= 0xFF;
+    let sha256 = [
+        Some(sha256_hex(&data[..100_000])),
+        Some(sha256_hex(&data[100_000..200_000])),
+    ];
+    left_behind(
+        &url,
+        &dir,
+        &[corrupt, data[100_000..200_000].to_vec()],
+        &sha256,
+    );
+
+    let output = resume(&url, &dir, true);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("failed its SHA-256 check"), "{stdout}");
+    assert!(
+        stdout.contains("Parts: 2 checked, 1 downloaded again"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn verify_parts_samples_a_kept_part_without_a_recorded_sha256() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone()).start();
+    let url = server.url("/file.bin");
+    let dir = scratch_dir("verify_parts_samples_a_kept_part_without_a_recorded_sha256");
+    left_behind(
+        &url,
+        &dir,
+        &[vec![0; 100_000], data[100_000..200_000].to_vec()],
+        &[],
+    );
+
+    let output = resume(&url, &dir, true);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("failed its sample check"), "{stdout}");
+    assert!(
+        stdout.contains("Parts: 2 checked, 1 downloaded again"),
+        "{stdout}"
+    );
+    let samples = server
+        .requests()
+        .iter()
+        .filter(|request| {
+            request.header("Range").is_some_and(|range| {
+                let (start, end) = range["bytes=".len()..].split_once('-').unwrap();
+                end.parse::<u64>().unwrap() - start.parse::<u64>().unwrap() == 4_095
+            })
+        })
+        .count();
+    // The first sample of the zeroed part fails it; the other is sampled
+    // in full.
+    assert_eq!(samples, 5);
+}
+
+#[test]
+fn a_failed_run_records_the_sha256_of_its_complete_parts() {
+    let data = payload(300_000);
+    let server = TestServer::builder(data.clone())
+        .handler(|request, _| {
+            request
+                .header("Range")
+                .is_some_and(|range| range.starts_with("bytes=200000-"))
+                .then(|| Response::new(403, "no"))
+        })
+        .start();
+    let url = server.url("/file.bin");
+    let dir = scratch_dir("a_failed_run_records_the_sha256_of_its_complete_parts");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "--part-files",
+        &url,
+        "download-async",
+        "--workers",
+        "3",
+    ]);
+    assert!(!output.status.success(), "{output:?}");
+    let plan =
+        PartLayout::load_plan(&Url::parse(&url).unwrap(), 300_000, &dir.join("file.bin")).unwrap();
+    assert_eq!(
+        plan.recorded_sha256(0),
+        Some(sha256_hex(&data[..100_000]).as_str())
+    );
+    assert_eq!(
+        plan.recorded_sha256(1),
+        Some(sha256_hex(&data[100_000..200_000]).as_str())
+    );
+    assert_eq!(plan.recorded_sha256(2), None);
+}
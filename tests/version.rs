@@ -0,0 +1,28 @@
+mod common;
+
+use common::run_dlm;
+
+#[test]
+fn version_reports_build_and_features() {
+    let output = run_dlm(&["version", "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(report["build_date"].as_str().unwrap().len(), 10);
+    assert!(!report["commit"].as_str().unwrap().is_empty());
+    let features = report["features"].as_array().unwrap();
+    assert_eq!(
+        features.contains(&"http3".into()),
+        cfg!(feature = "http3"),
+        "{features:?}"
+    );
+
+    let output = run_dlm(&["version"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with(&format!("dlm {}\n", env!("CARGO_PKG_VERSION"))),
+        "{stdout}"
+    );
+    assert!(stdout.contains("TLS:       rustls"), "{stdout}");
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+/// The `Range`s `server` was asked for, in order.
+fn ranges(server: &TestServer) -> Vec<String> {
+    server
+        .requests()
+        .iter()
+        .filter_map(|request| request.header("Range").map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn workers_warm_up_a_connection_each_before_their_chunks() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("warm_up_per_worker");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+
+    let ranges = ranges(&server);
+    assert_eq!(ranges.len(), 8, "{ranges:?}");
+    assert!(
+        ranges[..4].iter().all(|range| range == "bytes=0-0"),
+        "{ranges:?}"
+    );
+    assert!(
+        !ranges[4..].contains(&"bytes=0-0".to_string()),
+        "{ranges:?}"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Connections: "),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn no_warm_up_and_a_single_worker_go_straight_to_the_chunks() {
+    let data = payload(200_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("warm_up_skipped");
+    let url = server.url("/file.bin");
+    for (global, workers) in [(Some("--no-warm-up"), "4"), (None, "1")] {
+        let mut args = vec!["-t", dir.to_str().unwrap(), "-o"];
+        args.extend(global);
+        args.extend([url.as_str(), "download-async", "--workers", workers]);
+        let output = run_dlm(&args);
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+    }
+    assert!(!ranges(&server).contains(&"bytes=0-0".to_string()));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::fs;
+
+#[test]
+fn a_restart_counts_what_was_downloaded_before_it() {
+    let data = payload(100_000);
+    for command in ["download-blocking", "download-async"] {
+        // The connection drops, and the re-request gets the whole file.
+        let server = TestServer::builder(data.clone())
+            .no_ranges()
+            .fail_after(40_000, 1)
+            .start();
+        let dir = scratch_dir(&format!("wasted_restart_{command}"));
+        let output = run_dlm(&[
+            "-t",
+            dir.to_str().unwrap(),
+            "--restart-on-unresumable",
+            &server.url("/file.bin"),
+            command,
+        ]);
+
+        assert_downloaded(&output, &dir.join("file.bin"), &data);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("Wasted: 39.06 KiB downloaded for nothing (40.0% of the file)"),
+            "{command}: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn compared_ranges_that_matched_are_wasted() {
+    let data = payload(4 * 65_536);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("wasted_repair_compare");
+    let mut damaged = data.clone();
+    damaged[70_000] ^= 0xff;
+    fs::write(dir.join("file.bin"), damaged).unwrap();
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "repair",
+        "--compare",
+        "--sample-size",
+        "64k",
+    ]);
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Wasted: 192.00 KiB downloaded for nothing (75.0% of the file)"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn a_clean_download_wastes_nothing() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("wasted_nothing");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/file.bin"),
+        "download-async",
+        "--workers",
+        "4",
+    ]);
+
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Wasted"));
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::{TestServer, payload, scratch_dir};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// Sends a `method` request for `path` to `address`, returning the status
+/// line and the body.
+fn request(address: &str, method: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {address}\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[test]
+fn web_status_serves_a_read_only_page_while_downloading() {
+    let server = TestServer::builder(payload(200_000))
+        .drip(2_000, Duration::from_millis(50))
+        .start();
+    let dir = scratch_dir("web_status_page");
+    let mut child = common::dlm()
+        .args([
+            "-t",
+            dir.to_str().unwrap(),
+            "--web-status",
+            "127.0.0.1:0",
+            &server.url("/file.bin?token=secret"),
+            "download-async",
+            "--workers",
+            "2",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let address = stdout
+        .by_ref()
+        .map(Result::unwrap)
+        .find_map(|line| {
+            Some(
+                line.strip_prefix("Status page: http://")?
+                    .trim_end_matches('/')
+                    .to_string(),
+            )
+        })
+        .expect("no status page address");
+    // Keep reading, so dlm can go on printing.
+    let rest = std::thread::spawn(move || stdout.map(Result::unwrap).collect::<Vec<_>>());
+
+    let (status, page) = request(&address, "GET", "/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(page.contains("/status.json"));
+    assert!(!page.contains("src=") && !page.contains("href="), "{page}");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let json = loop {
+        let (_, body) = request(&address, "GET", "/status.json");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        if json["downloaded"].as_u64() > Some(0) {
+            break json;
+        }
+        assert!(Instant::now() < deadline, "no progress: {json}");
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(json["name"], "file.bin");
+    assert_eq!(json["url"], server.url("/file.bin"));
+    assert_eq!(json["state"], "running");
+    assert_eq!(json["total"], 200_000);
+    assert_eq!(json["paused"], false);
+    assert_eq!(json["chunk_map"].as_array().unwrap().len(), 2);
+
+    assert_eq!(
+        request(&address, "POST", "/status.json").0,
+        "HTTP/1.1 405 Method Not Allowed"
+    );
+    assert_eq!(
+        request(&address, "GET", "/pause").0,
+        "HTTP/1.1 404 Not Found"
+    );
+
+    assert!(child.wait().unwrap().success());
+    assert!(
+        rest.join()
+            .unwrap()
+            .iter()
+            .any(|line| line.starts_with("SHA256: "))
+    );
+    assert!(TcpStream::connect(&address).is_err());
+}
+
+#[test]
+fn web_status_needs_a_port_or_an_address() {
+    let output = common::run_dlm(&[
+        "--web-status",
+        "nowhere",
+        "https://example.com/a",
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("'nowhere' is neither a port nor an address like 0.0.0.0:8080"),
+        "{output:?}"
+    );
+}
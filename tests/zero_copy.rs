@@ -0,0 +1,147 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{Response, TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+
+fn download(server: &TestServer, dir: &std::path::Path, path: &str) -> std::process::Output {
+    run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--zero-copy",
+        &server.url(path),
+        "download-async",
+    ])
+}
+
+#[test]
+fn plain_http_is_spliced_to_the_file() {
+    let data = payload(3_000_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("plain_http_is_spliced_to_the_file");
+
+    let output = download(&server, &dir, "/file.bin");
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("with splice(2)"), "{stderr}");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("Connection"), Some("close"));
+}
+
+#[test]
+fn what_splicing_cant_handle_falls_back() {
+    let data = payload(100_000);
+    let server = TestServer::builder(data.clone()).start();
+    let dir = scratch_dir("what_splicing_cant_handle_falls_back");
+
+    // The redirect is followed by the usual download.
+    let output = download(&server, &dir, "/redirect/file.bin");
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("needs the usual download: HTTP/1.1 30"),
+        "{stderr}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--zero-copy",
+        "--resume",
+        "--output",
+        "resumed.bin",
+        &server.url("/file.bin"),
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("resumed.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Not using --zero-copy: resuming needs ranges"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn an_ipv6_literal_is_spliced_too() {
+    let data = payload(500_000);
+    let server = TestServer::builder(data.clone()).ipv6().start();
+    let dir = scratch_dir("an_ipv6_literal_is_spliced_too");
+
+    let url = server.url("/file.bin");
+    assert!(url.starts_with("http://[::1]:"), "{url}");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--zero-copy",
+        "--connect-timeout",
+        "5",
+        &url,
+        "download-async",
+    ]);
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("with splice(2)"), "{stderr}");
+}
+
+#[test]
+fn the_answer_is_checked_before_splicing() {
+    let server = TestServer::builder(payload(1_000))
+        .handler(|request, _| {
+            (request.path == "/backup.tar.zst").then(|| {
+                Response::new(200, r#"{"error": "NoSuchKey"}"#)
+                    .header("Content-Type", "application/json")
+            })
+        })
+        .start();
+    let dir = scratch_dir("the_answer_is_checked_before_splicing");
+
+    let output = download(&server, &dir, "/backup.tar.zst");
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("with splice(2)"), "{stderr}");
+    assert!(
+        stderr.contains("served as application/json, but named like a .zst file"),
+        "{stderr}"
+    );
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        "-v",
+        "--zero-copy",
+        "--overwrite",
+        "--strict-content-type",
+        &server.url("/backup.tar.zst"),
+        "download-async",
+    ]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Not using --zero-copy: --strict-content-type"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn a_body_cut_short_is_fetched_again_the_usual_way() {
+    let data = payload(1_000_000);
+    let server = TestServer::builder(data.clone())
+        .fail_after(300_000, 1)
+        .start();
+    let dir = scratch_dir("a_body_cut_short_is_fetched_again_the_usual_way");
+
+    let output = download(&server, &dir, "/file.bin");
+    assert_downloaded(&output, &dir.join("file.bin"), &data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("with splice(2)"), "{stderr}");
+    assert!(
+        stderr.contains("the connection dropped after")
+            && stderr.contains("the usual download takes over"),
+        "{stderr}"
+    );
+    assert_eq!(server.requests().len(), 2);
+}
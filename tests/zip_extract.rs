@@ -0,0 +1,149 @@
+mod common;
+
+use common::{TestServer, assert_downloaded, payload, run_dlm, scratch_dir};
+use std::io::{Cursor, Write};
+use zip::CompressionMethod;
+use zip::write::SimpleFileOptions;
+
+/// Builds an archive with a large stored filler first, so the members of
+/// interest sit far away from the central directory.
+fn archive() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let filler = payload(300_000);
+    let stored = payload(20_000);
+    let text = "all work and no play makes jack a dull boy\n"
+        .repeat(2_000)
+        .into_bytes();
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    for (name, method, data) in [
+        ("filler.bin", CompressionMethod::Stored, &filler),
+        ("data/stored.bin", CompressionMethod::Stored, &stored),
+        ("docs/readme.txt", CompressionMethod::Deflated, &text),
+    ] {
+        writer
+            .start_file(name, options.compression_method(method))
+            .unwrap();
+        writer.write_all(data).unwrap();
+    }
+    (writer.finish().unwrap().into_inner(), stored, text)
+}
+
+/// Rewrites the end of central directory the way archives over 4 GiB have
+/// it: a zip64 record and locator, and a classic record with every field
+/// saturated.
+fn into_zip64(mut zip: Vec<u8>) -> Vec<u8> {
+    let eocd = zip.split_off(zip.len() - 22);
+    assert_eq!(&eocd[..4], b"PK\x05\x06");
+    let entries = u16::from_le_bytes([eocd[10], eocd[11]]) as u64;
+    let cd_size = u32::from_le_bytes(eocd[12..16].try_into().unwrap()) as u64;
+    let cd_offset = u32::from_le_bytes(eocd[16..20].try_into().unwrap()) as u64;
+    let record_offset = zip.len() as u64;
+
+    zip.extend_from_slice(b"PK\x06\x06");
+    zip.extend_from_slice(&44u64.to_le_bytes());
+    zip.extend_from_slice(&[45, 0, 45, 0]);
+    zip.extend_from_slice(&[0; 8]);
+    zip.extend_from_slice(&entries.to_le_bytes());
+    zip.extend_from_slice(&entries.to_le_bytes());
+    zip.extend_from_slice(&cd_size.to_le_bytes());
+    zip.extend_from_slice(&cd_offset.to_le_bytes());
+
+    zip.extend_from_slice(b"PK\x06\x07");
+    zip.extend_from_slice(&[0; 4]);
+    zip.extend_from_slice(&record_offset.to_le_bytes());
+    zip.extend_from_slice(&1u32.to_le_bytes());
+
+    zip.extend_from_slice(b"PK\x05\x06");
+    zip.extend_from_slice(&[0xff; 16]);
+    zip.extend_from_slice(&[0; 2]);
+    zip
+}
+
+#[test]
+fn extracts_stored_and_deflated_members() {
+    for zip64 in [false, true] {
+        let (zip, stored, text) = archive();
+        let zip = if zip64 { into_zip64(zip) } else { zip };
+        let server = TestServer::builder(zip).start();
+        let dir = scratch_dir(&format!("extracts_members_zip64_{zip64}"));
+
+        for (member, name, data) in [
+            ("data/stored.bin", "stored.bin", &stored),
+            ("docs/readme.txt", "readme.txt", &text),
+        ] {
+            let output = run_dlm(&[
+                "-t",
+                dir.to_str().unwrap(),
+                &server.url("/archive.zip"),
+                "zip-extract",
+                member,
+            ]);
+            assert_downloaded(&output, &dir.join(name), data);
+        }
+
+        // Only the tail and the members' own bytes went over the wire, never
+        // the filler in front of them.
+        for request in server.requests() {
+            let range = request.header("Range").expect("every request is ranged");
+            let start = range
+                .trim_start_matches("bytes=")
+                .split('-')
+                .next()
+                .unwrap();
+            assert!(start.is_empty() || start.parse::<usize>().unwrap() > 300_000);
+        }
+    }
+}
+
+#[test]
+fn missing_and_encrypted_members_fail_clearly() {
+    let (mut zip, _, _) = archive();
+    let server = TestServer::builder(zip.clone()).start();
+    let dir = scratch_dir("missing_and_encrypted_members_fail_clearly");
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/archive.zip"),
+        "zip-extract",
+        "nope.txt",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No member 'nope.txt'"));
+
+    // Flag every central directory entry as encrypted.
+    let mut pos = 0;
+    while let Some(found) = zip[pos..].windows(4).position(|w| w == b"PK\x01\x02") {
+        pos += found;
+        zip[pos + 8] |= 1;
+        pos += 4;
+    }
+    let server = TestServer::builder(zip).start();
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/archive.zip"),
+        "zip-extract",
+        "docs/readme.txt",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("encrypted"));
+}
+
+#[test]
+fn servers_without_ranges_are_refused() {
+    let (zip, _, _) = archive();
+    let server = TestServer::builder(zip).no_ranges().start();
+    let dir = scratch_dir("servers_without_ranges_are_refused");
+
+    let output = run_dlm(&[
+        "-t",
+        dir.to_str().unwrap(),
+        &server.url("/archive.zip"),
+        "zip-extract",
+        "docs/readme.txt",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not support range requests"));
+}